@@ -0,0 +1,108 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rustvm::registers::register::Register;
+use rustvm::Vm;
+use rustvm::{blocks, predecode};
+
+/// Answers the terminal prompt, presses a key to start, plays a few dozen
+/// moves and then runs out of input, which halts the game.
+const GAME_INPUT: &str = "y wasdwasdddssaawwdsadsawdwasdwasdddssaawwdsadsawd";
+
+type Setup = fn() -> Box<Vm>;
+
+fn program(words: &[u16]) -> Box<Vm> {
+    let mut vm = Box::new(Vm::new());
+    for (offset, &word) in words.iter().enumerate() {
+        vm.mem_write(0x3000 + offset as u16, word);
+    }
+    vm.write_to_register(Register::Pc, 0x3000);
+    vm.output = Some(String::new());
+    vm
+}
+
+/// A tight arithmetic loop, counting R1 down from 30000.
+fn countdown() -> Box<Vm> {
+    program(&[
+        0b0010_001_000000100,   // LD R1, COUNT
+        0b0001_010_010_1_00011, // ADD R2, R2, #3
+        0b0001_001_001_1_11111, // ADD R1, R1, #-1
+        0b0000_001_111111101,   // BRp #-3
+        0xF025,                 // HALT
+        30000,                  // COUNT
+    ])
+}
+
+/// Copies 4096 words with LDR/STR, like a block move in student code.
+fn memory_copy() -> Box<Vm> {
+    let mut vm = program(&[
+        0b0010_001_000001001,   // LD R1, COUNT
+        0b0010_010_000001001,   // LD R2, SOURCE
+        0b0010_011_000001001,   // LD R3, DESTINATION
+        0b0110_100_010_000000,  // LDR R4, R2, #0
+        0b0111_100_011_000000,  // STR R4, R3, #0
+        0b0001_010_010_1_00001, // ADD R2, R2, #1
+        0b0001_011_011_1_00001, // ADD R3, R3, #1
+        0b0001_001_001_1_11111, // ADD R1, R1, #-1
+        0b0000_001_111111010,   // BRp #-6
+        0xF025,                 // HALT
+        4096,                   // COUNT
+        0x4000,                 // SOURCE
+        0x6000,                 // DESTINATION
+    ]);
+    for offset in 0..4096 {
+        vm.mem_write(0x4000 + offset, offset);
+    }
+    vm
+}
+
+fn game() -> Box<Vm> {
+    let mut vm = Box::new(Vm::new());
+    assert!(vm.read_file(concat!(env!("CARGO_MANIFEST_DIR"), "/2048.obj")));
+    vm.write_to_register(Register::Pc, 0x3000);
+    vm.input = Some(GAME_INPUT.encode_utf16().collect());
+    vm.output = Some(String::new());
+    vm
+}
+
+fn dispatch(c: &mut Criterion) {
+    let programs: [(&str, Setup); 3] = [
+        ("countdown", countdown),
+        ("memory_copy", memory_copy),
+        ("2048", game),
+    ];
+
+    for (name, setup) in programs {
+        let instructions = predecode::run(&mut setup());
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(instructions));
+
+        group.bench_function("interpreter", |b| {
+            b.iter_batched(
+                setup,
+                |mut vm| while vm.fetch_decode_execute() {},
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function("predecoded", |b| {
+            b.iter_batched(
+                setup,
+                |mut vm| predecode::run(&mut vm),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function("blocks", |b| {
+            b.iter_batched(setup, |mut vm| blocks::run(&mut vm), BatchSize::LargeInput)
+        });
+        #[cfg(feature = "jit")]
+        group.bench_function("jit", |b| {
+            b.iter_batched(
+                setup,
+                |mut vm| rustvm::jit::run(&mut vm),
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
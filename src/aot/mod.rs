@@ -0,0 +1,12 @@
+use crate::cfg;
+use crate::Vm;
+
+pub mod runtime;
+pub mod rust;
+
+/// Translates the code reachable from `entry` in the loaded images into a
+/// standalone Rust program that links against `runtime`.
+pub fn translate(vm: &Vm, entry: u16) -> String {
+    let flow = cfg::recover(&vm.memory, &vm.images, entry);
+    rust::emit(&vm.memory, &vm.images, &flow)
+}
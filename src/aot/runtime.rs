@@ -0,0 +1,113 @@
+use crate::registers::register::Register;
+use crate::registers::ConditionFlag;
+use crate::Vm;
+use std::process;
+
+/// What a translated block asks the runtime to do next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Dispatch again at the new PC.
+    Continue,
+    Halt,
+    /// Nothing was translated at the PC; run one instruction in the
+    /// interpreter.
+    Interpret,
+    /// A store rewrote translated code, so the translation can no longer
+    /// be trusted.
+    Modified,
+}
+
+pub type Dispatch = fn(&mut Vm) -> Exit;
+
+/// Writes `value` and returns whether it landed on translated code. The
+/// ranges are sorted, with exclusive ends.
+pub fn store(vm: &mut Vm, address: u16, value: u16, translated: &[(u16, u32)]) -> bool {
+    vm.mem_write(address, value);
+    let index = translated.partition_point(|&(start, _)| start <= address);
+    index > 0 && (address as u32) < translated[index - 1].1
+}
+
+/// Copies the images into memory and points the machine at `entry`, like
+/// loading them on the command line does.
+pub fn load(vm: &mut Vm, images: &[(u16, &[u16])], entry: u16) {
+    for &(origin, words) in images {
+        for (offset, &word) in words.iter().enumerate() {
+            vm.mem_write(origin.wrapping_add(offset as u16), word);
+        }
+    }
+    vm.registers[Register::Cond as usize] = ConditionFlag::Zro as u16;
+    vm.registers[Register::Pc as usize] = entry;
+}
+
+/// Runs translated code until the program halts. Addresses without a
+/// translation, such as indirect jump targets that were never found
+/// statically, go through the interpreter one instruction at a time, and
+/// so does everything once the program has modified its own code.
+pub fn run(vm: &mut Vm, dispatch: Dispatch) {
+    let mut modified = false;
+    loop {
+        let exit = if modified {
+            Exit::Interpret
+        } else {
+            dispatch(vm)
+        };
+        match exit {
+            Exit::Continue => {}
+            Exit::Halt => return,
+            Exit::Modified => modified = true,
+            Exit::Interpret => {
+                if !vm.fetch_decode_execute() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// The `main` of a translated program.
+pub fn main(images: &[(u16, &[u16])], entry: u16, dispatch: Dispatch) {
+    let mut vm = Vm::new();
+    load(&mut vm, images, entry);
+
+    vm.disable_input_buffering().ok();
+    run(&mut vm, dispatch);
+    vm.restore_input_buffering().ok();
+    if let Some(reason) = vm.stop_reason {
+        eprintln!("--- Stopped: {} ---", reason);
+        process::exit(reason.exit_code().unwrap_or(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aot::runtime::{run, store, Exit};
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    #[test]
+    fn test_store_reports_translated_code() {
+        let translated = [(0x3000, 0x3005), (0x3010, 0x3012)];
+        let mut vm = Vm::new();
+
+        assert!(store(&mut vm, 0x3000, 1, &translated));
+        assert!(store(&mut vm, 0x3011, 1, &translated));
+        assert!(!store(&mut vm, 0x3005, 1, &translated));
+        assert!(!store(&mut vm, 0x2FFF, 1, &translated));
+        assert!(!store(&mut vm, 0x4000, 1, &translated));
+        assert_eq!(vm.memory[0x3005], 1);
+    }
+
+    #[test]
+    fn test_untranslated_addresses_are_interpreted() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3000, 0b0001_001_001_1_00001); // ADD R1, R1, #1
+        vm.mem_write(0x3001, 0xF025); // HALT
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.output = Some(String::new());
+
+        run(&mut vm, |_| Exit::Interpret);
+
+        assert_eq!(vm.registers[Register::R1 as usize], 1);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3002);
+    }
+}
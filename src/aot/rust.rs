@@ -0,0 +1,341 @@
+use crate::cfg::{BasicBlock, ControlFlow};
+use crate::instructions::disassemble::disassemble;
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::{LoadedImage, MEMORY_MAX};
+use std::fmt::Write;
+
+/// Which runtime helpers the generated code calls, so only those are
+/// imported.
+#[derive(Default)]
+struct Uses {
+    flags: bool,
+    stores: bool,
+    traps: bool,
+}
+
+/// Renders `flow` as a standalone Rust program. The images are embedded,
+/// each basic block becomes a function, and `dispatch` maps the PC to the
+/// block starting there.
+pub fn emit(memory: &[u16; MEMORY_MAX], images: &[LoadedImage], flow: &ControlFlow) -> String {
+    let mut uses = Uses::default();
+    let mut functions = String::new();
+    for block in flow.blocks.values() {
+        emit_block(&mut functions, memory, block, &mut uses);
+    }
+
+    let mut out = String::new();
+    let sources: Vec<&str> = images
+        .iter()
+        .map(|image| image.file_name.as_str())
+        .collect();
+    writeln!(
+        out,
+        "//! Translated ahead of time from {} by RustVm. Build it against the\n\
+         //! rustvm crate; it behaves like running the image in the interpreter.\n",
+        sources.join(", ")
+    )
+    .unwrap();
+
+    out.push_str("use rustvm::aot::runtime::{self, Exit};\n");
+    if uses.traps {
+        out.push_str("use rustvm::instructions::trap::trap;\n");
+    }
+    if uses.flags {
+        out.push_str("use rustvm::instructions::update_flags;\n");
+    }
+    out.push_str("use rustvm::Vm;\n\n");
+    out.push_str("const PC: usize = 8;\n");
+    out.push_str("const COND: usize = 9;\n\n");
+    writeln!(out, "pub const ENTRY: u16 = 0x{:04X};\n", flow.entry).unwrap();
+
+    out.push_str("/// The loaded images as (origin, words).\n");
+    out.push_str("pub const IMAGES: &[(u16, &[u16])] = &[\n");
+    for image in images {
+        writeln!(out, "    (\n        0x{:04X},\n        &[", image.origin).unwrap();
+        let words: Vec<u16> = image
+            .addresses()
+            .map(|address| memory[address as usize])
+            .collect();
+        for line in words.chunks(8) {
+            let line: Vec<String> = line.iter().map(|word| format!("0x{:04X},", word)).collect();
+            writeln!(out, "            {}", line.join(" ")).unwrap();
+        }
+        out.push_str("        ],\n    ),\n");
+    }
+    out.push_str("];\n\n");
+
+    if uses.stores {
+        out.push_str(
+            "/// Translated instructions. A store into them hands the rest of the run to\n\
+             /// the interpreter.\n",
+        );
+        out.push_str("const TRANSLATED: &[(u16, u32)] = &[\n");
+        for (start, end) in translated_ranges(flow) {
+            writeln!(out, "    (0x{:04X}, 0x{:04X}),", start, end).unwrap();
+        }
+        out.push_str("];\n\n");
+        out.push_str(
+            "fn store(vm: &mut Vm, address: u16, value: u16) -> bool {\n    \
+             runtime::store(vm, address, value, TRANSLATED)\n}\n\n",
+        );
+    }
+
+    out.push_str("/// Runs the block starting at the PC.\n");
+    out.push_str("pub fn dispatch(vm: &mut Vm) -> Exit {\n");
+    out.push_str("    match vm.registers[PC] {\n");
+    for start in flow.blocks.keys() {
+        writeln!(out, "        0x{:04X} => block_{:04x}(vm),", start, start).unwrap();
+    }
+    out.push_str("        _ => Exit::Interpret,\n    }\n}\n\n");
+    out.push_str(&functions);
+    out.push_str("pub fn main() {\n    runtime::main(IMAGES, ENTRY, dispatch);\n}\n");
+    out
+}
+
+/// Merges adjacent blocks into the ranges of translated code.
+fn translated_ranges(flow: &ControlFlow) -> Vec<(u16, u32)> {
+    let mut ranges: Vec<(u16, u32)> = Vec::new();
+    for block in flow.blocks.values() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == block.start as u32 => *end = block.end,
+            _ => ranges.push((block.start, block.end)),
+        }
+    }
+    ranges
+}
+
+fn emit_block(out: &mut String, memory: &[u16; MEMORY_MAX], block: &BasicBlock, uses: &mut Uses) {
+    writeln!(out, "fn block_{:04x}(vm: &mut Vm) -> Exit {{", block.start).unwrap();
+
+    for address in block.start as u32..block.end {
+        let address = address as u16;
+        let instruction = memory[address as usize];
+        let next = address.wrapping_add(1);
+        writeln!(
+            out,
+            "    // x{:04X}: {}",
+            address,
+            disassemble(address, instruction)
+        )
+        .unwrap();
+        if let Some(tail) = emit_instruction(out, address, instruction, uses) {
+            writeln!(out, "    {}\n}}\n", tail).unwrap();
+            return;
+        }
+        if address as u32 + 1 == block.end {
+            writeln!(out, "    vm.registers[PC] = 0x{:04X};", next).unwrap();
+        }
+    }
+    out.push_str("    Exit::Continue\n}\n\n");
+}
+
+/// Writes the statements for one instruction. Returns the block's tail
+/// expression if the instruction ends the block.
+fn emit_instruction(
+    out: &mut String,
+    address: u16,
+    instruction: u16,
+    uses: &mut Uses,
+) -> Option<String> {
+    let Decoded {
+        operation,
+        dr,
+        sr,
+        operand,
+    } = Decoded::decode(instruction);
+    let next = address.wrapping_add(1);
+    let relative = next.wrapping_add(operand);
+
+    let assignment = match operation {
+        Operation::AddRegister => Some(format!(
+            "vm.registers[{}].wrapping_add(vm.registers[{}])",
+            sr, operand
+        )),
+        Operation::AddImmediate => Some(format!(
+            "vm.registers[{}].wrapping_add(0x{:04X})",
+            sr, operand
+        )),
+        Operation::AndRegister => Some(format!("vm.registers[{}] & vm.registers[{}]", sr, operand)),
+        Operation::AndImmediate => Some(format!("vm.registers[{}] & 0x{:04X}", sr, operand)),
+        Operation::Not => Some(format!("!vm.registers[{}]", sr)),
+        Operation::Lea => Some(format!("0x{:04X}", relative)),
+        Operation::Ld => Some(format!("vm.mem_read(0x{:04X})", relative)),
+        Operation::Ldi => {
+            writeln!(out, "    let pointer = vm.mem_read(0x{:04X});", relative).unwrap();
+            Some("vm.mem_read(pointer)".to_string())
+        }
+        Operation::Ldr => {
+            writeln!(
+                out,
+                "    let address = vm.registers[{}].wrapping_add(0x{:04X});",
+                sr, operand
+            )
+            .unwrap();
+            Some("vm.mem_read(address)".to_string())
+        }
+        _ => None,
+    };
+    if let Some(value) = assignment {
+        writeln!(out, "    vm.registers[{}] = {};", dr, value).unwrap();
+        writeln!(out, "    update_flags(&mut vm.registers, {});", dr).unwrap();
+        uses.flags = true;
+        return None;
+    }
+
+    let target = match operation {
+        Operation::St => Some(format!("0x{:04X}", relative)),
+        Operation::Sti => {
+            writeln!(out, "    let address = vm.mem_read(0x{:04X});", relative).unwrap();
+            Some("address".to_string())
+        }
+        Operation::Str => {
+            writeln!(
+                out,
+                "    let address = vm.registers[{}].wrapping_add(0x{:04X});",
+                sr, operand
+            )
+            .unwrap();
+            Some("address".to_string())
+        }
+        _ => None,
+    };
+    if let Some(target) = target {
+        writeln!(out, "    let value = vm.registers[{}];", dr).unwrap();
+        writeln!(out, "    if store(vm, {}, value) {{", target).unwrap();
+        writeln!(out, "        vm.registers[PC] = 0x{:04X};", next).unwrap();
+        out.push_str("        return Exit::Modified;\n    }\n");
+        uses.stores = true;
+        return None;
+    }
+
+    let tail = match operation {
+        Operation::Br if dr == 0 => {
+            writeln!(out, "    vm.registers[PC] = 0x{:04X};", next).unwrap();
+            "Exit::Continue".to_string()
+        }
+        Operation::Br if dr == 0b111 => {
+            writeln!(out, "    vm.registers[PC] = 0x{:04X};", relative).unwrap();
+            "Exit::Continue".to_string()
+        }
+        Operation::Br => {
+            writeln!(
+                out,
+                "    vm.registers[PC] = if vm.registers[COND] & 0b{:03b} != 0 {{\n        \
+                 0x{:04X}\n    }} else {{\n        0x{:04X}\n    }};",
+                dr, relative, next
+            )
+            .unwrap();
+            "Exit::Continue".to_string()
+        }
+        Operation::Jmp => {
+            writeln!(out, "    vm.registers[PC] = vm.registers[{}];", sr).unwrap();
+            "Exit::Continue".to_string()
+        }
+        Operation::Jsr => {
+            writeln!(out, "    vm.registers[7] = 0x{:04X};", next).unwrap();
+            writeln!(out, "    vm.registers[PC] = 0x{:04X};", relative).unwrap();
+            "Exit::Continue".to_string()
+        }
+        Operation::Jsrr => {
+            writeln!(out, "    vm.registers[7] = 0x{:04X};", next).unwrap();
+            writeln!(out, "    vm.registers[PC] = vm.registers[{}];", sr).unwrap();
+            "Exit::Continue".to_string()
+        }
+        Operation::Trap => {
+            writeln!(out, "    vm.registers[PC] = 0x{:04X};", next).unwrap();
+            uses.traps = true;
+            format!(
+                "if trap(vm, 0x{:04X}) {{\n        Exit::Continue\n    }} else {{\n        \
+                 Exit::Halt\n    }}",
+                instruction
+            )
+        }
+        _ => {
+            writeln!(out, "    vm.registers[PC] = 0x{:04X};", next).unwrap();
+            "Exit::Halt".to_string()
+        }
+    };
+    Some(tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aot::translate;
+    use crate::{LoadedImage, Vm};
+
+    /// The program behind `tests/translated/program.rs`: prints a greeting,
+    /// calls a subroutine in a loop, echoes a key, then patches its own code.
+    pub const PROGRAM: [u16; 24] = [
+        0xE014, // LEA R0, MSG
+        0xF022, // PUTS
+        0x5260, // AND R1, R1, #0
+        0x1265, // ADD R1, R1, #5
+        0x480B, // LOOP JSR DOUBLE
+        0x127F, // ADD R1, R1, #-1
+        0x03FD, // BRp LOOP
+        0xF020, // GETC
+        0xF021, // OUT
+        0x340A, // ST R2, RESULT
+        0x2609, // LD R3, RESULT
+        0xE802, // LEA R4, PATCH
+        0x2A06, // LD R5, NEWOP
+        0x7B00, // STR R5, R4, #0
+        0x1DA1, // PATCH ADD R6, R6, #1
+        0xF025, // HALT
+        0x1482, // DOUBLE ADD R2, R2, R2
+        0x14A1, // ADD R2, R2, #1
+        0xC1C0, // RET
+        0x1DA7, // NEWOP .FILL (ADD R6, R6, #7)
+        0x0000, // RESULT .FILL 0
+        0x0048, // MSG "Hi"
+        0x0069, 0x0000,
+    ];
+
+    fn program_vm() -> Vm {
+        let mut vm = Vm::new();
+        for (offset, &word) in PROGRAM.iter().enumerate() {
+            vm.mem_write(0x3000 + offset as u16, word);
+        }
+        vm.images.push(LoadedImage {
+            file_name: "program.obj".to_string(),
+            origin: 0x3000,
+            length: PROGRAM.len() as u16,
+        });
+        vm
+    }
+
+    // ========== Generated Source ==========
+
+    #[test]
+    fn test_every_block_gets_a_dispatch_arm() {
+        let source = translate(&program_vm(), 0x3000);
+
+        for start in [
+            0x3000, 0x3002, 0x3004, 0x3005, 0x3007, 0x3008, 0x3009, 0x3010,
+        ] {
+            assert!(
+                source.contains(&format!("0x{:04X} => block_{:04x}(vm),", start, start)),
+                "x{:04X}",
+                start
+            );
+        }
+        assert!(!source.contains("block_3013"));
+    }
+
+    #[test]
+    fn test_helpers_are_only_emitted_when_used() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3000, 0b0000_111_111111111); // BRnzp #-1
+        vm.images.push(LoadedImage {
+            file_name: "spin.obj".to_string(),
+            origin: 0x3000,
+            length: 1,
+        });
+
+        let source = translate(&vm, 0x3000);
+
+        assert!(!source.contains("fn store"));
+        assert!(!source.contains("use rustvm::instructions::trap::trap;"));
+        assert!(!source.contains("update_flags"));
+    }
+}
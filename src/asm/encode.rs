@@ -0,0 +1,317 @@
+use crate::asm::lexer::Operand;
+use crate::instructions::trap::{TRAP_GETC, TRAP_HALT, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP};
+use crate::symbols::symbol_table::SymbolTable;
+
+fn expect_operands(operation: &str, operands: &[Operand], count: usize) -> Result<(), String> {
+    if operands.len() != count {
+        return Err(format!(
+            "{} expects {} operand{}, got {}",
+            operation,
+            count,
+            if count == 1 { "" } else { "s" },
+            operands.len()
+        ));
+    }
+    Ok(())
+}
+
+fn register(operand: &Operand) -> Result<u16, String> {
+    match operand {
+        Operand::Register(register) => Ok(*register),
+        other => Err(format!("expected a register, got {}", describe(other))),
+    }
+}
+
+/// Checks that `value` fits in a signed field of `bits` bits and returns
+/// its two's complement encoding.
+fn signed(value: i32, bits: u32, what: &str) -> Result<u16, String> {
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+    if value < min || value > max {
+        return Err(format!(
+            "{} {} does not fit in {} bits ({}..{})",
+            what, value, bits, min, max
+        ));
+    }
+    Ok(value as u16 & ((1 << bits) - 1))
+}
+
+fn immediate(operand: &Operand, bits: u32) -> Result<u16, String> {
+    match operand {
+        Operand::Number(value) => signed(*value, bits, "immediate"),
+        other => Err(format!("expected an immediate, got {}", describe(other))),
+    }
+}
+
+/// A PC-relative offset to a label, or a literal offset.
+fn pc_offset(
+    operand: &Operand,
+    address: u16,
+    bits: u32,
+    symbols: &SymbolTable,
+) -> Result<u16, String> {
+    let offset = match operand {
+        Operand::Label(label) => {
+            let target = symbols
+                .address_of(label)
+                .ok_or_else(|| format!("undefined label '{}'", label))?;
+            target as i32 - (address as i32 + 1)
+        }
+        Operand::Number(value) => *value,
+        other => {
+            return Err(format!(
+                "expected a label or offset, got {}",
+                describe(other)
+            ))
+        }
+    };
+    signed(offset, bits, "PC offset")
+}
+
+pub fn describe(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => format!("R{}", register),
+        Operand::Number(value) => format!("#{}", value),
+        Operand::Label(label) => format!("'{}'", label),
+        Operand::String(_) => "a string".to_string(),
+        Operand::Expression(expr) => format!("'{}'", expr),
+    }
+}
+
+/// Encodes one instruction at `address`. Labels are looked up in `symbols`.
+pub fn encode(
+    operation: &str,
+    operands: &[Operand],
+    address: u16,
+    symbols: &SymbolTable,
+) -> Result<u16, String> {
+    let trap_alias = |vector: u16| -> Result<u16, String> {
+        expect_operands(operation, operands, 0)?;
+        Ok(0xF000 | vector)
+    };
+
+    match operation {
+        "ADD" | "AND" => {
+            expect_operands(operation, operands, 3)?;
+            let opcode = if operation == "ADD" { 0x1000 } else { 0x5000 };
+            let dr = register(&operands[0])?;
+            let sr1 = register(&operands[1])?;
+            let last = match &operands[2] {
+                Operand::Register(sr2) => *sr2,
+                other => 0x20 | immediate(other, 5)?,
+            };
+            Ok(opcode | dr << 9 | sr1 << 6 | last)
+        }
+        "NOT" => {
+            expect_operands(operation, operands, 2)?;
+            Ok(0x903F | register(&operands[0])? << 9 | register(&operands[1])? << 6)
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect_operands(operation, operands, 2)?;
+            let opcode = match operation {
+                "LD" => 0x2000,
+                "LDI" => 0xA000,
+                "LEA" => 0xE000,
+                "ST" => 0x3000,
+                _ => 0xB000,
+            };
+            let offset = pc_offset(&operands[1], address, 9, symbols)?;
+            Ok(opcode | register(&operands[0])? << 9 | offset)
+        }
+        "LDR" | "STR" => {
+            expect_operands(operation, operands, 3)?;
+            let opcode = if operation == "LDR" { 0x6000 } else { 0x7000 };
+            let offset = match &operands[2] {
+                Operand::Number(value) => signed(*value, 6, "offset")?,
+                other => return Err(format!("expected an offset, got {}", describe(other))),
+            };
+            Ok(opcode | register(&operands[0])? << 9 | register(&operands[1])? << 6 | offset)
+        }
+        "JMP" => {
+            expect_operands(operation, operands, 1)?;
+            Ok(0xC000 | register(&operands[0])? << 6)
+        }
+        "RET" => {
+            expect_operands(operation, operands, 0)?;
+            Ok(0xC1C0)
+        }
+        "JSR" => {
+            expect_operands(operation, operands, 1)?;
+            Ok(0x4800 | pc_offset(&operands[0], address, 11, symbols)?)
+        }
+        "JSRR" => {
+            expect_operands(operation, operands, 1)?;
+            Ok(0x4000 | register(&operands[0])? << 6)
+        }
+        "TRAP" => {
+            expect_operands(operation, operands, 1)?;
+            match &operands[0] {
+                Operand::Number(vector) if (0..=0xFF).contains(vector) => {
+                    Ok(0xF000 | *vector as u16)
+                }
+                other => Err(format!(
+                    "expected a trap vector x00..xFF, got {}",
+                    describe(other)
+                )),
+            }
+        }
+        "RTI" => {
+            expect_operands(operation, operands, 0)?;
+            Ok(0x8000)
+        }
+        "GETC" => trap_alias(TRAP_GETC),
+        "OUT" => trap_alias(TRAP_OUT),
+        "PUTS" => trap_alias(TRAP_PUTS),
+        "IN" => trap_alias(TRAP_IN),
+        "PUTSP" => trap_alias(TRAP_PUTSP),
+        "HALT" => trap_alias(TRAP_HALT),
+        _ => {
+            let Some(conditions) = operation.strip_prefix("BR") else {
+                return Err(format!("unknown operation '{}'", operation));
+            };
+            expect_operands(operation, operands, 1)?;
+            let mut nzp = 0;
+            for (flag, bit) in [('N', 0b100), ('Z', 0b010), ('P', 0b001)] {
+                if conditions.contains(flag) {
+                    nzp |= bit;
+                }
+            }
+            if nzp == 0 {
+                nzp = 0b111;
+            }
+            Ok(nzp << 9 | pc_offset(&operands[0], address, 9, symbols)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::encode::encode;
+    use crate::asm::lexer::Operand;
+    use crate::symbols::symbol_table::SymbolTable;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3000);
+        symbols.insert("FAR", 0x3200);
+        symbols
+    }
+
+    // ========== Operate Instructions ==========
+
+    #[test]
+    fn test_add_register_and_immediate_modes() {
+        let registers = [
+            Operand::Register(1),
+            Operand::Register(2),
+            Operand::Register(3),
+        ];
+        let immediate = [
+            Operand::Register(1),
+            Operand::Register(2),
+            Operand::Number(-1),
+        ];
+
+        assert_eq!(
+            encode("ADD", &registers, 0x3000, &symbols()),
+            Ok(0b0001_001_010_0_00_011)
+        );
+        assert_eq!(
+            encode("AND", &immediate, 0x3000, &symbols()),
+            Ok(0b0101_001_010_1_11111)
+        );
+    }
+
+    #[test]
+    fn test_immediate_out_of_range() {
+        let operands = [
+            Operand::Register(1),
+            Operand::Register(1),
+            Operand::Number(16),
+        ];
+
+        assert_eq!(
+            encode("ADD", &operands, 0x3000, &symbols()),
+            Err("immediate 16 does not fit in 5 bits (-16..15)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_not_sets_the_low_bits() {
+        let operands = [Operand::Register(2), Operand::Register(3)];
+
+        assert_eq!(
+            encode("NOT", &operands, 0x3000, &symbols()),
+            Ok(0b1001_010_011_111111)
+        );
+    }
+
+    // ========== PC-Relative Operands ==========
+
+    #[test]
+    fn test_branch_back_to_label() {
+        let operands = [Operand::Label("LOOP".to_string())];
+
+        assert_eq!(
+            encode("BRNP", &operands, 0x3002, &symbols()),
+            Ok(0b0000_101_111111101)
+        );
+        assert_eq!(
+            encode("BR", &operands, 0x3002, &symbols()),
+            Ok(0b0000_111_111111101)
+        );
+    }
+
+    #[test]
+    fn test_offset_out_of_range_and_undefined_label() {
+        let far = [Operand::Register(0), Operand::Label("FAR".to_string())];
+        let missing = [Operand::Register(0), Operand::Label("NOPE".to_string())];
+
+        assert_eq!(
+            encode("LD", &far, 0x3000, &symbols()),
+            Err("PC offset 511 does not fit in 9 bits (-256..255)".to_string())
+        );
+        assert_eq!(
+            encode("LEA", &missing, 0x3000, &symbols()),
+            Err("undefined label 'NOPE'".to_string())
+        );
+        assert_eq!(
+            encode(
+                "JSR",
+                &[Operand::Label("FAR".to_string())],
+                0x3000,
+                &symbols()
+            ),
+            Ok(0x4800 | 511)
+        );
+    }
+
+    // ========== Control and Traps ==========
+
+    #[test]
+    fn test_trap_aliases_and_returns() {
+        assert_eq!(encode("HALT", &[], 0x3000, &symbols()), Ok(0xF025));
+        assert_eq!(encode("PUTS", &[], 0x3000, &symbols()), Ok(0xF022));
+        assert_eq!(
+            encode("TRAP", &[Operand::Number(0x21)], 0x3000, &symbols()),
+            Ok(0xF021)
+        );
+        assert_eq!(encode("RET", &[], 0x3000, &symbols()), Ok(0xC1C0));
+        assert_eq!(
+            encode("JSRR", &[Operand::Register(4)], 0x3000, &symbols()),
+            Ok(0x4100)
+        );
+    }
+
+    #[test]
+    fn test_wrong_operand_count_and_kind() {
+        assert_eq!(
+            encode("HALT", &[Operand::Number(1)], 0x3000, &symbols()),
+            Err("HALT expects 0 operands, got 1".to_string())
+        );
+        assert_eq!(
+            encode("JMP", &[Operand::Number(1)], 0x3000, &symbols()),
+            Err("expected a register, got #1".to_string())
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Operand expressions such as `TABLE+3`, `#SIZE-1` and `hi(ADDRESS)`.
+
+use crate::asm::lexer::parse_number;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+    /// Comparisons give 1 or 0, for `.IF`.
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessOrEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i32),
+    /// A label or constant.
+    Name(String),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    /// `hi(x)`: bits 15..8 of a word.
+    High(Box<Expr>),
+    /// `lo(x)`: bits 7..0 of a word.
+    Low(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i32),
+    Name(String),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+/// Longest first, so `<<` is not read as two `<`.
+const OPERATORS: [&str; 18] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "~", "#",
+];
+
+fn tokens(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if let Some(&operator) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            // `#` only marks a decimal number or constant, as in `#SIZE-1`
+            if operator != "#" {
+                tokens.push(Token::Operator(operator));
+            }
+            rest = &rest[operator.len()..];
+        } else if c.is_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            tokens.push(match parse_number(word) {
+                Some(number) => Token::Number(number),
+                None if word.starts_with(|c: char| c.is_ascii_digit()) => {
+                    return Err(format!("invalid number '{}'", word));
+                }
+                None => Token::Name(word.to_string()),
+            });
+            rest = &rest[end..];
+        } else {
+            return Err(format!("unexpected '{}' in expression", c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Binary operators from the loosest binding to the tightest.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 7] = [
+    &[
+        ("==", BinaryOp::Equal),
+        ("!=", BinaryOp::NotEqual),
+        ("<", BinaryOp::Less),
+        ("<=", BinaryOp::LessOrEqual),
+        (">", BinaryOp::Greater),
+        (">=", BinaryOp::GreaterOrEqual),
+    ],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("<<", BinaryOp::ShiftLeft), (">>", BinaryOp::ShiftRight)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+    &[
+        ("*", BinaryOp::Multiply),
+        ("/", BinaryOp::Divide),
+        ("%", BinaryOp::Remainder),
+    ],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Operator(symbol)) => PRECEDENCE[level]
+                    .iter()
+                    .find(|(candidate, _)| candidate == symbol)
+                    .map(|&(_, op)| op),
+                _ => None,
+            };
+            let Some(op) = op else {
+                return Ok(left);
+            };
+            self.next += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.advance() {
+            Some(Token::Operator("-")) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Operator("~")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Name(name)) if self.peek() == Some(&Token::Open) => {
+                let byte: fn(Box<Expr>) -> Expr = match name.to_ascii_lowercase().as_str() {
+                    "hi" => Expr::High,
+                    "lo" => Expr::Low,
+                    _ => return Err(format!("unknown function '{}', expected hi or lo", name)),
+                };
+                self.next += 1;
+                Ok(byte(Box::new(self.parenthesised()?)))
+            }
+            Some(Token::Name(name)) => Ok(Expr::Name(name)),
+            Some(Token::Open) => self.parenthesised(),
+            Some(Token::Operator(operator)) => {
+                Err(format!("expected a number or name before '{}'", operator))
+            }
+            Some(Token::Close) => Err("expected a number or name before ')'".to_string()),
+            None => Err("expression ends too early".to_string()),
+        }
+    }
+
+    /// The rest of a bracketed expression whose `(` has been read.
+    fn parenthesised(&mut self) -> Result<Expr, String> {
+        let inner = self.binary(0)?;
+        match self.advance() {
+            Some(Token::Close) => Ok(inner),
+            _ => Err("missing ')'".to_string()),
+        }
+    }
+}
+
+/// Parses an operand expression. Numbers take the usual LC-3 prefixes and
+/// `#` may come before any number or name.
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokens(text)?,
+        next: 0,
+    };
+    let expr = parser.binary(0)?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(Token::Close) => Err("unmatched ')'".to_string()),
+        Some(_) => Err(format!("unexpected text after '{}'", expr)),
+    }
+}
+
+impl Expr {
+    /// Every label or constant the expression refers to.
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Name(name) => vec![name.as_str()],
+            Expr::Negate(inner) | Expr::Not(inner) | Expr::High(inner) | Expr::Low(inner) => {
+                inner.names()
+            }
+            Expr::Binary(_, left, right) => {
+                let mut names = left.names();
+                names.extend(right.names());
+                names
+            }
+        }
+    }
+
+    /// Computes the value, looking names up with `lookup`.
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<i32>) -> Result<i32, String> {
+        Ok(match self {
+            Expr::Number(number) => *number,
+            Expr::Name(name) => {
+                lookup(name).ok_or_else(|| format!("undefined label '{}'", name))?
+            }
+            Expr::Negate(inner) => inner.eval(lookup)?.wrapping_neg(),
+            Expr::Not(inner) => !inner.eval(lookup)?,
+            Expr::High(inner) => (inner.eval(lookup)? >> 8) & 0xFF,
+            Expr::Low(inner) => inner.eval(lookup)? & 0xFF,
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(lookup)?, right.eval(lookup)?);
+                match op {
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Subtract => left.wrapping_sub(right),
+                    BinaryOp::Multiply => left.wrapping_mul(right),
+                    BinaryOp::Divide | BinaryOp::Remainder if right == 0 => {
+                        return Err(format!("division by zero in '{}'", self));
+                    }
+                    BinaryOp::Divide => left.wrapping_div(right),
+                    BinaryOp::Remainder => left.wrapping_rem(right),
+                    BinaryOp::ShiftLeft | BinaryOp::ShiftRight if !(0..32).contains(&right) => {
+                        return Err(format!("shift by {} in '{}'", right, self));
+                    }
+                    BinaryOp::ShiftLeft => left << right,
+                    BinaryOp::ShiftRight => left >> right,
+                    BinaryOp::And => left & right,
+                    BinaryOp::Or => left | right,
+                    BinaryOp::Xor => left ^ right,
+                    BinaryOp::Equal => (left == right) as i32,
+                    BinaryOp::NotEqual => (left != right) as i32,
+                    BinaryOp::Less => (left < right) as i32,
+                    BinaryOp::LessOrEqual => (left <= right) as i32,
+                    BinaryOp::Greater => (left > right) as i32,
+                    BinaryOp::GreaterOrEqual => (left >= right) as i32,
+                }
+            }
+        })
+    }
+}
+
+/// Writes `expr`, bracketing it if it is itself a binary operation.
+fn operand(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    match expr {
+        Expr::Binary(..) => write!(f, "({})", expr),
+        _ => write!(f, "{}", expr),
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(number) => write!(f, "{}", number),
+            Expr::Name(name) => write!(f, "{}", name),
+            Expr::Negate(inner) => {
+                write!(f, "-")?;
+                operand(f, inner)
+            }
+            Expr::Not(inner) => {
+                write!(f, "~")?;
+                operand(f, inner)
+            }
+            Expr::High(inner) => write!(f, "hi({})", inner),
+            Expr::Low(inner) => write!(f, "lo({})", inner),
+            Expr::Binary(op, left, right) => {
+                operand(f, left)?;
+                write!(f, "{}", op.symbol())?;
+                operand(f, right)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::expr::parse;
+
+    fn value(text: &str) -> i32 {
+        let lookup = |name: &str| match name {
+            "TABLE" => Some(0x3010),
+            "SIZE" => Some(8),
+            _ => None,
+        };
+        parse(text).unwrap().eval(&lookup).unwrap()
+    }
+
+    // ========== Evaluation ==========
+
+    #[test]
+    fn test_labels_constants_and_arithmetic() {
+        assert_eq!(value("TABLE+3"), 0x3013);
+        assert_eq!(value("#SIZE-1"), 7);
+        assert_eq!(value("SIZE*2+1"), 17);
+        assert_eq!(value("SIZE*(2+1)"), 24);
+        assert_eq!(value("-SIZE"), -8);
+        assert_eq!(value("x10 | b11 & ~1"), 0x12);
+        assert_eq!(value("1 << 4 >> 2"), 4);
+        assert_eq!(value("SIZE*2 >= 16"), 1);
+        assert_eq!(value("SIZE != 8"), 0);
+    }
+
+    #[test]
+    fn test_high_and_low_bytes() {
+        assert_eq!(value("hi(TABLE)"), 0x30);
+        assert_eq!(value("lo(TABLE)"), 0x10);
+        assert_eq!(value("LO(xABCD)"), 0xCD);
+    }
+
+    #[test]
+    fn test_names_and_display() {
+        let expr = parse("(TABLE + SIZE) * 2").unwrap();
+        assert_eq!(expr.names(), ["TABLE", "SIZE"]);
+        assert_eq!(expr.to_string(), "(TABLE+SIZE)*2");
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_malformed_expressions() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(error("TABLE+"), "expression ends too early");
+        assert_eq!(error("(TABLE"), "missing ')'");
+        assert_eq!(error("TABLE)"), "unmatched ')'");
+        assert_eq!(
+            error("mid(TABLE)"),
+            "unknown function 'mid', expected hi or lo"
+        );
+        assert_eq!(error("3abc"), "invalid number '3abc'");
+        assert_eq!(
+            parse("TABLE/0").unwrap().eval(&|_| Some(1)).unwrap_err(),
+            "division by zero in 'TABLE/0'"
+        );
+        assert_eq!(
+            parse("NOWHERE+1").unwrap().eval(&|_| None).unwrap_err(),
+            "undefined label 'NOWHERE'"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use crate::asm::expr::{self, Expr};
+use crate::asm::AsmError;
+
+/// One operand as written in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(u16),
+    Number(i32),
+    Label(String),
+    String(String),
+    /// Arithmetic on numbers, labels and constants, such as `TABLE+3`.
+    Expression(Expr),
+}
+
+/// A source line split into its parts. `operation` is upper-cased; labels
+/// keep their case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub line: usize,
+    pub label: Option<String>,
+    pub operation: Option<String>,
+    pub operands: Vec<Operand>,
+}
+
+/// Every mnemonic and trap alias the assembler knows. Branches are listed
+/// once, as `BR`.
+pub const OPCODES: [&str; 23] = [
+    "ADD", "AND", "NOT", "BR", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI",
+    "STR", "TRAP", "RTI", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT",
+];
+
+/// Whether `word` is a mnemonic, trap alias or directive rather than a
+/// label.
+pub fn is_operation(word: &str) -> bool {
+    let word = word.to_ascii_uppercase();
+    if word.starts_with('.') {
+        return true;
+    }
+    if let Some(conditions) = word.strip_prefix("BR") {
+        return is_condition_mask(conditions);
+    }
+    OPCODES.contains(&word.as_str())
+}
+
+/// Whether `conditions` is a valid, possibly empty, `nzp` suffix.
+pub fn is_condition_mask(conditions: &str) -> bool {
+    let order = ['N', 'Z', 'P'];
+    let mut next = 0;
+    for c in conditions.chars() {
+        match order[next..].iter().position(|&o| o == c) {
+            Some(position) => next += position + 1,
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Parses an LC-3 number: `#10` or `10` in decimal, `x1F` or `0x1F` in hex
+/// and `b101` in binary, each with an optional minus sign after the prefix.
+pub fn parse_number(text: &str) -> Option<i32> {
+    let (digits, radix) = if let Some(rest) = text.strip_prefix('#') {
+        (rest, 10)
+    } else if let Some(rest) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (rest, 16)
+    } else if let Some(rest) = text.strip_prefix('x').or_else(|| text.strip_prefix('X')) {
+        (rest, 16)
+    } else if let Some(rest) = text.strip_prefix('b').or_else(|| text.strip_prefix('B')) {
+        (rest, 2)
+    } else {
+        (text, 10)
+    };
+
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i32::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
+/// Characters that make a word an expression rather than a label.
+const EXPRESSION_CHARACTERS: [char; 13] = [
+    '+', '-', '*', '/', '%', '&', '|', '^', '~', '<', '>', '(', '#',
+];
+
+fn operand(word: &str) -> Result<Operand, String> {
+    let register = word
+        .strip_prefix(['R', 'r'])
+        .filter(|number| number.len() == 1)
+        .and_then(|number| number.parse::<u16>().ok())
+        .filter(|&number| number < 8);
+    if let Some(register) = register {
+        return Ok(Operand::Register(register));
+    }
+    if let Some(number) = parse_number(word) {
+        return Ok(Operand::Number(number));
+    }
+    if word.contains(EXPRESSION_CHARACTERS) {
+        return expr::parse(word)
+            .map(Operand::Expression)
+            .map_err(|e| format!("bad expression '{}': {}", word, e));
+    }
+    Ok(Operand::Label(word.to_string()))
+}
+
+/// Splits a line into words and string literals, dropping the comment.
+fn tokens(text: &str, line: usize) -> Result<Vec<Operand>, AsmError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || c == ',' {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => string.push(match chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('e') => '\x1B',
+                        Some(other) => other,
+                        None => break,
+                    }),
+                    Some(other) => string.push(other),
+                    None => {
+                        return Err(AsmError {
+                            line,
+                            message: "unterminated string".to_string(),
+                        });
+                    }
+                }
+            }
+            tokens.push(Operand::String(string));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Operand::Label(word));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses one source line. `line` is 1-based and only used for errors.
+pub fn parse_line(text: &str, line: usize) -> Result<Statement, AsmError> {
+    let mut tokens = tokens(text, line)?.into_iter();
+    let mut statement = Statement {
+        line,
+        label: None,
+        operation: None,
+        operands: Vec::new(),
+    };
+
+    let mut next = tokens.next();
+    let label = match &next {
+        Some(Operand::Label(word)) if !is_operation(word) => Some(word.clone()),
+        _ => None,
+    };
+    if let Some(word) = label {
+        // `MOV R1, R2` is a misspelt operation rather than a label
+        let misspelt = match tokens.as_slice().first() {
+            Some(Operand::Label(following)) => matches!(
+                operand(following),
+                Ok(Operand::Register(_) | Operand::Number(_))
+            ),
+            _ => false,
+        };
+        if misspelt {
+            return Err(AsmError {
+                line,
+                message: format!("unknown operation '{}'", word),
+            });
+        }
+
+        let label = word.strip_suffix(':').unwrap_or(&word);
+        if label.is_empty() || !label.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(AsmError {
+                line,
+                message: format!("invalid label '{}'", word),
+            });
+        }
+        statement.label = Some(label.to_string());
+        next = tokens.next();
+    }
+
+    match next {
+        None => return Ok(statement),
+        Some(Operand::Label(word)) if is_operation(&word) => {
+            statement.operation = Some(word.to_ascii_uppercase());
+        }
+        Some(Operand::Label(word)) => {
+            return Err(AsmError {
+                line,
+                message: format!("unknown operation '{}'", word),
+            });
+        }
+        Some(_) => {
+            return Err(AsmError {
+                line,
+                message: "expected an operation before the string".to_string(),
+            });
+        }
+    }
+
+    statement.operands = tokens
+        .map(|token| match token {
+            Operand::Label(word) => operand(&word).map_err(|message| AsmError { line, message }),
+            other => Ok(other),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(statement)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::expr;
+    use crate::asm::lexer::{is_operation, parse_line, parse_number, Operand};
+
+    // ========== Numbers ==========
+
+    #[test]
+    fn test_parse_number_prefixes() {
+        assert_eq!(parse_number("#10"), Some(10));
+        assert_eq!(parse_number("#-16"), Some(-16));
+        assert_eq!(parse_number("x3000"), Some(0x3000));
+        assert_eq!(parse_number("0xFE00"), Some(0xFE00));
+        assert_eq!(parse_number("x-1"), Some(-1));
+        assert_eq!(parse_number("b101"), Some(5));
+        assert_eq!(parse_number("42"), Some(42));
+    }
+
+    #[test]
+    fn test_parse_number_leaves_labels_alone() {
+        assert_eq!(parse_number("xyz"), None);
+        assert_eq!(parse_number("BAD"), None);
+        assert_eq!(parse_number("#"), None);
+        assert_eq!(parse_number("LOOP"), None);
+    }
+
+    // ========== Lines ==========
+
+    #[test]
+    fn test_label_operation_and_operands() {
+        let statement = parse_line("LOOP  ADD R1, R1, #-1 ; count down", 3).unwrap();
+
+        assert_eq!(statement.line, 3);
+        assert_eq!(statement.label.as_deref(), Some("LOOP"));
+        assert_eq!(statement.operation.as_deref(), Some("ADD"));
+        assert_eq!(
+            statement.operands,
+            [
+                Operand::Register(1),
+                Operand::Register(1),
+                Operand::Number(-1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operation_without_label_is_case_insensitive() {
+        let statement = parse_line("  brnzp done", 1).unwrap();
+
+        assert_eq!(statement.label, None);
+        assert_eq!(statement.operation.as_deref(), Some("BRNZP"));
+        assert_eq!(statement.operands, [Operand::Label("done".to_string())]);
+    }
+
+    #[test]
+    fn test_string_literal_keeps_spaces_semicolons_and_escapes() {
+        let statement = parse_line("MSG .STRINGZ \"a; b\\n\"", 1).unwrap();
+
+        assert_eq!(statement.operands, [Operand::String("a; b\n".to_string())]);
+    }
+
+    #[test]
+    fn test_label_alone_and_trailing_colon() {
+        let statement = parse_line("DONE:", 1).unwrap();
+
+        assert_eq!(statement.label.as_deref(), Some("DONE"));
+        assert_eq!(statement.operation, None);
+    }
+
+    #[test]
+    fn test_unknown_operation_after_label() {
+        let error = parse_line("START MOV R1, R2", 7).unwrap_err();
+
+        assert_eq!(error.line, 7);
+        assert_eq!(error.message, "unknown operation 'MOV'");
+    }
+
+    #[test]
+    fn test_expressions_are_parsed() {
+        let statement = parse_line("ADD R1, R1, #SIZE-1", 1).unwrap();
+        assert_eq!(
+            statement.operands[2],
+            Operand::Expression(expr::parse("SIZE-1").unwrap())
+        );
+        assert_eq!(
+            parse_line(".FILL TABLE+", 4).unwrap_err().message,
+            "bad expression 'TABLE+': expression ends too early"
+        );
+    }
+
+    #[test]
+    fn test_branch_masks_must_be_in_nzp_order() {
+        assert!(is_operation("BRnp"));
+        assert!(is_operation("BR"));
+        assert!(!is_operation("BRpn"));
+        assert!(!is_operation("BRANCH"));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::asm::preprocess::{Line, Location};
+use std::fmt;
+
+/// One line of expanded source and the words it assembled to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingLine {
+    /// Where the first word went. Only meaningful when there are words.
+    pub address: u16,
+    pub words: Vec<u16>,
+    /// The line in the source, or the macro call for expanded code.
+    pub location: Location,
+    pub text: String,
+}
+
+/// Every source line next to the words it produced, in the layout of a
+/// classic assembler listing:
+///
+/// ```text
+/// x3000  5260  0101001001100000      3  LOOP  AND R1, R1, #0
+/// ```
+///
+/// Lines that produce no words, such as comments and `.ORIG`, are listed
+/// without an address.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+}
+
+impl Listing {
+    /// Pairs expanded `lines` with the words assembled from them. `sources`
+    /// gives the 1-based index into `lines` of each word.
+    pub fn new(lines: &[Line], origin: u16, words: &[u16], sources: &[usize]) -> Listing {
+        let mut listing: Vec<ListingLine> = lines
+            .iter()
+            .map(|line| ListingLine {
+                address: 0,
+                words: Vec::new(),
+                location: line.location.clone(),
+                text: line.text.clone(),
+            })
+            .collect();
+        for (offset, (&word, &source)) in words.iter().zip(sources).enumerate() {
+            let Some(line) = listing.get_mut(source.wrapping_sub(1)) else {
+                continue;
+            };
+            if line.words.is_empty() {
+                line.address = origin.wrapping_add(offset as u16);
+            }
+            line.words.push(word);
+        }
+        Listing { lines: listing }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            let location = match &line.location.file {
+                Some(file) => format!("{}:{}", file, line.location.line),
+                None => line.location.line.to_string(),
+            };
+            match line.words.split_first() {
+                Some((first, _)) => {
+                    write!(f, "x{:04X}  {:04X}  {:016b}", line.address, first, first)?
+                }
+                None => write!(f, "{:31}", "")?,
+            }
+            writeln!(f, "  {:>5}  {}", location, line.text.trim_end())?;
+
+            for (index, word) in line.words.iter().enumerate().skip(1) {
+                let address = line.address.wrapping_add(index as u16);
+                writeln!(f, "x{:04X}  {:04X}  {:016b}", address, word, word)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_listing_shows_words_next_to_their_source() {
+        let assembly =
+            assemble(".ORIG x3000\n; greet\nLEA R0, MSG\nMSG .STRINGZ \"Hi\"\n.END\n").unwrap();
+
+        assert_eq!(
+            assembly.listing.to_string(),
+            "                                     1  .ORIG x3000\n\
+             \x20                                    2  ; greet\n\
+             x3000  E000  1110000000000000      3  LEA R0, MSG\n\
+             x3001  0048  0000000001001000      4  MSG .STRINGZ \"Hi\"\n\
+             x3002  0069  0000000001101001\n\
+             x3003  0000  0000000000000000\n\
+             \x20                                    5  .END\n"
+        );
+    }
+
+    #[test]
+    fn test_macro_lines_are_listed_at_their_call() {
+        let assembly =
+            assemble(".MACRO TWICE op\nop\nop\n.ENDM\n.ORIG x3000\nTWICE HALT\n.END\n").unwrap();
+
+        let lines: Vec<_> = assembly
+            .listing
+            .lines
+            .iter()
+            .filter(|line| !line.words.is_empty())
+            .map(|line| (line.address, line.location.line, line.text.as_str()))
+            .collect();
+        assert_eq!(lines, [(0x3000, 6, "HALT"), (0x3001, 6, "HALT")]);
+    }
+}
@@ -0,0 +1,838 @@
+use crate::asm::encode::{describe, encode};
+use crate::asm::lexer::{parse_line, Operand, Statement};
+use crate::asm::listing::Listing;
+use crate::asm::preprocess::{preprocess, Line, Location};
+use crate::image::Image;
+use crate::link::object::{Relocation, RelocationKind};
+use crate::symbols::source_map::{SourceLocation, SourceMap};
+use crate::symbols::symbol_table::SymbolTable;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+pub mod encode;
+pub mod expr;
+pub mod lexer;
+pub mod listing;
+pub mod preprocess;
+
+/// A problem in the source, on a 1-based line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The words assembled from one `.ORIG` block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+    /// The source line each word came from. Code from an `.INCLUDE` or a
+    /// macro gives the line that brought it in.
+    pub lines: Vec<usize>,
+    /// Where each word came from, naming included files, with macro
+    /// expansions pointing at their call.
+    pub locations: Vec<Location>,
+    /// The 1-based column each word's code starts at on its `locations`
+    /// line, or 0 when unknown.
+    pub columns: Vec<usize>,
+    /// The source with the words each line assembled to.
+    pub listing: Listing,
+    /// Whether the code had no `.ORIG` and was assembled at x0000 for the
+    /// linker to move.
+    pub relocatable: bool,
+    pub exports: Vec<String>,
+    pub imports: Vec<String>,
+    /// The words the linker has to patch, by offset from `origin`.
+    pub relocations: Vec<Relocation>,
+    /// The words `.BLKW` set aside without a value, by offset from
+    /// `origin`. They load as zeros, but the program never gave them one.
+    pub reserved: Vec<u16>,
+}
+
+impl Assembly {
+    /// The assembled words, ready for `image::write` in any format.
+    pub fn image(&self) -> Image {
+        Image::new(self.origin, self.words.clone())
+    }
+
+    /// The symbol table in the layout `lc3as` writes to `.sym` files.
+    pub fn symbol_file(&self) -> String {
+        self.symbols.to_file()
+    }
+
+    /// Maps every word to its source. `file_name` is the file that was
+    /// assembled; included files keep their own names.
+    pub fn source_map(&self, file_name: &str) -> SourceMap {
+        let mut map = SourceMap::new();
+        for (offset, location) in self.locations.iter().enumerate() {
+            map.insert(
+                self.origin.wrapping_add(offset as u16),
+                SourceLocation {
+                    file: location.file.as_deref().unwrap_or(file_name).to_string(),
+                    line: location.line as u32,
+                    column: self.columns.get(offset).copied().unwrap_or(0) as u32,
+                },
+            );
+        }
+        for &offset in &self.reserved {
+            map.reserve(self.origin.wrapping_add(offset));
+        }
+        map
+    }
+}
+
+/// How many words a statement takes up.
+fn size(statement: &Statement) -> Result<u32, String> {
+    let Some(operation) = &statement.operation else {
+        return Ok(0);
+    };
+    match operation.as_str() {
+        ".ORIG" | ".END" | ".EXPORT" | ".IMPORT" | ".EXTERNAL" => Ok(0),
+        ".FILL" => Ok(1),
+        ".BLKW" => match statement.operands.as_slice() {
+            [Operand::Number(count)] | [Operand::Number(count), _] if *count > 0 => {
+                Ok(*count as u32)
+            }
+            _ => Err(".BLKW expects a positive word count".to_string()),
+        },
+        ".STRINGZ" => match statement.operands.as_slice() {
+            [Operand::String(text)] => Ok(text.chars().count() as u32 + 1),
+            _ => Err(".STRINGZ expects one string".to_string()),
+        },
+        directive if directive.starts_with('.') => {
+            Err(format!("unknown directive '{}'", directive))
+        }
+        _ => Ok(1),
+    }
+}
+
+/// A `.FILL` value: a number or the address of a label.
+fn fill_value(operand: &Operand, symbols: &SymbolTable) -> Result<u16, String> {
+    match operand {
+        Operand::Number(value) if (-0x8000..=0xFFFF).contains(value) => Ok(*value as u16),
+        Operand::Number(value) => Err(format!("{} does not fit in 16 bits", value)),
+        Operand::Label(label) => symbols
+            .address_of(label)
+            .ok_or_else(|| format!("undefined label '{}'", label)),
+        other => Err(format!(
+            "expected a number or label, got {}",
+            describe(other)
+        )),
+    }
+}
+
+/// Replaces constants, and expressions over nothing but constants, with
+/// their values.
+fn fold_constants(
+    mut statement: Statement,
+    constants: &BTreeMap<String, i32>,
+) -> Result<Statement, AsmError> {
+    for operand in &mut statement.operands {
+        let value = match operand {
+            Operand::Label(name) => match constants.get(name) {
+                Some(&value) => Ok(value),
+                None => continue,
+            },
+            Operand::Expression(expr)
+                if expr
+                    .names()
+                    .iter()
+                    .all(|name| constants.contains_key(*name)) =>
+            {
+                expr.eval(&|name| constants.get(name).copied())
+            }
+            _ => continue,
+        };
+        *operand = Operand::Number(value.map_err(|message| AsmError {
+            line: statement.line,
+            message,
+        })?);
+    }
+    Ok(statement)
+}
+
+/// Evaluates the expressions left after `fold_constants`, which refer to
+/// labels and perhaps constants. In a PC offset field the result is the address to reach;
+/// elsewhere it is the value itself.
+fn resolve_expressions(
+    operation: &str,
+    operands: &[Operand],
+    address: u16,
+    symbols: &SymbolTable,
+    constants: &BTreeMap<String, i32>,
+) -> Result<Vec<Operand>, String> {
+    let pc_relative = matches!(
+        relocation_kind(operation),
+        Some(RelocationKind::PcOffset9 | RelocationKind::PcOffset11)
+    );
+    let lookup = |name: &str| {
+        constants
+            .get(name)
+            .copied()
+            .or_else(|| symbols.address_of(name).map(i32::from))
+    };
+    operands
+        .iter()
+        .enumerate()
+        .map(|(index, operand)| match operand {
+            Operand::Expression(expr) => {
+                let value = expr.eval(&lookup)?;
+                if pc_relative && index == operands.len() - 1 {
+                    Ok(Operand::Number(value - (address as i32 + 1)))
+                } else {
+                    Ok(Operand::Number(value))
+                }
+            }
+            other => Ok(other.clone()),
+        })
+        .collect()
+}
+
+/// The relocation an imported label needs when `operation` refers to it.
+fn relocation_kind(operation: &str) -> Option<RelocationKind> {
+    match operation {
+        ".FILL" | ".BLKW" => Some(RelocationKind::Word),
+        "JSR" => Some(RelocationKind::PcOffset11),
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => Some(RelocationKind::PcOffset9),
+        branch if branch.starts_with("BR") => Some(RelocationKind::PcOffset9),
+        _ => None,
+    }
+}
+
+/// The labels a `.EXPORT` or `.IMPORT` line names.
+fn linkage_names(statement: &Statement) -> Result<Vec<String>, String> {
+    let operation = statement.operation.as_deref().unwrap_or_default();
+    if statement.operands.is_empty() {
+        return Err(format!("{} expects at least one label", operation));
+    }
+    statement
+        .operands
+        .iter()
+        .map(|operand| match operand {
+            Operand::Label(label) => Ok(label.clone()),
+            other => Err(format!(
+                "{} expects labels, got {}",
+                operation,
+                describe(other)
+            )),
+        })
+        .collect()
+}
+
+/// How to assemble a file.
+#[derive(Debug, Default, Clone)]
+pub struct AsmOptions {
+    /// Assemble a module for the linker; see `assemble_module`.
+    pub relocatable: bool,
+    /// Where `.INCLUDE` looks first, usually the source file's directory.
+    /// Defaults to the working directory.
+    pub directory: Option<PathBuf>,
+    /// Where `.INCLUDE` looks next, in order.
+    pub include_paths: Vec<PathBuf>,
+}
+
+/// Assembles LC-3 source with the standard directives (`.ORIG`, `.FILL`,
+/// `.BLKW`, `.STRINGZ`, `.END`). Every problem found is returned, not just
+/// the first.
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AsmError>> {
+    assemble_with(source, &AsmOptions::default())
+}
+
+/// Assembles one module of a multi-file program. `.IMPORT` names labels
+/// other modules define and `.EXPORT` offers labels to them; `.EXTERNAL`
+/// is accepted for `.IMPORT`. Without `.ORIG` the linker picks the address.
+pub fn assemble_module(source: &str) -> Result<Assembly, Vec<AsmError>> {
+    let options = AsmOptions {
+        relocatable: true,
+        ..AsmOptions::default()
+    };
+    assemble_with(source, &options)
+}
+
+/// Assembles `source` after expanding its includes, macros and conditional
+/// blocks as described in `preprocess`. Operands may be expressions such as
+/// `TABLE+3`, `#SIZE-1` or `hi(TABLE)`, written without spaces.
+pub fn assemble_with(source: &str, options: &AsmOptions) -> Result<Assembly, Vec<AsmError>> {
+    let (expansion, mut errors) = preprocess(source, options);
+    let lines = &expansion.lines;
+    // The assembler numbers expanded lines; put errors back on source lines
+    let on_source = |error: AsmError| match lines.get(error.line.wrapping_sub(1)) {
+        Some(line) => AsmError {
+            line: line.line,
+            message: match &line.context {
+                Some(context) => format!("{} ({})", error.message, context),
+                None => error.message,
+            },
+        },
+        None => error,
+    };
+
+    match assemble_lines(lines, &expansion.constants, options.relocatable) {
+        Ok(mut assembly) if errors.is_empty() => {
+            assembly.listing =
+                Listing::new(lines, assembly.origin, &assembly.words, &assembly.lines);
+            assembly.columns = assembly
+                .lines
+                .iter()
+                .map(|&index| lines[index - 1].column)
+                .collect();
+            assembly.locations = assembly
+                .lines
+                .iter()
+                .map(|&index| lines[index - 1].location.clone())
+                .collect();
+            for line in &mut assembly.lines {
+                *line = lines[*line - 1].line;
+            }
+            Ok(assembly)
+        }
+        Ok(_) => Err(errors),
+        Err(more) => {
+            errors.extend(more.into_iter().map(on_source));
+            errors.sort_by_key(|error| error.line);
+            Err(errors)
+        }
+    }
+}
+
+/// Assembles expanded lines. Statements and errors are numbered by their
+/// position in `lines`.
+fn assemble_lines(
+    lines: &[Line],
+    constants: &BTreeMap<String, i32>,
+    relocatable: bool,
+) -> Result<Assembly, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let mut statements = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        match parse_line(&line.text, index + 1).and_then(|s| fold_constants(s, constants)) {
+            Ok(statement) => {
+                let end = statement.operation.as_deref() == Some(".END");
+                statements.push(statement);
+                if end {
+                    break;
+                }
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+
+    // The first pass places every statement and records the labels
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut symbols = SymbolTable::new();
+    let mut placed = Vec::new();
+    let mut exports = Vec::new();
+    let mut imports = Vec::new();
+    let mut floating = false;
+    for statement in statements {
+        let line = statement.line;
+        let error = |message: String| AsmError { line, message };
+        let operation = statement.operation.as_deref();
+
+        // Linkage directives may come before .ORIG
+        if let Some(directive @ (".EXPORT" | ".IMPORT" | ".EXTERNAL")) = operation {
+            match linkage_names(&statement) {
+                Ok(names) if directive == ".EXPORT" => {
+                    exports.extend(names.into_iter().map(|name| (name, line)))
+                }
+                Ok(_) if !relocatable => errors.push(error(format!(
+                    "{} needs a relocatable module (asm --relocatable)",
+                    directive
+                ))),
+                Ok(names) => imports.extend(names.into_iter().map(|name| (name, line))),
+                Err(message) => errors.push(error(message)),
+            }
+            continue;
+        }
+
+        if origin.is_none() {
+            match (operation, statement.operands.as_slice()) {
+                (None, _) if statement.label.is_none() => continue,
+                (Some(".ORIG"), [Operand::Number(value)]) if (0..=0xFFFF).contains(value) => {
+                    origin = Some(*value as u16);
+                    address = *value as u32;
+                    continue;
+                }
+                (Some(".ORIG"), _) => {
+                    errors.push(error(".ORIG expects an address x0000..xFFFF".to_string()));
+                    return Err(errors);
+                }
+                _ if relocatable => {
+                    origin = Some(0);
+                    floating = true;
+                }
+                _ => {
+                    errors.push(error("expected .ORIG before any code".to_string()));
+                    return Err(errors);
+                }
+            }
+        }
+        match operation {
+            Some(".END") => break,
+            Some(".ORIG") => {
+                errors.push(error("only one .ORIG block is supported".to_string()));
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some(label) = &statement.label {
+            if constants.contains_key(label) {
+                errors.push(error(format!("label '{}' is already a constant", label)));
+            } else if symbols.address_of(label).is_some() {
+                errors.push(error(format!("label '{}' is defined twice", label)));
+            } else {
+                symbols.insert(label, address as u16);
+            }
+        }
+        match size(&statement) {
+            Ok(words) => {
+                placed.push((address as u16, statement));
+                address += words;
+            }
+            Err(message) => errors.push(error(message)),
+        }
+        if address > 0x10000 {
+            errors.push(error("code runs past xFFFF".to_string()));
+            return Err(errors);
+        }
+    }
+    let origin = match origin {
+        Some(origin) => origin,
+        None if relocatable => {
+            floating = true;
+            0
+        }
+        None if !errors.is_empty() => return Err(errors),
+        None => {
+            errors.push(AsmError {
+                line: lines.len().max(1),
+                message: "no .ORIG found".to_string(),
+            });
+            return Err(errors);
+        }
+    };
+
+    for (name, line) in &exports {
+        if symbols.address_of(name).is_none() {
+            errors.push(AsmError {
+                line: *line,
+                message: format!("exported label '{}' is not defined", name),
+            });
+        }
+    }
+    for (name, line) in &imports {
+        if symbols.address_of(name).is_some() {
+            errors.push(AsmError {
+                line: *line,
+                message: format!("label '{}' is both imported and defined here", name),
+            });
+        }
+    }
+
+    // The second pass encodes, now that every label is known
+    let mut assembly = Assembly {
+        origin,
+        words: Vec::new(),
+        symbols,
+        lines: Vec::new(),
+        locations: Vec::new(),
+        columns: Vec::new(),
+        listing: Listing::default(),
+        relocatable: floating,
+        exports: exports.into_iter().map(|(name, _)| name).collect(),
+        imports: imports.into_iter().map(|(name, _)| name).collect(),
+        relocations: Vec::new(),
+        reserved: Vec::new(),
+    };
+    for (address, statement) in placed {
+        let Some(operation) = statement.operation.as_deref() else {
+            continue;
+        };
+
+        // Imported labels encode as a zero offset or address for the
+        // linker to patch, and so do addresses of labels that will move
+        let reference = match statement.operands.last() {
+            Some(Operand::Label(label)) => Some(label.as_str()),
+            _ => None,
+        };
+        let data = matches!(operation, ".FILL" | ".BLKW");
+        let imported = reference.filter(|label| assembly.imports.iter().any(|name| name == label));
+        let relocated = imported.or(reference.filter(|_| floating && data));
+        let mut imported_symbols = None;
+        if let Some(label) = imported {
+            let mut symbols = assembly.symbols.clone();
+            symbols.insert(label, if data { 0 } else { address.wrapping_add(1) });
+            imported_symbols = Some(symbols);
+        }
+        let symbols = imported_symbols.as_ref().unwrap_or(&assembly.symbols);
+
+        // The linker patches plain labels only. A local label keeps its
+        // distance from the code, so PC offsets to it are still safe.
+        let pc_relative = !matches!(
+            relocation_kind(operation),
+            None | Some(RelocationKind::Word)
+        );
+        let moved = |name: &str| {
+            assembly.imports.iter().any(|import| import == name)
+                || (floating && !pc_relative && assembly.symbols.address_of(name).is_some())
+        };
+        let unrelocatable = statement.operands.iter().find_map(|operand| match operand {
+            Operand::Expression(expr) => expr.names().into_iter().find(|name| moved(name)),
+            _ => None,
+        });
+        if let Some(name) = unrelocatable {
+            errors.push(AsmError {
+                line: statement.line,
+                message: format!(
+                    "'{}' moves when linked, so it can only be used on its own",
+                    name
+                ),
+            });
+            continue;
+        }
+        let operands = match resolve_expressions(
+            operation,
+            &statement.operands,
+            address,
+            symbols,
+            constants,
+        ) {
+            Ok(operands) => operands,
+            Err(message) => {
+                errors.push(AsmError {
+                    line: statement.line,
+                    message,
+                });
+                continue;
+            }
+        };
+
+        let words = match (operation, operands.as_slice()) {
+            (".FILL", [value]) => fill_value(value, symbols).map(|word| vec![word]),
+            (".FILL", _) => Err(".FILL expects one value".to_string()),
+            (".BLKW", [Operand::Number(count)]) => Ok(vec![0; *count as usize]),
+            (".BLKW", [Operand::Number(count), value]) => {
+                fill_value(value, symbols).map(|word| vec![word; *count as usize])
+            }
+            (".STRINGZ", [Operand::String(text)]) => Ok(text
+                .chars()
+                .map(|c| c as u16)
+                .chain(std::iter::once(0))
+                .collect()),
+            _ => encode(operation, &operands, address, symbols).map(|word| vec![word]),
+        };
+        let words = match (words, relocated) {
+            (Ok(words), Some(label)) => match relocation_kind(operation) {
+                Some(kind) => {
+                    let offset = address.wrapping_sub(origin);
+                    for index in 0..words.len() as u16 {
+                        assembly.relocations.push(Relocation {
+                            offset: offset + index,
+                            kind,
+                            symbol: label.to_string(),
+                        });
+                    }
+                    Ok(words)
+                }
+                None => Err(format!(
+                    "imported label '{}' can only be a PC offset or a .FILL value",
+                    label
+                )),
+            },
+            (words, _) => words,
+        };
+        match words {
+            Ok(words) => {
+                if let (".BLKW", [_]) = (operation, operands.as_slice()) {
+                    let offset = address.wrapping_sub(origin);
+                    assembly
+                        .reserved
+                        .extend((0..words.len() as u16).map(|index| offset + index));
+                }
+                assembly
+                    .lines
+                    .extend(std::iter::repeat_n(statement.line, words.len()));
+                assembly.words.extend(words);
+            }
+            Err(message) => errors.push(AsmError {
+                line: statement.line,
+                message,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(assembly)
+    } else {
+        errors.sort_by_key(|error| error.line);
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{assemble, assemble_module, AsmError};
+    use crate::image::{write, Format};
+    use crate::link::object::{Relocation, RelocationKind};
+    use crate::symbols::symbol_table::SymbolTable;
+    use crate::Vm;
+
+    const HELLO: &str = "\
+; Prints a greeting five times
+        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #5
+LOOP    LEA R0, MSG
+        PUTS
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+MSG     .STRINGZ \"Hi\\n\"
+        .END
+";
+
+    // ========== Assembling ==========
+
+    #[test]
+    fn test_assembles_origin_words_and_labels() {
+        let assembly = assemble(HELLO).unwrap();
+
+        assert_eq!(assembly.origin, 0x3000);
+        assert_eq!(
+            assembly.words,
+            [0x5260, 0x1265, 0xE004, 0xF022, 0x127F, 0x03FC, 0xF025, 0x48, 0x69, 0x0A, 0x00]
+        );
+        assert_eq!(assembly.symbols.address_of("LOOP"), Some(0x3002));
+        assert_eq!(assembly.symbols.address_of("MSG"), Some(0x3007));
+        assert_eq!(assembly.lines[0], 3);
+        assert_eq!(assembly.lines[7..], [10, 10, 10, 10]);
+    }
+
+    #[test]
+    fn test_image_runs_in_the_vm() {
+        let assembly = assemble(HELLO).unwrap();
+        let path = std::env::temp_dir().join("rustvm_asm_hello.obj");
+        std::fs::write(&path, write(&assembly.image(), Format::Object).unwrap()).unwrap();
+
+        let mut vm = Vm::new();
+        vm.output = Some(String::new());
+        assert!(vm.read_file(path.to_str().unwrap()));
+        vm.registers[8] = 0x3000;
+        vm.run();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(vm.output.unwrap().starts_with("Hi\nHi\nHi\nHi\nHi\n"));
+    }
+
+    #[test]
+    fn test_fill_blkw_and_label_values() {
+        let assembly =
+            assemble(".ORIG x4000\nPTR .FILL DATA\n.FILL #-1\nDATA .BLKW 2\n.BLKW 2 xBEEF\n.END\n")
+                .unwrap();
+
+        assert_eq!(assembly.words, [0x4002, 0xFFFF, 0, 0, 0xBEEF, 0xBEEF]);
+    }
+
+    #[test]
+    fn test_code_after_end_is_ignored() {
+        let assembly = assemble(".ORIG x3000\nHALT\n.END\nthis is not code\n").unwrap();
+
+        assert_eq!(assembly.words, [0xF025]);
+    }
+
+    #[test]
+    fn test_modules_record_relocations() {
+        let assembly = assemble_module(
+            ".IMPORT DATA\nLOOP LD R0, DATA\nBR LOOP\nPTR .FILL LOOP\n.FILL DATA\n",
+        )
+        .unwrap();
+        let relocation = |offset, kind, symbol: &str| Relocation {
+            offset,
+            kind,
+            symbol: symbol.to_string(),
+        };
+
+        assert!(assembly.relocatable);
+        assert_eq!(assembly.origin, 0);
+        assert_eq!(assembly.words, [0x2000, 0x0FFE, 0x0000, 0x0000]);
+        assert_eq!(
+            assembly.relocations,
+            [
+                relocation(0, RelocationKind::PcOffset9, "DATA"),
+                relocation(2, RelocationKind::Word, "LOOP"),
+                relocation(3, RelocationKind::Word, "DATA"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expressions_and_constants() {
+        let assembly = assemble(
+            "BASE .EQU x3000\nSIZE .EQU 3\n.ORIG BASE+1\nLEA R0, TABLE+2\n\
+             ADD R1, R1, #SIZE-1\n.FILL hi(TABLE)\n.FILL lo(TABLE)*2\n\
+             TABLE .BLKW SIZE\n.FILL TABLE+SIZE\n.END\n",
+        )
+        .unwrap();
+
+        assert_eq!(assembly.origin, 0x3001);
+        assert_eq!(
+            assembly.words,
+            [0xE005, 0x1262, 0x0030, 0x000A, 0, 0, 0, 0x3008]
+        );
+        assert_eq!(assembly.symbols.address_of("SIZE"), None);
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_reports_every_error_with_its_line() {
+        let errors = assemble(
+            ".ORIG x3000\nADD R1, R1, #20\nBR NOWHERE\nX .FILL 1\nX .FILL 2\nMOV R1\n.END\n",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors,
+            [
+                AsmError {
+                    line: 2,
+                    message: "immediate 20 does not fit in 5 bits (-16..15)".to_string()
+                },
+                AsmError {
+                    line: 3,
+                    message: "undefined label 'NOWHERE'".to_string()
+                },
+                AsmError {
+                    line: 5,
+                    message: "label 'X' is defined twice".to_string()
+                },
+                AsmError {
+                    line: 6,
+                    message: "unknown operation 'MOV'".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_before_orig_is_an_error() {
+        let errors = assemble("ADD R1, R1, #1\n").unwrap_err();
+
+        assert_eq!(errors[0].message, "expected .ORIG before any code");
+    }
+
+    #[test]
+    fn test_linkage_mistakes_are_errors() {
+        let message = |source: &str, module: bool| {
+            let errors = if module {
+                assemble_module(source)
+            } else {
+                assemble(source)
+            };
+            errors.unwrap_err()[0].message.clone()
+        };
+        assert_eq!(
+            message(".IMPORT PUTC\n.ORIG x3000\nHALT\n", false),
+            ".IMPORT needs a relocatable module (asm --relocatable)"
+        );
+        assert_eq!(
+            message(".EXPORT MAIN\nHALT\n", true),
+            "exported label 'MAIN' is not defined"
+        );
+        assert_eq!(
+            message(".IMPORT X\nX HALT\n", true),
+            "label 'X' is both imported and defined here"
+        );
+        assert_eq!(
+            message(".IMPORT X\n.FILL X+1\n", true),
+            "'X' moves when linked, so it can only be used on its own"
+        );
+        assert_eq!(
+            message("HERE BR HERE+1\nPTR .FILL HERE+1\n", true),
+            "'HERE' moves when linked, so it can only be used on its own"
+        );
+    }
+
+    #[test]
+    fn test_errors_in_macros_name_the_call() {
+        let errors = assemble(
+            ".MACRO BUMP reg\nADD reg, reg, #99\n.ENDM\n.ORIG x3000\nBUMP R1\n\
+             N .EQU 1\nN HALT\n.END\n",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors,
+            [
+                AsmError {
+                    line: 5,
+                    message:
+                        "immediate 99 does not fit in 5 bits (-16..15) (in macro BUMP, line 2)"
+                            .to_string()
+                },
+                AsmError {
+                    line: 7,
+                    message: "label 'N' is already a constant".to_string()
+                },
+            ]
+        );
+    }
+
+    // ========== Outputs ==========
+
+    #[test]
+    fn test_symbol_file_reads_back() {
+        let assembly = assemble(HELLO).unwrap();
+
+        let table = SymbolTable::parse(&assembly.symbol_file()).unwrap();
+
+        assert_eq!(table, assembly.symbols);
+    }
+
+    #[test]
+    fn test_source_map_points_at_lines() {
+        let assembly = assemble(HELLO).unwrap();
+
+        let map = assembly.source_map("hello.asm");
+
+        assert_eq!(map.lookup(0x3002).unwrap().to_string(), "hello.asm:5");
+        assert_eq!(map.lookup(0x3006).unwrap().to_string(), "hello.asm:9");
+        assert_eq!(map.lookup(0x3001).unwrap().column, 9);
+        assert_eq!(map.lookup(0x3002).unwrap().column, 1);
+    }
+
+    #[test]
+    fn test_source_map_marks_reserved_words() {
+        let assembly =
+            assemble(".ORIG x3000\nHALT\nA .BLKW 2\nB .BLKW 1 x5\nC .FILL 0\n.END\n").unwrap();
+
+        assert_eq!(assembly.reserved, [1, 2]);
+        let map = assembly.source_map("data.asm");
+        assert_eq!(map.reserved().collect::<Vec<_>>(), [0x3001, 0x3002]);
+    }
+
+    #[test]
+    fn test_source_map_follows_macros_to_their_call() {
+        let assembly =
+            assemble(".MACRO TWICE op\nop\nop\n.ENDM\n.ORIG x3000\nTWICE HALT\nTWICE RET\n.END\n")
+                .unwrap();
+
+        let map = assembly.source_map("twice.asm");
+
+        assert_eq!(assembly.words, [0xF025, 0xF025, 0xC1C0, 0xC1C0]);
+        assert_eq!(assembly.lines, [6, 6, 7, 7]);
+        assert_eq!(map.lookup(0x3001).unwrap().to_string(), "twice.asm:6");
+        assert_eq!(map.lookup(0x3002).unwrap().to_string(), "twice.asm:7");
+    }
+}
@@ -0,0 +1,753 @@
+//! Expands `.INCLUDE`, `.DEFINE`, `.EQU`, macros and conditional assembly
+//! into plain source lines for the assembler.
+//!
+//! ```text
+//!         .INCLUDE "stdio.inc"
+//! SIZE    .EQU 8
+//!         .DEFINE SP R6
+//!         .MACRO PUSH reg
+//!         ADD SP, SP, #-1
+//!         STR reg, SP, #0
+//!         .ENDM
+//!         .IF SIZE > 4        ; also .IFDEF NAME, .IFNDEF NAME, .ELSE
+//!         PUSH R1
+//!         .ENDIF
+//! ```
+//!
+//! Inside a macro body, labels written `@NAME` are renamed on every
+//! expansion so a macro can be used more than once.
+
+use crate::asm::expr;
+use crate::asm::lexer::is_operation;
+use crate::asm::{AsmError, AsmOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How deeply includes and macro calls may nest before they are taken to
+/// recurse forever.
+const MAX_DEPTH: usize = 32;
+
+/// A line in one of the files that make up a program. `file` is `None` for
+/// the file being assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+/// One line of expanded source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub text: String,
+    /// The line of the file being assembled this one came from: itself, or
+    /// the `.INCLUDE` or macro call that brought it in.
+    pub line: usize,
+    /// Where a debugger should point: the line itself or, for code from a
+    /// macro, the outermost call.
+    pub location: Location,
+    /// The 1-based column the code starts at on the `location` line, or 0
+    /// for a line without code.
+    pub column: usize,
+    /// Where the text really is when that is not `line`, as in
+    /// `in macro PUSH, line 4`.
+    pub context: Option<String>,
+}
+
+/// The expanded program and the constants `.EQU` defined.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Expansion {
+    pub lines: Vec<Line>,
+    pub constants: BTreeMap<String, i32>,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<(String, Location)>,
+}
+
+/// Where the lines being processed came from.
+#[derive(Clone)]
+struct Site {
+    /// The line of the file being assembled, once inside an include or a
+    /// macro.
+    line: Option<usize>,
+    /// The outermost macro call.
+    call: Option<Location>,
+    /// Where the code starts on the line of `call`.
+    call_column: usize,
+    /// The innermost macro being expanded.
+    macro_name: Option<String>,
+    /// Where relative `.INCLUDE` paths start.
+    directory: PathBuf,
+    depth: usize,
+}
+
+/// One `.IF` being processed.
+struct Condition {
+    /// Whether lines in the current branch are assembled.
+    active: bool,
+    /// Whether the enclosing code is assembled at all.
+    enclosing: bool,
+    /// Whether a branch has been taken, so `.ELSE` is skipped.
+    taken: bool,
+    seen_else: bool,
+    location: Location,
+}
+
+/// Removes a `;` comment, leaving semicolons in strings alone.
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// The first word of `text` and what follows it. Words end at whitespace or
+/// a comma; a quoted string is one word.
+fn next_word(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    if text.is_empty() {
+        return None;
+    }
+    let end = if let Some(quoted) = text.strip_prefix('"') {
+        quoted.find('"').map_or(text.len(), |end| end + 2)
+    } else {
+        text.find(|c: char| c.is_whitespace() || c == ',')
+            .unwrap_or(text.len())
+    };
+    Some((&text[..end], &text[end..]))
+}
+
+/// The 1-based column of the first character of code on a line, or 0.
+fn column(text: &str) -> usize {
+    let code = strip_comment(text);
+    code.chars()
+        .position(|c| !c.is_whitespace())
+        .map_or(0, |position| position + 1)
+}
+
+fn words(mut text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    while let Some((word, rest)) = next_word(text) {
+        words.push(word);
+        text = rest;
+    }
+    words
+}
+
+fn is_word_character(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '@' || c == '.'
+}
+
+/// Replaces whole words outside strings and comments.
+fn substitute(text: &str, replace: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut word = String::new();
+    let mut chars = text.chars();
+    let finish = |out: &mut String, word: &mut String| {
+        match replace(word) {
+            Some(replacement) => out.push_str(&replacement),
+            None => out.push_str(word),
+        }
+        word.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        if is_word_character(c) {
+            word.push(c);
+            continue;
+        }
+        finish(&mut out, &mut word);
+        out.push(c);
+        match c {
+            ';' => {
+                out.push_str(chars.as_str());
+                return out;
+            }
+            '"' => {
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(&mut out, &mut word);
+    out
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !is_operation(name)
+}
+
+struct Preprocessor<'a> {
+    options: &'a AsmOptions,
+    macros: HashMap<String, Macro>,
+    defines: HashMap<String, String>,
+    constants: BTreeMap<String, i32>,
+    lines: Vec<Line>,
+    errors: Vec<AsmError>,
+    /// Expansions so far, which numbers the local labels.
+    expansions: usize,
+    /// The files being included, innermost last.
+    including: Vec<PathBuf>,
+}
+
+impl Preprocessor<'_> {
+    fn context(site: &Site, location: &Location) -> Option<String> {
+        match (&site.macro_name, &location.file) {
+            (Some(name), _) => Some(format!("in macro {}, {}", name, location)),
+            (None, Some(_)) => Some(format!("in {}", location)),
+            (None, None) => None,
+        }
+    }
+
+    fn error(&mut self, site: &Site, location: &Location, message: String) {
+        let message = match Self::context(site, location) {
+            Some(context) => format!("{} ({})", message, context),
+            None => message,
+        };
+        self.errors.push(AsmError {
+            line: site.line.unwrap_or(location.line),
+            message,
+        });
+    }
+
+    fn define(&self, text: &str) -> String {
+        if self.defines.is_empty() {
+            return text.to_string();
+        }
+        substitute(text, &|word| self.defines.get(word).cloned())
+    }
+
+    /// Evaluates an expression over the constants defined so far.
+    fn constant(&self, text: &str) -> Result<i32, String> {
+        let expr = expr::parse(&self.define(text))?;
+        expr.eval(&|name| self.constants.get(name).copied())
+            .map_err(|e| match e.strip_prefix("undefined label ") {
+                Some(name) => format!("{} is not a constant defined above", name),
+                None => e,
+            })
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.constants.contains_key(name)
+            || self.defines.contains_key(name)
+            || self.macros.contains_key(&name.to_ascii_uppercase())
+    }
+
+    fn process(&mut self, lines: Vec<(String, Location)>, site: &Site) {
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut definition: Option<(String, Macro, Location)> = None;
+
+        for (text, location) in lines {
+            let code = strip_comment(&text);
+            let (label, keyword, rest) = match next_word(code) {
+                Some((first, rest)) if first.starts_with('.') => {
+                    (None, Some(first.to_ascii_uppercase()), rest)
+                }
+                Some((first, after_first)) => match next_word(after_first) {
+                    Some((second, rest)) if second.starts_with('.') => {
+                        (Some(first), Some(second.to_ascii_uppercase()), rest)
+                    }
+                    _ => (None, None, code),
+                },
+                None => (None, None, code),
+            };
+
+            // Macro bodies are kept as written until they are expanded
+            if let Some((name, body, _)) = &mut definition {
+                match keyword.as_deref() {
+                    Some(".ENDM") => {
+                        let (name, body, _) = definition.take().unwrap();
+                        self.macros.insert(name, body);
+                    }
+                    Some(".MACRO") => {
+                        let message = format!("macro {} is missing its .ENDM", name);
+                        self.error(site, &location, message);
+                    }
+                    _ => body.body.push((text.clone(), location.clone())),
+                }
+                continue;
+            }
+
+            let active = conditions.last().is_none_or(|condition| condition.active);
+            match keyword.as_deref() {
+                Some(directive @ (".IF" | ".IFDEF" | ".IFNDEF")) => {
+                    let holds = if !active {
+                        false
+                    } else if directive == ".IF" {
+                        match self.constant(rest) {
+                            Ok(value) => value != 0,
+                            Err(e) => {
+                                self.error(site, &location, format!(".IF {}", e));
+                                false
+                            }
+                        }
+                    } else {
+                        let name = rest.trim();
+                        (directive == ".IFDEF") == self.is_defined(name)
+                    };
+                    conditions.push(Condition {
+                        active: holds,
+                        enclosing: active,
+                        taken: holds,
+                        seen_else: false,
+                        location: location.clone(),
+                    });
+                    continue;
+                }
+                Some(".ELSE") => {
+                    match conditions.last_mut() {
+                        Some(condition) if !condition.seen_else => {
+                            condition.active = condition.enclosing && !condition.taken;
+                            condition.seen_else = true;
+                        }
+                        Some(_) => self.error(site, &location, "second .ELSE for one .IF".into()),
+                        None => self.error(site, &location, ".ELSE without .IF".into()),
+                    }
+                    continue;
+                }
+                Some(".ENDIF") => {
+                    if conditions.pop().is_none() {
+                        self.error(site, &location, ".ENDIF without .IF".into());
+                    }
+                    continue;
+                }
+                _ if !active => continue,
+                _ => {}
+            }
+
+            match keyword.as_deref() {
+                Some(".MACRO") => {
+                    let mut names = words(rest).into_iter();
+                    let Some(name) = label.or_else(|| names.next()) else {
+                        self.error(site, &location, ".MACRO expects a name".into());
+                        continue;
+                    };
+                    let parameters: Vec<String> = names.map(str::to_string).collect();
+                    if let Some(bad) = std::iter::once(name)
+                        .chain(parameters.iter().map(String::as_str))
+                        .find(|name| !is_name(name))
+                    {
+                        let message = format!("'{}' cannot name a macro or parameter", bad);
+                        self.error(site, &location, message);
+                    }
+                    let body = Macro {
+                        parameters,
+                        body: Vec::new(),
+                    };
+                    definition = Some((name.to_ascii_uppercase(), body, location.clone()));
+                }
+                Some(".ENDM") => self.error(site, &location, ".ENDM without .MACRO".into()),
+                Some(".INCLUDE") => self.include(rest, site, &location),
+                Some(".DEFINE") => match next_word(rest) {
+                    Some((name, value)) if is_name(name) => {
+                        let value = self.define(value.trim());
+                        self.defines.insert(name.to_string(), value);
+                    }
+                    _ => self.error(site, &location, ".DEFINE expects a name and text".into()),
+                },
+                Some(".EQU") => {
+                    let (name, value) = match label {
+                        Some(name) => (name, rest),
+                        None => next_word(rest).unwrap_or(("", "")),
+                    };
+                    let name = name.strip_suffix(':').unwrap_or(name);
+                    if !is_name(name) {
+                        self.error(site, &location, ".EQU expects a name and a value".into());
+                    } else if self.constants.contains_key(name) {
+                        let message = format!("constant '{}' is defined twice", name);
+                        self.error(site, &location, message);
+                    } else {
+                        match self.constant(value) {
+                            Ok(value) => {
+                                self.constants.insert(name.to_string(), value);
+                            }
+                            Err(e) => self.error(site, &location, format!(".EQU {}", e)),
+                        }
+                    }
+                }
+                _ => {
+                    let text = self.define(&text);
+                    if !self.call(&text, site, &location) {
+                        let column = match site.call {
+                            Some(_) => site.call_column,
+                            None => column(&text),
+                        };
+                        self.lines.push(Line {
+                            text,
+                            line: site.line.unwrap_or(location.line),
+                            location: site.call.clone().unwrap_or(location.clone()),
+                            column,
+                            context: Self::context(site, &location),
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some((name, _, location)) = definition {
+            self.error(
+                site,
+                &location,
+                format!("macro {} is missing its .ENDM", name),
+            );
+        }
+        for condition in conditions {
+            self.error(
+                site,
+                &condition.location,
+                ".IF is missing its .ENDIF".into(),
+            );
+        }
+    }
+
+    fn include(&mut self, rest: &str, site: &Site, location: &Location) {
+        let name = rest.trim().trim_matches('"');
+        if name.is_empty() {
+            self.error(site, location, ".INCLUDE expects a file name".into());
+            return;
+        }
+        let found = std::iter::once(&site.directory)
+            .chain(&self.options.include_paths)
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file());
+        let Some(path) = found else {
+            self.error(
+                site,
+                location,
+                format!("could not find '{}' to include", name),
+            );
+            return;
+        };
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.including.contains(&canonical) || site.depth >= MAX_DEPTH {
+            self.error(site, location, format!("'{}' includes itself", name));
+            return;
+        }
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.error(site, location, format!("could not read '{}': {}", name, e));
+                return;
+            }
+        };
+
+        let file = path.to_string_lossy().into_owned();
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| {
+                let location = Location {
+                    file: Some(file.clone()),
+                    line: index + 1,
+                };
+                (line.to_string(), location)
+            })
+            .collect();
+        let inner = Site {
+            line: Some(site.line.unwrap_or(location.line)),
+            directory: path.parent().map_or(PathBuf::new(), Path::to_path_buf),
+            depth: site.depth + 1,
+            ..site.clone()
+        };
+        self.including.push(canonical);
+        self.process(lines, &inner);
+        self.including.pop();
+    }
+
+    /// Expands `text` if it calls a macro, returning whether it did.
+    fn call(&mut self, text: &str, site: &Site, location: &Location) -> bool {
+        let code = strip_comment(text);
+        let Some((first, after_first)) = next_word(code) else {
+            return false;
+        };
+        let (label, name, arguments) = if self.macros.contains_key(&first.to_ascii_uppercase()) {
+            (None, first, after_first)
+        } else {
+            match next_word(after_first) {
+                Some((second, rest)) if self.macros.contains_key(&second.to_ascii_uppercase()) => {
+                    (Some(first), second, rest)
+                }
+                _ => return false,
+            }
+        };
+        let name = name.to_ascii_uppercase();
+        let arguments = words(arguments);
+        let expected = self.macros[&name].parameters.len();
+        if arguments.len() != expected {
+            let message = format!(
+                "macro {} expects {} argument{}, got {}",
+                name,
+                expected,
+                if expected == 1 { "" } else { "s" },
+                arguments.len()
+            );
+            self.error(site, location, message);
+            return true;
+        }
+        if site.depth >= MAX_DEPTH {
+            let message = format!("macro {} nests too deeply; does it call itself?", name);
+            self.error(site, location, message);
+            return true;
+        }
+
+        self.expansions += 1;
+        let expansion = self.expansions;
+        let definition = &self.macros[&name];
+        let replace = |word: &str| {
+            if let Some(local) = word.strip_prefix('@') {
+                return Some(format!("{}__{}", local, expansion));
+            }
+            definition
+                .parameters
+                .iter()
+                .position(|parameter| parameter == word)
+                .map(|index| arguments[index].to_string())
+        };
+        let mut body: Vec<(String, Location)> = definition
+            .body
+            .iter()
+            .map(|(text, location)| (substitute(text, &replace), location.clone()))
+            .collect();
+        // A label on the call marks the first word of the expansion
+        if let Some(label) = label {
+            body.insert(0, (label.to_string(), location.clone()));
+        }
+
+        let inner = Site {
+            line: Some(site.line.unwrap_or(location.line)),
+            call: Some(site.call.clone().unwrap_or(location.clone())),
+            call_column: match site.call {
+                Some(_) => site.call_column,
+                None => column(text),
+            },
+            macro_name: Some(name),
+            depth: site.depth + 1,
+            ..site.clone()
+        };
+        self.process(body, &inner);
+        true
+    }
+}
+
+/// Expands `source`. Problems are reported against lines of `source`, with
+/// the include or macro they occurred in noted in the message; the lines
+/// that could be expanded are returned either way.
+pub fn preprocess(source: &str, options: &AsmOptions) -> (Expansion, Vec<AsmError>) {
+    let mut preprocessor = Preprocessor {
+        options,
+        macros: HashMap::new(),
+        defines: HashMap::new(),
+        constants: BTreeMap::new(),
+        lines: Vec::new(),
+        errors: Vec::new(),
+        expansions: 0,
+        including: Vec::new(),
+    };
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| {
+            let location = Location {
+                file: None,
+                line: index + 1,
+            };
+            (text.to_string(), location)
+        })
+        .collect();
+    let site = Site {
+        line: None,
+        call: None,
+        call_column: 0,
+        macro_name: None,
+        directory: options.directory.clone().unwrap_or_default(),
+        depth: 0,
+    };
+    preprocessor.process(lines, &site);
+
+    let expansion = Expansion {
+        lines: preprocessor.lines,
+        constants: preprocessor.constants,
+    };
+    (expansion, preprocessor.errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::preprocess::{preprocess, Location};
+    use crate::asm::AsmOptions;
+
+    fn expand(source: &str) -> Vec<String> {
+        let (expansion, errors) = preprocess(source, &AsmOptions::default());
+        assert!(errors.is_empty(), "{:?}", errors);
+        expansion
+            .lines
+            .into_iter()
+            .map(|line| line.text.trim().to_string())
+            .filter(|text| !text.is_empty())
+            .collect()
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let (_, errors) = preprocess(source, &AsmOptions::default());
+        errors.into_iter().map(|error| error.to_string()).collect()
+    }
+
+    // ========== Macros ==========
+
+    #[test]
+    fn test_macro_parameters_and_local_labels() {
+        let lines = expand(
+            ".MACRO WAIT count\n\
+             @LOOP ADD count, count, #-1\n\
+             BRp @LOOP\n\
+             .ENDM\n\
+             START WAIT R1\n\
+             WAIT R2\n",
+        );
+        assert_eq!(
+            lines,
+            [
+                "START",
+                "LOOP__1 ADD R1, R1, #-1",
+                "BRp LOOP__1",
+                "LOOP__2 ADD R2, R2, #-1",
+                "BRp LOOP__2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expansions_point_at_the_call() {
+        let (expansion, _) = preprocess(
+            ".MACRO TWICE\nHALT\nHALT\n.ENDM\n.ORIG x3000\nTWICE\n",
+            &AsmOptions::default(),
+        );
+        let halts: Vec<_> = expansion
+            .lines
+            .iter()
+            .filter(|line| line.text == "HALT")
+            .collect();
+        assert_eq!(halts.len(), 2);
+        assert_eq!(halts[1].line, 6);
+        assert_eq!(
+            halts[1].location,
+            Location {
+                file: None,
+                line: 6
+            }
+        );
+        assert_eq!(halts[1].context.as_deref(), Some("in macro TWICE, line 3"));
+    }
+
+    // ========== Constants and Conditions ==========
+
+    #[test]
+    fn test_defines_constants_and_conditions() {
+        let (expansion, errors) = preprocess(
+            "SIZE .EQU 4\n\
+             .EQU DOUBLE SIZE*2\n\
+             .DEFINE SP R6\n\
+             .IF DOUBLE > 4\n\
+             ADD SP, SP, #-1\n\
+             .ELSE\n\
+             HALT\n\
+             .ENDIF\n\
+             .IFNDEF DEBUG\n\
+             RET\n\
+             .ENDIF\n",
+            &AsmOptions::default(),
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(expansion.constants["DOUBLE"], 8);
+        let lines: Vec<&str> = expansion
+            .lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(lines, ["ADD R6, R6, #-1", "RET"]);
+    }
+
+    // ========== Includes ==========
+
+    #[test]
+    fn test_includes_are_read_relative_to_the_source() {
+        let directory = std::env::temp_dir().join("rustvm_preprocess_include");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("lib.inc"),
+            "NEWLINE .EQU x0A\n.MACRO NL\nLD R0, #NEWLINE\n.ENDM\n",
+        )
+        .unwrap();
+        let options = AsmOptions {
+            directory: Some(directory),
+            ..AsmOptions::default()
+        };
+
+        let (expansion, errors) = preprocess(".INCLUDE \"lib.inc\"\nNL\n", &options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(expansion.constants["NEWLINE"], 10);
+        assert_eq!(expansion.lines[0].text, "LD R0, #NEWLINE");
+
+        let (_, errors) = preprocess(".INCLUDE \"missing.inc\"\n", &options);
+        assert_eq!(
+            errors[0].to_string(),
+            "line 1: could not find 'missing.inc' to include"
+        );
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_unbalanced_blocks_and_bad_calls() {
+        assert_eq!(
+            errors(".IF 1\nHALT\n"),
+            ["line 1: .IF is missing its .ENDIF"]
+        );
+        assert_eq!(
+            errors(".MACRO M\nHALT\n"),
+            ["line 1: macro M is missing its .ENDM"]
+        );
+        assert_eq!(errors(".ENDIF\n"), ["line 1: .ENDIF without .IF"]);
+        assert_eq!(
+            errors(".MACRO M a\n.ENDM\nM\n"),
+            ["line 3: macro M expects 1 argument, got 0"]
+        );
+        assert_eq!(
+            errors(".EQU SIZE LATER+1\n"),
+            ["line 1: .EQU 'LATER' is not a constant defined above"]
+        );
+        assert_eq!(
+            errors(".MACRO LOOP\nLOOP\n.ENDM\nLOOP\n")[0],
+            "line 4: macro LOOP nests too deeply; does it call itself? (in macro LOOP, line 2)"
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! The LC-3 assembly language server. Editors start it and talk to it over
+//! stdin and stdout.
+
+use rustvm::lsp::Server;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process::exit;
+
+const USAGE: &str = "\
+Usage: lc3-lsp [--stdio] [-I dir]...
+
+Serves the Language Server Protocol for LC-3 assembly on stdin and stdout.
+  -I dir      also look for .INCLUDE files in dir
+  --stdio     accepted for clients that pass it; stdio is the only transport
+";
+
+fn main() {
+    let mut server = Server::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-I" => match args.next() {
+                Some(dir) => server.include_paths.push(PathBuf::from(dir)),
+                None => {
+                    eprintln!("-I expects a directory");
+                    exit(2)
+                }
+            },
+            "--stdio" => {}
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return;
+            }
+            "-V" | "--version" => {
+                println!("lc3-lsp {}", env!("CARGO_PKG_VERSION"));
+                return;
+            }
+            other => {
+                eprintln!("unknown option '{}'\n{}", other, USAGE);
+                exit(2)
+            }
+        }
+    }
+
+    let stdin = io::stdin();
+    match server.run(&mut stdin.lock(), &mut io::stdout().lock()) {
+        Ok(true) => {}
+        // The protocol asks for 1 when the client exits without a shutdown
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("lc3-lsp: {}", e);
+            exit(1)
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::blocks::translate::{translate, Block, MicroOp, MAX_BLOCK_INSTRUCTIONS};
+use crate::instructions::trap::trap;
+use crate::instructions::update_flags;
+use crate::registers::register::Register;
+use crate::registers::ConditionFlag;
+use crate::{Vm, MEMORY_MAX};
+
+pub mod translate;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    pub translated: u64,
+    pub invalidated: u64,
+    pub executed: u64,
+    pub fused: u64,
+}
+
+/// Translated blocks keyed by start address. A store into any address a
+/// block covers throws that block away, so self-modifying code is seen the
+/// next time the block is entered.
+pub struct BlockCache {
+    blocks: Vec<Option<Box<Block>>>,
+    /// How many cached blocks cover each address, so stores outside code
+    /// skip the invalidation search.
+    covered: Vec<u8>,
+    /// The range of the block being executed, which is out of `blocks`
+    /// while it runs, and whether a store has hit it.
+    running: (u16, u32),
+    running_invalidated: bool,
+    stats: BlockStats,
+}
+
+impl BlockCache {
+    pub fn new() -> BlockCache {
+        Self {
+            blocks: vec![None; MEMORY_MAX],
+            covered: vec![0; MEMORY_MAX],
+            running: (0, 0),
+            running_invalidated: false,
+            stats: BlockStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &BlockStats {
+        &self.stats
+    }
+
+    pub fn is_cached(&self, address: u16) -> bool {
+        self.blocks[address as usize].is_some()
+    }
+
+    /// Translates the block at `address` unless it is already cached.
+    pub fn get(&mut self, address: u16, memory: &[u16; MEMORY_MAX]) -> &Block {
+        if self.blocks[address as usize].is_none() {
+            let block = translate(memory, address);
+            self.stats.translated += 1;
+            self.stats.fused += block
+                .ops
+                .iter()
+                .filter(|(_, op)| op.instructions() > 1)
+                .count() as u64;
+            for covered in &mut self.covered[block.start as usize..block.end as usize] {
+                *covered += 1;
+            }
+            self.blocks[address as usize] = Some(Box::new(block));
+        }
+        self.blocks[address as usize].as_ref().unwrap()
+    }
+
+    /// Drops every block covering `address`. Only blocks starting at most
+    /// `MAX_BLOCK_INSTRUCTIONS` words earlier can reach it.
+    pub fn invalidate(&mut self, address: u16) {
+        if self.covered[address as usize] == 0 {
+            return;
+        }
+
+        let (running_start, running_end) = self.running;
+        if address >= running_start && (address as u32) < running_end && !self.running_invalidated {
+            self.running_invalidated = true;
+            self.forget(running_start, running_end);
+        }
+
+        let earliest = address.saturating_sub(MAX_BLOCK_INSTRUCTIONS as u16 - 1);
+        for start in earliest..=address {
+            let entry = &mut self.blocks[start as usize];
+            if entry.as_ref().is_some_and(|block| block.contains(address)) {
+                let block = entry.take().unwrap();
+                self.forget(block.start, block.end);
+            }
+        }
+    }
+
+    fn forget(&mut self, start: u16, end: u32) {
+        for covered in &mut self.covered[start as usize..end as usize] {
+            *covered -= 1;
+        }
+        self.stats.invalidated += 1;
+    }
+
+    /// Takes the block at `address` out of the cache to run it.
+    fn take(&mut self, address: u16, memory: &[u16; MEMORY_MAX]) -> Box<Block> {
+        self.get(address, memory);
+        let block = self.blocks[address as usize].take().unwrap();
+        self.running = (block.start, block.end);
+        self.running_invalidated = false;
+        block
+    }
+
+    /// Puts the block back after running it, unless it rewrote itself.
+    fn restore(&mut self, block: Box<Block>) {
+        self.running = (0, 0);
+        if !self.running_invalidated {
+            let start = block.start as usize;
+            self.blocks[start] = Some(block);
+        }
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs until the program halts and returns how many instructions executed.
+/// Behaves exactly like looping `Vm::fetch_decode_execute`.
+pub fn run(vm: &mut Vm) -> u64 {
+    let mut cache = BlockCache::new();
+    let mut executed = 0;
+    loop {
+        let (instructions, running) = step_block(vm, &mut cache);
+        executed += instructions;
+        if !running {
+            return executed;
+        }
+    }
+}
+
+/// Executes the block at the PC. Returns how many instructions ran and
+/// whether the machine is still running.
+pub fn step_block(vm: &mut Vm, cache: &mut BlockCache) -> (u64, bool) {
+    let block = cache.take(vm.registers[Register::Pc as usize], &vm.memory);
+    cache.stats.executed += 1;
+
+    let result = execute(vm, cache, &block);
+    cache.restore(block);
+    result
+}
+
+fn execute(vm: &mut Vm, cache: &mut BlockCache, block: &Block) -> (u64, bool) {
+    let mut executed = 0;
+    for &(address, op) in &block.ops {
+        executed += op.instructions() as u64;
+        let next = address.wrapping_add(op.instructions());
+
+        let registers = &mut vm.registers;
+        match op {
+            MicroOp::AddRegister { dr, sr1, sr2 } => {
+                registers[dr as usize] =
+                    registers[sr1 as usize].wrapping_add(registers[sr2 as usize]);
+                update_flags(registers, dr as u16);
+            }
+            MicroOp::AddImmediate { dr, sr, value } => {
+                registers[dr as usize] = registers[sr as usize].wrapping_add(value);
+                update_flags(registers, dr as u16);
+            }
+            MicroOp::AndRegister { dr, sr1, sr2 } => {
+                registers[dr as usize] = registers[sr1 as usize] & registers[sr2 as usize];
+                update_flags(registers, dr as u16);
+            }
+            MicroOp::AndImmediate { dr, sr, value } => {
+                registers[dr as usize] = registers[sr as usize] & value;
+                update_flags(registers, dr as u16);
+            }
+            MicroOp::Not { dr, sr } => {
+                registers[dr as usize] = !registers[sr as usize];
+                update_flags(registers, dr as u16);
+            }
+            MicroOp::LoadImmediate { dr, value, .. } => {
+                registers[dr as usize] = value;
+                update_flags(registers, dr as u16);
+            }
+            MicroOp::Ld { dr, address } => {
+                vm.registers[dr as usize] = vm.mem_read(address);
+                update_flags(&mut vm.registers, dr as u16);
+            }
+            MicroOp::Ldi { dr, address } => {
+                let pointer = vm.mem_read(address);
+                vm.registers[dr as usize] = vm.mem_read(pointer);
+                update_flags(&mut vm.registers, dr as u16);
+            }
+            MicroOp::Ldr { dr, base, offset } => {
+                vm.registers[dr as usize] =
+                    vm.mem_read(vm.registers[base as usize].wrapping_add(offset));
+                update_flags(&mut vm.registers, dr as u16);
+            }
+            MicroOp::St { sr, address } => {
+                if store(vm, cache, block, address, sr, next) {
+                    return (executed, true);
+                }
+            }
+            MicroOp::Sti { sr, address } => {
+                let target = vm.mem_read(address);
+                if store(vm, cache, block, target, sr, next) {
+                    return (executed, true);
+                }
+            }
+            MicroOp::Str { sr, base, offset } => {
+                let target = vm.registers[base as usize].wrapping_add(offset);
+                if store(vm, cache, block, target, sr, next) {
+                    return (executed, true);
+                }
+            }
+            MicroOp::Branch { nzp, target } => {
+                let taken = nzp as u16 & registers[Register::Cond as usize] != 0;
+                registers[Register::Pc as usize] = if taken { target } else { next };
+                return (executed, true);
+            }
+            MicroOp::AddImmediateBranch {
+                dr,
+                sr,
+                value,
+                nzp,
+                target,
+            } => {
+                let sum = registers[sr as usize].wrapping_add(value);
+                registers[dr as usize] = sum;
+                update_flags(registers, dr as u16);
+                let flag = match sum {
+                    0 => ConditionFlag::Zro,
+                    sum if sum >> 15 == 1 => ConditionFlag::Neg,
+                    _ => ConditionFlag::Pos,
+                };
+                let taken = nzp as u16 & flag as u16 != 0;
+                registers[Register::Pc as usize] = if taken { target } else { next };
+                return (executed, true);
+            }
+            MicroOp::Jmp { base } => {
+                registers[Register::Pc as usize] = registers[base as usize];
+                return (executed, true);
+            }
+            MicroOp::Jsr { target } => {
+                registers[Register::R7 as usize] = next;
+                registers[Register::Pc as usize] = target;
+                return (executed, true);
+            }
+            MicroOp::Jsrr { base } => {
+                registers[Register::R7 as usize] = next;
+                registers[Register::Pc as usize] = registers[base as usize];
+                return (executed, true);
+            }
+            MicroOp::Trap { instruction } => {
+                registers[Register::Pc as usize] = next;
+                return (executed, trap(vm, instruction));
+            }
+            MicroOp::Stop => {
+                registers[Register::Pc as usize] = next;
+                return (executed, false);
+            }
+        }
+    }
+
+    vm.registers[Register::Pc as usize] = block.end as u16;
+    (executed, true)
+}
+
+/// Stores a register, invalidating translations of the target. Returns
+/// `true` when the store rewrote a later part of the running block, which
+/// then has to be left at `next`.
+fn store(
+    vm: &mut Vm,
+    cache: &mut BlockCache,
+    block: &Block,
+    address: u16,
+    source: u8,
+    next: u16,
+) -> bool {
+    vm.mem_write(address, vm.registers[source as usize]);
+    cache.invalidate(address);
+
+    if block.contains(address) && address >= next {
+        vm.registers[Register::Pc as usize] = next;
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocks::{run, step_block, BlockCache};
+    use crate::registers::register::Register;
+    use crate::test_support::{seeded_block_vms, vm_with};
+
+    // ========== Equivalence With the Interpreter ==========
+
+    #[test]
+    fn test_random_blocks_match_the_interpreter() {
+        let mut seed = 0xD1B5_4A32_D192_ED03_u64;
+        for _ in 0..256 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let (mut interpreter, mut translated) = seeded_block_vms(seed | 1);
+
+            let (executed, running) = step_block(&mut translated, &mut BlockCache::new());
+            let mut interpreter_running = true;
+            for _ in 0..executed {
+                interpreter_running = interpreter.fetch_decode_execute();
+            }
+
+            assert_eq!(running, interpreter_running, "seed {:X}", seed);
+            assert_eq!(
+                interpreter.registers, translated.registers,
+                "seed {:X}",
+                seed
+            );
+            assert!(
+                interpreter.memory[..0xFE00] == translated.memory[..0xFE00],
+                "seed {:X}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn test_counting_loop_matches_the_interpreter() {
+        let program = [
+            0b0101_001_001_1_00000,  // AND R1, R1, #0
+            0b0001_001_001_1_01010,  // ADD R1, R1, #10
+            0b0001_010_010_0_00_001, // ADD R2, R2, R1
+            0b0001_001_001_1_11111,  // ADD R1, R1, #-1
+            0b0000_001_111111101,    // BRp #-3
+            0xF025,                  // HALT
+        ];
+        let mut interpreter = vm_with(&program);
+        let mut translated = vm_with(&program);
+
+        let mut expected = 1;
+        while interpreter.fetch_decode_execute() {
+            expected += 1;
+        }
+        let executed = run(&mut translated);
+
+        assert_eq!(interpreter.registers, translated.registers);
+        assert_eq!(translated.registers[Register::R2 as usize], 55);
+        assert_eq!(executed, expected);
+    }
+
+    #[test]
+    fn test_blocks_are_reused_across_iterations() {
+        let mut vm = vm_with(&[
+            0b0101_001_001_1_00000, // AND R1, R1, #0
+            0b0001_001_001_1_00101, // ADD R1, R1, #5
+            0b0001_001_001_1_11111, // ADD R1, R1, #-1
+            0b0000_001_111111110,   // BRp #-2
+            0xF025,                 // HALT
+        ]);
+        let mut cache = BlockCache::new();
+
+        while step_block(&mut vm, &mut cache).1 {}
+
+        // x3000 (falls into the loop), x3002 (the loop) and x3004 (HALT)
+        assert_eq!(cache.stats().translated, 3);
+        assert_eq!(cache.stats().executed, 1 + 4 + 1);
+        assert_eq!(cache.stats().fused, 2 + 1);
+    }
+
+    // ========== Self-Modifying Code ==========
+
+    #[test]
+    fn test_store_into_later_instruction_of_running_block() {
+        let mut vm = vm_with(&[
+            0b0011_001_000000000,   // ST R1, #0 (overwrites the next word)
+            0b0001_010_010_1_00001, // ADD R2, R2, #1
+            0xF025,                 // HALT
+        ]);
+        vm.write_to_register(Register::R1, 0b0001_010_010_1_00111); // ADD R2, R2, #7
+
+        run(&mut vm);
+
+        assert_eq!(vm.registers[Register::R2 as usize], 7);
+    }
+
+    #[test]
+    fn test_store_invalidates_cached_block() {
+        let mut vm = vm_with(&[
+            0b0001_010_010_1_00001, // ADD R2, R2, #1
+            0b0000_111_000000010,   // BRnzp #2
+            0xF025,                 // HALT
+            0xF025,                 // HALT
+            0b0011_001_111111011,   // ST R1, #-5 (rewrites x3000)
+            0b0000_111_111111010,   // BRnzp #-6
+        ]);
+        vm.write_to_register(Register::R1, 0b0001_010_010_1_00100); // ADD R2, R2, #4
+        let mut cache = BlockCache::new();
+
+        step_block(&mut vm, &mut cache);
+        assert!(cache.is_cached(0x3000));
+        step_block(&mut vm, &mut cache);
+
+        assert!(!cache.is_cached(0x3000));
+        assert_eq!(cache.stats().invalidated, 1);
+        step_block(&mut vm, &mut cache);
+        assert_eq!(vm.registers[Register::R2 as usize], 1 + 4);
+    }
+
+    #[test]
+    fn test_invalidate_reaches_blocks_starting_earlier() {
+        let mut vm = vm_with(&[0b1001_001_001_111111; 10]);
+        let mut cache = BlockCache::new();
+        cache.get(0x3000, &vm.memory);
+        cache.get(0x3004, &vm.memory);
+        vm.memory[0x3100] = 0xF025;
+        cache.get(0x3100, &vm.memory);
+
+        cache.invalidate(0x3005);
+
+        assert!(!cache.is_cached(0x3000));
+        assert!(!cache.is_cached(0x3004));
+        assert!(cache.is_cached(0x3100));
+    }
+}
@@ -0,0 +1,423 @@
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::MEMORY_MAX;
+
+/// Longest straight-line run translated into a single block.
+pub const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+
+/// One step of a translated block. PC-relative addresses are resolved at
+/// translation time, so no operation needs to know the PC except the ones
+/// that save it as a return address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicroOp {
+    AddRegister {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AddImmediate {
+        dr: u8,
+        sr: u8,
+        value: u16,
+    },
+    AndRegister {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AndImmediate {
+        dr: u8,
+        sr: u8,
+        value: u16,
+    },
+    Not {
+        dr: u8,
+        sr: u8,
+    },
+    /// `LEA`, or the fused `AND Rx,Rx,#0; ADD Rx,Rx,#imm`.
+    LoadImmediate {
+        dr: u8,
+        value: u16,
+        fused: bool,
+    },
+    Ld {
+        dr: u8,
+        address: u16,
+    },
+    Ldi {
+        dr: u8,
+        address: u16,
+    },
+    Ldr {
+        dr: u8,
+        base: u8,
+        offset: u16,
+    },
+    St {
+        sr: u8,
+        address: u16,
+    },
+    Sti {
+        sr: u8,
+        address: u16,
+    },
+    Str {
+        sr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Branch {
+        nzp: u8,
+        target: u16,
+    },
+    /// The fused `ADD Rx,Ry,#imm; BR`, which branches on the sum directly.
+    AddImmediateBranch {
+        dr: u8,
+        sr: u8,
+        value: u16,
+        nzp: u8,
+        target: u16,
+    },
+    Jmp {
+        base: u8,
+    },
+    Jsr {
+        target: u16,
+    },
+    Jsrr {
+        base: u8,
+    },
+    Trap {
+        instruction: u16,
+    },
+    Stop,
+}
+
+impl MicroOp {
+    /// How many LC-3 instructions this operation stands for.
+    pub fn instructions(&self) -> u16 {
+        match self {
+            MicroOp::LoadImmediate { fused: true, .. } | MicroOp::AddImmediateBranch { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn ends_block(&self) -> bool {
+        matches!(
+            self,
+            MicroOp::Branch { .. }
+                | MicroOp::AddImmediateBranch { .. }
+                | MicroOp::Jmp { .. }
+                | MicroOp::Jsr { .. }
+                | MicroOp::Jsrr { .. }
+                | MicroOp::Trap { .. }
+                | MicroOp::Stop
+        )
+    }
+}
+
+/// A translated basic block covering `start..end`. Each operation is paired
+/// with the address of the first instruction it was translated from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    pub end: u32,
+    pub ops: Vec<(u16, MicroOp)>,
+}
+
+impl Block {
+    pub fn contains(&self, address: u16) -> bool {
+        address >= self.start && (address as u32) < self.end
+    }
+
+    pub fn instructions(&self) -> u32 {
+        self.end - self.start as u32
+    }
+}
+
+/// Translates the basic block starting at `start`. Blocks end after the
+/// first control transfer, after `MAX_BLOCK_INSTRUCTIONS`, or at the top of
+/// memory.
+pub fn translate(memory: &[u16; MEMORY_MAX], start: u16) -> Block {
+    let mut ops: Vec<(u16, MicroOp)> = Vec::new();
+    let mut address = start as u32;
+
+    while address < MEMORY_MAX as u32
+        && ((address - start as u32) as usize) < MAX_BLOCK_INSTRUCTIONS
+    {
+        let mut op = micro_op(address as u16, memory[address as usize]);
+        match ops.last().and_then(|&(_, previous)| fuse(previous, op)) {
+            Some(fused) => {
+                ops.last_mut().unwrap().1 = fused;
+                op = fused;
+            }
+            None => ops.push((address as u16, op)),
+        }
+
+        address += 1;
+        if op.ends_block() {
+            break;
+        }
+    }
+
+    Block {
+        start,
+        end: address,
+        ops,
+    }
+}
+
+fn micro_op(address: u16, instruction: u16) -> MicroOp {
+    let decoded = Decoded::decode(instruction);
+    let next = address.wrapping_add(1);
+    let (dr, sr, operand) = (decoded.dr, decoded.sr, decoded.operand);
+    let relative = next.wrapping_add(operand);
+
+    match decoded.operation {
+        Operation::AddRegister => MicroOp::AddRegister {
+            dr,
+            sr1: sr,
+            sr2: operand as u8,
+        },
+        Operation::AddImmediate => MicroOp::AddImmediate {
+            dr,
+            sr,
+            value: operand,
+        },
+        Operation::AndRegister => MicroOp::AndRegister {
+            dr,
+            sr1: sr,
+            sr2: operand as u8,
+        },
+        Operation::AndImmediate => MicroOp::AndImmediate {
+            dr,
+            sr,
+            value: operand,
+        },
+        Operation::Not => MicroOp::Not { dr, sr },
+        Operation::Lea => MicroOp::LoadImmediate {
+            dr,
+            value: relative,
+            fused: false,
+        },
+        Operation::Ld => MicroOp::Ld {
+            dr,
+            address: relative,
+        },
+        Operation::Ldi => MicroOp::Ldi {
+            dr,
+            address: relative,
+        },
+        Operation::Ldr => MicroOp::Ldr {
+            dr,
+            base: sr,
+            offset: operand,
+        },
+        Operation::St => MicroOp::St {
+            sr: dr,
+            address: relative,
+        },
+        Operation::Sti => MicroOp::Sti {
+            sr: dr,
+            address: relative,
+        },
+        Operation::Str => MicroOp::Str {
+            sr: dr,
+            base: sr,
+            offset: operand,
+        },
+        Operation::Br => MicroOp::Branch {
+            nzp: dr,
+            target: relative,
+        },
+        Operation::Jmp => MicroOp::Jmp { base: sr },
+        Operation::Jsr => MicroOp::Jsr { target: relative },
+        Operation::Jsrr => MicroOp::Jsrr { base: sr },
+        Operation::Trap => MicroOp::Trap {
+            instruction: operand,
+        },
+        Operation::Stop => MicroOp::Stop,
+    }
+}
+
+/// The superinstruction for `first` followed by `second`, if there is one.
+fn fuse(first: MicroOp, second: MicroOp) -> Option<MicroOp> {
+    match (first, second) {
+        (
+            MicroOp::AndImmediate { dr, sr, value: 0 },
+            MicroOp::AddImmediate {
+                dr: add_dr,
+                sr: add_sr,
+                value,
+            },
+        ) if dr == sr && add_dr == dr && add_sr == dr => Some(MicroOp::LoadImmediate {
+            dr,
+            value,
+            fused: true,
+        }),
+        (MicroOp::AddImmediate { dr, sr, value }, MicroOp::Branch { nzp, target }) => {
+            Some(MicroOp::AddImmediateBranch {
+                dr,
+                sr,
+                value,
+                nzp,
+                target,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocks::translate::{translate, MicroOp, MAX_BLOCK_INSTRUCTIONS};
+    use crate::MEMORY_MAX;
+
+    fn memory_with(origin: u16, words: &[u16]) -> Box<[u16; MEMORY_MAX]> {
+        let mut memory = Box::new([0; MEMORY_MAX]);
+        for (offset, &word) in words.iter().enumerate() {
+            memory[origin as usize + offset] = word;
+        }
+        memory
+    }
+
+    // ========== Block Boundaries ==========
+
+    #[test]
+    fn test_block_ends_after_control_transfer() {
+        let memory = memory_with(
+            0x3000,
+            &[
+                0b0001_001_010_0_00_011, // ADD R1, R2, R3
+                0xF021,                  // OUT
+                0b0001_001_010_0_00_011, // ADD R1, R2, R3
+            ],
+        );
+
+        let block = translate(&memory, 0x3000);
+
+        assert_eq!(block.end, 0x3002);
+        assert_eq!(block.ops.len(), 2);
+        assert_eq!(
+            block.ops[1],
+            (
+                0x3001,
+                MicroOp::Trap {
+                    instruction: 0xF021
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_block_length_is_capped() {
+        // NOT R1, R1 everywhere
+        let memory = memory_with(0x3000, &[0b1001_001_001_111111; 100]);
+
+        let block = translate(&memory, 0x3000);
+
+        assert_eq!(block.instructions() as usize, MAX_BLOCK_INSTRUCTIONS);
+    }
+
+    #[test]
+    fn test_block_stops_at_top_of_memory() {
+        let memory = memory_with(0xFFFE, &[0b1001_001_001_111111; 2]);
+
+        let block = translate(&memory, 0xFFFE);
+
+        assert_eq!(block.end, 0x10000);
+        assert!(block.contains(0xFFFF));
+    }
+
+    #[test]
+    fn test_pc_relative_addresses_are_resolved() {
+        let memory = memory_with(
+            0x3000,
+            &[
+                0b0010_000_000000100, // LD R0, #4
+                0b0000_010_111111110, // BRz #-2
+            ],
+        );
+
+        let block = translate(&memory, 0x3000);
+
+        assert_eq!(
+            block.ops[0].1,
+            MicroOp::Ld {
+                dr: 0,
+                address: 0x3005
+            }
+        );
+        assert_eq!(
+            block.ops[1].1,
+            MicroOp::Branch {
+                nzp: 0b010,
+                target: 0x3000
+            }
+        );
+    }
+
+    // ========== Fusion ==========
+
+    #[test]
+    fn test_clear_then_add_becomes_load_immediate() {
+        let memory = memory_with(
+            0x3000,
+            &[
+                0b0101_011_011_1_00000, // AND R3, R3, #0
+                0b0001_011_011_1_00111, // ADD R3, R3, #7
+                0xF025,
+            ],
+        );
+
+        let block = translate(&memory, 0x3000);
+
+        assert_eq!(
+            block.ops[0],
+            (
+                0x3000,
+                MicroOp::LoadImmediate {
+                    dr: 3,
+                    value: 7,
+                    fused: true
+                }
+            )
+        );
+        assert_eq!(block.ops[1].0, 0x3002);
+    }
+
+    #[test]
+    fn test_clear_of_another_register_is_not_fused() {
+        let memory = memory_with(
+            0x3000,
+            &[
+                0b0101_011_011_1_00000, // AND R3, R3, #0
+                0b0001_100_100_1_00111, // ADD R4, R4, #7
+                0xF025,                 // HALT
+            ],
+        );
+
+        let block = translate(&memory, 0x3000);
+
+        assert!(matches!(block.ops[0].1, MicroOp::AndImmediate { .. }));
+        assert!(matches!(block.ops[1].1, MicroOp::AddImmediate { .. }));
+    }
+
+    #[test]
+    fn test_decrement_and_branch_fuse_and_end_the_block() {
+        let memory = memory_with(
+            0x3000,
+            &[
+                0b0001_001_001_1_11111, // ADD R1, R1, #-1
+                0b0000_001_111111110,   // BRp #-2
+                0xF025,
+            ],
+        );
+
+        let block = translate(&memory, 0x3000);
+
+        assert_eq!(block.end, 0x3002);
+        assert_eq!(block.ops.len(), 1);
+        assert_eq!(block.ops[0].1.instructions(), 2);
+        assert!(block.ops[0].1.ends_block());
+    }
+}
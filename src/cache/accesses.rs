@@ -0,0 +1,133 @@
+use crate::cache::Access;
+use crate::instructions::opcodes::Opcode;
+use crate::instructions::sign_extend;
+use crate::instructions::trap::{TRAP_PUTS, TRAP_PUTSP};
+use crate::registers::register::Register;
+use crate::Vm;
+
+/// The data memory accesses `instruction` is about to make, in order. Must
+/// be called after fetch, so the PC already points past the instruction.
+pub fn data_accesses(vm: &Vm, instruction: u16, opcode: Opcode) -> Vec<(u16, Access)> {
+    let pc = vm.registers[Register::Pc as usize];
+    let pc_relative = pc.wrapping_add(sign_extend(instruction & 0x1FF, 9));
+    let base = vm.registers[((instruction >> 6) & 0x7) as usize];
+    let base_relative = base.wrapping_add(sign_extend(instruction & 0x3F, 6));
+
+    match opcode {
+        Opcode::Ld => vec![(pc_relative, Access::Read)],
+        Opcode::Ldr => vec![(base_relative, Access::Read)],
+        Opcode::Ldi => vec![
+            (pc_relative, Access::Read),
+            (vm.memory[pc_relative as usize], Access::Read),
+        ],
+        Opcode::St => vec![(pc_relative, Access::Write)],
+        Opcode::Str => vec![(base_relative, Access::Write)],
+        Opcode::Sti => vec![
+            (pc_relative, Access::Read),
+            (vm.memory[pc_relative as usize], Access::Write),
+        ],
+        Opcode::Trap => match instruction & 0xFF {
+            TRAP_PUTS => string_reads(vm, |word| word == 0),
+            TRAP_PUTSP => string_reads(vm, |word| word & 0xFF == 0 || word >> 8 == 0),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Reads from R0 up to and including the word `last` accepts.
+fn string_reads(vm: &Vm, last: impl Fn(u16) -> bool) -> Vec<(u16, Access)> {
+    let mut address = vm.registers[Register::R0 as usize];
+    let mut accesses = Vec::new();
+    loop {
+        accesses.push((address, Access::Read));
+        if last(vm.memory[address as usize]) || accesses.len() == vm.memory.len() {
+            return accesses;
+        }
+        address = address.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::accesses::data_accesses;
+    use crate::cache::Access;
+    use crate::instructions::opcodes::Opcode;
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    #[test]
+    fn test_ld_reads_pc_relative_address() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3001);
+
+        // LD R0, #4
+        let accesses = data_accesses(&vm, 0b0010_000_000000100, Opcode::Ld);
+
+        assert_eq!(accesses, vec![(0x3005, Access::Read)]);
+    }
+
+    #[test]
+    fn test_str_writes_base_plus_offset() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::R6, 0x4000);
+
+        // STR R1, R6, #-1
+        let accesses = data_accesses(&vm, 0b0111_001_110_111111, Opcode::Str);
+
+        assert_eq!(accesses, vec![(0x3FFF, Access::Write)]);
+    }
+
+    #[test]
+    fn test_sti_reads_pointer_then_writes_target() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3001);
+        vm.mem_write(0x3002, 0x5000);
+
+        // STI R1, #1
+        let accesses = data_accesses(&vm, 0b1011_001_000000001, Opcode::Sti);
+
+        assert_eq!(
+            accesses,
+            vec![(0x3002, Access::Read), (0x5000, Access::Write)]
+        );
+    }
+
+    #[test]
+    fn test_puts_reads_through_terminator() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::R0, 0x4000);
+        vm.mem_write(0x4000, 'H' as u16);
+        vm.mem_write(0x4001, 'i' as u16);
+
+        let accesses = data_accesses(&vm, 0xF022, Opcode::Trap);
+
+        assert_eq!(
+            accesses
+                .iter()
+                .map(|&(address, _)| address)
+                .collect::<Vec<_>>(),
+            vec![0x4000, 0x4001, 0x4002]
+        );
+    }
+
+    #[test]
+    fn test_putsp_stops_at_odd_terminator() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::R0, 0x4000);
+        vm.mem_write(0x4000, 0x6948); // "Hi"
+        vm.mem_write(0x4001, 0x0021); // "!"
+
+        let accesses = data_accesses(&vm, 0xF024, Opcode::Trap);
+
+        assert_eq!(accesses.len(), 2);
+    }
+
+    #[test]
+    fn test_alu_instructions_make_no_data_accesses() {
+        let vm = Vm::new();
+
+        assert!(data_accesses(&vm, 0x1261, Opcode::Add).is_empty());
+        assert!(data_accesses(&vm, 0xF021, Opcode::Trap).is_empty());
+    }
+}
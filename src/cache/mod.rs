@@ -0,0 +1,376 @@
+use crate::cache::set_associative::Cache;
+use crate::loader::DEVICE_REGISTERS;
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+pub mod accesses;
+pub mod set_associative;
+
+/// The LC-3 memory map, used to break hit rates down by address.
+pub const REGIONS: [(&str, u16, u16); 5] = [
+    ("Trap vector table", 0x0000, 0x00FF),
+    ("Interrupt vector table", 0x0100, 0x01FF),
+    ("Operating system", 0x0200, 0x2FFF),
+    ("User program", 0x3000, 0xFDFF),
+    ("Device registers", 0xFE00, 0xFFFF),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    WriteBack,
+    WriteThrough,
+}
+
+/// Geometry and policies of one cache. Sizes are in 16-bit words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub size_words: usize,
+    pub ways: usize,
+    pub block_words: usize,
+    pub replacement: Replacement,
+    pub write_policy: WritePolicy,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            size_words: 256,
+            ways: 2,
+            block_words: 4,
+            replacement: Replacement::Lru,
+            write_policy: WritePolicy::WriteBack,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn sets(&self) -> usize {
+        self.size_words / (self.ways * self.block_words)
+    }
+
+    fn validate(self) -> Result<CacheConfig, String> {
+        if !self.block_words.is_power_of_two() {
+            return Err(format!(
+                "block size {} is not a power of two",
+                self.block_words
+            ));
+        }
+        if self.ways == 0 || !self.size_words.is_multiple_of(self.ways * self.block_words) {
+            return Err(format!(
+                "cache of {} words cannot be split into {}-way sets of {}-word blocks",
+                self.size_words, self.ways, self.block_words
+            ));
+        }
+        if !self.sets().is_power_of_two() {
+            return Err(format!("{} sets is not a power of two", self.sets()));
+        }
+        Ok(self)
+    }
+}
+
+/// Parses `size=256,ways=2,block=4,replace=lru,write=back`. Omitted keys
+/// keep their defaults, so `default` alone is also accepted.
+impl FromStr for CacheConfig {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut config = CacheConfig::default();
+        for setting in spec.split(',').map(str::trim) {
+            if setting.is_empty() || setting == "default" {
+                continue;
+            }
+            let Some((key, value)) = setting.split_once('=') else {
+                return Err(format!(
+                    "expected key=value in cache spec, got '{}'",
+                    setting
+                ));
+            };
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("{} expects a number, got '{}'", key, value))
+            };
+            match key {
+                "size" => config.size_words = number()?,
+                "ways" => config.ways = number()?,
+                "block" => config.block_words = number()?,
+                "replace" => {
+                    config.replacement = match value {
+                        "lru" => Replacement::Lru,
+                        "fifo" => Replacement::Fifo,
+                        "random" => Replacement::Random,
+                        _ => return Err(format!("unknown replacement policy '{}'", value)),
+                    }
+                }
+                "write" => {
+                    config.write_policy = match value {
+                        "back" => WritePolicy::WriteBack,
+                        "through" => WritePolicy::WriteThrough,
+                        _ => return Err(format!("unknown write policy '{}'", value)),
+                    }
+                }
+                _ => return Err(format!("unknown cache setting '{}'", key)),
+            }
+        }
+        config.validate()
+    }
+}
+
+impl fmt::Display for CacheConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} words, {}-way, {}-word blocks, {:?}, {}",
+            self.size_words,
+            self.ways,
+            self.block_words,
+            self.replacement,
+            match self.write_policy {
+                WritePolicy::WriteBack => "write-back",
+                WritePolicy::WriteThrough => "write-through",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RegionCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A cache together with its hit and miss counts per memory region.
+pub struct TrackedCache {
+    pub cache: Cache,
+    pub regions: [RegionCounts; REGIONS.len()],
+}
+
+impl TrackedCache {
+    fn new(config: CacheConfig) -> TrackedCache {
+        Self {
+            cache: Cache::new(config),
+            regions: [RegionCounts::default(); REGIONS.len()],
+        }
+    }
+
+    fn access(&mut self, address: u16, access: Access) -> bool {
+        let hit = self.cache.access(address, access);
+        let region = REGIONS
+            .iter()
+            .position(|&(_, start, end)| (start..=end).contains(&address))
+            .unwrap();
+        match hit {
+            true => self.regions[region].hits += 1,
+            false => self.regions[region].misses += 1,
+        }
+        hit
+    }
+
+    fn report(&self, name: &str, report: &mut String) {
+        let stats = self.cache.stats();
+        writeln!(report, "--- {} cache ({}) ---", name, self.cache.config()).unwrap();
+        writeln!(
+            report,
+            "Accesses: {} ({} reads, {} writes)",
+            stats.accesses(),
+            stats.reads,
+            stats.writes
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "Hits: {} ({})  Misses: {} ({})",
+            stats.hits(),
+            percentage(stats.hits(), stats.accesses()),
+            stats.misses(),
+            percentage(stats.misses(), stats.accesses())
+        )
+        .unwrap();
+        if stats.writes > 0 {
+            writeln!(
+                report,
+                "Writebacks: {}  Write-through words: {}",
+                stats.writebacks, stats.memory_writes
+            )
+            .unwrap();
+        }
+
+        for (&(region, start, end), counts) in REGIONS.iter().zip(&self.regions) {
+            let accesses = counts.hits + counts.misses;
+            if accesses == 0 {
+                continue;
+            }
+            writeln!(
+                report,
+                "  {:<24} x{:04X}-x{:04X}  {:>8} accesses  {:>7} hit",
+                region,
+                start,
+                end,
+                accesses,
+                percentage(counts.hits, accesses)
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Separate instruction and data caches. Fetches go through `instruction`,
+/// loads, stores and the string traps through `data`.
+pub struct Caches {
+    pub instruction: TrackedCache,
+    pub data: TrackedCache,
+}
+
+impl Caches {
+    pub fn new(instruction: CacheConfig, data: CacheConfig) -> Caches {
+        Self {
+            instruction: TrackedCache::new(instruction),
+            data: TrackedCache::new(data),
+        }
+    }
+
+    pub fn fetch(&mut self, address: u16) -> bool {
+        self.instruction.access(address, Access::Read)
+    }
+
+    /// Device registers are never cached: accesses to them go straight to
+    /// the device, count as misses to the caller and stay out of the
+    /// statistics.
+    pub fn access_data(&mut self, address: u16, access: Access) -> bool {
+        if address >= DEVICE_REGISTERS.start {
+            return false;
+        }
+        self.data.access(address, access)
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        self.instruction.report("Instruction", &mut report);
+        self.data.report("Data", &mut report);
+        report
+    }
+}
+
+fn percentage(part: u64, whole: u64) -> String {
+    match whole {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", part as f64 * 100.0 / whole as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{Access, CacheConfig, Caches, Replacement, WritePolicy};
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    // ========== Configuration ==========
+
+    #[test]
+    fn test_parse_full_spec() {
+        let config: CacheConfig = "size=64,ways=4,block=2,replace=fifo,write=through"
+            .parse()
+            .unwrap();
+
+        assert_eq!(config.size_words, 64);
+        assert_eq!(config.ways, 4);
+        assert_eq!(config.block_words, 2);
+        assert_eq!(config.replacement, Replacement::Fifo);
+        assert_eq!(config.write_policy, WritePolicy::WriteThrough);
+        assert_eq!(config.sets(), 8);
+    }
+
+    #[test]
+    fn test_parse_keeps_defaults_for_missing_keys() {
+        assert_eq!("default".parse(), Ok(CacheConfig::default()));
+        assert_eq!("ways=1".parse::<CacheConfig>().unwrap().size_words, 256);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_geometry() {
+        assert!("block=3".parse::<CacheConfig>().is_err());
+        assert!("size=100,ways=1,block=4".parse::<CacheConfig>().is_err());
+        assert!("size=96,ways=4,block=4".parse::<CacheConfig>().is_err());
+        assert!("ways=0".parse::<CacheConfig>().is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_settings() {
+        assert!("colour=blue".parse::<CacheConfig>().is_err());
+        assert!("replace=mru".parse::<CacheConfig>().is_err());
+        assert!("size".parse::<CacheConfig>().is_err());
+    }
+
+    // ========== Regions ==========
+
+    #[test]
+    fn test_counts_accesses_per_region() {
+        let mut caches = Caches::new(CacheConfig::default(), CacheConfig::default());
+
+        caches.fetch(0x3000);
+        caches.fetch(0x3000);
+        caches.access_data(0x0025, Access::Read);
+
+        assert_eq!(caches.instruction.regions[3].hits, 1);
+        assert_eq!(caches.instruction.regions[3].misses, 1);
+        assert_eq!(caches.data.regions[0].misses, 1);
+    }
+
+    #[test]
+    fn test_report_lists_used_regions_only() {
+        let mut caches = Caches::new(CacheConfig::default(), CacheConfig::default());
+        caches.fetch(0x3000);
+
+        let report = caches.report();
+
+        assert!(report.contains("--- Instruction cache (256 words, 2-way"));
+        assert!(report.contains("User program"));
+        assert!(!report.contains("Device registers"));
+    }
+
+    #[test]
+    fn test_device_registers_bypass_the_data_cache() {
+        let mut caches = Caches::new(CacheConfig::default(), CacheConfig::default());
+
+        assert!(!caches.access_data(0xFE00, Access::Read));
+        assert!(!caches.access_data(0xFE00, Access::Read));
+        caches.access_data(0xFE06, Access::Write);
+
+        assert_eq!(caches.data.cache.stats().accesses(), 0);
+        assert!(!caches.report().contains("Device registers"));
+    }
+
+    // ========== Integration With the VM ==========
+
+    #[test]
+    fn test_vm_routes_fetches_and_data_accesses() {
+        let mut vm = Vm::new();
+        vm.caches = Some(Caches::new(CacheConfig::default(), CacheConfig::default()));
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0b0010_000_000000010); // LD R0, #2
+        vm.mem_write(0x3001, 0b0011_000_000000001); // ST R0, #1
+
+        vm.fetch_decode_execute();
+        vm.fetch_decode_execute();
+
+        let caches = vm.caches.as_ref().unwrap();
+        assert_eq!(caches.instruction.cache.stats().reads, 2);
+        assert_eq!(caches.instruction.cache.stats().read_hits, 1);
+        assert_eq!(caches.data.cache.stats().reads, 1);
+        assert_eq!(caches.data.cache.stats().write_hits, 1);
+    }
+}
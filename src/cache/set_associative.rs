@@ -0,0 +1,274 @@
+use crate::cache::{Access, CacheConfig, Replacement, WritePolicy};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: u16,
+    last_used: u64,
+    filled: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub read_hits: u64,
+    pub write_hits: u64,
+    /// Dirty blocks written back to memory on eviction.
+    pub writebacks: u64,
+    /// Words written straight through to memory.
+    pub memory_writes: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.read_hits + self.write_hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.accesses() - self.hits()
+    }
+}
+
+/// A set-associative cache over word addresses. Only tags are modelled; the
+/// data itself always lives in `Vm::memory`.
+///
+/// Write-back caches allocate on a write miss, write-through caches do not.
+pub struct Cache {
+    config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    clock: u64,
+    random_state: u32,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Cache {
+        let set_count = config.sets();
+        Self {
+            config,
+            sets: vec![vec![Line::default(); config.ways]; set_count],
+            clock: 0,
+            random_state: 0x2545_F491,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Looks up `address`, filling or updating the cache as the policies
+    /// require. Returns whether the access hit.
+    pub fn access(&mut self, address: u16, access: Access) -> bool {
+        self.clock += 1;
+        let block = address as usize / self.config.block_words;
+        let set_index = block % self.sets.len();
+        let tag = (block / self.sets.len()) as u16;
+        let write = access == Access::Write;
+
+        match access {
+            Access::Read => self.stats.reads += 1,
+            Access::Write => self.stats.writes += 1,
+        }
+        if write && self.config.write_policy == WritePolicy::WriteThrough {
+            self.stats.memory_writes += 1;
+        }
+
+        let clock = self.clock;
+        let set = &mut self.sets[set_index];
+        if let Some(line) = set.iter_mut().find(|line| line.valid && line.tag == tag) {
+            line.last_used = clock;
+            if write && self.config.write_policy == WritePolicy::WriteBack {
+                line.dirty = true;
+            }
+            match access {
+                Access::Read => self.stats.read_hits += 1,
+                Access::Write => self.stats.write_hits += 1,
+            }
+            return true;
+        }
+
+        if write && self.config.write_policy == WritePolicy::WriteThrough {
+            return false;
+        }
+
+        let way = self.victim(set_index);
+        let line = &mut self.sets[set_index][way];
+        if line.valid && line.dirty {
+            self.stats.writebacks += 1;
+        }
+        *line = Line {
+            valid: true,
+            dirty: write,
+            tag,
+            last_used: clock,
+            filled: clock,
+        };
+        false
+    }
+
+    fn victim(&mut self, set_index: usize) -> usize {
+        let set = &self.sets[set_index];
+        if let Some(way) = set.iter().position(|line| !line.valid) {
+            return way;
+        }
+
+        match self.config.replacement {
+            Replacement::Lru => Self::oldest(set, |line| line.last_used),
+            Replacement::Fifo => Self::oldest(set, |line| line.filled),
+            Replacement::Random => {
+                self.random_state ^= self.random_state << 13;
+                self.random_state ^= self.random_state >> 17;
+                self.random_state ^= self.random_state << 5;
+                self.random_state as usize % set.len()
+            }
+        }
+    }
+
+    fn oldest(set: &[Line], age: impl Fn(&Line) -> u64) -> usize {
+        (0..set.len()).min_by_key(|&way| age(&set[way])).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::set_associative::Cache;
+    use crate::cache::{Access, CacheConfig, Replacement, WritePolicy};
+
+    fn config(size_words: usize, ways: usize, block_words: usize) -> CacheConfig {
+        CacheConfig {
+            size_words,
+            ways,
+            block_words,
+            ..CacheConfig::default()
+        }
+    }
+
+    // ========== Lookup ==========
+
+    #[test]
+    fn test_first_access_misses_then_hits() {
+        let mut cache = Cache::new(config(16, 1, 4));
+
+        assert!(!cache.access(0x3000, Access::Read));
+        assert!(cache.access(0x3000, Access::Read));
+    }
+
+    #[test]
+    fn test_block_brings_in_neighbouring_words() {
+        let mut cache = Cache::new(config(16, 1, 4));
+
+        cache.access(0x3000, Access::Read);
+
+        assert!(cache.access(0x3003, Access::Read));
+        assert!(!cache.access(0x3004, Access::Read));
+    }
+
+    #[test]
+    fn test_direct_mapped_conflict_evicts() {
+        // 4 sets of 4 words: x3000 and x3010 share set 0
+        let mut cache = Cache::new(config(16, 1, 4));
+
+        cache.access(0x3000, Access::Read);
+        cache.access(0x3010, Access::Read);
+
+        assert!(!cache.access(0x3000, Access::Read));
+        assert_eq!(cache.stats().read_hits, 0);
+    }
+
+    #[test]
+    fn test_associativity_avoids_conflict() {
+        let mut cache = Cache::new(config(16, 2, 4));
+
+        cache.access(0x3000, Access::Read);
+        cache.access(0x3010, Access::Read);
+
+        assert!(cache.access(0x3000, Access::Read));
+    }
+
+    // ========== Replacement ==========
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        // One set, two ways
+        let mut cache = Cache::new(config(2, 2, 1));
+
+        cache.access(0x0000, Access::Read);
+        cache.access(0x0001, Access::Read);
+        cache.access(0x0000, Access::Read);
+        cache.access(0x0002, Access::Read);
+
+        assert!(cache.access(0x0000, Access::Read));
+        assert!(!cache.access(0x0001, Access::Read));
+    }
+
+    #[test]
+    fn test_fifo_evicts_first_filled() {
+        let mut cache = Cache::new(CacheConfig {
+            replacement: Replacement::Fifo,
+            ..config(2, 2, 1)
+        });
+
+        cache.access(0x0000, Access::Read);
+        cache.access(0x0001, Access::Read);
+        cache.access(0x0000, Access::Read);
+        cache.access(0x0002, Access::Read);
+
+        assert!(!cache.access(0x0000, Access::Read));
+    }
+
+    #[test]
+    fn test_random_replacement_keeps_one_of_the_blocks() {
+        let mut cache = Cache::new(CacheConfig {
+            replacement: Replacement::Random,
+            ..config(2, 2, 1)
+        });
+
+        cache.access(0x0000, Access::Read);
+        cache.access(0x0001, Access::Read);
+        cache.access(0x0002, Access::Read);
+
+        assert!(cache.access(0x0002, Access::Read));
+        assert_eq!(cache.stats().misses(), 3);
+    }
+
+    // ========== Write Policies ==========
+
+    #[test]
+    fn test_write_back_counts_dirty_evictions() {
+        let mut cache = Cache::new(config(4, 1, 4));
+
+        cache.access(0x3000, Access::Write);
+        cache.access(0x3001, Access::Write);
+        cache.access(0x4000, Access::Read);
+
+        assert_eq!(cache.stats().write_hits, 1);
+        assert_eq!(cache.stats().writebacks, 1);
+        assert_eq!(cache.stats().memory_writes, 0);
+    }
+
+    #[test]
+    fn test_write_through_does_not_allocate() {
+        let mut cache = Cache::new(CacheConfig {
+            write_policy: WritePolicy::WriteThrough,
+            ..config(4, 1, 4)
+        });
+
+        cache.access(0x3000, Access::Write);
+
+        assert!(!cache.access(0x3000, Access::Read));
+        cache.access(0x3000, Access::Write);
+        assert_eq!(cache.stats().memory_writes, 2);
+        assert_eq!(cache.stats().writebacks, 0);
+    }
+}
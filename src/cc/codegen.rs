@@ -0,0 +1,1247 @@
+//! Turns a parsed program into LC-3 assembly.
+//!
+//! Functions follow the usual LC-3 stack convention. The caller pushes the
+//! arguments last to first, calls, pops the return value and then the
+//! arguments. The callee reserves a word for the return value, saves R7
+//! and the caller's R5, and points R5 at its first local:
+//!
+//! ```text
+//! R5 + 4 + n   argument n
+//! R5 + 3       return value
+//! R5 + 2       return address
+//! R5 + 1       caller's R5
+//! R5 - n       local word n
+//! ```
+//!
+//! Expressions are evaluated into R0, with intermediate values on the
+//! stack, so nothing needs saving across a call. Only R0 and R1 hold
+//! values, so R2 to R4 come back from a function as they went in.
+//! Comparisons subtract, so like most LC-3 compilers they go wrong when
+//! the difference overflows.
+
+use crate::asm::lexer::{is_operation, parse_number};
+use crate::cc::parser::{
+    BinaryOp, Declaration, Expr, ExprKind, Function, Initializer, Program, Stmt, StmtKind, Type,
+    UnaryOp,
+};
+use crate::cc::runtime::Routine;
+use crate::cc::CcError;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Where the stack starts: it grows down from just below the device
+/// registers.
+pub const STACK_BASE: u16 = 0xFE00;
+
+/// Functions the compiler turns into traps.
+const BUILTINS: [&str; 4] = ["putchar", "getchar", "puts", "halt"];
+
+#[derive(Debug, Clone)]
+enum Place {
+    /// A word at an offset from R5.
+    Frame(i16),
+    /// A label.
+    Global(String),
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    ty: Type,
+    place: Place,
+}
+
+struct Signature {
+    returns: Type,
+    parameters: Vec<Type>,
+}
+
+struct Loop {
+    next: String,
+    done: String,
+}
+
+struct Generator {
+    lines: Vec<String>,
+    errors: Vec<CcError>,
+    /// Jump and call through registers, for code too big for PC-relative
+    /// offsets.
+    long: bool,
+    functions: BTreeMap<String, Signature>,
+    globals: BTreeMap<String, Variable>,
+    scopes: Vec<BTreeMap<String, Variable>>,
+    /// The next free frame word, counting down from R5.
+    frame: i16,
+    /// How many frame words the current function has used.
+    locals: i16,
+    loops: Vec<Loop>,
+    /// The current function's name and return type.
+    function: (String, Type),
+    exit: String,
+    labels: usize,
+    strings: Vec<String>,
+    routines: BTreeSet<Routine>,
+}
+
+/// The label for a C name. Names the assembler would read as something
+/// else, such as `add`, `r1` or `x10`, get a leading underscore.
+pub fn symbol(name: &str) -> String {
+    let register = name.len() == 2
+        && name.starts_with(['r', 'R'])
+        && name.as_bytes()[1].is_ascii_digit()
+        && name.as_bytes()[1] < b'8';
+    if register || is_operation(name) || parse_number(name).is_some() {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn flags(mask: u8) -> String {
+    [(4, 'n'), (2, 'z'), (1, 'p')]
+        .iter()
+        .filter(|(bit, _)| mask & bit != 0)
+        .map(|&(_, flag)| flag)
+        .collect()
+}
+
+/// The condition codes that make a comparison true.
+fn mask(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Less => 0b100,
+        BinaryOp::LessOrEqual => 0b110,
+        BinaryOp::Equal => 0b010,
+        BinaryOp::NotEqual => 0b101,
+        BinaryOp::GreaterOrEqual => 0b011,
+        _ => 0b001,
+    }
+}
+
+fn small(value: i32) -> bool {
+    (-16..=15).contains(&value)
+}
+
+/// Evaluates an expression made only of numbers.
+fn constant(expr: &Expr) -> Option<i32> {
+    let value = match &expr.kind {
+        ExprKind::Number(value) => *value as u16 as i16,
+        ExprKind::Unary(UnaryOp::Negate, inner) => (constant(inner)? as i16).wrapping_neg(),
+        ExprKind::Unary(UnaryOp::Complement, inner) => !(constant(inner)? as i16),
+        ExprKind::Binary(op, left, right) => {
+            let (left, right) = (constant(left)? as i16, constant(right)? as i16);
+            match op {
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Subtract => left.wrapping_sub(right),
+                BinaryOp::Multiply => left.wrapping_mul(right),
+                BinaryOp::Divide if right != 0 => left.wrapping_div(right),
+                BinaryOp::Remainder if right != 0 => left.wrapping_rem(right),
+                BinaryOp::ShiftLeft => left.checked_shl(right as u32).unwrap_or(0),
+                BinaryOp::ShiftRight => left >> (right as u32).min(15),
+                BinaryOp::And => left & right,
+                BinaryOp::Or => left | right,
+                BinaryOp::Xor => left ^ right,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some(value as i32)
+}
+
+/// The error for initializing `name` with the wrong shape of value.
+fn needs(name: &str, ty: &Type) -> String {
+    match ty {
+        Type::Array(..) => format!("'{}' needs a list of values", name),
+        _ => format!("'{}' needs a single value", name),
+    }
+}
+
+fn is_zero(expr: &Expr) -> bool {
+    constant(expr) == Some(0)
+}
+
+/// Whether a value of type `value`, from `expr`, can be stored where
+/// `target` is expected. Pointers only mix with `void*` and the
+/// constant 0.
+fn assignable(target: &Type, value: &Type, expr: &Expr) -> bool {
+    let value = value.decay();
+    match (target, &value) {
+        _ if *target == value => true,
+        (Type::Pointer(_), Type::Int) => is_zero(expr),
+        (Type::Pointer(a), Type::Pointer(b)) => **a == Type::Void || **b == Type::Void,
+        _ => false,
+    }
+}
+
+/// The text of `.STRINGZ` for `text`, if the assembler can take it that
+/// way.
+fn stringz(text: &str) -> Option<String> {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\x1B' => quoted.push_str("\\e"),
+            ' '..='~' => quoted.push(c),
+            _ => return None,
+        }
+    }
+    quoted.push('"');
+    Some(quoted)
+}
+
+impl Generator {
+    fn error(&mut self, line: usize, message: String) {
+        self.errors.push(CcError { line, message });
+    }
+
+    fn emit(&mut self, instruction: impl AsRef<str>) {
+        self.lines.push(format!("        {}", instruction.as_ref()));
+    }
+
+    fn place(&mut self, label: &str) {
+        self.lines.push(label.to_string());
+    }
+
+    fn fresh(&mut self) -> String {
+        self.labels += 1;
+        format!("__L{}", self.labels)
+    }
+
+    /// Loads a word written inline, skipped over, for values and addresses
+    /// that do not fit an immediate or PC-relative operand.
+    fn word(&mut self, register: u8, value: &str) {
+        let at = self.fresh();
+        let skip = self.fresh();
+        self.emit(format!("LD R{}, {}", register, at));
+        self.emit(format!("BRnzp {}", skip));
+        self.lines.push(format!("{} .FILL {}", at, value));
+        self.place(&skip);
+    }
+
+    fn constant(&mut self, register: u8, value: i32) {
+        let value = value as u16 as i16 as i32;
+        if small(value) {
+            self.emit(format!("AND R{0}, R{0}, #0", register));
+            if value != 0 {
+                self.emit(format!("ADD R{0}, R{0}, #{1}", register, value));
+            }
+        } else {
+            self.word(register, &format!("#{}", value));
+        }
+    }
+
+    /// `target = source + amount`, in as many steps as the immediate needs.
+    fn add(&mut self, target: u8, source: u8, amount: i32) {
+        let (mut source, mut amount) = (source, amount);
+        while amount != 0 || source != target {
+            let step = amount.clamp(-16, 15);
+            self.emit(format!("ADD R{}, R{}, #{}", target, source, step));
+            source = target;
+            amount -= step;
+        }
+    }
+
+    fn push(&mut self, register: u8) {
+        self.emit("ADD R6, R6, #-1");
+        self.emit(format!("STR R{}, R6, #0", register));
+    }
+
+    fn pop(&mut self, register: u8) {
+        self.emit(format!("LDR R{}, R6, #0", register));
+        self.emit("ADD R6, R6, #1");
+    }
+
+    fn load_frame(&mut self, register: u8, offset: i16) {
+        if (-32..=31).contains(&offset) {
+            self.emit(format!("LDR R{}, R5, #{}", register, offset));
+        } else {
+            self.add(register, 5, offset as i32);
+            self.emit(format!("LDR R{0}, R{0}, #0", register));
+        }
+    }
+
+    /// Stores `register` in the frame, using `scratch` for far offsets.
+    fn store_frame(&mut self, register: u8, offset: i16, scratch: u8) {
+        if (-32..=31).contains(&offset) {
+            self.emit(format!("STR R{}, R5, #{}", register, offset));
+        } else {
+            self.add(scratch, 5, offset as i32);
+            self.emit(format!("STR R{}, R{}, #0", register, scratch));
+        }
+    }
+
+    /// Goes to `target` when the condition codes match `mask`.
+    fn jump(&mut self, mask: u8, target: &str) {
+        if mask == 0 {
+            return;
+        }
+        if !self.long {
+            self.emit(format!("BR{} {}", flags(mask), target));
+            return;
+        }
+        let skip = (mask != 0b111).then(|| self.fresh());
+        if let Some(skip) = &skip {
+            self.emit(format!("BR{} {}", flags(mask ^ 0b111), skip));
+        }
+        // Not R7, or it would read as a RET
+        self.word(1, target);
+        self.emit("JMP R1");
+        if let Some(skip) = &skip {
+            self.place(skip);
+        }
+    }
+
+    /// Calls a function. The arguments are on the stack by then, so R0 is
+    /// free to hold a far address.
+    fn call(&mut self, label: &str) {
+        if self.long {
+            self.word(0, label);
+            self.emit("JSRR R0");
+        } else {
+            self.emit(format!("JSR {}", label));
+        }
+    }
+
+    /// Calls a runtime routine, which takes its operands in R0 and R1.
+    fn routine(&mut self, routine: Routine) {
+        self.routines.insert(routine);
+        let label = routine.label();
+        if self.long {
+            self.push(4);
+            self.word(4, label);
+            self.emit("JSRR R4");
+            self.pop(4);
+        } else {
+            self.emit(format!("JSR {}", label));
+        }
+    }
+
+    fn string(&mut self, text: &str) -> String {
+        let index = match self.strings.iter().position(|known| known == text) {
+            Some(index) => index,
+            None => {
+                self.strings.push(text.to_string());
+                self.strings.len() - 1
+            }
+        };
+        format!("__S{}", index)
+    }
+
+    fn lookup(&self, name: &str) -> Option<Variable> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned()
+    }
+
+    fn undefined(&mut self, line: usize, name: &str) {
+        let message = if self.functions.contains_key(name) || BUILTINS.contains(&name) {
+            format!("'{}' is a function", name)
+        } else {
+            format!("'{}' is not declared", name)
+        };
+        self.error(line, message);
+    }
+
+    // ========== Expressions ==========
+
+    /// Evaluates `expr` into R0 and gives its type.
+    fn expr(&mut self, expr: &Expr) -> Type {
+        let line = expr.line;
+        match &expr.kind {
+            ExprKind::Number(value) => {
+                self.constant(0, *value);
+                Type::Int
+            }
+            ExprKind::String(text) => {
+                let label = self.string(text);
+                self.word(0, &label);
+                Type::Pointer(Box::new(Type::Int))
+            }
+            ExprKind::Name(name) => {
+                let Some(variable) = self.lookup(name) else {
+                    self.undefined(line, name);
+                    return Type::Int;
+                };
+                match (&variable.place, &variable.ty) {
+                    (Place::Frame(offset), Type::Array(..)) => self.add(0, 5, *offset as i32),
+                    (Place::Frame(offset), _) => self.load_frame(0, *offset),
+                    (Place::Global(label), ty) => {
+                        self.word(0, label);
+                        if !matches!(ty, Type::Array(..)) {
+                            self.emit("LDR R0, R0, #0");
+                        }
+                    }
+                }
+                variable.ty.decay()
+            }
+            ExprKind::Call(name, arguments) => self.call_function(line, name, arguments),
+            ExprKind::Index(..) | ExprKind::Unary(UnaryOp::Deref, _) => {
+                match self.address(expr, "read") {
+                    Some(ty) => {
+                        self.emit("LDR R0, R0, #0");
+                        ty.decay()
+                    }
+                    None => Type::Int,
+                }
+            }
+            ExprKind::Unary(UnaryOp::AddressOf, inner) => {
+                match self.address(inner, "take the address of") {
+                    Some(Type::Array(element, _)) => Type::Pointer(element),
+                    Some(ty) => Type::Pointer(Box::new(ty)),
+                    None => Type::Int,
+                }
+            }
+            ExprKind::Unary(op @ (UnaryOp::Negate | UnaryOp::Complement), inner) => {
+                let ty = self.value(inner);
+                self.expect_int(line, &ty, if *op == UnaryOp::Negate { "-" } else { "~" });
+                self.emit("NOT R0, R0");
+                if *op == UnaryOp::Negate {
+                    self.emit("ADD R0, R0, #1");
+                }
+                Type::Int
+            }
+            ExprKind::Unary(UnaryOp::Not, _) => self.truth(expr),
+            ExprKind::Binary(op, ..)
+                if op.is_comparison()
+                    || matches!(op, BinaryOp::LogicalAnd | BinaryOp::LogicalOr) =>
+            {
+                self.truth(expr)
+            }
+            ExprKind::Binary(op, left, right) => self.arithmetic(line, *op, left, right),
+            ExprKind::Assign(target, value) => self.assign(line, target, value),
+        }
+    }
+
+    /// Like `expr`, for places that need a value.
+    fn value(&mut self, expr: &Expr) -> Type {
+        let ty = self.expr(expr);
+        if ty == Type::Void {
+            self.error(expr.line, "a void value is not a value".to_string());
+            return Type::Int;
+        }
+        ty
+    }
+
+    fn expect_int(&mut self, line: usize, ty: &Type, operator: &str) {
+        if *ty != Type::Int {
+            self.error(
+                line,
+                format!("'{}' needs int operands, not '{}'", operator, ty),
+            );
+        }
+    }
+
+    /// Puts the address of `expr` in R0 and gives the type stored there.
+    /// `action` completes "cannot ... this expression" when `expr` has no
+    /// address.
+    fn address(&mut self, expr: &Expr, action: &str) -> Option<Type> {
+        let line = expr.line;
+        match &expr.kind {
+            ExprKind::Name(name) => {
+                let Some(variable) = self.lookup(name) else {
+                    self.undefined(line, name);
+                    return None;
+                };
+                match &variable.place {
+                    Place::Frame(offset) => self.add(0, 5, *offset as i32),
+                    Place::Global(label) => self.word(0, label),
+                }
+                Some(variable.ty)
+            }
+            ExprKind::Unary(UnaryOp::Deref, pointer) => {
+                let ty = self.value(pointer);
+                self.target(line, &ty, "dereference")
+            }
+            ExprKind::Index(base, index) => {
+                let ty = self.value(base);
+                match constant(index) {
+                    Some(offset) if small(offset) => {
+                        if offset != 0 {
+                            self.emit(format!("ADD R0, R0, #{}", offset));
+                        }
+                    }
+                    _ => {
+                        self.push(0);
+                        let index_type = self.value(index);
+                        self.expect_int(line, &index_type, "[]");
+                        self.pop(1);
+                        self.emit("ADD R0, R1, R0");
+                    }
+                }
+                self.target(line, &ty, "index")
+            }
+            _ => {
+                self.error(line, format!("cannot {} this expression", action));
+                None
+            }
+        }
+    }
+
+    /// What a pointer of type `ty` points at.
+    fn target(&mut self, line: usize, ty: &Type, action: &str) -> Option<Type> {
+        match ty {
+            Type::Pointer(target) if **target != Type::Void => Some((**target).clone()),
+            _ => {
+                self.error(line, format!("cannot {} '{}'", action, ty));
+                None
+            }
+        }
+    }
+
+    fn assign(&mut self, line: usize, target: &Expr, value: &Expr) -> Type {
+        let variable = match &target.kind {
+            ExprKind::Name(name) => self.lookup(name),
+            _ => None,
+        };
+        let ty = match variable {
+            // A variable is stored straight into its place
+            Some(Variable { ty, place }) if !matches!(ty, Type::Array(..)) => {
+                let value_type = self.value(value);
+                self.check_assignable(line, &ty, &value_type, value);
+                match place {
+                    Place::Frame(offset) => self.store_frame(0, offset, 1),
+                    Place::Global(label) => {
+                        self.word(1, &label);
+                        self.emit("STR R0, R1, #0");
+                    }
+                }
+                ty
+            }
+            _ => {
+                let Some(ty) = self.address(target, "assign to") else {
+                    self.value(value);
+                    return Type::Int;
+                };
+                if matches!(ty, Type::Array(..)) {
+                    self.error(line, format!("cannot assign to an array ('{}')", ty));
+                }
+                self.push(0);
+                let value_type = self.value(value);
+                self.check_assignable(line, &ty, &value_type, value);
+                self.pop(1);
+                self.emit("STR R0, R1, #0");
+                ty
+            }
+        };
+        ty.decay()
+    }
+
+    fn check_assignable(&mut self, line: usize, target: &Type, value: &Type, expr: &Expr) {
+        if !assignable(target, value, expr) {
+            self.error(
+                line,
+                format!("cannot assign '{}' to '{}'", value.decay(), target),
+            );
+        }
+    }
+
+    fn call_function(&mut self, line: usize, name: &str, arguments: &[Expr]) -> Type {
+        let builtin = match name {
+            "putchar" => Some(("OUT", vec![Type::Int], Type::Void)),
+            "puts" => Some(("PUTS", vec![Type::Pointer(Box::new(Type::Int))], Type::Void)),
+            "getchar" => Some(("GETC", vec![], Type::Int)),
+            "halt" => Some(("HALT", vec![], Type::Void)),
+            _ => None,
+        };
+        let (parameters, returns) = match (&builtin, self.functions.get(name)) {
+            (Some((_, parameters, returns)), _) => (parameters.clone(), returns.clone()),
+            (None, Some(signature)) => (signature.parameters.clone(), signature.returns.clone()),
+            (None, None) => {
+                let message = match self.lookup(name) {
+                    Some(_) => format!("'{}' is not a function", name),
+                    None => format!("'{}' is not declared", name),
+                };
+                self.error(line, message);
+                return Type::Int;
+            }
+        };
+        if arguments.len() != parameters.len() {
+            self.error(
+                line,
+                format!(
+                    "'{}' takes {} argument{}, not {}",
+                    name,
+                    parameters.len(),
+                    if parameters.len() == 1 { "" } else { "s" },
+                    arguments.len()
+                ),
+            );
+            return returns;
+        }
+
+        let check = |generator: &mut Generator, index: usize, ty: &Type| {
+            if !assignable(&parameters[index], ty, &arguments[index]) {
+                generator.error(
+                    line,
+                    format!(
+                        "argument {} of '{}' is '{}', not '{}'",
+                        index + 1,
+                        name,
+                        ty.decay(),
+                        parameters[index]
+                    ),
+                );
+            }
+        };
+        if let Some((trap, _, _)) = builtin {
+            if let Some(argument) = arguments.first() {
+                let ty = self.value(argument);
+                check(self, 0, &ty);
+            }
+            self.emit(trap);
+            return returns;
+        }
+        for (index, argument) in arguments.iter().enumerate().rev() {
+            let ty = self.value(argument);
+            check(self, index, &ty);
+            self.push(0);
+        }
+        self.call(&symbol(name));
+        // A void function leaves its result slot unset
+        if returns != Type::Void {
+            self.emit("LDR R0, R6, #0");
+        }
+        self.add(6, 6, arguments.len() as i32 + 1);
+        returns
+    }
+
+    fn arithmetic(&mut self, line: usize, op: BinaryOp, left: &Expr, right: &Expr) -> Type {
+        let left_type = self.value(left);
+        let immediate = constant(right).filter(|&value| match op {
+            BinaryOp::Add | BinaryOp::And => small(value),
+            BinaryOp::Subtract => small(-value),
+            BinaryOp::ShiftLeft => (0..16).contains(&value),
+            _ => false,
+        });
+        let right_type = match immediate {
+            Some(value) => {
+                match op {
+                    BinaryOp::Add => self.emit(format!("ADD R0, R0, #{}", value)),
+                    BinaryOp::Subtract => self.emit(format!("ADD R0, R0, #{}", -value)),
+                    BinaryOp::And => self.emit(format!("AND R0, R0, #{}", value)),
+                    _ => {
+                        for _ in 0..value {
+                            self.emit("ADD R0, R0, R0");
+                        }
+                    }
+                }
+                Type::Int
+            }
+            None => {
+                self.push(0);
+                let right_type = self.value(right);
+                self.emit("ADD R1, R0, #0");
+                self.pop(0);
+                match op {
+                    BinaryOp::Add => self.emit("ADD R0, R0, R1"),
+                    BinaryOp::Subtract => {
+                        self.emit("NOT R1, R1");
+                        self.emit("ADD R1, R1, #1");
+                        self.emit("ADD R0, R0, R1");
+                    }
+                    BinaryOp::And => self.emit("AND R0, R0, R1"),
+                    // a | b is ~(~a & ~b)
+                    BinaryOp::Or => {
+                        self.emit("NOT R0, R0");
+                        self.emit("NOT R1, R1");
+                        self.emit("AND R0, R0, R1");
+                        self.emit("NOT R0, R0");
+                    }
+                    // a ^ b is (a | b) - (a & b)
+                    BinaryOp::Xor => {
+                        self.push(0);
+                        self.emit("AND R0, R0, R1");
+                        self.emit("NOT R0, R0");
+                        self.emit("ADD R0, R0, #1");
+                        self.push(0);
+                        self.emit("LDR R0, R6, #1");
+                        self.emit("NOT R0, R0");
+                        self.emit("NOT R1, R1");
+                        self.emit("AND R0, R0, R1");
+                        self.emit("NOT R0, R0");
+                        self.emit("LDR R1, R6, #0");
+                        self.emit("ADD R0, R0, R1");
+                        self.emit("ADD R6, R6, #2");
+                    }
+                    BinaryOp::Multiply => self.routine(Routine::Multiply),
+                    BinaryOp::Divide => self.routine(Routine::DivMod),
+                    BinaryOp::Remainder => {
+                        self.routine(Routine::DivMod);
+                        self.emit("ADD R0, R1, #0");
+                    }
+                    BinaryOp::ShiftLeft => self.routine(Routine::ShiftLeft),
+                    _ => self.routine(Routine::ShiftRight),
+                }
+                right_type
+            }
+        };
+
+        let pointer = |ty: &Type| matches!(ty, Type::Pointer(_));
+        match (op, &left_type, &right_type) {
+            (_, Type::Int, Type::Int) => Type::Int,
+            (BinaryOp::Add | BinaryOp::Subtract, ty, Type::Int) if pointer(ty) => ty.clone(),
+            (BinaryOp::Add, Type::Int, ty) if pointer(ty) => ty.clone(),
+            (BinaryOp::Subtract, a, b) if pointer(a) && a == b => Type::Int,
+            _ => {
+                let symbol = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Subtract => "-",
+                    BinaryOp::Multiply => "*",
+                    BinaryOp::Divide => "/",
+                    BinaryOp::Remainder => "%",
+                    BinaryOp::ShiftLeft => "<<",
+                    BinaryOp::ShiftRight => ">>",
+                    BinaryOp::And => "&",
+                    BinaryOp::Or => "|",
+                    _ => "^",
+                };
+                self.error(
+                    line,
+                    format!(
+                        "cannot apply '{}' to '{}' and '{}'",
+                        symbol, left_type, right_type
+                    ),
+                );
+                Type::Int
+            }
+        }
+    }
+
+    /// Sets the condition codes from `left - right`.
+    fn compare(&mut self, line: usize, left: &Expr, right: &Expr) {
+        let left_type = self.value(left);
+        let right_type = match constant(right) {
+            Some(value) if small(-value) => {
+                self.emit(format!("ADD R0, R0, #{}", -value));
+                Type::Int
+            }
+            _ => {
+                self.push(0);
+                let right_type = self.value(right);
+                self.emit("NOT R1, R0");
+                self.emit("ADD R1, R1, #1");
+                self.pop(0);
+                self.emit("ADD R0, R0, R1");
+                right_type
+            }
+        };
+        let comparable = left_type == right_type
+            || (matches!(left_type, Type::Pointer(_)) && is_zero(right))
+            || (matches!(right_type, Type::Pointer(_)) && is_zero(left));
+        if !comparable {
+            self.error(
+                line,
+                format!("cannot compare '{}' and '{}'", left_type, right_type),
+            );
+        }
+    }
+
+    /// Jumps to `target` when `expr` is true, or when it is false if `when`
+    /// is false.
+    fn condition(&mut self, expr: &Expr, target: &str, when: bool) {
+        match &expr.kind {
+            ExprKind::Unary(UnaryOp::Not, inner) => self.condition(inner, target, !when),
+            ExprKind::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), left, right) => {
+                // `a && b` is false as soon as `a` is, `a || b` true
+                let decided_by = *op == BinaryOp::LogicalOr;
+                if when == decided_by {
+                    self.condition(left, target, when);
+                    self.condition(right, target, when);
+                } else {
+                    let skip = self.fresh();
+                    self.condition(left, &skip, decided_by);
+                    self.condition(right, target, when);
+                    self.place(&skip);
+                }
+            }
+            ExprKind::Binary(op, left, right) if op.is_comparison() => {
+                self.compare(expr.line, left, right);
+                let mask = mask(*op);
+                self.jump(if when { mask } else { mask ^ 0b111 }, target);
+            }
+            _ => match constant(expr) {
+                Some(value) => {
+                    if (value != 0) == when {
+                        self.jump(0b111, target);
+                    }
+                }
+                None => {
+                    self.value(expr);
+                    self.emit("ADD R0, R0, #0");
+                    self.jump(if when { 0b101 } else { 0b010 }, target);
+                }
+            },
+        }
+    }
+
+    /// Evaluates a condition to 1 or 0.
+    fn truth(&mut self, expr: &Expr) -> Type {
+        let yes = self.fresh();
+        let done = self.fresh();
+        self.condition(expr, &yes, true);
+        self.emit("AND R0, R0, #0");
+        self.jump(0b111, &done);
+        self.place(&yes);
+        self.emit("AND R0, R0, #0");
+        self.emit("ADD R0, R0, #1");
+        self.place(&done);
+        Type::Int
+    }
+
+    // ========== Statements ==========
+
+    fn declare(&mut self, declaration: &Declaration) {
+        let Declaration { name, ty, line, .. } = declaration;
+        let scope = self.scopes.last_mut().expect("a function has a scope");
+        if scope.contains_key(name) {
+            self.error(*line, format!("'{}' is already declared", name));
+            return;
+        }
+        let size = ty.size() as i16;
+        let offset = self.frame - (size - 1);
+        self.frame = offset - 1;
+        self.locals = self.locals.max(-self.frame);
+        scope.insert(
+            name.clone(),
+            Variable {
+                ty: ty.clone(),
+                place: Place::Frame(offset),
+            },
+        );
+
+        match (&declaration.initializer, ty) {
+            (None, _) => {}
+            (Some(Initializer::Expr(value)), _) if !matches!(ty, Type::Array(..)) => {
+                let value_type = self.value(value);
+                self.check_assignable(*line, ty, &value_type, value);
+                self.store_frame(0, offset, 1);
+            }
+            (Some(Initializer::List(values)), Type::Array(element, count)) => {
+                if values.len() > *count as usize {
+                    self.error(*line, format!("too many values for '{}'", name));
+                }
+                for (index, value) in values.iter().enumerate().take(*count as usize) {
+                    let value_type = self.value(value);
+                    self.check_assignable(*line, element, &value_type, value);
+                    self.store_frame(0, offset + index as i16, 1);
+                }
+            }
+            (Some(_), ty) => self.error(*line, needs(name, ty)),
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+            }
+            StmtKind::Declare(declaration) => self.declare(declaration),
+            StmtKind::If(condition, then, otherwise) => {
+                let skip = self.fresh();
+                self.condition(condition, &skip, false);
+                self.statement(then);
+                match otherwise {
+                    Some(otherwise) => {
+                        let done = self.fresh();
+                        self.jump(0b111, &done);
+                        self.place(&skip);
+                        self.statement(otherwise);
+                        self.place(&done);
+                    }
+                    None => self.place(&skip),
+                }
+            }
+            StmtKind::While(condition, body) => {
+                let next = self.fresh();
+                let done = self.fresh();
+                self.place(&next);
+                self.condition(condition, &done, false);
+                self.loops.push(Loop {
+                    next: next.clone(),
+                    done: done.clone(),
+                });
+                self.scoped(std::slice::from_ref(body));
+                self.loops.pop();
+                self.jump(0b111, &next);
+                self.place(&done);
+            }
+            StmtKind::Return(value) => {
+                let (name, returns) = self.function.clone();
+                match (value, &returns) {
+                    (Some(value), Type::Void) => {
+                        self.expr(value);
+                        self.error(stmt.line, format!("'{}' returns void", name));
+                    }
+                    (Some(value), returns) => {
+                        let ty = self.value(value);
+                        self.check_assignable(stmt.line, returns, &ty, value);
+                        self.emit("STR R0, R5, #3");
+                    }
+                    (None, Type::Void) => {}
+                    (None, returns) => {
+                        self.error(stmt.line, format!("'{}' must return '{}'", name, returns));
+                    }
+                }
+                let exit = self.exit.clone();
+                self.jump(0b111, &exit);
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let is_break = stmt.kind == StmtKind::Break;
+                let target = self.loops.last().map(|innermost| {
+                    if is_break {
+                        innermost.done.clone()
+                    } else {
+                        innermost.next.clone()
+                    }
+                });
+                match target {
+                    Some(target) => self.jump(0b111, &target),
+                    None => {
+                        let word = if is_break { "break" } else { "continue" };
+                        self.error(stmt.line, format!("'{}' outside a loop", word));
+                    }
+                }
+            }
+            StmtKind::Block(stmts) => self.scoped(stmts),
+        }
+    }
+
+    fn scoped(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(BTreeMap::new());
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn function(&mut self, function: &Function) {
+        let mut parameters = BTreeMap::new();
+        for (index, (name, ty)) in function.parameters.iter().enumerate() {
+            let variable = Variable {
+                ty: ty.clone(),
+                place: Place::Frame(4 + index as i16),
+            };
+            if parameters.insert(name.clone(), variable).is_some() {
+                self.error(function.line, format!("parameter '{}' appears twice", name));
+            }
+        }
+        self.scopes = vec![parameters, BTreeMap::new()];
+        self.frame = 0;
+        self.locals = 0;
+        self.function = (function.name.clone(), function.returns.clone());
+        self.exit = self.fresh();
+
+        let outside = std::mem::take(&mut self.lines);
+        for stmt in &function.body {
+            self.statement(stmt);
+        }
+        let mut body = std::mem::replace(&mut self.lines, outside);
+        // A return at the very end falls through to the exit anyway
+        if body.last().map(|line| line.trim()) == Some(&format!("BRnzp {}", self.exit)) {
+            body.pop();
+        }
+
+        let parameters = function
+            .parameters
+            .iter()
+            .map(|(name, ty)| format!("{} {}", ty, name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.lines.push(format!(
+            "; {} {}({})",
+            function.returns, function.name, parameters
+        ));
+        self.place(&symbol(&function.name));
+        self.emit("ADD R6, R6, #-1");
+        self.push(7);
+        self.push(5);
+        self.emit("ADD R5, R6, #-1");
+        self.add(6, 6, -(self.locals as i32));
+        self.lines.extend(body);
+        let exit = self.exit.clone();
+        self.place(&exit);
+        self.emit("ADD R6, R5, #1");
+        self.pop(5);
+        self.pop(7);
+        self.emit("RET");
+    }
+
+    // ========== Data ==========
+
+    /// The `.FILL` operand for a global's initial value.
+    fn initial(&mut self, ty: &Type, value: &Expr) -> Option<String> {
+        match (&value.kind, ty) {
+            (ExprKind::String(text), Type::Pointer(target)) if **target == Type::Int => {
+                Some(self.string(text))
+            }
+            _ if *ty == Type::Int || is_zero(value) => {
+                constant(value).map(|value| format!("#{}", value))
+            }
+            _ => None,
+        }
+    }
+
+    /// Lays out a global. Like C, globals without a value start at zero,
+    /// so even their `.BLKW` space is given one.
+    fn global(&mut self, declaration: &Declaration) {
+        let label = symbol(&declaration.name);
+        let line = declaration.line;
+        self.place(&label);
+        match (&declaration.ty, &declaration.initializer) {
+            (Type::Array(_, count), None) => self.emit(format!(".BLKW {} #0", count)),
+            (Type::Array(element, count), Some(Initializer::List(values))) => {
+                if values.len() > *count as usize {
+                    self.error(line, format!("too many values for '{}'", declaration.name));
+                }
+                for value in values.iter().take(*count as usize) {
+                    match self.initial(element, value) {
+                        Some(word) => self.emit(format!(".FILL {}", word)),
+                        None => {
+                            self.emit(".FILL #0");
+                            self.error(
+                                line,
+                                format!("'{}' needs constant values", declaration.name),
+                            );
+                        }
+                    }
+                }
+                if values.len() < *count as usize {
+                    self.emit(format!(".BLKW {} #0", *count as usize - values.len()));
+                }
+            }
+            (_, None) => self.emit(".FILL #0"),
+            (ty, Some(Initializer::Expr(value))) if !matches!(ty, Type::Array(..)) => {
+                match self.initial(ty, value) {
+                    Some(word) => self.emit(format!(".FILL {}", word)),
+                    None => {
+                        self.emit(".FILL #0");
+                        self.error(
+                            line,
+                            format!("'{}' needs a constant value", declaration.name),
+                        );
+                    }
+                }
+            }
+            (ty, _) => {
+                self.emit(format!(".BLKW {} #0", ty.size()));
+                self.error(line, needs(&declaration.name, ty));
+            }
+        }
+    }
+}
+
+/// Generates the assembly for `program`: a start-up stub at x3000 that
+/// sets up the stack and calls `main`, the functions, the runtime
+/// routines they use and then the global data. `long` makes every jump
+/// and call go through a register.
+pub fn generate(program: &Program, long: bool) -> Result<String, Vec<CcError>> {
+    let mut generator = Generator {
+        lines: Vec::new(),
+        errors: Vec::new(),
+        long,
+        functions: BTreeMap::new(),
+        globals: BTreeMap::new(),
+        scopes: Vec::new(),
+        frame: 0,
+        locals: 0,
+        loops: Vec::new(),
+        function: (String::new(), Type::Void),
+        exit: String::new(),
+        labels: 0,
+        strings: Vec::new(),
+        routines: BTreeSet::new(),
+    };
+
+    let mut labels = BTreeSet::new();
+    for declaration in &program.globals {
+        if !labels.insert(symbol(&declaration.name)) {
+            generator.error(
+                declaration.line,
+                format!("'{}' is already declared", declaration.name),
+            );
+        }
+        generator.globals.insert(
+            declaration.name.clone(),
+            Variable {
+                ty: declaration.ty.clone(),
+                place: Place::Global(symbol(&declaration.name)),
+            },
+        );
+    }
+    for function in &program.functions {
+        if BUILTINS.contains(&function.name.as_str()) {
+            generator.error(function.line, format!("'{}' is built in", function.name));
+        } else if !labels.insert(symbol(&function.name)) {
+            generator.error(
+                function.line,
+                format!("'{}' is already declared", function.name),
+            );
+        }
+        generator.functions.insert(
+            function.name.clone(),
+            Signature {
+                returns: function.returns.clone(),
+                parameters: function
+                    .parameters
+                    .iter()
+                    .map(|(_, ty)| ty.clone())
+                    .collect(),
+            },
+        );
+    }
+    match program
+        .functions
+        .iter()
+        .find(|function| function.name == "main")
+    {
+        None => generator.error(1, "there is no 'main' function".to_string()),
+        Some(main) if !main.parameters.is_empty() => {
+            generator.error(main.line, "'main' takes no parameters".to_string())
+        }
+        Some(_) => {}
+    }
+
+    generator.emit(".ORIG x3000");
+    generator.emit("LD R6, __stack");
+    generator.emit("ADD R5, R6, #0");
+    generator.call("main");
+    generator.emit("HALT");
+    generator
+        .lines
+        .push(format!("__stack .FILL x{:04X}", STACK_BASE));
+    for function in &program.functions {
+        generator.function(function);
+    }
+    for routine in generator.routines.clone() {
+        generator
+            .lines
+            .extend(routine.source().lines().map(str::to_string));
+    }
+    for declaration in &program.globals {
+        generator.global(declaration);
+    }
+    for (index, text) in generator.strings.clone().iter().enumerate() {
+        generator.place(&format!("__S{}", index));
+        match stringz(text) {
+            Some(quoted) => generator.emit(format!(".STRINGZ {}", quoted)),
+            None => {
+                for c in text.chars() {
+                    generator.emit(format!(".FILL x{:04X}", c as u32 as u16));
+                }
+                generator.emit(".FILL #0");
+            }
+        }
+    }
+    generator.emit(".END");
+
+    if generator.errors.is_empty() {
+        let mut text = generator.lines.join("\n");
+        text.push('\n');
+        Ok(text)
+    } else {
+        generator.errors.sort_by_key(|error| error.line);
+        Err(generator.errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cc::codegen::{generate, stringz, symbol};
+    use crate::cc::parser::parse;
+
+    fn lines(source: &str) -> Vec<String> {
+        generate(&parse(source).unwrap(), false)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    fn contains(lines: &[String], run: &[&str]) -> bool {
+        lines.windows(run.len()).any(|window| window == run)
+    }
+
+    #[test]
+    fn test_names_the_assembler_would_misread_are_prefixed() {
+        assert_eq!(symbol("count"), "count");
+        assert_eq!(symbol("add"), "_add");
+        assert_eq!(symbol("r3"), "_r3");
+        assert_eq!(symbol("r9"), "r9");
+        assert_eq!(symbol("x1F"), "_x1F");
+    }
+
+    #[test]
+    fn test_function_builds_a_frame_for_its_locals() {
+        let lines = lines("int main() { int a[2]; int k = 300; return k; }");
+
+        assert!(contains(
+            &lines,
+            &[
+                "main",
+                "ADD R6, R6, #-1",
+                "ADD R6, R6, #-1",
+                "STR R7, R6, #0",
+                "ADD R6, R6, #-1",
+                "STR R5, R6, #0",
+                "ADD R5, R6, #-1",
+                "ADD R6, R6, #-3",
+            ]
+        ));
+        assert!(contains(
+            &lines,
+            &["__L2 .FILL #300", "__L3", "STR R0, R5, #-2"]
+        ));
+        assert!(contains(
+            &lines,
+            &["LDR R0, R5, #-2", "STR R0, R5, #3", "__L1"]
+        ));
+    }
+
+    #[test]
+    fn test_calls_push_arguments_last_first() {
+        let lines = lines("int f(int a, int b) { return a; }\nint main() { return f(1, 2); }");
+
+        assert!(contains(
+            &lines,
+            &[
+                "ADD R0, R0, #2",
+                "ADD R6, R6, #-1",
+                "STR R0, R6, #0",
+                "AND R0, R0, #0",
+                "ADD R0, R0, #1",
+                "ADD R6, R6, #-1",
+                "STR R0, R6, #0",
+                "JSR f",
+                "LDR R0, R6, #0",
+                "ADD R6, R6, #3",
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_only_used_runtime_routines_are_included() {
+        let lines = lines("int main() { int a = 6; return a * 7 + a % 4; }");
+
+        assert!(lines.iter().any(|line| line == "__mul"));
+        assert!(lines.iter().any(|line| line == "__divmod"));
+        assert!(!lines.iter().any(|line| line == "__shr"));
+    }
+
+    #[test]
+    fn test_strings_use_stringz_when_they_can() {
+        assert_eq!(stringz("a \"b\"\n").unwrap(), r#""a \"b\"\n""#);
+        assert_eq!(stringz("\u{1}"), None);
+
+        let lines = lines("int main() { puts(\"hi\"); puts(\"hi\"); return 0; }");
+        assert_eq!(
+            lines.iter().filter(|line| line.starts_with("__S")).count(),
+            1
+        );
+        assert!(contains(&lines, &["__S0", ".STRINGZ \"hi\""]));
+    }
+}
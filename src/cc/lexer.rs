@@ -0,0 +1,226 @@
+use crate::cc::CcError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A number or character literal.
+    Number(i32),
+    String(String),
+    Name(String),
+    Keyword(&'static str),
+    Symbol(&'static str),
+}
+
+/// A token and the 1-based line it starts on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned {
+    pub token: Token,
+    pub line: usize,
+}
+
+pub const KEYWORDS: [&str; 8] = [
+    "int", "void", "if", "else", "while", "return", "break", "continue",
+];
+
+/// Longest first, so `<=` is not read as `<` then `=`.
+const SYMBOLS: [&str; 29] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+    "!", "<", ">", "=", "(", ")", "[", "]", "{", "}", ",", ";",
+];
+
+fn escape(c: char) -> Option<char> {
+    Some(match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        'e' => '\x1B',
+        '\\' | '\'' | '"' => c,
+        _ => return None,
+    })
+}
+
+/// Splits C source into tokens, dropping `//` and `/* */` comments.
+pub fn tokens(source: &str) -> Result<Vec<Spanned>, CcError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.char_indices().peekable();
+    let error = |line: usize, message: String| CcError { line, message };
+
+    while let Some(&(start, c)) = chars.peek() {
+        let rest = &source[start..];
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if rest.starts_with("//") {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if rest.starts_with("/*") {
+            let opened = line;
+            chars.next();
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some((at, '*')) if source[at..].starts_with("*/") => {
+                        chars.next();
+                        break;
+                    }
+                    Some((_, '\n')) => line += 1,
+                    Some(_) => {}
+                    None => return Err(error(opened, "unterminated comment".to_string())),
+                }
+            }
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, quote)) if quote == c => break,
+                    Some((_, '\\')) => {
+                        let escaped = chars.next().map(|(_, c)| c);
+                        match escaped.and_then(escape) {
+                            Some(escaped) => text.push(escaped),
+                            None => {
+                                let escaped = escaped.map_or(String::new(), String::from);
+                                return Err(error(line, format!("unknown escape '\\{}'", escaped)));
+                            }
+                        }
+                    }
+                    Some((_, '\n')) | None => {
+                        return Err(error(line, "unterminated literal".to_string()));
+                    }
+                    Some((_, other)) => text.push(other),
+                }
+            }
+            let token = if c == '"' {
+                Token::String(text)
+            } else {
+                let mut characters = text.chars();
+                match (characters.next(), characters.next()) {
+                    (Some(character), None) => Token::Number(character as i32),
+                    _ => {
+                        return Err(error(
+                            line,
+                            "a character literal holds one character".to_string(),
+                        ));
+                    }
+                }
+            };
+            tokens.push(Spanned { token, line });
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            for _ in 0..word.len() {
+                chars.next();
+            }
+            let token = if c.is_ascii_digit() {
+                let (digits, radix) = match word.strip_prefix("0x").or(word.strip_prefix("0X")) {
+                    Some(digits) => (digits, 16),
+                    None => (word, 10),
+                };
+                match i32::from_str_radix(digits, radix) {
+                    Ok(value) if value <= 0xFFFF => Token::Number(value),
+                    Ok(_) => return Err(error(line, format!("{} does not fit in 16 bits", word))),
+                    Err(_) => return Err(error(line, format!("invalid number '{}'", word))),
+                }
+            } else if let Some(&keyword) = KEYWORDS.iter().find(|&&keyword| keyword == word) {
+                Token::Keyword(keyword)
+            } else {
+                Token::Name(word.to_string())
+            };
+            tokens.push(Spanned { token, line });
+        } else if let Some(&symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push(Spanned {
+                token: Token::Symbol(symbol),
+                line,
+            });
+        } else {
+            return Err(error(line, format!("unexpected '{}'", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cc::lexer::{tokens, Token};
+
+    fn kinds(source: &str) -> Vec<Token> {
+        tokens(source)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens_of_a_declaration() {
+        assert_eq!(
+            kinds("int *p = &a[0x1F]; // done"),
+            [
+                Token::Keyword("int"),
+                Token::Symbol("*"),
+                Token::Name("p".to_string()),
+                Token::Symbol("="),
+                Token::Symbol("&"),
+                Token::Name("a".to_string()),
+                Token::Symbol("["),
+                Token::Number(31),
+                Token::Symbol("]"),
+                Token::Symbol(";"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operators_are_read_longest_first() {
+        assert_eq!(
+            kinds("a<<=b"),
+            [
+                Token::Name("a".to_string()),
+                Token::Symbol("<<"),
+                Token::Symbol("="),
+                Token::Name("b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_literals_take_escapes() {
+        assert_eq!(
+            kinds(r#"'\n' 'A' "hi\t\"x\"""#),
+            [
+                Token::Number(10),
+                Token::Number(65),
+                Token::String("hi\t\"x\"".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lines_count_through_comments() {
+        let tokens = tokens("a /* one\ntwo */ b\n// three\nc").unwrap();
+        let lines: Vec<usize> = tokens.iter().map(|spanned| spanned.line).collect();
+
+        assert_eq!(lines, [1, 2, 4]);
+    }
+
+    #[test]
+    fn test_bad_input_is_reported_with_its_line() {
+        let error = tokens("int a;\nint b = 70000;").unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "70000 does not fit in 16 bits")
+        );
+        assert_eq!(
+            tokens("\"open").unwrap_err().message,
+            "unterminated literal"
+        );
+        assert_eq!(tokens("a @ b").unwrap_err().message, "unexpected '@'");
+    }
+}
@@ -0,0 +1,394 @@
+pub mod codegen;
+pub mod lexer;
+pub mod parser;
+pub mod runtime;
+
+use crate::asm::{assemble, Assembly};
+use crate::cc::codegen::generate;
+use crate::cc::parser::parse;
+use std::fmt;
+
+/// A problem with the source, at a 1-based line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CcError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Compiles a C-like source file to LC-3 assembly that starts at x3000.
+///
+/// The code uses PC-relative branches and calls where it can. When the
+/// program is too big for them, it is generated again with every jump
+/// and call going through a register.
+pub fn compile(source: &str) -> Result<String, Vec<CcError>> {
+    let program = parse(source).map_err(|error| vec![error])?;
+    let short = generate(&program, false)?;
+    if assemble(&short).is_ok() {
+        return Ok(short);
+    }
+    let long = generate(&program, true)?;
+    match assemble(&long) {
+        Ok(_) => Ok(long),
+        Err(errors) => Err(errors
+            .into_iter()
+            .map(|error| CcError {
+                line: 0,
+                message: format!(
+                    "the generated assembly does not assemble: line {}: {}",
+                    error.line, error.message
+                ),
+            })
+            .collect()),
+    }
+}
+
+/// Compiles and assembles a source file, ready to load.
+pub fn compile_image(source: &str) -> Result<Assembly, Vec<CcError>> {
+    let assembly = compile(source)?;
+    Ok(assemble(&assembly).expect("compile checks that its output assembles"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::cc::{compile, compile_image, CcError};
+    use crate::decompile::decompile;
+    use crate::graph::Graph;
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    /// Compiles and runs `source`, giving back what it printed.
+    fn run(source: &str) -> String {
+        let assembly = compile_image(source).unwrap_or_else(|errors| panic!("{:?}", errors));
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", &assembly.image());
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.limits.max_instructions = Some(2_000_000);
+        vm.run();
+        vm.output.unwrap()
+    }
+
+    /// Compiles and runs `source` with marks in R2 to R4, and tells whether
+    /// they are still there when it halts.
+    fn keeps_r2_to_r4(source: &str) -> bool {
+        let assembly = compile_image(source).unwrap_or_else(|errors| panic!("{:?}", errors));
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", &assembly.image());
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.registers[Register::R2 as usize..=Register::R4 as usize]
+            .copy_from_slice(&[0x2222, 0x3333, 0x4444]);
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.limits.max_instructions = Some(2_000_000);
+        vm.run();
+        vm.registers[Register::R2 as usize..=Register::R4 as usize] == [0x2222, 0x3333, 0x4444]
+    }
+
+    /// A function too big for PC-relative branches, around `statements`.
+    fn far(statements: &str) -> String {
+        format!(
+            "int total;
+int one() {{ return 1; }}
+int main() {{
+    int i = 0;
+    while (i < 2) {{
+{}        {}
+        i = i + 1;
+    }}
+    return 0;
+}}",
+            "    total = total + 1;\n".repeat(200),
+            statements
+        )
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        compile(source)
+            .unwrap_err()
+            .iter()
+            .map(CcError::to_string)
+            .collect()
+    }
+
+    /// Prints a number in decimal, for the tests to call.
+    const PRINT: &str = "
+void print(int n) {
+    int digits[6];
+    int count = 0;
+    if (n < 0) {
+        putchar('-');
+        n = -n;
+    }
+    while (1) {
+        digits[count] = n % 10;
+        count = count + 1;
+        n = n / 10;
+        if (n == 0) break;
+    }
+    while (count > 0) {
+        count = count - 1;
+        putchar('0' + digits[count]);
+    }
+    putchar(' ');
+}
+";
+
+    // ========== Programs ==========
+
+    #[test]
+    fn test_hello_world() {
+        assert_eq!(
+            run("int main() { puts(\"Hello, \\\"world\\\"\\n\"); putchar('!'); return 0; }"),
+            "Hello, \"world\"\n!"
+        );
+    }
+
+    #[test]
+    fn test_loops_and_arithmetic() {
+        let output = run(&format!(
+            "{}
+int main() {{
+    int i = 0;
+    int total = 0;
+    while (i < 10) {{
+        i = i + 1;
+        if (i % 2 == 0) continue;
+        total = total + i * i;
+    }}
+    print(total);
+    print(-7 / 2);
+    print(-7 % 2);
+    print(1000 * 30);
+    print(3 << 4);
+    print(-64 >> 3);
+    print(12 & 10 | 1);
+    print(12 ^ 10);
+    print(~0);
+    return 0;
+}}",
+            PRINT
+        ));
+        assert_eq!(output, "165 -3 -1 30000 48 -8 9 6 -1 ");
+    }
+
+    #[test]
+    fn test_recursion() {
+        let output = run(&format!(
+            "{}
+int factorial(int n) {{
+    if (n <= 1) return 1;
+    return n * factorial(n - 1);
+}}
+int fib(int n) {{
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}}
+int main() {{
+    print(factorial(7));
+    print(fib(12));
+    return 0;
+}}",
+            PRINT
+        ));
+        assert_eq!(output, "5040 144 ");
+    }
+
+    #[test]
+    fn test_arrays_and_pointers() {
+        let output = run(&format!(
+            "{}
+int squares[5];
+int primes[4] = {{2, 3, 5}};
+void swap(int *a, int *b) {{
+    int t = *a;
+    *a = *b;
+    *b = t;
+}}
+int sum(int *p, int n) {{
+    int total = 0;
+    while (n > 0) {{
+        total = total + *p;
+        p = p + 1;
+        n = n - 1;
+    }}
+    return total;
+}}
+int main() {{
+    int i = 0;
+    int local[3] = {{7, 8, 9}};
+    int x = 1;
+    int y = 2;
+    while (i < 5) {{
+        squares[i] = i * i;
+        i = i + 1;
+    }}
+    print(sum(squares, 5));
+    print(sum(primes, 4));
+    print(sum(local, 3));
+    swap(&x, &y);
+    print(x * 10 + y);
+    print(&local[2] - local);
+    return 0;
+}}",
+            PRINT
+        ));
+        assert_eq!(output, "30 10 24 21 2 ");
+    }
+
+    #[test]
+    fn test_strings_and_short_circuits() {
+        let output = run("
+int *message = \"ok\";
+int calls;
+int touch() { calls = calls + 1; return 1; }
+int length(int *s) {
+    int n = 0;
+    while (s[n]) n = n + 1;
+    return n;
+}
+int main() {
+    if (0 && touch()) puts(\"wrong\");
+    if (1 || touch()) puts(message);
+    if (!(calls == 0)) puts(\"called\");
+    putchar('0' + length(\"four\"));
+    putchar('0' + (3 > 2) + (2 > 3));
+    return 0;
+}");
+        assert_eq!(output, "ok41");
+    }
+
+    #[test]
+    fn test_big_functions_use_long_jumps() {
+        let body = "    total = total + 1;\n".repeat(200);
+        let source = format!(
+            "int total;
+int main() {{
+    int i = 0;
+    while (i < 2) {{
+{}        i = i + 1;
+    }}
+    putchar('A' + total / 100);
+    return 0;
+}}",
+            body
+        );
+
+        assert!(compile(&source).unwrap().contains("JMP R1"));
+        assert_eq!(run(&source), "E");
+    }
+
+    #[test]
+    fn test_compiled_functions_decompile_with_frames() {
+        let assembly = compile_image(
+            "int sum(int a, int b) { return a + b; }
+int main() { return sum(1, 2); }",
+        )
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", &assembly.image());
+        let graph = Graph::recover(&vm.memory, &vm.images, assembly.origin, &assembly.symbols);
+        let functions = decompile(&graph, &assembly.symbols);
+
+        let sum = functions
+            .iter()
+            .find(|function| function.name == "sum")
+            .unwrap();
+        assert!(sum.frame && sum.returns_value);
+        assert_eq!(sum.parameters, 2);
+    }
+
+    // ========== Registers ==========
+
+    #[test]
+    fn test_xor_keeps_r2_and_r3() {
+        let source = "int main() { int a = 12; int b = 10; putchar('0' + (a ^ b)); return 0; }";
+
+        assert_eq!(run(source), "6");
+        assert!(keeps_r2_to_r4(source));
+    }
+
+    #[test]
+    fn test_far_jumps_do_not_read_as_returns() {
+        let text = compile(&far("")).unwrap();
+
+        assert!(text.contains("JMP R1"));
+        assert!(!text.contains("JMP R7"));
+    }
+
+    #[test]
+    fn test_far_calls_keep_r4() {
+        let source = far("total = total + one();");
+
+        assert!(compile(&source).unwrap().contains("JSRR R0"));
+        assert!(keeps_r2_to_r4(&source));
+    }
+
+    #[test]
+    fn test_far_runtime_calls_keep_r4() {
+        let source = far("total = total * 3;");
+
+        assert!(keeps_r2_to_r4(&source));
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_type_errors_are_all_reported() {
+        assert_eq!(
+            errors(
+                "int f(int a) { return a; }
+void g() { return 1; }
+int main() {
+    int x;
+    int *p;
+    x = p;
+    y = 1;
+    f(1, 2);
+    *x = 3;
+    5 = x;
+    x = g();
+    break;
+}"
+            ),
+            [
+                "line 2: 'g' returns void",
+                "line 6: cannot assign 'int*' to 'int'",
+                "line 7: 'y' is not declared",
+                "line 8: 'f' takes 1 argument, not 2",
+                "line 9: cannot dereference 'int'",
+                "line 10: cannot assign to this expression",
+                "line 11: a void value is not a value",
+                "line 12: 'break' outside a loop",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_program_needs_main() {
+        assert_eq!(
+            errors("int f() { return 0; }"),
+            ["line 1: there is no 'main' function"]
+        );
+        assert_eq!(
+            errors("int main() { return 0 }"),
+            ["line 1: expected ';', found '}'"]
+        );
+    }
+
+    #[test]
+    fn test_output_is_plain_assembly() {
+        let text = compile("int add;\nint main() { add = 3; return add; }").unwrap();
+
+        assert!(text.starts_with("        .ORIG x3000\n"));
+        assert!(text.contains("\n_add\n"));
+        assert!(assemble(&text).is_ok());
+    }
+}
@@ -0,0 +1,639 @@
+use crate::cc::lexer::{tokens, Spanned, Token};
+use crate::cc::CcError;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Void,
+    Pointer(Box<Type>),
+    /// An array of a number of elements. It stands for the address of its
+    /// first element wherever it is used as a value.
+    Array(Box<Type>, u16),
+}
+
+impl Type {
+    /// The type an array's name has as a value.
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(element, _) => Type::Pointer(element.clone()),
+            other => other.clone(),
+        }
+    }
+
+    /// How many words a value of the type takes up.
+    pub fn size(&self) -> u16 {
+        match self {
+            Type::Array(element, count) => element.size().saturating_mul(*count),
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => f.write_str("int"),
+            Type::Void => f.write_str("void"),
+            Type::Pointer(target) => write!(f, "{}*", target),
+            Type::Array(element, count) => write!(f, "{}[{}]", element, count),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    /// `!`.
+    Not,
+    /// `~`.
+    Complement,
+    Deref,
+    AddressOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl BinaryOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Equal
+                | BinaryOp::NotEqual
+                | BinaryOp::Less
+                | BinaryOp::LessOrEqual
+                | BinaryOp::Greater
+                | BinaryOp::GreaterOrEqual
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
+    Number(i32),
+    String(String),
+    Name(String),
+    Call(String, Vec<Expr>),
+    /// `base[index]`.
+    Index(Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Initializer {
+    Expr(Expr),
+    /// `{a, b, c}` for an array.
+    List(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub name: String,
+    pub ty: Type,
+    pub initializer: Option<Initializer>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StmtKind {
+    Expr(Expr),
+    Declare(Declaration),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Block(Vec<Stmt>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub returns: Type,
+    pub parameters: Vec<(String, Type)>,
+    pub body: Vec<Stmt>,
+    pub line: usize,
+}
+
+/// A whole source file: its global variables and functions in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub globals: Vec<Declaration>,
+    pub functions: Vec<Function>,
+}
+
+/// Binary operators from the loosest binding to the tightest.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::LogicalOr)],
+    &[("&&", BinaryOp::LogicalAnd)],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual)],
+    &[
+        ("<", BinaryOp::Less),
+        ("<=", BinaryOp::LessOrEqual),
+        (">", BinaryOp::Greater),
+        (">=", BinaryOp::GreaterOrEqual),
+    ],
+    &[("<<", BinaryOp::ShiftLeft), (">>", BinaryOp::ShiftRight)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+    &[
+        ("*", BinaryOp::Multiply),
+        ("/", BinaryOp::Divide),
+        ("%", BinaryOp::Remainder),
+    ],
+];
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|spanned| &spanned.token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .or(self.tokens.last())
+            .map_or(1, |spanned| spanned.line)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, CcError> {
+        Err(CcError {
+            line: self.line(),
+            message,
+        })
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(Token::Number(value)) => format!("'{}'", value),
+            Some(Token::String(_)) => "a string".to_string(),
+            Some(Token::Name(name)) => format!("'{}'", name),
+            Some(Token::Keyword(word) | Token::Symbol(word)) => format!("'{}'", word),
+            None => "the end of the file".to_string(),
+        }
+    }
+
+    /// Takes `symbol` if it is next.
+    fn eat(&mut self, symbol: &str) -> bool {
+        let next =
+            matches!(self.peek(), Some(Token::Symbol(s) | Token::Keyword(s)) if *s == symbol);
+        if next {
+            self.next += 1;
+        }
+        next
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), CcError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}', found {}", symbol, self.found()))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, CcError> {
+        match self.peek() {
+            Some(Token::Name(name)) if name.starts_with("__") => {
+                self.error(format!("names starting with '__' are reserved: '{}'", name))
+            }
+            Some(Token::Name(name)) => {
+                let name = name.clone();
+                self.next += 1;
+                Ok(name)
+            }
+            _ => self.error(format!("expected a name, found {}", self.found())),
+        }
+    }
+
+    /// `int` or `void` and any `*`s after it.
+    fn base_type(&mut self) -> Result<Type, CcError> {
+        let mut ty = if self.eat("int") {
+            Type::Int
+        } else if self.eat("void") {
+            Type::Void
+        } else {
+            return self.error(format!("expected a type, found {}", self.found()));
+        };
+        while self.eat("*") {
+            ty = Type::Pointer(Box::new(ty));
+        }
+        Ok(ty)
+    }
+
+    fn is_type(&self) -> bool {
+        matches!(self.peek(), Some(Token::Keyword("int" | "void")))
+    }
+
+    /// The name and `[n]` after a base type.
+    fn declarator(&mut self, ty: Type) -> Result<(String, Type), CcError> {
+        let name = self.name()?;
+        if !self.eat("[") {
+            return Ok((name, ty));
+        }
+        let count = match self.peek() {
+            Some(Token::Number(count)) if *count > 0 => *count as u16,
+            _ => return self.error("an array needs a positive constant size".to_string()),
+        };
+        self.next += 1;
+        self.expect("]")?;
+        Ok((name, Type::Array(Box::new(ty), count)))
+    }
+
+    /// A declaration after its type and name, up to and including the `;`.
+    fn declaration_rest(
+        &mut self,
+        name: String,
+        ty: Type,
+        line: usize,
+    ) -> Result<Declaration, CcError> {
+        if ty == Type::Void {
+            return self.error(format!("'{}' cannot be void", name));
+        }
+        let initializer = if !self.eat("=") {
+            None
+        } else if self.eat("{") {
+            let mut values = Vec::new();
+            if !self.eat("}") {
+                loop {
+                    values.push(self.expression()?);
+                    if self.eat("}") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            Some(Initializer::List(values))
+        } else {
+            Some(Initializer::Expr(self.expression()?))
+        };
+        self.expect(";")?;
+        Ok(Declaration {
+            name,
+            ty,
+            initializer,
+            line,
+        })
+    }
+
+    fn program(&mut self) -> Result<Program, CcError> {
+        let mut program = Program::default();
+        while self.peek().is_some() {
+            let line = self.line();
+            let ty = self.base_type()?;
+            let (name, ty) = self.declarator(ty)?;
+            if !self.eat("(") {
+                program.globals.push(self.declaration_rest(name, ty, line)?);
+                continue;
+            }
+            if matches!(ty, Type::Array(..)) {
+                return self.error(format!("'{}' cannot return an array", name));
+            }
+            let mut parameters = Vec::new();
+            // `f(void)` is `f()`
+            if matches!(self.tokens.get(self.next..self.next + 2), Some([void, close])
+                if void.token == Token::Keyword("void") && close.token == Token::Symbol(")"))
+            {
+                self.next += 1;
+            }
+            if !self.eat(")") {
+                loop {
+                    let ty = self.base_type()?;
+                    let (name, ty) = self.declarator(ty)?;
+                    if ty == Type::Void {
+                        return self.error(format!("parameter '{}' cannot be void", name));
+                    }
+                    parameters.push((name, ty.decay()));
+                    if self.eat(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            self.expect("{")?;
+            let body = self.block()?;
+            program.functions.push(Function {
+                name,
+                returns: ty,
+                parameters,
+                body,
+                line,
+            });
+        }
+        Ok(program)
+    }
+
+    /// The statements of a block whose `{` has been read, and its `}`.
+    fn block(&mut self) -> Result<Vec<Stmt>, CcError> {
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.error("expected '}', found the end of the file".to_string());
+            }
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CcError> {
+        let line = self.line();
+        let kind = if self.is_type() {
+            let ty = self.base_type()?;
+            let (name, ty) = self.declarator(ty)?;
+            StmtKind::Declare(self.declaration_rest(name, ty, line)?)
+        } else if self.eat("{") {
+            StmtKind::Block(self.block()?)
+        } else if self.eat("if") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            let then = Box::new(self.statement()?);
+            let otherwise = if self.eat("else") {
+                Some(Box::new(self.statement()?))
+            } else {
+                None
+            };
+            StmtKind::If(condition, then, otherwise)
+        } else if self.eat("while") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            StmtKind::While(condition, Box::new(self.statement()?))
+        } else if self.eat("return") {
+            let value = if self.eat(";") {
+                None
+            } else {
+                let value = self.expression()?;
+                self.expect(";")?;
+                Some(value)
+            };
+            StmtKind::Return(value)
+        } else if self.eat("break") {
+            self.expect(";")?;
+            StmtKind::Break
+        } else if self.eat("continue") {
+            self.expect(";")?;
+            StmtKind::Continue
+        } else if self.eat(";") {
+            StmtKind::Block(Vec::new())
+        } else {
+            let expr = self.expression()?;
+            self.expect(";")?;
+            StmtKind::Expr(expr)
+        };
+        Ok(Stmt { kind, line })
+    }
+
+    fn expression(&mut self) -> Result<Expr, CcError> {
+        let target = self.binary(0)?;
+        if !self.eat("=") {
+            return Ok(target);
+        }
+        let line = target.line;
+        // Assignment groups to the right: `a = b = 0`
+        let value = self.expression()?;
+        Ok(Expr {
+            kind: ExprKind::Assign(Box::new(target), Box::new(value)),
+            line,
+        })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, CcError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol(symbol)) => PRECEDENCE[level]
+                    .iter()
+                    .find(|(candidate, _)| candidate == symbol)
+                    .map(|&(_, op)| op),
+                _ => None,
+            };
+            let Some(op) = op else {
+                return Ok(left);
+            };
+            self.next += 1;
+            let right = self.binary(level + 1)?;
+            let line = left.line;
+            left = Expr {
+                kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+                line,
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CcError> {
+        let line = self.line();
+        let op = match self.peek() {
+            Some(Token::Symbol("-")) => Some(UnaryOp::Negate),
+            Some(Token::Symbol("!")) => Some(UnaryOp::Not),
+            Some(Token::Symbol("~")) => Some(UnaryOp::Complement),
+            Some(Token::Symbol("*")) => Some(UnaryOp::Deref),
+            Some(Token::Symbol("&")) => Some(UnaryOp::AddressOf),
+            _ => None,
+        };
+        let Some(op) = op else {
+            return self.postfix();
+        };
+        self.next += 1;
+        let operand = self.unary()?;
+        let kind = match (op, operand.kind) {
+            // So that -32768 fits
+            (UnaryOp::Negate, ExprKind::Number(value)) => ExprKind::Number(-value),
+            (op, kind) => ExprKind::Unary(
+                op,
+                Box::new(Expr {
+                    kind,
+                    line: operand.line,
+                }),
+            ),
+        };
+        Ok(Expr { kind, line })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, CcError> {
+        let mut expr = self.primary()?;
+        while self.eat("[") {
+            let index = self.expression()?;
+            self.expect("]")?;
+            let line = expr.line;
+            expr = Expr {
+                kind: ExprKind::Index(Box::new(expr), Box::new(index)),
+                line,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, CcError> {
+        let line = self.line();
+        let kind = match self.peek().cloned() {
+            Some(Token::Number(value)) => {
+                self.next += 1;
+                ExprKind::Number(value)
+            }
+            Some(Token::String(text)) => {
+                self.next += 1;
+                ExprKind::String(text)
+            }
+            Some(Token::Name(_)) => {
+                let name = self.name()?;
+                if self.eat("(") {
+                    let mut arguments = Vec::new();
+                    if !self.eat(")") {
+                        loop {
+                            arguments.push(self.expression()?);
+                            if self.eat(")") {
+                                break;
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    ExprKind::Call(name, arguments)
+                } else {
+                    ExprKind::Name(name)
+                }
+            }
+            Some(Token::Symbol("(")) => {
+                self.next += 1;
+                let inner = self.expression()?;
+                self.expect(")")?;
+                return Ok(inner);
+            }
+            _ => return self.error(format!("expected an expression, found {}", self.found())),
+        };
+        Ok(Expr { kind, line })
+    }
+}
+
+/// Parses a whole source file, stopping at the first syntax error.
+pub fn parse(source: &str) -> Result<Program, CcError> {
+    let mut parser = Parser {
+        tokens: tokens(source)?,
+        next: 0,
+    };
+    parser.program()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cc::parser::{parse, BinaryOp, ExprKind, Initializer, StmtKind, Type};
+
+    #[test]
+    fn test_globals_and_functions() {
+        let program = parse(
+            "int count = 3;\nint table[4] = {1, 2};\nint *next;\n\
+             int add(int a, int *b) { return a; }\nvoid main(void) { }",
+        )
+        .unwrap();
+
+        assert_eq!(program.globals.len(), 3);
+        assert_eq!(program.globals[1].ty, Type::Array(Box::new(Type::Int), 4));
+        assert!(matches!(
+            &program.globals[1].initializer,
+            Some(Initializer::List(values)) if values.len() == 2
+        ));
+        assert_eq!(program.globals[2].ty.to_string(), "int*");
+        assert_eq!(program.functions[0].parameters[1].1.to_string(), "int*");
+        assert_eq!(program.functions[1].returns, Type::Void);
+        assert!(program.functions[1].parameters.is_empty());
+    }
+
+    #[test]
+    fn test_precedence_and_assignment() {
+        let program = parse("void f() { a = b = 1 + 2 * 3 < 4 && !c; }").unwrap();
+        let StmtKind::Expr(expr) = &program.functions[0].body[0].kind else {
+            panic!("expected an expression statement");
+        };
+        let ExprKind::Assign(_, value) = &expr.kind else {
+            panic!("expected an assignment");
+        };
+        let ExprKind::Assign(_, value) = &value.kind else {
+            panic!("expected a second assignment");
+        };
+        let ExprKind::Binary(BinaryOp::LogicalAnd, left, _) = &value.kind else {
+            panic!("expected && at the top, got {:?}", value.kind);
+        };
+        let ExprKind::Binary(BinaryOp::Less, sum, _) = &left.kind else {
+            panic!("expected < under &&");
+        };
+        assert!(
+            matches!(&sum.kind, ExprKind::Binary(BinaryOp::Add, _, product)
+            if matches!(product.kind, ExprKind::Binary(BinaryOp::Multiply, _, _)))
+        );
+    }
+
+    #[test]
+    fn test_statements_keep_their_lines() {
+        let program = parse(
+            "int main() {\n  int i = 0;\n  while (i < 3) {\n    if (i) break; else continue;\n  }\n  return -32768;\n}",
+        )
+        .unwrap();
+        let body = &program.functions[0].body;
+        let lines: Vec<usize> = body.iter().map(|stmt| stmt.line).collect();
+
+        assert_eq!(lines, [2, 3, 6]);
+        assert!(matches!(&body[2].kind, StmtKind::Return(Some(value))
+            if value.kind == ExprKind::Number(-32768)));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert_eq!(
+            parse("int main() {\n  return 1\n}")
+                .unwrap_err()
+                .to_string(),
+            "line 3: expected ';', found '}'"
+        );
+        assert_eq!(
+            parse("int __x;").unwrap_err().message,
+            "names starting with '__' are reserved: '__x'"
+        );
+        assert_eq!(parse("void v;").unwrap_err().message, "'v' cannot be void");
+        assert_eq!(
+            parse("int f() {").unwrap_err().message,
+            "expected '}', found the end of the file"
+        );
+    }
+}
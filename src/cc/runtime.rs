@@ -0,0 +1,308 @@
+//! Arithmetic the LC-3 has no instruction for, as assembly routines the
+//! compiler adds to a program when it uses them. Each takes its operands
+//! in R0 and R1, leaves the result in R0, and keeps R2 to R6.
+
+/// A runtime routine, in the order they are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Routine {
+    /// `R0 * R1`, keeping the low 16 bits.
+    Multiply,
+    /// `R0 / R1` into R0 and `R0 % R1` into R1, rounding toward zero.
+    /// Dividing by zero gives a quotient of 0 and leaves the dividend as
+    /// the remainder.
+    DivMod,
+    /// `R0 << R1`.
+    ShiftLeft,
+    /// `R0 >> R1`, copying the sign bit in.
+    ShiftRight,
+}
+
+impl Routine {
+    pub fn label(self) -> &'static str {
+        match self {
+            Routine::Multiply => "__mul",
+            Routine::DivMod => "__divmod",
+            Routine::ShiftLeft => "__shl",
+            Routine::ShiftRight => "__shr",
+        }
+    }
+
+    pub fn source(self) -> &'static str {
+        match self {
+            Routine::Multiply => MULTIPLY,
+            Routine::DivMod => DIVMOD,
+            Routine::ShiftLeft => SHIFT_LEFT,
+            Routine::ShiftRight => SHIFT_RIGHT,
+        }
+    }
+}
+
+/// Adds R0 shifted left once per bit of R1 that is set.
+const MULTIPLY: &str = "\
+__mul
+        ST R2, __mul_r2
+        ST R3, __mul_r3
+        ST R4, __mul_r4
+        ADD R3, R0, #0
+        AND R2, R2, #0
+        AND R0, R0, #0
+        ADD R0, R0, #1
+__mul_loop
+        AND R4, R1, R0
+        BRz __mul_skip
+        ADD R2, R2, R3
+__mul_skip
+        ADD R3, R3, R3
+        ADD R0, R0, R0
+        BRnp __mul_loop
+        ADD R0, R2, #0
+        LD R2, __mul_r2
+        LD R3, __mul_r3
+        LD R4, __mul_r4
+        RET
+__mul_r2 .BLKW 1
+__mul_r3 .BLKW 1
+__mul_r4 .BLKW 1
+";
+
+/// Long division of the magnitudes, one bit at a time from the top, with
+/// the signs put back afterwards.
+const DIVMOD: &str = "\
+__divmod
+        ST R2, __div_r2
+        ST R3, __div_r3
+        ST R4, __div_r4
+        AND R2, R2, #0
+        AND R3, R3, #0
+        ST R0, __div_a
+        ADD R1, R1, #0
+        BRnp __div_start
+        ADD R1, R0, #0
+        AND R0, R0, #0
+        BRnzp __div_return
+__div_start
+        AND R4, R4, #0
+        ADD R0, R0, #0
+        BRzp __div_apos
+        NOT R0, R0
+        ADD R0, R0, #1
+        ADD R4, R4, #1
+__div_apos
+        ADD R1, R1, #0
+        BRzp __div_bpos
+        NOT R1, R1
+        ADD R1, R1, #1
+        ADD R4, R4, #1
+__div_bpos
+        ST R4, __div_sign
+        AND R4, R4, #0
+        ADD R4, R4, #15
+        ADD R4, R4, #1
+        ST R4, __div_count
+__div_loop
+        ADD R3, R3, R3
+        ADD R0, R0, #0
+        BRzp __div_shift
+        ADD R3, R3, #1
+__div_shift
+        ADD R0, R0, R0
+        ADD R2, R2, R2
+        ADD R3, R3, #0
+        BRn __div_rtop
+        ADD R1, R1, #0
+        BRn __div_next
+        BRnzp __div_compare
+__div_rtop
+        ADD R1, R1, #0
+        BRzp __div_subtract
+__div_compare
+        NOT R4, R1
+        ADD R4, R4, #1
+        ADD R4, R3, R4
+        BRn __div_next
+__div_subtract
+        NOT R4, R1
+        ADD R4, R4, #1
+        ADD R3, R3, R4
+        ADD R2, R2, #1
+__div_next
+        LD R4, __div_count
+        ADD R4, R4, #-1
+        ST R4, __div_count
+        BRp __div_loop
+        LD R4, __div_sign
+        AND R4, R4, #1
+        BRz __div_qpos
+        NOT R2, R2
+        ADD R2, R2, #1
+__div_qpos
+        LD R4, __div_a
+        BRzp __div_rpos
+        NOT R3, R3
+        ADD R3, R3, #1
+__div_rpos
+        ADD R0, R2, #0
+        ADD R1, R3, #0
+__div_return
+        LD R2, __div_r2
+        LD R3, __div_r3
+        LD R4, __div_r4
+        RET
+__div_r2 .BLKW 1
+__div_r3 .BLKW 1
+__div_r4 .BLKW 1
+__div_a .BLKW 1
+__div_sign .BLKW 1
+__div_count .BLKW 1
+";
+
+const SHIFT_LEFT: &str = "\
+__shl
+        ADD R1, R1, #0
+        BRnz __shl_done
+__shl_loop
+        ADD R0, R0, R0
+        ADD R1, R1, #-1
+        BRp __shl_loop
+__shl_done
+        RET
+";
+
+/// Copies bit `n + i` of R0 to bit `i` of the result, then fills the top
+/// with the sign.
+const SHIFT_RIGHT: &str = "\
+__shr
+        ST R2, __shr_r2
+        ST R3, __shr_r3
+        ST R4, __shr_r4
+        ADD R1, R1, #0
+        BRnz __shr_return
+        AND R2, R2, #0
+        ADD R2, R2, #1
+__shr_mask
+        ADD R2, R2, R2
+        ADD R1, R1, #-1
+        BRp __shr_mask
+        AND R1, R1, #0
+        ADD R1, R1, #1
+        AND R3, R3, #0
+        ADD R2, R2, #0
+__shr_loop
+        BRz __shr_sign
+        AND R4, R0, R2
+        BRz __shr_next
+        ADD R3, R3, R1
+__shr_next
+        ADD R1, R1, R1
+        ADD R2, R2, R2
+        BRnzp __shr_loop
+__shr_sign
+        ADD R0, R0, #0
+        BRzp __shr_done
+__shr_fill
+        ADD R1, R1, #0
+        BRz __shr_done
+        ADD R3, R3, R1
+        ADD R1, R1, R1
+        BRnzp __shr_fill
+__shr_done
+        ADD R0, R3, #0
+__shr_return
+        LD R2, __shr_r2
+        LD R3, __shr_r3
+        LD R4, __shr_r4
+        RET
+__shr_r2 .BLKW 1
+__shr_r3 .BLKW 1
+__shr_r4 .BLKW 1
+";
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::cc::runtime::Routine;
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    /// Calls `routine` with `a` and `b` and gives back R0 and R1.
+    fn call(routine: Routine, a: i16, b: i16) -> (i16, i16) {
+        let source = format!(
+            ".ORIG x3000\n        JSR {}\n        HALT\n{}        .END\n",
+            routine.label(),
+            routine.source()
+        );
+        let assembly = assemble(&source).unwrap();
+        let mut vm = Vm::new();
+        vm.load_image("runtime.obj", &assembly.image());
+        vm.registers[Register::R0 as usize] = a as u16;
+        vm.registers[Register::R1 as usize] = b as u16;
+        vm.registers[Register::R2 as usize] = 0x1234;
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.run();
+        assert_eq!(vm.registers[Register::R2 as usize], 0x1234);
+        (
+            vm.registers[Register::R0 as usize] as i16,
+            vm.registers[Register::R1 as usize] as i16,
+        )
+    }
+
+    #[test]
+    fn test_multiply() {
+        for (a, b) in [(6, 7), (-3, 5), (-4, -4), (0, 99), (300, 300)] {
+            assert_eq!(
+                call(Routine::Multiply, a, b).0,
+                a.wrapping_mul(b),
+                "{} * {}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn test_divide_rounds_toward_zero() {
+        let cases = [
+            (17, 5),
+            (-17, 5),
+            (17, -5),
+            (-17, -5),
+            (i16::MIN, 3),
+            (30000, 20000),
+            (5, 0),
+        ];
+        for (a, b) in cases {
+            let expected = if b == 0 {
+                (0, a)
+            } else {
+                (a.wrapping_div(b), a.wrapping_rem(b))
+            };
+            assert_eq!(call(Routine::DivMod, a, b), expected, "{} / {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_shifts() {
+        for (a, n) in [(1i16, 4), (-3, 2), (0x4000, 1), (5, 0), (7, 16)] {
+            let expected = if n >= 16 { 0 } else { a.wrapping_shl(n as u32) };
+            assert_eq!(call(Routine::ShiftLeft, a, n).0, expected, "{} << {}", a, n);
+        }
+        for (a, n) in [
+            (100, 2),
+            (-100, 2),
+            (i16::MIN, 15),
+            (-1, 20),
+            (12345, 16),
+            (9, 0),
+        ] {
+            assert_eq!(
+                call(Routine::ShiftRight, a, n).0,
+                a >> n.min(15),
+                "{} >> {}",
+                a,
+                n
+            );
+        }
+    }
+}
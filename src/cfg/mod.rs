@@ -0,0 +1,288 @@
+use crate::instructions::trap::TRAP_HALT;
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::{LoadedImage, MEMORY_MAX};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A straight-line run of reachable instructions covering `start..end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub end: u32,
+    /// Statically known successors, in order: the taken target first, then
+    /// the fall-through.
+    pub successors: Vec<u16>,
+}
+
+/// The code reachable from an entry point, split into basic blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlow {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    /// Every instruction in the blocks.
+    pub reachable: BTreeSet<u16>,
+    /// `JMP` and `JSRR` instructions, whose targets are only known at run
+    /// time and have to go through a dispatcher.
+    pub indirect: Vec<u16>,
+}
+
+impl ControlFlow {
+    pub fn instructions(&self) -> u32 {
+        self.blocks
+            .values()
+            .map(|block| block.end - block.start as u32)
+            .sum()
+    }
+}
+
+/// Where control can go after one instruction, without following calls.
+pub struct Flow {
+    pub decoded: Decoded,
+    /// The next instruction and any branch target, the target first. A
+    /// call's fall-through is where it returns to.
+    pub successors: Vec<u16>,
+    /// The target of a `JSR`.
+    pub call: Option<u16>,
+    /// Whether the instruction ends a basic block.
+    pub ends_block: bool,
+}
+
+impl Flow {
+    pub fn of(address: u16, instruction: u16) -> Flow {
+        let decoded = Decoded::decode(instruction);
+        let target = address.wrapping_add(1).wrapping_add(decoded.operand);
+        let (jump, falls_through, call) = match decoded.operation {
+            Operation::Br if decoded.dr == 0 => (None, true, None),
+            Operation::Br if decoded.dr == 0b111 => (Some(target), false, None),
+            Operation::Br => (Some(target), true, None),
+            Operation::Jsr => (None, true, Some(target)),
+            Operation::Jsrr => (None, true, None),
+            Operation::Jmp | Operation::Stop => (None, false, None),
+            Operation::Trap => (None, instruction & 0xFF != TRAP_HALT, None),
+            _ => {
+                return Flow {
+                    decoded,
+                    successors: address.checked_add(1).into_iter().collect(),
+                    call: None,
+                    ends_block: false,
+                };
+            }
+        };
+        let mut successors: Vec<u16> = jump.into_iter().collect();
+        if falls_through {
+            successors.extend(address.checked_add(1));
+        }
+        Flow {
+            decoded,
+            successors,
+            call,
+            ends_block: true,
+        }
+    }
+
+    /// Every statically known target, the call first.
+    pub fn targets(&self) -> impl Iterator<Item = u16> + '_ {
+        self.call.into_iter().chain(self.successors.iter().copied())
+    }
+}
+
+/// Whether `decoded` is `RET`, that is `JMP R7`.
+pub fn is_return(decoded: &Decoded) -> bool {
+    decoded.operation == Operation::Jmp && decoded.sr == 7
+}
+
+fn loaded(images: &[LoadedImage], address: u16) -> bool {
+    images
+        .iter()
+        .any(|image| address.wrapping_sub(image.origin) < image.length)
+}
+
+/// Follows every statically known path from `entry` through the loaded
+/// images. Code outside the images, and code only reachable through an
+/// indirect jump that does not land on a block found here, is left to the
+/// interpreter.
+pub fn recover(memory: &[u16; MEMORY_MAX], images: &[LoadedImage], entry: u16) -> ControlFlow {
+    recover_from(memory, &[entry], |address| loaded(images, address))
+}
+
+/// Follows every statically known path from `roots`, the first of them the
+/// entry, through the addresses `is_code` accepts.
+pub fn recover_from(
+    memory: &[u16; MEMORY_MAX],
+    roots: &[u16],
+    is_code: impl Fn(u16) -> bool,
+) -> ControlFlow {
+    let mut reachable = BTreeSet::new();
+    let mut leaders: BTreeSet<u16> = roots.iter().copied().collect();
+    let mut indirect = Vec::new();
+    let mut pending = roots.to_vec();
+
+    while let Some(address) = pending.pop() {
+        if !is_code(address) || !reachable.insert(address) {
+            continue;
+        }
+
+        let flow = Flow::of(address, memory[address as usize]);
+        if matches!(flow.decoded.operation, Operation::Jmp | Operation::Jsrr) {
+            indirect.push(address);
+        }
+        for target in flow.targets() {
+            if flow.ends_block {
+                leaders.insert(target);
+            }
+            pending.push(target);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders
+        .iter()
+        .filter(|&&leader| reachable.contains(&leader))
+    {
+        let mut address = start;
+        let block = loop {
+            let flow = Flow::of(address, memory[address as usize]);
+            let next = address as u32 + 1;
+            let continues = next < MEMORY_MAX as u32
+                && reachable.contains(&(next as u16))
+                && !leaders.contains(&(next as u16));
+            if flow.ends_block || !continues {
+                break BasicBlock {
+                    start,
+                    end: next,
+                    successors: flow.targets().collect(),
+                };
+            }
+            address += 1;
+        };
+        blocks.insert(start, block);
+    }
+
+    indirect.sort_unstable();
+    ControlFlow {
+        entry: roots[0],
+        blocks,
+        reachable,
+        indirect,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cfg::{recover, recover_from};
+    use crate::{LoadedImage, MEMORY_MAX};
+
+    fn image(words: &[u16]) -> (Box<[u16; MEMORY_MAX]>, Vec<LoadedImage>) {
+        let mut memory = Box::new([0; MEMORY_MAX]);
+        memory[0x3000..0x3000 + words.len()].copy_from_slice(words);
+        let images = vec![LoadedImage {
+            file_name: "test.obj".to_string(),
+            origin: 0x3000,
+            length: words.len() as u16,
+        }];
+        (memory, images)
+    }
+
+    // ========== Blocks ==========
+
+    #[test]
+    fn test_loop_splits_at_the_branch_target() {
+        let (memory, images) = image(&[
+            0b0101_001_001_1_00000, // AND R1, R1, #0
+            0b0001_001_001_1_00001, // ADD R1, R1, #1
+            0b0000_001_111111110,   // BRp #-2
+            0xF025,                 // HALT
+        ]);
+
+        let flow = recover(&memory, &images, 0x3000);
+
+        let starts: Vec<u16> = flow.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x3000, 0x3001, 0x3003]);
+        assert_eq!(flow.blocks[&0x3000].end, 0x3001);
+        assert_eq!(flow.blocks[&0x3000].successors, [0x3001]);
+        assert_eq!(flow.blocks[&0x3001].successors, [0x3001, 0x3003]);
+        assert!(flow.blocks[&0x3003].successors.is_empty());
+        assert_eq!(flow.instructions(), 4);
+    }
+
+    #[test]
+    fn test_data_after_halt_is_not_translated() {
+        let (memory, images) = image(&[
+            0b0010_000_000000001, // LD R0, #1
+            0xF025,               // HALT
+            0x1234,               // .FILL x1234
+        ]);
+
+        let flow = recover(&memory, &images, 0x3000);
+
+        assert_eq!(flow.blocks.len(), 1);
+        assert_eq!(flow.instructions(), 2);
+    }
+
+    #[test]
+    fn test_unconditional_branch_does_not_fall_through() {
+        let (memory, images) = image(&[
+            0b0000_111_000000001, // BRnzp #1
+            0x1234,               // .FILL x1234
+            0xF025,               // HALT
+        ]);
+
+        let flow = recover(&memory, &images, 0x3000);
+
+        assert!(!flow.blocks.contains_key(&0x3001));
+        assert_eq!(flow.blocks[&0x3000].successors, [0x3002]);
+    }
+
+    // ========== Calls ==========
+
+    #[test]
+    fn test_subroutine_and_return_point_become_blocks() {
+        let (memory, images) = image(&[
+            0b0100_1_00000000010,   // JSR #2
+            0xF025,                 // HALT
+            0x0000,                 // .FILL x0000
+            0b0001_000_000_1_00001, // ADD R0, R0, #1
+            0b1100_000_111_000000,  // RET
+        ]);
+
+        let flow = recover(&memory, &images, 0x3000);
+
+        assert_eq!(flow.blocks[&0x3000].successors, [0x3003, 0x3001]);
+        assert!(flow.blocks.contains_key(&0x3001));
+        assert_eq!(flow.blocks[&0x3003].end, 0x3005);
+        assert_eq!(flow.indirect, [0x3004]);
+    }
+
+    #[test]
+    fn test_targets_outside_the_image_are_not_followed() {
+        let (memory, images) = image(&[
+            0b0000_111_100000000, // BRnzp #-256
+        ]);
+
+        let flow = recover(&memory, &images, 0x3000);
+
+        assert_eq!(flow.blocks.len(), 1);
+        assert_eq!(flow.blocks[&0x3000].successors, [0x2F01]);
+    }
+
+    // ========== Roots ==========
+
+    #[test]
+    fn test_every_root_is_followed_through_the_code_only() {
+        let (memory, _) = image(&[
+            0xF025,                 // HALT
+            0b0001_000_000_1_00001, // ADD R0, R0, #1
+            0x1234,                 // .FILL x1234
+        ]);
+
+        let flow = recover_from(&memory, &[0x3000, 0x3001], |address| {
+            (0x3000..0x3002).contains(&address)
+        });
+
+        assert_eq!(flow.entry, 0x3000);
+        assert_eq!(
+            flow.reachable.iter().copied().collect::<Vec<_>>(),
+            [0x3000, 0x3001]
+        );
+        assert_eq!(flow.blocks[&0x3001].successors, [0x3002]);
+    }
+}
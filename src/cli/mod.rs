@@ -0,0 +1,783 @@
+use crate::asm::lexer::parse_number;
+use crate::cache::{CacheConfig, Caches};
+use crate::conventions::{parse_registers, ConventionChecker, DEFAULT_CALLEE_SAVED};
+use crate::coverage::{Coverage, CoverageOptions};
+use crate::cycles::CycleModel;
+use crate::image::{read_file, Format};
+use crate::limits::Limits;
+use crate::loader::{check, reserved_regions, LoadPolicy};
+use crate::machine::{Machine, MachineConfig, MCR};
+use crate::memcheck::MemCheck;
+use crate::pipeline::{Pipeline, PipelineConfig};
+use crate::registers::register::Register;
+use crate::registers::ConditionFlag;
+use crate::self_modifying::{CodePolicy, CodeWatch};
+use crate::symbols::source_map::SourceMap;
+use crate::{Vm, PC_START};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    #[default]
+    Run,
+    Asm,
+    Cc,
+    Disasm,
+    Debug,
+    Trace,
+    Dump,
+    Convert,
+    Link,
+    Lint,
+    Graph,
+    Decompile,
+}
+
+impl Command {
+    fn named(name: &str) -> Option<Command> {
+        match name {
+            "run" => Some(Command::Run),
+            "asm" => Some(Command::Asm),
+            "cc" => Some(Command::Cc),
+            "disasm" => Some(Command::Disasm),
+            "debug" => Some(Command::Debug),
+            "trace" => Some(Command::Trace),
+            "dump" => Some(Command::Dump),
+            "convert" => Some(Command::Convert),
+            "link" => Some(Command::Link),
+            "lint" => Some(Command::Lint),
+            "graph" => Some(Command::Graph),
+            "decompile" => Some(Command::Decompile),
+            _ => None,
+        }
+    }
+}
+
+/// Everything the command line asked for.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub command: Command,
+    pub files: Vec<String>,
+    /// The format of the input files, detected per file when not given.
+    pub format: Option<Format>,
+    /// Where raw images are loaded.
+    pub load_at: Option<u16>,
+    /// How strict loading is about overlaps and reserved memory.
+    pub load_policy: LoadPolicy,
+    /// Print where every image went before starting.
+    pub memory_map: bool,
+    /// Where execution starts. Defaults to the origin of the last image.
+    pub entry: Option<u16>,
+    /// Register numbers and their starting values.
+    pub registers: Vec<(usize, u16)>,
+    /// Written to every memory word before the images are loaded.
+    pub fill: Option<u16>,
+    pub limits: Limits,
+    /// A file whose bytes are fed to the program as keyboard input.
+    pub input: Option<String>,
+    /// A file that receives the program's output instead of stdout.
+    pub output: Option<String>,
+    pub quiet: bool,
+    /// `asm`: write relocatable `.rel` modules for `link`.
+    pub relocatable: bool,
+    /// `asm`: where `.INCLUDE` looks after the source file's directory.
+    pub include_paths: Vec<PathBuf>,
+    /// `link`: where sections without `.ORIG` start.
+    pub base: Option<u16>,
+    /// `asm`, `convert`, `link`: where to write the image.
+    pub object: Option<String>,
+    /// `asm`, `convert`: the format to write. Defaults to what the name of
+    /// the output file suggests.
+    pub output_format: Option<Format>,
+    /// `graph`: write the call graph rather than the control-flow graph.
+    pub call_graph: bool,
+    /// `graph`: write JSON rather than DOT.
+    pub json: bool,
+    /// `graph`: run the program first and add its execution counts.
+    pub profile: bool,
+    /// `dump`: the addresses to show, end exclusive.
+    pub range: Option<(u16, u32)>,
+    pub coverage: CoverageOptions,
+    pub cycles: bool,
+    pub memory_latency: u32,
+    pub microcode: bool,
+    pub trace_states: bool,
+    pub pipeline: Option<PipelineConfig>,
+    pub instruction_cache: Option<CacheConfig>,
+    pub data_cache: Option<CacheConfig>,
+    pub translate: Option<String>,
+    /// Check calls against the calling convention, with these registers
+    /// (a bit per register) callee-saved.
+    pub check_calls: Option<u8>,
+    /// Report reads of registers and memory nothing has set, stopping at
+    /// the first one when `true`.
+    pub memcheck: Option<bool>,
+    /// Watch for self-modifying code, and what to do about it.
+    pub self_modifying: Option<CodePolicy>,
+    pub machine: Machine,
+}
+
+#[derive(Debug)]
+pub enum Action {
+    Help,
+    Version,
+    Execute(Box<Options>),
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [command] [options] file...
+
+Commands:
+  run       run the images (the default)
+  asm       assemble .asm files into .obj, .sym, .map and .lst files
+  cc        compile C-like .c files into .obj and .sym files, or into
+            assembly with -o file.asm
+  disasm    disassemble the loaded images
+  debug     step through the program interactively
+  trace     run, printing every instruction and the registers to stderr
+  dump      print the loaded memory in hex
+  convert   write an image in another format
+  link      combine .rel modules into one image with a .sym file
+  lint      report likely mistakes in .asm files or images without running
+            them
+  graph     write the control-flow graph of the images as Graphviz DOT
+  decompile print each subroutine of the images as pseudo-C
+
+Machine:
+  --config machine.toml   describe the machine in a file; later options
+                          override it
+  --entry addr            start here instead of the origin of the last image
+  --reg Rn=value          set a register before starting (repeatable)
+  --fill value            fill all memory with a word before loading
+  --format fmt            read input images as obj, hex, bin, ihex or raw
+                          instead of detecting the format
+  --load-at addr          where to load raw little-endian images
+  --reserved policy       allow, warn (the default) or deny images in the
+                          vector tables and device registers
+  --allow-overlap         let a later image overwrite an earlier one
+  --memory-map            print where every image was loaded
+  --input file            read keyboard input from a file
+  --output file           write program output to a file
+  --quiet                 leave out the --- HALT --- banner
+
+Limits:
+  --max-instructions n    stop after n instructions
+  --timeout seconds       stop after this much wall time
+  --max-output bytes      stop once the program writes more than this
+  --detect-loops          stop in loops that can never exit
+
+Analysis:
+  --coverage, --symbols file.sym, --source-map file.map, --lcov out.info,
+  --cobertura out.xml, --cycles, --memory-latency n, --microcode,
+  --trace-states, --pipeline, --no-forwarding,
+  --predict not-taken|taken|btfn|two-bit, --cache spec, --icache spec,
+  --dcache spec, --translate out.rs, --check-calls, --callee-saved R2-R5,
+  --memcheck, --memcheck-halt, --self-modifying allow|warn|fault
+
+Other:
+  -o file                 asm, cc, convert, link: the image to write; graph,
+                          decompile: the file to write instead of stdout
+  --to fmt                asm, cc, convert, link: its format, if not clear from
+                          the name
+  -c, --relocatable       asm: write .rel modules to link instead of images
+  -I dir                  asm, lint: also look for .INCLUDE files in dir
+  --base addr             link: where modules without .ORIG go (x3000)
+  --range start:end       dump: the addresses to show
+  --calls                 graph: the call graph instead of the basic blocks
+  --json                  graph: write both graphs as JSON instead of DOT
+  --profile               graph: run the program first and show how often
+                          each instruction and edge ran
+  -h, --help              show this help
+  -V, --version           show the version
+"
+    )
+}
+
+/// Parses a 16-bit LC-3 number such as `x3000`, `#-1` or `42`.
+fn word(text: &str, option: &str) -> Result<u16, String> {
+    match parse_number(text) {
+        Some(value) if (-0x8000..=0xFFFF).contains(&value) => Ok(value as u16),
+        _ => Err(format!("{} expects a 16-bit value, got '{}'", option, text)),
+    }
+}
+
+fn register_assignment(text: &str) -> Result<(usize, u16), String> {
+    let (name, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("--reg expects Rn=value, got '{}'", text))?;
+    let register = name
+        .strip_prefix(['R', 'r'])
+        .and_then(|number| number.parse::<usize>().ok())
+        .filter(|&number| number < 8)
+        .ok_or_else(|| format!("--reg expects a register R0..R7, got '{}'", name))?;
+    Ok((register, word(value, "--reg")?))
+}
+
+fn number(text: String, option: &str) -> Result<u64, String> {
+    text.parse()
+        .map_err(|_| format!("{} expects a number, got '{}'", option, text))
+}
+
+fn range(text: &str) -> Result<(u16, u32), String> {
+    let error = || format!("--range expects start:end, got '{}'", text);
+    let (start, end) = text.split_once(':').ok_or_else(error)?;
+    let start = parse_number(start).filter(|value| (0..=0xFFFF).contains(value));
+    let end = parse_number(end).filter(|value| (0..=0x10000).contains(value));
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => Ok((start as u16, end as u32)),
+        _ => Err(error()),
+    }
+}
+
+/// Parses the arguments after the program name. Without a command name,
+/// `run` is assumed so `RustVm game.obj` keeps working.
+pub fn parse(arguments: &[String]) -> Result<Action, String> {
+    // The configuration is the base the other options are layered on,
+    // wherever it appears
+    let mut options = match arguments.iter().position(|argument| argument == "--config") {
+        Some(index) => {
+            let path = arguments.get(index + 1).ok_or("--config expects a value")?;
+            MachineConfig::load(path)?.options()
+        }
+        None => Options::default(),
+    };
+    let mut arguments = arguments.iter().peekable();
+    if let Some(command) = arguments.peek().and_then(|name| Command::named(name)) {
+        options.command = command;
+        arguments.next();
+    }
+
+    while let Some(argument) = arguments.next() {
+        let mut value = |option: &str| -> Result<String, String> {
+            arguments
+                .next()
+                .cloned()
+                .ok_or_else(|| format!("{} expects a value", option))
+        };
+
+        match argument.as_str() {
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "--config" => {
+                value(argument)?;
+            }
+            "--entry" => options.entry = Some(word(&value(argument)?, argument)?),
+            "--reg" => options
+                .registers
+                .push(register_assignment(&value(argument)?)?),
+            "--fill" => options.fill = Some(word(&value(argument)?, argument)?),
+            "--input" => options.input = Some(value(argument)?),
+            "--output" => options.output = Some(value(argument)?),
+            "--quiet" => options.quiet = true,
+            "-o" => options.object = Some(value(argument)?),
+            "--format" => options.format = Some(value(argument)?.parse()?),
+            "--to" => options.output_format = Some(value(argument)?.parse()?),
+            "--load-at" => options.load_at = Some(word(&value(argument)?, argument)?),
+            "--reserved" => {
+                options.load_policy.reserved = value(argument)?
+                    .parse()
+                    .map_err(|e| format!("{}: {}", argument, e))?
+            }
+            "--allow-overlap" => options.load_policy.allow_overlap = true,
+            "--memory-map" => options.memory_map = true,
+            "--range" => options.range = Some(range(&value(argument)?)?),
+            "--calls" => options.call_graph = true,
+            "--json" => options.json = true,
+            "--profile" => options.profile = true,
+            "-c" | "--relocatable" => options.relocatable = true,
+            "-I" => options.include_paths.push(PathBuf::from(value(argument)?)),
+            "--base" => options.base = Some(word(&value(argument)?, argument)?),
+            "--max-instructions" => {
+                options.limits.max_instructions = Some(number(value(argument)?, argument)?)
+            }
+            "--max-output" => options.limits.max_output = Some(number(value(argument)?, argument)?),
+            "--timeout" => {
+                let text = value(argument)?;
+                options.limits.max_time = match text.parse::<f64>().map(Duration::try_from_secs_f64)
+                {
+                    Ok(Ok(timeout)) => Some(timeout),
+                    _ => {
+                        return Err(format!(
+                            "--timeout expects a number of seconds, got '{}'",
+                            text
+                        ));
+                    }
+                };
+            }
+            "--detect-loops" => options.limits.detect_stuck = true,
+            "--coverage" => options.coverage.summary = true,
+            "--symbols" => options.coverage.symbols = Some(value(argument)?),
+            "--source-map" => options.coverage.source_map = Some(value(argument)?),
+            "--lcov" => options.coverage.lcov = Some(value(argument)?),
+            "--cobertura" => options.coverage.cobertura = Some(value(argument)?),
+            "--cycles" => options.cycles = true,
+            "--check-calls" => {
+                options.check_calls.get_or_insert(DEFAULT_CALLEE_SAVED);
+            }
+            "--callee-saved" => {
+                let text = value(argument)?;
+                let registers =
+                    parse_registers(&text).map_err(|e| format!("--callee-saved: {}", e))?;
+                options.check_calls = Some(registers);
+            }
+            "--memcheck" => {
+                options.memcheck.get_or_insert(false);
+            }
+            "--memcheck-halt" => options.memcheck = Some(true),
+            "--self-modifying" => {
+                options.self_modifying = Some(
+                    value(argument)?
+                        .parse()
+                        .map_err(|e| format!("{}: {}", argument, e))?,
+                )
+            }
+            "--memory-latency" => {
+                let text = value(argument)?;
+                options.memory_latency = text.parse().map_err(|_| {
+                    format!(
+                        "--memory-latency expects a number of cycles, got '{}'",
+                        text
+                    )
+                })?;
+                options.cycles = true;
+            }
+            "--microcode" => options.microcode = true,
+            "--trace-states" => {
+                options.microcode = true;
+                options.trace_states = true;
+            }
+            "--pipeline" => {
+                options.pipeline.get_or_insert_with(PipelineConfig::default);
+            }
+            "--no-forwarding" => {
+                options
+                    .pipeline
+                    .get_or_insert_with(PipelineConfig::default)
+                    .forwarding = false;
+            }
+            "--predict" => {
+                let prediction = value(argument)?.parse()?;
+                options
+                    .pipeline
+                    .get_or_insert_with(PipelineConfig::default)
+                    .prediction = prediction;
+            }
+            "--cache" | "--icache" | "--dcache" => {
+                let config: CacheConfig = value(argument)?
+                    .parse()
+                    .map_err(|e| format!("{}: {}", argument, e))?;
+                if argument != "--dcache" {
+                    options.instruction_cache = Some(config);
+                }
+                if argument != "--icache" {
+                    options.data_cache = Some(config);
+                }
+            }
+            "--translate" => options.translate = Some(value(argument)?),
+            option if option.starts_with('-') && option.len() > 1 => {
+                return Err(format!("unknown option '{}'", option));
+            }
+            _ => options.files.push(argument.clone()),
+        }
+    }
+
+    if options.files.is_empty() {
+        return Err("no input files".to_string());
+    }
+    Ok(Action::Execute(Box::new(options)))
+}
+
+/// The map used to name source lines in traces, the debugger and stop
+/// messages: `--source-map` if given, or else the `.map` files `asm` wrote
+/// next to the images.
+pub fn source_map(options: &Options) -> Result<SourceMap, String> {
+    if let Some(file_name) = &options.coverage.source_map {
+        return SourceMap::load(file_name);
+    }
+    let mut map = SourceMap::new();
+    for file in &options.files {
+        let path = Path::new(file).with_extension("map");
+        if path != Path::new(file) && path.is_file() {
+            map.merge(SourceMap::load(&path.to_string_lossy())?);
+        }
+    }
+    Ok(map)
+}
+
+/// Fills and loads memory, sets the registers and sets up the machine and
+/// the analyses the options ask for. The images are checked against each
+/// other before any is loaded; warnings go to stderr.
+pub fn prepare(vm: &mut Vm, options: &Options) -> Result<(), String> {
+    if let Some(fill) = options.fill {
+        vm.memory.fill(fill);
+    }
+    // Loading an image gives its words values, so this comes first
+    if let Some(halt) = options.memcheck {
+        vm.memcheck = Some(MemCheck::new(halt));
+    }
+    let mut images = Vec::new();
+    for file in &options.files {
+        let image = read_file(file, options.format, options.load_at)
+            .map_err(|e| format!("could not load {}: {}", file, e))?;
+        images.push((file.clone(), image));
+    }
+    let report = check(
+        &images,
+        &reserved_regions(&options.machine),
+        options.load_policy,
+    );
+    for warning in &report.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if !report.errors.is_empty() {
+        return Err(report.errors.join("\n"));
+    }
+    for (file, image) in &images {
+        vm.load_image(file, image);
+    }
+
+    let entry = options
+        .entry
+        .or_else(|| vm.images.last().map(|image| image.origin))
+        .unwrap_or(PC_START as u16);
+    vm.registers[Register::Cond as usize] = ConditionFlag::Zro as u16;
+    vm.registers[Register::Pc as usize] = entry;
+    for &(register, value) in &options.registers {
+        vm.registers[register] = value;
+    }
+    if let Some(memcheck) = vm.memcheck.as_mut() {
+        // Images don't record `.BLKW` space, so without the `.map` the
+        // assembler wrote it counts as set like the rest of the image
+        for address in source_map(options)?.reserved() {
+            memcheck.undefine(address);
+        }
+        for &(register, _) in &options.registers {
+            memcheck.define_register(register);
+        }
+    }
+
+    if let Some(file) = &options.input {
+        let bytes = fs::read(file).map_err(|e| format!("could not read {}: {}", file, e))?;
+        vm.input = Some(bytes.into_iter().map(u16::from).collect::<VecDeque<_>>());
+    }
+    if options.output.is_some() {
+        vm.output = Some(String::new());
+    }
+    vm.quiet_halt = options.quiet;
+    vm.limits = options.limits.clone();
+    vm.machine = options.machine;
+    if options.machine.devices.machine_control {
+        vm.memory[MCR as usize] |= 0x8000;
+    }
+
+    if options.coverage.enabled() {
+        vm.coverage = Some(Coverage::new());
+    }
+    if options.cycles {
+        vm.cycles = Some(CycleModel::new(options.memory_latency));
+    }
+    if let Some(callee_saved) = options.check_calls {
+        vm.conventions = Some(ConventionChecker::new(callee_saved));
+    }
+    if let Some(policy) = options.self_modifying {
+        vm.code_watch = Some(CodeWatch::new(policy));
+    }
+    if let Some(config) = options.pipeline {
+        vm.pipeline = Some(Pipeline::new(config));
+    }
+    if options.instruction_cache.is_some() || options.data_cache.is_some() {
+        vm.caches = Some(Caches::new(
+            options.instruction_cache.unwrap_or_default(),
+            options.data_cache.unwrap_or_default(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{parse, prepare, source_map, Action, Command, Options};
+    use crate::loader::ReservedPolicy;
+    use crate::registers::register::Register;
+    use crate::self_modifying::CodePolicy;
+    use crate::Vm;
+    use std::path::PathBuf;
+
+    fn arguments(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn options(text: &str) -> Options {
+        match parse(&arguments(text)) {
+            Ok(Action::Execute(options)) => *options,
+            other => panic!("expected options, got {:?}", other),
+        }
+    }
+
+    /// Writes an object file with `words` at `origin` and returns its path.
+    fn object(name: &str, origin: u16, words: &[u16]) -> String {
+        let path = std::env::temp_dir().join(name);
+        let bytes: Vec<u8> = std::iter::once(origin)
+            .chain(words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // ========== Parsing ==========
+
+    #[test]
+    fn test_bare_files_mean_run() {
+        let options = options("game.obj");
+
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.files, ["game.obj"]);
+    }
+
+    #[test]
+    fn test_subcommand_and_machine_options() {
+        let options = options(
+            "trace --entry x4000 --reg R1=x10 --reg r7=#-1 --fill xDEAD --quiet \
+             --max-instructions 500 a.obj b.obj",
+        );
+
+        assert_eq!(options.command, Command::Trace);
+        assert_eq!(options.entry, Some(0x4000));
+        assert_eq!(options.registers, [(1, 0x10), (7, 0xFFFF)]);
+        assert_eq!(options.fill, Some(0xDEAD));
+        assert!(options.quiet);
+        assert_eq!(options.limits.max_instructions, Some(500));
+        assert_eq!(options.files, ["a.obj", "b.obj"]);
+    }
+
+    #[test]
+    fn test_call_checking_options() {
+        assert_eq!(options("game.obj").check_calls, None);
+        assert_eq!(
+            options("--check-calls game.obj").check_calls,
+            Some(0b0011_1100)
+        );
+        assert_eq!(
+            options("--callee-saved R1,R4-R5 game.obj").check_calls,
+            Some(0b0011_0010)
+        );
+        assert_eq!(
+            parse(&arguments("--callee-saved R9 game.obj")).unwrap_err(),
+            "--callee-saved: expected a register R0 to R7, got 'R9'"
+        );
+    }
+
+    #[test]
+    fn test_memcheck_options() {
+        assert_eq!(options("game.obj").memcheck, None);
+        assert_eq!(options("--memcheck game.obj").memcheck, Some(false));
+        assert_eq!(options("--memcheck-halt game.obj").memcheck, Some(true));
+        assert_eq!(
+            options("--memcheck-halt --memcheck game.obj").memcheck,
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_self_modifying_option() {
+        assert_eq!(options("game.obj").self_modifying, None);
+        assert_eq!(
+            options("--self-modifying fault game.obj").self_modifying,
+            Some(CodePolicy::Fault)
+        );
+        assert_eq!(
+            parse(&arguments("--self-modifying never game.obj")).unwrap_err(),
+            "--self-modifying: unknown policy 'never', expected allow, warn or fault"
+        );
+    }
+
+    #[test]
+    fn test_help_and_version_win() {
+        assert!(matches!(parse(&arguments("run --help")), Ok(Action::Help)));
+        assert!(matches!(parse(&arguments("-V")), Ok(Action::Version)));
+    }
+
+    #[test]
+    fn test_errors_name_the_problem() {
+        assert_eq!(
+            parse(&arguments("--reg R8=1 a.obj")).unwrap_err(),
+            "--reg expects a register R0..R7, got 'R8'"
+        );
+        assert_eq!(
+            parse(&arguments("--entry nowhere a.obj")).unwrap_err(),
+            "--entry expects a 16-bit value, got 'nowhere'"
+        );
+        assert_eq!(
+            parse(&arguments("a.obj --fill")).unwrap_err(),
+            "--fill expects a value"
+        );
+        assert_eq!(
+            parse(&arguments("--bogus a.obj")).unwrap_err(),
+            "unknown option '--bogus'"
+        );
+        assert_eq!(parse(&arguments("run")).unwrap_err(), "no input files");
+    }
+
+    #[test]
+    fn test_assemble_and_link_options() {
+        assert!(options("asm -c main.asm").relocatable);
+        assert_eq!(options("lint main.asm").command, Command::Lint);
+        assert_eq!(options("cc -o game.asm game.c").command, Command::Cc);
+        let graph = options("graph --calls --json --profile 2048.obj");
+        assert_eq!(graph.command, Command::Graph);
+        assert!(graph.call_graph && graph.json && graph.profile);
+        assert_eq!(options("decompile 2048.obj").command, Command::Decompile);
+        assert_eq!(
+            options("asm -I lib -I /usr/lc3 main.asm").include_paths,
+            [PathBuf::from("lib"), PathBuf::from("/usr/lc3")]
+        );
+        let options = options("link --base x4000 -o game.obj main.rel print.rel");
+        assert_eq!(options.command, Command::Link);
+        assert_eq!(options.base, Some(0x4000));
+        assert_eq!(options.object.as_deref(), Some("game.obj"));
+    }
+
+    #[test]
+    fn test_dump_range() {
+        assert_eq!(
+            options("dump --range x3000:x3010 a.obj").range,
+            Some((0x3000, 0x3010))
+        );
+        assert!(parse(&arguments("dump --range x3010:x3000 a.obj")).is_err());
+    }
+
+    #[test]
+    fn test_load_policy() {
+        let options = options("--reserved deny --allow-overlap --memory-map a.obj");
+        assert_eq!(options.load_policy.reserved, ReservedPolicy::Deny);
+        assert!(options.load_policy.allow_overlap);
+        assert!(options.memory_map);
+        assert_eq!(
+            parse(&arguments("--reserved maybe a.obj")).unwrap_err(),
+            "--reserved: unknown policy 'maybe', expected allow, warn or deny"
+        );
+    }
+
+    #[test]
+    fn test_options_are_layered_on_the_config() {
+        let path = std::env::temp_dir().join("rustvm_cli_machine.toml");
+        std::fs::write(
+            &path,
+            "[machine]\nentry = \"x4000\"\nquiet = true\n\
+             [memory]\nimages = [\"lib.obj\"]\n\
+             [devices]\ndisplay = true\n",
+        )
+        .unwrap();
+        let options = options(&format!(
+            "--entry x5000 game.obj --config {}",
+            path.to_str().unwrap()
+        ));
+
+        assert_eq!(options.entry, Some(0x5000));
+        assert!(options.quiet);
+        assert!(options.machine.devices.display);
+        assert_eq!(options.files, ["lib.obj", "game.obj"]);
+    }
+
+    // ========== Preparing the Machine ==========
+
+    #[test]
+    fn test_entry_defaults_to_the_last_image() {
+        let first = object("rustvm_cli_first.obj", 0x3000, &[0xF025]);
+        let second = object("rustvm_cli_second.obj", 0x4000, &[0xF025]);
+        let mut vm = Vm::new();
+
+        prepare(
+            &mut vm,
+            &options(&format!("{} {} --fill x1234", first, second)),
+        )
+        .unwrap();
+
+        assert_eq!(vm.registers[Register::Pc as usize], 0x4000);
+        assert_eq!(vm.memory[0x3000], 0xF025);
+        assert_eq!(vm.memory[0x5000], 0x1234);
+    }
+
+    #[test]
+    fn test_raw_images_need_a_load_address() {
+        let path = std::env::temp_dir().join("rustvm_cli_dump.raw");
+        std::fs::write(&path, [0x25, 0xF0]).unwrap();
+        let path = path.to_str().unwrap();
+        let mut vm = Vm::new();
+
+        prepare(&mut vm, &options(&format!("--load-at x4000 {}", path))).unwrap();
+
+        assert_eq!(vm.memory[0x4000], 0xF025);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x4000);
+        assert_eq!(
+            prepare(&mut Vm::new(), &options(path)).unwrap_err(),
+            format!(
+                "could not load {}: raw images need a load address (--load-at)",
+                path
+            )
+        );
+    }
+
+    #[test]
+    fn test_overlapping_images_are_not_loaded() {
+        let first = object("rustvm_cli_overlap_a.obj", 0x3000, &[0x1111, 0x2222]);
+        let second = object("rustvm_cli_overlap_b.obj", 0x3001, &[0x3333]);
+        let mut vm = Vm::new();
+
+        let error = prepare(&mut vm, &options(&format!("{} {}", first, second))).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "{}: x3001-x3001 overwrites {} loaded at x3000-x3001",
+                second, first
+            )
+        );
+        assert_eq!(vm.memory[0x3000], 0);
+        assert!(vm.images.is_empty());
+
+        prepare(
+            &mut vm,
+            &options(&format!("--allow-overlap {} {}", first, second)),
+        )
+        .unwrap();
+        assert_eq!(vm.memory[0x3001], 0x3333);
+    }
+
+    #[test]
+    fn test_missing_file_is_an_error() {
+        let mut vm = Vm::new();
+
+        let error = prepare(&mut vm, &options("/nonexistent/rustvm.obj")).unwrap_err();
+        assert!(
+            error.starts_with("could not load /nonexistent/rustvm.obj: "),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_source_maps_are_found_next_to_images() {
+        let image = object("rustvm_cli_mapped.obj", 0x3000, &[0xF025]);
+        let map = std::path::Path::new(&image).with_extension("map");
+        std::fs::write(&map, "x3000 mapped.asm:4:9\n").unwrap();
+
+        let found = source_map(&options(&image)).unwrap();
+        let explicit = source_map(&options(&format!(
+            "--source-map {} other.obj",
+            map.display()
+        )))
+        .unwrap();
+        std::fs::remove_file(&map).unwrap();
+
+        assert_eq!(found.lookup(0x3000).unwrap().to_string(), "mapped.asm:4");
+        assert_eq!(explicit, found);
+        assert!(source_map(&options("/nonexistent/rustvm.obj"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,535 @@
+use crate::instructions::sign_extend;
+use crate::registers::register::Register;
+use crate::symbols::source_map::SourceMap;
+use crate::symbols::symbol_table::SymbolTable;
+use std::fmt;
+use std::fmt::Write;
+use std::mem::discriminant;
+
+/// The registers a subroutine must hand back unchanged unless told
+/// otherwise: R2 to R5. R0 and R1 carry results, R6 and R7 are checked
+/// on their own.
+pub const DEFAULT_CALLEE_SAVED: u8 = 0b0011_1100;
+
+/// Below this, stores land in the trap and interrupt vector tables.
+const VECTOR_TABLES_END: u16 = 0x0200;
+
+/// Reads a register list such as `R2-R5`, `R1,R4` or `none` into a bit
+/// mask.
+pub fn parse_registers(text: &str) -> Result<u8, String> {
+    if text.eq_ignore_ascii_case("none") {
+        return Ok(0);
+    }
+    let register = |name: &str| -> Result<u8, String> {
+        match name.trim().as_bytes() {
+            [b'R' | b'r', digit @ b'0'..=b'7'] => Ok(digit - b'0'),
+            _ => Err(format!("expected a register R0 to R7, got '{}'", name)),
+        }
+    };
+    let mut mask = 0;
+    for part in text.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (register(first)?, register(last)?),
+            None => (register(part)?, register(part)?),
+        };
+        if first > last {
+            return Err(format!("'{}' counts down", part));
+        }
+        for register in first..=last {
+            mask |= 1 << register;
+        }
+    }
+    Ok(mask)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// `RET` went somewhere other than the instruction after the call.
+    WrongReturn { expected: u16, target: u16 },
+    /// R7 was overwritten while the return address was nowhere else.
+    LostReturnAddress { return_address: u16 },
+    /// R6 was not back where it was at the call, or one below it with the
+    /// return value on top.
+    StackNotRestored { expected: u16, actual: u16 },
+    /// A callee-saved register came back changed.
+    RegisterChanged {
+        register: u8,
+        before: u16,
+        after: u16,
+    },
+    /// A store through R6 hit an instruction that has run.
+    StackIntoCode { address: u16 },
+    /// A store through R6 hit the trap or interrupt vector table.
+    StackIntoVectors { address: u16 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Problem::WrongReturn { expected, target } => write!(
+                f,
+                "returned to x{:04X} instead of x{:04X}",
+                target, expected
+            ),
+            Problem::LostReturnAddress { return_address } => write!(
+                f,
+                "overwrote R7 before saving the return address x{:04X}",
+                return_address
+            ),
+            Problem::StackNotRestored { expected, actual } => write!(
+                f,
+                "returned with R6 = x{:04X} instead of x{:04X}",
+                actual, expected
+            ),
+            Problem::RegisterChanged {
+                register,
+                before,
+                after,
+            } => write!(
+                f,
+                "returned with R{} = x{:04X} instead of x{:04X}",
+                register, after, before
+            ),
+            Problem::StackIntoCode { address } => {
+                write!(f, "pushed onto code at x{:04X}", address)
+            }
+            Problem::StackIntoVectors { address } => {
+                write!(f, "pushed into the vector tables at x{:04X}", address)
+            }
+        }
+    }
+}
+
+/// A problem, where it first happened and how often.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The instruction that showed the problem.
+    pub address: u16,
+    /// The entry of the subroutine it happened in, if any.
+    pub subroutine: Option<u16>,
+    pub problem: Problem,
+    pub count: u64,
+}
+
+/// A call the program has not returned from yet.
+#[derive(Debug, Clone)]
+struct Frame {
+    entry: u16,
+    return_address: u16,
+    /// The registers as the subroutine got them.
+    registers: [u16; 8],
+    /// Entered by `TRAP`, so the registers are the OS routine's business.
+    trap: bool,
+    /// Whether the return address has been copied out of R7.
+    kept: bool,
+}
+
+/// Keeps a shadow call stack from `JSR`, `JSRR` and `TRAP` into the OS,
+/// matches `RET`s against it, and reports breaks in the usual LC-3
+/// calling convention as they happen.
+pub struct ConventionChecker {
+    callee_saved: u8,
+    frames: Vec<Frame>,
+    /// One bit per address that has run as an instruction.
+    executed: Vec<u64>,
+    before: [u16; 8],
+    violations: Vec<Violation>,
+}
+
+impl ConventionChecker {
+    pub fn new(callee_saved: u8) -> ConventionChecker {
+        Self {
+            callee_saved,
+            frames: Vec::new(),
+            executed: vec![0; 1 << 10],
+            before: [0; 8],
+            violations: Vec::new(),
+        }
+    }
+
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// How many calls are still open.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Notes the registers ahead of the instruction at `address`.
+    pub fn before(&mut self, address: u16, registers: &[u16]) {
+        self.executed[address as usize / 64] |= 1 << (address % 64);
+        self.before.copy_from_slice(&registers[..8]);
+    }
+
+    /// Checks what the instruction at `address` did, given the registers
+    /// it left.
+    pub fn after(&mut self, address: u16, instruction: u16, after: &[u16]) {
+        let before = self.before;
+        let opcode = instruction >> 12;
+        let source = ((instruction >> 9) & 0x7) as usize;
+        let base = ((instruction >> 6) & 0x7) as usize;
+
+        // STR SR, R6, #offset
+        if opcode == 0b0111 && base == Register::R6 as usize {
+            let target = before[6].wrapping_add(sign_extend(instruction & 0x3F, 6));
+            if target < VECTOR_TABLES_END {
+                self.report(address, Problem::StackIntoVectors { address: target });
+            } else if self.executed[target as usize / 64] & (1 << (target % 64)) != 0 {
+                self.report(address, Problem::StackIntoCode { address: target });
+            }
+        }
+
+        let lost = self.frames.last_mut().and_then(|frame| {
+            let return_address = frame.return_address;
+            let stored = matches!(opcode, 0b0011 | 0b0111 | 0b1011)
+                && source == Register::R7 as usize
+                && before[7] == return_address;
+            let copied = (0..7).any(|register| {
+                after[register] == return_address && before[register] != return_address
+            });
+            // Once lost it is reported once; the RET that follows shows the rest
+            let overwritten = before[7] == return_address && after[7] != return_address;
+            let lost = !frame.kept && !stored && !copied && overwritten;
+            frame.kept |= stored || copied || lost;
+            lost.then_some(return_address)
+        });
+        if let Some(return_address) = lost {
+            self.report(address, Problem::LostReturnAddress { return_address });
+        }
+
+        let next = address.wrapping_add(1);
+        let pc = after[Register::Pc as usize];
+        match opcode {
+            0b0100 => self.frames.push(Frame {
+                entry: pc,
+                return_address: next,
+                registers: before,
+                trap: false,
+                kept: false,
+            }),
+            // Only traps that jump into OS code, not the built-in ones
+            0b1111 if pc != next => self.frames.push(Frame {
+                entry: pc,
+                return_address: next,
+                registers: before,
+                trap: true,
+                kept: false,
+            }),
+            0b1100 if base == Register::R7 as usize => self.ret(address, pc, after),
+            _ => {}
+        }
+    }
+
+    fn ret(&mut self, address: u16, target: u16, after: &[u16]) {
+        let Some(frame) = self.frames.last().cloned() else {
+            return;
+        };
+        if frame.return_address != target {
+            self.report(
+                address,
+                Problem::WrongReturn {
+                    expected: frame.return_address,
+                    target,
+                },
+            );
+            // Returning further up the stack unwinds to that caller
+            let open = self
+                .frames
+                .iter()
+                .rposition(|frame| frame.return_address == target)
+                .unwrap_or(self.frames.len() - 1);
+            self.frames.truncate(open);
+            return;
+        }
+
+        let (expected, stack) = (frame.registers[6], after[Register::R6 as usize]);
+        if stack != expected && stack != expected.wrapping_sub(1) {
+            self.report(
+                address,
+                Problem::StackNotRestored {
+                    expected,
+                    actual: stack,
+                },
+            );
+        }
+        if !frame.trap {
+            let callee_saved = self.callee_saved;
+            for register in (0..8u8).filter(|register| callee_saved & (1 << register) != 0) {
+                let (before, after) =
+                    (frame.registers[register as usize], after[register as usize]);
+                if before != after {
+                    self.report(
+                        address,
+                        Problem::RegisterChanged {
+                            register,
+                            before,
+                            after,
+                        },
+                    );
+                }
+            }
+        }
+        self.frames.pop();
+    }
+
+    /// Records a problem, counting repeats of the same kind at the same
+    /// place rather than listing them again.
+    fn report(&mut self, address: u16, problem: Problem) {
+        let subroutine = self.frames.last().map(|frame| frame.entry);
+        let known = self.violations.iter_mut().find(|violation| {
+            violation.address == address
+                && discriminant(&violation.problem) == discriminant(&problem)
+        });
+        match known {
+            Some(violation) => violation.count += 1,
+            None => self.violations.push(Violation {
+                address,
+                subroutine,
+                problem,
+                count: 1,
+            }),
+        }
+    }
+
+    /// One line per problem, naming subroutines and source lines where
+    /// the symbols and source map know them.
+    pub fn report_with(&self, symbols: &SymbolTable, source_map: &SourceMap) -> String {
+        let mut report = String::new();
+        match self.violations.len() {
+            0 => writeln!(report, "--- Calling convention: no problems ---").unwrap(),
+            1 => writeln!(report, "--- Calling convention: 1 problem ---").unwrap(),
+            count => writeln!(report, "--- Calling convention: {} problems ---", count).unwrap(),
+        }
+        for violation in &self.violations {
+            write!(report, "x{:04X}", violation.address).unwrap();
+            if let Some(entry) = violation.subroutine {
+                match symbols.label_at(entry) {
+                    Some(label) => write!(report, " in {}", label).unwrap(),
+                    None => write!(report, " in x{:04X}", entry).unwrap(),
+                }
+            }
+            if let Some(location) = source_map.lookup(violation.address) {
+                write!(report, " ({})", location).unwrap();
+            }
+            write!(report, ": {}", violation.problem).unwrap();
+            if violation.count > 1 {
+                write!(report, " ({} times)", violation.count).unwrap();
+            }
+            writeln!(report).unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::cc::compile_image;
+    use crate::conventions::{
+        parse_registers, ConventionChecker, Problem, Violation, DEFAULT_CALLEE_SAVED,
+    };
+    use crate::image::Image;
+    use crate::registers::register::Register;
+    use crate::symbols::source_map::SourceMap;
+    use crate::symbols::symbol_table::SymbolTable;
+    use crate::Vm;
+
+    fn run(image: &Image) -> ConventionChecker {
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", image);
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.limits.max_instructions = Some(100_000);
+        vm.conventions = Some(ConventionChecker::new(DEFAULT_CALLEE_SAVED));
+        vm.run();
+        vm.conventions.unwrap()
+    }
+
+    fn problems(source: &str) -> Vec<(u16, Problem)> {
+        run(&assemble(source).unwrap().image())
+            .violations()
+            .iter()
+            .map(|violation| (violation.address, violation.problem))
+            .collect()
+    }
+
+    // ========== Calls and Returns ==========
+
+    #[test]
+    fn test_well_behaved_subroutine_is_clean() {
+        let source = ".ORIG x3000
+        LD R6, STACK
+        JSR SHOW
+        JSR SHOW
+        HALT
+STACK   .FILL xFE00
+SHOW    ADD R6, R6, #-1
+        STR R7, R6, #0
+        LD R0, STAR
+        OUT
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+STAR    .FILL x2A
+.END
+";
+        let checker = run(&assemble(source).unwrap().image());
+
+        assert_eq!(checker.violations(), []);
+        assert_eq!(checker.depth(), 0);
+    }
+
+    #[test]
+    fn test_trap_in_subroutine_loses_the_return_address() {
+        let problems = problems(
+            ".ORIG x3000
+        JSR SHOW
+        HALT
+SHOW    LD R0, STAR
+        OUT
+        RET
+STAR    .FILL x2A
+.END
+",
+        );
+
+        assert_eq!(
+            problems[..2],
+            [
+                (
+                    0x3003,
+                    Problem::LostReturnAddress {
+                        return_address: 0x3001
+                    }
+                ),
+                (
+                    0x3004,
+                    Problem::WrongReturn {
+                        expected: 0x3001,
+                        target: 0x3004
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_registers_and_stack_must_come_back() {
+        let problems = problems(
+            ".ORIG x3000
+        LD R6, STACK
+        JSR BAD
+        HALT
+STACK   .FILL xFE00
+BAD     ADD R3, R3, #1
+        ADD R6, R6, #-2
+        ADD R1, R1, #1
+        RET
+.END
+",
+        );
+
+        assert_eq!(
+            problems,
+            [
+                (
+                    0x3007,
+                    Problem::StackNotRestored {
+                        expected: 0xFE00,
+                        actual: 0xFDFE
+                    }
+                ),
+                (
+                    0x3007,
+                    Problem::RegisterChanged {
+                        register: 3,
+                        before: 0,
+                        after: 1
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_writes_into_code_and_vectors() {
+        let problems = problems(
+            ".ORIG x3000
+        LEA R6, NEXT
+NEXT    STR R0, R6, #-1
+        AND R6, R6, #0
+        ADD R6, R6, #15
+        STR R0, R6, #0
+        HALT
+.END
+",
+        );
+
+        assert_eq!(
+            problems,
+            [
+                (0x3001, Problem::StackIntoCode { address: 0x3000 }),
+                (0x3004, Problem::StackIntoVectors { address: 0x000F }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compiled_programs_follow_the_convention() {
+        let source = "
+int fib(int n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+int main() {
+    int a[3] = {5, 6, 7};
+    putchar('0' + fib(a[0]) * 3 / 5 % 4 ^ 1);
+    return a[1] << a[2] >> 3;
+}";
+        let checker = run(&compile_image(source).unwrap().image());
+        assert_eq!(checker.violations(), []);
+
+        // Too big for PC-relative jumps, so they go through registers
+        let source = format!(
+            "int main() {{ int i = 0; while (i < 2) {{ {} i = i + 1; }} return i / 2; }}",
+            "i = i ^ 0;".repeat(100)
+        );
+        let checker = run(&compile_image(&source).unwrap().image());
+        assert_eq!(checker.violations(), []);
+    }
+
+    // ========== Options and Report ==========
+
+    #[test]
+    fn test_register_lists() {
+        assert_eq!(parse_registers("R2-R5"), Ok(DEFAULT_CALLEE_SAVED));
+        assert_eq!(parse_registers("r0, R7"), Ok(0b1000_0001));
+        assert_eq!(parse_registers("none"), Ok(0));
+        assert!(parse_registers("R5-R2").is_err());
+    }
+
+    #[test]
+    fn test_report_names_subroutines_and_counts_repeats() {
+        let mut checker = ConventionChecker::new(0);
+        checker.violations = vec![Violation {
+            address: 0x3004,
+            subroutine: Some(0x3003),
+            problem: Problem::WrongReturn {
+                expected: 0x3001,
+                target: 0x3004,
+            },
+            count: 3,
+        }];
+        let mut symbols = SymbolTable::new();
+        symbols.insert("SHOW", 0x3003);
+
+        assert_eq!(
+            checker.report_with(&symbols, &SourceMap::default()),
+            "--- Calling convention: 1 problem ---
+x3004 in SHOW: returned to x3004 instead of x3001 (3 times)
+"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::coverage::FileCoverage;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Renders coverage as a Cobertura XML report, with every source file as a
+/// class inside a single `lc3` package.
+pub fn to_cobertura(files: &[FileCoverage]) -> String {
+    let lines_valid: usize = files.iter().map(|f| f.lines.len()).sum();
+    let lines_covered: usize = files.iter().map(|f| f.lines_hit()).sum();
+    let branches_valid: usize = files.iter().map(|f| f.branches_found()).sum();
+    let branches_covered: usize = files.iter().map(|f| f.branches_hit()).sum();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    writeln!(out, "<?xml version=\"1.0\" ?>").unwrap();
+    writeln!(
+        out,
+        "<coverage line-rate=\"{}\" branch-rate=\"{}\" lines-covered=\"{}\" lines-valid=\"{}\" \
+         branches-covered=\"{}\" branches-valid=\"{}\" complexity=\"0\" version=\"{}\" timestamp=\"{}\">",
+        rate(lines_covered, lines_valid),
+        rate(branches_covered, branches_valid),
+        lines_covered,
+        lines_valid,
+        branches_covered,
+        branches_valid,
+        env!("CARGO_PKG_VERSION"),
+        timestamp
+    )
+    .unwrap();
+    writeln!(out, "  <sources><source>.</source></sources>").unwrap();
+    writeln!(out, "  <packages>").unwrap();
+    writeln!(
+        out,
+        "    <package name=\"lc3\" line-rate=\"{}\" branch-rate=\"{}\" complexity=\"0\">",
+        rate(lines_covered, lines_valid),
+        rate(branches_covered, branches_valid)
+    )
+    .unwrap();
+    writeln!(out, "      <classes>").unwrap();
+
+    for file in files {
+        writeln!(
+            out,
+            "        <class name=\"{}\" filename=\"{}\" line-rate=\"{}\" branch-rate=\"{}\" complexity=\"0\">",
+            escape(&file.path),
+            escape(&file.path),
+            rate(file.lines_hit(), file.lines.len()),
+            rate(file.branches_hit(), file.branches_found())
+        )
+        .unwrap();
+        writeln!(out, "          <methods/>").unwrap();
+        writeln!(out, "          <lines>").unwrap();
+
+        for (line, coverage) in &file.lines {
+            if coverage.branches.is_empty() {
+                writeln!(
+                    out,
+                    "            <line number=\"{}\" hits=\"{}\" branch=\"false\"/>",
+                    line, coverage.hits
+                )
+                .unwrap();
+                continue;
+            }
+
+            let found = coverage.branches.len() * 2;
+            let hit: usize = coverage
+                .branches
+                .iter()
+                .flatten()
+                .map(|counts| (counts.taken > 0) as usize + (counts.not_taken > 0) as usize)
+                .sum();
+            writeln!(
+                out,
+                "            <line number=\"{}\" hits=\"{}\" branch=\"true\" condition-coverage=\"{}% ({}/{})\"/>",
+                line,
+                coverage.hits,
+                hit * 100 / found,
+                hit,
+                found
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "          </lines>").unwrap();
+        writeln!(out, "        </class>").unwrap();
+    }
+
+    writeln!(out, "      </classes>").unwrap();
+    writeln!(out, "    </package>").unwrap();
+    writeln!(out, "  </packages>").unwrap();
+    writeln!(out, "</coverage>").unwrap();
+    out
+}
+
+fn rate(covered: usize, valid: usize) -> String {
+    if valid == 0 {
+        return "1".to_string();
+    }
+    format!("{:.4}", covered as f64 / valid as f64)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coverage::cobertura::to_cobertura;
+    use crate::coverage::{BranchCounts, FileCoverage, LineCoverage};
+
+    #[test]
+    fn test_to_cobertura_reports_rates_and_lines() {
+        let mut file = FileCoverage {
+            path: "main.asm".to_string(),
+            ..FileCoverage::default()
+        };
+        file.lines.insert(
+            1,
+            LineCoverage {
+                hits: 1,
+                branches: vec![],
+            },
+        );
+        file.lines.insert(
+            2,
+            LineCoverage {
+                hits: 1,
+                branches: vec![Some(BranchCounts {
+                    taken: 1,
+                    not_taken: 0,
+                })],
+            },
+        );
+        file.lines.insert(
+            3,
+            LineCoverage {
+                hits: 0,
+                branches: vec![],
+            },
+        );
+
+        let xml = to_cobertura(&[file]);
+
+        assert!(xml.contains("lines-covered=\"2\" lines-valid=\"3\""));
+        assert!(xml.contains("branches-covered=\"1\" branches-valid=\"2\""));
+        assert!(xml.contains("<class name=\"main.asm\" filename=\"main.asm\" line-rate=\"0.6667\""));
+        assert!(xml.contains("<line number=\"1\" hits=\"1\" branch=\"false\"/>"));
+        assert!(xml.contains("condition-coverage=\"50% (1/2)\""));
+    }
+
+    #[test]
+    fn test_to_cobertura_escapes_file_names() {
+        let file = FileCoverage {
+            path: "a&b.asm".to_string(),
+            ..FileCoverage::default()
+        };
+
+        assert!(to_cobertura(&[file]).contains("filename=\"a&amp;b.asm\""));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::coverage::FileCoverage;
+use std::fmt::Write;
+
+/// Renders coverage in the `lcov` tracefile format read by `genhtml` and most
+/// editor coverage plugins. Each conditional branch becomes a taken and a
+/// not-taken `BRDA` record.
+pub fn to_lcov(files: &[FileCoverage]) -> String {
+    let mut out = String::new();
+
+    for file in files {
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{}", file.path).unwrap();
+
+        for function in &file.functions {
+            writeln!(out, "FN:{},{}", function.line, function.name).unwrap();
+        }
+        for function in &file.functions {
+            writeln!(out, "FNDA:{},{}", function.hits, function.name).unwrap();
+        }
+        writeln!(out, "FNF:{}", file.functions.len()).unwrap();
+        writeln!(
+            out,
+            "FNH:{}",
+            file.functions.iter().filter(|f| f.hits > 0).count()
+        )
+        .unwrap();
+
+        for (line, coverage) in &file.lines {
+            for (block, branch) in coverage.branches.iter().enumerate() {
+                match branch {
+                    Some(counts) => {
+                        writeln!(out, "BRDA:{},{},0,{}", line, block, counts.taken).unwrap();
+                        writeln!(out, "BRDA:{},{},1,{}", line, block, counts.not_taken).unwrap();
+                    }
+                    None => {
+                        writeln!(out, "BRDA:{},{},0,-", line, block).unwrap();
+                        writeln!(out, "BRDA:{},{},1,-", line, block).unwrap();
+                    }
+                }
+            }
+        }
+        writeln!(out, "BRF:{}", file.branches_found()).unwrap();
+        writeln!(out, "BRH:{}", file.branches_hit()).unwrap();
+
+        for (line, coverage) in &file.lines {
+            writeln!(out, "DA:{},{}", line, coverage.hits).unwrap();
+        }
+        writeln!(out, "LF:{}", file.lines.len()).unwrap();
+        writeln!(out, "LH:{}", file.lines_hit()).unwrap();
+        writeln!(out, "end_of_record").unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coverage::lcov::to_lcov;
+    use crate::coverage::{BranchCounts, FileCoverage, FunctionCoverage, LineCoverage};
+
+    #[test]
+    fn test_to_lcov_writes_lines_branches_and_functions() {
+        let mut file = FileCoverage {
+            path: "main.asm".to_string(),
+            ..FileCoverage::default()
+        };
+        file.lines.insert(
+            3,
+            LineCoverage {
+                hits: 2,
+                branches: vec![],
+            },
+        );
+        file.lines.insert(
+            4,
+            LineCoverage {
+                hits: 2,
+                branches: vec![Some(BranchCounts {
+                    taken: 2,
+                    not_taken: 0,
+                })],
+            },
+        );
+        file.lines.insert(
+            5,
+            LineCoverage {
+                hits: 0,
+                branches: vec![None],
+            },
+        );
+        file.functions.push(FunctionCoverage {
+            name: "SUB".to_string(),
+            line: 3,
+            hits: 2,
+        });
+
+        let lcov = to_lcov(&[file]);
+
+        assert_eq!(
+            lcov,
+            "TN:\n\
+             SF:main.asm\n\
+             FN:3,SUB\n\
+             FNDA:2,SUB\n\
+             FNF:1\n\
+             FNH:1\n\
+             BRDA:4,0,0,2\n\
+             BRDA:4,0,1,0\n\
+             BRDA:5,0,0,-\n\
+             BRDA:5,0,1,-\n\
+             BRF:4\n\
+             BRH:1\n\
+             DA:3,2\n\
+             DA:4,2\n\
+             DA:5,0\n\
+             LF:3\n\
+             LH:2\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_to_lcov_without_files_is_empty() {
+        assert_eq!(to_lcov(&[]), "");
+    }
+}
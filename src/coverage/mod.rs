@@ -0,0 +1,536 @@
+use crate::cfg::recover_from;
+use crate::instructions::disassemble::{disassemble, pc_target};
+use crate::instructions::opcodes::Opcode;
+use crate::symbols::source_map::SourceMap;
+use crate::symbols::symbol_table::SymbolTable;
+use crate::{LoadedImage, Vm, MEMORY_MAX};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+
+pub mod cobertura;
+pub mod lcov;
+
+/// Which coverage reports to produce once the program halts.
+#[derive(Debug, Default)]
+pub struct CoverageOptions {
+    pub symbols: Option<String>,
+    pub source_map: Option<String>,
+    pub lcov: Option<String>,
+    pub cobertura: Option<String>,
+    pub summary: bool,
+}
+
+impl CoverageOptions {
+    pub fn enabled(&self) -> bool {
+        self.summary || self.lcov.is_some() || self.cobertura.is_some()
+    }
+
+    pub fn write_reports(&self, vm: &Vm) -> Result<(), String> {
+        let Some(coverage) = vm.coverage.as_ref() else {
+            return Ok(());
+        };
+
+        let symbols = match &self.symbols {
+            Some(file_name) => Some(SymbolTable::load(file_name)?),
+            None => None,
+        };
+        let source_map = match &self.source_map {
+            Some(file_name) => Some(SourceMap::load(file_name)?),
+            None => None,
+        };
+
+        if self.lcov.is_some() || self.cobertura.is_some() {
+            let files = coverage.report(
+                &vm.memory,
+                &vm.images,
+                source_map.as_ref(),
+                symbols.as_ref(),
+            );
+            if let Some(out) = &self.lcov {
+                fs::write(out, lcov::to_lcov(&files))
+                    .map_err(|e| format!("could not write {}: {}", out, e))?;
+            }
+            if let Some(out) = &self.cobertura {
+                fs::write(out, cobertura::to_cobertura(&files))
+                    .map_err(|e| format!("could not write {}: {}", out, e))?;
+            }
+        }
+
+        if self.summary {
+            print!(
+                "{}",
+                coverage.summary(
+                    &vm.memory,
+                    &vm.images,
+                    source_map.as_ref(),
+                    symbols.as_ref()
+                )
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BranchCounts {
+    pub taken: u32,
+    pub not_taken: u32,
+}
+
+/// Execution counts for every address plus the direction taken by each
+/// conditional branch, collected while the VM runs.
+pub struct Coverage {
+    hits: Vec<u32>,
+    branches: BTreeMap<u16, BranchCounts>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct LineCoverage {
+    pub hits: u32,
+    /// One entry per conditional branch on the line; `None` if it never ran.
+    pub branches: Vec<Option<BranchCounts>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionCoverage {
+    pub name: String,
+    pub line: u32,
+    pub hits: u32,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct FileCoverage {
+    pub path: String,
+    pub lines: BTreeMap<u32, LineCoverage>,
+    pub functions: Vec<FunctionCoverage>,
+}
+
+impl FileCoverage {
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|line| line.hits > 0).count()
+    }
+
+    pub fn branches_found(&self) -> usize {
+        self.lines
+            .values()
+            .map(|line| line.branches.len() * 2)
+            .sum()
+    }
+
+    pub fn branches_hit(&self) -> usize {
+        self.lines
+            .values()
+            .flat_map(|line| line.branches.iter().flatten())
+            .map(|counts| (counts.taken > 0) as usize + (counts.not_taken > 0) as usize)
+            .sum()
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Self {
+            hits: vec![0; MEMORY_MAX],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Records that the instruction at `address` is about to execute with the
+    /// given condition codes.
+    pub fn record(&mut self, address: u16, instruction: u16, cond: u16) {
+        self.hits[address as usize] = self.hits[address as usize].saturating_add(1);
+
+        if let Some(nzp) = conditional_branch_mask(instruction) {
+            let counts = self.branches.entry(address).or_default();
+            if nzp & cond != 0 {
+                counts.taken = counts.taken.saturating_add(1);
+            } else {
+                counts.not_taken = counts.not_taken.saturating_add(1);
+            }
+        }
+    }
+
+    pub fn hits(&self, address: u16) -> u32 {
+        self.hits[address as usize]
+    }
+
+    pub fn branch(&self, address: u16) -> Option<BranchCounts> {
+        self.branches.get(&address).copied()
+    }
+
+    /// Groups the counters by source line. With a source map every mapped
+    /// instruction is attributed to its line; without one each loaded image
+    /// is reported as a file whose line `n` is the `n`th word of the image.
+    /// Data is left out, as in `summary`.
+    /// Labels that are targets of a `JSR` in the loaded images become functions.
+    pub fn report(
+        &self,
+        memory: &[u16; MEMORY_MAX],
+        images: &[LoadedImage],
+        source_map: Option<&SourceMap>,
+        symbols: Option<&SymbolTable>,
+    ) -> Vec<FileCoverage> {
+        let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+        let mut locate: BTreeMap<u16, (String, u32)> = BTreeMap::new();
+
+        match source_map {
+            Some(map) => {
+                for (address, location) in map.iter() {
+                    locate.insert(address, (location.file.clone(), location.line));
+                }
+            }
+            None => {
+                for image in images {
+                    for (index, address) in image.addresses().enumerate() {
+                        locate.insert(address, (image.file_name.clone(), index as u32 + 1));
+                    }
+                }
+            }
+        }
+
+        // Data words are no lines to cover, and one that happens to look
+        // like a BR is no branch
+        let code = code_words(self, memory, images, source_map);
+        for (&address, (file, line)) in locate.iter().filter(|(address, _)| code.contains(address))
+        {
+            let file_coverage = files.entry(file.clone()).or_insert_with(|| FileCoverage {
+                path: file.clone(),
+                ..FileCoverage::default()
+            });
+            let line_coverage = file_coverage.lines.entry(*line).or_default();
+            line_coverage.hits = line_coverage.hits.saturating_add(self.hits(address));
+
+            if conditional_branch_mask(memory[address as usize]).is_some() {
+                line_coverage.branches.push(self.branch(address));
+            }
+        }
+
+        if let Some(symbols) = symbols {
+            let subroutines = subroutine_entries(memory, images);
+            for (label, address) in symbols.iter() {
+                if !subroutines.contains(&address) {
+                    continue;
+                }
+                let Some((file, line)) = locate.get(&address) else {
+                    continue;
+                };
+                if let Some(file_coverage) = files.get_mut(file) {
+                    file_coverage.functions.push(FunctionCoverage {
+                        name: label.to_string(),
+                        line: *line,
+                        hits: self.hits(address),
+                    });
+                }
+            }
+        }
+
+        files.into_values().collect()
+    }
+
+    /// Lists every loaded instruction that never executed, one per line,
+    /// with its disassembly plus the label and source location when they
+    /// are known. Data is left out: words the source map reserves, and words
+    /// no path from the image origins or the executed code reaches.
+    pub fn summary(
+        &self,
+        memory: &[u16; MEMORY_MAX],
+        images: &[LoadedImage],
+        source_map: Option<&SourceMap>,
+        symbols: Option<&SymbolTable>,
+    ) -> String {
+        let mut summary = String::new();
+        let mut total = 0;
+        let mut executed = 0;
+        let mut never_executed = Vec::new();
+
+        let code = code_words(self, memory, images, source_map);
+        for image in images {
+            for address in image.addresses().filter(|address| code.contains(address)) {
+                total += 1;
+                if self.hits(address) > 0 {
+                    executed += 1;
+                } else {
+                    never_executed.push(address);
+                }
+            }
+        }
+
+        let percent = if total == 0 {
+            0.0
+        } else {
+            executed as f64 * 100.0 / total as f64
+        };
+        writeln!(
+            summary,
+            "Coverage: {}/{} instructions executed ({:.1}%)",
+            executed, total, percent
+        )
+        .unwrap();
+
+        if never_executed.is_empty() {
+            return summary;
+        }
+
+        writeln!(summary, "Never executed:").unwrap();
+        for address in never_executed {
+            let instruction = memory[address as usize];
+            let mut text = disassemble(address, instruction);
+            if let Some(label) = symbols.and_then(|table| table.label_at(address)) {
+                text = format!("{}: {}", label, text);
+            }
+            match source_map.and_then(|map| map.lookup(address)) {
+                Some(location) => {
+                    writeln!(summary, "  x{:04X}  {:<24} {}", address, text, location).unwrap()
+                }
+                None => writeln!(summary, "  x{:04X}  {}", address, text).unwrap(),
+            }
+        }
+
+        summary
+    }
+}
+
+/// The loaded words that can run as code: everything statically reachable
+/// from the image origins and from whatever did execute, short of the
+/// words the source map reserves.
+fn code_words(
+    coverage: &Coverage,
+    memory: &[u16; MEMORY_MAX],
+    images: &[LoadedImage],
+    source_map: Option<&SourceMap>,
+) -> BTreeSet<u16> {
+    let reserved: BTreeSet<u16> =
+        source_map.map_or_else(BTreeSet::new, |map| map.reserved().collect());
+    let roots: Vec<u16> = images
+        .iter()
+        .map(|image| image.origin)
+        .chain(
+            images
+                .iter()
+                .flat_map(LoadedImage::addresses)
+                .filter(|&address| coverage.hits(address) > 0),
+        )
+        .collect();
+    if roots.is_empty() {
+        return BTreeSet::new();
+    }
+    let loaded = |address: u16| {
+        images
+            .iter()
+            .any(|image| address.wrapping_sub(image.origin) < image.length)
+    };
+    recover_from(memory, &roots, |address| {
+        loaded(address) && !reserved.contains(&address)
+    })
+    .reachable
+}
+
+/// Returns the NZP mask of a `BR` that can go either way.
+fn conditional_branch_mask(instruction: u16) -> Option<u16> {
+    if Opcode::get(instruction >> 12) != Some(Opcode::Br) {
+        return None;
+    }
+
+    match (instruction >> 9) & 0x7 {
+        0 | 0b111 => None,
+        nzp => Some(nzp),
+    }
+}
+
+fn subroutine_entries(memory: &[u16; MEMORY_MAX], images: &[LoadedImage]) -> BTreeSet<u16> {
+    let mut entries = BTreeSet::new();
+
+    for image in images {
+        for address in image.addresses() {
+            let instruction = memory[address as usize];
+            if Opcode::get(instruction >> 12) == Some(Opcode::Jsr) && (instruction >> 11) & 0x1 == 1
+            {
+                entries.insert(pc_target(address.wrapping_add(1), instruction, 11));
+            }
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coverage::lcov::to_lcov;
+    use crate::coverage::{BranchCounts, Coverage};
+    use crate::symbols::source_map::SourceMap;
+    use crate::symbols::symbol_table::SymbolTable;
+    use crate::{LoadedImage, Vm};
+
+    fn image(origin: u16, length: u16) -> LoadedImage {
+        LoadedImage {
+            file_name: "prog.obj".to_string(),
+            origin,
+            length,
+        }
+    }
+
+    // ========== Recording ==========
+
+    #[test]
+    fn test_record_counts_executions() {
+        let mut coverage = Coverage::new();
+
+        coverage.record(0x3000, 0x1021, 0);
+        coverage.record(0x3000, 0x1021, 0);
+        coverage.record(0x3001, 0xF025, 0);
+
+        assert_eq!(coverage.hits(0x3000), 2);
+        assert_eq!(coverage.hits(0x3001), 1);
+        assert_eq!(coverage.hits(0x3002), 0);
+    }
+
+    #[test]
+    fn test_record_tracks_branch_directions() {
+        let mut coverage = Coverage::new();
+
+        // BRz #-2 with Z set, then with P set
+        coverage.record(0x3005, 0b0000_010_111111110, 0b010);
+        coverage.record(0x3005, 0b0000_010_111111110, 0b001);
+        coverage.record(0x3005, 0b0000_010_111111110, 0b001);
+
+        assert_eq!(
+            coverage.branch(0x3005),
+            Some(BranchCounts {
+                taken: 1,
+                not_taken: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_unconditional_branch_is_not_a_branch_point() {
+        let mut coverage = Coverage::new();
+
+        // BRnzp #-1
+        coverage.record(0x3000, 0b0000_111_111111111, 0b010);
+
+        assert_eq!(coverage.branch(0x3000), None);
+    }
+
+    // ========== Reports ==========
+
+    #[test]
+    fn test_report_without_source_map_uses_image_offsets() {
+        let vm = Vm::new();
+        let mut coverage = Coverage::new();
+        coverage.record(0x3001, 0x1021, 0);
+
+        let files = coverage.report(&vm.memory, &[image(0x3000, 3)], None, None);
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "prog.obj");
+        assert_eq!(files[0].lines.len(), 3);
+        assert_eq!(files[0].lines[&2].hits, 1);
+        assert_eq!(files[0].lines_hit(), 1);
+    }
+
+    #[test]
+    fn test_report_with_source_map_groups_by_line() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3001, 0b0000_010_000000001); // BRz #1
+        let map =
+            SourceMap::parse("x3000 main.asm:3\nx3001 main.asm:4\nx3002 main.asm:4\n").unwrap();
+        let mut coverage = Coverage::new();
+        coverage.record(0x3000, 0x1021, 0);
+        coverage.record(0x3001, 0b0000_010_000000001, 0b010);
+
+        let files = coverage.report(&vm.memory, &[image(0x3000, 3)], Some(&map), None);
+
+        assert_eq!(files[0].path, "main.asm");
+        assert_eq!(files[0].lines[&3].hits, 1);
+        assert_eq!(files[0].lines[&4].hits, 1);
+        assert_eq!(files[0].branches_found(), 2);
+        assert_eq!(files[0].branches_hit(), 1);
+    }
+
+    #[test]
+    fn test_report_lists_jsr_targets_as_functions() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3000, 0b0100_1_00000000001); // JSR x3002
+        vm.mem_write(0x3001, 0xF025); // HALT
+        vm.mem_write(0x3002, 0b1100_000_111_000000); // RET
+        let symbols = SymbolTable::parse("MAIN x3000\nSUB x3002\n").unwrap();
+        let mut coverage = Coverage::new();
+        coverage.record(0x3002, 0b1100_000_111_000000, 0);
+
+        let files = coverage.report(&vm.memory, &[image(0x3000, 3)], None, Some(&symbols));
+
+        assert_eq!(files[0].functions.len(), 1);
+        assert_eq!(files[0].functions[0].name, "SUB");
+        assert_eq!(files[0].functions[0].line, 3);
+        assert_eq!(files[0].functions[0].hits, 1);
+    }
+
+    #[test]
+    fn test_lcov_leaves_out_data_lines() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3000, 0b0010_000_000000001); // LD R0, x3002
+        vm.mem_write(0x3001, 0xF025); // HALT
+        vm.mem_write(0x3002, 0b0000_010_000000001); // .FILL x0401, a BRz #1
+        let map =
+            SourceMap::parse("x3000 main.asm:1\nx3001 main.asm:2\nx3002 main.asm:3\n").unwrap();
+        let mut coverage = Coverage::new();
+        coverage.record(0x3000, 0b0010_000_000000001, 0);
+        coverage.record(0x3001, 0xF025, 0);
+
+        let files = coverage.report(&vm.memory, &[image(0x3000, 3)], Some(&map), None);
+        let lcov = to_lcov(&files);
+
+        assert!(lcov.contains("LF:2\nLH:2\n"), "{lcov}");
+        assert!(!lcov.contains("DA:3,"), "{lcov}");
+        assert!(!lcov.contains("BRDA"), "{lcov}");
+    }
+
+    #[test]
+    fn test_summary_lists_never_executed_words() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3000, 0x1021); // ADD R0, R0, #1
+        vm.mem_write(0x3001, 0xF025); // HALT
+        let mut coverage = Coverage::new();
+        coverage.record(0x3001, 0xF025, 0);
+
+        let symbols = SymbolTable::parse("MAIN x3000\n").unwrap();
+
+        let summary = coverage.summary(&vm.memory, &[image(0x3000, 2)], None, Some(&symbols));
+
+        assert!(summary.contains("1/2 instructions executed (50.0%)"));
+        assert!(summary.contains("x3000  MAIN: ADD R0, R0, #1"));
+        assert!(!summary.contains("HALT"));
+    }
+
+    #[test]
+    fn test_summary_leaves_out_data() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3000, 0b0010_000_000000010); // LD R0, x3003
+        vm.mem_write(0x3001, 0b0000_010_000000000); // BRz #0
+        vm.mem_write(0x3002, 0xF025); // HALT
+        vm.mem_write(0x3003, 0x0048); // .FILL x48
+        vm.mem_write(0x3004, 0x0000); // .BLKW 1
+        let mut map = SourceMap::parse(
+            "x3000 main.asm:1\nx3001 main.asm:2\nx3002 main.asm:3\nx3003 main.asm:4\n",
+        )
+        .unwrap();
+        map.reserve(0x3004);
+        let mut coverage = Coverage::new();
+        coverage.record(0x3000, 0b0010_000_000000010, 0);
+
+        let summary = coverage.summary(&vm.memory, &[image(0x3000, 5)], Some(&map), None);
+
+        assert!(summary.contains("1/3 instructions executed"), "{summary}");
+        assert!(summary.contains("x3002  HALT"), "{summary}");
+        assert!(!summary.contains("x3003"), "{summary}");
+        assert!(!summary.contains("x3004"), "{summary}");
+    }
+}
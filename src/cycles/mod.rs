@@ -0,0 +1,239 @@
+use crate::cycles::states::{execution_path, Phase, State, FETCH_AND_DECODE, PHASES};
+use crate::instructions::opcodes::Opcode;
+use std::fmt::Write;
+
+pub mod states;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OpcodeCycles {
+    pub count: u64,
+    pub cycles: u64,
+    pub memory_accesses: u64,
+}
+
+/// Charges every executed instruction for the states it visits in the LC-3
+/// control state machine. Each state costs one cycle, and states that access
+/// memory additionally wait `memory_latency` cycles.
+pub struct CycleModel {
+    memory_latency: u32,
+    per_opcode: [OpcodeCycles; 16],
+    per_phase: [u64; 6],
+}
+
+impl CycleModel {
+    pub fn new(memory_latency: u32) -> CycleModel {
+        Self {
+            memory_latency,
+            per_opcode: [OpcodeCycles::default(); 16],
+            per_phase: [0; 6],
+        }
+    }
+
+    /// Charges one instruction. `cond` is the condition codes before it runs,
+    /// which decide whether a `BR` takes the extra state.
+    pub fn charge(&mut self, opcode: Opcode, instruction: u16, cond: u16) -> u64 {
+        let branch_taken = (instruction >> 9) & 0x7 & cond != 0;
+        let path = execution_path(opcode, instruction, branch_taken);
+
+        let mut cycles = 0;
+        let mut memory_accesses = 0;
+        for state in FETCH_AND_DECODE.iter().chain(path) {
+            let state_cycles = self.state_cycles(state);
+            self.per_phase[state.phase as usize] += state_cycles;
+            cycles += state_cycles;
+            memory_accesses += state.accesses_memory as u64;
+        }
+
+        let stats = &mut self.per_opcode[opcode as usize];
+        stats.count += 1;
+        stats.cycles += cycles;
+        stats.memory_accesses += memory_accesses;
+        cycles
+    }
+
+    fn state_cycles(&self, state: &State) -> u64 {
+        if state.accesses_memory {
+            1 + self.memory_latency as u64
+        } else {
+            1
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.per_opcode.iter().map(|stats| stats.count).sum()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.per_opcode.iter().map(|stats| stats.cycles).sum()
+    }
+
+    pub fn cpi(&self) -> f64 {
+        match self.instructions() {
+            0 => 0.0,
+            instructions => self.cycles() as f64 / instructions as f64,
+        }
+    }
+
+    pub fn opcode(&self, opcode: Opcode) -> OpcodeCycles {
+        self.per_opcode[opcode as usize]
+    }
+
+    pub fn phase(&self, phase: Phase) -> u64 {
+        self.per_phase[phase as usize]
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let total_cycles = self.cycles().max(1) as f64;
+
+        writeln!(report, "--- Cycle accounting ---").unwrap();
+        writeln!(
+            report,
+            "Memory wait cycles per access: {}",
+            self.memory_latency
+        )
+        .unwrap();
+        writeln!(report, "Instructions: {}", self.instructions()).unwrap();
+        writeln!(report, "Cycles:       {}", self.cycles()).unwrap();
+        writeln!(report, "CPI:          {:.2}", self.cpi()).unwrap();
+        writeln!(report).unwrap();
+        writeln!(
+            report,
+            "{:<6} {:>10} {:>12} {:>8} {:>8}",
+            "Opcode", "Count", "Cycles", "CPI", "Share"
+        )
+        .unwrap();
+
+        for opcode in (0..16).filter_map(Opcode::get) {
+            let stats = self.opcode(opcode);
+            if stats.count == 0 {
+                continue;
+            }
+            writeln!(
+                report,
+                "{:<6} {:>10} {:>12} {:>8.2} {:>7.1}%",
+                opcode.name(),
+                stats.count,
+                stats.cycles,
+                stats.cycles as f64 / stats.count as f64,
+                stats.cycles as f64 * 100.0 / total_cycles
+            )
+            .unwrap();
+        }
+
+        writeln!(report).unwrap();
+        writeln!(report, "{:<17} {:>12} {:>8}", "Phase", "Cycles", "Share").unwrap();
+        for phase in PHASES {
+            writeln!(
+                report,
+                "{:<17} {:>12} {:>7.1}%",
+                phase.name(),
+                self.phase(phase),
+                self.phase(phase) as f64 * 100.0 / total_cycles
+            )
+            .unwrap();
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cycles::states::Phase;
+    use crate::cycles::CycleModel;
+    use crate::instructions::opcodes::Opcode;
+
+    // ========== Per-Instruction Costs ==========
+
+    #[test]
+    fn test_add_costs_fetch_decode_and_one_state() {
+        let mut model = CycleModel::new(0);
+
+        // ADD R1, R1, #1
+        assert_eq!(model.charge(Opcode::Add, 0x1261, 0), 5);
+    }
+
+    #[test]
+    fn test_memory_latency_is_added_per_access() {
+        let mut model = CycleModel::new(3);
+
+        // ADD: one memory access (fetch)
+        assert_eq!(model.charge(Opcode::Add, 0x1261, 0), 5 + 3);
+        // LD: fetch plus operand read
+        assert_eq!(model.charge(Opcode::Ld, 0x2001, 0), 7 + 6);
+        // LDI: fetch plus two reads
+        assert_eq!(model.charge(Opcode::Ldi, 0xA001, 0), 9 + 9);
+    }
+
+    #[test]
+    fn test_taken_branch_costs_an_extra_state() {
+        let mut model = CycleModel::new(0);
+
+        // BRz #1 with Z clear, then Z set
+        assert_eq!(model.charge(Opcode::Br, 0b0000_010_000000001, 0b001), 5);
+        assert_eq!(model.charge(Opcode::Br, 0b0000_010_000000001, 0b010), 6);
+    }
+
+    #[test]
+    fn test_store_costs() {
+        let mut model = CycleModel::new(1);
+
+        assert_eq!(model.charge(Opcode::St, 0x3001, 0), 7 + 2);
+        assert_eq!(model.charge(Opcode::Str, 0x7040, 0), 7 + 2);
+        assert_eq!(model.charge(Opcode::Sti, 0xB001, 0), 9 + 3);
+    }
+
+    // ========== Totals ==========
+
+    #[test]
+    fn test_totals_and_cpi() {
+        let mut model = CycleModel::new(0);
+
+        model.charge(Opcode::Add, 0x1261, 0);
+        model.charge(Opcode::Ld, 0x2001, 0);
+        model.charge(Opcode::Ld, 0x2001, 0);
+
+        assert_eq!(model.instructions(), 3);
+        assert_eq!(model.cycles(), 5 + 7 + 7);
+        assert!((model.cpi() - 19.0 / 3.0).abs() < 1e-9);
+        assert_eq!(model.opcode(Opcode::Ld).count, 2);
+        assert_eq!(model.opcode(Opcode::Ld).cycles, 14);
+        assert_eq!(model.opcode(Opcode::Ld).memory_accesses, 4);
+    }
+
+    #[test]
+    fn test_phase_breakdown_sums_to_total() {
+        let mut model = CycleModel::new(2);
+
+        model.charge(Opcode::Ldi, 0xA001, 0);
+        model.charge(Opcode::Trap, 0xF025, 0);
+
+        let sum: u64 = [
+            Phase::Fetch,
+            Phase::Decode,
+            Phase::EvaluateAddress,
+            Phase::FetchOperands,
+            Phase::Execute,
+            Phase::Store,
+        ]
+        .iter()
+        .map(|&phase| model.phase(phase))
+        .sum();
+        assert_eq!(sum, model.cycles());
+        assert_eq!(model.phase(Phase::Decode), 2);
+    }
+
+    #[test]
+    fn test_report_lists_only_executed_opcodes() {
+        let mut model = CycleModel::new(0);
+        model.charge(Opcode::Add, 0x1261, 0);
+
+        let report = model.report();
+
+        assert!(report.contains("Instructions: 1"));
+        assert!(report.contains("CPI:          5.00"));
+        assert!(report.contains("ADD"));
+        assert!(!report.contains("LDI"));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::instructions::opcodes::Opcode;
+
+/// The phases of the instruction cycle as described by Patt & Patel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Fetch = 0,
+    Decode = 1,
+    EvaluateAddress = 2,
+    FetchOperands = 3,
+    Execute = 4,
+    Store = 5,
+}
+
+pub const PHASES: [Phase; 6] = [
+    Phase::Fetch,
+    Phase::Decode,
+    Phase::EvaluateAddress,
+    Phase::FetchOperands,
+    Phase::Execute,
+    Phase::Store,
+];
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Fetch => "fetch",
+            Phase::Decode => "decode",
+            Phase::EvaluateAddress => "evaluate address",
+            Phase::FetchOperands => "fetch operands",
+            Phase::Execute => "execute",
+            Phase::Store => "store",
+        }
+    }
+}
+
+/// One state of the LC-3 control state machine. States that access memory
+/// stay in the state until memory is ready, so they pay the wait cycles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct State {
+    pub number: u8,
+    pub phase: Phase,
+    pub accesses_memory: bool,
+}
+
+const fn state(number: u8, phase: Phase) -> State {
+    State {
+        number,
+        phase,
+        accesses_memory: false,
+    }
+}
+
+const fn memory_state(number: u8, phase: Phase) -> State {
+    State {
+        number,
+        phase,
+        accesses_memory: true,
+    }
+}
+
+/// States 18, 33, 35 and 32: MAR <- PC, MDR <- M[MAR], IR <- MDR, decode.
+pub const FETCH_AND_DECODE: [State; 4] = [
+    state(18, Phase::Fetch),
+    memory_state(33, Phase::Fetch),
+    state(35, Phase::Fetch),
+    state(32, Phase::Decode),
+];
+
+const ADD: [State; 1] = [state(1, Phase::Execute)];
+const AND: [State; 1] = [state(5, Phase::Execute)];
+const NOT: [State; 1] = [state(9, Phase::Execute)];
+const BR_NOT_TAKEN: [State; 1] = [state(0, Phase::Execute)];
+const BR_TAKEN: [State; 2] = [state(0, Phase::Execute), state(22, Phase::Execute)];
+const JMP: [State; 1] = [state(12, Phase::Execute)];
+const JSR: [State; 2] = [state(4, Phase::Execute), state(21, Phase::Execute)];
+const JSRR: [State; 2] = [state(4, Phase::Execute), state(20, Phase::Execute)];
+const LEA: [State; 1] = [state(14, Phase::Execute)];
+const LD: [State; 3] = [
+    state(2, Phase::EvaluateAddress),
+    memory_state(25, Phase::FetchOperands),
+    state(27, Phase::Store),
+];
+const LDR: [State; 3] = [
+    state(6, Phase::EvaluateAddress),
+    memory_state(25, Phase::FetchOperands),
+    state(27, Phase::Store),
+];
+const LDI: [State; 5] = [
+    state(10, Phase::EvaluateAddress),
+    memory_state(24, Phase::EvaluateAddress),
+    state(26, Phase::EvaluateAddress),
+    memory_state(25, Phase::FetchOperands),
+    state(27, Phase::Store),
+];
+const ST: [State; 3] = [
+    state(3, Phase::EvaluateAddress),
+    state(23, Phase::FetchOperands),
+    memory_state(16, Phase::Store),
+];
+const STR: [State; 3] = [
+    state(7, Phase::EvaluateAddress),
+    state(23, Phase::FetchOperands),
+    memory_state(16, Phase::Store),
+];
+const STI: [State; 5] = [
+    state(11, Phase::EvaluateAddress),
+    memory_state(29, Phase::EvaluateAddress),
+    state(31, Phase::EvaluateAddress),
+    state(23, Phase::FetchOperands),
+    memory_state(16, Phase::Store),
+];
+const TRAP: [State; 3] = [
+    state(15, Phase::EvaluateAddress),
+    memory_state(28, Phase::FetchOperands),
+    state(30, Phase::Execute),
+];
+const RTI: [State; 1] = [state(8, Phase::Execute)];
+const RES: [State; 1] = [state(13, Phase::Execute)];
+
+/// The states an instruction visits after decode. Traps are charged for the
+/// TRAP instruction itself only, since the routines run natively.
+pub fn execution_path(opcode: Opcode, instruction: u16, branch_taken: bool) -> &'static [State] {
+    match opcode {
+        Opcode::Add => &ADD,
+        Opcode::And => &AND,
+        Opcode::Not => &NOT,
+        Opcode::Br if branch_taken => &BR_TAKEN,
+        Opcode::Br => &BR_NOT_TAKEN,
+        Opcode::Jmp => &JMP,
+        Opcode::Jsr if (instruction >> 11) & 0x1 == 1 => &JSR,
+        Opcode::Jsr => &JSRR,
+        Opcode::Lea => &LEA,
+        Opcode::Ld => &LD,
+        Opcode::Ldr => &LDR,
+        Opcode::Ldi => &LDI,
+        Opcode::St => &ST,
+        Opcode::Str => &STR,
+        Opcode::Sti => &STI,
+        Opcode::Trap => &TRAP,
+        Opcode::Rti => &RTI,
+        Opcode::Res => &RES,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cycles::states::{execution_path, FETCH_AND_DECODE};
+    use crate::instructions::opcodes::Opcode;
+
+    fn numbers(opcode: Opcode, instruction: u16, taken: bool) -> Vec<u8> {
+        execution_path(opcode, instruction, taken)
+            .iter()
+            .map(|state| state.number)
+            .collect()
+    }
+
+    #[test]
+    fn test_fetch_visits_states_18_33_35_32() {
+        let states: Vec<u8> = FETCH_AND_DECODE.iter().map(|s| s.number).collect();
+
+        assert_eq!(states, vec![18, 33, 35, 32]);
+    }
+
+    #[test]
+    fn test_branch_path_depends_on_ben() {
+        assert_eq!(numbers(Opcode::Br, 0x0E01, false), vec![0]);
+        assert_eq!(numbers(Opcode::Br, 0x0E01, true), vec![0, 22]);
+    }
+
+    #[test]
+    fn test_jsr_and_jsrr_take_different_states() {
+        assert_eq!(
+            numbers(Opcode::Jsr, 0b0100_1_00000000001, false),
+            vec![4, 21]
+        );
+        assert_eq!(
+            numbers(Opcode::Jsr, 0b0100_0_00_010_000000, false),
+            vec![4, 20]
+        );
+    }
+
+    #[test]
+    fn test_indirect_accesses_touch_memory_twice() {
+        let ldi = execution_path(Opcode::Ldi, 0xA000, false);
+        let sti = execution_path(Opcode::Sti, 0xB000, false);
+
+        assert_eq!(ldi.iter().filter(|s| s.accesses_memory).count(), 2);
+        assert_eq!(sti.iter().filter(|s| s.accesses_memory).count(), 2);
+    }
+}
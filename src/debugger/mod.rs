@@ -0,0 +1,485 @@
+use crate::asm::lexer::parse_number;
+use crate::instructions::disassemble::disassemble;
+use crate::limits::{run_with, StopReason};
+use crate::registers::register::Register;
+use crate::registers::ConditionFlag;
+use crate::symbols::source_map::SourceMap;
+use crate::symbols::symbol_table::SymbolTable;
+use crate::Vm;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+step [n]          run n instructions (s)
+continue          run to a breakpoint or the end (c)
+break [addr]      set a breakpoint, or list them (b)
+delete addr       remove a breakpoint (d)
+regs              show the registers (r)
+mem addr [n]      show n words of memory (m)
+dis [addr] [n]    disassemble n words (default: at the PC)
+set reg|addr val  change a register or memory word
+quit              leave the debugger (q)
+An empty line repeats the last command.
+";
+
+/// One word as an address, hex value and disassembly.
+pub fn instruction_line(address: u16, word: u16) -> String {
+    format!(
+        "x{:04X}  {:04X}  {}",
+        address,
+        word,
+        disassemble(address, word)
+    )
+}
+
+/// The general purpose registers and condition codes on one line.
+pub fn registers_line(vm: &Vm) -> String {
+    let mut line = String::new();
+    for register in 0..8 {
+        write!(line, "R{}={:04X} ", register, vm.registers[register]).unwrap();
+    }
+    let cond = vm.registers[Register::Cond as usize];
+    let flag = if cond == ConditionFlag::Neg as u16 {
+        'N'
+    } else if cond == ConditionFlag::Zro as u16 {
+        'Z'
+    } else if cond == ConditionFlag::Pos as u16 {
+        'P'
+    } else {
+        '-'
+    };
+    write!(line, "CC={}", flag).unwrap();
+    line
+}
+
+/// The instruction about to run at the PC, followed by the registers.
+pub fn trace_line(vm: &Vm) -> String {
+    let pc = vm.registers[Register::Pc as usize];
+    format!(
+        "{:<36}{}",
+        instruction_line(pc, vm.memory[pc as usize]),
+        registers_line(vm)
+    )
+}
+
+/// Memory from `start` up to `end` (exclusive) in rows of eight words.
+pub fn hex_dump(memory: &[u16], start: u16, end: u32) -> String {
+    let mut out = String::new();
+    let mut row = start as u32;
+    while row < end {
+        write!(out, "x{:04X} ", row).unwrap();
+        for address in row..(row + 8).min(end) {
+            write!(out, " {:04X}", memory[address as usize]).unwrap();
+        }
+        out.push('\n');
+        row += 8;
+    }
+    out
+}
+
+/// An interactive command-line debugger. Commands are read line by line, so
+/// sessions can be scripted from a file as well as typed.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    symbols: SymbolTable,
+    source_map: SourceMap,
+    halted: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Self::default()
+    }
+
+    /// Lets commands name addresses by label.
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Debugger {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Names the source line of the PC whenever the machine stops.
+    pub fn with_source_map(mut self, source_map: SourceMap) -> Debugger {
+        self.source_map = source_map;
+        self
+    }
+
+    /// The trace line for the PC, followed by its source line if known.
+    fn trace(&self, vm: &Vm) -> String {
+        let pc = vm.registers[Register::Pc as usize];
+        match self.source_map.lookup(pc) {
+            Some(location) => format!("{}  {}", trace_line(vm), location),
+            None => trace_line(vm),
+        }
+    }
+
+    /// Reads commands from `input` until `quit` or the end of the input.
+    pub fn run(
+        &mut self,
+        vm: &mut Vm,
+        input: &mut impl BufRead,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        writeln!(out, "{}", self.trace(vm))?;
+        loop {
+            write!(out, "(lc3) ")?;
+            out.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            let mut command = line.trim().to_string();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            self.last_command = command.clone();
+
+            match self.command(vm, &command) {
+                Some(response) => write!(out, "{}", response)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Runs one command and returns what to print, or `None` on `quit`.
+    pub fn command(&mut self, vm: &mut Vm, command: &str) -> Option<String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let Some((&name, arguments)) = words.split_first() else {
+            return Some(String::new());
+        };
+
+        let response = match name {
+            "s" | "step" => self.step(vm, arguments),
+            "c" | "continue" => self.resume(vm),
+            "b" | "break" => self.set_breakpoint(arguments),
+            "d" | "delete" => self.delete_breakpoint(arguments),
+            "r" | "regs" => Ok(format!(
+                "PC=x{:04X} {}\n",
+                vm.registers[Register::Pc as usize],
+                registers_line(vm)
+            )),
+            "m" | "mem" => self.memory(vm, arguments),
+            "dis" => self.disassemble(vm, arguments),
+            "set" => self.set(vm, arguments),
+            "h" | "help" => Ok(HELP.to_string()),
+            "q" | "quit" => return None,
+            _ => Err(format!("unknown command '{}', try 'help'", name)),
+        };
+        Some(response.unwrap_or_else(|message| format!("{}\n", message)))
+    }
+
+    fn address(&self, text: &str) -> Result<u16, String> {
+        if let Some(address) = self.symbols.address_of(text) {
+            return Ok(address);
+        }
+        match parse_number(text) {
+            Some(value) if (0..=0xFFFF).contains(&value) => Ok(value as u16),
+            _ => Err(format!("'{}' is not an address or known label", text)),
+        }
+    }
+
+    fn count(text: Option<&&str>, default: u32) -> Result<u32, String> {
+        match text {
+            None => Ok(default),
+            Some(text) => match parse_number(text) {
+                Some(count) if count > 0 => Ok(count as u32),
+                _ => Err(format!("'{}' is not a positive count", text)),
+            },
+        }
+    }
+
+    fn step(&mut self, vm: &mut Vm, arguments: &[&str]) -> Result<String, String> {
+        if self.halted {
+            return Err("the program has halted".to_string());
+        }
+        let count = Self::count(arguments.first(), 1)?;
+        let mut out = String::new();
+        for _ in 0..count {
+            if !vm.fetch_decode_execute() {
+                self.halted = true;
+                match vm.stop_reason.take() {
+                    Some(reason) => writeln!(out, "{}", reason).unwrap(),
+                    None => out.push_str("halted\n"),
+                }
+                return Ok(out);
+            }
+            writeln!(out, "{}", self.trace(vm)).unwrap();
+        }
+        Ok(out)
+    }
+
+    fn resume(&mut self, vm: &mut Vm) -> Result<String, String> {
+        if self.halted {
+            return Err("the program has halted".to_string());
+        }
+        // The breakpoint the machine is sitting on should not stop it again
+        let mut first = true;
+        let breakpoints = &self.breakpoints;
+        let reason = run_with(vm, |vm| {
+            let pc = vm.registers[Register::Pc as usize];
+            let hit = !first && breakpoints.contains(&pc);
+            first = false;
+            hit.then_some(StopReason::Breakpoint(pc))
+        });
+
+        match reason {
+            StopReason::Halted => {
+                self.halted = true;
+                Ok("halted\n".to_string())
+            }
+            reason @ (StopReason::UnknownTrap(_)
+            | StopReason::InputExhausted
+            | StopReason::Uninitialized { .. }
+            | StopReason::SelfModifying { .. }) => {
+                self.halted = true;
+                Ok(format!("{}\n", reason))
+            }
+            reason => Ok(format!("{}\n{}\n", reason, self.trace(vm))),
+        }
+    }
+
+    fn set_breakpoint(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let Some(text) = arguments.first() else {
+            let mut out = String::new();
+            for address in &self.breakpoints {
+                writeln!(out, "x{:04X}", address).unwrap();
+            }
+            if out.is_empty() {
+                out.push_str("no breakpoints\n");
+            }
+            return Ok(out);
+        };
+        let address = self.address(text)?;
+        self.breakpoints.insert(address);
+        Ok(format!("breakpoint at x{:04X}\n", address))
+    }
+
+    fn delete_breakpoint(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let text = arguments.first().ok_or("delete expects an address")?;
+        let address = self.address(text)?;
+        if !self.breakpoints.remove(&address) {
+            return Err(format!("no breakpoint at x{:04X}", address));
+        }
+        Ok(format!("deleted x{:04X}\n", address))
+    }
+
+    fn memory(&self, vm: &Vm, arguments: &[&str]) -> Result<String, String> {
+        let text = arguments.first().ok_or("mem expects an address")?;
+        let start = self.address(text)?;
+        let count = Self::count(arguments.get(1), 8)?;
+        let end = (start as u32 + count).min(0x10000);
+        Ok(hex_dump(&vm.memory, start, end))
+    }
+
+    fn disassemble(&self, vm: &Vm, arguments: &[&str]) -> Result<String, String> {
+        let start = match arguments.first() {
+            Some(text) => self.address(text)?,
+            None => vm.registers[Register::Pc as usize],
+        };
+        let count = Self::count(arguments.get(1), 8)?;
+        let mut out = String::new();
+        for address in start as u32..(start as u32 + count).min(0x10000) {
+            let address = address as u16;
+            if let Some(label) = self.symbols.label_at(address) {
+                writeln!(out, "{}:", label).unwrap();
+            }
+            writeln!(
+                out,
+                "{}",
+                instruction_line(address, vm.memory[address as usize])
+            )
+            .unwrap();
+        }
+        Ok(out)
+    }
+
+    fn set(&mut self, vm: &mut Vm, arguments: &[&str]) -> Result<String, String> {
+        let [target, value] = arguments else {
+            return Err("set expects a register or address and a value".to_string());
+        };
+        let value = match parse_number(value) {
+            Some(value) if (-0x8000..=0xFFFF).contains(&value) => value as u16,
+            _ => return Err(format!("'{}' is not a 16-bit value", value)),
+        };
+
+        let upper = target.to_ascii_uppercase();
+        let register = match upper.as_str() {
+            "PC" => Some(Register::Pc as usize),
+            _ => upper
+                .strip_prefix('R')
+                .and_then(|number| number.parse::<usize>().ok())
+                .filter(|&number| number < 8),
+        };
+        match register {
+            Some(register) => {
+                vm.registers[register] = value;
+                if register == Register::Pc as usize {
+                    self.halted = false;
+                }
+            }
+            None => vm.mem_write(self.address(target)?, value),
+        }
+        Ok(String::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger::{hex_dump, trace_line, Debugger};
+    use crate::registers::register::Register;
+    use crate::symbols::source_map::SourceMap;
+    use crate::symbols::symbol_table::SymbolTable;
+    use crate::test_support::vm_with;
+    use std::io::Cursor;
+
+    const COUNT_UP: [u16; 4] = [
+        0b0001_001_001_1_00001, // ADD R1, R1, #1
+        0b0001_001_001_1_00001, // ADD R1, R1, #1
+        0b0001_001_001_1_00001, // ADD R1, R1, #1
+        0xF025,                 // HALT
+    ];
+
+    // ========== Formatting ==========
+
+    #[test]
+    fn test_trace_line_shows_instruction_and_registers() {
+        let mut vm = vm_with(&COUNT_UP);
+        vm.write_to_register(Register::R1, 0x12);
+        vm.write_to_register(Register::Cond, 1);
+
+        assert_eq!(
+            trace_line(&vm),
+            "x3000  1261  ADD R1, R1, #1         R0=0000 R1=0012 R2=0000 R3=0000 \
+             R4=0000 R5=0000 R6=0000 R7=0000 CC=P"
+        );
+    }
+
+    #[test]
+    fn test_hex_dump_rows_of_eight() {
+        let vm = vm_with(&COUNT_UP);
+
+        assert_eq!(
+            hex_dump(&vm.memory, 0x3000, 0x300A),
+            "x3000  1261 1261 1261 F025 0000 0000 0000 0000\nx3008  0000 0000\n"
+        );
+    }
+
+    // ========== Commands ==========
+
+    #[test]
+    fn test_step_runs_and_reports_halt() {
+        let mut vm = vm_with(&COUNT_UP);
+        let mut debugger = Debugger::new();
+
+        let out = debugger.command(&mut vm, "step 2").unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.starts_with("x3001"));
+        assert_eq!(vm.registers[Register::R1 as usize], 2);
+
+        assert_eq!(
+            debugger.command(&mut vm, "s 5").unwrap().lines().last(),
+            Some("halted")
+        );
+        assert_eq!(
+            debugger.command(&mut vm, "s").unwrap(),
+            "the program has halted\n"
+        );
+    }
+
+    #[test]
+    fn test_continue_stops_at_breakpoints_then_resumes() {
+        let mut vm = vm_with(&COUNT_UP);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("THIRD", 0x3002);
+        let mut debugger = Debugger::new().with_symbols(symbols);
+
+        assert_eq!(
+            debugger.command(&mut vm, "b THIRD").unwrap(),
+            "breakpoint at x3002\n"
+        );
+        let out = debugger.command(&mut vm, "c").unwrap();
+        assert!(out.starts_with("breakpoint at x3002\n"));
+        assert_eq!(vm.registers[Register::R1 as usize], 2);
+
+        assert_eq!(debugger.command(&mut vm, "c").unwrap(), "halted\n");
+        assert_eq!(vm.registers[Register::R1 as usize], 3);
+    }
+
+    #[test]
+    fn test_stops_name_the_source_line() {
+        let mut vm = vm_with(&COUNT_UP);
+        let map = SourceMap::parse("x3001 count.asm:5\n").unwrap();
+        let mut debugger = Debugger::new().with_source_map(map);
+
+        let out = debugger.command(&mut vm, "s").unwrap();
+        assert!(out.starts_with("x3001  1261"), "{}", out);
+        assert!(out.ends_with("CC=P  count.asm:5\n"), "{}", out);
+        let out = debugger.command(&mut vm, "s").unwrap();
+        assert!(out.ends_with("CC=P\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_unknown_trap_ends_the_program() {
+        let mut vm = vm_with(&[0xF030]); // TRAP x30
+        let mut debugger = Debugger::new();
+
+        assert_eq!(
+            debugger.command(&mut vm, "s").unwrap(),
+            "unknown trap x30\n"
+        );
+        assert_eq!(
+            debugger.command(&mut vm, "c").unwrap(),
+            "the program has halted\n"
+        );
+
+        let mut vm = vm_with(&[0xF030]);
+        assert_eq!(
+            Debugger::new().command(&mut vm, "c").unwrap(),
+            "unknown trap x30\n"
+        );
+    }
+
+    #[test]
+    fn test_set_register_and_memory() {
+        let mut vm = vm_with(&COUNT_UP);
+        let mut debugger = Debugger::new();
+
+        debugger.command(&mut vm, "set r3 x00FF").unwrap();
+        debugger.command(&mut vm, "set x4000 #-1").unwrap();
+
+        assert_eq!(vm.registers[Register::R3 as usize], 0x00FF);
+        assert_eq!(vm.memory[0x4000], 0xFFFF);
+        assert_eq!(
+            debugger.command(&mut vm, "set r9 1").unwrap(),
+            "'r9' is not an address or known label\n"
+        );
+    }
+
+    #[test]
+    fn test_scripted_session_repeats_empty_lines_and_quits() {
+        let mut vm = vm_with(&COUNT_UP);
+        let mut input = Cursor::new("step\n\nregs\nquit\nstep\n");
+        let mut out = Vec::new();
+
+        Debugger::new().run(&mut vm, &mut input, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(vm.registers[Register::R1 as usize], 2);
+        assert!(out.contains("PC=x3002 R0=0000 R1=0002"));
+        assert_eq!(out.matches("(lc3) ").count(), 4);
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let mut vm = vm_with(&COUNT_UP);
+
+        assert_eq!(
+            Debugger::new().command(&mut vm, "jump").unwrap(),
+            "unknown command 'jump', try 'help'\n"
+        );
+    }
+}
@@ -0,0 +1,810 @@
+use crate::instructions::trap::{TRAP_GETC, TRAP_HALT, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP};
+use crate::predecode::decoded::{Decoded, Operation};
+use std::fmt;
+
+/// A word of a standard stack frame, addressed from R5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    /// `R5 - n`.
+    Local(u16),
+    /// The caller's R5, at `R5 + 1`.
+    SavedFrame,
+    /// R7, at `R5 + 2`.
+    ReturnAddress,
+    /// The return value, at `R5 + 3`.
+    Result,
+    /// `R5 + 4 + n`: the first argument pushed last.
+    Parameter(u16),
+}
+
+impl Slot {
+    fn at(offset: i16) -> Slot {
+        match offset {
+            ..=0 => Slot::Local(offset.unsigned_abs()),
+            1 => Slot::SavedFrame,
+            2 => Slot::ReturnAddress,
+            3 => Slot::Result,
+            _ => Slot::Parameter(offset as u16 - 4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Register(u8),
+    Constant(i16),
+    /// The word at a label, by name.
+    Global(String),
+    /// The address of a label, as `LEA` gives.
+    AddressOf(String),
+    /// The word an expression points at.
+    Deref(Box<Expr>),
+    /// `base[offset]`.
+    Index(Box<Expr>, i16),
+    Frame(Slot),
+    Add(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    /// What a call left on the stack.
+    Call(String, Vec<Expr>),
+    /// The character `GETC` or `IN` reads.
+    Input(u16),
+}
+
+fn boxed(left: Expr, right: Expr) -> (Box<Expr>, Box<Expr>) {
+    (Box::new(left), Box::new(right))
+}
+
+impl Expr {
+    fn is_register(&self, register: u8) -> bool {
+        *self == Expr::Register(register)
+    }
+
+    fn uses(&self, register: u8) -> bool {
+        match self {
+            Expr::Register(r) => *r == register,
+            Expr::Deref(inner) | Expr::Index(inner, _) | Expr::Not(inner) | Expr::Negate(inner) => {
+                inner.uses(register)
+            }
+            Expr::Add(left, right) | Expr::And(left, right) => {
+                left.uses(register) || right.uses(register)
+            }
+            Expr::Call(_, arguments) => arguments.iter().any(|argument| argument.uses(register)),
+            _ => false,
+        }
+    }
+}
+
+fn trap_name(vector: u16) -> String {
+    match vector {
+        TRAP_GETC => "getc".to_string(),
+        TRAP_OUT => "out".to_string(),
+        TRAP_PUTS => "puts".to_string(),
+        TRAP_IN => "in".to_string(),
+        TRAP_PUTSP => "putsp".to_string(),
+        TRAP_HALT => "halt".to_string(),
+        _ => format!("trap_x{:02X}", vector),
+    }
+}
+
+/// Writes a binary operand, in parentheses unless it is a single term.
+fn operand(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    match expr {
+        Expr::Add(..) | Expr::And(..) => write!(f, "({})", expr),
+        _ => write!(f, "{}", expr),
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Register(register) => write!(f, "R{}", register),
+            Expr::Constant(value) => write!(f, "{}", value),
+            Expr::Global(name) => f.write_str(name),
+            Expr::AddressOf(name) => write!(f, "&{}", name),
+            Expr::Deref(inner) => {
+                f.write_str("*")?;
+                operand(f, inner)
+            }
+            Expr::Index(base, offset) => {
+                operand(f, base)?;
+                write!(f, "[{}]", offset)
+            }
+            Expr::Frame(Slot::Local(index)) => write!(f, "local{}", index),
+            Expr::Frame(Slot::Parameter(index)) => write!(f, "param{}", index),
+            Expr::Frame(Slot::Result) => f.write_str("result"),
+            Expr::Frame(Slot::SavedFrame) => f.write_str("saved_frame"),
+            Expr::Frame(Slot::ReturnAddress) => f.write_str("return_address"),
+            Expr::Add(left, right) => {
+                operand(f, left)?;
+                match &**right {
+                    Expr::Constant(value) if *value < 0 => {
+                        write!(f, " - {}", value.unsigned_abs())
+                    }
+                    Expr::Negate(inner) => {
+                        f.write_str(" - ")?;
+                        operand(f, inner)
+                    }
+                    right => {
+                        f.write_str(" + ")?;
+                        operand(f, right)
+                    }
+                }
+            }
+            Expr::And(left, right) => {
+                operand(f, left)?;
+                f.write_str(" & ")?;
+                operand(f, right)
+            }
+            Expr::Not(inner) => {
+                f.write_str("~")?;
+                operand(f, inner)
+            }
+            Expr::Negate(inner) => {
+                f.write_str("-")?;
+                operand(f, inner)
+            }
+            Expr::Call(name, arguments) => {
+                write!(f, "{}(", name)?;
+                for (index, argument) in arguments.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                f.write_str(")")
+            }
+            Expr::Input(vector) => write!(f, "{}()", trap_name(*vector)),
+        }
+    }
+}
+
+/// A comparison of `left` with `right` on the condition codes in `mask`,
+/// which is the `nzp` field of the branch that tests it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub left: Expr,
+    pub right: Expr,
+    pub mask: u8,
+}
+
+impl Condition {
+    pub fn negate(&self) -> Condition {
+        Condition {
+            mask: self.mask ^ 0b111,
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comparison = match self.mask {
+            0b000 => return f.write_str("0"),
+            0b111 => return f.write_str("1"),
+            0b100 => "<",
+            0b010 => "==",
+            0b001 => ">",
+            0b110 => "<=",
+            0b011 => ">=",
+            _ => "!=",
+        };
+        write!(f, "{} {} {}", self.left, comparison, self.right)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    /// `target = value`, where the target is a register or memory.
+    Assign(Expr, Expr),
+    /// A subroutine call, with its arguments when they went on the stack.
+    Call(String, Vec<Expr>),
+    /// A `JSRR` through a register.
+    CallIndirect(Expr),
+    /// A trap other than `HALT`, with R0 for the ones that write it out.
+    Trap(u16, Option<Expr>),
+    /// `R6 -= 1; R6[0] = value`.
+    Push(Expr),
+    /// `R6 += n`.
+    Pop(u16),
+}
+
+impl Stmt {
+    /// Whether the statement calls a subroutine, which may leave any
+    /// register and the condition codes changed.
+    pub fn is_call(&self) -> bool {
+        matches!(self, Stmt::Call(..) | Stmt::CallIndirect(_))
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Assign(target, Expr::Add(left, right)) if left.as_ref() == target => {
+                match &**right {
+                    Expr::Constant(value) if *value < 0 => {
+                        write!(f, "{} -= {};", target, value.unsigned_abs())
+                    }
+                    Expr::Negate(inner) => write!(f, "{} -= {};", target, inner),
+                    right => write!(f, "{} += {};", target, right),
+                }
+            }
+            Stmt::Assign(target, value) => write!(f, "{} = {};", target, value),
+            Stmt::Call(name, arguments) => {
+                write!(f, "{};", Expr::Call(name.clone(), arguments.clone()))
+            }
+            Stmt::CallIndirect(target) => write!(f, "(*{})();", target),
+            Stmt::Trap(vector, Some(argument)) => {
+                write!(f, "{}({});", trap_name(*vector), argument)
+            }
+            Stmt::Trap(vector, None) => write!(f, "{}();", trap_name(*vector)),
+            Stmt::Push(value) => write!(f, "push({});", value),
+            Stmt::Pop(count) => write!(f, "pop({});", count),
+        }
+    }
+}
+
+/// How a block ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum End {
+    /// Continues at another block, by falling through or an unconditional
+    /// branch.
+    Goto(u16),
+    Branch {
+        condition: Condition,
+        taken: u16,
+        fall: u16,
+    },
+    Return,
+    Halt,
+    /// A `JMP` through a register.
+    Jump(Expr),
+    /// `RTI` or the reserved opcode.
+    Stop,
+}
+
+/// A basic block lifted out of machine code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lifted {
+    pub stmts: Vec<Stmt>,
+    pub end: End,
+    /// The register the condition codes were last set from, if known.
+    pub flags: Option<u8>,
+}
+
+/// Lifts the instructions of one block, `(address, word)` pairs in order.
+/// `name` gives the label, or a stand-in, for an address. A branch's
+/// condition is the register that last set the condition codes, or R0
+/// as a placeholder until `resolve_condition` finds a better one.
+pub fn lift(instructions: &[(u16, u16)], name: impl Fn(u16) -> String) -> Lifted {
+    let mut stmts = Vec::new();
+    let mut flags = None;
+    let mut end = None;
+    for &(address, word) in instructions {
+        let decoded = Decoded::decode(word);
+        let next = address.wrapping_add(1);
+        let target = next.wrapping_add(decoded.operand);
+        let dr = Expr::Register(decoded.dr);
+        let sr = Expr::Register(decoded.sr);
+        let offset = decoded.operand as i16;
+        let assign = |value: Expr| Stmt::Assign(Expr::Register(decoded.dr), value);
+        let stmt = match decoded.operation {
+            Operation::AddRegister => {
+                let (left, right) = boxed(sr, Expr::Register(decoded.operand as u8));
+                assign(Expr::Add(left, right))
+            }
+            Operation::AddImmediate if offset == 0 => assign(sr),
+            Operation::AddImmediate => {
+                let (left, right) = boxed(sr, Expr::Constant(offset));
+                assign(Expr::Add(left, right))
+            }
+            Operation::AndImmediate if offset == 0 => assign(Expr::Constant(0)),
+            Operation::AndImmediate => {
+                let (left, right) = boxed(sr, Expr::Constant(offset));
+                assign(Expr::And(left, right))
+            }
+            Operation::AndRegister => {
+                let (left, right) = boxed(sr, Expr::Register(decoded.operand as u8));
+                assign(Expr::And(left, right))
+            }
+            Operation::Not => assign(Expr::Not(Box::new(sr))),
+            Operation::Ld => assign(Expr::Global(name(target))),
+            Operation::Ldi => assign(Expr::Deref(Box::new(Expr::Global(name(target))))),
+            Operation::Ldr => assign(Expr::Index(Box::new(sr), offset)),
+            Operation::Lea => assign(Expr::AddressOf(name(target))),
+            Operation::St => Stmt::Assign(Expr::Global(name(target)), dr),
+            Operation::Sti => Stmt::Assign(Expr::Deref(Box::new(Expr::Global(name(target)))), dr),
+            Operation::Str => Stmt::Assign(Expr::Index(Box::new(sr), offset), dr),
+            Operation::Jsr => Stmt::Call(name(target), Vec::new()),
+            Operation::Jsrr => Stmt::CallIndirect(sr),
+            Operation::Trap => match word & 0xFF {
+                TRAP_HALT => {
+                    end = Some(End::Halt);
+                    break;
+                }
+                vector @ (TRAP_GETC | TRAP_IN) => {
+                    Stmt::Assign(Expr::Register(0), Expr::Input(vector))
+                }
+                vector @ (TRAP_OUT | TRAP_PUTS | TRAP_PUTSP) => {
+                    Stmt::Trap(vector, Some(Expr::Register(0)))
+                }
+                vector => Stmt::Trap(vector, None),
+            },
+            Operation::Br => {
+                let fall = next;
+                end = Some(match decoded.dr {
+                    0 => End::Goto(fall),
+                    0b111 => End::Goto(target),
+                    mask => End::Branch {
+                        condition: Condition {
+                            left: Expr::Register(flags.unwrap_or(0)),
+                            right: Expr::Constant(0),
+                            mask,
+                        },
+                        taken: target,
+                        fall,
+                    },
+                });
+                break;
+            }
+            Operation::Jmp if decoded.sr == 7 => {
+                end = Some(End::Return);
+                break;
+            }
+            Operation::Jmp => {
+                end = Some(End::Jump(sr));
+                break;
+            }
+            Operation::Stop => {
+                end = Some(End::Stop);
+                break;
+            }
+        };
+        flags = match decoded.operation {
+            Operation::St | Operation::Sti | Operation::Str | Operation::Trap => flags,
+            Operation::Jsr | Operation::Jsrr => None,
+            _ => Some(decoded.dr),
+        };
+        stmts.push(stmt);
+    }
+
+    // A block that ends without a jump continues at the next address
+    let next = instructions.last().map(|&(last, _)| last.wrapping_add(1));
+    let mut lifted = Lifted {
+        stmts,
+        end: end.or(next.map(End::Goto)).unwrap_or(End::Stop),
+        flags,
+    };
+    simplify(&mut lifted.stmts);
+    lifted
+}
+
+/// Folds the idioms LC-3 code spells out one instruction at a time:
+/// `NOT` then `ADD #1` is a negation, and `AND #0` then `ADD` a constant.
+fn simplify(stmts: &mut Vec<Stmt>) {
+    let mut index = 0;
+    while index + 1 < stmts.len() {
+        let folded = match (&stmts[index], &stmts[index + 1]) {
+            (Stmt::Assign(first, value), Stmt::Assign(second, Expr::Add(left, right)))
+                if first == second
+                    && matches!(first, Expr::Register(_))
+                    && left.as_ref() == first =>
+            {
+                match (value, &**right) {
+                    (Expr::Not(inner), Expr::Constant(1)) => Some(Expr::Negate(inner.clone())),
+                    (Expr::Constant(a), Expr::Constant(b)) => {
+                        Some(Expr::Constant(a.wrapping_add(*b)))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match folded {
+            Some(value) => {
+                // Both write the same place, so the second takes the result
+                stmts.remove(index);
+                if let Stmt::Assign(_, old) = &mut stmts[index] {
+                    *old = value;
+                }
+            }
+            None => index += 1,
+        }
+    }
+}
+
+/// Turns what a block knows about the condition codes into an explicit
+/// comparison. `flags` is the register that set them, and `stmts` the
+/// block's statements, which may show it to be a difference: `R2 = R0 - R1`
+/// tested for zero is `R0 == R1`, and `R2 = R0 - 5` is `R0 == 5`.
+pub fn resolve_condition(stmts: &[Stmt], flags: Option<u8>, mask: u8) -> Condition {
+    let Some(register) = flags else {
+        return Condition {
+            left: Expr::Global("flags".to_string()),
+            right: Expr::Constant(0),
+            mask,
+        };
+    };
+    let plain = Condition {
+        left: Expr::Register(register),
+        right: Expr::Constant(0),
+        mask,
+    };
+    // A call may change any register, so nothing before one is known after it
+    let definition = |register: u8, before: usize| {
+        stmts[..before]
+            .iter()
+            .rev()
+            .take_while(|stmt| !stmt.is_call())
+            .find_map(|stmt| match stmt {
+                Stmt::Assign(target, value) if target.is_register(register) => Some(value.clone()),
+                _ => None,
+            })
+    };
+    let Some(setter) = stmts
+        .iter()
+        .rposition(|stmt| matches!(stmt, Stmt::Assign(target, _) if target.is_register(register)))
+    else {
+        return plain;
+    };
+    let Stmt::Assign(_, Expr::Add(left, right)) = &stmts[setter] else {
+        return plain;
+    };
+    // The operands must still hold what they held when the sum was taken
+    let unchanged = |expr: &Expr| {
+        !stmts[setter + 1..].iter().any(|stmt| match stmt {
+            Stmt::Assign(Expr::Register(r), _) => expr.uses(*r),
+            stmt => stmt.is_call(),
+        })
+    };
+    let right = match &**right {
+        Expr::Register(r) => definition(*r, setter).unwrap_or(Expr::Register(*r)),
+        right => right.clone(),
+    };
+    let left = (**left).clone();
+    if left.uses(register) || !unchanged(&left) {
+        return plain;
+    }
+    match right {
+        Expr::Negate(subtrahend) if unchanged(&subtrahend) => Condition {
+            left,
+            right: *subtrahend,
+            mask,
+        },
+        Expr::Constant(value) if value < 0 => Condition {
+            left,
+            right: Expr::Constant(value.wrapping_neg()),
+            mask,
+        },
+        _ => plain,
+    }
+}
+
+/// Rewrites the stack operations of the standard calling convention:
+/// pushes and pops on R6, and a call whose arguments were pushed, last
+/// first, and whose result is read off the stack.
+pub fn fold_stack(stmts: &mut Vec<Stmt>) {
+    let stack = Expr::Register(6);
+    let mut folded = Vec::new();
+    let mut index = 0;
+    while index < stmts.len() {
+        match (&stmts[index], stmts.get(index + 1)) {
+            (
+                Stmt::Assign(target, Expr::Add(left, right)),
+                Some(Stmt::Assign(Expr::Index(base, 0), value)),
+            ) if *target == stack
+                && **left == stack
+                && **right == Expr::Constant(-1)
+                && **base == stack =>
+            {
+                folded.push(Stmt::Push(value.clone()));
+                index += 2;
+            }
+            (Stmt::Assign(target, Expr::Add(left, right)), _)
+                if *target == stack && **left == stack =>
+            {
+                match **right {
+                    Expr::Constant(count) if count > 0 => folded.push(Stmt::Pop(count as u16)),
+                    _ => folded.push(stmts[index].clone()),
+                }
+                index += 1;
+            }
+            (stmt, _) => {
+                folded.push(stmt.clone());
+                index += 1;
+            }
+        }
+    }
+
+    // push(an) ... push(a1); f(); [Rr = R6[0];] pop(k)
+    let mut calls = Vec::new();
+    let mut index = 0;
+    while index < folded.len() {
+        let folded_call = match (&folded[index], folded.get(index + 1), folded.get(index + 2)) {
+            (
+                Stmt::Call(name, arguments),
+                Some(Stmt::Assign(result, Expr::Index(base, 0))),
+                Some(Stmt::Pop(count)),
+            ) if arguments.is_empty()
+                && **base == stack
+                && matches!(result, Expr::Register(r) if *r != 7) =>
+            {
+                take_arguments(&mut calls, *count as usize - 1).map(|arguments| {
+                    let call = Expr::Call(name.clone(), arguments);
+                    (Stmt::Assign(result.clone(), call), 3)
+                })
+            }
+            (Stmt::Call(name, arguments), Some(Stmt::Pop(count)), _)
+                if arguments.is_empty() && *count > 0 =>
+            {
+                take_arguments(&mut calls, *count as usize)
+                    .map(|arguments| (Stmt::Call(name.clone(), arguments), 2))
+            }
+            _ => None,
+        };
+        if let Some((call, length)) = folded_call {
+            calls.push(call);
+            index += length;
+            continue;
+        }
+        calls.push(folded[index].clone());
+        index += 1;
+    }
+    *stmts = calls;
+}
+
+/// Takes the last `count` pushes off the end of `stmts` as a call's
+/// arguments, first argument first. Statements between the pushes stay
+/// where they are as long as they leave R6 and the pushed registers
+/// alone.
+fn take_arguments(stmts: &mut Vec<Stmt>, count: usize) -> Option<Vec<Expr>> {
+    let mut pushes = Vec::new();
+    // Registers written after a push, which it must not read
+    let mut written = Vec::new();
+    for (index, stmt) in stmts.iter().enumerate().rev() {
+        if pushes.len() == count {
+            break;
+        }
+        match stmt {
+            Stmt::Push(value) if !written.iter().any(|&r| value.uses(r)) => pushes.push(index),
+            Stmt::Assign(Expr::Register(r), value) if *r != 6 && !value.uses(6) => written.push(*r),
+            Stmt::Assign(target, value) if !target.uses(6) && !value.uses(6) => {}
+            Stmt::Trap(..) => {}
+            _ => return None,
+        }
+    }
+    if pushes.len() < count {
+        return None;
+    }
+    // Removing from the end keeps the other indices valid
+    Some(
+        pushes
+            .into_iter()
+            .map(|index| match stmts.remove(index) {
+                Stmt::Push(value) => value,
+                _ => unreachable!("only pushes were collected"),
+            })
+            .collect(),
+    )
+}
+
+/// Whether `stmts` hold the prologue of a standard frame: the caller's R5
+/// pushed, then R5 pointed just below it.
+pub fn has_frame(stmts: &[Stmt]) -> bool {
+    let saved = stmts
+        .iter()
+        .position(|stmt| *stmt == Stmt::Push(Expr::Register(5)));
+    saved.is_some_and(|saved| {
+        stmts[saved..].iter().any(|stmt| {
+            *stmt
+                == Stmt::Assign(
+                    Expr::Register(5),
+                    Expr::Add(Box::new(Expr::Register(6)), Box::new(Expr::Constant(-1))),
+                )
+        })
+    })
+}
+
+fn frame_slots(expr: Expr) -> Expr {
+    match expr {
+        Expr::Index(base, offset) if *base == Expr::Register(5) => Expr::Frame(Slot::at(offset)),
+        Expr::Index(base, offset) => Expr::Index(Box::new(frame_slots(*base)), offset),
+        Expr::Deref(inner) => Expr::Deref(Box::new(frame_slots(*inner))),
+        Expr::Not(inner) => Expr::Not(Box::new(frame_slots(*inner))),
+        Expr::Negate(inner) => Expr::Negate(Box::new(frame_slots(*inner))),
+        Expr::Add(left, right) => {
+            Expr::Add(Box::new(frame_slots(*left)), Box::new(frame_slots(*right)))
+        }
+        Expr::And(left, right) => {
+            Expr::And(Box::new(frame_slots(*left)), Box::new(frame_slots(*right)))
+        }
+        Expr::Call(name, arguments) => {
+            Expr::Call(name, arguments.into_iter().map(frame_slots).collect())
+        }
+        expr => expr,
+    }
+}
+
+/// Names the words of a standard frame and drops the bookkeeping that
+/// builds and tears it down: anything that moves R5 and R6 or saves and
+/// restores R5 and R7.
+pub fn fold_frame(stmts: &mut Vec<Stmt>) {
+    let bookkeeping = |stmt: &Stmt| {
+        matches!(
+            stmt,
+            Stmt::Assign(Expr::Register(5..=7), _)
+                | Stmt::Pop(_)
+                | Stmt::Push(Expr::Register(5 | 7))
+                | Stmt::Assign(Expr::Frame(Slot::SavedFrame | Slot::ReturnAddress), _)
+        )
+    };
+    let mut folded = Vec::new();
+    for stmt in stmts.drain(..) {
+        let stmt = match stmt {
+            Stmt::Assign(target, value) => Stmt::Assign(frame_slots(target), frame_slots(value)),
+            Stmt::Call(name, arguments) => {
+                Stmt::Call(name, arguments.into_iter().map(frame_slots).collect())
+            }
+            Stmt::Push(value) => Stmt::Push(frame_slots(value)),
+            stmt => stmt,
+        };
+        // A push that reserves room leaves R6 as the only trace
+        if !bookkeeping(&stmt) {
+            folded.push(stmt);
+        }
+    }
+    *stmts = folded;
+}
+
+/// Drops saving and restoring R7 around the calls a subroutine makes,
+/// which pseudo-C leaves implicit.
+pub fn drop_return_address(stmts: &mut Vec<Stmt>) {
+    stmts.retain(|stmt| {
+        !matches!(
+            stmt,
+            Stmt::Assign(Expr::Register(7), Expr::Global(_))
+                | Stmt::Assign(Expr::Global(_), Expr::Register(7))
+        )
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::decompile::ir::{
+        fold_frame, fold_stack, has_frame, lift, resolve_condition, End, Expr, Lifted, Slot, Stmt,
+    };
+
+    fn lifted(source: &str) -> Lifted {
+        let assembly = assemble(&format!(".ORIG x3000\n{}\n.END\n", source)).unwrap();
+        let instructions: Vec<(u16, u16)> = assembly
+            .words
+            .iter()
+            .enumerate()
+            .map(|(offset, &word)| (0x3000 + offset as u16, word))
+            .collect();
+        lift(&instructions, |address| {
+            assembly
+                .symbols
+                .label_at(address)
+                .map_or_else(|| format!("x{:04X}", address), str::to_string)
+        })
+    }
+
+    fn text(stmts: &[Stmt]) -> Vec<String> {
+        stmts.iter().map(ToString::to_string).collect()
+    }
+
+    // ========== Lifting ==========
+
+    #[test]
+    fn test_instructions_become_statements() {
+        let block = lifted(
+            "AND R1, R1, #0\nADD R1, R1, #5\nNOT R2, R1\nADD R2, R2, #1\nADD R3, R0, R2\n\
+             LDR R4, R3, #-2\nSTI R4, PTR\nLEA R0, MSG\nPUTS\nADD R3, R3, #-1\nHALT\n\
+             PTR .FILL x4000\nMSG .STRINGZ \"x\"",
+        );
+
+        assert_eq!(
+            text(&block.stmts),
+            [
+                "R1 = 5;",
+                "R2 = -R1;",
+                "R3 = R0 + R2;",
+                "R4 = R3[-2];",
+                "*PTR = R4;",
+                "R0 = &MSG;",
+                "puts(R0);",
+                "R3 -= 1;",
+            ]
+        );
+        assert_eq!(block.end, End::Halt);
+    }
+
+    #[test]
+    fn test_branch_tests_the_register_that_set_the_flags() {
+        let block = lifted(
+            "LD R1, COUNT\nADD R0, R0, #1\nST R0, COUNT\nBRnz DONE\nDONE HALT\nCOUNT .FILL 3",
+        );
+
+        let End::Branch {
+            condition,
+            taken,
+            fall,
+        } = block.end
+        else {
+            panic!("expected a branch, got {:?}", block.end);
+        };
+        assert_eq!(condition.to_string(), "R0 <= 0");
+        assert_eq!(condition.negate().to_string(), "R0 > 0");
+        assert_eq!((taken, fall), (0x3004, 0x3004));
+    }
+
+    // ========== Conditions ==========
+
+    #[test]
+    fn test_differences_become_comparisons() {
+        let block = lifted("NOT R2, R1\nADD R2, R2, #1\nADD R2, R0, R2\nBRz END\nEND HALT");
+        assert_eq!(
+            resolve_condition(&block.stmts, block.flags, 0b010).to_string(),
+            "R0 == R1"
+        );
+
+        let block = lifted("ADD R2, R0, #-10\nBRn END\nEND HALT");
+        assert_eq!(
+            resolve_condition(&block.stmts, block.flags, 0b100).to_string(),
+            "R0 < 10"
+        );
+
+        // Counting a register down tests what is left
+        let block = lifted("ADD R0, R0, #-1\nBRp END\nEND HALT");
+        assert_eq!(
+            resolve_condition(&block.stmts, block.flags, 0b001).to_string(),
+            "R0 > 0"
+        );
+        assert_eq!(
+            resolve_condition(&[], None, 0b101).to_string(),
+            "flags != 0"
+        );
+    }
+
+    // ========== Stack Frames ==========
+
+    #[test]
+    fn test_calls_with_stacked_arguments_are_folded() {
+        let mut block = lifted(
+            "ADD R6, R6, #-1\nSTR R1, R6, #0\nADD R6, R6, #-1\nSTR R0, R6, #0\nJSR SUM\n\
+             LDR R2, R6, #0\nADD R6, R6, #3\nJSR SHOW\nHALT\nSUM RET\nSHOW RET",
+        );
+        fold_stack(&mut block.stmts);
+
+        assert_eq!(text(&block.stmts), ["R2 = SUM(R0, R1);", "SHOW();"]);
+    }
+
+    #[test]
+    fn test_frame_words_are_named_and_bookkeeping_dropped() {
+        let mut block = lifted(
+            "ADD R6, R6, #-1\nADD R6, R6, #-1\nSTR R7, R6, #0\nADD R6, R6, #-1\nSTR R5, R6, #0\n\
+             ADD R5, R6, #-1\nADD R6, R6, #-1\nLDR R0, R5, #4\nLDR R1, R5, #5\nADD R0, R0, R1\n\
+             STR R0, R5, #0\nSTR R0, R5, #3\nADD R6, R5, #1\nLDR R5, R6, #0\nADD R6, R6, #1\n\
+             LDR R7, R6, #0\nADD R6, R6, #1\nRET",
+        );
+        fold_stack(&mut block.stmts);
+        assert!(has_frame(&block.stmts));
+        fold_frame(&mut block.stmts);
+
+        assert_eq!(
+            text(&block.stmts),
+            [
+                "R0 = param0;",
+                "R1 = param1;",
+                "R0 += R1;",
+                "local0 = R0;",
+                "result = R0;"
+            ]
+        );
+        assert_eq!(block.end, End::Return);
+        assert_eq!(Expr::Frame(Slot::Local(2)).to_string(), "local2");
+    }
+}
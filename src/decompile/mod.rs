@@ -0,0 +1,544 @@
+pub mod ir;
+pub mod structure;
+
+use crate::decompile::ir::{
+    drop_return_address, fold_frame, fold_stack, has_frame, lift, resolve_condition, End, Expr,
+    Lifted, Slot, Stmt,
+};
+use crate::decompile::structure::{structure, write_nodes, Node};
+use crate::graph::{EdgeKind, Graph};
+use crate::instructions::trap::TRAP_HALT;
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::symbols::symbol_table::SymbolTable;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// One subroutine as pseudo-C.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: u16,
+    pub name: String,
+    /// Whether it builds a standard R5 frame, so that its parameters,
+    /// locals and result have names.
+    pub frame: bool,
+    pub parameters: u16,
+    pub locals: u16,
+    /// Whether it leaves a result in its frame.
+    pub returns_value: bool,
+    pub body: Vec<Node>,
+    /// Names for the targets of `goto`.
+    pub labels: BTreeMap<u16, String>,
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "/* x{:04X} */", self.entry)?;
+        let parameters = (0..self.parameters)
+            .map(|index| format!("int param{}", index))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let kind = if self.returns_value { "int" } else { "void" };
+        writeln!(f, "{} {}({}) {{", kind, self.name, parameters)?;
+        for index in 0..self.locals {
+            writeln!(f, "    int local{};", index)?;
+        }
+        if self.returns_value {
+            writeln!(f, "    int result;")?;
+        }
+        let mut body = String::new();
+        let label = |address: u16| {
+            self.labels
+                .get(&address)
+                .cloned()
+                .unwrap_or_else(|| format!("L_x{:04X}", address))
+        };
+        write_nodes(&mut body, &self.body, 1, &label)?;
+        f.write_str(&body)?;
+        writeln!(f, "}}")
+    }
+}
+
+/// A subroutine lifted and folded, before it is structured.
+struct Analysed {
+    entry: u16,
+    name: String,
+    blocks: BTreeMap<u16, Lifted>,
+    frame: bool,
+    parameters: u16,
+    locals: u16,
+    returns_value: bool,
+}
+
+/// Whether a block ending in `word` only ends there because the
+/// instruction calls out and comes back.
+fn returns_here(word: u16) -> bool {
+    let decoded = Decoded::decode(word);
+    match decoded.operation {
+        Operation::Jsr | Operation::Jsrr => true,
+        Operation::Trap => word & 0xFF != TRAP_HALT,
+        _ => false,
+    }
+}
+
+/// The frame slots `expr` mentions.
+fn slots(expr: &Expr, found: &mut Vec<Slot>) {
+    match expr {
+        Expr::Frame(slot) => found.push(*slot),
+        Expr::Deref(inner) | Expr::Not(inner) | Expr::Negate(inner) | Expr::Index(inner, _) => {
+            slots(inner, found)
+        }
+        Expr::Add(left, right) | Expr::And(left, right) => {
+            slots(left, found);
+            slots(right, found);
+        }
+        Expr::Call(_, arguments) => arguments.iter().for_each(|argument| slots(argument, found)),
+        _ => {}
+    }
+}
+
+fn stmt_slots(stmt: &Stmt, found: &mut Vec<Slot>) {
+    match stmt {
+        Stmt::Assign(target, value) => {
+            slots(target, found);
+            slots(value, found);
+        }
+        Stmt::Call(_, arguments) => arguments.iter().for_each(|argument| slots(argument, found)),
+        Stmt::CallIndirect(expr) | Stmt::Push(expr) | Stmt::Trap(_, Some(expr)) => {
+            slots(expr, found)
+        }
+        _ => {}
+    }
+}
+
+/// Gives every `return` in `nodes` the frame's result.
+fn return_result(nodes: &mut [Node]) {
+    for node in nodes {
+        match node {
+            Node::Return(value @ None) => *value = Some(Expr::Frame(Slot::Result)),
+            Node::If(_, then, otherwise) => {
+                return_result(then);
+                return_result(otherwise);
+            }
+            Node::Loop(body) | Node::While(_, body) | Node::DoWhile(body, _) => return_result(body),
+            _ => {}
+        }
+    }
+}
+
+fn gotos(nodes: &[Node], found: &mut BTreeSet<u16>) {
+    for node in nodes {
+        match node {
+            Node::Goto(target) | Node::Label(target) => {
+                found.insert(*target);
+            }
+            Node::If(_, then, otherwise) => {
+                gotos(then, found);
+                gotos(otherwise, found);
+            }
+            Node::Loop(body) | Node::While(_, body) | Node::DoWhile(body, _) => gotos(body, found),
+            _ => {}
+        }
+    }
+}
+
+/// Decompiles every subroutine of `graph` to pseudo-C. Blocks split only
+/// by a call or trap are joined again, conditions are resolved against
+/// the instructions that set the flags, stack traffic and R5 frames are
+/// folded into calls and named slots, and the result is structured into
+/// loops and conditionals.
+pub fn decompile(graph: &Graph, symbols: &SymbolTable) -> Vec<Function> {
+    let name = |address: u16| match graph.subroutines.get(&address) {
+        Some(subroutine) => subroutine.name.clone(),
+        None => symbols
+            .label_at(address)
+            .map_or_else(|| format!("x{:04X}", address), str::to_string),
+    };
+    let mut predecessors: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+    for edge in graph
+        .edges
+        .iter()
+        .filter(|edge| edge.kind != EdgeKind::Call)
+    {
+        predecessors.entry(edge.to).or_default().push(edge.from);
+    }
+    let ends_in_call = |start: u16| {
+        graph.blocks[&start]
+            .instructions
+            .last()
+            .is_some_and(|instruction| returns_here(instruction.word))
+    };
+
+    let analysed: Vec<Analysed> = graph
+        .subroutines
+        .values()
+        .map(|subroutine| {
+            let owned: BTreeSet<u16> = subroutine.blocks.iter().copied().collect();
+            // A block that only starts where a call returns belongs to the
+            // block that made the call
+            let absorbed = |start: u16| {
+                start != subroutine.entry
+                    && !graph.subroutines.contains_key(&start)
+                    && matches!(predecessors.get(&start).map(Vec::as_slice), Some(&[from])
+                        if owned.contains(&from) && ends_in_call(from))
+            };
+            let mut raw: BTreeMap<u16, Lifted> = BTreeMap::new();
+            for &start in owned.iter().filter(|&&start| !absorbed(start)) {
+                let mut instructions = Vec::new();
+                let mut block = start;
+                loop {
+                    instructions.extend(
+                        graph.blocks[&block]
+                            .instructions
+                            .iter()
+                            .map(|instruction| (instruction.address, instruction.word)),
+                    );
+                    let next = instructions.last().map(|&(last, _)| last.wrapping_add(1));
+                    match next {
+                        Some(next) if owned.contains(&next) && absorbed(next) => block = next,
+                        _ => break,
+                    }
+                }
+                raw.insert(start, lift(&instructions, name));
+            }
+
+            // Branches test the flags the last setter left, which may be
+            // in the block before
+            let mut blocks = raw.clone();
+            for (start, lifted) in blocks.iter_mut() {
+                let End::Branch { condition, .. } = &mut lifted.end else {
+                    continue;
+                };
+                let calls = lifted.stmts.iter().any(Stmt::is_call);
+                *condition = match lifted.flags {
+                    Some(_) => resolve_condition(&lifted.stmts, lifted.flags, condition.mask),
+                    None if calls => resolve_condition(&[], None, condition.mask),
+                    None => {
+                        let incoming: Vec<&Lifted> = raw
+                            .iter()
+                            .filter(|(_, block)| match &block.end {
+                                End::Goto(target) => target == start,
+                                End::Branch { taken, fall, .. } => taken == start || fall == start,
+                                _ => false,
+                            })
+                            .map(|(_, block)| block)
+                            .collect();
+                        let flags = incoming.first().and_then(|block| block.flags);
+                        let agree =
+                            flags.is_some() && incoming.iter().all(|block| block.flags == flags);
+                        match incoming[..] {
+                            [single] if agree => {
+                                resolve_condition(&single.stmts, flags, condition.mask)
+                            }
+                            _ if agree => resolve_condition(&[], flags, condition.mask),
+                            _ => resolve_condition(&[], None, condition.mask),
+                        }
+                    }
+                };
+            }
+
+            for lifted in blocks.values_mut() {
+                // `ADD R0, R0, #0` only set the flags, which are resolved now
+                lifted
+                    .stmts
+                    .retain(|stmt| !matches!(stmt, Stmt::Assign(target, value) if target == value));
+                fold_stack(&mut lifted.stmts);
+            }
+            let frame = blocks
+                .get(&subroutine.entry)
+                .is_some_and(|entry| has_frame(&entry.stmts));
+            for lifted in blocks.values_mut() {
+                if frame {
+                    fold_frame(&mut lifted.stmts);
+                }
+                if subroutine.returns {
+                    drop_return_address(&mut lifted.stmts);
+                }
+            }
+
+            let mut found = Vec::new();
+            for lifted in blocks.values() {
+                lifted
+                    .stmts
+                    .iter()
+                    .for_each(|stmt| stmt_slots(stmt, &mut found));
+            }
+            let parameters = found
+                .iter()
+                .filter_map(|slot| match slot {
+                    Slot::Parameter(index) => Some(index + 1),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+            let locals = found
+                .iter()
+                .filter_map(|slot| match slot {
+                    Slot::Local(index) => Some(index + 1),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+            let returns_value = frame && found.contains(&Slot::Result);
+
+            Analysed {
+                entry: subroutine.entry,
+                name: subroutine.name.clone(),
+                blocks,
+                frame,
+                parameters,
+                locals,
+                returns_value,
+            }
+        })
+        .collect();
+
+    // Only a callee that leaves a result gives the caller one
+    let void: BTreeSet<String> = analysed
+        .iter()
+        .filter(|function| !function.returns_value)
+        .map(|function| function.name.clone())
+        .collect();
+    analysed
+        .into_iter()
+        .map(|mut function| {
+            for lifted in function.blocks.values_mut() {
+                for stmt in &mut lifted.stmts {
+                    match stmt {
+                        Stmt::Assign(_, Expr::Call(name, arguments))
+                            if void.contains(name.as_str()) =>
+                        {
+                            *stmt = Stmt::Call(name.clone(), std::mem::take(arguments));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            let mut body = structure(function.entry, &function.blocks);
+            if function.returns_value {
+                return_result(&mut body);
+            }
+            let mut targets = BTreeSet::new();
+            gotos(&body, &mut targets);
+            let labels = targets
+                .into_iter()
+                .filter_map(|target| {
+                    symbols
+                        .label_at(target)
+                        .map(|label| (target, label.to_string()))
+                })
+                .collect();
+
+            Function {
+                entry: function.entry,
+                name: function.name,
+                frame: function.frame,
+                parameters: function.parameters,
+                locals: function.locals,
+                returns_value: function.returns_value,
+                body,
+                labels,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::decompile::{decompile, Function};
+    use crate::graph::Graph;
+    use crate::Vm;
+
+    fn functions(source: &str) -> Vec<Function> {
+        let assembly = assemble(source).unwrap();
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", &assembly.image());
+        let graph = Graph::recover(&vm.memory, &vm.images, assembly.origin, &assembly.symbols);
+        decompile(&graph, &assembly.symbols)
+    }
+
+    #[test]
+    fn test_counting_loop_becomes_a_do_while() {
+        let functions = functions(
+            ".ORIG x3000
+MAIN    AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    LD R0, STAR
+        OUT
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+STAR    .FILL x2A
+.END
+",
+        );
+
+        assert_eq!(functions.len(), 1);
+        assert_eq!(
+            functions[0].to_string(),
+            "/* x3000 */
+void MAIN() {
+    R1 = 3;
+    do {
+        R0 = STAR;
+        out(R0);
+        R1 -= 1;
+    } while (R1 > 0);
+    halt();
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_comparison_selects_a_branch() {
+        let functions = functions(
+            ".ORIG x3000
+MAX     ST R7, SAVE
+        NOT R2, R1
+        ADD R2, R2, #1
+        ADD R2, R0, R2
+        BRzp DONE
+        ADD R0, R1, #0
+DONE    LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+.END
+",
+        );
+
+        assert_eq!(
+            functions[0].to_string(),
+            "/* x3000 */
+void MAX() {
+    R2 = -R1;
+    R2 = R0 + R2;
+    if (R0 < R1) {
+        R0 = R1;
+    }
+    return;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_frame_function_has_parameters_and_a_result() {
+        let functions = functions(
+            ".ORIG x3000
+MAIN    LD R6, STACK
+        AND R0, R0, #0
+        ADD R0, R0, #4
+        ADD R6, R6, #-1
+        STR R0, R6, #0
+        AND R1, R1, #0
+        ADD R1, R1, #2
+        ADD R6, R6, #-1
+        STR R1, R6, #0
+        JSR SUM
+        LDR R1, R6, #0
+        ADD R6, R6, #3
+        HALT
+STACK   .FILL xFE00
+SUM     ADD R6, R6, #-1
+        ADD R6, R6, #-1
+        STR R7, R6, #0
+        ADD R6, R6, #-1
+        STR R5, R6, #0
+        ADD R5, R6, #-1
+        LDR R0, R5, #4
+        LDR R1, R5, #5
+        ADD R0, R0, R1
+        STR R0, R5, #3
+        LDR R5, R6, #0
+        ADD R6, R6, #1
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+.END
+",
+        );
+
+        assert_eq!(functions.len(), 2);
+        assert!(
+            functions[0].to_string().contains("    R1 = SUM(R1, R0);\n"),
+            "{}",
+            functions[0]
+        );
+        let sum = &functions[1];
+        assert!(sum.frame);
+        assert_eq!((sum.parameters, sum.locals), (2, 0));
+        assert_eq!(
+            sum.to_string(),
+            "/* x300E */
+int SUM(int param0, int param1) {
+    int result;
+    R0 = param0;
+    R1 = param1;
+    R0 += R1;
+    result = R0;
+    return result;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_void_call_assigns_no_result() {
+        let functions = functions(
+            ".ORIG x3000
+MAIN    LD R6, STACK
+        ADD R6, R6, #-1
+        STR R0, R6, #0
+        JSR SHOW
+        LDR R0, R6, #0
+        ADD R6, R6, #2
+        HALT
+STACK   .FILL xFE00
+SHOW    ADD R6, R6, #-1
+        ADD R6, R6, #-1
+        STR R7, R6, #0
+        ADD R6, R6, #-1
+        STR R5, R6, #0
+        ADD R5, R6, #-1
+        LDR R0, R5, #4
+        OUT
+        LDR R5, R6, #0
+        ADD R6, R6, #1
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+.END
+",
+        );
+
+        assert!(!functions[1].returns_value);
+        let main = functions[0].to_string();
+        assert!(main.contains("    SHOW(R0);\n"), "{main}");
+        assert!(!main.contains("= SHOW("), "{main}");
+    }
+
+    #[test]
+    fn test_condition_after_a_call_tests_its_own_setter() {
+        let functions = functions(
+            ".ORIG x3000
+MAIN    LD R1, FIVE
+        NOT R1, R1
+        ADD R1, R1, #1
+        JSR F
+        ADD R2, R0, R1
+        BRz DONE
+        ADD R3, R3, #1
+DONE    HALT
+FIVE    .FILL 5
+F       ADD R1, R1, #1
+        RET
+.END
+",
+        );
+
+        let main = functions[0].to_string();
+        assert!(main.contains("    if (R2 != 0) {\n"), "{main}");
+    }
+}
@@ -0,0 +1,547 @@
+use crate::decompile::ir::{Condition, End, Expr, Lifted, Stmt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+/// Structured code: statements nested in conditionals and loops, with
+/// `goto` where the control flow has no structured form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Stmt(Stmt),
+    If(Condition, Vec<Node>, Vec<Node>),
+    /// `while (1)`.
+    Loop(Vec<Node>),
+    While(Condition, Vec<Node>),
+    DoWhile(Vec<Node>, Condition),
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Halt,
+    /// A `JMP` through a register.
+    Jump(Expr),
+    Stop,
+    Goto(u16),
+    Label(u16),
+}
+
+impl Node {
+    /// Whether control never goes on to the next node.
+    fn jumps(&self) -> bool {
+        matches!(
+            self,
+            Node::Break
+                | Node::Continue
+                | Node::Return(_)
+                | Node::Halt
+                | Node::Jump(_)
+                | Node::Stop
+                | Node::Goto(_)
+        )
+    }
+}
+
+fn successors(end: &End) -> Vec<u16> {
+    match *end {
+        End::Goto(target) => vec![target],
+        End::Branch { taken, fall, .. } => vec![taken, fall],
+        _ => Vec::new(),
+    }
+}
+
+/// The blocks every path from each block to an exit goes through.
+fn post_dominators(blocks: &BTreeMap<u16, Lifted>) -> BTreeMap<u16, BTreeSet<u16>> {
+    let all: BTreeSet<u16> = blocks.keys().copied().collect();
+    let mut sets: BTreeMap<u16, BTreeSet<u16>> =
+        blocks.keys().map(|&start| (start, all.clone())).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (&start, block) in blocks.iter().rev() {
+            let inside: Vec<u16> = successors(&block.end)
+                .into_iter()
+                .filter(|next| blocks.contains_key(next))
+                .collect();
+            let mut set = match inside.split_first() {
+                Some((first, rest)) => rest.iter().fold(sets[first].clone(), |set, next| {
+                    set.intersection(&sets[next]).copied().collect()
+                }),
+                None => BTreeSet::new(),
+            };
+            set.insert(start);
+            if set != sets[&start] {
+                sets.insert(start, set);
+                changed = true;
+            }
+        }
+    }
+    sets
+}
+
+/// The nearest block every path from `start` to an exit goes through.
+/// Blocks that never reach an exit have none.
+fn immediate_post_dominator(sets: &BTreeMap<u16, BTreeSet<u16>>, start: u16) -> Option<u16> {
+    let strict: BTreeSet<u16> = sets[&start]
+        .iter()
+        .copied()
+        .filter(|&block| block != start)
+        .collect();
+    if strict.len() + 1 == sets.len() && sets.len() > 1 {
+        return None;
+    }
+    strict.iter().copied().find(|candidate| {
+        let mut theirs = sets[candidate].clone();
+        theirs.insert(*candidate);
+        theirs == strict
+    })
+}
+
+/// Finds the natural loops: for every edge back to a block that is still
+/// on the depth-first path, the blocks that reach the edge's source
+/// without passing its target.
+fn loops(entry: u16, blocks: &BTreeMap<u16, Lifted>) -> BTreeMap<u16, BTreeSet<u16>> {
+    let mut back_edges: Vec<(u16, u16)> = Vec::new();
+    let mut visited = BTreeSet::new();
+    let mut on_path = BTreeSet::new();
+    let mut stack: Vec<(u16, usize)> = vec![(entry, 0)];
+    visited.insert(entry);
+    on_path.insert(entry);
+    while let Some((block, index)) = stack.pop() {
+        let next = successors(&blocks[&block].end)
+            .into_iter()
+            .filter(|next| blocks.contains_key(next))
+            .nth(index);
+        let Some(next) = next else {
+            on_path.remove(&block);
+            continue;
+        };
+        stack.push((block, index + 1));
+        if on_path.contains(&next) {
+            back_edges.push((block, next));
+        } else if visited.insert(next) {
+            on_path.insert(next);
+            stack.push((next, 0));
+        }
+    }
+
+    let mut predecessors: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+    for (&start, block) in blocks {
+        for next in successors(&block.end) {
+            predecessors.entry(next).or_default().push(start);
+        }
+    }
+    let mut loops: BTreeMap<u16, BTreeSet<u16>> = BTreeMap::new();
+    for (latch, header) in back_edges {
+        let body = loops
+            .entry(header)
+            .or_insert_with(|| BTreeSet::from([header]));
+        let mut pending = vec![latch];
+        while let Some(block) = pending.pop() {
+            if body.insert(block) {
+                pending.extend(predecessors.get(&block).into_iter().flatten());
+            }
+        }
+    }
+    loops
+}
+
+struct Context {
+    header: u16,
+    body: BTreeSet<u16>,
+    follow: Option<u16>,
+}
+
+struct Structurer<'a> {
+    blocks: &'a BTreeMap<u16, Lifted>,
+    loops: BTreeMap<u16, BTreeSet<u16>>,
+    post_dominators: BTreeMap<u16, BTreeSet<u16>>,
+    emitted: BTreeSet<u16>,
+    gotos: BTreeSet<u16>,
+}
+
+impl Structurer<'_> {
+    /// Where a loop goes when it is done: the exit of a test at its top or
+    /// bottom if there is one, or else its first exit.
+    fn follow(&self, header: u16, body: &BTreeSet<u16>) -> Option<u16> {
+        let exit = |block: u16| match &self.blocks[&block].end {
+            End::Branch { taken, fall, .. } => match (body.contains(taken), body.contains(fall)) {
+                (true, false) => Some(*fall),
+                (false, true) => Some(*taken),
+                _ => None,
+            },
+            _ => None,
+        };
+        let latches: Vec<u16> = body
+            .iter()
+            .copied()
+            .filter(|&block| successors(&self.blocks[&block].end).contains(&header))
+            .collect();
+        exit(header)
+            .or_else(|| match latches[..] {
+                [latch] => exit(latch),
+                _ => None,
+            })
+            .or_else(|| {
+                body.iter()
+                    .flat_map(|&block| successors(&self.blocks[&block].end))
+                    .filter(|next| !body.contains(next))
+                    .min()
+            })
+    }
+
+    fn sequence(
+        &mut self,
+        start: u16,
+        stop: Option<u16>,
+        contexts: &mut Vec<Context>,
+    ) -> Vec<Node> {
+        let mut out = Vec::new();
+        let mut current = Some(start);
+        while let Some(block) = current {
+            if Some(block) == stop {
+                break;
+            }
+            if let Some(context) = contexts.last() {
+                if block == context.header && self.emitted.contains(&block) {
+                    out.push(Node::Continue);
+                    break;
+                }
+                if Some(block) == context.follow {
+                    out.push(Node::Break);
+                    break;
+                }
+                if !context.body.contains(&block) {
+                    self.gotos.insert(block);
+                    out.push(Node::Goto(block));
+                    break;
+                }
+            }
+            if !self.blocks.contains_key(&block) || self.emitted.contains(&block) {
+                self.gotos.insert(block);
+                out.push(Node::Goto(block));
+                break;
+            }
+            let nested = contexts.iter().any(|context| context.header == block);
+            if let (Some(body), false) = (self.loops.get(&block).cloned(), nested) {
+                let follow = self.follow(block, &body);
+                contexts.push(Context {
+                    header: block,
+                    body,
+                    follow,
+                });
+                let inner = self.sequence(block, None, contexts);
+                contexts.pop();
+                out.push(Node::Loop(inner));
+                current = follow;
+                continue;
+            }
+
+            self.emitted.insert(block);
+            out.push(Node::Label(block));
+            let lifted = &self.blocks[&block];
+            out.extend(lifted.stmts.iter().cloned().map(Node::Stmt));
+            current = match &lifted.end {
+                End::Goto(target) => Some(*target),
+                End::Branch {
+                    condition,
+                    taken,
+                    fall,
+                } => {
+                    let (condition, taken, fall) = (condition.clone(), *taken, *fall);
+                    // A branch inside a loop joins inside it or not at all
+                    let join =
+                        immediate_post_dominator(&self.post_dominators, block).filter(|join| {
+                            contexts
+                                .last()
+                                .is_none_or(|context| context.body.contains(join))
+                        });
+                    let then = self.sequence(taken, join, contexts);
+                    let otherwise = self.sequence(fall, join, contexts);
+                    out.push(Node::If(condition, then, otherwise));
+                    join
+                }
+                End::Return => {
+                    out.push(Node::Return(None));
+                    None
+                }
+                End::Halt => {
+                    out.push(Node::Halt);
+                    None
+                }
+                End::Jump(target) => {
+                    out.push(Node::Jump(target.clone()));
+                    None
+                }
+                End::Stop => {
+                    out.push(Node::Stop);
+                    None
+                }
+            };
+        }
+        out
+    }
+}
+
+/// Tidies structured code: an `if` whose branch ends in a jump lets the
+/// other branch follow it, an empty `then` swaps with its `else`, and
+/// loops that test at the top or bottom become `while` and `do`-`while`.
+fn simplify(nodes: Vec<Node>) -> Vec<Node> {
+    let mut out = Vec::new();
+    for node in nodes {
+        match node {
+            Node::If(condition, then, otherwise) => {
+                let then = simplify(then);
+                let otherwise = simplify(otherwise);
+                match (then.is_empty(), otherwise.is_empty()) {
+                    (true, true) => {}
+                    (true, false) => out.push(Node::If(condition.negate(), otherwise, then)),
+                    (false, false) if then.last().is_some_and(Node::jumps) => {
+                        out.push(Node::If(condition, then, Vec::new()));
+                        out.extend(otherwise);
+                    }
+                    (false, false) if otherwise.last().is_some_and(Node::jumps) => {
+                        out.push(Node::If(condition.negate(), otherwise, Vec::new()));
+                        out.extend(then);
+                    }
+                    _ => out.push(Node::If(condition, then, otherwise)),
+                }
+            }
+            Node::Loop(body) => {
+                let mut body = simplify(body);
+                if body.last() == Some(&Node::Continue) {
+                    body.pop();
+                }
+                out.push(loop_form(body));
+            }
+            node => out.push(node),
+        }
+    }
+    out
+}
+
+/// The condition of `node` if it is `if (condition) jump;`.
+fn guard<'a>(node: Option<&'a Node>, jump: &Node) -> Option<&'a Condition> {
+    match node {
+        Some(Node::If(condition, then, otherwise))
+            if then.len() == 1 && then[0] == *jump && otherwise.is_empty() =>
+        {
+            Some(condition)
+        }
+        _ => None,
+    }
+}
+
+fn loop_form(mut body: Vec<Node>) -> Node {
+    if let Some(condition) = guard(body.first(), &Node::Break).map(Condition::negate) {
+        body.remove(0);
+        return Node::While(condition, body);
+    }
+    let length = body.len();
+    let before_last = length.checked_sub(2).and_then(|index| body.get(index));
+    if let (Some(Node::Break), Some(condition)) =
+        (body.last(), guard(before_last, &Node::Continue).cloned())
+    {
+        body.truncate(length - 2);
+        return Node::DoWhile(body, condition);
+    }
+    if let Some(condition) = guard(body.last(), &Node::Break).map(Condition::negate) {
+        body.pop();
+        return Node::DoWhile(body, condition);
+    }
+    Node::Loop(body)
+}
+
+/// Drops the labels no `goto` names.
+fn prune_labels(nodes: Vec<Node>, gotos: &BTreeSet<u16>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .filter(|node| !matches!(node, Node::Label(block) if !gotos.contains(block)))
+        .map(|node| match node {
+            Node::If(condition, then, otherwise) => Node::If(
+                condition,
+                prune_labels(then, gotos),
+                prune_labels(otherwise, gotos),
+            ),
+            Node::Loop(body) => Node::Loop(prune_labels(body, gotos)),
+            Node::While(condition, body) => Node::While(condition, prune_labels(body, gotos)),
+            Node::DoWhile(body, condition) => Node::DoWhile(prune_labels(body, gotos), condition),
+            node => node,
+        })
+        .collect()
+}
+
+/// Arranges the lifted `blocks` of one subroutine, starting at `entry`,
+/// into conditionals and loops.
+pub fn structure(entry: u16, blocks: &BTreeMap<u16, Lifted>) -> Vec<Node> {
+    let mut structurer = Structurer {
+        blocks,
+        loops: loops(entry, blocks),
+        post_dominators: post_dominators(blocks),
+        emitted: BTreeSet::new(),
+        gotos: BTreeSet::new(),
+    };
+    let nodes = structurer.sequence(entry, None, &mut Vec::new());
+    let gotos = structurer.gotos;
+    simplify(prune_labels(nodes, &gotos))
+}
+
+/// Writes `nodes` as pseudo-C, indented by `depth` levels. `label` names
+/// the targets of `goto`.
+pub fn write_nodes(
+    out: &mut String,
+    nodes: &[Node],
+    depth: usize,
+    label: &dyn Fn(u16) -> String,
+) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        match node {
+            Node::Stmt(stmt) => writeln!(out, "{}{}", indent, stmt)?,
+            Node::If(condition, then, otherwise) => {
+                writeln!(out, "{}if ({}) {{", indent, condition)?;
+                write_nodes(out, then, depth + 1, label)?;
+                if !otherwise.is_empty() {
+                    writeln!(out, "{}}} else {{", indent)?;
+                    write_nodes(out, otherwise, depth + 1, label)?;
+                }
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::Loop(body) => {
+                writeln!(out, "{}while (1) {{", indent)?;
+                write_nodes(out, body, depth + 1, label)?;
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::While(condition, body) => {
+                writeln!(out, "{}while ({}) {{", indent, condition)?;
+                write_nodes(out, body, depth + 1, label)?;
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::DoWhile(body, condition) => {
+                writeln!(out, "{}do {{", indent)?;
+                write_nodes(out, body, depth + 1, label)?;
+                writeln!(out, "{}}} while ({});", indent, condition)?;
+            }
+            Node::Break => writeln!(out, "{}break;", indent)?,
+            Node::Continue => writeln!(out, "{}continue;", indent)?,
+            Node::Return(Some(value)) => writeln!(out, "{}return {};", indent, value)?,
+            Node::Return(None) => writeln!(out, "{}return;", indent)?,
+            Node::Halt => writeln!(out, "{}halt();", indent)?,
+            Node::Jump(target) => writeln!(out, "{}goto *{};", indent, target)?,
+            Node::Stop => writeln!(out, "{}rti();", indent)?,
+            Node::Goto(target) => writeln!(out, "{}goto {};", indent, label(*target))?,
+            Node::Label(target) => writeln!(out, "{}:", label(*target))?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decompile::ir::{Condition, End, Expr, Lifted, Stmt};
+    use crate::decompile::structure::{structure, write_nodes};
+    use std::collections::BTreeMap;
+
+    fn block(stmt: &str, end: End) -> Lifted {
+        Lifted {
+            stmts: vec![Stmt::Assign(
+                Expr::Register(0),
+                Expr::Global(stmt.to_string()),
+            )],
+            end,
+            flags: None,
+        }
+    }
+
+    fn branch(mask: u8, taken: u16, fall: u16) -> End {
+        End::Branch {
+            condition: Condition {
+                left: Expr::Register(1),
+                right: Expr::Constant(0),
+                mask,
+            },
+            taken,
+            fall,
+        }
+    }
+
+    fn text(blocks: &[(u16, Lifted)]) -> String {
+        let blocks: BTreeMap<u16, Lifted> = blocks.iter().cloned().collect();
+        let mut out = String::new();
+        write_nodes(&mut out, &structure(1, &blocks), 0, &|block| {
+            format!("L{}", block)
+        })
+        .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_if_else_joins_at_the_post_dominator() {
+        let text = text(&[
+            (1, block("a", branch(0b010, 3, 2))),
+            (2, block("b", End::Goto(4))),
+            (3, block("c", End::Goto(4))),
+            (4, block("d", End::Return)),
+        ]);
+
+        assert_eq!(
+            text,
+            "R0 = a;\nif (R1 == 0) {\n    R0 = c;\n} else {\n    R0 = b;\n}\nR0 = d;\nreturn;\n"
+        );
+    }
+
+    #[test]
+    fn test_if_without_else_is_inverted() {
+        let text = text(&[
+            (1, block("a", branch(0b100, 3, 2))),
+            (2, block("b", End::Goto(3))),
+            (3, block("c", End::Halt)),
+        ]);
+
+        assert_eq!(
+            text,
+            "R0 = a;\nif (R1 >= 0) {\n    R0 = b;\n}\nR0 = c;\nhalt();\n"
+        );
+    }
+
+    #[test]
+    fn test_test_at_the_top_makes_a_while_loop() {
+        let text = text(&[
+            (1, block("init", End::Goto(2))),
+            (2, block("test", branch(0b010, 4, 3))),
+            (3, block("body", End::Goto(2))),
+            (4, block("done", End::Halt)),
+        ]);
+
+        assert_eq!(
+            text,
+            "R0 = init;\nwhile (1) {\n    R0 = test;\n    if (R1 == 0) {\n        break;\n    }\n    \
+             R0 = body;\n}\nR0 = done;\nhalt();\n"
+        );
+    }
+
+    #[test]
+    fn test_test_at_the_bottom_makes_a_do_while_loop() {
+        let text = text(&[
+            (1, block("init", End::Goto(2))),
+            (2, block("body", branch(0b001, 2, 3))),
+            (3, block("done", End::Halt)),
+        ]);
+
+        assert_eq!(
+            text,
+            "R0 = init;\ndo {\n    R0 = body;\n} while (R1 > 0);\nR0 = done;\nhalt();\n"
+        );
+    }
+
+    #[test]
+    fn test_unstructured_jumps_become_gotos() {
+        // Two loops that jump into each other's middle
+        let text = text(&[
+            (1, block("a", branch(0b010, 3, 2))),
+            (2, block("b", End::Goto(3))),
+            (3, block("c", branch(0b001, 2, 4))),
+            (4, block("d", End::Halt)),
+        ]);
+
+        assert!(text.contains("goto L"), "{}", text);
+        assert!(text.contains("L2:") || text.contains("L3:"), "{}", text);
+    }
+}
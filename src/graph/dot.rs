@@ -0,0 +1,163 @@
+use crate::graph::{Callee, EdgeKind, Graph};
+use crate::instructions::disassemble::disassemble;
+use std::fmt::Write;
+
+/// Escapes `text` for use inside a double-quoted Graphviz string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
+
+fn with_count(text: &str, count: Option<u32>) -> String {
+    match count {
+        Some(count) if text.is_empty() => count.to_string(),
+        Some(count) => format!("{} {}", text, count),
+        None => text.to_string(),
+    }
+}
+
+/// Renders the control-flow graph in Graphviz DOT, one cluster per
+/// subroutine. Each block lists its disassembly, with execution counts in
+/// brackets when the graph has a profile. Calls are dashed.
+pub fn to_cfg_dot(graph: &Graph) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph cfg {{").unwrap();
+    writeln!(out, "  node [shape=box, fontname=\"monospace\"];").unwrap();
+
+    for subroutine in graph.subroutines.values() {
+        writeln!(out, "  subgraph cluster_x{:04X} {{", subroutine.entry).unwrap();
+        writeln!(out, "    label={};", quote(&subroutine.name)).unwrap();
+        for start in &subroutine.blocks {
+            let block = &graph.blocks[start];
+            // `\l` ends a left-justified line
+            let mut label = String::new();
+            if let Some(name) = &block.label {
+                label.push_str(&format!("{}:\\l", escape(name)));
+            }
+            for instruction in &block.instructions {
+                let mut line = format!("x{:04X}  {}", instruction.address, instruction.text);
+                if let Some(count) = instruction.count {
+                    line.push_str(&format!("  [{}]", count));
+                }
+                label.push_str(&escape(&line));
+                label.push_str("\\l");
+            }
+            writeln!(out, "    b_x{:04X} [label=\"{}\"];", start, label).unwrap();
+        }
+        writeln!(out, "  }}").unwrap();
+    }
+
+    for edge in &graph.edges {
+        let (text, style) = match edge.kind {
+            EdgeKind::Taken => ("taken", ""),
+            EdgeKind::FallThrough => ("", ""),
+            EdgeKind::Call => ("call", ", style=dashed"),
+        };
+        write!(out, "  b_x{:04X} -> b_x{:04X}", edge.from, edge.to).unwrap();
+        let label = with_count(text, edge.count);
+        if label.is_empty() && style.is_empty() {
+            writeln!(out, ";").unwrap();
+        } else {
+            writeln!(out, " [label={}{}];", quote(&label), style).unwrap();
+        }
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Renders the call graph in Graphviz DOT. Traps are ellipses; an edge is
+/// labelled with its number of call sites when there are several, or with
+/// how often it ran when the graph has a profile.
+pub fn to_call_graph_dot(graph: &Graph) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph calls {{").unwrap();
+    writeln!(out, "  node [shape=box];").unwrap();
+
+    for subroutine in graph.subroutines.values() {
+        writeln!(
+            out,
+            "  s_x{:04X} [label=\"{}\\nx{:04X}\"];",
+            subroutine.entry,
+            escape(&subroutine.name),
+            subroutine.entry
+        )
+        .unwrap();
+    }
+    let calls = graph.calls();
+    let mut traps: Vec<u8> = calls
+        .iter()
+        .filter_map(|call| match call.callee {
+            Callee::Trap(vector) => Some(vector),
+            Callee::Subroutine(_) => None,
+        })
+        .collect();
+    traps.sort_unstable();
+    traps.dedup();
+    for vector in traps {
+        let name = disassemble(0, 0xF000 | vector as u16);
+        writeln!(
+            out,
+            "  t_x{:02X} [label={}, shape=ellipse];",
+            vector,
+            quote(&name)
+        )
+        .unwrap();
+    }
+
+    for call in &calls {
+        let callee = match call.callee {
+            Callee::Subroutine(entry) => format!("s_x{:04X}", entry),
+            Callee::Trap(vector) => format!("t_x{:02X}", vector),
+        };
+        write!(out, "  s_x{:04X} -> {}", call.caller, callee).unwrap();
+        match call.count {
+            Some(count) => writeln!(out, " [label=\"{}\"];", count).unwrap(),
+            None if call.sites > 1 => writeln!(out, " [label=\"{} sites\"];", call.sites).unwrap(),
+            None => writeln!(out, ";").unwrap(),
+        }
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::dot::{to_call_graph_dot, to_cfg_dot};
+    use crate::graph::tests::{graph, PROGRAM};
+
+    #[test]
+    fn test_cfg_clusters_blocks_by_subroutine() {
+        let (_, graph) = graph(PROGRAM);
+        let dot = to_cfg_dot(&graph);
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("  subgraph cluster_x3006 {\n    label=\"SHOW\";\n"));
+        assert!(dot.contains("    b_x3002 [label=\"LOOP:\\lx3002  JSR x3006 ; SHOW\\l\"];\n"));
+        assert!(dot.contains("  b_x3002 -> b_x3006 [label=\"call\", style=dashed];\n"));
+        assert!(dot.contains("  b_x3003 -> b_x3002 [label=\"taken\"];\n"));
+        assert!(dot.contains("  b_x3003 -> b_x3005;\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_call_graph_names_subroutines_and_traps() {
+        let (_, graph) = graph(PROGRAM);
+
+        assert_eq!(
+            to_call_graph_dot(&graph),
+            "digraph calls {\n  \
+             node [shape=box];\n  \
+             s_x3000 [label=\"MAIN\\nx3000\"];\n  \
+             s_x3006 [label=\"SHOW\\nx3006\"];\n  \
+             t_x21 [label=\"OUT\", shape=ellipse];\n  \
+             t_x25 [label=\"HALT\", shape=ellipse];\n  \
+             s_x3000 -> s_x3006;\n  \
+             s_x3000 -> t_x25;\n  \
+             s_x3006 -> t_x21;\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::graph::{Callee, Graph};
+use crate::lsp::json::{object, Value};
+
+fn address(address: u16) -> Value {
+    Value::from(format!("x{:04X}", address))
+}
+
+fn count(count: Option<u32>) -> Value {
+    count.map_or(Value::Null, Value::from)
+}
+
+/// The graph as JSON, with addresses written as `x3000` strings and counts
+/// as `null` without a profile:
+///
+/// ```text
+/// {"entry": "x3000",
+///  "subroutines": [{"entry", "name", "blocks", "returns"}],
+///  "blocks": [{"start", "label", "subroutine", "instructions": [{"address", "word", "text", "count"}]}],
+///  "edges": [{"from", "to", "kind", "count"}],
+///  "calls": [{"caller", "callee", "trap", "sites", "count"}]}
+/// ```
+///
+/// A call to a trap has its vector in `trap` and a `null` callee.
+pub fn to_json(graph: &Graph) -> Value {
+    let subroutines = graph
+        .subroutines
+        .values()
+        .map(|subroutine| {
+            object([
+                ("entry", address(subroutine.entry)),
+                ("name", Value::from(subroutine.name.as_str())),
+                (
+                    "blocks",
+                    Value::from(
+                        subroutine
+                            .blocks
+                            .iter()
+                            .map(|&start| address(start))
+                            .collect::<Vec<_>>(),
+                    ),
+                ),
+                ("returns", Value::from(subroutine.returns)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    let blocks = graph
+        .blocks
+        .values()
+        .map(|block| {
+            let instructions = block
+                .instructions
+                .iter()
+                .map(|instruction| {
+                    object([
+                        ("address", address(instruction.address)),
+                        ("word", address(instruction.word)),
+                        ("text", Value::from(instruction.text.as_str())),
+                        ("count", count(instruction.count)),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            object([
+                ("start", address(block.start)),
+                (
+                    "label",
+                    block.label.as_deref().map_or(Value::Null, Value::from),
+                ),
+                ("subroutine", address(block.subroutine)),
+                ("instructions", Value::from(instructions)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    let edges = graph
+        .edges
+        .iter()
+        .map(|edge| {
+            object([
+                ("from", address(edge.from)),
+                ("to", address(edge.to)),
+                ("kind", Value::from(edge.kind.name())),
+                ("count", count(edge.count)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    let calls = graph
+        .calls()
+        .iter()
+        .map(|call| {
+            let (callee, trap) = match call.callee {
+                Callee::Subroutine(entry) => (address(entry), Value::Null),
+                Callee::Trap(vector) => (Value::Null, Value::from(format!("x{:02X}", vector))),
+            };
+            object([
+                ("caller", address(call.caller)),
+                ("callee", callee),
+                ("trap", trap),
+                ("sites", Value::from(call.sites)),
+                ("count", count(call.count)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    object([
+        ("entry", address(graph.entry)),
+        ("subroutines", Value::from(subroutines)),
+        ("blocks", Value::from(blocks)),
+        ("edges", Value::from(edges)),
+        ("calls", Value::from(calls)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::json::to_json;
+    use crate::graph::tests::{graph, PROGRAM};
+    use crate::lsp::json::{parse, Value};
+
+    #[test]
+    fn test_json_describes_blocks_edges_and_calls() {
+        let (_, graph) = graph(PROGRAM);
+        let json = parse(&to_json(&graph).to_string()).unwrap();
+
+        assert_eq!(json.get("entry").and_then(Value::as_str), Some("x3000"));
+        let blocks = json.get("blocks").and_then(Value::as_array).unwrap();
+        assert_eq!(blocks.len(), 6);
+        assert_eq!(
+            blocks[1].to_string(),
+            r#"{"start":"x3002","label":"LOOP","subroutine":"x3000","instructions":[{"address":"x3002","word":"x4803","text":"JSR x3006 ; SHOW","count":null}]}"#
+        );
+        let edges = json.get("edges").and_then(Value::as_array).unwrap();
+        assert_eq!(
+            edges[1].to_string(),
+            r#"{"from":"x3002","to":"x3006","kind":"call","count":null}"#
+        );
+        let calls = json.get("calls").and_then(Value::as_array).unwrap();
+        assert_eq!(
+            calls[2].to_string(),
+            r#"{"caller":"x3006","callee":null,"trap":"x21","sites":1,"count":null}"#
+        );
+    }
+}
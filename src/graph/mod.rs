@@ -0,0 +1,443 @@
+use crate::cfg::{is_return, recover};
+use crate::coverage::Coverage;
+use crate::instructions::disassemble::disassemble;
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::symbols::symbol_table::SymbolTable;
+use crate::{LoadedImage, MEMORY_MAX};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub mod dot;
+pub mod json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// A branch or `JMP` to a known target.
+    Taken,
+    /// The next instruction, including where a call returns to.
+    FallThrough,
+    /// A `JSR` to the entry of a subroutine.
+    Call,
+}
+
+impl EdgeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Taken => "taken",
+            EdgeKind::FallThrough => "fall-through",
+            EdgeKind::Call => "call",
+        }
+    }
+}
+
+/// An edge between the blocks starting at `from` and `to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub kind: EdgeKind,
+    /// How often the edge was followed in a profile run.
+    pub count: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub word: u16,
+    /// The disassembly, followed by the label of the target if it has one.
+    pub text: String,
+    pub count: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    pub label: Option<String>,
+    pub instructions: Vec<Instruction>,
+    /// The entry of the subroutine the block belongs to.
+    pub subroutine: u16,
+}
+
+/// The blocks reachable from an entry point without following calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: u16,
+    /// The label at the entry, or `sub_x3000` without one.
+    pub name: String,
+    pub blocks: Vec<u16>,
+    /// Whether a block ends with `RET`, that is `JMP R7`.
+    pub returns: bool,
+}
+
+/// Who a call goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Callee {
+    Subroutine(u16),
+    /// A `TRAP` with this vector.
+    Trap(u8),
+}
+
+/// All the calls from one subroutine to one callee.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub caller: u16,
+    pub callee: Callee,
+    /// How many instructions make the call.
+    pub sites: u32,
+    /// How often they ran in a profile run.
+    pub count: Option<u32>,
+}
+
+/// The control-flow graph of the code reachable from an entry point,
+/// split into subroutines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Graph {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, Block>,
+    pub edges: Vec<Edge>,
+    pub subroutines: BTreeMap<u16, Subroutine>,
+}
+
+/// The disassembly of `word`, naming the label its PC-relative operand
+/// points at.
+fn text(address: u16, word: u16, symbols: &SymbolTable) -> String {
+    let text = disassemble(address, word);
+    let decoded = Decoded::decode(word);
+    let relative = matches!(
+        decoded.operation,
+        Operation::Br
+            | Operation::Jsr
+            | Operation::Ld
+            | Operation::Ldi
+            | Operation::Lea
+            | Operation::St
+            | Operation::Sti
+    );
+    let target = address.wrapping_add(1).wrapping_add(decoded.operand);
+    match symbols.label_at(target) {
+        Some(label) if relative && !(decoded.operation == Operation::Br && decoded.dr == 0) => {
+            format!("{} ; {}", text, label)
+        }
+        _ => text,
+    }
+}
+
+impl Graph {
+    /// Recovers the blocks reachable from `entry` in the loaded images and
+    /// groups them into subroutines: the entry and every `JSR` target each
+    /// start one, and own the blocks they reach before any other does.
+    pub fn recover(
+        memory: &[u16; MEMORY_MAX],
+        images: &[LoadedImage],
+        entry: u16,
+        symbols: &SymbolTable,
+    ) -> Graph {
+        let flow = recover(memory, images, entry);
+
+        let mut edges = Vec::new();
+        for block in flow.blocks.values() {
+            let last = (block.end - 1) as u16;
+            let decoded = Decoded::decode(memory[last as usize]);
+            for (index, &to) in block.successors.iter().enumerate() {
+                let kind = if decoded.operation == Operation::Jsr && index == 0 {
+                    EdgeKind::Call
+                } else if to as u32 == block.end && index == block.successors.len() - 1 {
+                    EdgeKind::FallThrough
+                } else {
+                    EdgeKind::Taken
+                };
+                edges.push(Edge {
+                    from: block.start,
+                    to,
+                    kind,
+                    count: None,
+                });
+            }
+        }
+
+        let mut entries = BTreeSet::from([entry]);
+        entries.extend(
+            edges
+                .iter()
+                .filter(|edge| edge.kind == EdgeKind::Call)
+                .map(|edge| edge.to)
+                .filter(|to| flow.blocks.contains_key(to)),
+        );
+        let mut owners: BTreeMap<u16, u16> = BTreeMap::new();
+        let mut subroutines = BTreeMap::new();
+        // The entry point claims its blocks first
+        let order = [entry]
+            .into_iter()
+            .chain(entries.iter().copied().filter(|&start| start != entry));
+        for subroutine in order {
+            let mut blocks = Vec::new();
+            let mut pending = vec![subroutine];
+            while let Some(start) = pending.pop() {
+                if owners.contains_key(&start) || !flow.blocks.contains_key(&start) {
+                    continue;
+                }
+                owners.insert(start, subroutine);
+                blocks.push(start);
+                pending.extend(
+                    edges
+                        .iter()
+                        .filter(|edge| edge.from == start && edge.kind != EdgeKind::Call)
+                        .map(|edge| edge.to),
+                );
+            }
+            blocks.sort_unstable();
+            let returns = blocks.iter().any(|start| {
+                let last = (flow.blocks[start].end - 1) as u16;
+                is_return(&Decoded::decode(memory[last as usize]))
+            });
+            let name = symbols
+                .label_at(subroutine)
+                .map_or_else(|| format!("sub_x{:04X}", subroutine), str::to_string);
+            subroutines.insert(
+                subroutine,
+                Subroutine {
+                    entry: subroutine,
+                    name,
+                    blocks,
+                    returns,
+                },
+            );
+        }
+
+        let blocks = flow
+            .blocks
+            .values()
+            .map(|block| {
+                let instructions = (block.start as u32..block.end)
+                    .map(|address| {
+                        let address = address as u16;
+                        let word = memory[address as usize];
+                        Instruction {
+                            address,
+                            word,
+                            text: text(address, word, symbols),
+                            count: None,
+                        }
+                    })
+                    .collect();
+                let start = block.start;
+                let block = Block {
+                    start,
+                    label: symbols.label_at(start).map(str::to_string),
+                    instructions,
+                    subroutine: owners.get(&start).copied().unwrap_or(entry),
+                };
+                (start, block)
+            })
+            .collect();
+
+        Graph {
+            entry,
+            blocks,
+            edges,
+            subroutines,
+        }
+    }
+
+    /// Adds the execution counts `coverage` recorded in a run of the
+    /// program. A conditional branch's edges get its taken and not-taken
+    /// counts; every other edge gets the count of the instruction it
+    /// leaves.
+    pub fn with_profile(mut self, coverage: &Coverage) -> Graph {
+        for block in self.blocks.values_mut() {
+            for instruction in &mut block.instructions {
+                instruction.count = Some(coverage.hits(instruction.address));
+            }
+        }
+        for edge in &mut self.edges {
+            let Some(last) = self.blocks[&edge.from].instructions.last() else {
+                continue;
+            };
+            let hits = coverage.hits(last.address);
+            edge.count = Some(match (coverage.branch(last.address), edge.kind) {
+                (Some(counts), EdgeKind::Taken) => counts.taken,
+                (Some(counts), EdgeKind::FallThrough) => counts.not_taken,
+                _ => hits,
+            });
+        }
+        self
+    }
+
+    /// The call graph: every subroutine's calls, to other subroutines and
+    /// to traps, in order of caller and callee.
+    pub fn calls(&self) -> Vec<Call> {
+        let mut calls: BTreeMap<(u16, Callee), Call> = BTreeMap::new();
+        let mut add = |caller: u16, callee: Callee, count: Option<u32>| {
+            let call = calls.entry((caller, callee)).or_insert(Call {
+                caller,
+                callee,
+                sites: 0,
+                count: count.map(|_| 0),
+            });
+            call.sites += 1;
+            if let (Some(total), Some(count)) = (call.count.as_mut(), count) {
+                *total = total.saturating_add(count);
+            }
+        };
+
+        for edge in self.edges.iter().filter(|edge| edge.kind == EdgeKind::Call) {
+            add(
+                self.blocks[&edge.from].subroutine,
+                Callee::Subroutine(edge.to),
+                edge.count,
+            );
+        }
+        for block in self.blocks.values() {
+            for instruction in &block.instructions {
+                if instruction.word >> 12 == 0xF {
+                    add(
+                        block.subroutine,
+                        Callee::Trap(instruction.word as u8),
+                        instruction.count,
+                    );
+                }
+            }
+        }
+        calls.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::coverage::Coverage;
+    use crate::graph::{Call, Callee, EdgeKind, Graph};
+    use crate::Vm;
+
+    pub(crate) fn graph(source: &str) -> (Vm, Graph) {
+        let assembly = assemble(source).unwrap();
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", &assembly.image());
+        let graph = Graph::recover(&vm.memory, &vm.images, assembly.origin, &assembly.symbols);
+        (vm, graph)
+    }
+
+    pub(crate) const PROGRAM: &str = "\
+.ORIG x3000
+MAIN    AND R1, R1, #0
+        ADD R1, R1, #2
+LOOP    JSR SHOW
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+SHOW    ST R7, SAVE
+        LD R0, STAR
+        OUT
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+STAR    .FILL x2A
+.END
+";
+
+    // ========== Recovery ==========
+
+    #[test]
+    fn test_blocks_are_grouped_into_subroutines() {
+        let (_, graph) = graph(PROGRAM);
+
+        assert_eq!(
+            graph.blocks.keys().copied().collect::<Vec<_>>(),
+            [0x3000, 0x3002, 0x3003, 0x3005, 0x3006, 0x3009]
+        );
+        let subroutines: Vec<_> = graph
+            .subroutines
+            .values()
+            .map(|subroutine| {
+                (
+                    subroutine.name.as_str(),
+                    subroutine.blocks.clone(),
+                    subroutine.returns,
+                )
+            })
+            .collect();
+        assert_eq!(
+            subroutines,
+            [
+                ("MAIN", vec![0x3000, 0x3002, 0x3003, 0x3005], false),
+                ("SHOW", vec![0x3006, 0x3009], true),
+            ]
+        );
+        assert_eq!(graph.blocks[&0x3002].label.as_deref(), Some("LOOP"));
+        assert_eq!(
+            graph.blocks[&0x3002].instructions[0].text,
+            "JSR x3006 ; SHOW"
+        );
+    }
+
+    #[test]
+    fn test_edges_are_classified() {
+        let (_, graph) = graph(PROGRAM);
+
+        let edges: Vec<_> = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.from, edge.to, edge.kind))
+            .collect();
+        assert_eq!(
+            edges,
+            [
+                (0x3000, 0x3002, EdgeKind::FallThrough),
+                (0x3002, 0x3006, EdgeKind::Call),
+                (0x3002, 0x3003, EdgeKind::FallThrough),
+                (0x3003, 0x3002, EdgeKind::Taken),
+                (0x3003, 0x3005, EdgeKind::FallThrough),
+                (0x3006, 0x3009, EdgeKind::FallThrough),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_graph_includes_traps() {
+        let (_, graph) = graph(PROGRAM);
+
+        assert_eq!(
+            graph.calls(),
+            [
+                Call {
+                    caller: 0x3000,
+                    callee: Callee::Subroutine(0x3006),
+                    sites: 1,
+                    count: None,
+                },
+                Call {
+                    caller: 0x3000,
+                    callee: Callee::Trap(0x25),
+                    sites: 1,
+                    count: None,
+                },
+                Call {
+                    caller: 0x3006,
+                    callee: Callee::Trap(0x21),
+                    sites: 1,
+                    count: None,
+                },
+            ]
+        );
+    }
+
+    // ========== Profiles ==========
+
+    #[test]
+    fn test_profile_counts_instructions_and_edges() {
+        let (mut vm, graph) = graph(PROGRAM);
+        vm.coverage = Some(Coverage::new());
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.registers[crate::registers::register::Register::Pc as usize] = 0x3000;
+        vm.run();
+        let graph = graph.with_profile(vm.coverage.as_ref().unwrap());
+
+        assert_eq!(graph.blocks[&0x3002].instructions[0].count, Some(2));
+        let counts: Vec<_> = graph.edges.iter().map(|edge| edge.count).collect();
+        assert_eq!(
+            counts,
+            [Some(1), Some(2), Some(2), Some(1), Some(1), Some(2)]
+        );
+        assert_eq!(graph.calls()[0].count, Some(2));
+    }
+}
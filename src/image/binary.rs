@@ -0,0 +1,81 @@
+use crate::image::{Image, ImageError, Segment};
+
+fn words(bytes: &[u8], word: fn([u8; 2]) -> u16) -> Result<Vec<u16>, ImageError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(ImageError::OddLength(bytes.len()));
+    }
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|pair| word([pair[0], pair[1]]))
+        .collect())
+}
+
+/// Reads the format `lc3as` writes: the origin, then the words, all
+/// big-endian.
+pub fn read_object(bytes: &[u8]) -> Result<Image, ImageError> {
+    let mut words = words(bytes, u16::from_be_bytes)?;
+    if words.is_empty() {
+        return Err(ImageError::Empty);
+    }
+    let origin = words.remove(0);
+    Ok(Image::new(origin, words))
+}
+
+pub fn write_object(segment: &Segment) -> Vec<u8> {
+    std::iter::once(segment.origin)
+        .chain(segment.words.iter().copied())
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+/// Reads a little-endian memory dump, which does not say where it goes.
+pub fn read_raw(bytes: &[u8], load_at: u16) -> Result<Image, ImageError> {
+    let words = words(bytes, u16::from_le_bytes)?;
+    if words.is_empty() {
+        return Err(ImageError::Empty);
+    }
+    Ok(Image::new(load_at, words))
+}
+
+pub fn write_raw(segment: &Segment) -> Vec<u8> {
+    segment
+        .words
+        .iter()
+        .copied()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::binary::{read_object, read_raw, write_raw};
+    use crate::image::{Image, ImageError};
+
+    // ========== Object Files ==========
+
+    #[test]
+    fn test_object_starts_with_the_origin() {
+        assert_eq!(
+            read_object(&[0x30, 0x00, 0x12, 0x61, 0xF0, 0x25]).unwrap(),
+            Image::new(0x3000, vec![0x1261, 0xF025])
+        );
+    }
+
+    #[test]
+    fn test_truncated_objects_are_errors() {
+        assert!(matches!(read_object(&[]), Err(ImageError::Empty)));
+        assert!(matches!(
+            read_object(&[0x30, 0x00, 0x12]),
+            Err(ImageError::OddLength(3))
+        ));
+    }
+
+    // ========== Raw Dumps ==========
+
+    #[test]
+    fn test_raw_words_are_little_endian() {
+        let image = read_raw(&[0x61, 0x12, 0x25, 0xF0], 0x4000).unwrap();
+        assert_eq!(image, Image::new(0x4000, vec![0x1261, 0xF025]));
+        assert_eq!(write_raw(&image.segments[0]), [0x61, 0x12, 0x25, 0xF0]);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::image::{Image, ImageError, Segment};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Data bytes per record when writing.
+const RECORD_BYTES: usize = 16;
+
+/// Whether `text` starts with an Intel HEX record.
+pub fn looks_like(text: &str) -> bool {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| line.starts_with(':'))
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> ImageError {
+    ImageError::Syntax {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Decodes one `:LLAAAATT...CC` record into its bytes, checksum included.
+fn record(text: &str, line: usize) -> Result<Vec<u8>, ImageError> {
+    let digits = text
+        .strip_prefix(':')
+        .ok_or_else(|| syntax(line, "records start with ':'"))?;
+    if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(syntax(line, "expected pairs of hex digits"));
+    }
+    let bytes: Vec<u8> = (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16).unwrap())
+        .collect();
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err(syntax(line, "record length does not match its byte count"));
+    }
+
+    let (contents, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = contents
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg();
+    if checksum[0] != expected {
+        return Err(ImageError::Checksum {
+            line,
+            expected,
+            found: checksum[0],
+        });
+    }
+    Ok(bytes)
+}
+
+/// Reads Intel HEX. Addresses count bytes and each word is stored high byte
+/// first, so word `w` lives at bytes `2w` and `2w + 1`.
+pub fn read(text: &str) -> Result<Image, ImageError> {
+    let mut bytes = BTreeMap::new();
+    let mut base = 0u32;
+    let mut ended = false;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+        if ended {
+            return Err(syntax(line, "record after the end-of-file record"));
+        }
+
+        let record = record(raw, line)?;
+        let address = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            DATA => {
+                for (offset, &byte) in data.iter().enumerate() {
+                    let at = base + address + offset as u32;
+                    if at >= 0x20000 {
+                        return Err(syntax(
+                            line,
+                            format!("byte address {:X} is past the end of memory", at),
+                        ));
+                    }
+                    bytes.insert(at, byte);
+                }
+            }
+            END_OF_FILE => ended = true,
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                let value = u16::from_be_bytes([data[0], data[1]]) as u32;
+                base = if record[3] == EXTENDED_SEGMENT_ADDRESS {
+                    value << 4
+                } else {
+                    value << 16
+                };
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            kind => return Err(syntax(line, format!("unexpected record type {:02X}", kind))),
+        }
+    }
+    if !ended {
+        return Err(syntax(
+            text.lines().count(),
+            "missing the end-of-file record",
+        ));
+    }
+
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut previous = None;
+    for &at in bytes.keys() {
+        let address = (at / 2) as u16;
+        if previous == Some(address) {
+            continue;
+        }
+        let word = u16::from_be_bytes([
+            bytes.get(&(at & !1)).copied().unwrap_or(0),
+            bytes.get(&(at | 1)).copied().unwrap_or(0),
+        ]);
+        match segments.last_mut() {
+            Some(segment)
+                if previous.is_some_and(|previous: u16| previous.wrapping_add(1) == address) =>
+            {
+                segment.words.push(word)
+            }
+            _ => segments.push(Segment {
+                origin: address,
+                words: vec![word],
+            }),
+        }
+        previous = Some(address);
+    }
+    if segments.is_empty() {
+        return Err(ImageError::Empty);
+    }
+    Ok(Image { segments })
+}
+
+fn write_record(out: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    out.push(':');
+    for byte in bytes {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+pub fn write(image: &Image) -> String {
+    let mut out = String::new();
+    let mut upper = 0u16;
+    for segment in &image.segments {
+        let bytes: Vec<u8> = segment
+            .words
+            .iter()
+            .copied()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let start = segment.origin as u32 * 2;
+        for (index, chunk) in bytes.chunks(RECORD_BYTES).enumerate() {
+            let at = start + (index * RECORD_BYTES) as u32;
+            if (at >> 16) as u16 != upper {
+                upper = (at >> 16) as u16;
+                write_record(&mut out, EXTENDED_LINEAR_ADDRESS, 0, &upper.to_be_bytes());
+            }
+            // A record may not run past its 64 KiB page
+            let split = chunk.len().min(0x10000 - (at & 0xFFFF) as usize);
+            write_record(&mut out, DATA, at as u16, &chunk[..split]);
+            if split < chunk.len() {
+                upper += 1;
+                write_record(&mut out, EXTENDED_LINEAR_ADDRESS, 0, &upper.to_be_bytes());
+                write_record(&mut out, DATA, 0, &chunk[split..]);
+            }
+        }
+    }
+    write_record(&mut out, END_OF_FILE, 0, &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::intel_hex::{read, write};
+    use crate::image::{Image, ImageError};
+
+    // ========== Reading ==========
+
+    #[test]
+    fn test_reads_byte_addressed_records() {
+        // Words x1261 and xF025 at word address x3000, byte address x6000
+        let image = read(":046000001261F02514\n:00000001FF\n").unwrap();
+        assert_eq!(image, Image::new(0x3000, vec![0x1261, 0xF025]));
+    }
+
+    #[test]
+    fn test_extended_linear_address_reaches_high_memory() {
+        // Byte address x1FFFC is word address xFFFE
+        let image = read(":020000040001F9\n:02FFFC00ABCD8B\n:00000001FF\n").unwrap();
+        assert_eq!(image, Image::new(0xFFFE, vec![0xABCD]));
+    }
+
+    #[test]
+    fn test_write_crosses_into_the_upper_page() {
+        let image = Image::new(0x7FFC, (0..16).collect());
+        let text = write(&image);
+        assert!(text.contains(":020000040001F9\n"));
+        assert_eq!(read(&text).unwrap(), image);
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_bad_records_are_reported_by_line() {
+        match read(":00000001FF\n:046000001261F02515\n") {
+            Err(ImageError::Syntax { line: 2, .. }) => {}
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+        match read(":046000001261F02515\n:00000001FF\n") {
+            Err(ImageError::Checksum {
+                line: 1,
+                expected: 0x14,
+                found: 0x15,
+            }) => {}
+            other => panic!("expected a checksum error, got {:?}", other),
+        }
+        assert_eq!(
+            read(":046000001261F02514\n").unwrap_err().to_string(),
+            "line 1: missing the end-of-file record"
+        );
+        assert_eq!(
+            read(":0460000012\n").unwrap_err().to_string(),
+            "line 1: record length does not match its byte count"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Reading and writing memory images in the formats LC-3 tools exchange.
+
+pub mod binary;
+pub mod intel_hex;
+pub mod text;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Words to place at consecutive addresses starting at `origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+/// The contents of an image file. Most formats hold a single segment; Intel
+/// HEX files may leave gaps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+}
+
+impl Image {
+    pub fn new(origin: u16, words: Vec<u16>) -> Image {
+        Image {
+            segments: vec![Segment { origin, words }],
+        }
+    }
+
+    /// The only segment, for formats that cannot express gaps.
+    fn contiguous(&self, format: Format) -> Result<&Segment, ImageError> {
+        match self.segments.as_slice() {
+            [segment] => Ok(segment),
+            segments => Err(ImageError::NotContiguous {
+                format,
+                segments: segments.len(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A big-endian origin word followed by big-endian words.
+    Object,
+    /// One word per line as four hex digits, the origin first.
+    Hex,
+    /// One word per line as sixteen binary digits, the origin first.
+    Bin,
+    IntelHex,
+    /// Little-endian words without an origin, loaded at an address given
+    /// separately.
+    Raw,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Object => "obj",
+            Format::Hex => "hex",
+            Format::Bin => "bin",
+            Format::IntelHex => "ihex",
+            Format::Raw => "raw",
+        }
+    }
+
+    /// The format a file name promises. `.hex` and `.bin` are ambiguous, so
+    /// `detect` also looks at the contents.
+    pub fn from_extension(path: &str) -> Option<Format> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "obj" => Some(Format::Object),
+            "hex" => Some(Format::Hex),
+            "bin" => Some(Format::Bin),
+            "ihex" | "ihx" | "mcs" => Some(Format::IntelHex),
+            "raw" => Some(Format::Raw),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [
+            Format::Object,
+            Format::Hex,
+            Format::Bin,
+            Format::IntelHex,
+            Format::Raw,
+        ]
+        .into_iter()
+        .find(|format| format.name() == name)
+        .ok_or_else(|| {
+            format!(
+                "unknown image format '{}', expected obj, hex, bin, ihex or raw",
+                name
+            )
+        })
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    Empty,
+    /// A binary image ends partway through a word.
+    OddLength(usize),
+    /// A text image has a malformed line.
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    Checksum {
+        line: usize,
+        expected: u8,
+        found: u8,
+    },
+    MissingLoadAddress,
+    /// The words run past the top of memory.
+    TooLong {
+        origin: u16,
+        words: usize,
+    },
+    NotContiguous {
+        format: Format,
+        segments: usize,
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::Empty => write!(f, "the image is empty"),
+            ImageError::OddLength(bytes) => {
+                write!(f, "{} bytes is not a whole number of words", bytes)
+            }
+            ImageError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            ImageError::Checksum {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: checksum is {:02X}, expected {:02X}",
+                line, found, expected
+            ),
+            ImageError::MissingLoadAddress => {
+                write!(f, "raw images need a load address (--load-at)")
+            }
+            ImageError::TooLong { origin, words } => write!(
+                f,
+                "{} words at x{:04X} run past the end of memory",
+                words, origin
+            ),
+            ImageError::NotContiguous { format, segments } => write!(
+                f,
+                "{} images hold one block of words, this image has {}",
+                format, segments
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+fn check_fits(origin: u16, words: usize) -> Result<(), ImageError> {
+    if origin as usize + words > 0x10000 {
+        return Err(ImageError::TooLong { origin, words });
+    }
+    Ok(())
+}
+
+/// Picks the format of an image from its contents and name. Intel HEX and
+/// the text formats are recognised by their contents, and a text file named
+/// `.hex` or `.bin` is read as one so its mistakes get reported. Anything
+/// else is an object file unless the name says it is raw.
+pub fn detect(path: &str, bytes: &[u8]) -> Format {
+    let by_name = Format::from_extension(path);
+    let contents = std::str::from_utf8(bytes).ok().filter(|text| {
+        text.chars()
+            .all(|c| c.is_ascii_graphic() || c.is_ascii_whitespace())
+    });
+    if let Some(contents) = contents {
+        if intel_hex::looks_like(contents) {
+            return Format::IntelHex;
+        }
+        if let Some(format @ (Format::Hex | Format::Bin)) = by_name {
+            return format;
+        }
+        if text::looks_like(contents, Format::Bin) {
+            return Format::Bin;
+        }
+        if text::looks_like(contents, Format::Hex) {
+            return Format::Hex;
+        }
+    }
+    match by_name {
+        Some(Format::Raw | Format::Bin) => Format::Raw,
+        Some(format @ (Format::Hex | Format::IntelHex)) => format,
+        _ => Format::Object,
+    }
+}
+
+/// Decodes an image. `load_at` is where raw images go; other formats carry
+/// their own origin.
+pub fn read(bytes: &[u8], format: Format, load_at: Option<u16>) -> Result<Image, ImageError> {
+    let image = match format {
+        Format::Object => binary::read_object(bytes)?,
+        Format::Raw => binary::read_raw(bytes, load_at.ok_or(ImageError::MissingLoadAddress)?)?,
+        Format::Hex | Format::Bin => text::read(&text_of(bytes)?, format)?,
+        Format::IntelHex => intel_hex::read(&text_of(bytes)?)?,
+    };
+    for segment in &image.segments {
+        check_fits(segment.origin, segment.words.len())?;
+    }
+    Ok(image)
+}
+
+fn text_of(bytes: &[u8]) -> Result<String, ImageError> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| ImageError::Syntax {
+        line: bytes[..e.utf8_error().valid_up_to()]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count()
+            + 1,
+        message: "not a text file".to_string(),
+    })
+}
+
+/// Reads `path`, detecting the format unless one is given.
+pub fn read_file(
+    path: &str,
+    format: Option<Format>,
+    load_at: Option<u16>,
+) -> Result<Image, ImageError> {
+    let bytes = fs::read(path)?;
+    let format = format.unwrap_or_else(|| detect(path, &bytes));
+    read(&bytes, format, load_at)
+}
+
+pub fn write(image: &Image, format: Format) -> Result<Vec<u8>, ImageError> {
+    Ok(match format {
+        Format::Object => binary::write_object(image.contiguous(format)?),
+        Format::Raw => binary::write_raw(image.contiguous(format)?),
+        Format::Hex | Format::Bin => text::write(image.contiguous(format)?, format).into_bytes(),
+        Format::IntelHex => intel_hex::write(image).into_bytes(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::{detect, read, write, Format, Image, ImageError, Segment};
+
+    // ========== Detection ==========
+
+    #[test]
+    fn test_detects_by_content_before_name() {
+        assert_eq!(detect("a.obj", &[0x30, 0x00, 0xF0, 0x25]), Format::Object);
+        assert_eq!(detect("a.hex", b"3000\nF025\n"), Format::Hex);
+        assert_eq!(detect("a.hex", b":00000001FF\n"), Format::IntelHex);
+        assert_eq!(
+            detect("a.txt", b"0011000000000000\n1111000000100101\n"),
+            Format::Bin
+        );
+        assert_eq!(detect("a.bin", &[0x25, 0xF0]), Format::Raw);
+        assert_eq!(detect("a.raw", b"3000\n"), Format::Hex);
+        assert_eq!(detect("typo.hex", b"3000\nZZZZ\n"), Format::Hex);
+        assert_eq!(detect("image", &[0x30, 0x00]), Format::Object);
+    }
+
+    #[test]
+    fn test_format_names_round_trip() {
+        for name in ["obj", "hex", "bin", "ihex", "raw"] {
+            assert_eq!(name.parse::<Format>().unwrap().name(), name);
+        }
+        assert!("elf".parse::<Format>().is_err());
+    }
+
+    // ========== Round Trips ==========
+
+    #[test]
+    fn test_every_format_round_trips() {
+        let image = Image::new(0x3000, vec![0x1261, 0xF025, 0x0000, 0xFFFF]);
+        for format in [Format::Object, Format::Hex, Format::Bin, Format::IntelHex] {
+            let bytes = write(&image, format).unwrap();
+            assert_eq!(detect("image", &bytes), format);
+            assert_eq!(read(&bytes, format, None).unwrap(), image, "{}", format);
+        }
+        let raw = write(&image, Format::Raw).unwrap();
+        assert_eq!(read(&raw, Format::Raw, Some(0x3000)).unwrap(), image);
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_images_must_fit_in_memory() {
+        let error = read(&[0xFF, 0xFF, 0x12, 0x34, 0x56, 0x78], Format::Object, None);
+        assert!(matches!(
+            error,
+            Err(ImageError::TooLong {
+                origin: 0xFFFF,
+                words: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_gaps_only_fit_intel_hex() {
+        let image = Image {
+            segments: vec![
+                Segment {
+                    origin: 0x3000,
+                    words: vec![1],
+                },
+                Segment {
+                    origin: 0x4000,
+                    words: vec![2],
+                },
+            ],
+        };
+        assert_eq!(
+            write(&image, Format::Hex).unwrap_err().to_string(),
+            "hex images hold one block of words, this image has 2"
+        );
+        let bytes = write(&image, Format::IntelHex).unwrap();
+        assert_eq!(read(&bytes, Format::IntelHex, None).unwrap(), image);
+    }
+}
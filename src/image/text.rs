@@ -0,0 +1,112 @@
+use crate::image::{Format, Image, ImageError, Segment};
+
+/// Digits per word and their radix in `format`, which is `Hex` or `Bin`.
+fn layout(format: Format) -> (usize, u32) {
+    match format {
+        Format::Bin => (16, 2),
+        _ => (4, 16),
+    }
+}
+
+/// The meaningful lines: `;` starts a comment and blank lines are skipped.
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.split(';').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn word(line: &str, format: Format) -> Option<u16> {
+    let (digits, radix) = layout(format);
+    if line.len() != digits || !line.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u16::from_str_radix(line, radix).ok()
+}
+
+/// Whether every line of `text` is a word in `format`.
+pub fn looks_like(text: &str, format: Format) -> bool {
+    let mut lines = lines(text).peekable();
+    lines.peek().is_some() && lines.all(|(_, line)| word(line, format).is_some())
+}
+
+/// Reads the `.hex` or `.bin` text format: one word per line, origin first.
+pub fn read(text: &str, format: Format) -> Result<Image, ImageError> {
+    let (digits, radix) = layout(format);
+    let mut words = Vec::new();
+    for (number, line) in lines(text) {
+        let word = word(line, format).ok_or_else(|| ImageError::Syntax {
+            line: number,
+            message: format!(
+                "expected {} {} digits, got '{}'",
+                digits,
+                if radix == 2 { "binary" } else { "hex" },
+                line
+            ),
+        })?;
+        words.push(word);
+    }
+
+    if words.is_empty() {
+        return Err(ImageError::Empty);
+    }
+    let origin = words.remove(0);
+    Ok(Image::new(origin, words))
+}
+
+pub fn write(segment: &Segment, format: Format) -> String {
+    std::iter::once(segment.origin)
+        .chain(segment.words.iter().copied())
+        .map(|word| match format {
+            Format::Bin => format!("{:016b}\n", word),
+            _ => format!("{:04X}\n", word),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::text::{read, write};
+    use crate::image::{Format, Image};
+
+    // ========== Reading ==========
+
+    #[test]
+    fn test_reads_hex_and_bin_with_comments() {
+        let image = Image::new(0x3000, vec![0xF025]);
+        assert_eq!(
+            read("; hello\n3000\n\nf025 ; HALT\n", Format::Hex).unwrap(),
+            image
+        );
+        assert_eq!(
+            read("0011000000000000\n1111000000100101\n", Format::Bin).unwrap(),
+            image
+        );
+    }
+
+    #[test]
+    fn test_bad_lines_are_reported_by_number() {
+        let error = read("3000\nF025\n12345\n", Format::Hex).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 3: expected 4 hex digits, got '12345'"
+        );
+        let error = read("0011000000000002\n", Format::Bin).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1: expected 16 binary digits, got '0011000000000002'"
+        );
+    }
+
+    // ========== Writing ==========
+
+    #[test]
+    fn test_writes_the_origin_first() {
+        let image = Image::new(0x3000, vec![0x1261]);
+        assert_eq!(write(&image.segments[0], Format::Hex), "3000\n1261\n");
+        assert_eq!(
+            write(&image.segments[0], Format::Bin),
+            "0011000000000000\n0001001001100001\n"
+        );
+    }
+}
@@ -23,6 +23,7 @@ pub fn add(registers: &mut [u16; (Count as u16) as usize], instruction: u16) {
 mod tests {
     use crate::instructions::add::add;
     use crate::registers::register::Register;
+    use crate::registers::ConditionFlag;
     use crate::Vm;
 
     // ========== Immediate Mode Tests ==========
@@ -256,4 +257,38 @@ mod tests {
 
         assert_eq!(vm.registers[Register::R2 as usize], 17);
     }
+
+    // ========== Condition Codes ==========
+
+    // `update_flags` once set N only for a result of exactly 1 and P for
+    // every negative result
+
+    #[test]
+    fn should_add_set_negative_flag_for_negative_result() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::R3, 2);
+
+        // R2 = R3 + (-5)
+        add(&mut vm.registers, 0b0001_010_011_1_11011);
+
+        assert_eq!(vm.registers[Register::R2 as usize], 0xFFFD);
+        assert_eq!(
+            vm.registers[Register::Cond as usize],
+            ConditionFlag::Neg as u16
+        );
+    }
+
+    #[test]
+    fn should_add_set_positive_flag_for_result_of_one() {
+        let mut vm = Vm::new();
+
+        // R2 = R3 + 1
+        add(&mut vm.registers, 0b0001_010_011_1_00001);
+
+        assert_eq!(vm.registers[Register::R2 as usize], 1);
+        assert_eq!(
+            vm.registers[Register::Cond as usize],
+            ConditionFlag::Pos as u16
+        );
+    }
 }
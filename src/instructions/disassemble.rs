@@ -0,0 +1,196 @@
+use crate::instructions::opcodes::Opcode;
+use crate::instructions::sign_extend;
+use crate::instructions::trap::{TRAP_GETC, TRAP_HALT, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP};
+
+/// Renders a single instruction word as LC-3 assembly. `address` is the
+/// location of the word, so PC-relative operands are shown as absolute targets.
+pub fn disassemble(address: u16, instruction: u16) -> String {
+    let opcode = match Opcode::get(instruction >> 12) {
+        Some(opcode) => opcode,
+        None => return format!(".FILL x{:04X}", instruction),
+    };
+
+    let dr = (instruction >> 9) & 0x7;
+    let sr1 = (instruction >> 6) & 0x7;
+    let next_pc = address.wrapping_add(1);
+
+    match opcode {
+        Opcode::Br => {
+            let nzp = (instruction >> 9) & 0x7;
+            if nzp == 0 {
+                return "NOP".to_string();
+            }
+            let mut mnemonic = String::from("BR");
+            if nzp & 0b100 != 0 {
+                mnemonic.push('n');
+            }
+            if nzp & 0b010 != 0 {
+                mnemonic.push('z');
+            }
+            if nzp & 0b001 != 0 {
+                mnemonic.push('p');
+            }
+            format!("{} x{:04X}", mnemonic, pc_target(next_pc, instruction, 9))
+        }
+        Opcode::Add | Opcode::And => {
+            let mnemonic = if opcode == Opcode::Add { "ADD" } else { "AND" };
+            if (instruction >> 5) & 0x1 == 1 {
+                let imm5 = sign_extend(instruction & 0x1F, 5) as i16;
+                format!("{} R{}, R{}, #{}", mnemonic, dr, sr1, imm5)
+            } else {
+                format!("{} R{}, R{}, R{}", mnemonic, dr, sr1, instruction & 0x7)
+            }
+        }
+        Opcode::Ld => format!("LD R{}, x{:04X}", dr, pc_target(next_pc, instruction, 9)),
+        Opcode::Ldi => format!("LDI R{}, x{:04X}", dr, pc_target(next_pc, instruction, 9)),
+        Opcode::Lea => format!("LEA R{}, x{:04X}", dr, pc_target(next_pc, instruction, 9)),
+        Opcode::St => format!("ST R{}, x{:04X}", dr, pc_target(next_pc, instruction, 9)),
+        Opcode::Sti => format!("STI R{}, x{:04X}", dr, pc_target(next_pc, instruction, 9)),
+        Opcode::Ldr | Opcode::Str => {
+            let mnemonic = if opcode == Opcode::Ldr { "LDR" } else { "STR" };
+            let offset_6 = sign_extend(instruction & 0x3F, 6) as i16;
+            format!("{} R{}, R{}, #{}", mnemonic, dr, sr1, offset_6)
+        }
+        Opcode::Jsr => {
+            if (instruction >> 11) & 0x1 == 1 {
+                format!("JSR x{:04X}", pc_target(next_pc, instruction, 11))
+            } else {
+                format!("JSRR R{}", sr1)
+            }
+        }
+        Opcode::Jmp => {
+            if sr1 == 7 {
+                "RET".to_string()
+            } else {
+                format!("JMP R{}", sr1)
+            }
+        }
+        Opcode::Not => format!("NOT R{}, R{}", dr, sr1),
+        Opcode::Rti => "RTI".to_string(),
+        Opcode::Res => format!(".FILL x{:04X}", instruction),
+        Opcode::Trap => match instruction & 0xFF {
+            TRAP_GETC => "GETC".to_string(),
+            TRAP_OUT => "OUT".to_string(),
+            TRAP_PUTS => "PUTS".to_string(),
+            TRAP_IN => "IN".to_string(),
+            TRAP_PUTSP => "PUTSP".to_string(),
+            TRAP_HALT => "HALT".to_string(),
+            vector => format!("TRAP x{:02X}", vector),
+        },
+    }
+}
+
+/// Resolves the PC-relative target of an instruction with a `bit_count`-wide offset.
+pub fn pc_target(next_pc: u16, instruction: u16, bit_count: u16) -> u16 {
+    let mask = (1 << bit_count) - 1;
+    next_pc.wrapping_add(sign_extend(instruction & mask, bit_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instructions::disassemble::{disassemble, pc_target};
+
+    // ========== Operate Instructions ==========
+
+    #[test]
+    fn test_disassemble_add_register_mode() {
+        // ADD R1, R2, R3
+        assert_eq!(
+            disassemble(0x3000, 0b0001_001_010_0_00_011),
+            "ADD R1, R2, R3"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_add_negative_immediate() {
+        // ADD R1, R1, #-1
+        assert_eq!(
+            disassemble(0x3000, 0b0001_001_001_1_11111),
+            "ADD R1, R1, #-1"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_and_clear() {
+        // AND R0, R0, #0
+        assert_eq!(
+            disassemble(0x3000, 0b0101_000_000_1_00000),
+            "AND R0, R0, #0"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_not() {
+        assert_eq!(disassemble(0x3000, 0b1001_011_100_111111), "NOT R3, R4");
+    }
+
+    // ========== Control Flow ==========
+
+    #[test]
+    fn test_disassemble_branch_resolves_target() {
+        // BRnp #-3 at x3005 jumps to x3003
+        assert_eq!(disassemble(0x3005, 0b0000_101_111111101), "BRnp x3003");
+    }
+
+    #[test]
+    fn test_disassemble_branch_with_empty_mask_is_nop() {
+        assert_eq!(disassemble(0x3000, 0b0000_000_000000101), "NOP");
+    }
+
+    #[test]
+    fn test_disassemble_ret_and_jmp() {
+        assert_eq!(disassemble(0x3000, 0b1100_000_111_000000), "RET");
+        assert_eq!(disassemble(0x3000, 0b1100_000_010_000000), "JMP R2");
+    }
+
+    #[test]
+    fn test_disassemble_jsr_and_jsrr() {
+        // JSR #16 at x3000
+        assert_eq!(disassemble(0x3000, 0b0100_1_00000010000), "JSR x3011");
+        assert_eq!(disassemble(0x3000, 0b0100_0_00_101_000000), "JSRR R5");
+    }
+
+    // ========== Memory Instructions ==========
+
+    #[test]
+    fn test_disassemble_pc_relative_loads_and_stores() {
+        assert_eq!(disassemble(0x3000, 0b0010_000_000000100), "LD R0, x3005");
+        assert_eq!(disassemble(0x3000, 0b1010_001_000000000), "LDI R1, x3001");
+        assert_eq!(disassemble(0x3000, 0b1110_010_111111111), "LEA R2, x3000");
+        assert_eq!(disassemble(0x3000, 0b0011_011_000000001), "ST R3, x3002");
+        assert_eq!(disassemble(0x3000, 0b1011_100_000000001), "STI R4, x3002");
+    }
+
+    #[test]
+    fn test_disassemble_base_offset_loads_and_stores() {
+        assert_eq!(
+            disassemble(0x3000, 0b0110_001_110_111110),
+            "LDR R1, R6, #-2"
+        );
+        assert_eq!(disassemble(0x3000, 0b0111_111_110_000000), "STR R7, R6, #0");
+    }
+
+    // ========== Traps and Reserved ==========
+
+    #[test]
+    fn test_disassemble_trap_aliases() {
+        assert_eq!(disassemble(0x3000, 0xF020), "GETC");
+        assert_eq!(disassemble(0x3000, 0xF021), "OUT");
+        assert_eq!(disassemble(0x3000, 0xF022), "PUTS");
+        assert_eq!(disassemble(0x3000, 0xF023), "IN");
+        assert_eq!(disassemble(0x3000, 0xF024), "PUTSP");
+        assert_eq!(disassemble(0x3000, 0xF025), "HALT");
+        assert_eq!(disassemble(0x3000, 0xF030), "TRAP x30");
+    }
+
+    #[test]
+    fn test_disassemble_reserved_and_rti() {
+        assert_eq!(disassemble(0x3000, 0xD123), ".FILL xD123");
+        assert_eq!(disassemble(0x3000, 0x8000), "RTI");
+    }
+
+    #[test]
+    fn test_pc_target_wraps_around_memory() {
+        assert_eq!(pc_target(0xFFFF, 0x0002, 9), 0x0001);
+    }
+}
@@ -1,26 +1,22 @@
 use crate::instructions::{sign_extend, update_flags};
-use crate::registers::register::Register::{Count, Pc};
-use crate::MEMORY_MAX;
-
-pub fn ldi(
-    registers: &mut [u16; (Count as u16) as usize],
-    memory: &[u16; MEMORY_MAX],
-    instruction: u16,
-) {
+use crate::registers::register::Register::Pc;
+use crate::Vm;
+
+pub fn ldi(vm: &mut Vm, instruction: u16) {
     let destination_register = (instruction >> 9) & 0x7;
     let pc_offset_9 = sign_extend(instruction & 0x1FF, 9);
 
-    let address_of_value_to_load =
-        memory[registers[(Pc as u16) as usize].wrapping_add(pc_offset_9) as usize];
-    registers[destination_register as usize] = memory[address_of_value_to_load as usize];
-    update_flags(registers, destination_register)
+    let address_of_value_to_load = vm.mem_read(vm.registers[Pc as usize].wrapping_add(pc_offset_9));
+    vm.registers[destination_register as usize] = vm.mem_read(address_of_value_to_load);
+    update_flags(&mut vm.registers, destination_register)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::instructions::ldi::ldi;
-    use crate::registers::register::Register;
+    use crate::registers::register::{MemoryMappedRegister, Register};
     use crate::Vm;
+    use std::collections::VecDeque;
 
     // ========== Basic Functionality Tests ==========
 
@@ -32,7 +28,7 @@ mod tests {
         vm.mem_write(0x4000, 42); // Actual value
 
         // LDI R2, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_010_000000000);
+        ldi(&mut vm, 0b1010_010_000000000);
 
         assert_eq!(vm.registers[Register::R2 as usize], 42);
     }
@@ -45,7 +41,7 @@ mod tests {
         vm.mem_write(0x4000, 123); // Actual value
 
         // LDI R3, 5
-        ldi(&mut vm.registers, &vm.memory, 0b1010_011_000000101);
+        ldi(&mut vm, 0b1010_011_000000101);
 
         assert_eq!(vm.registers[Register::R3 as usize], 123);
     }
@@ -58,7 +54,7 @@ mod tests {
         vm.mem_write(0x5000, 99); // Actual value
 
         // LDI R1, -8 (offset = 0x1F8 in 9-bit two's complement)
-        ldi(&mut vm.registers, &vm.memory, 0b1010_001_111111000);
+        ldi(&mut vm, 0b1010_001_111111000);
 
         assert_eq!(vm.registers[Register::R1 as usize], 99);
     }
@@ -71,7 +67,7 @@ mod tests {
         vm.mem_write(0x4000, 77); // Actual value
 
         // LDI R4, 255 (max positive 9-bit offset)
-        ldi(&mut vm.registers, &vm.memory, 0b1010_100_011111111);
+        ldi(&mut vm, 0b1010_100_011111111);
 
         assert_eq!(vm.registers[Register::R4 as usize], 77);
     }
@@ -84,7 +80,7 @@ mod tests {
         vm.mem_write(0x4000, 88); // Actual value
 
         // LDI R5, -256 (max negative 9-bit offset)
-        ldi(&mut vm.registers, &vm.memory, 0b1010_101_100000000);
+        ldi(&mut vm, 0b1010_101_100000000);
 
         assert_eq!(vm.registers[Register::R5 as usize], 88);
     }
@@ -99,7 +95,7 @@ mod tests {
         vm.mem_write(0x4000, 11);
 
         // LDI R0, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_000_000000000);
+        ldi(&mut vm, 0b1010_000_000000000);
 
         assert_eq!(vm.registers[Register::R0 as usize], 11);
     }
@@ -112,7 +108,7 @@ mod tests {
         vm.mem_write(0x4000, 22);
 
         // LDI R7, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_111_000000000);
+        ldi(&mut vm, 0b1010_111_000000000);
 
         assert_eq!(vm.registers[Register::R7 as usize], 22);
     }
@@ -127,7 +123,7 @@ mod tests {
         vm.mem_write(0x4000, 0);
 
         // LDI R2, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_010_000000000);
+        ldi(&mut vm, 0b1010_010_000000000);
 
         assert_eq!(vm.registers[Register::R2 as usize], 0);
     }
@@ -140,7 +136,7 @@ mod tests {
         vm.mem_write(0x4000, 0x7FFF); // Max positive 16-bit signed value
 
         // LDI R3, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_011_000000000);
+        ldi(&mut vm, 0b1010_011_000000000);
 
         assert_eq!(vm.registers[Register::R3 as usize], 0x7FFF);
     }
@@ -153,7 +149,7 @@ mod tests {
         vm.mem_write(0x4000, 0xFFFF); // -1 in two's complement
 
         // LDI R4, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_100_000000000);
+        ldi(&mut vm, 0b1010_100_000000000);
 
         assert_eq!(vm.registers[Register::R4 as usize], 0xFFFF);
     }
@@ -166,7 +162,7 @@ mod tests {
         vm.mem_write(0x4000, 0xFFFF);
 
         // LDI R5, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_101_000000000);
+        ldi(&mut vm, 0b1010_101_000000000);
 
         assert_eq!(vm.registers[Register::R5 as usize], 0xFFFF);
     }
@@ -181,7 +177,7 @@ mod tests {
         vm.mem_write(0x0010, 55); // Value in low memory
 
         // LDI R6, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_110_000000000);
+        ldi(&mut vm, 0b1010_110_000000000);
 
         assert_eq!(vm.registers[Register::R6 as usize], 55);
     }
@@ -194,7 +190,7 @@ mod tests {
         vm.mem_write(0xFE00, 66); // Value in high memory
 
         // LDI R1, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_001_000000000);
+        ldi(&mut vm, 0b1010_001_000000000);
 
         assert_eq!(vm.registers[Register::R1 as usize], 66);
     }
@@ -209,7 +205,7 @@ mod tests {
         vm.mem_write(0x6000, 111);
 
         // LDI R2, 3
-        ldi(&mut vm.registers, &vm.memory, 0b1010_010_000000011);
+        ldi(&mut vm, 0b1010_010_000000011);
 
         assert_eq!(vm.registers[Register::R2 as usize], 111);
     }
@@ -222,7 +218,7 @@ mod tests {
         vm.mem_write(0x1000, 222);
 
         // LDI R3, 1
-        ldi(&mut vm.registers, &vm.memory, 0b1010_011_000000001);
+        ldi(&mut vm, 0b1010_011_000000001);
 
         assert_eq!(vm.registers[Register::R3 as usize], 222);
     }
@@ -238,7 +234,7 @@ mod tests {
         vm.mem_write(0x4000, 42);
 
         // LDI R2, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_010_000000000);
+        ldi(&mut vm, 0b1010_010_000000000);
 
         assert_eq!(vm.registers[Register::R2 as usize], 42);
     }
@@ -259,11 +255,11 @@ mod tests {
         vm.mem_write(0x4100, 20);
 
         // First LDI R1, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_001_000000000);
+        ldi(&mut vm, 0b1010_001_000000000);
         assert_eq!(vm.registers[Register::R1 as usize], 10);
 
         // Second LDI R2, 1
-        ldi(&mut vm.registers, &vm.memory, 0b1010_010_000000001);
+        ldi(&mut vm, 0b1010_010_000000001);
         assert_eq!(vm.registers[Register::R2 as usize], 20);
     }
 
@@ -277,7 +273,7 @@ mod tests {
 
         // This is weird but valid - the pointer value is what matters
         // LDI R4, 0
-        ldi(&mut vm.registers, &vm.memory, 0b1010_100_000000000);
+        ldi(&mut vm, 0b1010_100_000000000);
 
         assert_eq!(vm.registers[Register::R4 as usize], 33);
     }
@@ -292,7 +288,7 @@ mod tests {
         vm.mem_write(0x4000, 44);
 
         // LDI R5, -5 (0x1FB in 9-bit two's complement)
-        ldi(&mut vm.registers, &vm.memory, 0b1010_101_111111011);
+        ldi(&mut vm, 0b1010_101_111111011);
 
         assert_eq!(vm.registers[Register::R5 as usize], 44);
     }
@@ -305,8 +301,27 @@ mod tests {
         vm.mem_write(0x4000, 55);
 
         // LDI R6, -1 (0x1FF in 9-bit two's complement)
-        ldi(&mut vm.registers, &vm.memory, 0b1010_110_111111111);
+        ldi(&mut vm, 0b1010_110_111111111);
 
         assert_eq!(vm.registers[Register::R6 as usize], 55);
     }
+
+    // ========== Device Register Tests ==========
+
+    #[test]
+    fn should_poll_keyboard_through_pointer() {
+        let mut vm = Vm::new();
+        vm.input = Some(VecDeque::from([u16::from(b'y')]));
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, MemoryMappedRegister::MR_KBSR as u16);
+        vm.mem_write(0x3001, MemoryMappedRegister::MR_KBDR as u16);
+
+        // LDI R0, 0 (KBSR, like the usual polling loop)
+        ldi(&mut vm, 0b1010_000_000000000);
+        // LDI R1, 1 (KBDR)
+        ldi(&mut vm, 0b1010_001_000000001);
+
+        assert_eq!(vm.registers[Register::R0 as usize], 0x8000);
+        assert_eq!(vm.registers[Register::R1 as usize], u16::from(b'y'));
+    }
 }
@@ -1,27 +1,23 @@
 use crate::instructions::{sign_extend, update_flags};
-use crate::registers::register::Register::Count;
-use crate::MEMORY_MAX;
-
-pub fn ldr(
-    registers: &mut [u16; (Count as u16) as usize],
-    memory: &[u16; MEMORY_MAX],
-    instruction: u16,
-) {
+use crate::Vm;
+
+pub fn ldr(vm: &mut Vm, instruction: u16) {
     let destination_register = (instruction >> 9) & 0x7;
     let base_register = (instruction >> 6) & 0x7;
     let offset_6 = sign_extend(instruction & 0x3F, 6);
 
-    registers[destination_register as usize] =
-        memory[registers[base_register as usize].wrapping_add(offset_6) as usize];
+    vm.registers[destination_register as usize] =
+        vm.mem_read(vm.registers[base_register as usize].wrapping_add(offset_6));
 
-    update_flags(registers, destination_register);
+    update_flags(&mut vm.registers, destination_register);
 }
 
 #[cfg(test)]
 mod tests {
     use crate::instructions::load_register::ldr;
-    use crate::registers::register::Register;
+    use crate::registers::register::{MemoryMappedRegister, Register};
     use crate::Vm;
+    use std::collections::VecDeque;
 
     // ========== Basic LDR Operations ==========
 
@@ -32,7 +28,7 @@ mod tests {
         vm.mem_write(0x3005, 42);
 
         // LDR R2, R1, 5 (load from R1 + 5)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000101);
+        ldr(&mut vm, 0b0110_010_001_000101);
 
         assert_eq!(vm.registers[Register::R2 as usize], 42);
     }
@@ -44,7 +40,7 @@ mod tests {
         vm.mem_write(0x3000, 123);
 
         // LDR R4, R3, 0 (load from R3 + 0)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_100_011_000000);
+        ldr(&mut vm, 0b0110_100_011_000000);
 
         assert_eq!(vm.registers[Register::R4 as usize], 123);
     }
@@ -56,7 +52,7 @@ mod tests {
         vm.mem_write(0x3008, 99);
 
         // LDR R2, R1, -8 (0x38 in 6-bit two's complement)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_111000);
+        ldr(&mut vm, 0b0110_010_001_111000);
 
         assert_eq!(vm.registers[Register::R2 as usize], 99);
     }
@@ -68,7 +64,7 @@ mod tests {
         vm.mem_write(0x301F, 255);
 
         // LDR R1, R0, 31 (max positive 6-bit offset)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_001_000_011111);
+        ldr(&mut vm, 0b0110_001_000_011111);
 
         assert_eq!(vm.registers[Register::R1 as usize], 255);
     }
@@ -80,7 +76,7 @@ mod tests {
         vm.mem_write(0x3000, 77);
 
         // LDR R3, R2, -32 (max negative 6-bit offset)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_011_010_100000);
+        ldr(&mut vm, 0b0110_011_010_100000);
 
         assert_eq!(vm.registers[Register::R3 as usize], 77);
     }
@@ -94,7 +90,7 @@ mod tests {
         vm.mem_write(0x3001, 111);
 
         // LDR R0, R1, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_000_001_000001);
+        ldr(&mut vm, 0b0110_000_001_000001);
 
         assert_eq!(vm.registers[Register::R0 as usize], 111);
     }
@@ -106,7 +102,7 @@ mod tests {
         vm.mem_write(0x3002, 222);
 
         // LDR R7, R6, 2
-        ldr(&mut vm.registers, &vm.memory, 0b0110_111_110_000010);
+        ldr(&mut vm, 0b0110_111_110_000010);
 
         assert_eq!(vm.registers[Register::R7 as usize], 222);
     }
@@ -120,7 +116,7 @@ mod tests {
         vm.mem_write(0x4005, 333);
 
         // LDR R1, R0, 5
-        ldr(&mut vm.registers, &vm.memory, 0b0110_001_000_000101);
+        ldr(&mut vm, 0b0110_001_000_000101);
 
         assert_eq!(vm.registers[Register::R1 as usize], 333);
     }
@@ -132,7 +128,7 @@ mod tests {
         vm.mem_write(0x5003, 444);
 
         // LDR R2, R1, 3
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000011);
+        ldr(&mut vm, 0b0110_010_001_000011);
 
         assert_eq!(vm.registers[Register::R2 as usize], 444);
     }
@@ -144,7 +140,7 @@ mod tests {
         vm.mem_write(0x6007, 555);
 
         // LDR R3, R2, 7
-        ldr(&mut vm.registers, &vm.memory, 0b0110_011_010_000111);
+        ldr(&mut vm, 0b0110_011_010_000111);
 
         assert_eq!(vm.registers[Register::R3 as usize], 555);
     }
@@ -156,7 +152,7 @@ mod tests {
         vm.mem_write(0x7002, 666);
 
         // LDR R4, R3, 2
-        ldr(&mut vm.registers, &vm.memory, 0b0110_100_011_000010);
+        ldr(&mut vm, 0b0110_100_011_000010);
 
         assert_eq!(vm.registers[Register::R4 as usize], 666);
     }
@@ -168,7 +164,7 @@ mod tests {
         vm.mem_write(0x8004, 777);
 
         // LDR R5, R4, 4
-        ldr(&mut vm.registers, &vm.memory, 0b0110_101_100_000100);
+        ldr(&mut vm, 0b0110_101_100_000100);
 
         assert_eq!(vm.registers[Register::R5 as usize], 777);
     }
@@ -180,7 +176,7 @@ mod tests {
         vm.mem_write(0x9001, 888);
 
         // LDR R6, R5, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_110_101_000001);
+        ldr(&mut vm, 0b0110_110_101_000001);
 
         assert_eq!(vm.registers[Register::R6 as usize], 888);
     }
@@ -192,7 +188,7 @@ mod tests {
         vm.mem_write(0xA006, 999);
 
         // LDR R7, R6, 6
-        ldr(&mut vm.registers, &vm.memory, 0b0110_111_110_000110);
+        ldr(&mut vm, 0b0110_111_110_000110);
 
         assert_eq!(vm.registers[Register::R7 as usize], 999);
     }
@@ -204,7 +200,7 @@ mod tests {
         vm.mem_write(0xB003, 1111);
 
         // LDR R0, R7, 3
-        ldr(&mut vm.registers, &vm.memory, 0b0110_000_111_000011);
+        ldr(&mut vm, 0b0110_000_111_000011);
 
         assert_eq!(vm.registers[Register::R0 as usize], 1111);
     }
@@ -218,7 +214,7 @@ mod tests {
         vm.mem_write(0x3005, 42);
 
         // LDR R3, R3, 5 (load into same register used as base)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_011_011_000101);
+        ldr(&mut vm, 0b0110_011_011_000101);
 
         assert_eq!(vm.registers[Register::R3 as usize], 42);
     }
@@ -232,7 +228,7 @@ mod tests {
         vm.mem_write(0x3001, 0);
 
         // LDR R2, R1, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000001);
+        ldr(&mut vm, 0b0110_010_001_000001);
 
         assert_eq!(vm.registers[Register::R2 as usize], 0);
     }
@@ -244,7 +240,7 @@ mod tests {
         vm.mem_write(0x3001, 0x7FFF);
 
         // LDR R4, R3, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_100_011_000001);
+        ldr(&mut vm, 0b0110_100_011_000001);
 
         assert_eq!(vm.registers[Register::R4 as usize], 0x7FFF);
     }
@@ -256,7 +252,7 @@ mod tests {
         vm.mem_write(0x3001, 0xFFFF);
 
         // LDR R6, R5, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_110_101_000001);
+        ldr(&mut vm, 0b0110_110_101_000001);
 
         assert_eq!(vm.registers[Register::R6 as usize], 0xFFFF);
     }
@@ -272,7 +268,7 @@ mod tests {
             vm.mem_write(0x3000 + i as u16, value);
 
             let instruction = 0b0110_001_000_000000 | (i as u16);
-            ldr(&mut vm.registers, &vm.memory, instruction);
+            ldr(&mut vm, instruction);
 
             assert_eq!(vm.registers[Register::R1 as usize], value);
         }
@@ -290,7 +286,7 @@ mod tests {
             vm.mem_write(base + 10, 42);
 
             // LDR R2, R1, 10
-            ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_001010);
+            ldr(&mut vm, 0b0110_010_001_001010);
 
             assert_eq!(vm.registers[Register::R2 as usize], 42);
         }
@@ -303,7 +299,7 @@ mod tests {
         vm.mem_write(0x0015, 99);
 
         // LDR R3, R2, 5
-        ldr(&mut vm.registers, &vm.memory, 0b0110_011_010_000101);
+        ldr(&mut vm, 0b0110_011_010_000101);
 
         assert_eq!(vm.registers[Register::R3 as usize], 99);
     }
@@ -315,7 +311,7 @@ mod tests {
         vm.mem_write(0xFE10, 88);
 
         // LDR R5, R4, 16
-        ldr(&mut vm.registers, &vm.memory, 0b0110_101_100_010000);
+        ldr(&mut vm, 0b0110_101_100_010000);
 
         assert_eq!(vm.registers[Register::R5 as usize], 88);
     }
@@ -330,7 +326,7 @@ mod tests {
         vm.mem_write(0x3005, 42);
 
         // LDR R2, R1, 5
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000101);
+        ldr(&mut vm, 0b0110_010_001_000101);
 
         // Base register should be unchanged
         assert_eq!(vm.registers[Register::R1 as usize], base_address);
@@ -345,7 +341,7 @@ mod tests {
         vm.mem_write(0x3005, 42);
 
         // LDR R2, R1, 5
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000101);
+        ldr(&mut vm, 0b0110_010_001_000101);
 
         // Check other registers unchanged
         assert_eq!(vm.registers[Register::R0 as usize], 0x1111);
@@ -362,7 +358,7 @@ mod tests {
         vm.mem_write(0x3005, 42);
 
         // LDR R2, R1, 5
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000101);
+        ldr(&mut vm, 0b0110_010_001_000101);
 
         assert_eq!(vm.registers[Register::R2 as usize], 42);
     }
@@ -378,15 +374,15 @@ mod tests {
         vm.mem_write(0x3003, 30);
 
         // LDR R1, R0, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_001_000_000001);
+        ldr(&mut vm, 0b0110_001_000_000001);
         assert_eq!(vm.registers[Register::R1 as usize], 10);
 
         // LDR R2, R0, 2
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_000_000010);
+        ldr(&mut vm, 0b0110_010_000_000010);
         assert_eq!(vm.registers[Register::R2 as usize], 20);
 
         // LDR R3, R0, 3
-        ldr(&mut vm.registers, &vm.memory, 0b0110_011_000_000011);
+        ldr(&mut vm, 0b0110_011_000_000011);
         assert_eq!(vm.registers[Register::R3 as usize], 30);
     }
 
@@ -404,7 +400,7 @@ mod tests {
         // Load array elements
         for i in 0..10 {
             let instruction = 0b0110_001_000_000000 | (i as u16);
-            ldr(&mut vm.registers, &vm.memory, instruction);
+            ldr(&mut vm, instruction);
             assert_eq!(vm.registers[Register::R1 as usize], i * 10);
         }
     }
@@ -418,7 +414,7 @@ mod tests {
         vm.mem_write(0x3000, 55);
 
         // LDR R5, R4, -1 (0x3F in 6-bit two's complement)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_101_100_111111);
+        ldr(&mut vm, 0b0110_101_100_111111);
 
         assert_eq!(vm.registers[Register::R5 as usize], 55);
     }
@@ -430,7 +426,7 @@ mod tests {
         vm.mem_write(0x3001, 66);
 
         // LDR R7, R6, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_111_110_000001);
+        ldr(&mut vm, 0b0110_111_110_000001);
 
         assert_eq!(vm.registers[Register::R7 as usize], 66);
     }
@@ -445,7 +441,7 @@ mod tests {
         vm.mem_write(pointer, 123);
 
         // LDR R2, R1, 0 (dereference pointer)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000000);
+        ldr(&mut vm, 0b0110_010_001_000000);
 
         assert_eq!(vm.registers[Register::R2 as usize], 123);
     }
@@ -462,11 +458,11 @@ mod tests {
         vm.mem_write(struct_base + 2, 300); // field 2
 
         // Load field 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_001_000_000001);
+        ldr(&mut vm, 0b0110_001_000_000001);
         assert_eq!(vm.registers[Register::R1 as usize], 200);
 
         // Load field 2
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_000_000010);
+        ldr(&mut vm, 0b0110_010_000_000010);
         assert_eq!(vm.registers[Register::R2 as usize], 300);
     }
 
@@ -479,7 +475,7 @@ mod tests {
         vm.mem_write(0xFFFF, 0xDEAD);
 
         // LDR R0, R7, 31
-        ldr(&mut vm.registers, &vm.memory, 0b0110_000_111_011111);
+        ldr(&mut vm, 0b0110_000_111_011111);
 
         assert_eq!(vm.registers[Register::R0 as usize], 0xDEAD);
     }
@@ -491,7 +487,7 @@ mod tests {
         vm.mem_write(0x0000, 0xBEEF);
 
         // LDR R2, R1, -5 (0x3B in 6-bit two's complement)
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_111011);
+        ldr(&mut vm, 0b0110_010_001_111011);
 
         assert_eq!(vm.registers[Register::R2 as usize], 0xBEEF);
     }
@@ -505,7 +501,7 @@ mod tests {
         vm.mem_write(0x3001, 0xAAAA);
 
         // LDR R2, R1, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000001);
+        ldr(&mut vm, 0b0110_010_001_000001);
 
         assert_eq!(vm.registers[Register::R2 as usize], 0xAAAA);
     }
@@ -517,7 +513,7 @@ mod tests {
         vm.mem_write(0x3001, 0xFFFF);
 
         // LDR R4, R3, 1
-        ldr(&mut vm.registers, &vm.memory, 0b0110_100_011_000001);
+        ldr(&mut vm, 0b0110_100_011_000001);
 
         assert_eq!(vm.registers[Register::R4 as usize], 0xFFFF);
     }
@@ -532,7 +528,7 @@ mod tests {
         vm.mem_write(0x3005, 42);
 
         // LDR R2, R1, 5
-        ldr(&mut vm.registers, &vm.memory, 0b0110_010_001_000101);
+        ldr(&mut vm, 0b0110_010_001_000101);
 
         assert_eq!(vm.registers[Register::R2 as usize], 42);
 
@@ -554,13 +550,43 @@ mod tests {
         vm.mem_write(string_base + 2, 0x0043); // 'C'
 
         // Load characters
-        ldr(&mut vm.registers, &vm.memory, 0b0110_001_000_000000);
+        ldr(&mut vm, 0b0110_001_000_000000);
         assert_eq!(vm.registers[Register::R1 as usize], 0x0041);
 
-        ldr(&mut vm.registers, &vm.memory, 0b0110_001_000_000001);
+        ldr(&mut vm, 0b0110_001_000_000001);
         assert_eq!(vm.registers[Register::R1 as usize], 0x0042);
 
-        ldr(&mut vm.registers, &vm.memory, 0b0110_001_000_000010);
+        ldr(&mut vm, 0b0110_001_000_000010);
         assert_eq!(vm.registers[Register::R1 as usize], 0x0043);
     }
+
+    // ========== Device Register Tests ==========
+
+    #[test]
+    fn test_ldr_polls_keyboard_status() {
+        let mut vm = Vm::new();
+        vm.input = Some(VecDeque::from([u16::from(b'y')]));
+        vm.write_to_register(Register::R2, MemoryMappedRegister::MR_KBSR as u16);
+
+        // LDR R0, R2, 0 (KBSR)
+        ldr(&mut vm, 0b0110_000_010_000000);
+        // LDR R1, R2, 2 (KBDR)
+        ldr(&mut vm, 0b0110_001_010_000010);
+
+        assert_eq!(vm.registers[Register::R0 as usize], 0x8000);
+        assert_eq!(vm.registers[Register::R1 as usize], u16::from(b'y'));
+    }
+
+    #[test]
+    fn test_ldr_sees_empty_keyboard_as_not_ready() {
+        let mut vm = Vm::new();
+        vm.input = Some(VecDeque::new());
+        vm.write_to_register(Register::R2, MemoryMappedRegister::MR_KBSR as u16);
+        vm.mem_write(MemoryMappedRegister::MR_KBSR as u16, 0x8000);
+
+        // LDR R0, R2, 0
+        ldr(&mut vm, 0b0110_000_010_000000);
+
+        assert_eq!(vm.registers[Register::R0 as usize], 0);
+    }
 }
@@ -4,6 +4,7 @@ use crate::registers::ConditionFlag;
 pub mod add;
 pub mod and;
 pub mod branch;
+pub mod disassemble;
 pub mod jump;
 pub mod jump_register;
 pub mod ldi;
@@ -12,12 +13,13 @@ pub mod load_effective;
 pub mod load_register;
 pub mod not;
 pub mod opcodes;
+pub mod return_from_interrupt;
 pub mod store;
 pub mod store_indirect;
 pub mod store_register;
 pub mod trap;
 
-fn sign_extend(input: u16, bit_count: u16) -> u16 {
+pub fn sign_extend(input: u16, bit_count: u16) -> u16 {
     let sign_bit = input >> (bit_count - 1);
 
     if sign_bit & 1 == 1 {
@@ -27,17 +29,22 @@ fn sign_extend(input: u16, bit_count: u16) -> u16 {
     input
 }
 
-fn update_flags(registers: &mut [u16; (Register::Count as u16) as usize], r: u16) {
+/// Sets the condition codes from register `r`: N when its sign bit is set,
+/// Z when it is zero and P otherwise.
+pub fn update_flags(registers: &mut [u16; (Register::Count as u16) as usize], r: u16) {
     match registers[r as usize] {
         0 => registers[Register::Cond as usize] = ConditionFlag::Zro as u16,
-        1 => registers[Register::Cond as usize] = ConditionFlag::Neg as u16,
+        value if value >> 15 == 1 => registers[Register::Cond as usize] = ConditionFlag::Neg as u16,
         _ => registers[Register::Cond as usize] = ConditionFlag::Pos as u16,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::instructions::sign_extend;
+    use crate::instructions::{sign_extend, update_flags};
+    use crate::registers::register::Register;
+    use crate::registers::ConditionFlag;
+    use crate::Vm;
 
     #[test]
     fn should_sign_extend_5bit_positive_number() {
@@ -77,4 +84,30 @@ mod tests {
         assert_eq!(sign_extend(0, 9), 0);
         assert_eq!(sign_extend(0, 11), 0);
     }
+
+    #[test]
+    fn test_update_flags_sets_negative_for_sign_bit() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::R1, 0x8000);
+
+        update_flags(&mut vm.registers, Register::R1 as u16);
+
+        assert_eq!(
+            vm.registers[Register::Cond as usize],
+            ConditionFlag::Neg as u16
+        );
+    }
+
+    #[test]
+    fn test_update_flags_sets_positive_for_one() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::R1, 1);
+
+        update_flags(&mut vm.registers, Register::R1 as u16);
+
+        assert_eq!(
+            vm.registers[Register::Cond as usize],
+            ConditionFlag::Pos as u16
+        );
+    }
 }
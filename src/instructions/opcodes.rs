@@ -1,4 +1,5 @@
 #[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Opcode {
     Br = 0,    /* branch */
     Add = 1,   /* add  */
@@ -40,4 +41,25 @@ impl Opcode {
             _ => None,
         }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcode::Br => "BR",
+            Opcode::Add => "ADD",
+            Opcode::Ld => "LD",
+            Opcode::St => "ST",
+            Opcode::Jsr => "JSR",
+            Opcode::And => "AND",
+            Opcode::Ldr => "LDR",
+            Opcode::Str => "STR",
+            Opcode::Rti => "RTI",
+            Opcode::Not => "NOT",
+            Opcode::Ldi => "LDI",
+            Opcode::Sti => "STI",
+            Opcode::Jmp => "JMP",
+            Opcode::Res => "RES",
+            Opcode::Lea => "LEA",
+            Opcode::Trap => "TRAP",
+        }
+    }
 }
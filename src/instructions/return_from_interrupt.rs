@@ -0,0 +1,81 @@
+use crate::machine::Privilege;
+use crate::registers::register::Register::{Cond, Pc, R6};
+use crate::Vm;
+
+/// Pops the PC and then the PSR off the stack in R6. Only supervisor code
+/// may return from an interrupt, so in user mode the machine stops instead.
+/// The saved user stack pointer is not modelled: R6 keeps pointing just
+/// past the popped words.
+pub fn rti(vm: &mut Vm) -> bool {
+    if vm.machine.privilege != Privilege::Supervisor {
+        return false;
+    }
+
+    let stack = vm.registers[R6 as usize];
+    let pc = vm.mem_read(stack);
+    let psr = vm.mem_read(stack.wrapping_add(1));
+    vm.registers[R6 as usize] = stack.wrapping_add(2);
+    vm.registers[Pc as usize] = pc;
+    vm.registers[Cond as usize] = psr & 0x7;
+    vm.machine.privilege = if psr & 0x8000 != 0 {
+        Privilege::User
+    } else {
+        Privilege::Supervisor
+    };
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instructions::return_from_interrupt::rti;
+    use crate::machine::Privilege;
+    use crate::registers::register::Register;
+    use crate::registers::ConditionFlag;
+    use crate::Vm;
+
+    // ========== Basic RTI Operations ==========
+
+    #[test]
+    fn test_rti_stops_in_user_mode() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3001);
+        vm.write_to_register(Register::R6, 0x2FFE);
+
+        assert!(!rti(&mut vm));
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3001);
+        assert_eq!(vm.registers[Register::R6 as usize], 0x2FFE);
+    }
+
+    #[test]
+    fn test_rti_pops_pc_and_psr() {
+        let mut vm = Vm::new();
+        vm.machine.privilege = Privilege::Supervisor;
+        vm.write_to_register(Register::R6, 0x2FFE);
+        vm.mem_write(0x2FFE, 0x3005); // saved PC
+        vm.mem_write(0x2FFF, 0x8000 | ConditionFlag::Neg as u16); // user mode, N
+
+        assert!(rti(&mut vm));
+
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3005);
+        assert_eq!(vm.registers[Register::R6 as usize], 0x3000);
+        assert_eq!(
+            vm.registers[Register::Cond as usize],
+            ConditionFlag::Neg as u16
+        );
+        assert_eq!(vm.machine.privilege, Privilege::User);
+    }
+
+    #[test]
+    fn test_rti_can_stay_in_supervisor_mode() {
+        let mut vm = Vm::new();
+        vm.machine.privilege = Privilege::Supervisor;
+        vm.write_to_register(Register::R6, 0x2FFE);
+        vm.mem_write(0x2FFE, 0x0400);
+        vm.mem_write(0x2FFF, ConditionFlag::Zro as u16);
+
+        assert!(rti(&mut vm));
+
+        assert_eq!(vm.registers[Register::Pc as usize], 0x0400);
+        assert_eq!(vm.machine.privilege, Privilege::Supervisor);
+    }
+}
@@ -1,56 +1,70 @@
 use crate::instructions::update_flags;
+use crate::limits::StopReason;
+use crate::machine::TrapMode;
 use crate::registers::register::Register::{Pc, R0, R7};
 use crate::Vm;
-use std::io::{Read, Write};
-use std::{io, process};
 
-const TRAP_GETC: u16 = 0x20; /* get character from keyboard, not echoed onto the terminal */
-const TRAP_OUT: u16 = 0x21; /* output a character */
-const TRAP_PUTS: u16 = 0x22; /* output a word string */
-const TRAP_IN: u16 = 0x23; /* get character from keyboard, echoed onto the terminal */
-const TRAP_PUTSP: u16 = 0x24; /* output a byte string */
-const TRAP_HALT: u16 = 0x25; /* halt the program */
-
-pub fn trap(vm: &mut Vm, instruction: u16) {
+pub const TRAP_GETC: u16 = 0x20; /* get character from keyboard, not echoed onto the terminal */
+pub const TRAP_OUT: u16 = 0x21; /* output a character */
+pub const TRAP_PUTS: u16 = 0x22; /* output a word string */
+pub const TRAP_IN: u16 = 0x23; /* get character from keyboard, echoed onto the terminal */
+pub const TRAP_PUTSP: u16 = 0x24; /* output a byte string */
+pub const TRAP_HALT: u16 = 0x25; /* halt the program */
+
+/// Runs a trap routine natively, or jumps through the trap vector table
+/// when the machine has an OS. Returns `false` once the program halts, or
+/// on a vector with no routine or a read past the end of the scripted
+/// input, which `vm.stop_reason` then names.
+pub fn trap(vm: &mut Vm, instruction: u16) -> bool {
     vm.registers[R7 as usize] = vm.registers[Pc as usize];
+    if vm.machine.traps == TrapMode::Os {
+        vm.registers[Pc as usize] = vm.memory[(instruction & 0xFF) as usize];
+        return true;
+    }
 
     match instruction & 0xFF {
         TRAP_GETC => {
-            // Use the VM's get_char which works with raw console mode
-            let c = vm.get_char();
+            // Use the VM's read_char which works with raw console mode
+            let Some(c) = vm.read_char() else {
+                vm.stop_reason = Some(StopReason::InputExhausted);
+                return false;
+            };
             vm.registers[R0 as usize] = c;
             update_flags(&mut vm.registers, R0 as u16);
         }
         TRAP_OUT => {
-            print!("{}", vm.registers[R0 as usize] as u8 as char);
-            io::stdout().flush().expect("Could not flush stdout");
+            let c = vm.registers[R0 as usize] as u8 as char;
+            vm.write_output(&c.to_string());
         }
         TRAP_PUTS => {
             let mut memory_address = vm.registers[R0 as usize];
+            let mut text = String::new();
             loop {
                 let character = vm.memory[memory_address as usize];
                 if character == 0x0000 {
                     break;
                 }
                 let c = (character & 0xFF) as u8;
-                print!("{}", c as char);
+                text.push(c as char);
                 memory_address += 1;
             }
-            io::stdout().flush().expect("Could not flush stdout");
+            vm.write_output(&text);
         }
         TRAP_IN => {
-            print!("Enter a character: ");
-            io::stdout().flush().unwrap();
-
-            // Use the VM's get_char
-            let c = vm.get_char();
-            print!("{}", c as u8 as char); // Echo the character
-            io::stdout().flush().unwrap();
+            vm.write_output("Enter a character: ");
+
+            // Use the VM's read_char
+            let Some(c) = vm.read_char() else {
+                vm.stop_reason = Some(StopReason::InputExhausted);
+                return false;
+            };
+            vm.write_output(&(c as u8 as char).to_string()); // Echo the character
             vm.registers[R0 as usize] = c;
             update_flags(&mut vm.registers, R0 as u16);
         }
         TRAP_PUTSP => {
             let mut memory_address = vm.registers[R0 as usize];
+            let mut text = String::new();
             loop {
                 let word = vm.memory[memory_address as usize];
                 if word == 0x0000 {
@@ -59,38 +73,44 @@ pub fn trap(vm: &mut Vm, instruction: u16) {
 
                 let c1 = (word & 0xFF) as u8;
                 if c1 != 0 {
-                    print!("{}", c1 as char);
+                    text.push(c1 as char);
                 } else {
                     break;
                 }
 
                 let c2 = ((word >> 8) & 0xFF) as u8;
                 if c2 != 0 {
-                    print!("{}", c2 as char);
+                    text.push(c2 as char);
                 } else {
                     break;
                 }
 
                 memory_address += 1;
             }
-            io::stdout().flush().unwrap();
+            vm.write_output(&text);
         }
         TRAP_HALT => {
-            println!("\n--- HALT ---");
-            io::stdout().flush().unwrap();
-            process::exit(1);
+            if !vm.quiet_halt {
+                vm.write_output("\n--- HALT ---\n");
+            }
+            return false;
         }
-        _ => {
-            eprintln!("Unknown trap code: 0x{:02X}", instruction & 0xFF);
-            process::exit(1);
+        vector => {
+            vm.stop_reason = Some(StopReason::UnknownTrap(vector as u8));
+            return false;
         }
     }
+
+    true
 }
 #[cfg(test)]
 mod tests {
     use crate::instructions::trap::trap;
+    use crate::limits::StopReason;
+    use crate::machine::TrapMode;
     use crate::registers::register::Register;
     use crate::Vm;
+    use std::collections::VecDeque;
 
     // ========== Basic TRAP Operations ==========
 
@@ -208,6 +228,43 @@ mod tests {
         assert_eq!(vm.registers[Register::R7 as usize], 0x3000);
     }
 
+    #[test]
+    fn test_trap_halt_stops_execution() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.write_to_register(Register::R0, 0x0041);
+
+        // TRAP x25 (HALT) stops, TRAP x21 (OUT) keeps running
+        assert!(!trap(&mut vm, 0b1111_0000_00100101));
+        assert!(trap(&mut vm, 0b1111_0000_00100001));
+    }
+
+    #[test]
+    fn test_trap_halt_banner_can_be_silenced() {
+        let mut vm = Vm::new();
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+
+        assert!(!trap(&mut vm, 0b1111_0000_00100101));
+        assert_eq!(vm.output.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_trap_jumps_through_vector_table_with_an_os() {
+        let mut vm = Vm::new();
+        vm.machine.traps = TrapMode::Os;
+        vm.output = Some(String::new());
+        vm.write_to_register(Register::Pc, 0x3001);
+        vm.mem_write(0x0025, 0x0520);
+
+        // TRAP x25 (HALT) runs the OS routine instead of stopping
+        assert!(trap(&mut vm, 0b1111_0000_00100101));
+
+        assert_eq!(vm.registers[Register::Pc as usize], 0x0520);
+        assert_eq!(vm.registers[Register::R7 as usize], 0x3001);
+        assert_eq!(vm.output.as_deref(), Some(""));
+    }
+
     // ========== Different Trap Vectors ==========
 
     #[test]
@@ -481,6 +538,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unknown_trap_stops_the_machine_with_a_reason() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+
+        assert!(!trap(&mut vm, 0xF030));
+        assert_eq!(vm.stop_reason, Some(StopReason::UnknownTrap(0x30)));
+
+        vm.stop_reason = None;
+        vm.mem_write(0x3000, 0xF0FF);
+        vm.write_to_register(Register::Pc, 0x3000);
+        assert_eq!(vm.run(), StopReason::UnknownTrap(0xFF));
+        assert_eq!(vm.stop_reason, None);
+    }
+
+    #[test]
+    fn test_reading_past_the_scripted_input_stops_with_a_reason() {
+        for instruction in [0xF020, 0xF023] {
+            let mut vm = Vm::new();
+            vm.input = Some(VecDeque::new());
+            vm.output = Some(String::new());
+            vm.mem_write(0x3000, instruction);
+            vm.write_to_register(Register::Pc, 0x3000);
+
+            assert_eq!(vm.run(), StopReason::InputExhausted);
+        }
+    }
+
     // ========== Boundary Trap Vectors ==========
 
     #[test]
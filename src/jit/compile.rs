@@ -0,0 +1,428 @@
+use crate::blocks::translate::{Block, MicroOp};
+use crate::jit::emitter::{
+    Assembler, Condition, Memory, R12, R13, R14, R15, R8, RAX, RBP, RBX, RCX, RDI, RDX, RSI,
+};
+use crate::registers::register::{MemoryMappedRegister, Register};
+use crate::registers::ConditionFlag;
+
+/// Set in the returned word when the block stopped in front of an
+/// instruction the interpreter has to run.
+pub const FALLBACK: u32 = 1 << 31;
+
+/// Instructions a chain of blocks may run before control goes back to the
+/// driver.
+pub const CHAIN_BUDGET: u32 = 1 << 16;
+
+/// Generated code is entered with the VM registers, the VM memory, the
+/// per-address count of compiled blocks, so stores can spot code, and the
+/// table of block entry points.
+pub type Enter = extern "sysv64" fn(*mut u16, *mut u16, *const u8, *const usize) -> u32;
+
+const REGISTERS: u8 = RDI;
+const MEMORY: u8 = RSI;
+const COVERED: u8 = RBX;
+const TABLE: u8 = RDX;
+/// Instructions run since entering, plus `FALLBACK` once it is set.
+const EXECUTED: u8 = RBP;
+
+const KBSR: u32 = MemoryMappedRegister::MR_KBSR as u32;
+
+/// LC-3 registers R0-R7 live in r8d-r15d while blocks run.
+fn host(register: u8) -> u8 {
+    R8 + register
+}
+
+fn register_slot(register: usize) -> Memory {
+    Memory::Offset(REGISTERS, (register * 2) as i8)
+}
+
+fn memory_word() -> Memory {
+    Memory::Indexed(MEMORY, RAX, 2)
+}
+
+/// Offsets of the code shared by all blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stubs {
+    /// An `Enter` function that loads the registers and jumps to the block
+    /// at the PC, which has to be compiled.
+    pub enter: usize,
+    /// Where blocks jump to write the registers back and return.
+    pub leave: usize,
+    /// The offset just past the stubs.
+    pub end: usize,
+}
+
+/// Emits the shared stubs for placement at `origin`.
+pub fn stubs(origin: usize) -> (Vec<u8>, Stubs) {
+    let mut asm = Assembler::at(origin);
+
+    let enter = asm.position();
+    for register in [RBX, RBP, R12, R13, R14, R15] {
+        asm.push(register);
+    }
+    asm.mov64(COVERED, RDX);
+    asm.mov64(TABLE, RCX);
+    asm.mov_immediate(EXECUTED, 0);
+    for register in 0..8 {
+        asm.load16(host(register), register_slot(register as usize));
+    }
+    asm.load16(RCX, register_slot(Register::Pc as usize));
+    asm.load64(RAX, Memory::Indexed(TABLE, RCX, 8));
+    asm.jump_register(RAX);
+
+    let leave = asm.position();
+    for register in 0..8 {
+        asm.store16(register_slot(register as usize), host(register));
+    }
+    asm.mov(RAX, EXECUTED);
+    for register in [R15, R14, R13, R12, RBP, RBX] {
+        asm.pop(register);
+    }
+    asm.ret();
+
+    let end = asm.position();
+    (asm.code, Stubs { enter, leave, end })
+}
+
+/// Compiles `block` for placement at `origin`. The code runs the block and
+/// then jumps straight into the block compiled at the new PC, if there is
+/// one. It leaves through `leave` when there is none, when the chain has
+/// used up `CHAIN_BUDGET`, or with `FALLBACK` set in front of a trap, a
+/// keyboard status read or a store into compiled code. The PC is left at
+/// the next instruction to run either way.
+pub fn compile(block: &Block, origin: usize, leave: usize) -> Vec<u8> {
+    let mut compiler = Compiler {
+        asm: Assembler::at(origin),
+        flags: None,
+        leave,
+    };
+
+    let mut executed = 0;
+    for &(address, op) in &block.ops {
+        let next = address.wrapping_add(op.instructions());
+        if !compiler.op(op, address, next, executed) {
+            return compiler.asm.code;
+        }
+        executed += op.instructions() as u32;
+    }
+
+    compiler.store_flags();
+    compiler.asm.mov_immediate(RCX, block.end as u16 as u32);
+    compiler.chain(executed);
+    compiler.asm.code
+}
+
+struct Compiler {
+    asm: Assembler,
+    /// The register the condition codes were last set from. Writing COND
+    /// is deferred until the block is left.
+    flags: Option<u8>,
+    leave: usize,
+}
+
+impl Compiler {
+    /// Writes COND from the last flag-setting register, leaving it in eax.
+    fn store_flags(&mut self) {
+        let Some(register) = self.flags else {
+            return;
+        };
+        self.asm.mov_immediate(RAX, ConditionFlag::Pos as u32);
+        self.asm.mov_immediate(RCX, ConditionFlag::Zro as u32);
+        self.asm.test16(host(register), host(register));
+        self.asm.cmov(Condition::Equal, RAX, RCX);
+        self.asm.mov_immediate(RCX, ConditionFlag::Neg as u32);
+        self.asm.cmov(Condition::Sign, RAX, RCX);
+        self.asm
+            .store16(register_slot(Register::Cond as usize), RAX);
+    }
+
+    /// Continues at the PC in ecx, chaining into the block compiled there.
+    fn chain(&mut self, executed: u32) {
+        self.asm.store16(register_slot(Register::Pc as usize), RCX);
+        self.asm.add_immediate(EXECUTED, executed);
+        self.asm.cmp_immediate(EXECUTED, CHAIN_BUDGET);
+        self.asm.jump_to(Some(Condition::AboveOrEqual), self.leave);
+        self.asm.load64(RAX, Memory::Indexed(TABLE, RCX, 8));
+        self.asm.test64(RAX, RAX);
+        self.asm.jump_to(Some(Condition::Equal), self.leave);
+        self.asm.jump_register(RAX);
+    }
+
+    /// Leaves in front of the instruction at `address`.
+    fn fallback(&mut self, address: u16, executed: u32) {
+        self.store_flags();
+        self.asm
+            .store16_immediate(register_slot(Register::Pc as usize), address);
+        self.asm.add_immediate(EXECUTED, executed);
+        self.asm.or_immediate(EXECUTED, FALLBACK);
+        self.asm.jump_to(None, self.leave);
+    }
+
+    /// Falls back unless `condition` is false for the value just compared.
+    fn fallback_if(&mut self, condition: Condition, address: u16, executed: u32) {
+        let inverse = match condition {
+            Condition::Equal => Condition::NotEqual,
+            _ => Condition::Equal,
+        };
+        let skip = self.asm.jump_forward(inverse);
+        self.fallback(address, executed);
+        self.asm.bind(skip);
+    }
+
+    /// Loads the word at the address in eax into `dr`, unless it is the
+    /// keyboard status register, which only the interpreter can read.
+    fn load(&mut self, dr: u8, address: u16, executed: u32) {
+        self.asm.cmp_immediate(RAX, KBSR);
+        self.fallback_if(Condition::Equal, address, executed);
+        self.asm.load16(host(dr), memory_word());
+        self.flags = Some(dr);
+    }
+
+    /// Stores `sr` to the address in eax, unless compiled code lives there.
+    fn store(&mut self, sr: u8, address: u16, executed: u32) {
+        self.asm.cmp8_immediate(Memory::Indexed(COVERED, RAX, 1), 0);
+        self.fallback_if(Condition::NotEqual, address, executed);
+        self.asm.store16(memory_word(), host(sr));
+    }
+
+    /// Continues at `target` or `next` depending on COND.
+    fn branch(&mut self, nzp: u8, target: u16, next: u16, executed: u32) {
+        if self.flags.is_some() {
+            self.store_flags();
+        } else {
+            self.asm.load16(RAX, register_slot(Register::Cond as usize));
+        }
+        self.asm.test_immediate(RAX, nzp as u32);
+        self.asm.mov_immediate(RCX, target as u32);
+        let taken = self.asm.jump_forward(Condition::NotEqual);
+        self.asm.mov_immediate(RCX, next as u32);
+        self.asm.bind(taken);
+        self.chain(executed);
+    }
+
+    /// Emits one operation. Returns `false` once the block has been left
+    /// unconditionally.
+    fn op(&mut self, op: MicroOp, address: u16, next: u16, executed: u32) -> bool {
+        let done = executed + op.instructions() as u32;
+        let asm = &mut self.asm;
+        match op {
+            MicroOp::AddRegister { dr, sr1, sr2 } => {
+                asm.mov(RAX, host(sr1));
+                asm.add(RAX, host(sr2));
+                asm.movzx16(host(dr), RAX);
+                self.flags = Some(dr);
+            }
+            MicroOp::AddImmediate { dr, sr, value } => {
+                asm.mov(RAX, host(sr));
+                asm.add_immediate(RAX, value as u32);
+                asm.movzx16(host(dr), RAX);
+                self.flags = Some(dr);
+            }
+            MicroOp::AndRegister { dr, sr1, sr2 } => {
+                asm.mov(RAX, host(sr1));
+                asm.and(RAX, host(sr2));
+                asm.mov(host(dr), RAX);
+                self.flags = Some(dr);
+            }
+            MicroOp::AndImmediate { dr, sr, value } => {
+                asm.mov(RAX, host(sr));
+                asm.and_immediate(RAX, value as u32);
+                asm.mov(host(dr), RAX);
+                self.flags = Some(dr);
+            }
+            MicroOp::Not { dr, sr } => {
+                asm.mov(RAX, host(sr));
+                asm.xor_immediate(RAX, 0xFFFF);
+                asm.mov(host(dr), RAX);
+                self.flags = Some(dr);
+            }
+            MicroOp::LoadImmediate { dr, value, .. } => {
+                asm.mov_immediate(host(dr), value as u32);
+                self.flags = Some(dr);
+            }
+            MicroOp::Ld {
+                dr,
+                address: source,
+            } => {
+                asm.mov_immediate(RAX, source as u32);
+                self.load(dr, address, executed);
+            }
+            MicroOp::Ldi {
+                dr,
+                address: pointer,
+            } => {
+                if pointer as u32 == KBSR {
+                    self.fallback(address, executed);
+                    return false;
+                }
+                asm.mov_immediate(RAX, pointer as u32);
+                asm.load16(RAX, memory_word());
+                self.load(dr, address, executed);
+            }
+            MicroOp::Ldr { dr, base, offset } => {
+                asm.mov(RAX, host(base));
+                asm.add_immediate(RAX, offset as u32);
+                asm.movzx16(RAX, RAX);
+                self.load(dr, address, executed);
+            }
+            MicroOp::St {
+                sr,
+                address: target,
+            } => {
+                asm.mov_immediate(RAX, target as u32);
+                self.store(sr, address, executed);
+            }
+            MicroOp::Sti {
+                sr,
+                address: pointer,
+            } => {
+                if pointer as u32 == KBSR {
+                    self.fallback(address, executed);
+                    return false;
+                }
+                asm.mov_immediate(RAX, pointer as u32);
+                asm.load16(RAX, memory_word());
+                self.store(sr, address, executed);
+            }
+            MicroOp::Str { sr, base, offset } => {
+                asm.mov(RAX, host(base));
+                asm.add_immediate(RAX, offset as u32);
+                asm.movzx16(RAX, RAX);
+                self.store(sr, address, executed);
+            }
+            MicroOp::Branch { nzp, target } => {
+                self.branch(nzp, target, next, done);
+                return false;
+            }
+            MicroOp::AddImmediateBranch {
+                dr,
+                sr,
+                value,
+                nzp,
+                target,
+            } => {
+                asm.mov(RAX, host(sr));
+                asm.add_immediate(RAX, value as u32);
+                asm.movzx16(host(dr), RAX);
+                self.flags = Some(dr);
+                self.branch(nzp, target, next, done);
+                return false;
+            }
+            MicroOp::Jmp { base } => {
+                self.store_flags();
+                self.asm.mov(RCX, host(base));
+                self.chain(done);
+                return false;
+            }
+            MicroOp::Jsr { target } => {
+                // COND may come from R7, which is about to be overwritten
+                self.store_flags();
+                self.asm.mov_immediate(host(7), next as u32);
+                self.asm.mov_immediate(RCX, target as u32);
+                self.chain(done);
+                return false;
+            }
+            MicroOp::Jsrr { base } => {
+                self.store_flags();
+                self.asm.mov_immediate(host(7), next as u32);
+                self.asm.mov(RCX, host(base));
+                self.chain(done);
+                return false;
+            }
+            MicroOp::Trap { .. } | MicroOp::Stop => {
+                self.fallback(address, executed);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jit::compile::FALLBACK;
+    use crate::jit::Jit;
+    use crate::registers::register::Register;
+    use crate::test_support::vm_with;
+    use crate::Vm;
+
+    /// Compiles the block at x3000 alone and runs it once on `vm`.
+    fn run_once(vm: &mut Vm) -> u32 {
+        let mut jit = Jit::new().unwrap();
+        jit.compile(0x3000, &vm.memory);
+        jit.enter(vm)
+    }
+
+    // ========== Arithmetic ==========
+
+    #[test]
+    fn test_sums_wrap_to_sixteen_bits() {
+        let mut vm = vm_with(&[
+            0b0001_011_001_0_00_010, // ADD R3, R1, R2
+            0b0000_111_000000000,    // BRnzp #0
+        ]);
+        vm.write_to_register(Register::R1, 0xFFFF);
+        vm.write_to_register(Register::R2, 0x0002);
+
+        assert_eq!(run_once(&mut vm), 2);
+        assert_eq!(vm.registers[Register::R3 as usize], 0x0001);
+        assert_eq!(vm.registers[Register::Cond as usize], 1);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3002);
+    }
+
+    #[test]
+    fn test_condition_codes_come_from_the_last_result() {
+        let mut vm = vm_with(&[
+            0b1001_100_100_111111,  // NOT R4, R4
+            0b0101_101_101_1_00000, // AND R5, R5, #0
+            0b0000_010_000000011,   // BRz #3
+        ]);
+
+        run_once(&mut vm);
+
+        assert_eq!(vm.registers[Register::R4 as usize], 0xFFFF);
+        assert_eq!(vm.registers[Register::Cond as usize], 2);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3006);
+    }
+
+    // ========== Leaving the Block ==========
+
+    #[test]
+    fn test_trap_falls_back_with_pc_on_the_trap() {
+        let mut vm = vm_with(&[
+            0b0001_001_001_1_00001, // ADD R1, R1, #1
+            0xF025,                 // HALT
+        ]);
+
+        let result = run_once(&mut vm);
+
+        assert_eq!(result, FALLBACK | 1);
+        assert_eq!(vm.registers[Register::R1 as usize], 1);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3001);
+    }
+
+    #[test]
+    fn test_keyboard_status_read_falls_back() {
+        let mut vm = vm_with(&[
+            0b0110_000_001_000000, // LDR R0, R1, #0
+            0xF025,                // HALT
+        ]);
+        vm.write_to_register(Register::R1, 0xFE00);
+
+        assert_eq!(run_once(&mut vm), FALLBACK);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+    }
+
+    #[test]
+    fn test_jsrr_saves_return_address_before_jumping() {
+        let mut vm = vm_with(&[
+            0b0100_0_00_111_000000, // JSRR R7
+        ]);
+        vm.write_to_register(Register::R7, 0x4000);
+
+        run_once(&mut vm);
+
+        assert_eq!(vm.registers[Register::R7 as usize], 0x3001);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3001);
+    }
+}
@@ -0,0 +1,446 @@
+/// Host register numbers as they appear in ModRM and REX bits.
+pub const RAX: u8 = 0;
+pub const RCX: u8 = 1;
+pub const RDX: u8 = 2;
+pub const RBX: u8 = 3;
+pub const RBP: u8 = 5;
+pub const RSI: u8 = 6;
+pub const RDI: u8 = 7;
+pub const R8: u8 = 8;
+pub const R12: u8 = 12;
+pub const R13: u8 = 13;
+pub const R14: u8 = 14;
+pub const R15: u8 = 15;
+
+/// Condition codes in the low nibble of `Jcc` and `CMOVcc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    AboveOrEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    Sign = 0x8,
+}
+
+/// A memory operand. Only the addressing forms the compiler needs exist,
+/// and none of them may use `rsp`, `rbp`, `r12` or `r13` as the base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Memory {
+    /// `[base + disp8]`
+    Offset(u8, i8),
+    /// `[base + index * scale]`
+    Indexed(u8, u8, u8),
+}
+
+/// The position of a forward jump whose target is not known yet.
+pub struct Label(usize);
+
+/// Encodes the handful of x86-64 instructions the compiler emits. All
+/// register operations are 32-bit unless the name says otherwise.
+#[derive(Default)]
+pub struct Assembler {
+    pub code: Vec<u8>,
+    /// Where the code will be placed, so jumps to code outside it can be
+    /// made relative.
+    origin: usize,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Self::at(0)
+    }
+
+    /// An assembler for code that will be copied to offset `origin`.
+    pub fn at(origin: usize) -> Assembler {
+        Self {
+            code: Vec::new(),
+            origin,
+        }
+    }
+
+    /// The offset of the next instruction.
+    pub fn position(&self) -> usize {
+        self.origin + self.code.len()
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.code.push(byte);
+    }
+
+    fn imm16(&mut self, value: u16) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn imm32(&mut self, value: u32) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn rex(&mut self, wide: bool, reg: u8, index: u8, base: u8) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | (index >> 3) << 1 | base >> 3;
+        if rex != 0x40 {
+            self.byte(rex);
+        }
+    }
+
+    fn rex_memory(&mut self, wide: bool, reg: u8, memory: Memory) {
+        match memory {
+            Memory::Offset(base, _) => self.rex(wide, reg, 0, base),
+            Memory::Indexed(base, index, _) => self.rex(wide, reg, index, base),
+        }
+    }
+
+    fn modrm_register(&mut self, reg: u8, rm: u8) {
+        self.byte(0xC0 | (reg & 7) << 3 | rm & 7);
+    }
+
+    fn modrm_memory(&mut self, reg: u8, memory: Memory) {
+        match memory {
+            Memory::Offset(base, displacement) => {
+                debug_assert!(base & 7 != 4 && base & 7 != 5);
+                self.byte(0x40 | (reg & 7) << 3 | base & 7);
+                self.byte(displacement as u8);
+            }
+            Memory::Indexed(base, index, scale) => {
+                debug_assert!(base & 7 != 4 && base & 7 != 5);
+                let scale_bits = match scale {
+                    1 => 0,
+                    2 => 1,
+                    4 => 2,
+                    _ => 3,
+                };
+                self.byte((reg & 7) << 3 | 0b100);
+                self.byte(scale_bits << 6 | (index & 7) << 3 | base & 7);
+            }
+        }
+    }
+
+    // ========== Register Operations ==========
+
+    /// `mov dst, src`
+    pub fn mov(&mut self, dst: u8, src: u8) {
+        self.rex(false, src, 0, dst);
+        self.byte(0x89);
+        self.modrm_register(src, dst);
+    }
+
+    /// `mov dst, src` on the full 64-bit registers.
+    pub fn mov64(&mut self, dst: u8, src: u8) {
+        self.rex(true, src, 0, dst);
+        self.byte(0x89);
+        self.modrm_register(src, dst);
+    }
+
+    /// `mov dst, imm32`
+    pub fn mov_immediate(&mut self, dst: u8, value: u32) {
+        self.rex(false, 0, 0, dst);
+        self.byte(0xB8 + (dst & 7));
+        self.imm32(value);
+    }
+
+    /// `add dst, src`
+    pub fn add(&mut self, dst: u8, src: u8) {
+        self.rex(false, src, 0, dst);
+        self.byte(0x01);
+        self.modrm_register(src, dst);
+    }
+
+    /// `and dst, src`
+    pub fn and(&mut self, dst: u8, src: u8) {
+        self.rex(false, src, 0, dst);
+        self.byte(0x21);
+        self.modrm_register(src, dst);
+    }
+
+    fn group1(&mut self, extension: u8, dst: u8, value: u32) {
+        self.rex(false, 0, 0, dst);
+        self.byte(0x81);
+        self.modrm_register(extension, dst);
+        self.imm32(value);
+    }
+
+    /// `add dst, imm32`
+    pub fn add_immediate(&mut self, dst: u8, value: u32) {
+        self.group1(0, dst, value);
+    }
+
+    /// `or dst, imm32`
+    pub fn or_immediate(&mut self, dst: u8, value: u32) {
+        self.group1(1, dst, value);
+    }
+
+    /// `and dst, imm32`
+    pub fn and_immediate(&mut self, dst: u8, value: u32) {
+        self.group1(4, dst, value);
+    }
+
+    /// `xor dst, imm32`
+    pub fn xor_immediate(&mut self, dst: u8, value: u32) {
+        self.group1(6, dst, value);
+    }
+
+    /// `cmp dst, imm32`
+    pub fn cmp_immediate(&mut self, dst: u8, value: u32) {
+        self.group1(7, dst, value);
+    }
+
+    /// `test dst, imm32`
+    pub fn test_immediate(&mut self, dst: u8, value: u32) {
+        self.rex(false, 0, 0, dst);
+        self.byte(0xF7);
+        self.modrm_register(0, dst);
+        self.imm32(value);
+    }
+
+    /// `test a, b` on the full 64-bit registers.
+    pub fn test64(&mut self, a: u8, b: u8) {
+        self.rex(true, b, 0, a);
+        self.byte(0x85);
+        self.modrm_register(b, a);
+    }
+
+    /// `test a16, b16`, setting SF from bit 15 of the word.
+    pub fn test16(&mut self, a: u8, b: u8) {
+        self.byte(0x66);
+        self.rex(false, b, 0, a);
+        self.byte(0x85);
+        self.modrm_register(b, a);
+    }
+
+    /// `movzx dst, src16`, which also wraps a sum to 16 bits.
+    pub fn movzx16(&mut self, dst: u8, src: u8) {
+        self.rex(false, dst, 0, src);
+        self.byte(0x0F);
+        self.byte(0xB7);
+        self.modrm_register(dst, src);
+    }
+
+    /// `cmovcc dst, src`
+    pub fn cmov(&mut self, condition: Condition, dst: u8, src: u8) {
+        self.rex(false, dst, 0, src);
+        self.byte(0x0F);
+        self.byte(0x40 | condition as u8);
+        self.modrm_register(dst, src);
+    }
+
+    // ========== Memory Operations ==========
+
+    /// `movzx dst, word [memory]`
+    pub fn load16(&mut self, dst: u8, memory: Memory) {
+        self.rex_memory(false, dst, memory);
+        self.byte(0x0F);
+        self.byte(0xB7);
+        self.modrm_memory(dst, memory);
+    }
+
+    /// `mov dst, qword [memory]`
+    pub fn load64(&mut self, dst: u8, memory: Memory) {
+        self.rex_memory(true, dst, memory);
+        self.byte(0x8B);
+        self.modrm_memory(dst, memory);
+    }
+
+    /// `mov word [memory], src16`
+    pub fn store16(&mut self, memory: Memory, src: u8) {
+        self.byte(0x66);
+        self.rex_memory(false, src, memory);
+        self.byte(0x89);
+        self.modrm_memory(src, memory);
+    }
+
+    /// `mov word [memory], imm16`
+    pub fn store16_immediate(&mut self, memory: Memory, value: u16) {
+        self.byte(0x66);
+        self.rex_memory(false, 0, memory);
+        self.byte(0xC7);
+        self.modrm_memory(0, memory);
+        self.imm16(value);
+    }
+
+    /// `cmp byte [memory], imm8`
+    pub fn cmp8_immediate(&mut self, memory: Memory, value: u8) {
+        self.rex_memory(false, 0, memory);
+        self.byte(0x80);
+        self.modrm_memory(7, memory);
+        self.byte(value);
+    }
+
+    // ========== Control Flow ==========
+
+    /// `jcc rel32` to a label bound later.
+    pub fn jump_forward(&mut self, condition: Condition) -> Label {
+        self.byte(0x0F);
+        self.byte(0x80 | condition as u8);
+        self.imm32(0);
+        Label(self.code.len())
+    }
+
+    /// Points a forward jump at the current position.
+    pub fn bind(&mut self, label: Label) {
+        let relative = (self.code.len() - label.0) as u32;
+        self.code[label.0 - 4..label.0].copy_from_slice(&relative.to_le_bytes());
+    }
+
+    /// `jmp rel32`, or `jcc rel32` with a condition, to the code at offset
+    /// `target`.
+    pub fn jump_to(&mut self, condition: Option<Condition>, target: usize) {
+        match condition {
+            Some(condition) => {
+                self.byte(0x0F);
+                self.byte(0x80 | condition as u8);
+            }
+            None => self.byte(0xE9),
+        }
+        let end = self.position() + 4;
+        self.imm32((target as i64 - end as i64) as i32 as u32);
+    }
+
+    /// `jmp register`
+    pub fn jump_register(&mut self, register: u8) {
+        self.rex(false, 0, 0, register);
+        self.byte(0xFF);
+        self.modrm_register(4, register);
+    }
+
+    pub fn push(&mut self, register: u8) {
+        self.rex(false, 0, 0, register);
+        self.byte(0x50 + (register & 7));
+    }
+
+    pub fn pop(&mut self, register: u8) {
+        self.rex(false, 0, 0, register);
+        self.byte(0x58 + (register & 7));
+    }
+
+    pub fn ret(&mut self) {
+        self.byte(0xC3);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jit::emitter::{
+        Assembler, Condition, Memory, R12, R8, RAX, RBX, RCX, RDI, RDX, RSI,
+    };
+
+    fn encode(emit: impl FnOnce(&mut Assembler)) -> Vec<u8> {
+        let mut assembler = Assembler::new();
+        emit(&mut assembler);
+        assembler.code
+    }
+
+    // ========== Register Operations ==========
+
+    #[test]
+    fn test_mov_between_low_and_extended_registers() {
+        // mov eax, r9d
+        assert_eq!(encode(|a| a.mov(RAX, R8 + 1)), [0x44, 0x89, 0xC8]);
+        // mov r15d, ecx
+        assert_eq!(encode(|a| a.mov(R8 + 7, RCX)), [0x41, 0x89, 0xCF]);
+        // mov rbx, rdx
+        assert_eq!(encode(|a| a.mov64(RBX, RDX)), [0x48, 0x89, 0xD3]);
+    }
+
+    #[test]
+    fn test_immediate_forms() {
+        // mov r10d, 0x3000
+        assert_eq!(
+            encode(|a| a.mov_immediate(R8 + 2, 0x3000)),
+            [0x41, 0xBA, 0x00, 0x30, 0x00, 0x00]
+        );
+        // and eax, 0xFFFF
+        assert_eq!(
+            encode(|a| a.and_immediate(RAX, 0xFFFF)),
+            [0x81, 0xE0, 0xFF, 0xFF, 0x00, 0x00]
+        );
+        // cmp eax, 0xFE00
+        assert_eq!(
+            encode(|a| a.cmp_immediate(RAX, 0xFE00)),
+            [0x81, 0xF8, 0x00, 0xFE, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_word_operations() {
+        // movzx r8d, ax
+        assert_eq!(encode(|a| a.movzx16(R8, RAX)), [0x44, 0x0F, 0xB7, 0xC0]);
+        // test r11w, r11w
+        assert_eq!(
+            encode(|a| a.test16(R8 + 3, R8 + 3)),
+            [0x66, 0x45, 0x85, 0xDB]
+        );
+        // cmovs eax, edx
+        assert_eq!(
+            encode(|a| a.cmov(Condition::Sign, RAX, RDX)),
+            [0x0F, 0x48, 0xC2]
+        );
+    }
+
+    // ========== Memory Operations ==========
+
+    #[test]
+    fn test_memory_operands() {
+        // movzx r8d, word [rdi + 4]
+        assert_eq!(
+            encode(|a| a.load16(R8, Memory::Offset(RDI, 4))),
+            [0x44, 0x0F, 0xB7, 0x47, 0x04]
+        );
+        // mov word [rsi + rax*2], r10w
+        assert_eq!(
+            encode(|a| a.store16(Memory::Indexed(RSI, RAX, 2), R8 + 2)),
+            [0x66, 0x44, 0x89, 0x14, 0x46]
+        );
+        // mov word [rdi + 16], 0x3001
+        assert_eq!(
+            encode(|a| a.store16_immediate(Memory::Offset(RDI, 16), 0x3001)),
+            [0x66, 0xC7, 0x47, 0x10, 0x01, 0x30]
+        );
+        // cmp byte [rbx + rax], 0
+        assert_eq!(
+            encode(|a| a.cmp8_immediate(Memory::Indexed(RBX, RAX, 1), 0)),
+            [0x80, 0x3C, 0x03, 0x00]
+        );
+    }
+
+    // ========== Control Flow ==========
+
+    #[test]
+    fn test_forward_jump_is_patched_when_bound() {
+        let code = encode(|a| {
+            let label = a.jump_forward(Condition::NotEqual);
+            a.ret();
+            a.ret();
+            a.bind(label);
+        });
+
+        assert_eq!(code, [0x0F, 0x85, 0x02, 0x00, 0x00, 0x00, 0xC3, 0xC3]);
+    }
+
+    #[test]
+    fn test_jump_to_is_relative_to_the_origin() {
+        let mut assembler = Assembler::at(0x100);
+        assembler.jump_to(None, 0x80);
+        assembler.jump_to(Some(Condition::AboveOrEqual), 0x200);
+
+        assert_eq!(
+            assembler.code,
+            [0xE9, 0x7B, 0xFF, 0xFF, 0xFF, 0x0F, 0x83, 0xF5, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_indirect_jump_through_a_table() {
+        // mov rax, [rdx + rcx*8]; test rax, rax; jmp rax
+        let code = encode(|a| {
+            a.load64(RAX, Memory::Indexed(RDX, RCX, 8));
+            a.test64(RAX, RAX);
+            a.jump_register(RAX);
+        });
+
+        assert_eq!(code, [0x48, 0x8B, 0x04, 0xCA, 0x48, 0x85, 0xC0, 0xFF, 0xE0]);
+    }
+
+    #[test]
+    fn test_push_and_pop_extended_registers() {
+        assert_eq!(encode(|a| a.push(R12)), [0x41, 0x54]);
+        assert_eq!(encode(|a| a.pop(RBX)), [0x5B]);
+    }
+}
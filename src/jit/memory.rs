@@ -0,0 +1,164 @@
+use std::arch::asm;
+use std::ops::Range;
+
+const SYS_MMAP: usize = 9;
+const SYS_MPROTECT: usize = 10;
+const SYS_MUNMAP: usize = 11;
+
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const PROT_EXEC: usize = 0x4;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_ANONYMOUS: usize = 0x20;
+const PAGE_SIZE: usize = 4096;
+
+/// Raw Linux system call, so the JIT needs no libc bindings.
+unsafe fn syscall6(number: usize, arguments: [usize; 6]) -> isize {
+    let result: isize;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number as isize => result,
+            in("rdi") arguments[0],
+            in("rsi") arguments[1],
+            in("rdx") arguments[2],
+            in("r10") arguments[3],
+            in("r8") arguments[4],
+            in("r9") arguments[5],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}
+
+/// A fixed-size mapping that holds generated code. It is only writable
+/// while code is being appended and only executable otherwise.
+pub struct ExecutableMemory {
+    pointer: *mut u8,
+    capacity: usize,
+    length: usize,
+}
+
+impl ExecutableMemory {
+    /// Maps `capacity` bytes, or returns `None` if the kernel refuses.
+    pub fn new(capacity: usize) -> Option<ExecutableMemory> {
+        let result = unsafe {
+            syscall6(
+                SYS_MMAP,
+                [
+                    0,
+                    capacity,
+                    PROT_READ | PROT_EXEC,
+                    MAP_PRIVATE | MAP_ANONYMOUS,
+                    usize::MAX,
+                    0,
+                ],
+            )
+        };
+        // Errors come back as -errno
+        if (-4095..0).contains(&result) {
+            return None;
+        }
+
+        Some(Self {
+            pointer: result as *mut u8,
+            capacity,
+            length: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Copies `code` after the code already there and returns its offset,
+    /// or `None` once the mapping is full.
+    pub fn append(&mut self, code: &[u8]) -> Option<usize> {
+        if self.length + code.len() > self.capacity {
+            return None;
+        }
+
+        let offset = self.length;
+        // Only the pages being written lose execute permission
+        let pages =
+            offset / PAGE_SIZE * PAGE_SIZE..(offset + code.len()).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        if !self.protect(pages.clone(), PROT_READ | PROT_WRITE) {
+            return None;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(code.as_ptr(), self.pointer.add(offset), code.len());
+        }
+        if !self.protect(pages, PROT_READ | PROT_EXEC) {
+            return None;
+        }
+
+        self.length += code.len();
+        Some(offset)
+    }
+
+    /// Forgets all code after the first `length` bytes. Entry points into
+    /// the dropped code must not be used again.
+    pub fn truncate(&mut self, length: usize) {
+        self.length = self.length.min(length);
+    }
+
+    /// The address of the code appended at `offset`.
+    pub fn address(&self, offset: usize) -> *const u8 {
+        debug_assert!(offset < self.length);
+        unsafe { self.pointer.add(offset) }
+    }
+
+    fn protect(&self, range: Range<usize>, protection: usize) -> bool {
+        let start = self.pointer as usize + range.start;
+        let result = unsafe { syscall6(SYS_MPROTECT, [start, range.len(), protection, 0, 0, 0]) };
+        result == 0
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        unsafe {
+            syscall6(
+                SYS_MUNMAP,
+                [self.pointer as usize, self.capacity, 0, 0, 0, 0],
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jit::memory::ExecutableMemory;
+
+    #[test]
+    fn test_appended_code_can_be_called() {
+        let mut memory = ExecutableMemory::new(4096).unwrap();
+        // mov eax, 42; ret
+        let offset = memory.append(&[0xB8, 42, 0, 0, 0, 0xC3]).unwrap();
+
+        let function: extern "sysv64" fn() -> u32 =
+            unsafe { std::mem::transmute(memory.address(offset)) };
+
+        assert_eq!(function(), 42);
+        assert_eq!(memory.len(), 6);
+    }
+
+    #[test]
+    fn test_append_fails_once_full() {
+        let mut memory = ExecutableMemory::new(4096).unwrap();
+
+        assert_eq!(memory.append(&[0xC3; 4000]), Some(0));
+        assert_eq!(memory.append(&[0xC3; 100]), None);
+        memory.truncate(10);
+        assert_eq!(memory.len(), 10);
+        assert_eq!(memory.append(&[0xC3; 100]), Some(10));
+        memory.truncate(0);
+        assert!(memory.is_empty());
+    }
+}
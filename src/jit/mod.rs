@@ -0,0 +1,382 @@
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("the jit feature needs an x86-64 Linux host");
+
+use crate::blocks::translate::{translate, MAX_BLOCK_INSTRUCTIONS};
+use crate::jit::compile::{compile, stubs, Enter, Stubs, FALLBACK};
+use crate::jit::memory::ExecutableMemory;
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::registers::register::Register;
+use crate::{blocks, Vm, MEMORY_MAX};
+
+pub mod compile;
+pub mod emitter;
+pub mod memory;
+
+/// Times a block has to be entered in the interpreter before it is compiled.
+pub const HOT_THRESHOLD: u8 = 16;
+
+const CODE_CAPACITY: usize = 1 << 20;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JitStats {
+    pub compiled: u64,
+    pub invalidated: u64,
+    pub entered: u64,
+    pub interpreted: u64,
+    pub flushes: u64,
+}
+
+/// Native code for hot blocks. Cold code, traps, keyboard reads and stores
+/// into compiled code run in the interpreter.
+pub struct Jit {
+    code: ExecutableMemory,
+    stubs: Stubs,
+    /// Entry point of the block compiled at each address, or 0. Compiled
+    /// blocks jump through it to each other.
+    table: Vec<usize>,
+    /// Instructions covered by the block compiled at each address.
+    lengths: Vec<u8>,
+    /// How many compiled blocks cover each address. Generated stores check
+    /// it and hand stores into code to the interpreter.
+    covered: Vec<u8>,
+    heat: Vec<u8>,
+    stats: JitStats,
+}
+
+impl Jit {
+    /// Returns `None` if the host will not map executable memory.
+    pub fn new() -> Option<Jit> {
+        let mut code = ExecutableMemory::new(CODE_CAPACITY)?;
+        let (stub_code, stubs) = stubs(0);
+        code.append(&stub_code)?;
+
+        Some(Self {
+            code,
+            stubs,
+            table: vec![0; MEMORY_MAX],
+            lengths: vec![0; MEMORY_MAX],
+            covered: vec![0; MEMORY_MAX],
+            heat: vec![0; MEMORY_MAX],
+            stats: JitStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> &JitStats {
+        &self.stats
+    }
+
+    pub fn is_compiled(&self, address: u16) -> bool {
+        self.table[address as usize] != 0
+    }
+
+    /// Compiles the block at `address`. When the code area is full all
+    /// compiled blocks are thrown away first.
+    pub fn compile(&mut self, address: u16, memory: &[u16; MEMORY_MAX]) {
+        let block = translate(memory, address);
+        let code = compile(&block, self.code.len(), self.stubs.leave);
+        let offset = match self.code.append(&code) {
+            Some(offset) => offset,
+            None => {
+                self.flush();
+                let code = compile(&block, self.code.len(), self.stubs.leave);
+                match self.code.append(&code) {
+                    Some(offset) => offset,
+                    None => return,
+                }
+            }
+        };
+
+        for covered in &mut self.covered[block.start as usize..block.end as usize] {
+            *covered += 1;
+        }
+        self.table[address as usize] = self.code.address(offset) as usize;
+        self.lengths[address as usize] = block.instructions() as u8;
+        self.stats.compiled += 1;
+    }
+
+    fn flush(&mut self) {
+        self.table.fill(0);
+        self.lengths.fill(0);
+        self.covered.fill(0);
+        self.code.truncate(self.stubs.end);
+        self.stats.flushes += 1;
+    }
+
+    /// Drops every compiled block covering `address`. Their heat starts
+    /// over, so they are compiled again once they are hot.
+    pub fn invalidate(&mut self, address: u16) {
+        if self.covered[address as usize] == 0 {
+            return;
+        }
+
+        let earliest = address.saturating_sub(MAX_BLOCK_INSTRUCTIONS as u16 - 1);
+        for start in earliest..=address {
+            let end = start as usize + self.lengths[start as usize] as usize;
+            if self.is_compiled(start) && (address as usize) < end {
+                self.table[start as usize] = 0;
+                for covered in &mut self.covered[start as usize..end] {
+                    *covered -= 1;
+                }
+                self.heat[start as usize] = 0;
+                self.stats.invalidated += 1;
+            }
+        }
+    }
+
+    /// Runs compiled code from the PC if the block there is compiled, or a
+    /// single instruction otherwise. Returns how many instructions ran and
+    /// whether the machine is still running.
+    pub fn step(&mut self, vm: &mut Vm) -> (u64, bool) {
+        let pc = vm.registers[Register::Pc as usize];
+        if !self.is_compiled(pc) {
+            let heat = &mut self.heat[pc as usize];
+            *heat = heat.saturating_add(1);
+            if *heat == HOT_THRESHOLD {
+                self.compile(pc, &vm.memory);
+            }
+            return (1, self.interpret(vm));
+        }
+
+        let result = self.enter(vm);
+        let executed = (result & !FALLBACK) as u64;
+        if result & FALLBACK == 0 {
+            return (executed, true);
+        }
+        (executed + 1, self.interpret(vm))
+    }
+
+    /// Runs compiled code from the PC, which has to be compiled, and
+    /// returns what the code left in eax.
+    fn enter(&mut self, vm: &mut Vm) -> u32 {
+        self.stats.entered += 1;
+        let enter: Enter = unsafe { std::mem::transmute(self.code.address(self.stubs.enter)) };
+        enter(
+            vm.registers.as_mut_ptr(),
+            vm.memory.as_mut_ptr(),
+            self.covered.as_ptr(),
+            self.table.as_ptr(),
+        )
+    }
+
+    /// Runs one instruction in the interpreter, dropping any compiled code
+    /// it stores into.
+    fn interpret(&mut self, vm: &mut Vm) -> bool {
+        self.stats.interpreted += 1;
+        let target = store_target(vm);
+        let running = vm.fetch_decode_execute();
+        if let Some(address) = target {
+            self.invalidate(address);
+        }
+        running
+    }
+}
+
+/// The address the instruction at the PC is about to store to, if it is a
+/// store.
+fn store_target(vm: &Vm) -> Option<u16> {
+    let pc = vm.registers[Register::Pc as usize];
+    let decoded = Decoded::decode(vm.memory[pc as usize]);
+    let relative = pc.wrapping_add(1).wrapping_add(decoded.operand);
+    match decoded.operation {
+        Operation::St => Some(relative),
+        Operation::Sti => Some(vm.memory[relative as usize]),
+        Operation::Str => Some(vm.registers[decoded.sr as usize].wrapping_add(decoded.operand)),
+        _ => None,
+    }
+}
+
+/// Runs until the program halts and returns how many instructions executed.
+/// Uses the block translator instead if executable memory is unavailable.
+pub fn run(vm: &mut Vm) -> u64 {
+    let Some(mut jit) = Jit::new() else {
+        return blocks::run(vm);
+    };
+
+    let mut executed = 0;
+    loop {
+        let (instructions, running) = jit.step(vm);
+        executed += instructions;
+        if !running {
+            return executed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jit::compile::CHAIN_BUDGET;
+    use crate::jit::{run, Jit, HOT_THRESHOLD};
+    use crate::registers::register::Register;
+    use crate::test_support::{seeded_block_vms, vm_with};
+    use crate::Vm;
+
+    fn game(input: &str) -> Vm {
+        let mut vm = Vm::new();
+        assert!(vm.read_file(concat!(env!("CARGO_MANIFEST_DIR"), "/2048.obj")));
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.input = Some(input.encode_utf16().collect());
+        vm.output = Some(String::new());
+        vm
+    }
+
+    // ========== Equivalence With the Interpreter ==========
+
+    #[test]
+    fn test_random_blocks_match_the_interpreter() {
+        let mut seed = 0x9E37_79B9_7F4A_7C15_u64;
+        for _ in 0..256 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let (mut interpreter, mut compiled) = seeded_block_vms(seed | 1);
+
+            let mut jit = Jit::new().unwrap();
+            jit.compile(0x3000, &compiled.memory);
+            let (executed, running) = jit.step(&mut compiled);
+            let mut interpreter_running = true;
+            for _ in 0..executed {
+                interpreter_running = interpreter.fetch_decode_execute();
+            }
+
+            assert_eq!(running, interpreter_running, "seed {:X}", seed);
+            assert_eq!(interpreter.registers, compiled.registers, "seed {:X}", seed);
+            assert!(
+                interpreter.memory[..0xFE00] == compiled.memory[..0xFE00],
+                "seed {:X}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn test_counting_loop_matches_the_interpreter() {
+        let program = [
+            0b0101_001_001_1_00000,  // AND R1, R1, #0
+            0b0010_011_000000101,    // LD R3, COUNT
+            0b0001_010_010_0_00_001, // ADD R2, R2, R1
+            0b0001_001_001_1_00001,  // ADD R1, R1, #1
+            0b0001_011_011_1_11111,  // ADD R3, R3, #-1
+            0b0000_001_111111100,    // BRp #-4
+            0xF025,                  // HALT
+            1000,                    // COUNT
+        ];
+        let mut interpreter = vm_with(&program);
+        let mut compiled = vm_with(&program);
+
+        let mut expected = 1;
+        while interpreter.fetch_decode_execute() {
+            expected += 1;
+        }
+        let executed = run(&mut compiled);
+
+        assert_eq!(interpreter.registers, compiled.registers);
+        assert_eq!(compiled.registers[Register::R2 as usize], 499500_u32 as u16);
+        assert_eq!(executed, expected);
+    }
+
+    #[test]
+    fn test_game_matches_the_interpreter() {
+        let input = "y wasdwasdddssaawwdsadsawd";
+        let mut interpreter = game(input);
+        let mut compiled = game(input);
+
+        while interpreter.fetch_decode_execute() {}
+        let mut jit = Jit::new().unwrap();
+        while jit.step(&mut compiled).1 {}
+
+        assert_eq!(interpreter.output, compiled.output);
+        assert_eq!(interpreter.registers, compiled.registers);
+        assert!(interpreter.memory == compiled.memory);
+        assert!(jit.stats().compiled > 0);
+        assert!(jit.stats().entered > 0);
+    }
+
+    // ========== Hot Blocks ==========
+
+    #[test]
+    fn test_block_is_compiled_once_hot() {
+        let mut vm = vm_with(&[
+            0b0001_001_001_1_00001, // ADD R1, R1, #1
+            0b0000_111_111111110,   // BRnzp #-2
+        ]);
+        let mut jit = Jit::new().unwrap();
+
+        for _ in 0..HOT_THRESHOLD - 1 {
+            jit.step(&mut vm);
+            jit.step(&mut vm);
+        }
+        assert!(!jit.is_compiled(0x3000));
+        jit.step(&mut vm);
+        assert!(jit.is_compiled(0x3000));
+
+        jit.step(&mut vm);
+
+        // The loop chains into itself until the budget runs out
+        assert_eq!(jit.step(&mut vm), (CHAIN_BUDGET as u64, true));
+        assert_eq!(jit.stats().entered, 1);
+    }
+
+    // ========== Self-Modifying Code ==========
+
+    #[test]
+    fn test_store_into_compiled_block_falls_back_and_invalidates() {
+        let mut vm = vm_with(&[
+            0b0011_001_000000000,   // ST R1, #0 (overwrites the next word)
+            0b0001_010_010_1_00001, // ADD R2, R2, #1
+            0xF025,                 // HALT
+        ]);
+        vm.write_to_register(Register::R1, 0b0001_010_010_1_00111); // ADD R2, R2, #7
+        let mut jit = Jit::new().unwrap();
+        jit.compile(0x3000, &vm.memory);
+
+        while jit.step(&mut vm).1 {}
+
+        assert_eq!(vm.registers[Register::R2 as usize], 7);
+        assert!(!jit.is_compiled(0x3000));
+        assert_eq!(jit.stats().invalidated, 1);
+    }
+
+    #[test]
+    fn test_block_is_recompiled_after_a_store_into_it() {
+        let mut vm = vm_with(&[
+            0b0001_010_010_1_00001, // ADD R2, R2, #1
+            0xF025,                 // HALT
+            0b0011_001_111111110,   // ST R1, #-2 (rewrites the HALT)
+        ]);
+        vm.write_to_register(Register::R1, 0xF025);
+        let mut jit = Jit::new().unwrap();
+        let heat_up = |jit: &mut Jit, vm: &mut Vm| {
+            for _ in 0..HOT_THRESHOLD {
+                vm.write_to_register(Register::Pc, 0x3000);
+                jit.step(vm);
+            }
+        };
+
+        heat_up(&mut jit, &mut vm);
+        assert!(jit.is_compiled(0x3000));
+
+        vm.write_to_register(Register::Pc, 0x3002);
+        jit.step(&mut vm);
+        assert!(!jit.is_compiled(0x3000));
+
+        heat_up(&mut jit, &mut vm);
+        assert!(jit.is_compiled(0x3000));
+        assert_eq!(jit.stats().compiled, 2);
+    }
+
+    #[test]
+    fn test_stores_outside_code_stay_native() {
+        let mut vm = vm_with(&[
+            0b0111_001_010_000000, // STR R1, R2, #0
+            0xF025,                // HALT
+        ]);
+        vm.write_to_register(Register::R1, 0x1234);
+        vm.write_to_register(Register::R2, 0x5000);
+        let mut jit = Jit::new().unwrap();
+        jit.compile(0x3000, &vm.memory);
+
+        assert_eq!(jit.step(&mut vm), (2, false));
+
+        assert_eq!(vm.memory[0x5000], 0x1234);
+        assert!(jit.is_compiled(0x3000));
+        assert_eq!(jit.stats().interpreted, 1);
+    }
+}
@@ -0,0 +1,482 @@
+pub mod aot;
+pub mod asm;
+pub mod blocks;
+pub mod cache;
+pub mod cc;
+pub mod cfg;
+pub mod cli;
+pub mod conventions;
+pub mod coverage;
+pub mod cycles;
+pub mod debugger;
+pub mod decompile;
+pub mod graph;
+pub mod image;
+pub mod instructions;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod limits;
+pub mod link;
+pub mod lint;
+pub mod loader;
+pub mod lsp;
+pub mod machine;
+pub mod memcheck;
+pub mod microcode;
+pub mod pipeline;
+pub mod predecode;
+pub mod registers;
+pub mod self_modifying;
+pub mod symbols;
+#[cfg(test)]
+mod test_support;
+
+use crate::cache::accesses::data_accesses;
+use crate::cache::Caches;
+use crate::conventions::ConventionChecker;
+use crate::coverage::Coverage;
+use crate::cycles::CycleModel;
+use crate::image::Image;
+use crate::instructions::add::add;
+use crate::instructions::and::and;
+use crate::instructions::branch::br;
+use crate::instructions::jump::jmp;
+use crate::instructions::jump_register::jsr;
+use crate::instructions::ldi::ldi;
+use crate::instructions::load::ld;
+use crate::instructions::load_effective::lea;
+use crate::instructions::load_register::ldr;
+use crate::instructions::not::not;
+use crate::instructions::opcodes::Opcode;
+use crate::instructions::return_from_interrupt::rti;
+use crate::instructions::store::st;
+use crate::instructions::store_indirect::sti;
+use crate::instructions::store_register::str;
+use crate::instructions::trap::trap;
+use crate::limits::{Limits, StopReason};
+use crate::machine::{Machine, TrapMode, DDR, DSR, MCR};
+use crate::memcheck::MemCheck;
+use crate::pipeline::Pipeline;
+use crate::registers::register::{MemoryMappedRegister, Register};
+use crate::self_modifying::CodeWatch;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::sync::OnceLock;
+use windows::Win32::Foundation::{HANDLE, INVALID_HANDLE_VALUE};
+use windows::Win32::System::Console::CONSOLE_MODE;
+use windows::Win32::System::Console::{
+    FlushConsoleInputBuffer, GetConsoleMode, GetStdHandle, PeekConsoleInputW, ReadConsoleInputW,
+    SetConsoleMode, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, INPUT_RECORD, KEY_EVENT,
+    STD_INPUT_HANDLE,
+};
+
+static H_STDIN_RAW: OnceLock<isize> = OnceLock::new();
+static mut FDW_OLD_MODE: CONSOLE_MODE = CONSOLE_MODE(0);
+
+pub const MEMORY_MAX: usize = 1 << 16;
+pub const PC_START: usize = 0x3000;
+
+/// An object file that has been copied into memory by `Vm::read_file`.
+pub struct LoadedImage {
+    pub file_name: String,
+    pub origin: u16,
+    pub length: u16,
+}
+
+impl LoadedImage {
+    pub fn addresses(&self) -> impl Iterator<Item = u16> + use<> {
+        let origin = self.origin;
+        (0..self.length).map(move |offset| origin.wrapping_add(offset))
+    }
+}
+
+pub struct Vm {
+    pub memory: [u16; MEMORY_MAX],
+    pub registers: [u16; (Register::Count as u16) as usize],
+    pub images: Vec<LoadedImage>,
+    pub coverage: Option<Coverage>,
+    pub cycles: Option<CycleModel>,
+    pub pipeline: Option<Pipeline>,
+    pub caches: Option<Caches>,
+    /// Checks calls and returns against the LC-3 calling convention.
+    pub conventions: Option<ConventionChecker>,
+    /// Tracks which registers and memory words hold a value and reports
+    /// reads of the ones that don't.
+    pub memcheck: Option<MemCheck>,
+    /// Watches for stores into code that ran and jumps into words the
+    /// program wrote.
+    pub code_watch: Option<CodeWatch>,
+    /// Keys to feed the program instead of reading the console. Once it
+    /// runs dry, `GETC` and `IN` halt the program.
+    pub input: Option<VecDeque<u16>>,
+    /// Collects trap output instead of printing it to stdout.
+    pub output: Option<String>,
+    /// Bytes of trap output written so far, wherever it went.
+    pub output_bytes: u64,
+    pub limits: Limits,
+    /// Leaves out the `--- HALT ---` banner.
+    pub quiet_halt: bool,
+    pub machine: Machine,
+    /// Why the machine stopped, when it was something other than `HALT`.
+    /// Whatever stops it sets this, and `run` hands it back.
+    pub stop_reason: Option<StopReason>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Self {
+            registers: [0; (Register::Count as u16) as usize],
+            memory: [0; MEMORY_MAX],
+            images: Vec::new(),
+            coverage: None,
+            cycles: None,
+            pipeline: None,
+            caches: None,
+            conventions: None,
+            memcheck: None,
+            code_watch: None,
+            input: None,
+            output: None,
+            output_bytes: 0,
+            limits: Limits::default(),
+            quiet_halt: false,
+            machine: Machine::default(),
+            stop_reason: None,
+        }
+    }
+
+    pub fn write_to_register(&mut self, register: Register, value: u16) {
+        self.registers[register as usize] = value;
+    }
+
+    pub fn mem_write(&mut self, offset: u16, value: u16) {
+        self.memory[offset as usize] = value;
+        if offset == DDR && self.machine.devices.display {
+            self.write_output(&((value & 0xFF) as u8 as char).to_string());
+        }
+    }
+
+    /// Loads an image file in any format `image::detect` recognises,
+    /// reporting failures on stderr.
+    pub fn read_file(&mut self, file_name: &str) -> bool {
+        match image::read_file(file_name, None, None) {
+            Ok(image) => {
+                self.load_image(file_name, &image);
+                true
+            }
+            Err(e) => {
+                eprintln!("Error loading {}: {}", file_name, e);
+                false
+            }
+        }
+    }
+
+    /// Copies each segment of `image` into memory.
+    pub fn load_image(&mut self, file_name: &str, image: &Image) {
+        for segment in &image.segments {
+            let start = segment.origin as usize;
+            self.memory[start..start + segment.words.len()].copy_from_slice(&segment.words);
+            if let Some(memcheck) = self.memcheck.as_mut() {
+                memcheck.define(segment.origin, segment.words.len());
+            }
+            self.images.push(LoadedImage {
+                file_name: file_name.to_string(),
+                origin: segment.origin,
+                length: segment.words.len() as u16,
+            });
+        }
+    }
+
+    pub fn mem_read(&mut self, address: u16) -> u16 {
+        // Without a console or scripted input the device registers read as
+        // plain memory
+        let keyboard =
+            self.machine.devices.keyboard && (self.input.is_some() || self.get_handle().is_some());
+        if address == MemoryMappedRegister::MR_KBSR as u16 && keyboard {
+            if self.check_key() {
+                self.memory[MemoryMappedRegister::MR_KBSR as usize] = 1 << 15;
+                let c = self.read_char().unwrap_or(0);
+                self.memory[MemoryMappedRegister::MR_KBDR as usize] = c;
+            } else {
+                self.memory[MemoryMappedRegister::MR_KBSR as usize] = 0
+            }
+        }
+        if address == DSR && self.machine.devices.display {
+            self.memory[DSR as usize] = 1 << 15;
+        }
+
+        self.memory[address as usize]
+    }
+
+    /// Reads one key from the scripted input if there is any, or waits for
+    /// the console otherwise.
+    pub fn read_char(&mut self) -> Option<u16> {
+        match self.input.as_mut() {
+            Some(input) => input.pop_front(),
+            None => Some(self.get_char()),
+        }
+    }
+
+    pub fn write_output(&mut self, text: &str) {
+        self.output_bytes += text.len() as u64;
+        match self.output.as_mut() {
+            Some(output) => output.push_str(text),
+            None => {
+                print!("{}", text);
+                io::stdout().flush().expect("Could not flush stdout");
+            }
+        }
+    }
+
+    fn check_key(&self) -> bool {
+        if let Some(input) = &self.input {
+            return !input.is_empty();
+        }
+
+        unsafe {
+            if let Some(h_stdin) = self.get_handle() {
+                let mut buffer: [INPUT_RECORD; 128] = std::mem::zeroed();
+                let mut events_read: u32 = 0;
+
+                if PeekConsoleInputW(h_stdin, &mut buffer, &mut events_read).is_ok() {
+                    for i in 0..events_read as usize {
+                        if buffer[i].EventType == KEY_EVENT as u16 {
+                            let key_event = buffer[i].Event.KeyEvent;
+                            if key_event.bKeyDown.as_bool() && key_event.uChar.AsciiChar != 0 {
+                                return true;
+                            }
+                        }
+                    }
+                }
+            }
+            false
+        }
+    }
+
+    fn get_char(&mut self) -> u16 {
+        unsafe {
+            if let Some(h_stdin) = self.get_handle() {
+                let mut buffer: [INPUT_RECORD; 128] = std::mem::zeroed();
+                let mut events_read: u32 = 0;
+
+                loop {
+                    if ReadConsoleInputW(h_stdin, &mut buffer, &mut events_read).is_ok() {
+                        for i in 0..events_read as usize {
+                            if buffer[i].EventType == KEY_EVENT as u16 {
+                                let key_event = buffer[i].Event.KeyEvent;
+                                // Only process key DOWN events with an ASCII character
+                                if key_event.bKeyDown.as_bool() && key_event.uChar.AsciiChar != 0 {
+                                    return key_event.uChar.AsciiChar as u16;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            panic!("Failed to read character");
+        }
+    }
+    fn get_handle(&self) -> Option<HANDLE> {
+        H_STDIN_RAW.get().map(|&raw| HANDLE(raw as *mut _))
+    }
+
+    /// Runs until the program halts or breaks one of `self.limits`.
+    pub fn run(&mut self) -> StopReason {
+        let reason = if self.limits.enabled() {
+            limits::run(self)
+        } else {
+            if self.instrumented() || self.machine.needs_interpreter() {
+                while self.fetch_decode_execute() {}
+            } else {
+                #[cfg(feature = "jit")]
+                jit::run(self);
+                #[cfg(not(feature = "jit"))]
+                blocks::run(self);
+            }
+            self.stop_reason.take().unwrap_or(StopReason::Halted)
+        };
+
+        if let Some(cycles) = &self.cycles {
+            eprint!("{}", cycles.report());
+        }
+        if let Some(pipeline) = &self.pipeline {
+            eprint!("{}", pipeline.report());
+        }
+        if let Some(caches) = &self.caches {
+            eprint!("{}", caches.report());
+        }
+        reason
+    }
+
+    /// Whether any analysis needs to see each instruction go through
+    /// `fetch_decode_execute`.
+    fn instrumented(&self) -> bool {
+        self.coverage.is_some()
+            || self.cycles.is_some()
+            || self.pipeline.is_some()
+            || self.caches.is_some()
+            || self.conventions.is_some()
+            || self.memcheck.is_some()
+            || self.code_watch.is_some()
+    }
+
+    pub fn fetch_decode_execute(&mut self) -> bool {
+        let address = self.registers[Register::Pc as usize];
+        let instruction = self.fetch();
+        let opcode = Self::decode(instruction);
+
+        // Checks that can stop an instruction go first, so the analyses
+        // after them only see instructions that run
+        if let Some(memcheck) = self.memcheck.as_mut() {
+            let os_traps = self.machine.traps == TrapMode::Os;
+            let stop = memcheck.check(
+                address,
+                instruction,
+                &self.registers,
+                &self.memory,
+                os_traps,
+            );
+            if stop.is_some() {
+                return self.stop_before(address, stop);
+            }
+        }
+        if let Some(mut watch) = self.code_watch.take() {
+            let stop = watch.check(self, address, instruction);
+            self.code_watch = Some(watch);
+            if stop.is_some() {
+                return self.stop_before(address, stop);
+            }
+        }
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(
+                address,
+                instruction,
+                self.registers[Register::Cond as usize],
+            );
+        }
+        if let Some(cycles) = self.cycles.as_mut() {
+            cycles.charge(opcode, instruction, self.registers[Register::Cond as usize]);
+        }
+        if let Some(pipeline) = self.pipeline.as_mut() {
+            pipeline.issue(
+                address,
+                instruction,
+                opcode,
+                self.registers[Register::Cond as usize],
+            );
+        }
+        if let Some(mut caches) = self.caches.take() {
+            for (address, access) in data_accesses(self, instruction, opcode) {
+                caches.access_data(address, access);
+            }
+            self.caches = Some(caches);
+        }
+
+        if let Some(conventions) = self.conventions.as_mut() {
+            conventions.before(address, &self.registers);
+        }
+        let running = self.execute(instruction, opcode);
+        if let Some(conventions) = self.conventions.as_mut() {
+            conventions.after(address, instruction, &self.registers);
+        }
+        running && self.clock_enabled()
+    }
+
+    /// Stops the machine with the PC back on the instruction at `address`,
+    /// which does not run.
+    fn stop_before(&mut self, address: u16, reason: Option<StopReason>) -> bool {
+        self.registers[Register::Pc as usize] = address;
+        self.stop_reason = reason;
+        false
+    }
+
+    /// Whether the machine control register, when mapped, still lets the
+    /// clock run.
+    fn clock_enabled(&self) -> bool {
+        !self.machine.devices.machine_control || self.memory[MCR as usize] & 0x8000 != 0
+    }
+
+    fn execute(&mut self, instruction: u16, opcode: Opcode) -> bool {
+        match opcode {
+            Opcode::Br => br(&mut self.registers, instruction),
+            Opcode::Add => add(&mut self.registers, instruction),
+            Opcode::Ld => ld(self, instruction),
+            Opcode::St => st(self, instruction),
+            Opcode::Jsr => jsr(&mut self.registers, instruction),
+            Opcode::And => and(&mut self.registers, instruction),
+            Opcode::Ldr => ldr(self, instruction),
+            Opcode::Str => str(self, instruction),
+            Opcode::Not => not(&mut self.registers, instruction),
+            Opcode::Ldi => ldi(self, instruction),
+            Opcode::Sti => sti(self, instruction),
+            Opcode::Jmp => jmp(&mut self.registers, instruction),
+            Opcode::Lea => lea(&mut self.registers, instruction),
+            Opcode::Trap => return trap(self, instruction),
+            Opcode::Rti => return rti(self),
+            Opcode::Res => {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn decode(instruction: u16) -> Opcode {
+        let opcode: Opcode = match Opcode::get(instruction >> 12) {
+            Some(opcode) => opcode,
+            None => panic!("Invalid opcode {:X}", instruction),
+        };
+        opcode
+    }
+
+    fn fetch(&mut self) -> u16 {
+        let address_of_instruction = self.registers[Register::Pc as usize];
+        let instruction: u16 = self.memory[address_of_instruction as usize];
+        if let Some(caches) = self.caches.as_mut() {
+            caches.fetch(address_of_instruction);
+        }
+        self.registers[Register::Pc as usize] =
+            self.registers[Register::Pc as usize].wrapping_add(1);
+        instruction
+    }
+
+    pub fn disable_input_buffering(&self) -> windows::core::Result<()> {
+        unsafe {
+            let h_stdin = GetStdHandle(STD_INPUT_HANDLE)?;
+            if h_stdin == INVALID_HANDLE_VALUE {
+                panic!("Invalid handle to STD_INPUT_HANDLE");
+            }
+
+            H_STDIN_RAW.set(h_stdin.0 as isize).ok();
+
+            let mut old_mode = CONSOLE_MODE(0);
+            GetConsoleMode(h_stdin, &mut old_mode)?;
+            FDW_OLD_MODE = old_mode;
+
+            // Disable echo and line input (same behavior as C code)
+            let new_mode = old_mode.0 ^ ENABLE_ECHO_INPUT.0 ^ ENABLE_LINE_INPUT.0;
+            let new_mode = CONSOLE_MODE(new_mode);
+
+            SetConsoleMode(h_stdin, new_mode)?;
+            FlushConsoleInputBuffer(h_stdin)?;
+            Ok(())
+        }
+    }
+
+    pub fn restore_input_buffering(&self) -> windows::core::Result<()> {
+        unsafe {
+            if let Some(h_stdin) = self.get_handle() {
+                SetConsoleMode(h_stdin, FDW_OLD_MODE)?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use crate::memcheck::Location;
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::registers::register::Register;
+use crate::self_modifying::CodeWrite;
+use crate::Vm;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many instructions run between clock reads.
+const CLOCK_INTERVAL: u64 = 1024;
+/// Loads from here up touch the memory-mapped devices.
+const DEVICE_REGISTERS: u16 = 0xFE00;
+
+/// Bounds on a run, for programs that may never halt. A limit of `None` is
+/// not enforced.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_instructions: Option<u64>,
+    pub max_time: Option<Duration>,
+    /// Bytes of trap output.
+    pub max_output: Option<u64>,
+    /// Stop on states that can only repeat forever, like `BRnzp #-1`.
+    pub detect_stuck: bool,
+}
+
+impl Limits {
+    pub fn enabled(&self) -> bool {
+        self.max_instructions.is_some()
+            || self.max_time.is_some()
+            || self.max_output.is_some()
+            || self.detect_stuck
+    }
+}
+
+/// Why `Vm::run` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// `HALT`, or an instruction that stops the machine.
+    Halted,
+    InstructionLimit,
+    TimeLimit,
+    OutputLimit,
+    /// The machine would loop forever at this address.
+    Stuck(u16),
+    /// A debugger breakpoint, before the instruction there runs.
+    Breakpoint(u16),
+    /// `TRAP` with a vector that has no built-in routine.
+    UnknownTrap(u8),
+    /// `GETC` or `IN` found the scripted input used up.
+    InputExhausted,
+    /// The memory check stopped the instruction at `pc` from reading a
+    /// value that was never set.
+    Uninitialized {
+        pc: u16,
+        location: Location,
+    },
+    /// The self-modifying code check stopped the instruction at `pc` under
+    /// its `fault` policy.
+    SelfModifying {
+        pc: u16,
+        write: CodeWrite,
+    },
+}
+
+impl StopReason {
+    /// The process exit code for a run that stopped this way, or `None`
+    /// when it finished normally. Distinct codes let a grading script
+    /// tell the reasons apart.
+    pub fn exit_code(self) -> Option<i32> {
+        match self {
+            StopReason::Halted | StopReason::Breakpoint(_) => None,
+            StopReason::InstructionLimit => Some(3),
+            StopReason::TimeLimit => Some(4),
+            StopReason::OutputLimit => Some(5),
+            StopReason::Stuck(_) => Some(6),
+            StopReason::UnknownTrap(_) => Some(7),
+            StopReason::Uninitialized { .. } => Some(8),
+            StopReason::SelfModifying { .. } => Some(9),
+            StopReason::InputExhausted => Some(10),
+        }
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Halted => write!(f, "halted"),
+            StopReason::InstructionLimit => write!(f, "instruction limit reached"),
+            StopReason::TimeLimit => write!(f, "time limit reached"),
+            StopReason::OutputLimit => write!(f, "output limit reached"),
+            StopReason::Stuck(address) => write!(f, "stuck in a loop at x{:04X}", address),
+            StopReason::Breakpoint(address) => write!(f, "breakpoint at x{:04X}", address),
+            StopReason::UnknownTrap(vector) => write!(f, "unknown trap x{:02X}", vector),
+            StopReason::InputExhausted => write!(f, "out of input"),
+            StopReason::Uninitialized { pc, location } => {
+                write!(f, "uninitialized read of {} at x{:04X}", location, pc)
+            }
+            StopReason::SelfModifying { pc, write } => {
+                write!(f, "self-modifying code at x{:04X}: {}", pc, write)
+            }
+        }
+    }
+}
+
+/// Spots loops that can never exit. A branch to itself is stuck at once.
+/// Otherwise the registers are remembered at each backward jump, and
+/// arriving at the same jump with the same registers means the loop body
+/// changed nothing, as long as it did no stores and no I/O in between.
+#[derive(Default)]
+struct StuckDetector {
+    jump: Option<[u16; Register::Count as usize]>,
+    quiet: bool,
+}
+
+impl StuckDetector {
+    /// Looks at the instruction about to run.
+    fn stuck(&mut self, vm: &Vm) -> bool {
+        let pc = vm.registers[Register::Pc as usize];
+        let decoded = Decoded::decode(vm.memory[pc as usize]);
+        let next = pc.wrapping_add(1);
+
+        match decoded.operation {
+            Operation::Br if decoded.dr as u16 & vm.registers[Register::Cond as usize] != 0 => {
+                self.jump_to(next.wrapping_add(decoded.operand), vm)
+            }
+            Operation::Jmp => self.jump_to(vm.registers[decoded.sr as usize], vm),
+            Operation::St | Operation::Sti | Operation::Str | Operation::Trap => {
+                self.quiet = false;
+                false
+            }
+            Operation::Ld | Operation::Ldi | Operation::Ldr => {
+                let address = match decoded.operation {
+                    Operation::Ld => next.wrapping_add(decoded.operand),
+                    Operation::Ldi => vm.memory[next.wrapping_add(decoded.operand) as usize],
+                    _ => vm.registers[decoded.sr as usize].wrapping_add(decoded.operand),
+                };
+                // The keyboard can change between reads
+                if address >= DEVICE_REGISTERS {
+                    self.quiet = false;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn jump_to(&mut self, target: u16, vm: &Vm) -> bool {
+        let pc = vm.registers[Register::Pc as usize];
+        if target == pc {
+            return true;
+        }
+        if target > pc {
+            return false;
+        }
+
+        if self.quiet && self.jump == Some(vm.registers) {
+            return true;
+        }
+        self.jump = Some(vm.registers);
+        self.quiet = true;
+        false
+    }
+}
+
+/// Runs like `Vm::run` until the program halts or breaks one of
+/// `vm.limits`.
+pub fn run(vm: &mut Vm) -> StopReason {
+    run_with(vm, |_| None)
+}
+
+/// Like `run`, but calls `before` ahead of every instruction. Returning a
+/// reason from it stops the run there.
+pub fn run_with(vm: &mut Vm, before: impl FnMut(&Vm) -> Option<StopReason>) -> StopReason {
+    run_stepping(vm, before, Vm::fetch_decode_execute)
+}
+
+/// Like `run_with`, but runs each instruction with `step`, which returns
+/// `false` once the machine stops. Other engines use this to keep to the
+/// same limits as the interpreter.
+pub fn run_stepping(
+    vm: &mut Vm,
+    mut before: impl FnMut(&Vm) -> Option<StopReason>,
+    mut step: impl FnMut(&mut Vm) -> bool,
+) -> StopReason {
+    let limits = vm.limits.clone();
+    let started = Instant::now();
+    let output_before = vm.output_bytes;
+    let mut detector = StuckDetector::default();
+    let mut executed = 0;
+
+    loop {
+        if limits.max_instructions.is_some_and(|max| executed >= max) {
+            return StopReason::InstructionLimit;
+        }
+        if limits
+            .max_output
+            .is_some_and(|max| vm.output_bytes - output_before > max)
+        {
+            return StopReason::OutputLimit;
+        }
+        if executed % CLOCK_INTERVAL == 0
+            && limits.max_time.is_some_and(|max| started.elapsed() >= max)
+        {
+            return StopReason::TimeLimit;
+        }
+        if limits.detect_stuck && detector.stuck(vm) {
+            return StopReason::Stuck(vm.registers[Register::Pc as usize]);
+        }
+        if let Some(reason) = before(vm) {
+            return reason;
+        }
+
+        executed += 1;
+        if !step(vm) {
+            return vm.stop_reason.take().unwrap_or(StopReason::Halted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocks;
+    use crate::limits::{run_with, Limits, StopReason};
+    use crate::registers::register::Register;
+    use crate::test_support::vm_with;
+    use crate::Vm;
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    fn limited(words: &[u16], limits: Limits) -> Vm {
+        let mut vm = vm_with(words);
+        vm.limits = limits;
+        vm
+    }
+
+    // ========== Limits ==========
+
+    #[test]
+    fn test_halting_program_is_unaffected() {
+        let mut vm = limited(
+            &[
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0xF025,                 // HALT
+            ],
+            Limits {
+                max_instructions: Some(10),
+                detect_stuck: true,
+                ..Limits::default()
+            },
+        );
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert_eq!(vm.registers[Register::R1 as usize], 1);
+    }
+
+    #[test]
+    fn test_instruction_limit_stops_after_exactly_that_many() {
+        let mut vm = limited(
+            &[
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0b0000_111_111111110,   // BRnzp #-2
+            ],
+            Limits {
+                max_instructions: Some(101),
+                ..Limits::default()
+            },
+        );
+
+        assert_eq!(vm.run(), StopReason::InstructionLimit);
+        assert_eq!(vm.registers[Register::R1 as usize], 51);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3001);
+    }
+
+    #[test]
+    fn test_time_limit_stops_a_counting_loop() {
+        let mut vm = limited(
+            &[
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0b0000_111_111111110,   // BRnzp #-2
+            ],
+            Limits {
+                max_time: Some(Duration::from_millis(20)),
+                ..Limits::default()
+            },
+        );
+
+        assert_eq!(vm.run(), StopReason::TimeLimit);
+    }
+
+    #[test]
+    fn test_output_limit_counts_trap_output() {
+        let mut vm = limited(
+            &[
+                0b0101_000_000_1_00000,  // AND R0, R0, #0
+                0b0001_000_000_1_01111,  // ADD R0, R0, #15
+                0b0001_000_000_0_00_000, // ADD R0, R0, R0
+                0b0001_000_000_0_00_000, // ADD R0, R0, R0
+                0xF021,                  // OUT
+                0b0000_111_111111110,    // BRnzp #-2
+            ],
+            Limits {
+                max_output: Some(3),
+                ..Limits::default()
+            },
+        );
+
+        assert_eq!(vm.run(), StopReason::OutputLimit);
+        assert_eq!(vm.output.as_deref(), Some("<<<<"));
+    }
+
+    // ========== Stuck Detection ==========
+
+    #[test]
+    fn test_branch_to_itself_is_stuck() {
+        let mut vm = limited(
+            &[
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0b0000_111_111111111,   // BRnzp #-1
+            ],
+            Limits {
+                detect_stuck: true,
+                ..Limits::default()
+            },
+        );
+
+        assert_eq!(vm.run(), StopReason::Stuck(0x3001));
+        assert_eq!(vm.registers[Register::R1 as usize], 1);
+    }
+
+    #[test]
+    fn test_loop_that_changes_nothing_is_stuck() {
+        let mut vm = limited(
+            &[
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0b0001_001_001_1_11111, // ADD R1, R1, #-1
+                0b0000_111_111111101,   // BRnzp #-3
+            ],
+            Limits {
+                detect_stuck: true,
+                ..Limits::default()
+            },
+        );
+
+        assert_eq!(vm.run(), StopReason::Stuck(0x3002));
+    }
+
+    #[test]
+    fn test_counting_loop_is_not_stuck() {
+        let mut vm = limited(
+            &[
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0b0000_101_111111110,   // BRnp #-2
+                0xF025,                 // HALT
+            ],
+            Limits {
+                detect_stuck: true,
+                ..Limits::default()
+            },
+        );
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert_eq!(vm.registers[Register::R1 as usize], 0);
+    }
+
+    #[test]
+    fn test_keyboard_polling_loop_is_not_stuck() {
+        let mut vm = limited(
+            &[
+                0b1010_000_000000010, // LDI R0, #2
+                0b0000_011_111111110, // BRzp #-2
+                0xF025,               // HALT
+                0xFE00,               // .FILL KBSR
+            ],
+            Limits {
+                max_instructions: Some(1000),
+                detect_stuck: true,
+                ..Limits::default()
+            },
+        );
+        vm.input = Some(VecDeque::new());
+
+        assert_eq!(vm.run(), StopReason::InstructionLimit);
+    }
+
+    #[test]
+    fn test_running_off_the_end_of_memory_wraps_around() {
+        let program = [
+            (0xFFFF, 0b0001_001_001_1_00001), // ADD R1, R1, #1
+            (0x0000, 0b0001_001_001_1_00001), // ADD R1, R1, #1
+            (0x0001, 0xF025),                 // HALT
+        ];
+        let vm_at_the_end = |limits| {
+            let mut vm = limited(&[], limits);
+            for (address, word) in program {
+                vm.mem_write(address, word);
+            }
+            vm.write_to_register(Register::Pc, 0xFFFF);
+            vm
+        };
+
+        let mut limited = vm_at_the_end(Limits {
+            max_instructions: Some(2),
+            ..Limits::default()
+        });
+        assert_eq!(limited.run(), StopReason::InstructionLimit);
+        assert_eq!(limited.registers[Register::Pc as usize], 0x0001);
+
+        let mut interpreted = vm_at_the_end(Limits {
+            max_instructions: Some(10),
+            ..Limits::default()
+        });
+        let mut cached = vm_at_the_end(Limits::default());
+        assert_eq!(interpreted.run(), StopReason::Halted);
+        blocks::run(&mut cached);
+        assert_eq!(interpreted.registers, cached.registers);
+        assert_eq!(cached.registers[Register::R1 as usize], 2);
+    }
+
+    #[test]
+    fn test_hook_sees_each_instruction_and_can_stop_the_run() {
+        let mut vm = limited(
+            &[
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0b0001_001_001_1_00001, // ADD R1, R1, #1
+                0xF025,                 // HALT
+            ],
+            Limits::default(),
+        );
+        let mut seen = Vec::new();
+
+        let reason = run_with(&mut vm, |vm| {
+            let pc = vm.registers[Register::Pc as usize];
+            seen.push(pc);
+            (pc == 0x3002).then_some(StopReason::Breakpoint(pc))
+        });
+
+        assert_eq!(reason, StopReason::Breakpoint(0x3002));
+        assert_eq!(seen, [0x3000, 0x3001, 0x3002]);
+        assert_eq!(vm.registers[Register::R1 as usize], 2);
+    }
+}
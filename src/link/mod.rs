@@ -0,0 +1,430 @@
+//! Combining relocatable modules into one loadable image.
+
+pub mod object;
+
+use crate::image::{Image, Segment};
+use crate::link::object::{Module, RelocationKind};
+use crate::symbols::symbol_table::SymbolTable;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Where floating sections go when no base is given.
+pub const DEFAULT_BASE: u16 = 0x3000;
+
+/// Where the linker put one section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub module: String,
+    pub section: String,
+    pub origin: u16,
+    pub length: usize,
+    /// Whether the section asked for this address with `.ORIG`.
+    pub fixed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linked {
+    pub image: Image,
+    /// Exported labels by name, every other label as `module.LABEL`.
+    pub symbols: SymbolTable,
+    pub placements: Vec<Placement>,
+}
+
+impl Linked {
+    /// One line per section, in address order.
+    pub fn layout(&self) -> String {
+        let mut placements: Vec<&Placement> = self.placements.iter().collect();
+        placements.sort_by_key(|placement| placement.origin);
+        let mut out = String::new();
+        for placement in placements {
+            let end = (placement.origin as usize + placement.length).saturating_sub(1);
+            writeln!(
+                out,
+                "x{:04X}-x{:04X}  {}.{} ({} words{})",
+                placement.origin,
+                end.max(placement.origin as usize),
+                placement.module,
+                placement.section,
+                placement.length,
+                if placement.fixed { ", fixed" } else { "" }
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// The first address past `origin` and `length` words, which may be x10000.
+fn end_of(origin: u16, length: usize) -> usize {
+    origin as usize + length
+}
+
+/// Places every section, leaving fixed ones at their origin and packing
+/// the rest upwards from `base` around them.
+fn place(modules: &[Module], base: u16, errors: &mut Vec<String>) -> Vec<Placement> {
+    let mut placements = Vec::new();
+    for module in modules {
+        for section in module.sections.iter() {
+            if let Some(origin) = section.origin {
+                placements.push(Placement {
+                    module: module.name.clone(),
+                    section: section.name.clone(),
+                    origin,
+                    length: section.words.len(),
+                    fixed: true,
+                });
+            }
+        }
+    }
+
+    let mut fixed: Vec<&Placement> = placements.iter().collect();
+    fixed.sort_by_key(|placement| placement.origin);
+    for placement in &fixed {
+        if end_of(placement.origin, placement.length) > 0x10000 {
+            errors.push(format!(
+                "{}.{} runs past the end of memory",
+                placement.module, placement.section
+            ));
+        }
+    }
+    for pair in fixed.windows(2) {
+        if end_of(pair[0].origin, pair[0].length) > pair[1].origin as usize {
+            errors.push(format!(
+                "{}.{} (x{:04X}, {} words) overlaps {}.{} (x{:04X}, {} words)",
+                pair[0].module,
+                pair[0].section,
+                pair[0].origin,
+                pair[0].length,
+                pair[1].module,
+                pair[1].section,
+                pair[1].origin,
+                pair[1].length
+            ));
+        }
+    }
+    let reserved: Vec<(usize, usize)> = fixed
+        .iter()
+        .filter(|placement| placement.length > 0)
+        .map(|placement| {
+            (
+                placement.origin as usize,
+                end_of(placement.origin, placement.length),
+            )
+        })
+        .collect();
+
+    let mut next = base as usize;
+    for module in modules {
+        for section in module.sections.iter().filter(|s| s.origin.is_none()) {
+            let length = section.words.len();
+            // Step past every fixed section the floating one would cover
+            while let Some(&(_, end)) = reserved
+                .iter()
+                .find(|&&(start, end)| next < end && start < next + length)
+            {
+                next = end;
+            }
+            if next + length > 0x10000 {
+                errors.push(format!(
+                    "{}.{} ({} words) does not fit below x10000",
+                    module.name, section.name, length
+                ));
+                continue;
+            }
+            placements.push(Placement {
+                module: module.name.clone(),
+                section: section.name.clone(),
+                origin: next as u16,
+                length,
+                fixed: false,
+            });
+            next += length;
+        }
+    }
+    placements
+}
+
+/// Links `modules` into one image. Sections without an origin are placed
+/// from `base` in the order given. Every problem is reported, not just the
+/// first.
+pub fn link(modules: &[Module], base: u16) -> Result<Linked, Vec<String>> {
+    let mut errors = Vec::new();
+    let placements = place(modules, base, &mut errors);
+    let origin_of = |module: &Module, section: &str| {
+        placements
+            .iter()
+            .find(|placement| placement.module == module.name && placement.section == section)
+            .map(|placement| placement.origin)
+    };
+
+    // Each module's own labels, then the ones it offers the others
+    let mut locals: Vec<HashMap<&str, u16>> = Vec::new();
+    for module in modules {
+        let mut labels = HashMap::new();
+        for section in &module.sections {
+            let Some(origin) = origin_of(module, &section.name) else {
+                continue;
+            };
+            for (label, offset) in &section.symbols {
+                labels.insert(label.as_str(), origin.wrapping_add(*offset));
+            }
+        }
+        locals.push(labels);
+    }
+    let mut exports: HashMap<&str, (&str, u16)> = HashMap::new();
+    for (module, labels) in modules.iter().zip(&locals) {
+        for name in &module.exports {
+            let Some(&address) = labels.get(name.as_str()) else {
+                errors.push(format!(
+                    "module '{}' exports '{}' but does not define it",
+                    module.name, name
+                ));
+                continue;
+            };
+            match exports.get(name.as_str()) {
+                Some((other, _)) => errors.push(format!(
+                    "'{}' is exported by both '{}' and '{}'",
+                    name, other, module.name
+                )),
+                None => {
+                    exports.insert(name, (&module.name, address));
+                }
+            }
+        }
+    }
+    for module in modules {
+        for name in &module.imports {
+            if !exports.contains_key(name.as_str()) {
+                errors.push(format!(
+                    "module '{}' imports '{}', which no module exports",
+                    module.name, name
+                ));
+            }
+        }
+    }
+
+    let mut segments = Vec::new();
+    for (module, labels) in modules.iter().zip(&locals) {
+        for section in &module.sections {
+            let Some(origin) = origin_of(module, &section.name) else {
+                continue;
+            };
+            let mut words = section.words.clone();
+            for relocation in &section.relocations {
+                let symbol = relocation.symbol.as_str();
+                let target = match labels.get(symbol) {
+                    Some(&address) => address,
+                    None => match exports.get(symbol) {
+                        Some(&(_, address)) => address,
+                        // Unresolved imports were reported above
+                        None if module.imports.iter().any(|name| name == symbol) => continue,
+                        None => {
+                            errors.push(format!(
+                                "module '{}' refers to '{}', which it neither defines nor imports",
+                                module.name, symbol
+                            ));
+                            continue;
+                        }
+                    },
+                };
+
+                let at = origin.wrapping_add(relocation.offset);
+                let word = &mut words[relocation.offset as usize];
+                let bits = match relocation.kind {
+                    RelocationKind::Word => {
+                        *word = target;
+                        continue;
+                    }
+                    RelocationKind::PcOffset9 => 9,
+                    RelocationKind::PcOffset11 => 11,
+                };
+                let offset = target as i32 - (at as i32 + 1);
+                let reach = 1 << (bits - 1);
+                if !(-reach..reach).contains(&offset) {
+                    errors.push(format!(
+                        "module '{}' at x{:04X}: '{}' at x{:04X} is {} words away, \
+                         more than PCoffset{} can reach",
+                        module.name, at, symbol, target, offset, bits
+                    ));
+                    continue;
+                }
+                let mask = (1u16 << bits) - 1;
+                *word = (*word & !mask) | (offset as u16 & mask);
+            }
+            segments.push(Segment { origin, words });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Sections that end where the next begins share a segment
+    segments.sort_by_key(|segment| segment.origin);
+    let mut merged: Vec<Segment> = Vec::new();
+    for segment in segments.into_iter().filter(|s| !s.words.is_empty()) {
+        match merged.last_mut() {
+            Some(last) if end_of(last.origin, last.words.len()) == segment.origin as usize => {
+                last.words.extend(segment.words)
+            }
+            _ => merged.push(segment),
+        }
+    }
+
+    let mut symbols = SymbolTable::new();
+    for (module, labels) in modules.iter().zip(&locals) {
+        for (&label, &address) in labels {
+            if exports
+                .get(label)
+                .is_some_and(|&(owner, _)| owner == module.name)
+            {
+                symbols.insert(label, address);
+            } else {
+                symbols.insert(&format!("{}.{}", module.name, label), address);
+            }
+        }
+    }
+
+    Ok(Linked {
+        image: Image { segments: merged },
+        symbols,
+        placements,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{assemble, assemble_module};
+    use crate::image::Image;
+    use crate::link::object::Module;
+    use crate::link::{link, DEFAULT_BASE};
+    use crate::Vm;
+
+    fn module(name: &str, source: &str) -> Module {
+        Module::from_assembly(name, &assemble_module(source).unwrap())
+    }
+
+    fn errors(modules: &[Module]) -> Vec<String> {
+        link(modules, DEFAULT_BASE).unwrap_err()
+    }
+
+    const MAIN: &str = "\
+.IMPORT PRINT
+.EXPORT MAIN
+MAIN    LEA R0, MSG
+        JSR PRINT
+        LD R1, COUNT
+        HALT
+COUNT   .FILL MSG
+MSG     .STRINGZ \"Hi\"
+";
+
+    const PRINT: &str = "\
+.EXPORT PRINT
+PRINT   ST R7, SAVE
+        PUTS
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+";
+
+    // ========== Linking ==========
+
+    #[test]
+    fn test_links_modules_into_a_runnable_image() {
+        let linked = link(&[module("main", MAIN), module("print", PRINT)], 0x3000).unwrap();
+
+        assert_eq!(linked.symbols.address_of("MAIN"), Some(0x3000));
+        assert_eq!(linked.symbols.address_of("PRINT"), Some(0x3008));
+        assert_eq!(linked.symbols.address_of("main.MSG"), Some(0x3005));
+        assert_eq!(linked.symbols.address_of("print.SAVE"), Some(0x300C));
+
+        let [segment] = linked.image.segments.as_slice() else {
+            panic!("expected one segment, got {:?}", linked.image);
+        };
+        // JSR PRINT is patched to reach x3008 from x3002
+        assert_eq!(segment.words[1], 0x4806);
+        // .FILL MSG holds the final address
+        assert_eq!(segment.words[4], 0x3005);
+
+        let mut vm = Vm::new();
+        vm.load_image("linked", &linked.image);
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.run();
+        assert_eq!(vm.output.as_deref(), Some("Hi"));
+        assert_eq!(vm.registers[1], 0x3005);
+    }
+
+    #[test]
+    fn test_floating_sections_flow_around_fixed_ones() {
+        let fixed = Module::from_assembly(
+            "vectors",
+            &assemble(".ORIG x3002\n.FILL 1\n.FILL 2\n").unwrap(),
+        );
+        let linked = link(
+            &[module("a", "HALT\nHALT\n"), fixed, module("b", "HALT\n")],
+            0x3000,
+        )
+        .unwrap();
+
+        let origins: Vec<(&str, u16)> = linked
+            .placements
+            .iter()
+            .map(|placement| (placement.module.as_str(), placement.origin))
+            .collect();
+        assert_eq!(origins, [("vectors", 0x3002), ("a", 0x3000), ("b", 0x3004)]);
+        assert_eq!(
+            linked.image,
+            Image::new(0x3000, vec![0xF025, 0xF025, 1, 2, 0xF025])
+        );
+        assert!(linked
+            .layout()
+            .starts_with("x3000-x3001  a.text (2 words)\n"));
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_unresolved_and_duplicate_symbols() {
+        assert_eq!(
+            errors(&[module("main", MAIN)]),
+            ["module 'main' imports 'PRINT', which no module exports"]
+        );
+        assert_eq!(
+            errors(&[
+                module("main", MAIN),
+                module("print", PRINT),
+                module("copy", PRINT)
+            ]),
+            ["'PRINT' is exported by both 'print' and 'copy'"]
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_offsets_are_reported() {
+        let far = Module::from_assembly(
+            "far",
+            &assemble_module(".EXPORT PRINT\n.ORIG x5000\nPRINT RET\n").unwrap(),
+        );
+        assert_eq!(
+            errors(&[module("main", MAIN), far]),
+            [
+                "module 'main' at x3001: 'PRINT' at x5000 is 8190 words away, \
+              more than PCoffset11 can reach"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overlapping_fixed_sections_are_reported() {
+        let at = |name: &str, source: &str| Module::from_assembly(name, &assemble(source).unwrap());
+        assert_eq!(
+            errors(&[
+                at("a", ".ORIG x3000\n.BLKW 4\n"),
+                at("b", ".ORIG x3002\nHALT\n")
+            ]),
+            ["a.text (x3000, 4 words) overlaps b.text (x3002, 1 words)"]
+        );
+    }
+}
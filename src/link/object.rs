@@ -0,0 +1,277 @@
+use crate::asm::Assembly;
+use std::fmt;
+use std::fs;
+
+/// How the linker patches a word once it knows where a symbol ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// The low 9 bits hold a PC offset (BR, LD, LDI, LEA, ST, STI).
+    PcOffset9,
+    /// The low 11 bits hold a PC offset (JSR).
+    PcOffset11,
+    /// The whole word is the address (`.FILL label`).
+    Word,
+}
+
+impl RelocationKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            RelocationKind::PcOffset9 => "PC9",
+            RelocationKind::PcOffset11 => "PC11",
+            RelocationKind::Word => "WORD",
+        }
+    }
+
+    fn named(name: &str) -> Option<RelocationKind> {
+        [
+            RelocationKind::PcOffset9,
+            RelocationKind::PcOffset11,
+            RelocationKind::Word,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+}
+
+/// A word at `offset` within its section that refers to `symbol`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: u16,
+    pub kind: RelocationKind,
+    pub symbol: String,
+}
+
+/// A block of words the linker places as a unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    /// Where the section must go, or `None` to let the linker choose.
+    pub origin: Option<u16>,
+    pub words: Vec<u16>,
+    /// Labels defined in the section, by offset.
+    pub symbols: Vec<(String, u16)>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// One relocatable object file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    pub name: String,
+    pub sections: Vec<Section>,
+    /// Labels other modules may refer to.
+    pub exports: Vec<String>,
+    /// Labels this module expects another module to export.
+    pub imports: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Words per `.WORDS` line when writing.
+const WORDS_PER_LINE: usize = 8;
+
+impl Module {
+    /// Wraps an assembly as a module with a single `text` section.
+    pub fn from_assembly(name: &str, assembly: &Assembly) -> Module {
+        let origin = (!assembly.relocatable).then_some(assembly.origin);
+        let symbols = assembly
+            .symbols
+            .iter()
+            .map(|(label, address)| (label.to_string(), address.wrapping_sub(assembly.origin)))
+            .collect();
+        Module {
+            name: name.to_string(),
+            sections: vec![Section {
+                name: "text".to_string(),
+                origin,
+                words: assembly.words.clone(),
+                symbols,
+                relocations: assembly.relocations.clone(),
+            }],
+            exports: assembly.exports.clone(),
+            imports: assembly.imports.clone(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Module, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        Module::parse(&text).map_err(|e| format!("{}:{}: {}", path, e.line, e.message))
+    }
+
+    /// Reads the text layout `Display` writes.
+    pub fn parse(text: &str) -> Result<Module, ObjectError> {
+        let mut module = Module {
+            name: String::new(),
+            sections: Vec::new(),
+            exports: Vec::new(),
+            imports: Vec::new(),
+        };
+
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let error = |message: String| ObjectError { line, message };
+            let fields: Vec<&str> = raw.split(';').next().unwrap().split_whitespace().collect();
+            let Some((&directive, arguments)) = fields.split_first() else {
+                continue;
+            };
+            let hex = |text: &str| {
+                u16::from_str_radix(text.trim_start_matches('x'), 16)
+                    .map_err(|_| error(format!("expected a hex word, got '{}'", text)))
+            };
+            let section = module.sections.last_mut();
+
+            match (directive, arguments, section) {
+                (".MODULE", [name], _) => module.name = name.to_string(),
+                (".EXPORT", names, _) => module.exports.extend(names.iter().map(|n| n.to_string())),
+                (".IMPORT", names, _) => module.imports.extend(names.iter().map(|n| n.to_string())),
+                (".SECTION", [name, rest @ ..], _) if rest.len() <= 1 => {
+                    let origin = rest.first().map(|origin| hex(origin)).transpose()?;
+                    module.sections.push(Section {
+                        name: name.to_string(),
+                        origin,
+                        words: Vec::new(),
+                        symbols: Vec::new(),
+                        relocations: Vec::new(),
+                    });
+                }
+                (".SYMBOL", [name, offset], Some(section)) => {
+                    section.symbols.push((name.to_string(), hex(offset)?));
+                }
+                (".WORDS", words, Some(section)) => {
+                    for word in words {
+                        section.words.push(hex(word)?);
+                    }
+                }
+                (".RELOC", [offset, kind, symbol], Some(section)) => {
+                    let kind = RelocationKind::named(kind)
+                        .ok_or_else(|| error(format!("unknown relocation kind '{}'", kind)))?;
+                    let offset = hex(offset)?;
+                    // Relocations follow the words they patch
+                    if offset as usize >= section.words.len() {
+                        return Err(error(format!(
+                            "relocation at {:04X} is past the end of section {}",
+                            offset, section.name
+                        )));
+                    }
+                    section.relocations.push(Relocation {
+                        offset,
+                        kind,
+                        symbol: symbol.to_string(),
+                    });
+                }
+                (".SYMBOL" | ".WORDS" | ".RELOC", _, None) => {
+                    return Err(error(format!("{} before any .SECTION", directive)));
+                }
+                _ => return Err(error(format!("malformed line '{}'", raw.trim()))),
+            }
+        }
+
+        if module.name.is_empty() {
+            return Err(ObjectError {
+                line: 1,
+                message: "missing .MODULE".to_string(),
+            });
+        }
+        Ok(module)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "; LC-3 relocatable object")?;
+        writeln!(f, ".MODULE {}", self.name)?;
+        if !self.exports.is_empty() {
+            writeln!(f, ".EXPORT {}", self.exports.join(" "))?;
+        }
+        if !self.imports.is_empty() {
+            writeln!(f, ".IMPORT {}", self.imports.join(" "))?;
+        }
+        for section in &self.sections {
+            match section.origin {
+                Some(origin) => writeln!(f, ".SECTION {} x{:04X}", section.name, origin)?,
+                None => writeln!(f, ".SECTION {}", section.name)?,
+            }
+            for (name, offset) in &section.symbols {
+                writeln!(f, ".SYMBOL {} {:04X}", name, offset)?;
+            }
+            for words in section.words.chunks(WORDS_PER_LINE) {
+                let words: Vec<String> = words.iter().map(|word| format!("{:04X}", word)).collect();
+                writeln!(f, ".WORDS {}", words.join(" "))?;
+            }
+            for relocation in &section.relocations {
+                writeln!(
+                    f,
+                    ".RELOC {:04X} {} {}",
+                    relocation.offset,
+                    relocation.kind.name(),
+                    relocation.symbol
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble_module;
+    use crate::link::object::Module;
+
+    // ========== Text Format ==========
+
+    #[test]
+    fn test_round_trips_through_text() {
+        let assembly = assemble_module(
+            ".IMPORT PRINT\n.EXPORT MAIN\nMAIN LEA R0, MSG\nJSR PRINT\nHALT\nMSG .STRINGZ \"Hi\"\n",
+        )
+        .unwrap();
+        let module = Module::from_assembly("main", &assembly);
+        let text = module.to_string();
+
+        assert!(text.contains(".SECTION text\n"));
+        assert!(text.contains(".RELOC 0001 PC11 PRINT\n"));
+        assert_eq!(Module::parse(&text).unwrap(), module);
+    }
+
+    #[test]
+    fn test_fixed_sections_keep_their_origin() {
+        let module =
+            Module::parse(".MODULE os\n.SECTION vectors x0020\n.WORDS 0400 0430\n").unwrap();
+        assert_eq!(module.sections[0].origin, Some(0x0020));
+        assert_eq!(module.sections[0].words, [0x0400, 0x0430]);
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_malformed_objects_are_rejected() {
+        let error = |text: &str| Module::parse(text).unwrap_err().to_string();
+        assert_eq!(
+            error(".MODULE m\n.WORDS 1234\n"),
+            "line 2: .WORDS before any .SECTION"
+        );
+        assert_eq!(
+            error(".MODULE m\n.SECTION t\n.WORDS 12G4\n"),
+            "line 3: expected a hex word, got '12G4'"
+        );
+        assert_eq!(
+            error(".MODULE m\n.SECTION t\n.WORDS 0000\n.RELOC 0000 PC5 X\n"),
+            "line 4: unknown relocation kind 'PC5'"
+        );
+        assert_eq!(
+            error(".MODULE m\n.SECTION t\n.WORDS 0000\n.RELOC 0003 WORD X\n"),
+            "line 4: relocation at 0003 is past the end of section t"
+        );
+        assert_eq!(error(".SECTION t\n"), "line 1: missing .MODULE");
+    }
+}
@@ -0,0 +1,794 @@
+use crate::asm::lexer::parse_line;
+use crate::asm::Assembly;
+use crate::cfg::{is_return, recover_from, Flow};
+use crate::instructions::disassemble::disassemble;
+use crate::instructions::trap::{TRAP_GETC, TRAP_HALT, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP};
+use crate::loader::{Region, DEVICE_REGISTERS, TRAP_VECTORS};
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::{LoadedImage, MEMORY_MAX};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// The mistakes `lint` looks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    /// Execution runs or jumps into words assembled as data.
+    FallsIntoData,
+    /// A subroutine calls another, or a trap, before saving its return
+    /// address.
+    UnsavedR7,
+    /// Code that no path from the entry reaches.
+    Unreachable,
+    /// A branch with an empty `nzp` mask, which never jumps.
+    NeverTaken,
+    /// A register read on some path before anything writes it.
+    UninitializedRead,
+    /// A store into the trap vector table or the device registers.
+    ReservedWrite,
+    /// No path reaches `HALT`, or execution runs off the end of an image.
+    MissingHalt,
+}
+
+impl Check {
+    /// The name findings are tagged with.
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::FallsIntoData => "falls-into-data",
+            Check::UnsavedR7 => "unsaved-r7",
+            Check::Unreachable => "unreachable",
+            Check::NeverTaken => "never-taken",
+            Check::UninitializedRead => "uninitialized-read",
+            Check::ReservedWrite => "reserved-write",
+            Check::MissingHalt => "missing-halt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub address: u16,
+    pub check: Check,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X}: {} [{}]",
+            self.address,
+            self.message,
+            self.check.name()
+        )
+    }
+}
+
+/// The words of `assembly` that came from `.FILL`, `.BLKW` and `.STRINGZ`.
+pub fn data_words(assembly: &Assembly) -> BTreeSet<u16> {
+    let mut data = BTreeSet::new();
+    for line in &assembly.listing.lines {
+        let Ok(statement) = parse_line(&line.text, 0) else {
+            continue;
+        };
+        let operation = statement.operation.unwrap_or_default();
+        if matches!(
+            operation.to_ascii_uppercase().as_str(),
+            ".FILL" | ".BLKW" | ".STRINGZ"
+        ) {
+            data.extend((0..line.words.len()).map(|index| line.address.wrapping_add(index as u16)));
+        }
+    }
+    data
+}
+
+const R0: u8 = 1 << 0;
+const R7: u8 = 1 << 7;
+const ALL: u8 = 0xFF;
+
+/// The registers an instruction reads and writes, as bit masks.
+fn registers(decoded: &Decoded) -> (u8, u8) {
+    let dr = 1 << decoded.dr;
+    let sr = 1 << decoded.sr;
+    match decoded.operation {
+        Operation::AddRegister | Operation::AndRegister => (sr | 1 << (decoded.operand & 0x7), dr),
+        // `AND R0, R0, #0` clears R0 whatever it held
+        Operation::AndImmediate if decoded.operand == 0 => (0, dr),
+        Operation::AddImmediate | Operation::AndImmediate | Operation::Not => (sr, dr),
+        Operation::Ld | Operation::Ldi | Operation::Lea => (0, dr),
+        Operation::Ldr => (sr, dr),
+        Operation::St | Operation::Sti => (dr, 0),
+        Operation::Str => (dr | sr, 0),
+        Operation::Jmp => (sr, 0),
+        Operation::Jsr => (0, R7),
+        Operation::Jsrr => (sr, R7),
+        Operation::Trap => match decoded.operand & 0xFF {
+            TRAP_GETC | TRAP_IN => (0, R0 | R7),
+            TRAP_OUT | TRAP_PUTS | TRAP_PUTSP => (R0, R7),
+            _ => (0, R7),
+        },
+        Operation::Br | Operation::Stop => (0, 0),
+    }
+}
+
+fn mnemonic(address: u16, instruction: u16) -> String {
+    let text = disassemble(address, instruction);
+    text.split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// A program as the checks see it.
+struct Program<'a> {
+    memory: &'a [u16; MEMORY_MAX],
+    images: &'a [LoadedImage],
+    data: Option<&'a BTreeSet<u16>>,
+}
+
+impl Program<'_> {
+    fn image_of(&self, address: u16) -> Option<&LoadedImage> {
+        self.images
+            .iter()
+            .rev()
+            .find(|image| address.wrapping_sub(image.origin) < image.length)
+    }
+
+    fn is_data(&self, address: u16) -> bool {
+        self.data.is_some_and(|data| data.contains(&address))
+    }
+
+    fn is_code(&self, address: u16) -> bool {
+        self.image_of(address).is_some() && !self.is_data(address)
+    }
+
+    fn flow(&self, address: u16) -> Flow {
+        Flow::of(address, self.memory[address as usize])
+    }
+
+    /// Runs a forward analysis over the code reachable from `roots`,
+    /// combining the states that meet at an instruction with `meet`.
+    /// `transfer` gives the states to pass on, as (address, state) pairs.
+    fn solve<S: Copy + PartialEq>(
+        &self,
+        roots: &[(u16, S)],
+        meet: impl Fn(S, S) -> S,
+        transfer: impl Fn(&Flow, S) -> Vec<(u16, S)>,
+    ) -> BTreeMap<u16, S> {
+        let mut states: BTreeMap<u16, S> = BTreeMap::new();
+        let mut pending: Vec<(u16, S)> = roots.to_vec();
+        while let Some((address, state)) = pending.pop() {
+            if !self.is_code(address) {
+                continue;
+            }
+            let state = match states.get(&address) {
+                Some(&old) => {
+                    let new = meet(old, state);
+                    if new == old {
+                        continue;
+                    }
+                    new
+                }
+                None => state,
+            };
+            states.insert(address, state);
+            pending.extend(transfer(&self.flow(address), state));
+        }
+        states
+    }
+}
+
+/// Builds the control flow of the code loaded in `images` from `entry` and
+/// reports the mistakes it finds, ordered by address.
+///
+/// `data` holds the words known to be data, as `data_words` gives for an
+/// assembled source. Without it every loaded word might be code, so
+/// unreachable code is not reported.
+pub fn lint(
+    memory: &[u16; MEMORY_MAX],
+    images: &[LoadedImage],
+    entry: u16,
+    data: Option<&BTreeSet<u16>>,
+) -> Vec<Finding> {
+    let program = Program {
+        memory,
+        images,
+        data,
+    };
+    let mut findings = Vec::new();
+
+    let mut reachable = reach(&program, &[entry], &mut Vec::new());
+    let indirect = reachable.iter().any(|&address| {
+        let decoded = program.flow(address).decoded;
+        decoded.operation == Operation::Jsrr
+            || decoded.operation == Operation::Jmp && !is_return(&decoded)
+    });
+    // An indirect jump can land on any code whose address the program takes
+    let mut roots = vec![entry];
+    if indirect {
+        roots.extend(address_taken(&program, &reachable));
+    }
+    reachable = reach(&program, &roots, &mut findings);
+    for &address in &reachable {
+        let instruction = memory[address as usize];
+        let decoded = Decoded::decode(instruction);
+        if decoded.operation == Operation::Br && decoded.dr == 0 {
+            findings.push(Finding {
+                address,
+                check: Check::NeverTaken,
+                message: format!(
+                    "branch x{:04X} has an empty nzp mask, so it never jumps",
+                    instruction
+                ),
+            });
+        }
+        reserved_write(&program, address, &decoded, &mut findings);
+    }
+    if !reachable
+        .iter()
+        .any(|&address| memory[address as usize] == 0xF000 | TRAP_HALT)
+    {
+        findings.push(Finding {
+            address: entry,
+            check: Check::MissingHalt,
+            message: "no path from the entry reaches HALT".to_string(),
+        });
+    }
+    if data.is_some() {
+        unreachable(&program, &reachable, &mut findings);
+    }
+    unsaved_returns(&program, &reachable, &mut findings);
+    uninitialized_reads(&program, entry, &reachable, &mut findings);
+
+    findings.sort_by_key(|finding| (finding.address, finding.check));
+    findings
+}
+
+/// Follows every path from `roots` into calls and branches, reporting
+/// paths that run into data or off the end of an image.
+fn reach(program: &Program, roots: &[u16], findings: &mut Vec<Finding>) -> BTreeSet<u16> {
+    let reachable =
+        recover_from(program.memory, roots, |address| program.is_code(address)).reachable;
+    for &address in &reachable {
+        let flow = program.flow(address);
+        for target in flow.successors.iter().copied().chain(flow.call) {
+            let falls_through = target == address.wrapping_add(1) && flow.call != Some(target);
+            if program.is_data(target) {
+                let message = if falls_through {
+                    format!("execution falls through into the data at x{:04X}", target)
+                } else {
+                    format!(
+                        "{} jumps into the data at x{:04X}",
+                        mnemonic(address, program.memory[address as usize]),
+                        target
+                    )
+                };
+                findings.push(Finding {
+                    address,
+                    check: Check::FallsIntoData,
+                    message,
+                });
+            } else if let (true, None, Some(image)) = (
+                falls_through,
+                program.image_of(target),
+                program.image_of(address),
+            ) {
+                findings.push(Finding {
+                    address,
+                    check: Check::MissingHalt,
+                    message: format!("execution runs off the end of {}", image.file_name),
+                });
+            }
+        }
+    }
+    reachable
+}
+
+/// The code that `LEA` instructions and data words point at.
+fn address_taken(program: &Program, reachable: &BTreeSet<u16>) -> Vec<u16> {
+    let loaded = reachable
+        .iter()
+        .filter(|&&address| {
+            Decoded::decode(program.memory[address as usize]).operation == Operation::Lea
+        })
+        .map(|&address| {
+            let offset = Decoded::decode(program.memory[address as usize]).operand;
+            address.wrapping_add(1).wrapping_add(offset)
+        });
+    let stored = program
+        .data
+        .into_iter()
+        .flatten()
+        .map(|&address| program.memory[address as usize]);
+    loaded
+        .chain(stored)
+        .filter(|&address| program.is_code(address))
+        .collect()
+}
+
+fn reserved_write(program: &Program, address: u16, decoded: &Decoded, findings: &mut Vec<Finding>) {
+    let target = address.wrapping_add(1).wrapping_add(decoded.operand);
+    let written = match decoded.operation {
+        Operation::St => target,
+        // Only a pointer that is part of the program is known before it runs
+        Operation::Sti if program.image_of(target).is_some() => program.memory[target as usize],
+        _ => return,
+    };
+    let reserved: [Region; 2] = [TRAP_VECTORS, DEVICE_REGISTERS];
+    if let Some(region) = reserved
+        .iter()
+        .find(|region| (region.start..=region.end).contains(&written))
+    {
+        findings.push(Finding {
+            address,
+            check: Check::ReservedWrite,
+            message: format!(
+                "{} writes x{:04X} in the {}",
+                mnemonic(address, program.memory[address as usize]),
+                written,
+                region.name
+            ),
+        });
+    }
+}
+
+fn unreachable(program: &Program, reachable: &BTreeSet<u16>, findings: &mut Vec<Finding>) {
+    for image in program.images {
+        let mut run: Option<(u16, u16)> = None;
+        let addresses = image.addresses().map(Some).chain([None]);
+        for address in addresses {
+            let dead = address
+                .filter(|&address| program.is_code(address) && !reachable.contains(&address));
+            match (dead, run) {
+                (Some(address), Some((start, _))) => run = Some((start, address)),
+                (Some(address), None) => run = Some((address, address)),
+                (None, Some((start, end))) => {
+                    let message = if start == end {
+                        "this instruction can never be reached".to_string()
+                    } else {
+                        format!("x{:04X}-x{:04X} can never be reached", start, end)
+                    };
+                    findings.push(Finding {
+                        address: start,
+                        check: Check::Unreachable,
+                        message,
+                    });
+                    run = None;
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+/// Checks that every subroutine that returns with `RET` saves R7 before
+/// anything overwrites it.
+fn unsaved_returns(program: &Program, reachable: &BTreeSet<u16>, findings: &mut Vec<Finding>) {
+    let subroutines: BTreeSet<u16> = reachable
+        .iter()
+        .filter_map(|&address| program.flow(address).call)
+        .filter(|&target| program.is_code(target))
+        .collect();
+
+    for &subroutine in &subroutines {
+        // Whether R7 is saved on every path to each instruction
+        let saved = program.solve(
+            &[(subroutine, false)],
+            |a, b| a && b,
+            |flow, saved| {
+                let decoded = &flow.decoded;
+                let saves = match decoded.operation {
+                    Operation::St | Operation::Sti | Operation::Str => decoded.dr == 7,
+                    // `ADD R6, R7, #0` keeps a copy
+                    Operation::AddImmediate => decoded.sr == 7 && decoded.operand == 0,
+                    _ => false,
+                };
+                let saved = saved || saves;
+                flow.successors.iter().map(|&next| (next, saved)).collect()
+            },
+        );
+        if !saved
+            .keys()
+            .any(|&address| is_return(&program.flow(address).decoded))
+        {
+            continue;
+        }
+        for (&address, &saved) in &saved {
+            let decoded = program.flow(address).decoded;
+            let (_, writes) = registers(&decoded);
+            if saved
+                || writes & R7 == 0
+                || decoded.operation == Operation::Trap && decoded.operand & 0xFF == TRAP_HALT
+            {
+                continue;
+            }
+            findings.push(Finding {
+                address,
+                check: Check::UnsavedR7,
+                message: format!(
+                    "{} overwrites R7 before subroutine x{:04X} has saved it, so its RET \
+                     will not return to the caller",
+                    mnemonic(address, program.memory[address as usize]),
+                    subroutine
+                ),
+            });
+        }
+    }
+}
+
+/// The registers each subroutine writes on every path to its `RET`.
+fn summaries(program: &Program, subroutines: &BTreeSet<u16>) -> BTreeMap<u16, u8> {
+    let mut summaries: BTreeMap<u16, u8> = subroutines
+        .iter()
+        .map(|&subroutine| (subroutine, ALL))
+        .collect();
+    loop {
+        let mut changed = false;
+        for &subroutine in subroutines {
+            let written = program.solve(
+                &[(subroutine, 0u8)],
+                |a, b| a & b,
+                |flow, written| {
+                    let written = written | written_by(flow, &summaries);
+                    flow.successors
+                        .iter()
+                        .map(|&next| (next, written))
+                        .collect()
+                },
+            );
+            let summary = written
+                .iter()
+                .filter(|&(&address, _)| is_return(&program.flow(address).decoded))
+                .fold(ALL, |summary, (_, &written)| summary & written);
+            if summaries.insert(subroutine, summary) != Some(summary) {
+                changed = true;
+            }
+        }
+        if !changed {
+            return summaries;
+        }
+    }
+}
+
+/// The registers written by the instruction at `address`, counting what a
+/// call writes before it returns. A `JSRR` could go anywhere, so it is
+/// taken to write everything.
+fn written_by(flow: &Flow, summaries: &BTreeMap<u16, u8>) -> u8 {
+    let (_, writes) = registers(&flow.decoded);
+    match (flow.decoded.operation, flow.call) {
+        (Operation::Jsrr, _) => ALL,
+        (_, Some(target)) => writes | summaries.get(&target).copied().unwrap_or(ALL),
+        _ => writes,
+    }
+}
+
+fn uninitialized_reads(
+    program: &Program,
+    entry: u16,
+    reachable: &BTreeSet<u16>,
+    findings: &mut Vec<Finding>,
+) {
+    let subroutines: BTreeSet<u16> = reachable
+        .iter()
+        .filter_map(|&address| program.flow(address).call)
+        .filter(|&target| program.is_code(target))
+        .collect();
+    let summaries = summaries(program, &subroutines);
+
+    // The registers written on every path from the entry to each
+    // instruction, with calls passing theirs on to the subroutine
+    let written = program.solve(
+        &[(entry, 0u8)],
+        |a, b| a & b,
+        |flow, written| {
+            let mut next: Vec<(u16, u8)> = flow
+                .successors
+                .iter()
+                .map(|&next| (next, written | written_by(flow, &summaries)))
+                .collect();
+            if let Some(target) = flow.call {
+                next.push((target, written | R7));
+            }
+            next
+        },
+    );
+
+    for (&address, &written) in &written {
+        let decoded = program.flow(address).decoded;
+        let (mut reads, _) = registers(&decoded);
+        // `STR Rn, R6, #k` of a register nothing wrote saves it for the
+        // caller, as memcheck's copies do, rather than using it
+        if decoded.operation == Operation::Str && decoded.sr == 6 {
+            reads &= !(1 << decoded.dr);
+        }
+        let unwritten = reads & !written;
+        for register in (0..8).filter(|register| unwritten & 1 << register != 0) {
+            findings.push(Finding {
+                address,
+                check: Check::UninitializedRead,
+                message: format!(
+                    "{} reads R{} before anything has written it",
+                    mnemonic(address, program.memory[address as usize]),
+                    register
+                ),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::lint::{data_words, lint, Check, Finding};
+    use crate::{LoadedImage, MEMORY_MAX};
+
+    fn findings(source: &str) -> Vec<Finding> {
+        let assembly = assemble(source).unwrap();
+        let mut memory = [0u16; MEMORY_MAX];
+        let origin = assembly.origin as usize;
+        memory[origin..origin + assembly.words.len()].copy_from_slice(&assembly.words);
+        let images = [LoadedImage {
+            file_name: "test.obj".to_string(),
+            origin: assembly.origin,
+            length: assembly.words.len() as u16,
+        }];
+        let data = data_words(&assembly);
+        lint(&memory, &images, assembly.origin, Some(&data))
+    }
+
+    fn checks(source: &str) -> Vec<(u16, Check)> {
+        findings(source)
+            .iter()
+            .map(|finding| (finding.address, finding.check))
+            .collect()
+    }
+
+    // ========== Clean Programs ==========
+
+    #[test]
+    fn test_clean_program_has_no_findings() {
+        let source = "\
+.ORIG x3000
+        LEA R0, MSG
+        PUTS
+        AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    JSR SHOW
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+SHOW    ST R7, SAVE
+        LD R0, STAR
+        OUT
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+STAR    .FILL x2A
+MSG     .STRINGZ \"Hi\"
+.END
+";
+        assert_eq!(findings(source), []);
+    }
+
+    // ========== Control Flow ==========
+
+    #[test]
+    fn test_falling_into_data_is_reported() {
+        let found = findings(".ORIG x3000\nAND R0, R0, #0\nOUT\nVALUE .FILL x41\n.END\n");
+
+        assert_eq!(
+            found.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "x3000: no path from the entry reaches HALT [missing-halt]",
+                "x3001: execution falls through into the data at x3002 [falls-into-data]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_running_off_the_end_of_an_image_is_reported() {
+        let found =
+            findings(".ORIG x3000\nAND R0, R0, #0\nBRz DONE\nHALT\nDONE ADD R0, R0, #1\n.END\n");
+
+        assert_eq!(
+            found.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["x3003: execution runs off the end of test.obj [missing-halt]"]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_code_is_reported_as_a_run() {
+        let found =
+            findings(".ORIG x3000\nBRnzp END\nADD R0, R0, #1\nADD R0, R0, #2\nEND HALT\n.END\n");
+
+        assert_eq!(
+            found.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["x3001: x3001-x3002 can never be reached [unreachable]"]
+        );
+    }
+
+    #[test]
+    fn test_empty_branch_mask_is_never_taken() {
+        let mut memory = [0u16; MEMORY_MAX];
+        memory[0x3000..0x3003].copy_from_slice(&[0x0001, 0x5020, 0xF025]);
+        let images = [LoadedImage {
+            file_name: "raw.obj".to_string(),
+            origin: 0x3000,
+            length: 3,
+        }];
+        let found = lint(&memory, &images, 0x3000, None);
+        assert_eq!(
+            found.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["x3000: branch x0001 has an empty nzp mask, so it never jumps [never-taken]"]
+        );
+    }
+
+    #[test]
+    fn test_code_reached_through_a_pointer_is_reachable() {
+        let source = "\
+.ORIG x3000
+        LD R1, TABLE
+        JSRR R1
+        HALT
+TASK    RET
+TABLE   .FILL TASK
+.END
+";
+        assert_eq!(checks(source), []);
+    }
+
+    #[test]
+    fn test_program_without_halt_is_reported() {
+        assert_eq!(
+            checks(".ORIG x3000\nLOOP BRnzp LOOP\n.END\n"),
+            [(0x3000, Check::MissingHalt)]
+        );
+    }
+
+    // ========== Subroutines ==========
+
+    #[test]
+    fn test_nested_call_without_saving_r7_is_reported() {
+        let source = "\
+.ORIG x3000
+        JSR OUTER
+        HALT
+OUTER   AND R0, R0, #0
+        JSR INNER
+        RET
+INNER   RET
+.END
+";
+        let found = findings(source);
+
+        assert_eq!(
+            found.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "x3003: JSR overwrites R7 before subroutine x3002 has saved it, so its RET \
+              will not return to the caller [unsaved-r7]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trap_in_a_subroutine_needs_r7_saved() {
+        let unsaved = ".ORIG x3000\nAND R0, R0, #0\nJSR PRINT\nHALT\nPRINT OUT\nRET\n.END\n";
+        assert_eq!(checks(unsaved), [(0x3003, Check::UnsavedR7)]);
+
+        let copied = ".ORIG x3000\nAND R0, R0, #0\nJSR PRINT\nHALT\n\
+                      PRINT ADD R6, R7, #0\nOUT\nADD R7, R6, #0\nRET\n.END\n";
+        assert_eq!(checks(copied), []);
+    }
+
+    // ========== Registers ==========
+
+    #[test]
+    fn test_reading_a_register_before_writing_it_is_reported() {
+        let found = findings(".ORIG x3000\nADD R1, R2, #1\nAND R3, R3, #0\nHALT\n.END\n");
+
+        assert_eq!(
+            found.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["x3000: ADD reads R2 before anything has written it [uninitialized-read]"]
+        );
+    }
+
+    #[test]
+    fn test_register_written_on_one_path_only_is_reported() {
+        let source = "\
+.ORIG x3000
+        AND R0, R0, #0
+        BRz SKIP
+        LD R1, ONE
+SKIP    ADD R0, R1, #0
+        HALT
+ONE     .FILL 1
+.END
+";
+        assert_eq!(checks(source), [(0x3003, Check::UninitializedRead)]);
+    }
+
+    #[test]
+    fn test_registers_a_subroutine_writes_count_after_the_call() {
+        let source = "\
+.ORIG x3000
+        JSR READ
+        OUT
+        HALT
+READ    ST R7, SAVE
+        GETC
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+.END
+";
+        assert_eq!(checks(source), []);
+    }
+
+    #[test]
+    fn test_subroutine_arguments_come_from_the_callers() {
+        let source = "\
+.ORIG x3000
+        AND R1, R1, #0
+        JSR DOUBLE
+        JSR TWICE
+        HALT
+DOUBLE  ADD R1, R1, R1
+        RET
+TWICE   ADD R2, R2, R2
+        RET
+.END
+";
+        assert_eq!(checks(source), [(0x3006, Check::UninitializedRead)]);
+    }
+
+    #[test]
+    fn test_callee_saves_on_the_stack_are_not_reads() {
+        let source = "\
+.ORIG x3000
+        LD R6, STACK
+        JSR WORK
+        HALT
+STACK   .FILL xFE00
+WORK    ADD R6, R6, #-3
+        STR R1, R6, #0
+        STR R2, R6, #1
+        STR R3, R6, #2
+        AND R1, R1, #0
+        AND R2, R2, #0
+        AND R3, R3, #0
+        LDR R1, R6, #0
+        LDR R2, R6, #1
+        LDR R3, R6, #2
+        ADD R6, R6, #3
+        RET
+.END
+";
+        assert_eq!(checks(source), []);
+    }
+
+    // ========== Reserved Memory ==========
+
+    #[test]
+    fn test_stores_into_reserved_memory_are_reported() {
+        let source = "\
+.ORIG x3000
+        AND R0, R0, #0
+        STI R0, DDR
+        STI R0, VECTOR
+        ST R0, SAFE
+        HALT
+SAFE    .BLKW 1
+DDR     .FILL xFE06
+VECTOR  .FILL x0021
+.END
+";
+        let found = findings(source);
+
+        assert_eq!(
+            found.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "x3001: STI writes xFE06 in the device registers [reserved-write]",
+                "x3002: STI writes x0021 in the trap vector table [reserved-write]",
+            ]
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Checks a set of images against each other and against the reserved
+//! parts of memory before any of them is written.
+
+use crate::image::Image;
+use crate::machine::{Machine, TrapMode};
+use crate::LoadedImage;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// A range of memory programs should not be loaded into, `end` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: u16,
+    pub end: u16,
+}
+
+pub const TRAP_VECTORS: Region = Region {
+    name: "trap vector table",
+    start: 0x0000,
+    end: 0x00FF,
+};
+
+pub const INTERRUPT_VECTORS: Region = Region {
+    name: "interrupt vector table",
+    start: 0x0100,
+    end: 0x01FF,
+};
+
+pub const DEVICE_REGISTERS: Region = Region {
+    name: "device registers",
+    start: 0xFE00,
+    end: 0xFFFF,
+};
+
+/// The regions reserved on `machine`. When traps go through the vector
+/// table the OS image is expected to fill it, so only the device page is
+/// reserved.
+pub fn reserved_regions(machine: &Machine) -> Vec<Region> {
+    match machine.traps {
+        TrapMode::Native => vec![TRAP_VECTORS, INTERRUPT_VECTORS, DEVICE_REGISTERS],
+        TrapMode::Os => vec![DEVICE_REGISTERS],
+    }
+}
+
+/// What to do about an image that reaches into a reserved region.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReservedPolicy {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+impl ReservedPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            ReservedPolicy::Allow => "allow",
+            ReservedPolicy::Warn => "warn",
+            ReservedPolicy::Deny => "deny",
+        }
+    }
+}
+
+impl FromStr for ReservedPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [
+            ReservedPolicy::Allow,
+            ReservedPolicy::Warn,
+            ReservedPolicy::Deny,
+        ]
+        .into_iter()
+        .find(|policy| policy.name() == name)
+        .ok_or_else(|| format!("unknown policy '{}', expected allow, warn or deny", name))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LoadPolicy {
+    pub reserved: ReservedPolicy,
+    /// Let a later image overwrite an earlier one, with a warning.
+    pub allow_overlap: bool,
+}
+
+/// One loaded segment, `length` words from `origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapEntry {
+    pub file_name: String,
+    pub origin: u16,
+    pub length: usize,
+}
+
+impl MapEntry {
+    /// The first address past the segment, which may be x10000.
+    fn end(&self) -> usize {
+        self.origin as usize + self.length
+    }
+
+    fn covers(&self, region: &Region) -> bool {
+        self.length > 0 && self.origin <= region.end && (region.start as usize) < self.end()
+    }
+
+    fn overlaps(&self, other: &MapEntry) -> bool {
+        self.length > 0
+            && other.length > 0
+            && (self.origin as usize) < other.end()
+            && (other.origin as usize) < self.end()
+    }
+
+    fn range(&self) -> String {
+        format!(
+            "x{:04X}-x{:04X}",
+            self.origin,
+            self.end().saturating_sub(1).max(self.origin as usize)
+        )
+    }
+}
+
+/// Every segment of every image, in load order.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub entries: Vec<MapEntry>,
+}
+
+impl MemoryMap {
+    pub fn of_images(images: &[(String, Image)]) -> MemoryMap {
+        let entries = images
+            .iter()
+            .flat_map(|(file_name, image)| {
+                image.segments.iter().map(move |segment| MapEntry {
+                    file_name: file_name.clone(),
+                    origin: segment.origin,
+                    length: segment.words.len(),
+                })
+            })
+            .collect();
+        MemoryMap { entries }
+    }
+
+    /// The map of what a VM has loaded so far.
+    pub fn of_loaded(images: &[LoadedImage]) -> MemoryMap {
+        let entries = images
+            .iter()
+            .map(|image| MapEntry {
+                file_name: image.file_name.clone(),
+                origin: image.origin,
+                length: image.length as usize,
+            })
+            .collect();
+        MemoryMap { entries }
+    }
+
+    /// One line per segment and reserved region, in address order.
+    pub fn layout(&self, reserved: &[Region]) -> String {
+        let mut lines: Vec<(u16, String)> = reserved
+            .iter()
+            .map(|region| {
+                (
+                    region.start,
+                    format!(
+                        "x{:04X}-x{:04X}  {} (reserved)",
+                        region.start, region.end, region.name
+                    ),
+                )
+            })
+            .collect();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let mut line = format!(
+                "{}  {} ({} words)",
+                entry.range(),
+                entry.file_name,
+                entry.length
+            );
+            let earlier: Vec<&str> = self.entries[..index]
+                .iter()
+                .filter(|other| other.overlaps(entry))
+                .map(|other| other.file_name.as_str())
+                .collect();
+            if !earlier.is_empty() {
+                write!(line, ", over {}", earlier.join(", ")).unwrap();
+            }
+            lines.push((entry.origin, line));
+        }
+        // Stable, so a region comes before an image at the same address
+        lines.sort_by_key(|(start, _)| *start);
+
+        let mut out = String::new();
+        for (_, line) in lines {
+            writeln!(out, "{}", line).unwrap();
+        }
+        out
+    }
+}
+
+/// What `check` found. Nothing should be loaded if there are errors.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub map: MemoryMap,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Checks that `images` fit in memory, do not overlap each other and stay
+/// out of the `reserved` regions, as far as `policy` asks.
+pub fn check(images: &[(String, Image)], reserved: &[Region], policy: LoadPolicy) -> Report {
+    let mut report = Report {
+        map: MemoryMap::of_images(images),
+        ..Report::default()
+    };
+
+    for (index, entry) in report.map.entries.iter().enumerate() {
+        if entry.end() > 0x10000 {
+            report.errors.push(format!(
+                "{}: {} words at x{:04X} run past xFFFF",
+                entry.file_name, entry.length, entry.origin
+            ));
+            continue;
+        }
+
+        for region in reserved.iter().filter(|region| entry.covers(region)) {
+            let message = format!(
+                "{}: {} overlaps the {} at x{:04X}-x{:04X}",
+                entry.file_name,
+                entry.range(),
+                region.name,
+                region.start,
+                region.end
+            );
+            match policy.reserved {
+                ReservedPolicy::Allow => {}
+                ReservedPolicy::Warn => report.warnings.push(message),
+                ReservedPolicy::Deny => report.errors.push(message),
+            }
+        }
+
+        for earlier in report.map.entries[..index]
+            .iter()
+            .filter(|earlier| earlier.overlaps(entry))
+        {
+            let message = format!(
+                "{}: {} overwrites {} loaded at {}",
+                entry.file_name,
+                entry.range(),
+                earlier.file_name,
+                earlier.range()
+            );
+            if policy.allow_overlap {
+                report.warnings.push(message);
+            } else {
+                report.errors.push(message);
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::{Image, Segment};
+    use crate::loader::{
+        check, reserved_regions, LoadPolicy, ReservedPolicy, DEVICE_REGISTERS, TRAP_VECTORS,
+    };
+    use crate::machine::{Machine, TrapMode};
+
+    fn image(file_name: &str, origin: u16, length: usize) -> (String, Image) {
+        (file_name.to_string(), Image::new(origin, vec![0; length]))
+    }
+
+    // ========== Overlaps and Bounds ==========
+
+    #[test]
+    fn test_separate_images_are_clean() {
+        let report = check(
+            &[image("a.obj", 0x3000, 16), image("b.obj", 0x3010, 16)],
+            &reserved_regions(&Machine::default()),
+            LoadPolicy::default(),
+        );
+        assert!(report.warnings.is_empty() && report.errors.is_empty());
+        assert_eq!(report.map.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_overlaps_fail_unless_allowed() {
+        let images = [image("a.obj", 0x3000, 16), image("b.obj", 0x300F, 2)];
+        let report = check(&images, &[], LoadPolicy::default());
+        assert_eq!(
+            report.errors,
+            ["b.obj: x300F-x3010 overwrites a.obj loaded at x3000-x300F"]
+        );
+
+        let policy = LoadPolicy {
+            allow_overlap: true,
+            ..LoadPolicy::default()
+        };
+        let report = check(&images, &[], policy);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_wraparound_is_an_error() {
+        let wrapping = (
+            "high.obj".to_string(),
+            Image {
+                segments: vec![Segment {
+                    origin: 0xFFFE,
+                    words: vec![0; 4],
+                }],
+            },
+        );
+        let report = check(&[wrapping], &[], LoadPolicy::default());
+        assert_eq!(report.errors, ["high.obj: 4 words at xFFFE run past xFFFF"]);
+    }
+
+    // ========== Reserved Regions ==========
+
+    #[test]
+    fn test_reserved_regions_follow_the_policy() {
+        let images = [image("vectors.obj", 0x0020, 2), image("io.obj", 0xFDFF, 2)];
+        let reserved = [TRAP_VECTORS, DEVICE_REGISTERS];
+
+        let report = check(&images, &reserved, LoadPolicy::default());
+        assert_eq!(
+            report.warnings,
+            [
+                "vectors.obj: x0020-x0021 overlaps the trap vector table at x0000-x00FF",
+                "io.obj: xFDFF-xFE00 overlaps the device registers at xFE00-xFFFF",
+            ]
+        );
+
+        let deny = LoadPolicy {
+            reserved: ReservedPolicy::Deny,
+            ..LoadPolicy::default()
+        };
+        assert_eq!(check(&images, &reserved, deny).errors.len(), 2);
+        let allow = LoadPolicy {
+            reserved: ReservedPolicy::Allow,
+            ..LoadPolicy::default()
+        };
+        let report = check(&images, &reserved, allow);
+        assert!(report.warnings.is_empty() && report.errors.is_empty());
+    }
+
+    #[test]
+    fn test_os_machines_may_fill_the_vector_tables() {
+        let machine = Machine {
+            traps: TrapMode::Os,
+            ..Machine::default()
+        };
+        assert_eq!(reserved_regions(&machine), [DEVICE_REGISTERS]);
+    }
+
+    // ========== Layout ==========
+
+    #[test]
+    fn test_layout_lists_segments_and_regions_by_address() {
+        let report = check(
+            &[image("prog.obj", 0x3000, 16), image("lib.obj", 0x3008, 4)],
+            &[DEVICE_REGISTERS],
+            LoadPolicy {
+                allow_overlap: true,
+                ..LoadPolicy::default()
+            },
+        );
+        assert_eq!(
+            report.map.layout(&[DEVICE_REGISTERS]),
+            "x3000-x300F  prog.obj (16 words)\n\
+             x3008-x300B  lib.obj (4 words), over prog.obj\n\
+             xFE00-xFFFF  device registers (reserved)\n"
+        );
+    }
+}
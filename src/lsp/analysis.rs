@@ -0,0 +1,545 @@
+use crate::asm::lexer::{is_operation, parse_number, OPCODES};
+use crate::asm::{assemble_with, AsmOptions, Assembly};
+use crate::lsp::docs::{self, DIRECTIVES};
+
+/// A stretch of one line. Lines and characters are 0-based and characters
+/// are counted in UTF-16 code units, as the protocol expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: u32,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Span {
+    fn contains(&self, line: u32, character: u32) -> bool {
+        self.line == line && self.start <= character && character <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A label on an instruction.
+    Code,
+    /// A label on `.FILL`, `.BLKW` or `.STRINGZ`.
+    Data,
+    /// A name from `.EQU` or `.DEFINE`.
+    Constant,
+    Macro,
+}
+
+/// A name the document defines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Operation,
+    Definition(SymbolKind),
+    Reference,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    name: String,
+    span: Span,
+    role: Role,
+}
+
+/// A word on a line with its span, skipping strings and the comment.
+fn words(text: &str, line: u32) -> Vec<(String, Span)> {
+    let mut words = Vec::new();
+    let mut column = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let start = column;
+        column += c.len_utf16() as u32;
+        match c {
+            ';' => break,
+            '"' => {
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    column += c.len_utf16() as u32;
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '@' => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    word.push(next);
+                    column += next.len_utf16() as u32;
+                    chars.next();
+                }
+                let span = Span {
+                    line,
+                    start,
+                    end: column,
+                };
+                words.push((word, span));
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// Whether `word` can only be a register, number or function, not a name.
+fn is_value(word: &str) -> bool {
+    let register = matches!(word.as_bytes(), [b'R' | b'r', b'0'..=b'7']);
+    register
+        || parse_number(word).is_some()
+        || word.starts_with(|c: char| c.is_ascii_digit())
+        || word.eq_ignore_ascii_case("hi")
+        || word.eq_ignore_ascii_case("lo")
+}
+
+/// Finds the names each line defines and uses. Lines inside a macro define
+/// nothing, since their labels only exist once the macro is used.
+fn tokens(lines: &[&str]) -> Vec<Token> {
+    let macros: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(index, text)| {
+            let words = words(text, index as u32);
+            match words.as_slice() {
+                [(directive, _), (name, _), ..] if directive.eq_ignore_ascii_case(".MACRO") => {
+                    Some(name.to_ascii_uppercase())
+                }
+                _ => None,
+            }
+        })
+        .collect();
+    let is_macro = |word: &str| macros.contains(&word.to_ascii_uppercase());
+
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+    for (index, text) in lines.iter().enumerate() {
+        let words = words(text, index as u32);
+        let operation = words
+            .iter()
+            .position(|(word, _)| is_operation(word) || is_macro(word));
+        let directive = operation.map(|index| words[index].0.to_ascii_uppercase());
+        let kind = match directive.as_deref() {
+            Some(".FILL" | ".BLKW" | ".STRINGZ") => SymbolKind::Data,
+            Some(".EQU") => SymbolKind::Constant,
+            _ => SymbolKind::Code,
+        };
+
+        for (position, (word, span)) in words.iter().enumerate() {
+            let role = match operation {
+                Some(operation) if position == operation && is_operation(word) => Role::Operation,
+                Some(operation) if position == operation => Role::Reference,
+                // A label before the operation, or alone on its line
+                _ if position == 0 && operation != Some(0) && !in_macro => Role::Definition(kind),
+                // The name after `.EQU`, `.DEFINE` or `.MACRO`
+                Some(operation) if position == operation + 1 && position == 1 && !in_macro => {
+                    match directive.as_deref() {
+                        Some(".EQU" | ".DEFINE") => Role::Definition(SymbolKind::Constant),
+                        Some(".MACRO") => Role::Definition(SymbolKind::Macro),
+                        _ => Role::Reference,
+                    }
+                }
+                _ => Role::Reference,
+            };
+            if role == Role::Reference && (is_value(word) || word.starts_with(['.', '@'])) {
+                continue;
+            }
+            tokens.push(Token {
+                name: word.clone(),
+                span: *span,
+                role,
+            });
+        }
+
+        match directive.as_deref() {
+            Some(".MACRO") => in_macro = true,
+            Some(".ENDM") => in_macro = false,
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// What the language server knows about one document.
+pub struct Analysis {
+    lines: Vec<String>,
+    tokens: Vec<Token>,
+    assembly: Option<Assembly>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    /// Assembles `text` and indexes its names. Files that link with others,
+    /// or have no `.ORIG` like libraries of macros, are assembled as
+    /// relocatable modules.
+    pub fn new(text: &str, options: &AsmOptions) -> Analysis {
+        let lines: Vec<&str> = text.lines().collect();
+        let tokens = tokens(&lines);
+        let uses = |directives: &[&str]| {
+            tokens.iter().any(|token| {
+                token.role == Role::Operation
+                    && directives.contains(&token.name.to_ascii_uppercase().as_str())
+            })
+        };
+        let options = AsmOptions {
+            relocatable: !uses(&[".ORIG"]) || uses(&[".IMPORT", ".EXTERNAL", ".EXPORT"]),
+            ..options.clone()
+        };
+
+        let mut analysis = Analysis {
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            tokens,
+            assembly: None,
+            diagnostics: Vec::new(),
+        };
+        match assemble_with(text, &options) {
+            Ok(assembly) => analysis.assembly = Some(assembly),
+            Err(errors) => {
+                analysis.diagnostics = errors
+                    .into_iter()
+                    .map(|error| Diagnostic {
+                        span: analysis.error_span(error.line, &error.message),
+                        message: error.message,
+                    })
+                    .collect();
+            }
+        }
+        analysis
+    }
+
+    /// The name an error is about if it quotes one on its line, or else the
+    /// code on the line.
+    fn error_span(&self, line: usize, message: &str) -> Span {
+        let line = (line.max(1) - 1).min(self.lines.len().saturating_sub(1)) as u32;
+        let quoted = message.split('\'').nth(1);
+        let named = self
+            .tokens
+            .iter()
+            .find(|token| token.span.line == line && Some(token.name.as_str()) == quoted);
+        if let Some(token) = named {
+            return token.span;
+        }
+        let words = words(
+            self.lines.get(line as usize).map_or("", String::as_str),
+            line,
+        );
+        match (words.first(), words.last()) {
+            (Some((_, first)), Some((_, last))) => Span {
+                line,
+                start: first.start,
+                end: last.end,
+            },
+            _ => Span {
+                line,
+                start: 0,
+                end: 0,
+            },
+        }
+    }
+
+    fn token_at(&self, line: u32, character: u32) -> Option<&Token> {
+        self.tokens
+            .iter()
+            .find(|token| token.span.contains(line, character))
+    }
+
+    fn definition_of(&self, name: &str) -> Option<&Token> {
+        self.tokens
+            .iter()
+            .find(|token| matches!(token.role, Role::Definition(_)) && token.name == name)
+    }
+
+    /// Where the name at a position is defined.
+    pub fn definition(&self, line: u32, character: u32) -> Option<Span> {
+        let token = self.token_at(line, character)?;
+        self.definition_of(&token.name).map(|token| token.span)
+    }
+
+    /// Every use of the name at a position, and its definition if asked.
+    pub fn references(&self, line: u32, character: u32, include_definition: bool) -> Vec<Span> {
+        let Some(token) = self.token_at(line, character) else {
+            return Vec::new();
+        };
+        if token.role == Role::Operation {
+            return Vec::new();
+        }
+        self.tokens
+            .iter()
+            .filter(|other| other.name == token.name)
+            .filter(|other| include_definition || !matches!(other.role, Role::Definition(_)))
+            .map(|other| other.span)
+            .collect()
+    }
+
+    /// Markdown describing the operation or name at a position.
+    pub fn hover(&self, line: u32, character: u32) -> Option<String> {
+        let token = self.token_at(line, character)?;
+        if token.role == Role::Operation {
+            let doc = docs::lookup(&token.name.to_ascii_uppercase())?;
+            let mut text = doc.markdown();
+            let words = self.words_on(line);
+            if !words.is_empty() {
+                text.push_str("\n\nAssembled:");
+                for (address, word) in words.iter().take(8) {
+                    text.push_str(&format!(
+                        "\n- `x{:04X}: {:04X} {:016b}`",
+                        address, word, word
+                    ));
+                }
+                if words.len() > 8 {
+                    text.push_str(&format!("\n- and {} more words", words.len() - 8));
+                }
+            }
+            return Some(text);
+        }
+
+        let definition = self.definition_of(&token.name)?;
+        let source = self.lines[definition.span.line as usize].trim();
+        let source = source.split(';').next().unwrap_or(source).trim_end();
+        let address = self
+            .assembly
+            .as_ref()
+            .and_then(|assembly| assembly.symbols.address_of(&token.name));
+        Some(match address {
+            Some(address) => format!(
+                "```\n{}\n```\n`{}` is at x{:04X}",
+                source, token.name, address
+            ),
+            None => format!("```\n{}\n```", source),
+        })
+    }
+
+    /// The words a line of the document assembled to, with their addresses.
+    fn words_on(&self, line: u32) -> Vec<(u16, u16)> {
+        let Some(assembly) = &self.assembly else {
+            return Vec::new();
+        };
+        assembly
+            .listing
+            .lines
+            .iter()
+            .filter(|listed| listed.location.file.is_none())
+            .filter(|listed| listed.location.line == line as usize + 1)
+            .flat_map(|listed| {
+                (listed.address..)
+                    .zip(listed.words.iter().copied())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The names the document defines, in order.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        self.tokens
+            .iter()
+            .filter_map(|token| match token.role {
+                Role::Definition(kind) => Some(Symbol {
+                    name: token.name.clone(),
+                    kind,
+                    span: token.span,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Everything worth offering at the cursor: mnemonics with every branch
+    /// mask, directives and the document's own names.
+    pub fn completions(&self) -> Vec<Completion> {
+        let mut completions = Vec::new();
+        for mnemonic in OPCODES {
+            if mnemonic == "BR" {
+                for mask in ["", "n", "z", "p", "nz", "np", "zp", "nzp"] {
+                    completions.push(Completion::keyword(format!("BR{}", mask)));
+                }
+            } else {
+                completions.push(Completion::keyword(mnemonic.to_string()));
+            }
+        }
+        for directive in DIRECTIVES {
+            completions.push(Completion::keyword(directive.to_string()));
+        }
+        for symbol in self.symbols() {
+            if completions
+                .iter()
+                .all(|completion| completion.label != symbol.name)
+            {
+                completions.push(Completion {
+                    label: symbol.name,
+                    kind: Some(symbol.kind),
+                    detail: None,
+                });
+            }
+        }
+        completions
+    }
+}
+
+/// One completion. `kind` is `None` for mnemonics and directives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: Option<SymbolKind>,
+    pub detail: Option<&'static str>,
+}
+
+impl Completion {
+    fn keyword(label: String) -> Completion {
+        let detail = docs::lookup(&label.to_ascii_uppercase()).map(|doc| doc.summary);
+        Completion {
+            label,
+            kind: None,
+            detail,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::AsmOptions;
+    use crate::lsp::analysis::{Analysis, Span, SymbolKind};
+
+    const PROGRAM: &str = "\
+SIZE    .EQU 3
+        .ORIG x3000
+        LD R1, COUNT   ; load \"COUNT\"
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL SIZE
+        .END
+";
+
+    fn analyze(text: &str) -> Analysis {
+        Analysis::new(text, &AsmOptions::default())
+    }
+
+    fn span(line: u32, start: u32, end: u32) -> Span {
+        Span { line, start, end }
+    }
+
+    // ========== Diagnostics ==========
+
+    #[test]
+    fn test_clean_program_has_no_diagnostics() {
+        assert!(analyze(PROGRAM).diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_diagnostics_point_at_the_problem() {
+        let analysis = analyze(".ORIG x3000\nADD R1, R1, #99\nBR NOWHERE\nMOV R1, R2\nLD R0, FAR\n.BLKW 300\nFAR .FILL 0\n.END\n");
+        let diagnostics: Vec<_> = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.span, diagnostic.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            diagnostics,
+            [
+                (
+                    span(1, 0, 15),
+                    "immediate 99 does not fit in 5 bits (-16..15)"
+                ),
+                (span(2, 3, 10), "undefined label 'NOWHERE'"),
+                (span(3, 0, 3), "unknown operation 'MOV'"),
+                (
+                    span(4, 0, 10),
+                    "PC offset 300 does not fit in 9 bits (-256..255)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_files_without_orig_are_checked_as_modules() {
+        assert!(analyze(".MACRO PRINT msg\nLEA R0, msg\nPUTS\n.ENDM\n")
+            .diagnostics
+            .is_empty());
+    }
+
+    // ========== Navigation ==========
+
+    #[test]
+    fn test_definition_and_references() {
+        let analysis = analyze(PROGRAM);
+
+        assert_eq!(analysis.definition(4, 14), Some(span(3, 0, 4)));
+        assert_eq!(analysis.definition(6, 15), Some(span(0, 0, 4)));
+        assert_eq!(analysis.definition(2, 8), None);
+        assert_eq!(
+            analysis.references(3, 2, true),
+            [span(3, 0, 4), span(4, 12, 16)]
+        );
+        assert_eq!(analysis.references(6, 2, false), [span(2, 15, 20)]);
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let symbols: Vec<_> = analyze(PROGRAM)
+            .symbols()
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.kind))
+            .collect();
+
+        assert_eq!(
+            symbols,
+            [
+                ("SIZE".to_string(), SymbolKind::Constant),
+                ("LOOP".to_string(), SymbolKind::Code),
+                ("COUNT".to_string(), SymbolKind::Data),
+            ]
+        );
+    }
+
+    // ========== Hover and Completion ==========
+
+    #[test]
+    fn test_hover_shows_encoding_and_addresses() {
+        let analysis = analyze(PROGRAM);
+
+        let hover = analysis.hover(3, 9).unwrap();
+        assert!(hover.starts_with("```\nADD DR, SR1, SR2"), "{}", hover);
+        assert!(
+            hover.ends_with("- `x3001: 127F 0001001001111111`"),
+            "{}",
+            hover
+        );
+        assert_eq!(
+            analysis.hover(4, 13).unwrap(),
+            "```\nLOOP    ADD R1, R1, #-1\n```\n`LOOP` is at x3001"
+        );
+        assert_eq!(analysis.hover(6, 16).unwrap(), "```\nSIZE    .EQU 3\n```");
+    }
+
+    #[test]
+    fn test_completion_offers_mnemonics_directives_and_labels() {
+        let labels: Vec<_> = analyze(PROGRAM)
+            .completions()
+            .into_iter()
+            .map(|completion| completion.label)
+            .collect();
+
+        for expected in ["BRnzp", "PUTSP", ".STRINGZ", ".MACRO", "LOOP", "COUNT"] {
+            assert!(labels.iter().any(|label| label == expected), "{}", expected);
+        }
+    }
+}
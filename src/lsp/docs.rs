@@ -0,0 +1,251 @@
+/// What hovering over a mnemonic or directive shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Doc {
+    pub syntax: &'static str,
+    /// The bit layout, most significant bit first. Empty for directives.
+    pub encoding: &'static str,
+    pub summary: &'static str,
+}
+
+const fn doc(syntax: &'static str, encoding: &'static str, summary: &'static str) -> Doc {
+    Doc {
+        syntax,
+        encoding,
+        summary,
+    }
+}
+
+impl Doc {
+    /// The entry as Markdown.
+    pub fn markdown(&self) -> String {
+        let mut text = format!("```\n{}\n```\n{}", self.syntax, self.summary);
+        if !self.encoding.is_empty() {
+            text.push_str(&format!(
+                "\n\nEncoding: `{}`",
+                self.encoding.replace('\n', "` or `")
+            ));
+        }
+        text
+    }
+}
+
+/// The directives the assembler and its preprocessor accept.
+pub const DIRECTIVES: [&str; 18] = [
+    ".ORIG",
+    ".FILL",
+    ".BLKW",
+    ".STRINGZ",
+    ".END",
+    ".IMPORT",
+    ".EXTERNAL",
+    ".EXPORT",
+    ".INCLUDE",
+    ".DEFINE",
+    ".EQU",
+    ".MACRO",
+    ".ENDM",
+    ".IF",
+    ".IFDEF",
+    ".IFNDEF",
+    ".ELSE",
+    ".ENDIF",
+];
+
+/// The documentation for `word`, an upper-case mnemonic, trap alias or
+/// directive. Branches with any condition mask share `BR`'s entry.
+pub fn lookup(word: &str) -> Option<Doc> {
+    let word = match word.strip_prefix("BR") {
+        Some(mask) if crate::asm::lexer::is_condition_mask(mask) => "BR",
+        _ => word,
+    };
+    Some(match word {
+        "ADD" => doc(
+            "ADD DR, SR1, SR2\nADD DR, SR1, imm5",
+            "0001 DR SR1 0 00 SR2\n0001 DR SR1 1 imm5",
+            "DR = SR1 + SR2, or SR1 plus the sign-extended imm5. Sets the condition codes.",
+        ),
+        "AND" => doc(
+            "AND DR, SR1, SR2\nAND DR, SR1, imm5",
+            "0101 DR SR1 0 00 SR2\n0101 DR SR1 1 imm5",
+            "DR = SR1 & SR2, or SR1 and the sign-extended imm5. Sets the condition codes.",
+        ),
+        "NOT" => doc(
+            "NOT DR, SR",
+            "1001 DR SR 111111",
+            "DR = ~SR. Sets the condition codes.",
+        ),
+        "BR" => doc(
+            "BR[n][z][p] LABEL",
+            "0000 n z p PCoffset9",
+            "Jumps to LABEL if one of the named condition codes is set; plain BR always jumps.",
+        ),
+        "JMP" => doc("JMP BaseR", "1100 000 BaseR 000000", "PC = BaseR."),
+        "RET" => doc(
+            "RET",
+            "1100 000 111 000000",
+            "PC = R7, returning from JSR or JSRR.",
+        ),
+        "JSR" => doc(
+            "JSR LABEL",
+            "0100 1 PCoffset11",
+            "R7 = PC, then jumps to LABEL.",
+        ),
+        "JSRR" => doc(
+            "JSRR BaseR",
+            "0100 0 00 BaseR 000000",
+            "R7 = PC, then PC = BaseR.",
+        ),
+        "LD" => doc(
+            "LD DR, LABEL",
+            "0010 DR PCoffset9",
+            "DR = mem[LABEL]. Sets the condition codes.",
+        ),
+        "LDI" => doc(
+            "LDI DR, LABEL",
+            "1010 DR PCoffset9",
+            "DR = mem[mem[LABEL]]. Sets the condition codes.",
+        ),
+        "LDR" => doc(
+            "LDR DR, BaseR, offset6",
+            "0110 DR BaseR offset6",
+            "DR = mem[BaseR + offset6]. Sets the condition codes.",
+        ),
+        "LEA" => doc(
+            "LEA DR, LABEL",
+            "1110 DR PCoffset9",
+            "DR = the address of LABEL. Sets the condition codes.",
+        ),
+        "ST" => doc("ST SR, LABEL", "0011 SR PCoffset9", "mem[LABEL] = SR."),
+        "STI" => doc(
+            "STI SR, LABEL",
+            "1011 SR PCoffset9",
+            "mem[mem[LABEL]] = SR.",
+        ),
+        "STR" => doc(
+            "STR SR, BaseR, offset6",
+            "0111 SR BaseR offset6",
+            "mem[BaseR + offset6] = SR.",
+        ),
+        "TRAP" => doc(
+            "TRAP trapvect8",
+            "1111 0000 trapvect8",
+            "R7 = PC, then PC = mem[trapvect8], calling an operating system routine.",
+        ),
+        "RTI" => doc(
+            "RTI",
+            "1000 000000000000",
+            "Returns from an interrupt, restoring the PC and PSR from the supervisor stack.",
+        ),
+        "GETC" => doc(
+            "GETC",
+            "1111 0000 00100000",
+            "TRAP x20: reads a character into R0 without echoing it.",
+        ),
+        "OUT" => doc(
+            "OUT",
+            "1111 0000 00100001",
+            "TRAP x21: writes the character in R0.",
+        ),
+        "PUTS" => doc(
+            "PUTS",
+            "1111 0000 00100010",
+            "TRAP x22: writes the string at R0, one character per word.",
+        ),
+        "IN" => doc(
+            "IN",
+            "1111 0000 00100011",
+            "TRAP x23: prompts for a character, echoes it and leaves it in R0.",
+        ),
+        "PUTSP" => doc(
+            "PUTSP",
+            "1111 0000 00100100",
+            "TRAP x24: writes the string at R0, two characters per word.",
+        ),
+        "HALT" => doc("HALT", "1111 0000 00100101", "TRAP x25: stops the machine."),
+        ".ORIG" => doc(
+            ".ORIG address",
+            "",
+            "Assembles the code that follows at address.",
+        ),
+        ".FILL" => doc(
+            ".FILL value",
+            "",
+            "One word holding value, which may be a label.",
+        ),
+        ".BLKW" => doc(
+            ".BLKW count [value]",
+            "",
+            "count words, zero or all holding value.",
+        ),
+        ".STRINGZ" => doc(
+            ".STRINGZ \"text\"",
+            "",
+            "The characters of text, one per word, then a zero word.",
+        ),
+        ".END" => doc(".END", "", "Ends the source; anything after it is ignored."),
+        ".IMPORT" | ".EXTERNAL" => doc(
+            ".IMPORT LABEL...",
+            "",
+            "Labels another module defines, filled in by the linker.",
+        ),
+        ".EXPORT" => doc(".EXPORT LABEL...", "", "Labels other modules may import."),
+        ".INCLUDE" => doc(
+            ".INCLUDE \"file\"",
+            "",
+            "Assembles file here, found next to this one or on the -I paths.",
+        ),
+        ".DEFINE" => doc(
+            ".DEFINE NAME text",
+            "",
+            "Replaces the word NAME with text on every later line.",
+        ),
+        ".EQU" => doc(
+            "NAME .EQU expression",
+            "",
+            "Names a constant, usable wherever a number is.",
+        ),
+        ".MACRO" => doc(
+            ".MACRO NAME [param...]",
+            "",
+            "Starts a macro, ended by .ENDM. Labels written @NAME are local to each use.",
+        ),
+        ".ENDM" => doc(".ENDM", "", "Ends a macro."),
+        ".IF" => doc(
+            ".IF expression",
+            "",
+            "Assembles the lines up to .ELSE or .ENDIF only if expression is not zero.",
+        ),
+        ".IFDEF" | ".IFNDEF" => doc(
+            ".IFDEF NAME\n.IFNDEF NAME",
+            "",
+            "Assembles the lines up to .ELSE or .ENDIF only if NAME is, or is not, defined.",
+        ),
+        ".ELSE" => doc(".ELSE", "", "Starts the lines used when the .IF is false."),
+        ".ENDIF" => doc(".ENDIF", "", "Ends an .IF block."),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::lexer::OPCODES;
+    use crate::lsp::docs::{lookup, DIRECTIVES};
+
+    #[test]
+    fn test_every_operation_is_documented() {
+        for word in OPCODES.iter().chain(DIRECTIVES.iter()) {
+            assert!(lookup(word).is_some(), "{} has no documentation", word);
+        }
+        assert_eq!(lookup("BRNZ"), lookup("BR"));
+        assert_eq!(lookup("BRPN"), None);
+    }
+
+    #[test]
+    fn test_markdown_shows_syntax_summary_and_encoding() {
+        assert_eq!(
+            lookup("NOT").unwrap().markdown(),
+            "```\nNOT DR, SR\n```\nDR = ~SR. Sets the condition codes.\n\n\
+             Encoding: `1001 DR SR 111111`"
+        );
+    }
+}
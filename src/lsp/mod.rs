@@ -0,0 +1,571 @@
+//! A language server for LC-3 assembly, speaking the Language Server
+//! Protocol over stdin and stdout. It runs the crate's own assembler on
+//! every change, so editors see the same errors `asm` reports.
+
+use crate::asm::AsmOptions;
+use crate::lsp::analysis::{Analysis, Span, SymbolKind};
+use crate::lsp::json::{object, Value};
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+pub mod analysis;
+pub mod docs;
+pub mod json;
+
+/// The longest message body read. Far beyond any LC-3 source, but it
+/// keeps a bad header from asking for any amount of memory.
+const MAX_MESSAGE: usize = 16 << 20;
+
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_REQUEST: i32 = -32600;
+const PARSE_ERROR: i32 = -32700;
+
+/// Reads one message framed by a `Content-Length` header. Returns `None`
+/// at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let content_length = header
+            .split_once(':')
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"));
+        if let Some((_, value)) = content_length {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        ));
+    };
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "message of {} bytes is over the limit of {}",
+                length, MAX_MESSAGE
+            ),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// The path a `file://` URI names.
+fn path_of(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // `file:///C:/x` names `C:/x` on Windows
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => path[1..].to_string(),
+        _ => path,
+    };
+    Some(PathBuf::from(path))
+}
+
+fn range(span: Span) -> Value {
+    let position = |character: u32| {
+        object([
+            ("line", Value::from(span.line)),
+            ("character", Value::from(character)),
+        ])
+    };
+    object([("start", position(span.start)), ("end", position(span.end))])
+}
+
+fn location(uri: &str, span: Span) -> Value {
+    object([("uri", Value::from(uri)), ("range", range(span))])
+}
+
+/// The protocol's `SymbolKind` and `CompletionItemKind` numbers.
+fn kinds(kind: SymbolKind) -> (u32, u32) {
+    match kind {
+        SymbolKind::Code => (12, 3),
+        SymbolKind::Data => (13, 6),
+        SymbolKind::Constant => (14, 21),
+        SymbolKind::Macro => (25, 15),
+    }
+}
+
+/// The server's state: the open documents, analysed after every change.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Analysis>,
+    /// Where `.INCLUDE` looks after the document's own directory.
+    pub include_paths: Vec<PathBuf>,
+    shut_down: bool,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Self::default()
+    }
+
+    /// Serves messages from `input` until `exit` or the end of the input.
+    /// Returns whether the client asked for a shutdown first, which is
+    /// what decides the exit code.
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+        while let Some(body) = read_message(input)? {
+            let message = match json::parse(&body) {
+                Ok(message) => message,
+                Err(e) => {
+                    let error = error_response(Value::Null, PARSE_ERROR, &e);
+                    write_message(output, &error)?;
+                    continue;
+                }
+            };
+            if message.get("method").and_then(Value::as_str) == Some("exit") {
+                return Ok(self.shut_down);
+            }
+            for reply in self.handle(&message) {
+                write_message(output, &reply)?;
+            }
+        }
+        Ok(self.shut_down)
+    }
+
+    /// Handles one request or notification and returns the messages to
+    /// send back: a response for requests, diagnostics after changes.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Value::Null);
+        let Some(id) = message.get("id").cloned() else {
+            return self.notification(method, params);
+        };
+
+        if self.shut_down {
+            return vec![error_response(
+                id,
+                INVALID_REQUEST,
+                "the server has shut down",
+            )];
+        }
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Some(Value::Null)
+            }
+            "textDocument/hover" => Some(self.at_position(params, |analysis, line, character| {
+                match analysis.hover(line, character) {
+                    Some(text) => object([(
+                        "contents",
+                        object([("kind", "markdown".into()), ("value", text.into())]),
+                    )]),
+                    None => Value::Null,
+                }
+            })),
+            "textDocument/definition" => {
+                let uri = document_uri(params).to_string();
+                Some(self.at_position(params, |analysis, line, character| {
+                    match analysis.definition(line, character) {
+                        Some(span) => location(&uri, span),
+                        None => Value::Null,
+                    }
+                }))
+            }
+            "textDocument/references" => {
+                let uri = document_uri(params).to_string();
+                let declaration = params
+                    .path(&["context", "includeDeclaration"])
+                    .and_then(Value::as_bool)
+                    .unwrap_or(true);
+                Some(self.at_position(params, |analysis, line, character| {
+                    let spans = analysis.references(line, character, declaration);
+                    spans
+                        .into_iter()
+                        .map(|span| location(&uri, span))
+                        .collect::<Vec<_>>()
+                        .into()
+                }))
+            }
+            "textDocument/completion" => Some(self.completions(params)),
+            "textDocument/documentSymbol" => Some(self.symbols(params)),
+            _ => None,
+        };
+        match result {
+            Some(result) => vec![object([
+                ("jsonrpc", "2.0".into()),
+                ("id", id),
+                ("result", result),
+            ])],
+            None => vec![error_response(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("unknown method '{}'", method),
+            )],
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = document_uri(params).to_string();
+        let text = match method {
+            "textDocument/didOpen" => params.path(&["textDocument", "text"]),
+            // Only whole-document changes are asked for, so the last wins
+            "textDocument/didChange" => params
+                .get("contentChanges")
+                .and_then(Value::as_array)
+                .and_then(|changes| changes.last())
+                .and_then(|change| change.get("text")),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        };
+        let Some(text) = text.and_then(Value::as_str) else {
+            return Vec::new();
+        };
+
+        let options = AsmOptions {
+            directory: path_of(&uri).and_then(|path| path.parent().map(Path::to_path_buf)),
+            include_paths: self.include_paths.clone(),
+            ..AsmOptions::default()
+        };
+        let analysis = Analysis::new(text, &options);
+        let found = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                object([
+                    ("range", range(diagnostic.span)),
+                    ("severity", Value::from(1u32)),
+                    ("source", "lc3".into()),
+                    ("message", diagnostic.message.clone().into()),
+                ])
+            })
+            .collect();
+        self.documents.insert(uri.clone(), analysis);
+        vec![diagnostics(&uri, found)]
+    }
+
+    /// Answers a request about a position in an open document, with `null`
+    /// for documents the server has not seen.
+    fn at_position(&self, params: &Value, answer: impl Fn(&Analysis, u32, u32) -> Value) -> Value {
+        let analysis = self.documents.get(document_uri(params));
+        let line = params.path(&["position", "line"]).and_then(Value::as_u32);
+        let character = params
+            .path(&["position", "character"])
+            .and_then(Value::as_u32);
+        match (analysis, line, character) {
+            (Some(analysis), Some(line), Some(character)) => answer(analysis, line, character),
+            _ => Value::Null,
+        }
+    }
+
+    fn completions(&self, params: &Value) -> Value {
+        let Some(analysis) = self.documents.get(document_uri(params)) else {
+            return Value::Array(Vec::new());
+        };
+        analysis
+            .completions()
+            .into_iter()
+            .map(|completion| {
+                // Mnemonics and directives are keywords
+                let kind = completion.kind.map_or(14, |kind| kinds(kind).1);
+                let mut item = vec![
+                    ("label".to_string(), Value::from(completion.label)),
+                    ("kind".to_string(), Value::from(kind)),
+                ];
+                if let Some(detail) = completion.detail {
+                    item.push(("detail".to_string(), detail.into()));
+                }
+                Value::Object(item)
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn symbols(&self, params: &Value) -> Value {
+        let Some(analysis) = self.documents.get(document_uri(params)) else {
+            return Value::Array(Vec::new());
+        };
+        analysis
+            .symbols()
+            .into_iter()
+            .map(|symbol| {
+                object([
+                    ("name", symbol.name.into()),
+                    ("kind", Value::from(kinds(symbol.kind).0)),
+                    ("range", range(symbol.span)),
+                    ("selectionRange", range(symbol.span)),
+                ])
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+fn document_uri(params: &Value) -> &str {
+    params
+        .path(&["textDocument", "uri"])
+        .and_then(Value::as_str)
+        .unwrap_or("")
+}
+
+fn capabilities() -> Value {
+    object([
+        (
+            "capabilities",
+            object([
+                ("textDocumentSync", Value::from(1u32)),
+                ("hoverProvider", true.into()),
+                ("definitionProvider", true.into()),
+                ("referencesProvider", true.into()),
+                ("documentSymbolProvider", true.into()),
+                (
+                    "completionProvider",
+                    object([("triggerCharacters", vec![Value::from(".")].into())]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            object([
+                ("name", "lc3-lsp".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+fn diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        ),
+    ])
+}
+
+fn error_response(id: Value, code: i32, message: &str) -> Value {
+    object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            object([
+                ("code", Value::Number(code as f64)),
+                ("message", message.into()),
+            ]),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lsp::json::{self, Value};
+    use crate::lsp::{path_of, read_message, write_message, Server};
+    use std::io;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    const URI: &str = "file:///course/main.asm";
+
+    fn message(text: &str) -> Value {
+        json::parse(text).unwrap()
+    }
+
+    fn open(server: &mut Server, text: &str) -> Value {
+        let text = Value::from(text).to_string();
+        let replies = server.handle(&message(&format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"lc3","version":1,"text":{}}}}}}}"#,
+            URI, text
+        )));
+        replies.into_iter().next().unwrap()
+    }
+
+    fn request(server: &mut Server, method: &str, params: &str) -> Value {
+        let replies = server.handle(&message(&format!(
+            r#"{{"jsonrpc":"2.0","id":7,"method":"{}","params":{}}}"#,
+            method, params
+        )));
+        assert_eq!(replies.len(), 1);
+        replies[0].clone()
+    }
+
+    fn at(line: u32, character: u32) -> String {
+        format!(
+            r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+            URI, line, character
+        )
+    }
+
+    // ========== Framing ==========
+
+    #[test]
+    fn test_messages_round_trip_through_framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, &message(r#"{"id":1}"#)).unwrap();
+        assert_eq!(out, b"Content-Length: 8\r\n\r\n{\"id\":1}");
+
+        let mut input = Cursor::new(out);
+        assert_eq!(read_message(&mut input).unwrap().unwrap(), r#"{"id":1}"#);
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_oversized_message_is_refused() {
+        let mut input = Cursor::new(b"Content-Length: 99999999999\r\n\r\n{}".to_vec());
+
+        let error = read_message(&mut input).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_uris_become_paths() {
+        assert_eq!(
+            path_of("file:///home/me/my%20lab/main.asm"),
+            Some(PathBuf::from("/home/me/my lab/main.asm"))
+        );
+        assert_eq!(
+            path_of("file:///C%3A/lab/main.asm"),
+            Some(PathBuf::from("C:/lab/main.asm"))
+        );
+        assert_eq!(path_of("untitled:1"), None);
+    }
+
+    // ========== Requests ==========
+
+    #[test]
+    fn test_session_from_initialize_to_exit() {
+        let initialize = message(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#);
+        let shutdown = message(r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#);
+        let exit = message(r#"{"jsonrpc":"2.0","method":"exit"}"#);
+        let mut input = Vec::new();
+        for message in [&initialize, &shutdown, &exit] {
+            write_message(&mut input, message).unwrap();
+        }
+
+        let mut out = Vec::new();
+        let clean = Server::new()
+            .run(&mut Cursor::new(input), &mut out)
+            .unwrap();
+
+        assert!(clean);
+        let mut replies = Cursor::new(out);
+        let first = json::parse(&read_message(&mut replies).unwrap().unwrap()).unwrap();
+        assert_eq!(
+            first.path(&["result", "capabilities", "hoverProvider"]),
+            Some(&Value::Bool(true))
+        );
+        let second = read_message(&mut replies).unwrap().unwrap();
+        assert_eq!(second, r#"{"jsonrpc":"2.0","id":2,"result":null}"#);
+    }
+
+    #[test]
+    fn test_changes_publish_diagnostics() {
+        let mut server = Server::new();
+
+        let published = open(&mut server, ".ORIG x3000\nBR NOWHERE\n.END\n");
+        assert_eq!(
+            published
+                .path(&["params", "diagnostics"])
+                .unwrap()
+                .to_string(),
+            r#"[{"range":{"start":{"line":1,"character":3},"end":{"line":1,"character":10}},"severity":1,"source":"lc3","message":"undefined label 'NOWHERE'"}]"#
+        );
+
+        let changed = server.handle(&message(&format!(
+            r#"{{"method":"textDocument/didChange","params":{{"textDocument":{{"uri":"{}"}},"contentChanges":[{{"text":".ORIG x3000\nHALT\n.END\n"}}]}}}}"#,
+            URI
+        )));
+        assert_eq!(
+            changed[0].path(&["params", "diagnostics"]),
+            Some(&Value::Array(Vec::new()))
+        );
+    }
+
+    #[test]
+    fn test_navigation_hover_and_symbols() {
+        let mut server = Server::new();
+        open(&mut server, ".ORIG x3000\nLOOP BR LOOP\n.END\n");
+
+        let definition = request(&mut server, "textDocument/definition", &at(1, 9));
+        assert_eq!(
+            definition.get("result").unwrap().to_string(),
+            format!(
+                r#"{{"uri":"{}","range":{{"start":{{"line":1,"character":0}},"end":{{"line":1,"character":4}}}}}}"#,
+                URI
+            )
+        );
+        let references = request(&mut server, "textDocument/references", &at(1, 1));
+        assert_eq!(
+            references
+                .get("result")
+                .and_then(Value::as_array)
+                .map(<[_]>::len),
+            Some(2)
+        );
+        let hover = request(&mut server, "textDocument/hover", &at(1, 6));
+        let text = hover
+            .path(&["result", "contents", "value"])
+            .and_then(Value::as_str);
+        assert!(text.unwrap().contains("0000 n z p PCoffset9"));
+        let symbols = request(
+            &mut server,
+            "textDocument/documentSymbol",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        );
+        assert_eq!(
+            symbols.path(&["result"]).and_then(Value::as_array).unwrap()[0].get("name"),
+            Some(&Value::from("LOOP"))
+        );
+    }
+
+    #[test]
+    fn test_unknown_methods_are_errors() {
+        let mut server = Server::new();
+
+        let reply = request(&mut server, "workspace/symbol", "{}");
+
+        assert_eq!(
+            reply.path(&["error", "code"]),
+            Some(&Value::Number(-32601.0))
+        );
+        assert!(server
+            .handle(&message(
+                r#"{"method":"$/cancelRequest","params":{"id":3}}"#
+            ))
+            .is_empty());
+    }
+}
@@ -0,0 +1,592 @@
+//! Declarative machine descriptions, read from TOML files such as
+//!
+//! ```toml
+//! [machine]
+//! name = "lc3os"
+//! traps = "os"              # "native" (the default) or "os"
+//! os_image = "lc3os.obj"    # loaded before the program when traps = "os"
+//! privilege = "supervisor"  # "user" (the default) or "supervisor"
+//! entry = "x3000"
+//!
+//! [memory]
+//! fill = 0
+//! images = ["library.obj"]
+//! reserved = "deny"         # images in reserved memory: "allow", "warn", "deny"
+//! allow_overlap = false
+//!
+//! [registers]
+//! R6 = "xFE00"
+//!
+//! [devices]
+//! keyboard = true
+//! display = true
+//! machine_control = true
+//!
+//! [limits]
+//! max_instructions = 1_000_000
+//! timeout = 2.5
+//!
+//! [extensions]
+//! cycles = true
+//! pipeline = true
+//! predict = "two-bit"
+//! icache = "size=64,ways=2"
+//! ```
+//!
+//! Every key is optional. An empty file describes the machine `RustVm`
+//! runs when no configuration is given.
+
+pub mod toml;
+
+use crate::asm::lexer::parse_number;
+use crate::cache::CacheConfig;
+use crate::cli::{prepare, Options};
+use crate::limits::Limits;
+use crate::loader::{LoadPolicy, ReservedPolicy};
+use crate::machine::toml::{Entry, Value};
+use crate::pipeline::PipelineConfig;
+use crate::Vm;
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+/// Display status register. Bit 15 is set when the display is ready.
+pub const DSR: u16 = 0xFE04;
+/// Display data register. Writing a character here prints it.
+pub const DDR: u16 = 0xFE06;
+/// Machine control register. Clearing bit 15 stops the clock.
+pub const MCR: u16 = 0xFFFE;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrapMode {
+    /// Trap routines run as Rust code inside the VM.
+    #[default]
+    Native,
+    /// TRAP jumps through the trap vector table, as on real hardware.
+    Os,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    #[default]
+    User,
+    Supervisor,
+}
+
+/// Which memory-mapped devices respond to their registers. Unmapped device
+/// registers read and write as plain memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Devices {
+    pub keyboard: bool,
+    pub display: bool,
+    pub machine_control: bool,
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        Self {
+            keyboard: true,
+            display: false,
+            machine_control: false,
+        }
+    }
+}
+
+/// The parts of a machine description the VM consults while running.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Machine {
+    pub traps: TrapMode,
+    pub privilege: Privilege,
+    pub devices: Devices,
+}
+
+impl Machine {
+    /// Whether the machine uses something only `Vm::fetch_decode_execute`
+    /// models: memory-mapped output, the machine control register or RTI.
+    pub fn needs_interpreter(&self) -> bool {
+        self.devices.display
+            || self.devices.machine_control
+            || self.privilege == Privilege::Supervisor
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A whole machine: how it behaves, what is in memory and where it starts.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MachineConfig {
+    pub name: Option<String>,
+    pub machine: Machine,
+    pub os_image: Option<String>,
+    pub entry: Option<u16>,
+    pub quiet: bool,
+    pub fill: Option<u16>,
+    /// Loaded after the OS image and before any images named on the
+    /// command line.
+    pub images: Vec<String>,
+    pub load_policy: LoadPolicy,
+    pub registers: Vec<(usize, u16)>,
+    pub limits: Limits,
+    pub cycles: bool,
+    pub memory_latency: u32,
+    pub pipeline: Option<PipelineConfig>,
+    pub instruction_cache: Option<CacheConfig>,
+    pub data_cache: Option<CacheConfig>,
+}
+
+const TABLES: [(&str, &[&str]); 6] = [
+    (
+        "machine",
+        &["name", "traps", "os_image", "privilege", "entry", "quiet"],
+    ),
+    ("memory", &["fill", "images", "reserved", "allow_overlap"]),
+    (
+        "registers",
+        &["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7"],
+    ),
+    ("devices", &["keyboard", "display", "machine_control"]),
+    (
+        "limits",
+        &["max_instructions", "timeout", "max_output", "detect_loops"],
+    ),
+    (
+        "extensions",
+        &[
+            "cycles",
+            "memory_latency",
+            "pipeline",
+            "forwarding",
+            "predict",
+            "icache",
+            "dcache",
+        ],
+    ),
+];
+
+fn mismatch(expected: &str, value: &Value) -> String {
+    format!("expects {}, got {}", expected, value.kind())
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(value) => Ok(*value),
+        _ => Err(mismatch("true or false", value)),
+    }
+}
+
+fn text(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        _ => Err(mismatch("a string", value)),
+    }
+}
+
+fn count(value: &Value) -> Result<u64, String> {
+    match value {
+        Value::Integer(count) if *count >= 0 => Ok(*count as u64),
+        Value::Integer(count) => Err(format!("expects a count, got {}", count)),
+        _ => Err(mismatch("an integer", value)),
+    }
+}
+
+/// A 16-bit word, written as an integer or as an LC-3 literal like "x3000".
+fn word(value: &Value) -> Result<u16, String> {
+    let number = match value {
+        Value::Integer(number) => *number,
+        Value::String(literal) => parse_number(literal)
+            .ok_or_else(|| format!("expects a 16-bit value, got \"{}\"", literal))?
+            as i64,
+        _ => return Err(mismatch("a 16-bit value", value)),
+    };
+    match number {
+        -0x8000..=0xFFFF => Ok(number as u16),
+        _ => Err(format!("expects a 16-bit value, got {}", number)),
+    }
+}
+
+fn seconds(value: &Value) -> Result<Duration, String> {
+    let seconds = match value {
+        Value::Integer(seconds) => *seconds as f64,
+        Value::Float(seconds) => *seconds,
+        _ => return Err(mismatch("a number of seconds", value)),
+    };
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| format!("expects a number of seconds, got {}", seconds))
+}
+
+fn choice<T: Copy>(value: &Value, choices: &[(&str, T)]) -> Result<T, String> {
+    let names: Vec<String> = choices
+        .iter()
+        .map(|(name, _)| format!("\"{}\"", name))
+        .collect();
+    let expected = format!("one of {}", names.join(", "));
+    let name = text(value).map_err(|_| mismatch(&expected, value))?;
+    choices
+        .iter()
+        .find(|(choice, _)| *choice == name)
+        .map(|&(_, choice)| choice)
+        .ok_or_else(|| format!("expects {}, got \"{}\"", expected, name))
+}
+
+fn files(value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(items) => items.iter().map(text).collect(),
+        _ => Err(mismatch("an array of file names", value)),
+    }
+}
+
+impl MachineConfig {
+    pub fn parse(text: &str) -> Result<MachineConfig, ConfigError> {
+        let mut config = MachineConfig::default();
+        let mut traps_line = 0;
+
+        for table in toml::parse(text)? {
+            if table.name.is_empty() {
+                if let Some(entry) = table.entries.first() {
+                    return Err(ConfigError {
+                        line: entry.line,
+                        message: format!("key '{}' must be inside a table", entry.key),
+                    });
+                }
+                continue;
+            }
+            let Some((_, keys)) = TABLES.iter().find(|(name, _)| *name == table.name) else {
+                let names: Vec<&str> = TABLES.iter().map(|(name, _)| *name).collect();
+                return Err(ConfigError {
+                    line: table.line,
+                    message: format!(
+                        "unknown table [{}], expected one of {}",
+                        table.name,
+                        names.join(", ")
+                    ),
+                });
+            };
+
+            for entry in &table.entries {
+                if !keys.contains(&entry.key.as_str()) {
+                    return Err(ConfigError {
+                        line: entry.line,
+                        message: format!(
+                            "unknown key '{}' in [{}], expected one of {}",
+                            entry.key,
+                            table.name,
+                            keys.join(", ")
+                        ),
+                    });
+                }
+                if entry.key == "traps" {
+                    traps_line = entry.line;
+                }
+                config
+                    .set(&table.name, entry)
+                    .map_err(|message| ConfigError {
+                        line: entry.line,
+                        message: format!("{}.{} {}", table.name, entry.key, message),
+                    })?;
+            }
+        }
+
+        if config.machine.traps == TrapMode::Os && config.os_image.is_none() {
+            return Err(ConfigError {
+                line: traps_line,
+                message: "traps = \"os\" needs an os_image holding the trap routines".to_string(),
+            });
+        }
+        Ok(config)
+    }
+
+    /// Reads a configuration file. Errors name the file and line.
+    pub fn load(path: &str) -> Result<MachineConfig, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        MachineConfig::parse(&text).map_err(|e| format!("{}:{}: {}", path, e.line, e.message))
+    }
+
+    fn set(&mut self, table: &str, entry: &Entry) -> Result<(), String> {
+        let value = &entry.value;
+        match (table, entry.key.as_str()) {
+            ("machine", "name") => self.name = Some(text(value)?),
+            ("machine", "traps") => {
+                self.machine.traps =
+                    choice(value, &[("native", TrapMode::Native), ("os", TrapMode::Os)])?
+            }
+            ("machine", "os_image") => self.os_image = Some(text(value)?),
+            ("machine", "privilege") => {
+                self.machine.privilege = choice(
+                    value,
+                    &[
+                        ("user", Privilege::User),
+                        ("supervisor", Privilege::Supervisor),
+                    ],
+                )?
+            }
+            ("machine", "entry") => self.entry = Some(word(value)?),
+            ("machine", "quiet") => self.quiet = boolean(value)?,
+            ("memory", "fill") => self.fill = Some(word(value)?),
+            ("memory", "images") => self.images = files(value)?,
+            ("memory", "reserved") => {
+                self.load_policy.reserved = choice(
+                    value,
+                    &[
+                        ("allow", ReservedPolicy::Allow),
+                        ("warn", ReservedPolicy::Warn),
+                        ("deny", ReservedPolicy::Deny),
+                    ],
+                )?
+            }
+            ("memory", "allow_overlap") => self.load_policy.allow_overlap = boolean(value)?,
+            ("registers", register) => {
+                let register = register[1..].parse().unwrap();
+                self.registers.push((register, word(value)?));
+            }
+            ("devices", "keyboard") => self.machine.devices.keyboard = boolean(value)?,
+            ("devices", "display") => self.machine.devices.display = boolean(value)?,
+            ("devices", "machine_control") => {
+                self.machine.devices.machine_control = boolean(value)?
+            }
+            ("limits", "max_instructions") => self.limits.max_instructions = Some(count(value)?),
+            ("limits", "timeout") => self.limits.max_time = Some(seconds(value)?),
+            ("limits", "max_output") => self.limits.max_output = Some(count(value)?),
+            ("limits", "detect_loops") => self.limits.detect_stuck = boolean(value)?,
+            ("extensions", "cycles") => self.cycles = boolean(value)?,
+            ("extensions", "memory_latency") => {
+                self.memory_latency =
+                    u32::try_from(count(value)?).map_err(|_| "is too large".to_string())?;
+                self.cycles = true;
+            }
+            ("extensions", "pipeline") => {
+                if boolean(value)? {
+                    self.pipeline.get_or_insert_with(PipelineConfig::default);
+                } else {
+                    self.pipeline = None;
+                }
+            }
+            ("extensions", "forwarding") => {
+                self.pipeline
+                    .get_or_insert_with(PipelineConfig::default)
+                    .forwarding = boolean(value)?
+            }
+            ("extensions", "predict") => {
+                self.pipeline
+                    .get_or_insert_with(PipelineConfig::default)
+                    .prediction = text(value)?.parse()?
+            }
+            ("extensions", "icache") => self.instruction_cache = Some(text(value)?.parse()?),
+            ("extensions", "dcache") => self.data_cache = Some(text(value)?.parse()?),
+            _ => unreachable!("{}.{} is listed in TABLES", table, entry.key),
+        }
+        Ok(())
+    }
+
+    /// The command-line options that describe the same machine, for
+    /// further options to be layered on.
+    pub fn options(&self) -> Options {
+        Options {
+            files: self.os_image.iter().chain(&self.images).cloned().collect(),
+            entry: self.entry,
+            registers: self.registers.clone(),
+            fill: self.fill,
+            load_policy: self.load_policy,
+            limits: self.limits.clone(),
+            quiet: self.quiet,
+            cycles: self.cycles,
+            memory_latency: self.memory_latency,
+            pipeline: self.pipeline,
+            instruction_cache: self.instruction_cache,
+            data_cache: self.data_cache,
+            machine: self.machine,
+            ..Options::default()
+        }
+    }
+
+    /// Builds a VM with the images loaded and the registers set.
+    pub fn vm(&self) -> Result<Vm, String> {
+        let mut vm = Vm::new();
+        prepare(&mut vm, &self.options())?;
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::Options;
+    use crate::loader::ReservedPolicy;
+    use crate::machine::{MachineConfig, Privilege, TrapMode, DDR, DSR, MCR};
+    use crate::pipeline::prediction::BranchPrediction;
+    use crate::registers::register::Register;
+    use crate::Vm;
+    use std::time::Duration;
+
+    fn error(text: &str) -> String {
+        MachineConfig::parse(text).unwrap_err().to_string()
+    }
+
+    // ========== Parsing ==========
+
+    #[test]
+    fn test_empty_file_is_the_default_machine() {
+        let config = MachineConfig::parse("# nothing here\n").unwrap();
+        assert_eq!(config, MachineConfig::default());
+        let options = config.options();
+        let defaults = Options::default();
+        assert_eq!(options.files, defaults.files);
+        assert_eq!(options.machine, defaults.machine);
+        assert!(!config.machine.needs_interpreter());
+    }
+
+    #[test]
+    fn test_parses_every_table() {
+        let config = MachineConfig::parse(
+            "[machine]\n\
+             name = \"lab\"\n\
+             traps = \"os\"\n\
+             os_image = \"os.obj\"\n\
+             privilege = \"supervisor\"\n\
+             entry = \"x0200\"\n\
+             [memory]\n\
+             fill = 0xFFFF\n\
+             images = [\"lib.obj\"]\n\
+             reserved = \"deny\"\n\
+             [registers]\n\
+             R6 = \"xFE00\"\n\
+             R1 = -1\n\
+             [devices]\n\
+             display = true\n\
+             [limits]\n\
+             max_instructions = 1_000\n\
+             timeout = 0.5\n\
+             [extensions]\n\
+             predict = \"two-bit\"\n\
+             icache = \"size=64,ways=2\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.name.as_deref(), Some("lab"));
+        assert_eq!(config.machine.traps, TrapMode::Os);
+        assert_eq!(config.machine.privilege, Privilege::Supervisor);
+        assert!(config.machine.devices.keyboard && config.machine.devices.display);
+        assert_eq!(config.entry, Some(0x0200));
+        assert_eq!(config.fill, Some(0xFFFF));
+        assert_eq!(config.load_policy.reserved, ReservedPolicy::Deny);
+        assert_eq!(config.registers, [(6, 0xFE00), (1, 0xFFFF)]);
+        assert_eq!(config.limits.max_instructions, Some(1000));
+        assert_eq!(config.limits.max_time, Some(Duration::from_millis(500)));
+        assert_eq!(
+            config.pipeline.map(|pipeline| pipeline.prediction),
+            Some(BranchPrediction::TwoBit)
+        );
+        assert!(config.instruction_cache.is_some() && config.data_cache.is_none());
+        assert_eq!(config.options().files, ["os.obj", "lib.obj"]);
+    }
+
+    // ========== Validation ==========
+
+    #[test]
+    fn test_errors_say_what_was_expected() {
+        assert_eq!(
+            error("[devices]\nkeybord = true\n"),
+            "line 2: unknown key 'keybord' in [devices], expected one of \
+             keyboard, display, machine_control"
+        );
+        assert_eq!(
+            error("[device]\n"),
+            "line 1: unknown table [device], expected one of \
+             machine, memory, registers, devices, limits, extensions"
+        );
+        assert_eq!(
+            error("[devices]\ndisplay = 1\n"),
+            "line 2: devices.display expects true or false, got an integer"
+        );
+        assert_eq!(
+            error("\n[machine]\ntraps = \"bios\"\n"),
+            "line 3: machine.traps expects one of \"native\", \"os\", got \"bios\""
+        );
+        assert_eq!(
+            error("[machine]\nentry = 0x10000\n"),
+            "line 2: machine.entry expects a 16-bit value, got 65536"
+        );
+        assert_eq!(
+            error("[limits]\nmax_output = -5\n"),
+            "line 2: limits.max_output expects a count, got -5"
+        );
+        assert_eq!(
+            error("entry = 1\n"),
+            "line 1: key 'entry' must be inside a table"
+        );
+    }
+
+    #[test]
+    fn test_os_traps_need_an_os_image() {
+        assert_eq!(
+            error("[machine]\nname = \"x\"\ntraps = \"os\"\n"),
+            "line 3: traps = \"os\" needs an os_image holding the trap routines"
+        );
+    }
+
+    #[test]
+    fn test_load_names_the_file() {
+        let path = std::env::temp_dir().join("rustvm_bad_machine.toml");
+        std::fs::write(&path, "[limits]\ntimeout = \"soon\"\n").unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            MachineConfig::load(path).unwrap_err(),
+            format!(
+                "{}:2: limits.timeout expects a number of seconds, got a string",
+                path
+            )
+        );
+    }
+
+    // ========== Devices ==========
+
+    #[test]
+    fn test_display_prints_characters_written_to_ddr() {
+        let mut vm = MachineConfig::parse("[devices]\ndisplay = true\n")
+            .unwrap()
+            .vm()
+            .unwrap();
+        vm.output = Some(String::new());
+        vm.write_to_register(Register::R0, DDR);
+        vm.write_to_register(Register::R1, 'A' as u16);
+        vm.mem_write(0x3000, 0b0111_001_000_000000); // STR R1, R0, #0
+
+        assert!(vm.fetch_decode_execute());
+        assert_eq!(vm.output.as_deref(), Some("A"));
+        assert_eq!(vm.mem_read(DSR), 0x8000);
+    }
+
+    #[test]
+    fn test_unmapped_display_is_plain_memory() {
+        let mut vm = Vm::new();
+        vm.output = Some(String::new());
+        vm.mem_write(DDR, 'A' as u16);
+        assert_eq!(vm.output.as_deref(), Some(""));
+        assert_eq!(vm.mem_read(DSR), 0);
+    }
+
+    #[test]
+    fn test_clearing_mcr_stops_the_clock() {
+        let mut vm = MachineConfig::parse("[devices]\nmachine_control = true\n")
+            .unwrap()
+            .vm()
+            .unwrap();
+        assert_eq!(vm.memory[MCR as usize], 0x8000);
+        vm.mem_write(0x3000, 0b0001_001_001_1_00001); // ADD R1, R1, #1
+        vm.mem_write(0x3001, 0b1011_000_000000001); // STI R0, MCR_ADDRESS
+        vm.mem_write(0x3003, MCR);
+
+        assert!(vm.fetch_decode_execute());
+        assert!(!vm.fetch_decode_execute());
+        assert_eq!(vm.memory[MCR as usize], 0);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::machine::ConfigError;
+
+/// The part of TOML machine files use: tables, `key = value` pairs and
+/// comments. Values are strings, integers, floats, booleans and arrays of
+/// those on one line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+/// A `[name]` table. Keys before the first header land in a table named "".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+fn error(line: usize, message: String) -> ConfigError {
+    ConfigError { line, message }
+}
+
+/// Cuts a `#` comment off, leaving `#` inside strings alone.
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn string(text: &str, line: usize) -> Result<(String, &str), ConfigError> {
+    let mut value = String::new();
+    let mut characters = text.char_indices().skip(1);
+    while let Some((index, c)) = characters.next() {
+        match c {
+            '"' => return Ok((value, &text[index + 1..])),
+            '\\' => {
+                let escaped = match characters.next() {
+                    Some((_, 'n')) => '\n',
+                    Some((_, 't')) => '\t',
+                    Some((_, '"')) => '"',
+                    Some((_, '\\')) => '\\',
+                    Some((_, other)) => {
+                        return Err(error(line, format!("unknown escape '\\{}'", other)));
+                    }
+                    None => break,
+                };
+                value.push(escaped);
+            }
+            _ => value.push(c),
+        }
+    }
+    Err(error(line, "unterminated string".to_string()))
+}
+
+fn scalar(text: &str, line: usize) -> Result<Value, ConfigError> {
+    match text {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+
+    let digits = text.replace('_', "");
+    let (negative, magnitude) = match digits.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+    };
+    let integer = match magnitude.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => match magnitude.strip_prefix("0b") {
+            Some(binary) => i64::from_str_radix(binary, 2).ok(),
+            None => magnitude.parse::<i64>().ok(),
+        },
+    };
+    if let Some(integer) = integer {
+        return Ok(Value::Integer(if negative { -integer } else { integer }));
+    }
+    // Rust would also accept words like `inf` and `NaN`
+    let numeric = text.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+');
+    if let Some(float) = digits.parse::<f64>().ok().filter(|_| numeric) {
+        return Ok(Value::Float(float));
+    }
+    Err(error(line, format!("'{}' is not a value", text)))
+}
+
+/// Parses the value at the start of `text`, returning it and the rest.
+fn value(text: &str, line: usize) -> Result<(Value, &str), ConfigError> {
+    let text = text.trim_start();
+    if text.starts_with('"') {
+        let (value, rest) = string(text, line)?;
+        return Ok((Value::String(value), rest));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = value(rest, line)?;
+            items.push(item);
+            rest = after.trim_start();
+            match rest.chars().next() {
+                Some(',') => rest = &rest[1..],
+                Some(']') => {}
+                _ => return Err(error(line, "unterminated array".to_string())),
+            }
+        }
+    }
+
+    let end = text.find([',', ']']).unwrap_or(text.len());
+    let word = text[..end].trim_end();
+    if word.is_empty() {
+        return Err(error(line, "missing value".to_string()));
+    }
+    Ok((scalar(word, line)?, &text[end..]))
+}
+
+/// Splits `text` into tables. Duplicate tables and keys are errors.
+pub fn parse(text: &str) -> Result<Vec<Table>, ConfigError> {
+    let mut tables = vec![Table {
+        name: String::new(),
+        line: 0,
+        entries: Vec::new(),
+    }];
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let text = strip_comment(raw).trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = text.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .map(str::trim)
+                .filter(|name| is_bare_key(name))
+                .ok_or_else(|| error(line, format!("invalid table header '{}'", text)))?;
+            if let Some(table) = tables.iter().find(|table| table.name == name) {
+                return Err(error(
+                    line,
+                    format!("table [{}] already defined on line {}", name, table.line),
+                ));
+            }
+            tables.push(Table {
+                name: name.to_string(),
+                line,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let (key, rest) = text
+            .split_once('=')
+            .ok_or_else(|| error(line, format!("expected key = value, got '{}'", text)))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(error(line, format!("invalid key '{}'", key)));
+        }
+        let (value, rest) = value(rest, line)?;
+        if !rest.trim().is_empty() {
+            return Err(error(
+                line,
+                format!("unexpected '{}' after the value", rest.trim()),
+            ));
+        }
+
+        let table = tables.last_mut().unwrap();
+        if let Some(entry) = table.entries.iter().find(|entry| entry.key == key) {
+            return Err(error(
+                line,
+                format!("key '{}' already set on line {}", key, entry.line),
+            ));
+        }
+        table.entries.push(Entry {
+            key: key.to_string(),
+            value,
+            line,
+        });
+    }
+
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::toml::{parse, Value};
+
+    // ========== Values ==========
+
+    #[test]
+    fn test_parses_each_kind_of_value() {
+        let tables = parse(
+            "name = \"lab # 3\" # a comment\n\
+             count = 0x3000\n\
+             step = -1_000\n\
+             timeout = 2.5\n\
+             enabled = true\n\
+             images = [\"a.obj\", \"b.obj\"]\n",
+        )
+        .unwrap();
+        let values: Vec<&Value> = tables[0].entries.iter().map(|entry| &entry.value).collect();
+        assert_eq!(
+            values,
+            [
+                &Value::String("lab # 3".to_string()),
+                &Value::Integer(0x3000),
+                &Value::Integer(-1000),
+                &Value::Float(2.5),
+                &Value::Boolean(true),
+                &Value::Array(vec![
+                    Value::String("a.obj".to_string()),
+                    Value::String("b.obj".to_string())
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keys_go_into_the_table_above_them() {
+        let tables = parse("top = 1\n\n[devices]\ndisplay = false\n").unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].entries[0].key, "top");
+        assert_eq!(tables[1].name, "devices");
+        assert_eq!(tables[1].line, 3);
+        assert_eq!(tables[1].entries[0].line, 4);
+    }
+
+    // ========== Errors ==========
+
+    #[test]
+    fn test_reports_the_line_of_malformed_input() {
+        let cases = [
+            ("a = 1\nb = \"open\n", 2, "unterminated string"),
+            ("[machine\n", 1, "invalid table header '[machine'"),
+            ("just words\n", 1, "expected key = value, got 'just words'"),
+            ("a = yes\n", 1, "'yes' is not a value"),
+            ("a = 1 2\n", 1, "'1 2' is not a value"),
+            ("a = [1, 2\n", 1, "unterminated array"),
+            ("a = 1\na = 2\n", 2, "key 'a' already set on line 1"),
+            ("[x]\n[x]\n", 2, "table [x] already defined on line 1"),
+        ];
+        for (text, line, message) in cases {
+            let error = parse(text).unwrap_err();
+            assert_eq!(
+                (error.line, error.message.as_str()),
+                (line, message),
+                "{:?}",
+                text
+            );
+        }
+    }
+}
@@ -1,271 +1,550 @@
-mod instructions;
-mod registers;
-
-use crate::instructions::add::add;
-use crate::instructions::and::and;
-use crate::instructions::branch::br;
-use crate::instructions::jump::jmp;
-use crate::instructions::jump_register::jsr;
-use crate::instructions::ldi::ldi;
-use crate::instructions::load::ld;
-use crate::instructions::load_effective::lea;
-use crate::instructions::load_register::ldr;
-use crate::instructions::not::not;
-use crate::instructions::opcodes::Opcode;
-use crate::instructions::store::st;
-use crate::instructions::store_indirect::sti;
-use crate::instructions::store_register::str;
-use crate::instructions::trap::trap;
-use crate::registers::register::{MemoryMappedRegister, Register};
-use crate::registers::ConditionFlag;
-use byteorder::{BigEndian, ReadBytesExt};
-use std::fs::File;
-use std::io::{BufReader, Read};
+use rustvm::asm::{assemble_with, AsmOptions};
+use rustvm::cc::compile;
+use rustvm::cli::{parse, prepare, source_map, usage, Action, Command, Options};
+use rustvm::coverage::Coverage;
+use rustvm::debugger::{hex_dump, instruction_line, trace_line, Debugger};
+use rustvm::decompile::decompile;
+use rustvm::graph::dot::{to_call_graph_dot, to_cfg_dot};
+use rustvm::graph::json::to_json;
+use rustvm::graph::Graph;
+use rustvm::image::{write, Format, Image};
+use rustvm::limits::{run_with, StopReason};
+use rustvm::link::object::Module;
+use rustvm::link::{link, DEFAULT_BASE};
+use rustvm::lint::{data_words, lint};
+use rustvm::loader::{reserved_regions, MemoryMap};
+use rustvm::microcode::MicroEngine;
+use rustvm::registers::register::Register;
+use rustvm::symbols::symbol_table::SymbolTable;
+use rustvm::{Vm, PC_START};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::process::exit;
-use std::sync::OnceLock;
-use std::{env, io};
-use windows::Win32::Foundation::{HANDLE, INVALID_HANDLE_VALUE};
-use windows::Win32::System::Console::CONSOLE_MODE;
-use windows::Win32::System::Console::{
-    FlushConsoleInputBuffer, GetConsoleMode, GetStdHandle, PeekConsoleInputW, ReadConsoleInputW,
-    SetConsoleMode, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, INPUT_RECORD, KEY_EVENT,
-    STD_INPUT_HANDLE,
-};
-
-static H_STDIN_RAW: OnceLock<isize> = OnceLock::new();
-static mut FDW_OLD_MODE: CONSOLE_MODE = CONSOLE_MODE(0);
-
-const MEMORY_MAX: usize = 1 << 16;
-const PC_START: usize = 0x3000;
-
-struct Vm {
-    memory: [u16; MEMORY_MAX],
-    registers: [u16; (Register::Count as u16) as usize],
-}
 
-impl Vm {
-    fn new() -> Vm {
-        Self {
-            registers: [0; (Register::Count as u16) as usize],
-            memory: [0; MEMORY_MAX],
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args.first().map_or("RustVm", String::as_str);
+
+    let options = match parse(args.get(1..).unwrap_or_default()) {
+        Ok(Action::Execute(options)) => *options,
+        Ok(Action::Help) => {
+            print!("{}", usage(program));
+            return;
+        }
+        Ok(Action::Version) => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return;
         }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Run '{} --help' for usage.", program);
+            exit(2)
+        }
+    };
+
+    match options.command {
+        Command::Asm => assemble_files(&options),
+        Command::Cc => compile_files(&options),
+        Command::Disasm => disassemble_images(&options),
+        Command::Dump => dump_images(&options),
+        Command::Convert => convert_image(&options),
+        Command::Link => link_modules(&options),
+        Command::Lint => lint_program(&options),
+        Command::Graph => graph_program(&options),
+        Command::Decompile => decompile_program(&options),
+        Command::Run | Command::Trace | Command::Debug => run(&options),
+    }
+}
+
+fn load(options: &Options) -> Vm {
+    let mut vm = Vm::new();
+    if let Err(e) = prepare(&mut vm, options) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+    if options.memory_map {
+        eprint!(
+            "{}",
+            MemoryMap::of_loaded(&vm.images).layout(&reserved_regions(&options.machine))
+        );
     }
+    vm
+}
 
-    fn write_to_register(&mut self, register: Register, value: u16) {
-        self.registers[register as usize] = value;
+fn symbols(options: &Options) -> SymbolTable {
+    match &options.coverage.symbols {
+        Some(file_name) => SymbolTable::load(file_name).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1)
+        }),
+        None => SymbolTable::new(),
     }
+}
 
-    fn mem_write(&mut self, offset: u16, value: u16) {
-        self.memory[offset as usize] = value;
+fn assemble_files(options: &Options) {
+    if options.object.is_some() && options.files.len() > 1 {
+        eprintln!("-o can only be used with a single source file");
+        exit(2);
     }
 
-    fn read_file(&mut self, file_name: &str) -> bool {
-        let mut file_reader = BufReader::new(File::open(file_name).unwrap());
-        let mut address = match file_reader.read_u16::<BigEndian>() {
-            Ok(addr) => addr,
+    let mut failed = false;
+    for file in &options.files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
             Err(e) => {
-                eprintln!("Error reading start address: {}", e);
-                return false;
+                eprintln!("could not read {}: {}", file, e);
+                exit(1)
             }
         };
-
-        loop {
-            match file_reader.read_u16::<BigEndian>() {
-                Ok(instruction) => {
-                    if address as usize >= self.memory.len() {
-                        eprintln!(
-                            "Error: memory overflow at address 0x{:04X} (memory size {})",
-                            address,
-                            self.memory.len()
-                        );
-                        return false;
-                    }
-
-                    self.memory[address as usize] = instruction;
-                    address = address.wrapping_add(1); // Prevent panic, wraps safely
-                }
-                Err(e) => {
-                    return if e.kind() == io::ErrorKind::UnexpectedEof {
-                        true // finished reading normally
-                    } else {
-                        eprintln!("Error reading from file: {}", e);
-                        false
-                    };
+        let asm_options = AsmOptions {
+            relocatable: options.relocatable,
+            directory: Path::new(file).parent().map(Path::to_path_buf),
+            include_paths: options.include_paths.clone(),
+        };
+        let assembly = match assemble_with(&source, &asm_options) {
+            Ok(assembly) => assembly,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}:{}: {}", file, error.line, error.message);
                 }
+                failed = true;
+                continue;
             }
+        };
+
+        if options.relocatable {
+            let path = match &options.object {
+                Some(object) => Path::new(object).to_path_buf(),
+                None => Path::new(file).with_extension("rel"),
+            };
+            let name = Path::new(file)
+                .file_stem()
+                .map_or(file.clone(), |stem| stem.to_string_lossy().into_owned());
+            let module = Module::from_assembly(&name, &assembly);
+            write_file(&path, module.to_string().into_bytes());
+            eprintln!(
+                "{}: {} words, {} relocations -> {}",
+                file,
+                assembly.words.len(),
+                assembly.relocations.len(),
+                path.display()
+            );
+            continue;
         }
-    }
 
-    fn mem_read(&mut self, address: u16) -> u16 {
-        if address == MemoryMappedRegister::MR_KBSR as u16 {
-            if self.check_key() {
-                self.memory[MemoryMappedRegister::MR_KBSR as usize] = 1 << 15;
-                let c = self.get_char();
-                self.memory[MemoryMappedRegister::MR_KBDR as usize] = c;
-            } else {
-                self.memory[MemoryMappedRegister::MR_KBSR as usize] = 0
-            }
+        let object = match &options.object {
+            Some(object) => Path::new(object).to_path_buf(),
+            None => Path::new(file).with_extension("obj"),
+        };
+        let outputs = [
+            (
+                object.clone(),
+                encode(&assembly.image(), &object.to_string_lossy(), options),
+            ),
+            (
+                object.with_extension("sym"),
+                assembly.symbol_file().into_bytes(),
+            ),
+            (
+                object.with_extension("map"),
+                assembly.source_map(file).to_string().into_bytes(),
+            ),
+            (
+                object.with_extension("lst"),
+                assembly.listing.to_string().into_bytes(),
+            ),
+        ];
+        for (path, contents) in outputs {
+            write_file(&path, contents);
         }
+        eprintln!(
+            "{}: {} words at x{:04X} -> {}",
+            file,
+            assembly.words.len(),
+            assembly.origin,
+            object.display()
+        );
+    }
 
-        self.memory[address as usize]
+    if failed {
+        exit(1);
     }
+}
 
-    fn check_key(&self) -> bool {
-        unsafe {
-            if let Some(h_stdin) = self.get_handle() {
-                let mut buffer: [INPUT_RECORD; 128] = std::mem::zeroed();
-                let mut events_read: u32 = 0;
-
-                if PeekConsoleInputW(h_stdin, &mut buffer, &mut events_read).is_ok() {
-                    for i in 0..events_read as usize {
-                        if buffer[i].EventType == KEY_EVENT as u16 {
-                            let key_event = buffer[i].Event.KeyEvent;
-                            if key_event.bKeyDown.as_bool() && key_event.uChar.AsciiChar != 0 {
-                                return true;
-                            }
-                        }
-                    }
-                }
-            }
-            false
-        }
+/// Compiles each source file to an image and symbol file next to it, or
+/// to the assembly when `-o` names an `.asm` file.
+fn compile_files(options: &Options) {
+    if options.object.is_some() && options.files.len() > 1 {
+        eprintln!("-o can only be used with a single source file");
+        exit(2);
     }
 
-    fn get_char(&mut self) -> u16 {
-        unsafe {
-            if let Some(h_stdin) = self.get_handle() {
-                let mut buffer: [INPUT_RECORD; 128] = std::mem::zeroed();
-                let mut events_read: u32 = 0;
-
-                loop {
-                    if ReadConsoleInputW(h_stdin, &mut buffer, &mut events_read).is_ok() {
-                        for i in 0..events_read as usize {
-                            if buffer[i].EventType == KEY_EVENT as u16 {
-                                let key_event = buffer[i].Event.KeyEvent;
-                                // Only process key DOWN events with an ASCII character
-                                if key_event.bKeyDown.as_bool() && key_event.uChar.AsciiChar != 0 {
-                                    return key_event.uChar.AsciiChar as u16;
-                                }
-                            }
-                        }
-                    }
+    let mut failed = false;
+    for file in &options.files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("could not read {}: {}", file, e);
+                exit(1)
+            }
+        };
+        let text = match compile(&source) {
+            Ok(text) => text,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}:{}: {}", file, error.line, error.message);
                 }
+                failed = true;
+                continue;
             }
-            panic!("Failed to read character");
+        };
+
+        let object = match &options.object {
+            Some(object) => Path::new(object).to_path_buf(),
+            None => Path::new(file).with_extension("obj"),
+        };
+        if object
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("asm"))
+        {
+            let lines = text.lines().count();
+            write_file(&object, text.into_bytes());
+            eprintln!("{}: {} lines -> {}", file, lines, object.display());
+            continue;
         }
-    }
-    fn get_handle(&self) -> Option<HANDLE> {
-        H_STDIN_RAW.get().map(|&raw| HANDLE(raw as *mut _))
+        let assembly = assemble_with(&text, &AsmOptions::default())
+            .expect("the compiler checks that its output assembles");
+        write_file(
+            &object,
+            encode(&assembly.image(), &object.to_string_lossy(), options),
+        );
+        write_file(
+            &object.with_extension("sym"),
+            assembly.symbol_file().into_bytes(),
+        );
+        eprintln!(
+            "{}: {} words at x{:04X} -> {}",
+            file,
+            assembly.words.len(),
+            assembly.origin,
+            object.display()
+        );
     }
 
-    fn run(&mut self) {
-        while self.fetch_decode_execute() {}
+    if failed {
+        exit(1);
     }
+}
 
-    fn fetch_decode_execute(&mut self) -> bool {
-        let instruction = self.fetch();
-        let opcode = Self::decode(instruction);
-        self.execute(instruction, opcode)
+fn write_file(path: &Path, contents: Vec<u8>) {
+    if let Err(e) = fs::write(path, contents) {
+        eprintln!("could not write {}: {}", path.display(), e);
+        exit(1);
     }
+}
 
-    fn execute(&mut self, instruction: u16, opcode: Opcode) -> bool {
-        match opcode {
-            Opcode::Br => br(&mut self.registers, instruction),
-            Opcode::Add => add(&mut self.registers, instruction),
-            Opcode::Ld => ld(self, instruction),
-            Opcode::St => st(self, instruction),
-            Opcode::Jsr => jsr(&mut self.registers, instruction),
-            Opcode::And => and(&mut self.registers, instruction),
-            Opcode::Ldr => ldr(&mut self.registers, &self.memory, instruction),
-            Opcode::Str => str(self, instruction),
-            Opcode::Not => not(&mut self.registers, instruction),
-            Opcode::Ldi => ldi(&mut self.registers, &self.memory, instruction),
-            Opcode::Sti => sti(self, instruction),
-            Opcode::Jmp => jmp(&mut self.registers, instruction),
-            Opcode::Lea => lea(&mut self.registers, instruction),
-            Opcode::Trap => trap(self, instruction),
-            Opcode::Res | Opcode::Rti => {
-                return false;
-            }
+fn link_modules(options: &Options) {
+    let modules: Vec<Module> = options
+        .files
+        .iter()
+        .map(|file| {
+            Module::load(file).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                exit(1)
+            })
+        })
+        .collect();
+    let linked = link(&modules, options.base.unwrap_or(DEFAULT_BASE)).unwrap_or_else(|errors| {
+        for error in errors {
+            eprintln!("{}", error);
         }
+        exit(1)
+    });
+
+    let object = Path::new(options.object.as_deref().unwrap_or("a.obj"));
+    write_file(
+        object,
+        encode(&linked.image, &object.to_string_lossy(), options),
+    );
+    write_file(
+        &object.with_extension("sym"),
+        linked.symbols.to_file().into_bytes(),
+    );
+    eprint!("{}", linked.layout());
+}
+
+/// Writes `image` in the format asked for, or the one `path` suggests.
+fn encode(image: &Image, path: &str, options: &Options) -> Vec<u8> {
+    let format = options
+        .output_format
+        .or_else(|| Format::from_extension(path))
+        .unwrap_or(Format::Object);
+    write(image, format).unwrap_or_else(|e| {
+        eprintln!("could not write {}: {}", path, e);
+        exit(1)
+    })
+}
 
-        true
+fn convert_image(options: &Options) {
+    let (Some(output), [input]) = (&options.object, options.files.as_slice()) else {
+        eprintln!("convert expects one input file and -o output");
+        exit(2)
+    };
+    let image =
+        rustvm::image::read_file(input, options.format, options.load_at).unwrap_or_else(|e| {
+            eprintln!("could not load {}: {}", input, e);
+            exit(1)
+        });
+    if let Err(e) = fs::write(output, encode(&image, output, options)) {
+        eprintln!("could not write {}: {}", output, e);
+        exit(1);
     }
+}
 
-    fn decode(instruction: u16) -> Opcode {
-        let opcode: Opcode = match Opcode::get(instruction >> 12) {
-            Some(opcode) => opcode,
-            None => panic!("Invalid opcode {:X}", instruction),
+/// Assembles the `.asm` files and loads the images among `options.files`,
+/// then lints the whole program from its entry.
+fn lint_program(options: &Options) {
+    let mut vm = Vm::new();
+    let mut map = source_map(options).unwrap_or_default();
+    let mut data = BTreeSet::new();
+    let mut sources_only = true;
+    for file in &options.files {
+        if !file.ends_with(".asm") {
+            let image = rustvm::image::read_file(file, options.format, options.load_at)
+                .unwrap_or_else(|e| {
+                    eprintln!("could not load {}: {}", file, e);
+                    exit(1)
+                });
+            vm.load_image(file, &image);
+            sources_only = false;
+            continue;
+        }
+        let source = fs::read_to_string(file).unwrap_or_else(|e| {
+            eprintln!("could not read {}: {}", file, e);
+            exit(1)
+        });
+        let asm_options = AsmOptions {
+            relocatable: false,
+            directory: Path::new(file).parent().map(Path::to_path_buf),
+            include_paths: options.include_paths.clone(),
         };
-        opcode
+        let assembly = assemble_with(&source, &asm_options).unwrap_or_else(|errors| {
+            for error in errors {
+                eprintln!("{}:{}: {}", file, error.line, error.message);
+            }
+            exit(1)
+        });
+        vm.load_image(file, &assembly.image());
+        data.extend(data_words(&assembly));
+        map.merge(assembly.source_map(file));
     }
 
-    fn fetch(&mut self) -> u16 {
-        let address_of_instruction = self.registers[Register::Pc as usize];
-        let instruction: u16 = self.memory[address_of_instruction as usize];
-        self.registers[Register::Pc as usize] += 1;
-        instruction
+    let entry = options
+        .entry
+        .or_else(|| vm.images.last().map(|image| image.origin))
+        .unwrap_or(PC_START as u16);
+    // Data is only known when every file came with its source
+    let data = sources_only.then_some(&data);
+    let findings = lint(&vm.memory, &vm.images, entry, data);
+    for finding in &findings {
+        match map.lookup(finding.address) {
+            Some(location) => println!("{}: {}", location, finding),
+            None => println!("{}", finding),
+        }
+    }
+    if !findings.is_empty() {
+        eprintln!("{} problem(s) found", findings.len());
+        exit(1);
     }
+}
 
-    pub fn disable_input_buffering(&self) -> windows::core::Result<()> {
-        unsafe {
-            let h_stdin = GetStdHandle(STD_INPUT_HANDLE)?;
-            if h_stdin == INVALID_HANDLE_VALUE {
-                panic!("Invalid handle to STD_INPUT_HANDLE");
-            }
+fn graph_program(options: &Options) {
+    let mut vm = load(options);
+    let entry = vm.registers[Register::Pc as usize];
+    let mut graph = Graph::recover(&vm.memory, &vm.images, entry, &symbols(options));
 
-            H_STDIN_RAW.set(h_stdin.0 as isize).ok();
+    if options.profile {
+        // The program's output would get mixed into the graph
+        if vm.output.is_none() {
+            vm.output = Some(String::new());
+        }
+        vm.quiet_halt = true;
+        vm.coverage = Some(Coverage::new());
+        let raw_console = options.input.is_none();
+        if raw_console {
+            vm.disable_input_buffering().ok();
+        }
+        let reason = vm.run();
+        if raw_console {
+            vm.restore_input_buffering().ok();
+        }
+        if reason != StopReason::Halted {
+            eprintln!("--- Profile run stopped: {} ---", reason);
+        }
+        if let Some(coverage) = &vm.coverage {
+            graph = graph.with_profile(coverage);
+        }
+    }
 
-            let mut old_mode = CONSOLE_MODE(0);
-            GetConsoleMode(h_stdin, &mut old_mode)?;
-            FDW_OLD_MODE = old_mode;
+    let text = if options.json {
+        format!("{}\n", to_json(&graph))
+    } else if options.call_graph {
+        to_call_graph_dot(&graph)
+    } else {
+        to_cfg_dot(&graph)
+    };
+    match &options.object {
+        Some(path) => write_file(Path::new(path), text.into_bytes()),
+        None => print!("{}", text),
+    }
+}
 
-            // Disable echo and line input (same behavior as C code)
-            let new_mode = old_mode.0 ^ ENABLE_ECHO_INPUT.0 ^ ENABLE_LINE_INPUT.0;
-            let new_mode = CONSOLE_MODE(new_mode);
+fn decompile_program(options: &Options) {
+    let vm = load(options);
+    let entry = vm.registers[Register::Pc as usize];
+    let symbols = symbols(options);
+    let graph = Graph::recover(&vm.memory, &vm.images, entry, &symbols);
+    let text = decompile(&graph, &symbols)
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+    match &options.object {
+        Some(path) => write_file(Path::new(path), text.into_bytes()),
+        None => print!("{}", text),
+    }
+}
 
-            SetConsoleMode(h_stdin, new_mode)?;
-            FlushConsoleInputBuffer(h_stdin)?;
-            Ok(())
+fn disassemble_images(options: &Options) {
+    let vm = load(options);
+    let symbols = symbols(options);
+    for image in &vm.images {
+        println!(
+            "; {} at x{:04X}, {} words",
+            image.file_name, image.origin, image.length
+        );
+        for address in image.addresses() {
+            if let Some(label) = symbols.label_at(address) {
+                println!("{}:", label);
+            }
+            println!("{}", instruction_line(address, vm.memory[address as usize]));
         }
     }
+}
 
-    pub fn restore_input_buffering(&self) -> windows::core::Result<()> {
-        unsafe {
-            if let Some(h_stdin) = self.get_handle() {
-                SetConsoleMode(h_stdin, FDW_OLD_MODE)?;
+fn dump_images(options: &Options) {
+    let vm = load(options);
+    match options.range {
+        Some((start, end)) => print!("{}", hex_dump(&vm.memory, start, end)),
+        None => {
+            for image in &vm.images {
+                println!("; {}", image.file_name);
+                let end = image.origin as u32 + image.length as u32;
+                print!("{}", hex_dump(&vm.memory, image.origin, end));
             }
-            Ok(())
         }
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    let mut vm = Vm::new();
+fn run(options: &Options) {
+    let mut vm = load(options);
+    let source_map = source_map(options).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1)
+    });
+    let source_line = |address: u16, format: fn(String) -> String| {
+        source_map
+            .lookup(address)
+            .map_or(String::new(), |location| format(location.to_string()))
+    };
+
+    if let Some(path) = &options.translate {
+        let source = rustvm::aot::translate(&vm, vm.registers[Register::Pc as usize]);
+        if let Err(e) = fs::write(path, source) {
+            eprintln!("Could not write {}: {}", path, e);
+            exit(1);
+        }
+        eprintln!("--- Translated to {} ---", path);
+        return;
+    }
 
-    vm.disable_input_buffering();
+    // The debugger reads whole command lines, so the console stays buffered
+    let raw_console = options.command != Command::Debug && options.input.is_none();
+    if raw_console {
+        vm.disable_input_buffering().ok();
+    }
 
-    match args.len() {
-        1 => {
-            println!("Usage: {} [image-file1]...", args[0]);
-            exit(2)
-        }
-        _ => {
-            for image_file in args {
-                if !vm.read_file(&image_file) {
-                    println!("{} is not a valid image.", &image_file);
-                    exit(1);
-                }
+    let reason = match options.command {
+        Command::Debug => {
+            let mut debugger = Debugger::new()
+                .with_symbols(symbols(options))
+                .with_source_map(source_map.clone());
+            if let Err(e) = debugger.run(&mut vm, &mut io::stdin().lock(), &mut io::stdout()) {
+                eprintln!("{}", e);
+                exit(1);
             }
+            StopReason::Halted
         }
-    }
+        Command::Trace => run_with(&mut vm, |vm| {
+            let pc = vm.registers[Register::Pc as usize];
+            eprintln!(
+                "{}{}",
+                trace_line(vm),
+                source_line(pc, |l| format!("  {}", l))
+            );
+            None
+        }),
+        _ if options.microcode => {
+            let mut engine =
+                MicroEngine::new(options.memory_latency).with_trace(options.trace_states);
+            let reason = engine.run(&mut vm);
+            eprintln!("--- Microcode engine: {} cycles ---", engine.cycles);
+            reason
+        }
+        _ => vm.run(),
+    };
 
-    vm.registers[Register::Cond as usize] = ConditionFlag::Zro as u16;
-    vm.registers[Register::Pc as usize] = PC_START as u16;
+    if raw_console {
+        vm.restore_input_buffering().ok();
+    }
 
-    vm.run();
+    if let (Some(path), Some(output)) = (&options.output, &vm.output) {
+        fs::write(path, output).unwrap_or_else(|e| {
+            eprintln!("could not write {}: {}", path, e);
+            exit(1)
+        });
+    }
+    if let Err(e) = options.coverage.write_reports(&vm) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+    if let Some(conventions) = &vm.conventions {
+        eprint!(
+            "{}",
+            conventions.report_with(&symbols(options), &source_map)
+        );
+    }
+    if let Some(memcheck) = &vm.memcheck {
+        eprint!("{}", memcheck.report_with(&source_map));
+    }
+    if let Some(code_watch) = &vm.code_watch {
+        eprint!("{}", code_watch.report_with(&symbols(options), &source_map));
+    }
 
-    vm.restore_input_buffering().unwrap();
+    let Some(code) = reason.exit_code() else {
+        return;
+    };
+    let pc = match reason {
+        StopReason::Stuck(address) => address,
+        _ => vm.registers[Register::Pc as usize],
+    };
+    eprintln!(
+        "--- Stopped: {}{} ---",
+        reason,
+        source_line(pc, |l| format!(" ({})", l))
+    );
+    exit(code);
 }
@@ -0,0 +1,681 @@
+use crate::instructions::sign_extend;
+use crate::instructions::trap::{TRAP_GETC, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP};
+use crate::limits::StopReason;
+use crate::symbols::source_map::SourceMap;
+use std::fmt;
+use std::fmt::Write;
+
+/// Where the device registers start. Reading them is always fine.
+const DEVICES: u16 = 0xFE00;
+
+/// Where an undefined value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Nothing ever wrote it.
+    Unset,
+    /// The instruction at `pc` loaded it from `address`, where a store had
+    /// copied a value that was never set.
+    Loaded { address: u16, pc: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Register(u8),
+    Memory(u16),
+    /// The condition codes, read by a conditional branch.
+    Condition,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Location::Register(register) => write!(f, "R{}", register),
+            Location::Memory(address) => write!(f, "x{:04X}", address),
+            Location::Condition => write!(f, "the condition codes"),
+        }
+    }
+}
+
+/// A use of an undefined value, where it first happened and how often.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UninitializedRead {
+    pub pc: u16,
+    pub location: Location,
+    pub origin: Origin,
+    pub count: u64,
+}
+
+impl fmt::Display for UninitializedRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Location::Register(register) => write!(f, "reads R{}", register)?,
+            Location::Memory(address) => write!(f, "reads x{:04X}", address)?,
+            Location::Condition => write!(f, "branches on a value")?,
+        }
+        match self.origin {
+            Origin::Unset => write!(f, ", which was never set"),
+            Origin::Loaded { address, pc } => write!(
+                f,
+                " loaded at x{:04X} from x{:04X}, which was never set",
+                pc, address
+            ),
+        }
+    }
+}
+
+/// Keeps an "initialized" bit for every memory word, register and the
+/// condition codes, and reports uses of values nothing has set.
+///
+/// Loading a word nothing ever wrote is reported at the load. Copies of
+/// undefined registers carry the bits along instead, as in Valgrind's
+/// memcheck: `ST`, `STR` and `STI` of an undefined register leave a copy
+/// that `LD`, `LDR` and `LDI` hand on to the register they load, and so
+/// does `ADD DR, SR, #0`. That keeps a subroutine saving and restoring
+/// registers its caller never set quiet. Everything else that reads a
+/// value uses it: arithmetic, addresses, jump targets, conditional
+/// branches, trap arguments and fetching an instruction.
+///
+/// Loading an image defines its words, `.BLKW` space included, since an
+/// image can't tell it apart from the rest. The caller undefines the words
+/// a source map lists as reserved, so `.BLKW` space only counts as unset
+/// when the assembler's `.map` file is found with the image.
+pub struct MemCheck {
+    /// One bit per address that holds a value.
+    memory: Vec<u64>,
+    /// One bit per address a store copied an undefined register into.
+    copies: Vec<u64>,
+    /// `None` for each register that holds a value.
+    registers: [Option<Origin>; 8],
+    condition: Option<Origin>,
+    /// Stop at the first use instead of noting it and going on.
+    halt: bool,
+    reads: Vec<UninitializedRead>,
+    /// The first read found in the instruction being checked.
+    found: Option<Location>,
+}
+
+impl MemCheck {
+    /// Starts with the registers and all memory but the device registers
+    /// undefined.
+    pub fn new(halt: bool) -> MemCheck {
+        let mut memcheck = Self {
+            memory: vec![0; 1 << 10],
+            copies: vec![0; 1 << 10],
+            registers: [Some(Origin::Unset); 8],
+            condition: Some(Origin::Unset),
+            halt,
+            reads: Vec::new(),
+            found: None,
+        };
+        memcheck.define(DEVICES, 0x10000 - DEVICES as usize);
+        memcheck
+    }
+
+    pub fn reads(&self) -> &[UninitializedRead] {
+        &self.reads
+    }
+
+    /// Whether it stopped the program.
+    pub fn halted(&self) -> bool {
+        self.halt && !self.reads.is_empty()
+    }
+
+    /// Marks `length` words from `start` as holding values.
+    pub fn define(&mut self, start: u16, length: usize) {
+        for address in (start as usize..).take(length) {
+            self.set(address as u16, true);
+        }
+    }
+
+    /// Marks a word as holding no value, for `.BLKW` space.
+    pub fn undefine(&mut self, address: u16) {
+        self.set(address, false);
+    }
+
+    pub fn define_register(&mut self, register: usize) {
+        self.registers[register] = None;
+    }
+
+    pub fn is_defined(&self, address: u16) -> bool {
+        bit(&self.memory, address)
+    }
+
+    /// Marks a word defined or never written.
+    fn set(&mut self, address: u16, defined: bool) {
+        set_bit(&mut self.memory, address, defined);
+        set_bit(&mut self.copies, address, false);
+    }
+
+    fn report(&mut self, pc: u16, location: Location, origin: Origin) {
+        self.found.get_or_insert(location);
+        let known = self
+            .reads
+            .iter_mut()
+            .find(|read| read.pc == pc && read.location == location);
+        match known {
+            Some(read) => read.count += 1,
+            None => self.reads.push(UninitializedRead {
+                pc,
+                location,
+                origin,
+                count: 1,
+            }),
+        }
+    }
+
+    /// Uses a register, which holds a value from then on so one mistake
+    /// is not reported again for every instruction after it.
+    fn use_register(&mut self, pc: u16, register: u16) {
+        if let Some(origin) = self.registers[register as usize].take() {
+            self.report(pc, Location::Register(register as u8), origin);
+        }
+    }
+
+    fn use_memory(&mut self, pc: u16, address: u16) {
+        if !self.is_defined(address) {
+            self.report(pc, Location::Memory(address), Origin::Unset);
+            self.set(address, true);
+        }
+    }
+
+    /// Loads `register` from `address`. A copy of an undefined value passes
+    /// on to the register, while a word nothing wrote is read right here.
+    fn load(&mut self, pc: u16, register: u16, address: u16) {
+        let origin = if bit(&self.copies, address) {
+            Some(Origin::Loaded { address, pc })
+        } else {
+            self.use_memory(pc, address);
+            None
+        };
+        self.write(register, origin);
+    }
+
+    /// Writes a register and the condition codes with it.
+    fn write(&mut self, register: u16, origin: Option<Origin>) {
+        self.registers[register as usize] = origin;
+        self.condition = origin;
+    }
+
+    fn store(&mut self, address: u16, register: u16) {
+        let defined = self.registers[register as usize].is_none();
+        set_bit(&mut self.memory, address, defined);
+        set_bit(&mut self.copies, address, !defined);
+    }
+
+    /// Checks the instruction at `pc` against the registers and memory it
+    /// is about to run with, and notes what it writes. In halt mode, gives
+    /// back why to stop the program before the instruction runs.
+    pub fn check(
+        &mut self,
+        pc: u16,
+        instruction: u16,
+        registers: &[u16],
+        memory: &[u16],
+        os_traps: bool,
+    ) -> Option<StopReason> {
+        self.found = None;
+        self.use_memory(pc, pc);
+
+        let next = pc.wrapping_add(1);
+        let destination = (instruction >> 9) & 0x7;
+        let base = (instruction >> 6) & 0x7;
+        let pc_offset = next.wrapping_add(sign_extend(instruction & 0x1FF, 9));
+        let base_offset = registers[base as usize].wrapping_add(sign_extend(instruction & 0x3F, 6));
+        let immediate = instruction & 0x20 != 0;
+        let value = sign_extend(instruction & 0x1F, 5);
+
+        match instruction >> 12 {
+            // ADD and AND; AND with #0 is how registers get cleared
+            0b0001 | 0b0101 => {
+                let opcode = instruction >> 12;
+                if immediate && value == 0 && opcode == 0b0101 {
+                    self.write(destination, None);
+                } else if immediate && value == 0 {
+                    self.write(destination, self.registers[base as usize]);
+                } else {
+                    self.use_register(pc, base);
+                    if !immediate {
+                        self.use_register(pc, instruction & 0x7);
+                    }
+                    self.write(destination, None);
+                }
+            }
+            // NOT
+            0b1001 => {
+                self.use_register(pc, base);
+                self.write(destination, None);
+            }
+            // BR, which only reads the condition codes when it tests some
+            0b0000 if destination != 0 && destination != 0b111 => {
+                if let Some(origin) = self.condition.take() {
+                    self.report(pc, Location::Condition, origin);
+                }
+            }
+            // JMP and RET
+            0b1100 => self.use_register(pc, base),
+            // JSR and JSRR
+            0b0100 => {
+                if instruction & 0x800 == 0 {
+                    self.use_register(pc, base);
+                }
+                self.registers[7] = None;
+            }
+            // LD
+            0b0010 => self.load(pc, destination, pc_offset),
+            // LDI
+            0b1010 => {
+                self.use_memory(pc, pc_offset);
+                self.load(pc, destination, memory[pc_offset as usize]);
+            }
+            // LDR
+            0b0110 => {
+                self.use_register(pc, base);
+                self.load(pc, destination, base_offset);
+            }
+            // LEA
+            0b1110 => self.write(destination, None),
+            // ST
+            0b0011 => self.store(pc_offset, destination),
+            // STI
+            0b1011 => {
+                self.use_memory(pc, pc_offset);
+                self.store(memory[pc_offset as usize], destination);
+            }
+            // STR
+            0b0111 => {
+                self.use_register(pc, base);
+                self.store(base_offset, destination);
+            }
+            0b1111 => {
+                let vector = instruction & 0xFF;
+                if os_traps {
+                    self.use_memory(pc, vector);
+                } else {
+                    self.trap(pc, vector, registers, memory);
+                }
+                self.registers[7] = None;
+            }
+            // RTI pops the PC and PSR
+            0b1000 => {
+                self.use_register(pc, 6);
+                let stack = registers[6];
+                self.use_memory(pc, stack);
+                self.use_memory(pc, stack.wrapping_add(1));
+            }
+            _ => {}
+        }
+
+        let location = self.found.filter(|_| self.halt)?;
+        Some(StopReason::Uninitialized { pc, location })
+    }
+
+    /// The built-in trap routines: `OUT` and `PUTS` read R0 and the string
+    /// it points to, `GETC` and `IN` write R0.
+    fn trap(&mut self, pc: u16, vector: u16, registers: &[u16], memory: &[u16]) {
+        match vector {
+            TRAP_OUT => self.use_register(pc, 0),
+            TRAP_PUTS | TRAP_PUTSP => {
+                self.use_register(pc, 0);
+                let mut address = registers[0];
+                loop {
+                    self.use_memory(pc, address);
+                    let word = memory[address as usize];
+                    let packed_end =
+                        vector == TRAP_PUTSP && (word & 0xFF == 0 || word & 0xFF00 == 0);
+                    if word == 0 || packed_end || address == 0xFFFF {
+                        break;
+                    }
+                    address += 1;
+                }
+            }
+            TRAP_GETC | TRAP_IN => self.write(0, None),
+            _ => {}
+        }
+    }
+
+    /// One line per use, with source lines where the map knows them.
+    pub fn report_with(&self, source_map: &SourceMap) -> String {
+        let mut report = String::new();
+        match (self.halted(), self.reads.len()) {
+            (true, _) => writeln!(
+                report,
+                "--- Memory check: stopped at an uninitialized read ---"
+            ),
+            (false, 0) => writeln!(report, "--- Memory check: no uninitialized reads ---"),
+            (false, 1) => writeln!(report, "--- Memory check: 1 uninitialized read ---"),
+            (false, count) => {
+                writeln!(
+                    report,
+                    "--- Memory check: {} uninitialized reads ---",
+                    count
+                )
+            }
+        }
+        .unwrap();
+        for read in &self.reads {
+            write!(report, "x{:04X}", read.pc).unwrap();
+            if let Some(location) = source_map.lookup(read.pc) {
+                write!(report, " ({})", location).unwrap();
+            }
+            write!(report, ": {}", read).unwrap();
+            if read.count > 1 {
+                write!(report, " ({} times)", read.count).unwrap();
+            }
+            writeln!(report).unwrap();
+        }
+        report
+    }
+}
+
+fn bit(bits: &[u64], address: u16) -> bool {
+    bits[address as usize / 64] & (1 << (address % 64)) != 0
+}
+
+fn set_bit(bits: &mut [u64], address: u16, value: bool) {
+    let (word, mask) = (address as usize / 64, 1 << (address % 64));
+    if value {
+        bits[word] |= mask;
+    } else {
+        bits[word] &= !mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::cc::compile_image;
+    use crate::limits::StopReason;
+    use crate::memcheck::{Location, MemCheck, Origin};
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    /// Assembles and runs `source` under the checker, with `.BLKW` space
+    /// left undefined the way the CLI does it.
+    fn check(source: &str, halt: bool) -> Vm {
+        let assembly = assemble(source).unwrap();
+        let mut vm = Vm::new();
+        vm.memcheck = Some(MemCheck::new(halt));
+        vm.load_image("test.obj", &assembly.image());
+        let memcheck = vm.memcheck.as_mut().unwrap();
+        for address in assembly.source_map("test.asm").reserved() {
+            memcheck.undefine(address);
+        }
+        vm.registers[Register::Pc as usize] = assembly.origin;
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.limits.max_instructions = Some(1000);
+        vm.run();
+        vm
+    }
+
+    fn reads(vm: &Vm) -> Vec<String> {
+        let memcheck = vm.memcheck.as_ref().unwrap();
+        memcheck
+            .reads()
+            .iter()
+            .map(|read| read.to_string())
+            .collect()
+    }
+
+    // ========== Registers ==========
+
+    #[test]
+    fn test_register_never_set() {
+        let vm = check(".ORIG x3000\nADD R1, R3, #1\nHALT\n.END\n", false);
+        let memcheck = vm.memcheck.as_ref().unwrap();
+
+        assert_eq!(memcheck.reads().len(), 1);
+        assert_eq!(memcheck.reads()[0].pc, 0x3000);
+        assert_eq!(memcheck.reads()[0].location, Location::Register(3));
+        assert_eq!(reads(&vm), ["reads R3, which was never set"]);
+    }
+
+    #[test]
+    fn test_cleared_and_copied_registers_are_not_uses() {
+        let vm = check(
+            ".ORIG x3000
+AND R0, R0, #0
+ADD R0, R0, #5
+ST R2, SAVE
+ADD R3, R2, #0
+LD R2, SAVE
+ADD R0, R0, #-1
+BRp #-2
+HALT
+SAVE .FILL 0
+.END
+",
+            false,
+        );
+
+        assert_eq!(reads(&vm), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_branch_on_unset_condition_codes() {
+        let vm = check(".ORIG x3000\nBRz #0\nBRnzp #0\nHALT\n.END\n", false);
+        let memcheck = vm.memcheck.as_ref().unwrap();
+
+        assert_eq!(memcheck.reads().len(), 1);
+        assert_eq!(memcheck.reads()[0].location, Location::Condition);
+    }
+
+    // ========== Memory ==========
+
+    #[test]
+    fn test_loading_a_reserved_word_is_a_read() {
+        let vm = check(
+            ".ORIG x3000
+LD R2, BUFFER
+LEA R1, BUFFER
+LDR R3, R1, #1
+HALT
+BUFFER .BLKW 2
+.END
+",
+            false,
+        );
+        let memcheck = vm.memcheck.as_ref().unwrap();
+
+        assert_eq!(memcheck.reads()[0].location, Location::Memory(0x3004));
+        assert_eq!(
+            reads(&vm),
+            [
+                "reads x3004, which was never set",
+                "reads x3005, which was never set"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stored_words_are_initialized() {
+        let vm = check(
+            ".ORIG x3000
+AND R1, R1, #0
+ST R1, COUNT
+LD R2, COUNT
+ADD R2, R2, #1
+HALT
+COUNT .BLKW 1
+.END
+",
+            false,
+        );
+
+        assert!(reads(&vm).is_empty());
+    }
+
+    #[test]
+    fn test_copies_of_unset_registers_pass_through_memory() {
+        let vm = check(
+            ".ORIG x3000
+ST R4, SAVE
+LD R1, SAVE
+ADD R1, R1, #1
+HALT
+SAVE .BLKW 1
+.END
+",
+            false,
+        );
+        let memcheck = vm.memcheck.as_ref().unwrap();
+
+        assert_eq!(memcheck.reads().len(), 1);
+        assert_eq!(memcheck.reads()[0].pc, 0x3002);
+        assert_eq!(
+            memcheck.reads()[0].origin,
+            Origin::Loaded {
+                address: 0x3004,
+                pc: 0x3001
+            }
+        );
+        assert_eq!(
+            reads(&vm),
+            ["reads R1 loaded at x3001 from x3004, which was never set"]
+        );
+    }
+
+    #[test]
+    fn test_blkw_with_a_value_is_initialized() {
+        let vm = check(
+            ".ORIG x3000\nLD R1, COUNT\nADD R1, R1, #1\nHALT\nCOUNT .BLKW 1 #0\n.END\n",
+            false,
+        );
+
+        assert!(reads(&vm).is_empty());
+    }
+
+    #[test]
+    fn test_puts_of_an_unset_string() {
+        let vm = check(
+            ".ORIG x3000\nLEA R0, TEXT\nPUTS\nHALT\nTEXT .BLKW 2\n.END\n",
+            false,
+        );
+
+        assert_eq!(reads(&vm), ["reads x3003, which was never set"]);
+    }
+
+    #[test]
+    fn test_running_off_into_unset_memory() {
+        let vm = check(".ORIG x3000\nAND R0, R0, #0\nBRz #1\nHALT\n.END\n", false);
+
+        assert_eq!(reads(&vm)[0], "reads x3003, which was never set");
+    }
+
+    // ========== Halting ==========
+
+    #[test]
+    fn test_halt_mode_stops_before_the_read() {
+        let vm = check(
+            ".ORIG x3000\nADD R1, R1, #1\nADD R2, R2, #1\nHALT\n.END\n",
+            true,
+        );
+        let memcheck = vm.memcheck.as_ref().unwrap();
+
+        assert!(memcheck.halted());
+        assert_eq!(memcheck.reads().len(), 1);
+        assert_eq!(vm.registers[1], 0);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+        assert!(memcheck.report_with(&Default::default()).starts_with(
+            "--- Memory check: stopped at an uninitialized read ---\nx3000: reads R1"
+        ));
+    }
+
+    #[test]
+    fn test_halt_mode_names_the_read_it_stopped_at() {
+        let source = ".ORIG x3000\nLD R1, BUFFER\nHALT\nBUFFER .BLKW 1\n.END\n";
+        let assembly = assemble(source).unwrap();
+        let mut vm = Vm::new();
+        vm.memcheck = Some(MemCheck::new(true));
+        vm.load_image("test.obj", &assembly.image());
+        vm.memcheck.as_mut().unwrap().undefine(0x3002);
+        vm.registers[Register::Pc as usize] = 0x3000;
+
+        assert_eq!(
+            vm.run(),
+            StopReason::Uninitialized {
+                pc: 0x3000,
+                location: Location::Memory(0x3002)
+            }
+        );
+        assert_eq!(
+            StopReason::Uninitialized {
+                pc: 0x3000,
+                location: Location::Register(3)
+            }
+            .to_string(),
+            "uninitialized read of R3 at x3000"
+        );
+    }
+
+    #[test]
+    fn test_report_counts_repeats() {
+        let mut memcheck = MemCheck::new(false);
+        memcheck.define(0x3000, 2);
+        let memory = vec![0; 0x10000];
+        for _ in 0..3 {
+            memcheck.check(0x3000, 0x1261, &[0; 10], &memory, false);
+            memcheck.registers[1] = Some(Origin::Unset);
+        }
+
+        assert_eq!(
+            memcheck.report_with(&Default::default()),
+            "--- Memory check: 1 uninitialized read ---\nx3000: reads R1, which was never set (3 times)\n"
+        );
+    }
+
+    /// Compiles and runs `source` under the checker.
+    fn check_compiled(source: &str) -> Vm {
+        let assembly = compile_image(source).unwrap();
+        let mut vm = Vm::new();
+        vm.memcheck = Some(MemCheck::new(false));
+        vm.load_image("test.obj", &assembly.image());
+        let memcheck = vm.memcheck.as_mut().unwrap();
+        for address in assembly.source_map("test.asm").reserved() {
+            memcheck.undefine(address);
+        }
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.run();
+        vm
+    }
+
+    #[test]
+    fn test_compiled_programs_are_clean() {
+        let vm = check_compiled(
+            "int total;
+int table[4];
+int sum(int *p, int n) {
+    int s = 0;
+    while (n > 0) { s = s + *p; p = p + 1; n = n - 1; }
+    return s;
+}
+int main() {
+    int i = 0;
+    while (i < 4) { table[i] = i; i = i + 1; }
+    total = sum(table, 4);
+    putchar('0' + total);
+    return 0;
+}",
+        );
+
+        assert_eq!(vm.output.as_deref(), Some("6"));
+        assert_eq!(reads(&vm), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_compiled_void_calls_are_clean() {
+        let vm = check_compiled(
+            "void show(int n) { putchar('0' + n); }
+int main() {
+    show(4);
+    show(2);
+    return 0;
+}",
+        );
+
+        assert_eq!(vm.output.as_deref(), Some("42"));
+        assert_eq!(reads(&vm), Vec::<String>::new());
+    }
+}
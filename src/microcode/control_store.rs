@@ -0,0 +1,447 @@
+/// How the microsequencer picks the next state when `IRD` is clear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Unconditional,
+    /// J[1] is set once memory signals ready.
+    MemoryReady,
+    /// J[2] is set when the branch enable bit is set.
+    Branch,
+    /// J[0] is set when IR[11] is set (JSR vs JSRR).
+    AddressingMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcMux {
+    PcPlusOne,
+    Bus,
+    Adder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrMux {
+    Ir11To9,
+    R7,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sr1Mux {
+    Ir11To9,
+    Ir8To6,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Addr1Mux {
+    Pc,
+    BaseR,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Addr2Mux {
+    Zero,
+    Offset6,
+    PcOffset9,
+    PcOffset11,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarMux {
+    ZeroExtendIr7To0,
+    Adder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aluk {
+    Add,
+    And,
+    Not,
+    PassA,
+}
+
+/// One word of the control store: the datapath control signals asserted in
+/// a state plus the fields the microsequencer uses to choose the next state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Microinstruction {
+    pub ird: bool,
+    pub condition: Condition,
+    pub j: u8,
+    pub ld_mar: bool,
+    pub ld_mdr: bool,
+    pub ld_ir: bool,
+    pub ld_ben: bool,
+    pub ld_reg: bool,
+    pub ld_cc: bool,
+    pub ld_pc: bool,
+    pub gate_pc: bool,
+    pub gate_mdr: bool,
+    pub gate_alu: bool,
+    pub gate_marmux: bool,
+    pub pc_mux: PcMux,
+    pub dr_mux: DrMux,
+    pub sr1_mux: Sr1Mux,
+    pub addr1_mux: Addr1Mux,
+    pub addr2_mux: Addr2Mux,
+    pub mar_mux: MarMux,
+    pub aluk: Aluk,
+    pub mio_en: bool,
+    pub write: bool,
+}
+
+const NONE: Microinstruction = Microinstruction {
+    ird: false,
+    condition: Condition::Unconditional,
+    j: 18,
+    ld_mar: false,
+    ld_mdr: false,
+    ld_ir: false,
+    ld_ben: false,
+    ld_reg: false,
+    ld_cc: false,
+    ld_pc: false,
+    gate_pc: false,
+    gate_mdr: false,
+    gate_alu: false,
+    gate_marmux: false,
+    pc_mux: PcMux::PcPlusOne,
+    dr_mux: DrMux::Ir11To9,
+    sr1_mux: Sr1Mux::Ir8To6,
+    addr1_mux: Addr1Mux::Pc,
+    addr2_mux: Addr2Mux::Zero,
+    mar_mux: MarMux::Adder,
+    aluk: Aluk::Add,
+    mio_en: false,
+    write: false,
+};
+
+/// MAR <- PC, PC <- PC + 1
+const STATE_18: Microinstruction = Microinstruction {
+    j: 33,
+    ld_mar: true,
+    ld_pc: true,
+    gate_pc: true,
+    pc_mux: PcMux::PcPlusOne,
+    ..NONE
+};
+/// MDR <- M[MAR]
+const STATE_33: Microinstruction = Microinstruction {
+    j: 33,
+    condition: Condition::MemoryReady,
+    ld_mdr: true,
+    mio_en: true,
+    ..NONE
+};
+/// IR <- MDR
+const STATE_35: Microinstruction = Microinstruction {
+    j: 32,
+    ld_ir: true,
+    gate_mdr: true,
+    ..NONE
+};
+/// BEN <- IR[11] & N + IR[10] & Z + IR[9] & P, then decode on IR[15:12]
+const STATE_32: Microinstruction = Microinstruction {
+    ird: true,
+    ld_ben: true,
+    ..NONE
+};
+/// BR: branch to 22 if BEN
+const STATE_0: Microinstruction = Microinstruction {
+    j: 18,
+    condition: Condition::Branch,
+    ..NONE
+};
+/// ADD: DR <- SR1 + OP2, set CC
+const STATE_1: Microinstruction = Microinstruction {
+    ld_reg: true,
+    ld_cc: true,
+    gate_alu: true,
+    aluk: Aluk::Add,
+    ..NONE
+};
+/// AND: DR <- SR1 & OP2, set CC
+const STATE_5: Microinstruction = Microinstruction {
+    aluk: Aluk::And,
+    ..STATE_1
+};
+/// NOT: DR <- NOT(SR), set CC
+const STATE_9: Microinstruction = Microinstruction {
+    aluk: Aluk::Not,
+    ..STATE_1
+};
+/// LEA: DR <- PC + off9, set CC
+const STATE_14: Microinstruction = Microinstruction {
+    ld_reg: true,
+    ld_cc: true,
+    gate_marmux: true,
+    addr1_mux: Addr1Mux::Pc,
+    addr2_mux: Addr2Mux::PcOffset9,
+    ..NONE
+};
+/// LD: MAR <- PC + off9
+const STATE_2: Microinstruction = Microinstruction {
+    j: 25,
+    ld_mar: true,
+    gate_marmux: true,
+    addr1_mux: Addr1Mux::Pc,
+    addr2_mux: Addr2Mux::PcOffset9,
+    ..NONE
+};
+/// LDR: MAR <- BaseR + off6
+const STATE_6: Microinstruction = Microinstruction {
+    j: 25,
+    ld_mar: true,
+    gate_marmux: true,
+    addr1_mux: Addr1Mux::BaseR,
+    addr2_mux: Addr2Mux::Offset6,
+    ..NONE
+};
+/// LDI: MAR <- PC + off9
+const STATE_10: Microinstruction = Microinstruction { j: 24, ..STATE_2 };
+/// MDR <- M[MAR]
+const STATE_24: Microinstruction = Microinstruction { j: 24, ..STATE_33 };
+/// MAR <- MDR
+const STATE_26: Microinstruction = Microinstruction {
+    j: 25,
+    ld_mar: true,
+    gate_mdr: true,
+    ..NONE
+};
+/// MDR <- M[MAR]
+const STATE_25: Microinstruction = Microinstruction { j: 25, ..STATE_33 };
+/// DR <- MDR, set CC
+const STATE_27: Microinstruction = Microinstruction {
+    ld_reg: true,
+    ld_cc: true,
+    gate_mdr: true,
+    ..NONE
+};
+/// ST: MAR <- PC + off9
+const STATE_3: Microinstruction = Microinstruction { j: 23, ..STATE_2 };
+/// STR: MAR <- BaseR + off6
+const STATE_7: Microinstruction = Microinstruction { j: 23, ..STATE_6 };
+/// STI: MAR <- PC + off9
+const STATE_11: Microinstruction = Microinstruction { j: 29, ..STATE_2 };
+/// MDR <- M[MAR]
+const STATE_29: Microinstruction = Microinstruction { j: 29, ..STATE_33 };
+/// MAR <- MDR
+const STATE_31: Microinstruction = Microinstruction { j: 23, ..STATE_26 };
+/// MDR <- SR
+const STATE_23: Microinstruction = Microinstruction {
+    j: 16,
+    ld_mdr: true,
+    gate_alu: true,
+    aluk: Aluk::PassA,
+    sr1_mux: Sr1Mux::Ir11To9,
+    ..NONE
+};
+/// M[MAR] <- MDR
+const STATE_16: Microinstruction = Microinstruction {
+    j: 16,
+    condition: Condition::MemoryReady,
+    mio_en: true,
+    write: true,
+    ..NONE
+};
+/// PC <- PC + off9
+const STATE_22: Microinstruction = Microinstruction {
+    ld_pc: true,
+    pc_mux: PcMux::Adder,
+    addr1_mux: Addr1Mux::Pc,
+    addr2_mux: Addr2Mux::PcOffset9,
+    ..NONE
+};
+/// JMP: PC <- BaseR
+const STATE_12: Microinstruction = Microinstruction {
+    ld_pc: true,
+    pc_mux: PcMux::Adder,
+    addr1_mux: Addr1Mux::BaseR,
+    addr2_mux: Addr2Mux::Zero,
+    ..NONE
+};
+/// JSR: R7 <- PC, then 21 (JSR) or 20 (JSRR) on IR[11]
+const STATE_4: Microinstruction = Microinstruction {
+    j: 20,
+    condition: Condition::AddressingMode,
+    ld_reg: true,
+    gate_pc: true,
+    dr_mux: DrMux::R7,
+    ..NONE
+};
+/// JSRR: PC <- BaseR
+const STATE_20: Microinstruction = STATE_12;
+/// JSR: PC <- PC + off11
+const STATE_21: Microinstruction = Microinstruction {
+    addr2_mux: Addr2Mux::PcOffset11,
+    ..STATE_22
+};
+/// TRAP: MAR <- ZEXT(IR[7:0])
+const STATE_15: Microinstruction = Microinstruction {
+    j: 28,
+    ld_mar: true,
+    gate_marmux: true,
+    mar_mux: MarMux::ZeroExtendIr7To0,
+    ..NONE
+};
+/// MDR <- M[MAR], R7 <- PC
+const STATE_28: Microinstruction = Microinstruction {
+    j: 28,
+    condition: Condition::MemoryReady,
+    ld_mdr: true,
+    mio_en: true,
+    ld_reg: true,
+    gate_pc: true,
+    dr_mux: DrMux::R7,
+    ..NONE
+};
+/// PC <- MDR
+const STATE_30: Microinstruction = Microinstruction {
+    ld_pc: true,
+    pc_mux: PcMux::Bus,
+    gate_mdr: true,
+    ..NONE
+};
+
+/// Returns the control store word for `state`, or `None` for states this
+/// model does not implement (privilege, interrupts and RTI).
+pub fn microinstruction(state: u8) -> Option<&'static Microinstruction> {
+    let word = match state {
+        0 => &STATE_0,
+        1 => &STATE_1,
+        2 => &STATE_2,
+        3 => &STATE_3,
+        4 => &STATE_4,
+        5 => &STATE_5,
+        6 => &STATE_6,
+        7 => &STATE_7,
+        9 => &STATE_9,
+        10 => &STATE_10,
+        11 => &STATE_11,
+        12 => &STATE_12,
+        14 => &STATE_14,
+        15 => &STATE_15,
+        16 => &STATE_16,
+        18 => &STATE_18,
+        20 => &STATE_20,
+        21 => &STATE_21,
+        22 => &STATE_22,
+        23 => &STATE_23,
+        24 => &STATE_24,
+        25 => &STATE_25,
+        26 => &STATE_26,
+        27 => &STATE_27,
+        28 => &STATE_28,
+        29 => &STATE_29,
+        30 => &STATE_30,
+        31 => &STATE_31,
+        32 => &STATE_32,
+        33 => &STATE_33,
+        35 => &STATE_35,
+        _ => return None,
+    };
+    Some(word)
+}
+
+impl Microinstruction {
+    /// Names of the asserted signals, in the notation of the textbook.
+    pub fn asserted(&self) -> Vec<String> {
+        let mut signals = Vec::new();
+        let flags = [
+            (self.ld_mar, "LD.MAR"),
+            (self.ld_mdr, "LD.MDR"),
+            (self.ld_ir, "LD.IR"),
+            (self.ld_ben, "LD.BEN"),
+            (self.ld_reg, "LD.REG"),
+            (self.ld_cc, "LD.CC"),
+            (self.ld_pc, "LD.PC"),
+            (self.gate_pc, "GatePC"),
+            (self.gate_mdr, "GateMDR"),
+            (self.gate_alu, "GateALU"),
+            (self.gate_marmux, "GateMARMUX"),
+            (self.mio_en, "MIO.EN"),
+        ];
+        for (asserted, name) in flags {
+            if asserted {
+                signals.push(name.to_string());
+            }
+        }
+
+        if self.ld_pc {
+            signals.push(format!("PCMUX={:?}", self.pc_mux));
+        }
+        if self.ld_reg {
+            signals.push(format!("DRMUX={:?}", self.dr_mux));
+        }
+        if self.gate_alu {
+            signals.push(format!("ALUK={:?}", self.aluk));
+        }
+        let uses_adder = self.gate_marmux && self.mar_mux == MarMux::Adder;
+        if uses_adder || (self.ld_pc && self.pc_mux == PcMux::Adder) {
+            signals.push(format!("ADDR1MUX={:?}", self.addr1_mux));
+            signals.push(format!("ADDR2MUX={:?}", self.addr2_mux));
+        }
+        if self.gate_marmux {
+            signals.push(format!("MARMUX={:?}", self.mar_mux));
+        }
+        if self.mio_en {
+            signals.push(if self.write { "R.W=W" } else { "R.W=R" }.to_string());
+        }
+        if self.ird {
+            signals.push("IRD".to_string());
+        }
+
+        signals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::microcode::control_store::{microinstruction, Condition};
+
+    #[test]
+    fn test_memory_states_wait_on_ready() {
+        for state in [16, 24, 25, 28, 29, 33] {
+            let word = microinstruction(state).unwrap();
+            assert_eq!(word.condition, Condition::MemoryReady, "state {}", state);
+            assert!(word.mio_en, "state {}", state);
+        }
+    }
+
+    #[test]
+    fn test_ready_bit_selects_the_following_state() {
+        // J | 2 gives the state after a memory access
+        assert_eq!(microinstruction(33).unwrap().j | 2, 35);
+        assert_eq!(microinstruction(25).unwrap().j | 2, 27);
+        assert_eq!(microinstruction(24).unwrap().j | 2, 26);
+        assert_eq!(microinstruction(29).unwrap().j | 2, 31);
+        assert_eq!(microinstruction(16).unwrap().j | 2, 18);
+        assert_eq!(microinstruction(28).unwrap().j | 2, 30);
+    }
+
+    #[test]
+    fn test_at_most_one_gate_drives_the_bus() {
+        for state in 0..64 {
+            if let Some(word) = microinstruction(state) {
+                let gates = [word.gate_pc, word.gate_mdr, word.gate_alu, word.gate_marmux]
+                    .iter()
+                    .filter(|&&gate| gate)
+                    .count();
+                assert!(gates <= 1, "state {} drives the bus {} times", state, gates);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unimplemented_states() {
+        assert!(microinstruction(8).is_none());
+        assert!(microinstruction(13).is_none());
+        assert!(microinstruction(64).is_none());
+    }
+
+    #[test]
+    fn test_asserted_signal_names() {
+        let signals = microinstruction(18).unwrap().asserted();
+
+        assert_eq!(
+            signals,
+            vec!["LD.MAR", "LD.PC", "GatePC", "PCMUX=PcPlusOne"]
+        );
+    }
+}
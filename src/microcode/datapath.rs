@@ -0,0 +1,219 @@
+use crate::instructions::sign_extend;
+use crate::microcode::control_store::{
+    Addr1Mux, Addr2Mux, Aluk, DrMux, MarMux, Microinstruction, PcMux, Sr1Mux,
+};
+use crate::registers::register::Register;
+use crate::registers::ConditionFlag;
+use crate::Vm;
+
+/// The microarchitectural registers that are not visible to programs. The
+/// general purpose registers, PC and condition codes live in the `Vm` so both
+/// execution engines share the same architectural state.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Datapath {
+    pub mar: u16,
+    pub mdr: u16,
+    pub ir: u16,
+    pub ben: bool,
+}
+
+/// The values on the datapath's wires during one clock cycle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Wires {
+    pub bus: Option<u16>,
+    pub alu: u16,
+    pub adder: u16,
+    pub marmux: u16,
+    pub memory: Option<u16>,
+}
+
+impl Datapath {
+    /// Drives the combinational logic for one cycle and clocks every register
+    /// whose load signal is asserted.
+    pub fn clock(&mut self, vm: &mut Vm, signals: &Microinstruction) -> Wires {
+        let pc = vm.registers[Register::Pc as usize];
+        let sr1 = vm.registers[self.sr1(signals.sr1_mux) as usize];
+
+        let operand_2 = if (self.ir >> 5) & 0x1 == 1 {
+            sign_extend(self.ir & 0x1F, 5)
+        } else {
+            vm.registers[(self.ir & 0x7) as usize]
+        };
+        let alu = match signals.aluk {
+            Aluk::Add => sr1.wrapping_add(operand_2),
+            Aluk::And => sr1 & operand_2,
+            Aluk::Not => !sr1,
+            Aluk::PassA => sr1,
+        };
+
+        let addr1 = match signals.addr1_mux {
+            Addr1Mux::Pc => pc,
+            Addr1Mux::BaseR => sr1,
+        };
+        let addr2 = match signals.addr2_mux {
+            Addr2Mux::Zero => 0,
+            Addr2Mux::Offset6 => sign_extend(self.ir & 0x3F, 6),
+            Addr2Mux::PcOffset9 => sign_extend(self.ir & 0x1FF, 9),
+            Addr2Mux::PcOffset11 => sign_extend(self.ir & 0x7FF, 11),
+        };
+        let adder = addr1.wrapping_add(addr2);
+        let marmux = match signals.mar_mux {
+            MarMux::ZeroExtendIr7To0 => self.ir & 0xFF,
+            MarMux::Adder => adder,
+        };
+
+        let memory = match (signals.mio_en, signals.write) {
+            (true, false) => Some(vm.mem_read(self.mar)),
+            (true, true) => {
+                vm.mem_write(self.mar, self.mdr);
+                None
+            }
+            _ => None,
+        };
+
+        let bus = if signals.gate_pc {
+            Some(pc)
+        } else if signals.gate_mdr {
+            Some(self.mdr)
+        } else if signals.gate_alu {
+            Some(alu)
+        } else if signals.gate_marmux {
+            Some(marmux)
+        } else {
+            None
+        };
+        let bus_value = bus.unwrap_or(0);
+
+        if signals.ld_ben {
+            let nzp = (self.ir >> 9) & 0x7;
+            self.ben = nzp & vm.registers[Register::Cond as usize] != 0;
+        }
+        if signals.ld_mar {
+            self.mar = bus_value;
+        }
+        if signals.ld_mdr {
+            self.mdr = memory.unwrap_or(bus_value);
+        }
+        if signals.ld_ir {
+            self.ir = bus_value;
+        }
+        if signals.ld_reg {
+            let destination = match signals.dr_mux {
+                DrMux::Ir11To9 => (self.ir >> 9) & 0x7,
+                DrMux::R7 => Register::R7 as u16,
+            };
+            vm.registers[destination as usize] = bus_value;
+        }
+        if signals.ld_cc {
+            vm.registers[Register::Cond as usize] = condition_codes(bus_value);
+        }
+        if signals.ld_pc {
+            vm.registers[Register::Pc as usize] = match signals.pc_mux {
+                PcMux::PcPlusOne => pc.wrapping_add(1),
+                PcMux::Bus => bus_value,
+                PcMux::Adder => adder,
+            };
+        }
+
+        Wires {
+            bus,
+            alu,
+            adder,
+            marmux,
+            memory,
+        }
+    }
+
+    fn sr1(&self, sr1_mux: Sr1Mux) -> u16 {
+        match sr1_mux {
+            Sr1Mux::Ir11To9 => (self.ir >> 9) & 0x7,
+            Sr1Mux::Ir8To6 => (self.ir >> 6) & 0x7,
+        }
+    }
+}
+
+fn condition_codes(value: u16) -> u16 {
+    if value == 0 {
+        ConditionFlag::Zro as u16
+    } else if value >> 15 == 1 {
+        ConditionFlag::Neg as u16
+    } else {
+        ConditionFlag::Pos as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::microcode::control_store::microinstruction;
+    use crate::microcode::datapath::Datapath;
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    #[test]
+    fn test_fetch_states_move_pc_through_mar_and_mdr_into_ir() {
+        let mut vm = Vm::new();
+        let mut datapath = Datapath::default();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0x1261);
+
+        let wires = datapath.clock(&mut vm, microinstruction(18).unwrap());
+        assert_eq!(wires.bus, Some(0x3000));
+        assert_eq!(datapath.mar, 0x3000);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3001);
+
+        let wires = datapath.clock(&mut vm, microinstruction(33).unwrap());
+        assert_eq!(wires.bus, None);
+        assert_eq!(wires.memory, Some(0x1261));
+        assert_eq!(datapath.mdr, 0x1261);
+
+        datapath.clock(&mut vm, microinstruction(35).unwrap());
+        assert_eq!(datapath.ir, 0x1261);
+    }
+
+    #[test]
+    fn test_alu_state_writes_register_and_condition_codes() {
+        let mut vm = Vm::new();
+        let mut datapath = Datapath {
+            ir: 0b0001_001_001_1_11110, // ADD R1, R1, #-2
+            ..Datapath::default()
+        };
+        vm.write_to_register(Register::R1, 1);
+
+        let wires = datapath.clock(&mut vm, microinstruction(1).unwrap());
+
+        assert_eq!(wires.alu, 0xFFFF);
+        assert_eq!(vm.registers[Register::R1 as usize], 0xFFFF);
+        assert_eq!(vm.registers[Register::Cond as usize], 0b100);
+    }
+
+    #[test]
+    fn test_store_state_writes_mdr_to_memory() {
+        let mut vm = Vm::new();
+        let mut datapath = Datapath {
+            mar: 0x4000,
+            mdr: 0xBEEF,
+            ..Datapath::default()
+        };
+
+        datapath.clock(&mut vm, microinstruction(16).unwrap());
+
+        assert_eq!(vm.memory[0x4000], 0xBEEF);
+    }
+
+    #[test]
+    fn test_decode_state_computes_branch_enable() {
+        let mut vm = Vm::new();
+        let mut datapath = Datapath {
+            ir: 0b0000_010_000000011, // BRz #3
+            ..Datapath::default()
+        };
+
+        vm.write_to_register(Register::Cond, 0b010);
+        datapath.clock(&mut vm, microinstruction(32).unwrap());
+        assert!(datapath.ben);
+
+        vm.write_to_register(Register::Cond, 0b001);
+        datapath.clock(&mut vm, microinstruction(32).unwrap());
+        assert!(!datapath.ben);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::instructions::trap::trap;
+use crate::limits::{self, StopReason};
+use crate::microcode::control_store::{microinstruction, Condition, Microinstruction};
+use crate::microcode::datapath::{Datapath, Wires};
+use crate::registers::register::Register;
+use crate::Vm;
+use std::fmt;
+
+pub mod control_store;
+pub mod datapath;
+
+const FETCH_STATE: u8 = 18;
+/// PC <- MDR would enter the OS trap routine. There is no OS image, so the
+/// engine runs the native routine from `instructions::trap` here instead.
+const TRAP_DISPATCH_STATE: u8 = 30;
+
+/// Everything that happened in one clock cycle, for inspection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleRecord {
+    pub cycle: u64,
+    pub state: u8,
+    pub next_state: u8,
+    pub signals: Microinstruction,
+    pub wires: Wires,
+    pub datapath: Datapath,
+    pub pc: u16,
+    pub memory_ready: bool,
+}
+
+impl fmt::Display for CycleRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bus = match self.wires.bus {
+            Some(value) => format!("x{:04X}", value),
+            None => "-----".to_string(),
+        };
+        write!(
+            f,
+            "{:>8}  state {:>2} -> {:>2}  PC=x{:04X} MAR=x{:04X} MDR=x{:04X} IR=x{:04X} BEN={} BUS={}  {}",
+            self.cycle,
+            self.state,
+            self.next_state,
+            self.pc,
+            self.datapath.mar,
+            self.datapath.mdr,
+            self.datapath.ir,
+            self.datapath.ben as u8,
+            bus,
+            self.signals.asserted().join(" ")
+        )?;
+        if !self.memory_ready {
+            write!(f, "  (waiting on memory)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Executes programs one microstate at a time on the Patt & Patel LC-3
+/// datapath, as an alternative to the per-opcode functions in `instructions`.
+pub struct MicroEngine {
+    pub datapath: Datapath,
+    pub state: u8,
+    pub cycles: u64,
+    pub halted: bool,
+    memory_latency: u32,
+    waited: u32,
+    trace: bool,
+}
+
+impl MicroEngine {
+    pub fn new(memory_latency: u32) -> MicroEngine {
+        Self {
+            datapath: Datapath::default(),
+            state: FETCH_STATE,
+            cycles: 0,
+            memory_latency,
+            waited: 0,
+            halted: false,
+            trace: false,
+        }
+    }
+
+    /// Prints every cycle to stderr while running.
+    pub fn with_trace(mut self, trace: bool) -> MicroEngine {
+        self.trace = trace;
+        self
+    }
+
+    /// Clocks the machine once. Returns `None` once the program has halted.
+    pub fn step_state(&mut self, vm: &mut Vm) -> Option<CycleRecord> {
+        if self.halted {
+            return None;
+        }
+
+        let Some(signals) = microinstruction(self.state) else {
+            // RTI and the reserved opcode lead to states this model omits
+            self.halted = true;
+            return None;
+        };
+
+        let memory_ready = !signals.mio_en || self.waited >= self.memory_latency;
+        let wires = if signals.mio_en && !memory_ready {
+            self.waited += 1;
+            Wires::default()
+        } else {
+            self.waited = 0;
+            if self.state == TRAP_DISPATCH_STATE {
+                self.halted = !trap(vm, self.datapath.ir);
+                Wires::default()
+            } else {
+                self.datapath.clock(vm, signals)
+            }
+        };
+
+        let state = self.state;
+        self.state = self.next_state(signals, memory_ready);
+        self.cycles += 1;
+
+        let record = CycleRecord {
+            cycle: self.cycles,
+            state,
+            next_state: self.state,
+            signals: *signals,
+            wires,
+            datapath: self.datapath,
+            pc: vm.registers[Register::Pc as usize],
+            memory_ready,
+        };
+        if self.trace {
+            eprintln!("{}", record);
+        }
+        Some(record)
+    }
+
+    /// Runs microstates until the next instruction is about to be fetched.
+    /// Returns `false` once the program has halted.
+    pub fn step_instruction(&mut self, vm: &mut Vm) -> bool {
+        loop {
+            if self.step_state(vm).is_none() {
+                return false;
+            }
+            if self.state == FETCH_STATE {
+                return !self.halted;
+            }
+        }
+    }
+
+    /// Runs until the program halts or breaks one of `vm.limits`.
+    pub fn run(&mut self, vm: &mut Vm) -> StopReason {
+        if vm.limits.enabled() {
+            return limits::run_stepping(vm, |_| None, |vm| self.step_instruction(vm));
+        }
+        while self.step_instruction(vm) {}
+        vm.stop_reason.take().unwrap_or(StopReason::Halted)
+    }
+
+    fn next_state(&self, signals: &Microinstruction, memory_ready: bool) -> u8 {
+        if signals.ird {
+            return (self.datapath.ir >> 12) as u8;
+        }
+
+        match signals.condition {
+            Condition::Unconditional => signals.j,
+            Condition::MemoryReady => signals.j | ((memory_ready as u8) << 1),
+            Condition::Branch => signals.j | ((self.datapath.ben as u8) << 2),
+            Condition::AddressingMode => signals.j | ((self.datapath.ir >> 11) & 0x1) as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::limits::StopReason;
+    use crate::microcode::MicroEngine;
+    use crate::registers::register::Register;
+    use crate::test_support::seeded_vms;
+    use crate::Vm;
+
+    fn run_one(instruction: u16, seed: u64) -> (Vm, Vm) {
+        let (mut interpreter, mut micro) = seeded_vms(seed);
+        for vm in [&mut interpreter, &mut micro] {
+            vm.write_to_register(Register::Pc, 0x3000);
+            vm.mem_write(0x3000, instruction);
+        }
+
+        interpreter.fetch_decode_execute();
+        assert!(MicroEngine::new(0).step_instruction(&mut micro));
+        (interpreter, micro)
+    }
+
+    fn assert_same_architectural_state(interpreter: &Vm, micro: &Vm, instruction: u16) {
+        assert_eq!(
+            interpreter.registers, micro.registers,
+            "registers differ for x{:04X}",
+            instruction
+        );
+        assert!(
+            interpreter.memory[..0xFE00] == micro.memory[..0xFE00],
+            "memory differs for x{:04X}",
+            instruction
+        );
+    }
+
+    // ========== Equivalence With the Interpreter ==========
+
+    #[test]
+    fn test_every_non_trap_opcode_matches_the_interpreter() {
+        let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+        for opcode in [0u16, 1, 2, 3, 4, 5, 6, 7, 9, 10, 11, 12, 14] {
+            for _ in 0..64 {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                let instruction = (opcode << 12) | (seed >> 48) as u16 & 0x0FFF;
+                let (interpreter, micro) = run_one(instruction, seed | 1);
+                assert_same_architectural_state(&interpreter, &micro, instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn test_trap_saves_return_address_like_the_interpreter() {
+        let (interpreter, micro) = run_one(0xF021, 7); // OUT
+
+        assert_same_architectural_state(&interpreter, &micro, 0xF021);
+        assert_eq!(micro.registers[Register::R7 as usize], 0x3001);
+    }
+
+    // ========== Microsequencing ==========
+
+    #[test]
+    fn test_add_visits_fetch_decode_and_state_1() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0x1261); // ADD R1, R1, #1
+        let mut engine = MicroEngine::new(0);
+
+        let mut states = Vec::new();
+        loop {
+            let record = engine.step_state(&mut vm).unwrap();
+            states.push(record.state);
+            if record.next_state == 18 {
+                break;
+            }
+        }
+
+        assert_eq!(states, vec![18, 33, 35, 32, 1]);
+        assert_eq!(vm.registers[Register::R1 as usize], 1);
+    }
+
+    #[test]
+    fn test_ldi_visits_both_memory_reads() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0b1010_010_000000001); // LDI R2, #1
+        vm.mem_write(0x3002, 0x4000);
+        vm.mem_write(0x4000, 0x8001);
+        let mut engine = MicroEngine::new(0);
+
+        let mut states = Vec::new();
+        while states.last() != Some(&27) {
+            states.push(engine.step_state(&mut vm).unwrap().state);
+        }
+
+        assert_eq!(states, vec![18, 33, 35, 32, 10, 24, 26, 25, 27]);
+        assert_eq!(vm.registers[Register::R2 as usize], 0x8001);
+        assert_eq!(vm.registers[Register::Cond as usize], 0b100);
+    }
+
+    #[test]
+    fn test_memory_latency_holds_memory_states() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0x1261);
+        let mut engine = MicroEngine::new(2);
+
+        assert!(engine.step_instruction(&mut vm));
+
+        // 5 states plus two wait cycles in state 33
+        assert_eq!(engine.cycles, 7);
+    }
+
+    #[test]
+    fn test_halt_stops_the_engine() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0x1021); // ADD R0, R0, #1
+        vm.mem_write(0x3001, 0xF025); // HALT
+        let mut engine = MicroEngine::new(0);
+
+        engine.run(&mut vm);
+
+        assert!(engine.halted);
+        assert_eq!(vm.registers[Register::R0 as usize], 1);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3002);
+    }
+
+    #[test]
+    fn test_instruction_limit_stops_the_engine() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0b0000_111_111111111); // BRnzp #-1
+        vm.limits.max_instructions = Some(100);
+        let mut engine = MicroEngine::new(0);
+
+        assert_eq!(engine.run(&mut vm), StopReason::InstructionLimit);
+        assert!(!engine.halted);
+    }
+
+    #[test]
+    fn test_reserved_opcode_stops_the_engine() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0xD000);
+        let mut engine = MicroEngine::new(0);
+
+        assert!(!engine.step_instruction(&mut vm));
+        assert!(engine.halted);
+    }
+}
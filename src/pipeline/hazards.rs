@@ -0,0 +1,141 @@
+use crate::instructions::opcodes::Opcode;
+use crate::instructions::trap::{TRAP_GETC, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP};
+
+/// Index of the condition codes in the dependency tracker, after R0-R7.
+pub const CONDITION_CODES: usize = 8;
+
+/// What an instruction reads and writes, as seen by the hazard logic.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Operands {
+    /// Registers (0-7) and `CONDITION_CODES` read in decode.
+    pub sources: Vec<usize>,
+    /// Registers (0-7) and `CONDITION_CODES` written back.
+    pub destinations: Vec<usize>,
+    /// The result is only available after the memory stage.
+    pub loads: bool,
+    /// The instruction spends two cycles in the memory stage.
+    pub indirect: bool,
+}
+
+pub fn operands(opcode: Opcode, instruction: u16) -> Operands {
+    let dr = ((instruction >> 9) & 0x7) as usize;
+    let sr1 = ((instruction >> 6) & 0x7) as usize;
+    let sr2 = (instruction & 0x7) as usize;
+    let immediate = (instruction >> 5) & 0x1 == 1;
+
+    let mut operands = Operands::default();
+    match opcode {
+        Opcode::Add | Opcode::And => {
+            operands.sources.push(sr1);
+            if !immediate {
+                operands.sources.push(sr2);
+            }
+            operands.destinations = vec![dr, CONDITION_CODES];
+        }
+        Opcode::Not => {
+            operands.sources.push(sr1);
+            operands.destinations = vec![dr, CONDITION_CODES];
+        }
+        Opcode::Lea => operands.destinations = vec![dr, CONDITION_CODES],
+        Opcode::Br => operands.sources.push(CONDITION_CODES),
+        Opcode::Jmp => operands.sources.push(sr1),
+        Opcode::Jsr => {
+            if (instruction >> 11) & 0x1 == 0 {
+                operands.sources.push(sr1);
+            }
+            operands.destinations.push(7);
+        }
+        Opcode::Ld | Opcode::Ldi | Opcode::Ldr => {
+            if opcode == Opcode::Ldr {
+                operands.sources.push(sr1);
+            }
+            operands.destinations = vec![dr, CONDITION_CODES];
+            operands.loads = true;
+            operands.indirect = opcode == Opcode::Ldi;
+        }
+        Opcode::St | Opcode::Sti | Opcode::Str => {
+            operands.sources.push(dr);
+            if opcode == Opcode::Str {
+                operands.sources.push(sr1);
+            }
+            operands.indirect = opcode == Opcode::Sti;
+        }
+        Opcode::Trap => {
+            match instruction & 0xFF {
+                TRAP_OUT | TRAP_PUTS | TRAP_PUTSP => operands.sources.push(0),
+                TRAP_GETC | TRAP_IN => operands.destinations = vec![0, CONDITION_CODES],
+                _ => {}
+            }
+            operands.destinations.push(7);
+            operands.loads = true;
+        }
+        Opcode::Rti | Opcode::Res => {}
+    }
+
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instructions::opcodes::Opcode;
+    use crate::pipeline::hazards::{operands, CONDITION_CODES};
+
+    #[test]
+    fn test_add_register_mode_reads_both_sources() {
+        // ADD R1, R2, R3
+        let operands = operands(Opcode::Add, 0b0001_001_010_0_00_011);
+
+        assert_eq!(operands.sources, vec![2, 3]);
+        assert_eq!(operands.destinations, vec![1, CONDITION_CODES]);
+        assert!(!operands.loads);
+    }
+
+    #[test]
+    fn test_add_immediate_mode_ignores_sr2_field() {
+        // ADD R1, R2, #3
+        let operands = operands(Opcode::Add, 0b0001_001_010_1_00011);
+
+        assert_eq!(operands.sources, vec![2]);
+    }
+
+    #[test]
+    fn test_branch_reads_condition_codes() {
+        let operands = operands(Opcode::Br, 0b0000_010_000000011);
+
+        assert_eq!(operands.sources, vec![CONDITION_CODES]);
+        assert!(operands.destinations.is_empty());
+    }
+
+    #[test]
+    fn test_loads_and_stores() {
+        let ldr = operands(Opcode::Ldr, 0b0110_011_110_000001);
+        assert_eq!(ldr.sources, vec![6]);
+        assert_eq!(ldr.destinations, vec![3, CONDITION_CODES]);
+        assert!(ldr.loads);
+
+        let ldi = operands(Opcode::Ldi, 0b1010_011_000000001);
+        assert!(ldi.indirect);
+
+        let str = operands(Opcode::Str, 0b0111_011_110_000001);
+        assert_eq!(str.sources, vec![3, 6]);
+        assert!(str.destinations.is_empty());
+    }
+
+    #[test]
+    fn test_jsrr_reads_base_and_writes_r7() {
+        let operands = operands(Opcode::Jsr, 0b0100_0_00_011_000000);
+
+        assert_eq!(operands.sources, vec![3]);
+        assert_eq!(operands.destinations, vec![7]);
+    }
+
+    #[test]
+    fn test_trap_io_registers() {
+        assert_eq!(operands(Opcode::Trap, 0xF021).sources, vec![0]);
+        assert_eq!(
+            operands(Opcode::Trap, 0xF020).destinations,
+            vec![0, CONDITION_CODES, 7]
+        );
+        assert_eq!(operands(Opcode::Trap, 0xF025).destinations, vec![7]);
+    }
+}
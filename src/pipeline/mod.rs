@@ -0,0 +1,413 @@
+use crate::instructions::opcodes::Opcode;
+use crate::pipeline::hazards::{operands, CONDITION_CODES};
+use crate::pipeline::prediction::{BranchPrediction, Predictor};
+use std::fmt::Write;
+
+pub mod hazards;
+pub mod prediction;
+
+/// Cycles lost when a branch or jump is resolved in execute.
+const EXECUTE_RESOLUTION_PENALTY: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    pub forwarding: bool,
+    pub prediction: BranchPrediction,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            forwarding: true,
+            prediction: BranchPrediction::NotTaken,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PipelineStats {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub data_stalls: u64,
+    pub control_stalls: u64,
+    pub structural_stalls: u64,
+    pub flushes: u64,
+    pub branches: u64,
+    pub mispredictions: u64,
+    /// How often a read of R0-R7 (0-7) or the condition codes (8) stalled.
+    pub hazards: [u64; 9],
+}
+
+/// A timing model of a classic fetch/decode/execute/memory/writeback
+/// pipeline. The interpreter still executes every instruction, so the
+/// architectural results are unchanged; the model is told about each
+/// instruction in program order and works out when it would have issued.
+///
+/// Register operands are read in decode and written back in writeback, with
+/// a split-cycle register file. Branches resolve in execute, `JSR` targets in
+/// decode, and `TRAP` drains the pipeline.
+pub struct Pipeline {
+    config: PipelineConfig,
+    predictor: Predictor,
+    stats: PipelineStats,
+    last_decode: u64,
+    last_memory_end: u64,
+    next_decode_floor: u64,
+    ready: [u64; 9],
+}
+
+impl Pipeline {
+    pub fn new(config: PipelineConfig) -> Pipeline {
+        Self {
+            config,
+            predictor: Predictor::new(config.prediction),
+            stats: PipelineStats::default(),
+            last_decode: 1,
+            last_memory_end: 0,
+            next_decode_floor: 0,
+            ready: [0; 9],
+        }
+    }
+
+    pub fn stats(&self) -> &PipelineStats {
+        &self.stats
+    }
+
+    /// Accounts for the instruction at `address`, given the condition codes
+    /// it sees when it executes.
+    pub fn issue(&mut self, address: u16, instruction: u16, opcode: Opcode, cond: u16) {
+        let operands = operands(opcode, instruction);
+
+        let base = self.last_decode + 1;
+        let control_bound = base.max(self.next_decode_floor);
+        let structural_bound = control_bound.max(self.last_memory_end.saturating_sub(1));
+        let mut decode = structural_bound;
+        for &source in &operands.sources {
+            if self.ready[source] > decode {
+                decode = self.ready[source];
+            }
+        }
+        for &source in &operands.sources {
+            if self.ready[source] > structural_bound && self.ready[source] == decode {
+                self.stats.hazards[source] += 1;
+            }
+        }
+
+        self.stats.control_stalls += control_bound - base;
+        self.stats.structural_stalls += structural_bound - control_bound;
+        self.stats.data_stalls += decode - structural_bound;
+
+        let execute = decode + 1;
+        let memory_end = execute + 1 + operands.indirect as u64;
+        let writeback = memory_end + 1;
+
+        for &destination in &operands.destinations {
+            self.ready[destination] = match (self.config.forwarding, operands.loads) {
+                (true, false) => execute,
+                (true, true) => memory_end,
+                (false, _) => writeback,
+            };
+        }
+
+        self.next_decode_floor = self.control_floor(address, instruction, opcode, cond, decode);
+        if opcode == Opcode::Trap {
+            self.next_decode_floor = writeback + 1;
+            self.stats.flushes += 1;
+        }
+
+        self.last_decode = decode;
+        self.last_memory_end = memory_end;
+        self.stats.instructions += 1;
+        self.stats.cycles = self.stats.cycles.max(writeback);
+    }
+
+    /// The earliest cycle the next instruction can be decoded, given how
+    /// this one redirects fetch.
+    fn control_floor(
+        &mut self,
+        address: u16,
+        instruction: u16,
+        opcode: Opcode,
+        cond: u16,
+        decode: u64,
+    ) -> u64 {
+        let next = decode + 1;
+        match opcode {
+            Opcode::Br => {
+                let nzp = (instruction >> 9) & 0x7;
+                match nzp {
+                    0 => next,
+                    0b111 => next + self.predictor.taken_redirect_penalty(),
+                    _ => {
+                        let taken = nzp & cond != 0;
+                        let backward = (instruction >> 8) & 0x1 == 1;
+                        let predicted = self.predictor.predict(address, backward);
+                        self.predictor.update(address, taken);
+                        self.stats.branches += 1;
+
+                        if predicted != taken {
+                            self.stats.mispredictions += 1;
+                            self.stats.flushes += 1;
+                            next + EXECUTE_RESOLUTION_PENALTY
+                        } else if taken {
+                            next + self.predictor.taken_redirect_penalty()
+                        } else {
+                            next
+                        }
+                    }
+                }
+            }
+            Opcode::Jsr if (instruction >> 11) & 0x1 == 1 => {
+                next + self.predictor.taken_redirect_penalty()
+            }
+            Opcode::Jsr | Opcode::Jmp => {
+                self.stats.flushes += 1;
+                next + EXECUTE_RESOLUTION_PENALTY
+            }
+            _ => next,
+        }
+    }
+
+    pub fn cpi(&self) -> f64 {
+        match self.stats.instructions {
+            0 => 0.0,
+            instructions => self.stats.cycles as f64 / instructions as f64,
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let stats = self.stats();
+        let mut report = String::new();
+
+        writeln!(report, "--- Pipeline ---").unwrap();
+        writeln!(
+            report,
+            "Forwarding: {}, branch prediction: {:?}",
+            if self.config.forwarding { "on" } else { "off" },
+            self.config.prediction
+        )
+        .unwrap();
+        writeln!(report, "Instructions:      {}", stats.instructions).unwrap();
+        writeln!(report, "Cycles:            {}", stats.cycles).unwrap();
+        writeln!(report, "Effective CPI:     {:.2}", self.cpi()).unwrap();
+        writeln!(report, "Data stalls:       {}", stats.data_stalls).unwrap();
+        writeln!(report, "Control stalls:    {}", stats.control_stalls).unwrap();
+        writeln!(report, "Structural stalls: {}", stats.structural_stalls).unwrap();
+        writeln!(report, "Flushes:           {}", stats.flushes).unwrap();
+        writeln!(
+            report,
+            "Branches:          {} ({} mispredicted)",
+            stats.branches, stats.mispredictions
+        )
+        .unwrap();
+
+        let hazards: Vec<String> = stats
+            .hazards
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| match index {
+                CONDITION_CODES => format!("CC={}", count),
+                register => format!("R{}={}", register, count),
+            })
+            .collect();
+        if !hazards.is_empty() {
+            writeln!(report, "Stalling reads:    {}", hazards.join(" ")).unwrap();
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instructions::opcodes::Opcode;
+    use crate::pipeline::prediction::BranchPrediction;
+    use crate::pipeline::{Pipeline, PipelineConfig};
+    use crate::registers::register::Register;
+    use crate::Vm;
+
+    fn issue_all(pipeline: &mut Pipeline, program: &[(u16, u16)]) {
+        for (index, &(instruction, cond)) in program.iter().enumerate() {
+            let opcode = Opcode::get(instruction >> 12).unwrap();
+            pipeline.issue(0x3000 + index as u16, instruction, opcode, cond);
+        }
+    }
+
+    const ADD_R1_R1_1: u16 = 0b0001_001_001_1_00001;
+    const ADD_R2_R2_1: u16 = 0b0001_010_010_1_00001;
+    const ADD_R3_R3_1: u16 = 0b0001_011_011_1_00001;
+
+    // ========== Data Hazards ==========
+
+    #[test]
+    fn test_independent_instructions_issue_every_cycle() {
+        let mut pipeline = Pipeline::new(PipelineConfig::default());
+
+        issue_all(
+            &mut pipeline,
+            &[(ADD_R1_R1_1, 0), (ADD_R2_R2_1, 0), (ADD_R3_R3_1, 0)],
+        );
+
+        assert_eq!(pipeline.stats().cycles, 3 + 4);
+        assert_eq!(pipeline.stats().data_stalls, 0);
+    }
+
+    #[test]
+    fn test_forwarding_hides_alu_dependencies() {
+        let mut pipeline = Pipeline::new(PipelineConfig::default());
+
+        issue_all(&mut pipeline, &[(ADD_R1_R1_1, 0), (ADD_R1_R1_1, 0)]);
+
+        assert_eq!(pipeline.stats().data_stalls, 0);
+    }
+
+    #[test]
+    fn test_without_forwarding_dependent_instruction_waits_for_writeback() {
+        let mut pipeline = Pipeline::new(PipelineConfig {
+            forwarding: false,
+            ..PipelineConfig::default()
+        });
+
+        issue_all(&mut pipeline, &[(ADD_R1_R1_1, 0), (ADD_R1_R1_1, 0)]);
+
+        assert_eq!(pipeline.stats().data_stalls, 2);
+        assert_eq!(pipeline.stats().hazards[1], 1);
+        assert_eq!(pipeline.stats().cycles, 2 + 4 + 2);
+    }
+
+    #[test]
+    fn test_load_use_costs_one_stall_with_forwarding() {
+        let mut pipeline = Pipeline::new(PipelineConfig::default());
+
+        // LDR R1, R6, #0 then ADD R1, R1, #1
+        issue_all(
+            &mut pipeline,
+            &[(0b0110_001_110_000000, 0), (ADD_R1_R1_1, 0)],
+        );
+
+        assert_eq!(pipeline.stats().data_stalls, 1);
+    }
+
+    #[test]
+    fn test_branch_waits_for_condition_codes_without_forwarding() {
+        let mut pipeline = Pipeline::new(PipelineConfig {
+            forwarding: false,
+            ..PipelineConfig::default()
+        });
+
+        // ADD R1, R1, #1 then BRz (not taken)
+        issue_all(
+            &mut pipeline,
+            &[(ADD_R1_R1_1, 0), (0b0000_010_000000011, 0b001)],
+        );
+
+        assert_eq!(pipeline.stats().hazards[8], 1);
+    }
+
+    #[test]
+    fn test_indirect_access_occupies_memory_stage_twice() {
+        let mut pipeline = Pipeline::new(PipelineConfig::default());
+
+        // LDI R1, #0 followed by an independent ADD
+        issue_all(
+            &mut pipeline,
+            &[(0b1010_001_000000000, 0), (ADD_R2_R2_1, 0)],
+        );
+
+        assert_eq!(pipeline.stats().structural_stalls, 1);
+    }
+
+    // ========== Control Hazards ==========
+
+    #[test]
+    fn test_mispredicted_branch_flushes_two_instructions() {
+        let mut pipeline = Pipeline::new(PipelineConfig::default());
+
+        // BRz taken under predict-not-taken
+        issue_all(
+            &mut pipeline,
+            &[(0b0000_010_000000011, 0b010), (ADD_R1_R1_1, 0)],
+        );
+
+        assert_eq!(pipeline.stats().mispredictions, 1);
+        assert_eq!(pipeline.stats().flushes, 1);
+        assert_eq!(pipeline.stats().control_stalls, 2);
+    }
+
+    #[test]
+    fn test_predict_taken_pays_one_bubble_when_right() {
+        let mut pipeline = Pipeline::new(PipelineConfig {
+            prediction: BranchPrediction::Taken,
+            ..PipelineConfig::default()
+        });
+
+        issue_all(
+            &mut pipeline,
+            &[(0b0000_010_000000011, 0b010), (ADD_R1_R1_1, 0)],
+        );
+
+        assert_eq!(pipeline.stats().mispredictions, 0);
+        assert_eq!(pipeline.stats().control_stalls, 1);
+    }
+
+    #[test]
+    fn test_two_bit_predictor_learns_a_loop() {
+        let mut pipeline = Pipeline::new(PipelineConfig {
+            prediction: BranchPrediction::TwoBit,
+            ..PipelineConfig::default()
+        });
+
+        // BRp #-1 taken ten times
+        for _ in 0..10 {
+            pipeline.issue(0x3005, 0b0000_001_111111111, Opcode::Br, 0b001);
+        }
+
+        assert_eq!(pipeline.stats().branches, 10);
+        assert_eq!(pipeline.stats().mispredictions, 1);
+    }
+
+    #[test]
+    fn test_register_jump_resolves_in_execute() {
+        let mut pipeline = Pipeline::new(PipelineConfig::default());
+
+        // RET then ADD
+        issue_all(
+            &mut pipeline,
+            &[(0b1100_000_111_000000, 0), (ADD_R1_R1_1, 0)],
+        );
+
+        assert_eq!(pipeline.stats().control_stalls, 2);
+        assert_eq!(pipeline.stats().flushes, 1);
+    }
+
+    // ========== Architectural Results ==========
+
+    #[test]
+    fn test_pipeline_model_does_not_change_results() {
+        let program = [
+            0b0101_001_001_1_00000, // AND R1, R1, #0
+            0b0001_001_001_1_00101, // ADD R1, R1, #5
+            0b0001_010_010_1_00011, // ADD R2, R2, #3
+            0b0001_001_001_1_11111, // ADD R1, R1, #-1
+            0b0000_001_111111101,   // BRp #-3
+            0xF025,                 // HALT
+        ];
+        let mut plain = Vm::new();
+        let mut pipelined = Vm::new();
+        pipelined.pipeline = Some(Pipeline::new(PipelineConfig::default()));
+        for vm in [&mut plain, &mut pipelined] {
+            for (offset, &word) in program.iter().enumerate() {
+                vm.mem_write(0x3000 + offset as u16, word);
+            }
+            vm.write_to_register(Register::Pc, 0x3000);
+            while vm.fetch_decode_execute() {}
+        }
+
+        assert_eq!(plain.registers, pipelined.registers);
+        assert_eq!(plain.registers[Register::R2 as usize], 15);
+        let stats = pipelined.pipeline.as_ref().unwrap().stats();
+        assert_eq!(stats.instructions, 2 + 3 * 5 + 1);
+        assert_eq!(stats.mispredictions, 4);
+    }
+}
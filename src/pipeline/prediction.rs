@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// How the fetch stage guesses the direction of a conditional branch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BranchPrediction {
+    /// Keep fetching sequentially; every taken branch is a misprediction.
+    NotTaken,
+    /// Redirect fetch to the target once it is computed in decode.
+    Taken,
+    /// Backward branches are predicted taken, forward ones not taken.
+    BackwardTaken,
+    /// Two-bit saturating counters per branch, with a target buffer so a
+    /// correct taken prediction costs nothing.
+    TwoBit,
+}
+
+impl FromStr for BranchPrediction {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "not-taken" => Ok(BranchPrediction::NotTaken),
+            "taken" => Ok(BranchPrediction::Taken),
+            "btfn" => Ok(BranchPrediction::BackwardTaken),
+            "two-bit" => Ok(BranchPrediction::TwoBit),
+            _ => Err(format!(
+                "unknown branch prediction policy '{}' (expected not-taken, taken, btfn or two-bit)",
+                name
+            )),
+        }
+    }
+}
+
+pub struct Predictor {
+    policy: BranchPrediction,
+    counters: HashMap<u16, u8>,
+}
+
+impl Predictor {
+    pub fn new(policy: BranchPrediction) -> Predictor {
+        Self {
+            policy,
+            counters: HashMap::new(),
+        }
+    }
+
+    pub fn predict(&self, address: u16, backward: bool) -> bool {
+        match self.policy {
+            BranchPrediction::NotTaken => false,
+            BranchPrediction::Taken => true,
+            BranchPrediction::BackwardTaken => backward,
+            BranchPrediction::TwoBit => self.counters.get(&address).copied().unwrap_or(1) >= 2,
+        }
+    }
+
+    pub fn update(&mut self, address: u16, taken: bool) {
+        if self.policy != BranchPrediction::TwoBit {
+            return;
+        }
+        let counter = self.counters.entry(address).or_insert(1);
+        *counter = if taken {
+            (*counter + 1).min(3)
+        } else {
+            counter.saturating_sub(1)
+        };
+    }
+
+    /// Bubbles inserted when a branch was predicted correctly as taken:
+    /// static policies only know the target once the branch is decoded.
+    pub fn taken_redirect_penalty(&self) -> u64 {
+        match self.policy {
+            BranchPrediction::TwoBit => 0,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::prediction::{BranchPrediction, Predictor};
+
+    #[test]
+    fn test_static_policies() {
+        assert!(!Predictor::new(BranchPrediction::NotTaken).predict(0x3000, true));
+        assert!(Predictor::new(BranchPrediction::Taken).predict(0x3000, false));
+
+        let btfn = Predictor::new(BranchPrediction::BackwardTaken);
+        assert!(btfn.predict(0x3000, true));
+        assert!(!btfn.predict(0x3000, false));
+    }
+
+    #[test]
+    fn test_two_bit_counter_needs_two_misses_to_flip() {
+        let mut predictor = Predictor::new(BranchPrediction::TwoBit);
+        assert!(!predictor.predict(0x3000, true));
+
+        predictor.update(0x3000, true);
+        predictor.update(0x3000, true);
+        assert!(predictor.predict(0x3000, false));
+
+        predictor.update(0x3000, false);
+        assert!(predictor.predict(0x3000, false));
+        predictor.update(0x3000, false);
+        assert!(!predictor.predict(0x3000, false));
+    }
+
+    #[test]
+    fn test_counters_are_per_branch() {
+        let mut predictor = Predictor::new(BranchPrediction::TwoBit);
+
+        predictor.update(0x3000, true);
+
+        assert!(predictor.predict(0x3000, false));
+        assert!(!predictor.predict(0x3001, false));
+    }
+
+    #[test]
+    fn test_parse_policy_names() {
+        assert_eq!("btfn".parse(), Ok(BranchPrediction::BackwardTaken));
+        assert_eq!("two-bit".parse(), Ok(BranchPrediction::TwoBit));
+        assert!("sometimes".parse::<BranchPrediction>().is_err());
+    }
+}
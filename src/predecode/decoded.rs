@@ -0,0 +1,132 @@
+use crate::instructions::sign_extend;
+
+/// An opcode with its addressing mode already resolved, so dispatch does not
+/// have to look at mode bits again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Br,
+    AddRegister,
+    AddImmediate,
+    AndRegister,
+    AndImmediate,
+    Not,
+    Ld,
+    Ldi,
+    Ldr,
+    Lea,
+    St,
+    Sti,
+    Str,
+    Jmp,
+    Jsr,
+    Jsrr,
+    Trap,
+    /// `RTI` and the reserved opcode, which stop the machine.
+    Stop,
+}
+
+/// An instruction with every field extracted and sign-extended once.
+///
+/// `dr` is the destination register, the source register of a store, or the
+/// `nzp` mask of a branch. `sr` is the first source or base register.
+/// `operand` is the second source register, the sign-extended immediate or
+/// offset, or the whole instruction word for `TRAP`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    pub operation: Operation,
+    pub dr: u8,
+    pub sr: u8,
+    pub operand: u16,
+}
+
+impl Decoded {
+    pub fn decode(instruction: u16) -> Decoded {
+        let dr = ((instruction >> 9) & 0x7) as u8;
+        let sr = ((instruction >> 6) & 0x7) as u8;
+        let immediate = (instruction >> 5) & 0x1 == 1;
+        let offset_9 = sign_extend(instruction & 0x1FF, 9);
+        let offset_6 = sign_extend(instruction & 0x3F, 6);
+
+        let (operation, operand) = match instruction >> 12 {
+            0x0 => (Operation::Br, offset_9),
+            0x1 if immediate => (Operation::AddImmediate, sign_extend(instruction & 0x1F, 5)),
+            0x1 => (Operation::AddRegister, instruction & 0x7),
+            0x2 => (Operation::Ld, offset_9),
+            0x3 => (Operation::St, offset_9),
+            0x4 if (instruction >> 11) & 0x1 == 1 => {
+                (Operation::Jsr, sign_extend(instruction & 0x7FF, 11))
+            }
+            0x4 => (Operation::Jsrr, 0),
+            0x5 if immediate => (Operation::AndImmediate, sign_extend(instruction & 0x1F, 5)),
+            0x5 => (Operation::AndRegister, instruction & 0x7),
+            0x6 => (Operation::Ldr, offset_6),
+            0x7 => (Operation::Str, offset_6),
+            0x9 => (Operation::Not, 0),
+            0xA => (Operation::Ldi, offset_9),
+            0xB => (Operation::Sti, offset_9),
+            0xC => (Operation::Jmp, 0),
+            0xE => (Operation::Lea, offset_9),
+            0xF => (Operation::Trap, instruction),
+            _ => (Operation::Stop, 0),
+        };
+
+        Decoded {
+            operation,
+            dr,
+            sr,
+            operand,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::predecode::decoded::{Decoded, Operation};
+
+    #[test]
+    fn test_add_modes_are_resolved() {
+        // ADD R1, R2, #-1
+        let immediate = Decoded::decode(0b0001_001_010_1_11111);
+        assert_eq!(immediate.operation, Operation::AddImmediate);
+        assert_eq!((immediate.dr, immediate.sr), (1, 2));
+        assert_eq!(immediate.operand, 0xFFFF);
+
+        // ADD R1, R2, R3
+        let register = Decoded::decode(0b0001_001_010_0_00_011);
+        assert_eq!(register.operation, Operation::AddRegister);
+        assert_eq!(register.operand, 3);
+    }
+
+    #[test]
+    fn test_offsets_are_sign_extended() {
+        // BRnz #-3
+        let branch = Decoded::decode(0b0000_110_111111101);
+        assert_eq!(branch.dr, 0b110);
+        assert_eq!(branch.operand, 0xFFFD);
+
+        // LDR R0, R6, #-32
+        let ldr = Decoded::decode(0b0110_000_110_100000);
+        assert_eq!(ldr.operand, 0xFFE0);
+
+        // JSR #-1024
+        let jsr = Decoded::decode(0b0100_1_10000000000);
+        assert_eq!(jsr.operation, Operation::Jsr);
+        assert_eq!(jsr.operand, 0xFC00);
+    }
+
+    #[test]
+    fn test_jsrr_and_trap() {
+        assert_eq!(
+            Decoded::decode(0b0100_0_00_101_000000).operation,
+            Operation::Jsrr
+        );
+        assert_eq!(Decoded::decode(0b0100_0_00_101_000000).sr, 5);
+        assert_eq!(Decoded::decode(0xF025).operand, 0xF025);
+    }
+
+    #[test]
+    fn test_rti_and_reserved_stop() {
+        assert_eq!(Decoded::decode(0x8000).operation, Operation::Stop);
+        assert_eq!(Decoded::decode(0xD000).operation, Operation::Stop);
+    }
+}
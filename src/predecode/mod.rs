@@ -0,0 +1,259 @@
+use crate::instructions::trap::trap;
+use crate::instructions::update_flags;
+use crate::predecode::decoded::{Decoded, Operation};
+use crate::registers::register::Register;
+use crate::{Vm, MEMORY_MAX};
+
+pub mod decoded;
+
+/// Decoded instructions keyed by address. An entry is filled the first time
+/// its address is fetched and dropped whenever the program stores to it.
+pub struct DecodeCache {
+    entries: Vec<Option<Decoded>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        Self {
+            entries: vec![None; MEMORY_MAX],
+        }
+    }
+
+    pub fn get(&mut self, address: u16, memory: &[u16; MEMORY_MAX]) -> Decoded {
+        let entry = &mut self.entries[address as usize];
+        match entry {
+            Some(decoded) => *decoded,
+            None => *entry.insert(Decoded::decode(memory[address as usize])),
+        }
+    }
+
+    pub fn invalidate(&mut self, address: u16) {
+        self.entries[address as usize] = None;
+    }
+
+    pub fn is_cached(&self, address: u16) -> bool {
+        self.entries[address as usize].is_some()
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs until the program halts and returns how many instructions executed.
+/// Behaves exactly like looping `Vm::fetch_decode_execute`, but without any
+/// of the analysis hooks.
+pub fn run(vm: &mut Vm) -> u64 {
+    let mut cache = DecodeCache::new();
+    let mut executed = 1;
+    while step(vm, &mut cache) {
+        executed += 1;
+    }
+    executed
+}
+
+/// Executes one instruction. Returns `false` once the machine stops.
+pub fn step(vm: &mut Vm, cache: &mut DecodeCache) -> bool {
+    let pc = vm.registers[Register::Pc as usize];
+    let decoded = cache.get(pc, &vm.memory);
+    let pc = pc.wrapping_add(1);
+    vm.registers[Register::Pc as usize] = pc;
+
+    let registers = &mut vm.registers;
+    let dr = decoded.dr as usize;
+    let sr = decoded.sr as usize;
+    let operand = decoded.operand;
+
+    match decoded.operation {
+        Operation::Br => {
+            if decoded.dr as u16 & registers[Register::Cond as usize] != 0 {
+                registers[Register::Pc as usize] = pc.wrapping_add(operand);
+            }
+        }
+        Operation::AddRegister => {
+            registers[dr] = registers[sr].wrapping_add(registers[operand as usize]);
+            update_flags(registers, dr as u16);
+        }
+        Operation::AddImmediate => {
+            registers[dr] = registers[sr].wrapping_add(operand);
+            update_flags(registers, dr as u16);
+        }
+        Operation::AndRegister => {
+            registers[dr] = registers[sr] & registers[operand as usize];
+            update_flags(registers, dr as u16);
+        }
+        Operation::AndImmediate => {
+            registers[dr] = registers[sr] & operand;
+            update_flags(registers, dr as u16);
+        }
+        Operation::Not => {
+            registers[dr] = !registers[sr];
+            update_flags(registers, dr as u16);
+        }
+        Operation::Lea => {
+            registers[dr] = pc.wrapping_add(operand);
+            update_flags(registers, dr as u16);
+        }
+        Operation::Ld => {
+            vm.registers[dr] = vm.mem_read(pc.wrapping_add(operand));
+            update_flags(&mut vm.registers, dr as u16);
+        }
+        Operation::Ldi => {
+            let address = vm.mem_read(pc.wrapping_add(operand));
+            vm.registers[dr] = vm.mem_read(address);
+            update_flags(&mut vm.registers, dr as u16);
+        }
+        Operation::Ldr => {
+            vm.registers[dr] = vm.mem_read(vm.registers[sr].wrapping_add(operand));
+            update_flags(&mut vm.registers, dr as u16);
+        }
+        Operation::St => store(vm, cache, pc.wrapping_add(operand), dr),
+        Operation::Sti => {
+            let address = vm.mem_read(pc.wrapping_add(operand));
+            store(vm, cache, address, dr);
+        }
+        Operation::Str => {
+            let address = vm.registers[sr].wrapping_add(operand);
+            store(vm, cache, address, dr);
+        }
+        Operation::Jmp => registers[Register::Pc as usize] = registers[sr],
+        Operation::Jsr => {
+            registers[Register::R7 as usize] = pc;
+            registers[Register::Pc as usize] = pc.wrapping_add(operand);
+        }
+        Operation::Jsrr => {
+            registers[Register::R7 as usize] = pc;
+            registers[Register::Pc as usize] = registers[sr];
+        }
+        Operation::Trap => return trap(vm, operand),
+        Operation::Stop => return false,
+    }
+
+    true
+}
+
+fn store(vm: &mut Vm, cache: &mut DecodeCache, address: u16, source: usize) {
+    vm.mem_write(address, vm.registers[source]);
+    cache.invalidate(address);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::predecode::{run, step, DecodeCache};
+    use crate::registers::register::Register;
+    use crate::test_support::seeded_vms;
+    use crate::Vm;
+
+    // ========== Equivalence With the Interpreter ==========
+
+    #[test]
+    fn test_every_non_trap_opcode_matches_the_interpreter() {
+        let mut seed = 0x9E37_79B9_7F4A_7C15_u64;
+        for opcode in 0u16..15 {
+            for _ in 0..64 {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                let instruction = (opcode << 12) | (seed >> 48) as u16 & 0x0FFF;
+                let (mut interpreter, mut predecoded) = seeded_vms(seed | 1);
+                for vm in [&mut interpreter, &mut predecoded] {
+                    vm.write_to_register(Register::Pc, 0x3000);
+                    vm.mem_write(0x3000, instruction);
+                }
+
+                let expected = interpreter.fetch_decode_execute();
+                let actual = step(&mut predecoded, &mut DecodeCache::new());
+
+                assert_eq!(expected, actual, "stop differs for x{:04X}", instruction);
+                assert_eq!(
+                    interpreter.registers, predecoded.registers,
+                    "registers differ for x{:04X}",
+                    instruction
+                );
+                assert!(
+                    interpreter.memory[..0xFE00] == predecoded.memory[..0xFE00],
+                    "memory differs for x{:04X}",
+                    instruction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_loop_program_matches_the_interpreter() {
+        let program = [
+            0b0101_001_001_1_00000,  // AND R1, R1, #0
+            0b0001_001_001_1_01010,  // ADD R1, R1, #10
+            0b0001_010_010_0_00_001, // ADD R2, R2, R1
+            0b0111_010_110_000000,   // STR R2, R6, #0
+            0b0001_001_001_1_11111,  // ADD R1, R1, #-1
+            0b0000_001_111111100,    // BRp #-4
+            0xF025,                  // HALT
+        ];
+        let mut interpreter = Vm::new();
+        let mut predecoded = Vm::new();
+        for vm in [&mut interpreter, &mut predecoded] {
+            for (offset, &word) in program.iter().enumerate() {
+                vm.mem_write(0x3000 + offset as u16, word);
+            }
+            vm.write_to_register(Register::Pc, 0x3000);
+            vm.write_to_register(Register::R6, 0x4000);
+            vm.output = Some(String::new());
+        }
+
+        while interpreter.fetch_decode_execute() {}
+        let executed = run(&mut predecoded);
+
+        assert_eq!(interpreter.registers, predecoded.registers);
+        assert_eq!(predecoded.memory[0x4000], 55);
+        assert_eq!(executed, 2 + 4 * 10 + 1);
+    }
+
+    // ========== Invalidation ==========
+
+    #[test]
+    fn test_store_into_code_invalidates_decoded_entry() {
+        let mut vm = Vm::new();
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.write_to_register(Register::Cond, 0b010);
+        vm.write_to_register(Register::R1, 0x1261); // ADD R1, R1, #1
+        vm.mem_write(0x3000, 0b0011_001_000000010); // ST R1, #2
+        vm.mem_write(0x3001, 0b0000_111_000000001); // BRnzp #1
+        vm.mem_write(0x3003, 0xD000);
+        let mut cache = DecodeCache::new();
+        cache.get(0x3003, &vm.memory);
+
+        step(&mut vm, &mut cache);
+
+        assert!(!cache.is_cached(0x3003));
+        step(&mut vm, &mut cache);
+        assert!(step(&mut vm, &mut cache));
+        assert_eq!(vm.registers[Register::R1 as usize], 0x1262);
+    }
+
+    #[test]
+    fn test_decoded_entries_are_reused() {
+        let mut vm = Vm::new();
+        vm.mem_write(0x3000, 0x1261);
+        let mut cache = DecodeCache::new();
+
+        let first = cache.get(0x3000, &vm.memory);
+        vm.memory[0x3000] = 0xD000; // bypasses the store path on purpose
+
+        assert_eq!(cache.get(0x3000, &vm.memory), first);
+    }
+
+    // ========== Scripted Input ==========
+
+    #[test]
+    fn test_getc_reads_scripted_input_and_halts_when_it_runs_out() {
+        let mut vm = Vm::new();
+        vm.input = Some("a".encode_utf16().collect());
+        vm.write_to_register(Register::Pc, 0x3000);
+        vm.mem_write(0x3000, 0xF020); // GETC
+        vm.mem_write(0x3001, 0xF020); // GETC
+
+        assert_eq!(run(&mut vm), 2);
+        assert_eq!(vm.registers[Register::R0 as usize], 'a' as u16);
+    }
+}
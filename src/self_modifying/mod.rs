@@ -0,0 +1,392 @@
+use crate::instructions::sign_extend;
+use crate::limits::StopReason;
+use crate::registers::register::Register;
+use crate::symbols::source_map::SourceMap;
+use crate::symbols::symbol_table::SymbolTable;
+use crate::Vm;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// What to do when a program writes over its own code or runs a word it
+/// wrote.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CodePolicy {
+    /// Only list them in the report after the run.
+    Allow,
+    /// Also warn on stderr the first time each happens.
+    #[default]
+    Warn,
+    /// Stop the program before the instruction does it.
+    Fault,
+}
+
+impl CodePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            CodePolicy::Allow => "allow",
+            CodePolicy::Warn => "warn",
+            CodePolicy::Fault => "fault",
+        }
+    }
+}
+
+impl FromStr for CodePolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [CodePolicy::Allow, CodePolicy::Warn, CodePolicy::Fault]
+            .into_iter()
+            .find(|policy| policy.name() == name)
+            .ok_or_else(|| format!("unknown policy '{}', expected allow, warn or fault", name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeWrite {
+    /// A store changed a word that had already run as an instruction.
+    ModifiedCode { address: u16 },
+    /// The program ran a word that the store at `writer` put there,
+    /// rather than one loaded from an image.
+    RanWrittenWord { writer: u16 },
+}
+
+impl fmt::Display for CodeWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CodeWrite::ModifiedCode { address } => {
+                write!(f, "writes x{:04X}, which already ran as code", address)
+            }
+            CodeWrite::RanWrittenWord { writer } => {
+                write!(f, "runs a word written by the store at x{:04X}", writer)
+            }
+        }
+    }
+}
+
+/// One kind of code write at one instruction, and how often it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeWriteEvent {
+    pub address: u16,
+    pub write: CodeWrite,
+    pub count: u64,
+}
+
+/// Tells code from data as the program runs. Every word fetched as an
+/// instruction is marked as code and every word a store writes is marked
+/// as written, so stores into code that already ran and jumps into words
+/// the program wrote itself stand out. Words loaded from an image are
+/// neither.
+pub struct CodeWatch {
+    policy: CodePolicy,
+    /// One bit per address that has run as an instruction.
+    executed: Vec<u64>,
+    /// The store that last wrote each word, while nothing has run it.
+    written: HashMap<u16, u16>,
+    events: Vec<CodeWriteEvent>,
+}
+
+impl CodeWatch {
+    pub fn new(policy: CodePolicy) -> CodeWatch {
+        Self {
+            policy,
+            executed: vec![0; 1 << 10],
+            written: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn events(&self) -> &[CodeWriteEvent] {
+        &self.events
+    }
+
+    /// Whether it stopped the program.
+    pub fn halted(&self) -> bool {
+        self.policy == CodePolicy::Fault && !self.events.is_empty()
+    }
+
+    pub fn has_run(&self, address: u16) -> bool {
+        self.executed[address as usize / 64] & (1 << (address % 64)) != 0
+    }
+
+    fn note(&mut self, address: u16, write: CodeWrite) {
+        let known = self
+            .events
+            .iter_mut()
+            .find(|event| event.address == address && event.write == write);
+        match known {
+            Some(event) => event.count += 1,
+            None => {
+                if self.policy == CodePolicy::Warn {
+                    eprintln!("warning: x{:04X}: {}", address, write);
+                }
+                self.events.push(CodeWriteEvent {
+                    address,
+                    write,
+                    count: 1,
+                });
+            }
+        }
+    }
+
+    /// Looks at the instruction at `pc` before it runs: whether it was
+    /// written by the program, and for stores, whether they hit code.
+    /// Returns why to stop the program under [`CodePolicy::Fault`]. Must be
+    /// called after fetch, so the PC already points past the instruction.
+    pub fn check(&mut self, vm: &Vm, pc: u16, instruction: u16) -> Option<StopReason> {
+        let found = self.events.len();
+        if let Some(writer) = self.written.remove(&pc) {
+            self.note(pc, CodeWrite::RanWrittenWord { writer });
+        }
+        self.executed[pc as usize / 64] |= 1 << (pc % 64);
+
+        let pc_offset =
+            vm.registers[Register::Pc as usize].wrapping_add(sign_extend(instruction & 0x1FF, 9));
+        let target = match instruction >> 12 {
+            // ST
+            0b0011 => Some(pc_offset),
+            // STI. Reading the pointer through the devices would take a
+            // key the instruction itself then misses
+            0b1011 => Some(vm.memory[pc_offset as usize]),
+            // STR
+            0b0111 => {
+                let base = vm.registers[((instruction >> 6) & 0x7) as usize];
+                Some(base.wrapping_add(sign_extend(instruction & 0x3F, 6)))
+            }
+            _ => None,
+        };
+        if let Some(address) = target {
+            if self.has_run(address) {
+                self.note(pc, CodeWrite::ModifiedCode { address });
+            }
+            self.written.insert(address, pc);
+        }
+
+        let event = self.events[found..]
+            .first()
+            .filter(|_| self.policy == CodePolicy::Fault)?;
+        Some(StopReason::SelfModifying {
+            pc,
+            write: event.write,
+        })
+    }
+
+    /// One line per instruction and kind of write, with labels and source
+    /// lines where they are known.
+    pub fn report_with(&self, symbols: &SymbolTable, source_map: &SourceMap) -> String {
+        let mut report = String::new();
+        match (self.halted(), self.events.len()) {
+            (true, _) => writeln!(report, "--- Self-modifying code: stopped ---"),
+            (false, 0) => writeln!(report, "--- Self-modifying code: none ---"),
+            (false, 1) => writeln!(report, "--- Self-modifying code: 1 write ---"),
+            (false, count) => writeln!(report, "--- Self-modifying code: {} writes ---", count),
+        }
+        .unwrap();
+        for event in &self.events {
+            write!(report, "x{:04X}", event.address).unwrap();
+            if let Some(label) = symbols.label_at(event.address) {
+                write!(report, " {}", label).unwrap();
+            }
+            if let Some(location) = source_map.lookup(event.address) {
+                write!(report, " ({})", location).unwrap();
+            }
+            write!(report, ": {}", event.write).unwrap();
+            if event.count > 1 {
+                write!(report, " ({} times)", event.count).unwrap();
+            }
+            writeln!(report).unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::limits::StopReason;
+    use crate::registers::register::{MemoryMappedRegister, Register};
+    use crate::self_modifying::{CodePolicy, CodeWatch, CodeWrite};
+    use crate::symbols::source_map::SourceMap;
+    use crate::Vm;
+    use std::collections::VecDeque;
+
+    const KBSR: u16 = MemoryMappedRegister::MR_KBSR as u16;
+
+    fn run(source: &str, policy: CodePolicy) -> Vm {
+        let assembly = assemble(source).unwrap();
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", &assembly.image());
+        vm.code_watch = Some(CodeWatch::new(policy));
+        vm.registers[Register::Pc as usize] = assembly.origin;
+        vm.output = Some(String::new());
+        vm.quiet_halt = true;
+        vm.limits.max_instructions = Some(1000);
+        vm.run();
+        vm
+    }
+
+    fn writes(vm: &Vm) -> Vec<(u16, CodeWrite, u64)> {
+        let watch = vm.code_watch.as_ref().unwrap();
+        watch
+            .events()
+            .iter()
+            .map(|event| (event.address, event.write, event.count))
+            .collect()
+    }
+
+    /// Patches the `ADD` at `PATCH` into `ADD R1, R1, #2` after it has run
+    /// once, then runs it again.
+    const PATCHING: &str = ".ORIG x3000
+        AND R2, R2, #0
+LOOP    ADD R2, R2, #1
+PATCH   ADD R1, R1, #1
+        ADD R0, R2, #-2
+        BRz DONE
+        LD R3, NEW
+        ST R3, PATCH
+        BRnzp LOOP
+DONE    HALT
+NEW     ADD R1, R1, #2
+.END
+";
+
+    // ========== Policies ==========
+
+    #[test]
+    fn test_policy_names() {
+        assert_eq!("fault".parse(), Ok(CodePolicy::Fault));
+        assert_eq!(CodePolicy::default().name(), "warn");
+        assert_eq!(
+            "deny".parse::<CodePolicy>().unwrap_err(),
+            "unknown policy 'deny', expected allow, warn or fault"
+        );
+    }
+
+    // ========== Detection ==========
+
+    #[test]
+    fn test_store_into_code_that_ran_then_running_it() {
+        let vm = run(PATCHING, CodePolicy::Allow);
+
+        assert_eq!(vm.registers[1], 3);
+        assert_eq!(
+            writes(&vm),
+            [
+                (0x3006, CodeWrite::ModifiedCode { address: 0x3002 }, 1),
+                (0x3002, CodeWrite::RanWrittenWord { writer: 0x3006 }, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_stores_and_loaded_code_are_fine() {
+        let vm = run(
+            ".ORIG x3000
+AND R0, R0, #0
+ADD R0, R0, #3
+LOOP ST R0, COUNT
+ADD R0, R0, #-1
+BRp LOOP
+HALT
+COUNT .FILL 0
+.END
+",
+            CodePolicy::Allow,
+        );
+
+        assert!(writes(&vm).is_empty());
+    }
+
+    #[test]
+    fn test_jumping_into_written_words() {
+        let vm = run(
+            ".ORIG x3000
+LD R0, HALT_WORD
+LEA R1, BUFFER
+STR R0, R1, #0
+JMP R1
+HALT_WORD HALT
+BUFFER .BLKW 1
+.END
+",
+            CodePolicy::Allow,
+        );
+
+        assert_eq!(
+            writes(&vm),
+            [(0x3005, CodeWrite::RanWrittenWord { writer: 0x3002 }, 1)]
+        );
+    }
+
+    #[test]
+    fn test_fault_stops_before_the_store() {
+        let vm = run(PATCHING, CodePolicy::Fault);
+        let watch = vm.code_watch.as_ref().unwrap();
+
+        assert!(watch.halted());
+        assert_eq!(vm.memory[0x3002], 0x1261);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3006);
+    }
+
+    #[test]
+    fn test_fault_stops_with_its_own_reason() {
+        let assembly = assemble(PATCHING).unwrap();
+        let mut vm = Vm::new();
+        vm.load_image("test.obj", &assembly.image());
+        vm.code_watch = Some(CodeWatch::new(CodePolicy::Fault));
+        vm.registers[Register::Pc as usize] = assembly.origin;
+        vm.quiet_halt = true;
+
+        let reason = vm.run();
+
+        assert_eq!(
+            reason,
+            StopReason::SelfModifying {
+                pc: 0x3006,
+                write: CodeWrite::ModifiedCode { address: 0x3002 },
+            }
+        );
+        assert_eq!(
+            reason.to_string(),
+            "self-modifying code at x3006: writes x3002, which already ran as code"
+        );
+    }
+
+    #[test]
+    fn test_sti_pointer_does_not_read_the_keyboard() {
+        let mut vm = Vm::new();
+        vm.machine.devices.keyboard = true;
+        vm.input = Some(VecDeque::from([u16::from(b'a')]));
+        let mut watch = CodeWatch::new(CodePolicy::Allow);
+
+        // STI R0, #0 just below KBSR, so the pointer is the status register
+        vm.registers[Register::Pc as usize] = KBSR;
+        watch.check(&vm, KBSR - 1, 0xB000);
+
+        assert_eq!(vm.input.as_ref().map(VecDeque::len), Some(1));
+    }
+
+    #[test]
+    fn test_report_names_labels_and_lines() {
+        let assembly = assemble(PATCHING).unwrap();
+        let vm = run(PATCHING, CodePolicy::Allow);
+        let report = vm
+            .code_watch
+            .as_ref()
+            .unwrap()
+            .report_with(&assembly.symbols, &assembly.source_map("patch.asm"));
+
+        assert_eq!(
+            report,
+            "--- Self-modifying code: 2 writes ---
+x3006 (patch.asm:8): writes x3002, which already ran as code
+x3002 PATCH (patch.asm:4): runs a word written by the store at x3006
+"
+        );
+        assert_eq!(
+            CodeWatch::new(CodePolicy::Allow).report_with(&Default::default(), &SourceMap::new()),
+            "--- Self-modifying code: none ---\n"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+
+pub mod source_map;
+pub mod symbol_table;
+
+/// A problem found while parsing a `.sym` or source map file.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn parse_hex_address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix('x')
+        .or_else(|| text.strip_prefix('X'))
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbols::parse_hex_address;
+
+    #[test]
+    fn test_parse_hex_address_accepts_lc3_and_c_prefixes() {
+        assert_eq!(parse_hex_address("x3000"), Some(0x3000));
+        assert_eq!(parse_hex_address("X30ff"), Some(0x30FF));
+        assert_eq!(parse_hex_address("0xFE00"), Some(0xFE00));
+        assert_eq!(parse_hex_address("3000"), Some(0x3000));
+    }
+
+    #[test]
+    fn test_parse_hex_address_rejects_garbage() {
+        assert_eq!(parse_hex_address(""), None);
+        assert_eq!(parse_hex_address("x"), None);
+        assert_eq!(parse_hex_address("x10000"), None);
+        assert_eq!(parse_hex_address("-----"), None);
+    }
+}
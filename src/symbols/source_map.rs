@@ -0,0 +1,238 @@
+use crate::symbols::{parse_hex_address, ParseError};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Maps memory addresses back to the assembly source that produced them.
+///
+/// On disk every entry is one line, `x3000 main.asm:12:5`; the column is optional.
+/// Words reserved without a value get a line `x3000 reserved` as well.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceMap {
+    locations: BTreeMap<u16, SourceLocation>,
+    reserved: BTreeSet<u16>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        Self::default()
+    }
+
+    pub fn load(file_name: &str) -> Result<SourceMap, String> {
+        let text = fs::read_to_string(file_name)
+            .map_err(|e| format!("could not read {}: {}", file_name, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file_name, e))
+    }
+
+    pub fn parse(text: &str) -> Result<SourceMap, ParseError> {
+        let mut map = SourceMap::new();
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| ParseError {
+                line: index + 1,
+                message,
+            };
+
+            let (address, location) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                error(format!(
+                    "expected '<address> <file>:<line>', got '{}'",
+                    line
+                ))
+            })?;
+            let address = parse_hex_address(address)
+                .ok_or_else(|| error(format!("invalid address '{}'", address)))?;
+            if location.trim() == "reserved" {
+                map.reserve(address);
+                continue;
+            }
+            let location = parse_location(location.trim())
+                .ok_or_else(|| error(format!("invalid source location '{}'", location.trim())))?;
+
+            map.insert(address, location);
+        }
+
+        Ok(map)
+    }
+
+    pub fn insert(&mut self, address: u16, location: SourceLocation) {
+        self.locations.insert(address, location);
+    }
+
+    pub fn lookup(&self, address: u16) -> Option<&SourceLocation> {
+        self.locations.get(&address)
+    }
+
+    /// Marks a word as reserved with no value, like `.BLKW` space.
+    pub fn reserve(&mut self, address: u16) {
+        self.reserved.insert(address);
+    }
+
+    pub fn reserved(&self) -> impl Iterator<Item = u16> + '_ {
+        self.reserved.iter().copied()
+    }
+
+    /// Adds every entry of `other`, which wins where both have one.
+    pub fn merge(&mut self, other: SourceMap) {
+        self.locations.extend(other.locations);
+        self.reserved.extend(other.reserved);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty() && self.reserved.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &SourceLocation)> {
+        self.locations
+            .iter()
+            .map(|(&address, location)| (address, location))
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (address, location) in self.iter() {
+            writeln!(
+                f,
+                "x{:04X} {}:{}:{}",
+                address, location.file, location.line, location.column
+            )?;
+        }
+        for address in self.reserved() {
+            writeln!(f, "x{:04X} reserved", address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `file:line[:column]` from the right so Windows drive letters survive.
+fn parse_location(text: &str) -> Option<SourceLocation> {
+    let mut parts = text.rsplitn(3, ':');
+    let last = parts.next()?.parse::<u32>().ok()?;
+    let middle = parts.next()?;
+    let rest = parts.next();
+
+    match (middle.parse::<u32>(), rest) {
+        (Ok(line), Some(file)) if !file.is_empty() => Some(SourceLocation {
+            file: file.to_string(),
+            line,
+            column: last,
+        }),
+        _ => {
+            let file = match rest {
+                Some(rest) => format!("{}:{}", rest, middle),
+                None => middle.to_string(),
+            };
+            if file.is_empty() {
+                return None;
+            }
+            Some(SourceLocation {
+                file,
+                line: last,
+                column: 0,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbols::source_map::{SourceLocation, SourceMap};
+
+    #[test]
+    fn test_parse_entries_with_and_without_columns() {
+        let map = SourceMap::parse("x3000 main.asm:12:5\nx3001 main.asm:13\n").unwrap();
+
+        assert_eq!(
+            map.lookup(0x3000),
+            Some(&SourceLocation {
+                file: "main.asm".to_string(),
+                line: 12,
+                column: 5
+            })
+        );
+        assert_eq!(map.lookup(0x3001).unwrap().line, 13);
+        assert_eq!(map.lookup(0x3001).unwrap().column, 0);
+        assert_eq!(map.lookup(0x3002), None);
+    }
+
+    #[test]
+    fn test_parse_keeps_windows_drive_letters() {
+        let map = SourceMap::parse("x3000 C:\\course\\main.asm:7\n").unwrap();
+
+        assert_eq!(map.lookup(0x3000).unwrap().file, "C:\\course\\main.asm");
+        assert_eq!(map.lookup(0x3000).unwrap().line, 7);
+    }
+
+    #[test]
+    fn test_parse_skips_comments_and_blank_lines() {
+        let map = SourceMap::parse("# generated\n\n// note\nx3000 a.asm:1\n").unwrap();
+
+        assert_eq!(map.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_parse_reports_missing_line_number() {
+        let error = SourceMap::parse("x3000 main.asm:1\nx3001 main.asm\n").unwrap_err();
+
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn test_merge_prefers_the_later_map() {
+        let mut map = SourceMap::parse(
+            "x3000 a.asm:1
+x3001 a.asm:2
+",
+        )
+        .unwrap();
+        map.merge(
+            SourceMap::parse(
+                "x3001 b.asm:9
+",
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(map.lookup(0x3000).unwrap().to_string(), "a.asm:1");
+        assert_eq!(map.lookup(0x3001).unwrap().to_string(), "b.asm:9");
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let text = "x3000 main.asm:12:5\nx3001 lib.asm:3:1\nx3001 reserved\n";
+        let map = SourceMap::parse(text).unwrap();
+
+        assert_eq!(map.reserved().collect::<Vec<_>>(), [0x3001]);
+        assert_eq!(map.to_string(), text);
+        assert_eq!(SourceMap::parse(&map.to_string()).unwrap(), map);
+    }
+
+    #[test]
+    fn test_location_displays_as_file_and_line() {
+        let location = SourceLocation {
+            file: "main.asm".to_string(),
+            line: 47,
+            column: 3,
+        };
+
+        assert_eq!(location.to_string(), "main.asm:47");
+    }
+}
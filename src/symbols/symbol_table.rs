@@ -0,0 +1,138 @@
+use crate::symbols::{parse_hex_address, ParseError};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+/// Labels and their addresses, as written by `lc3as` into a `.sym` file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SymbolTable {
+    symbols: BTreeMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        Self::default()
+    }
+
+    pub fn load(file_name: &str) -> Result<SymbolTable, String> {
+        let text = fs::read_to_string(file_name)
+            .map_err(|e| format!("could not read {}: {}", file_name, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file_name, e))
+    }
+
+    /// Parses the `lc3as` layout (`//\tLABEL   3000`). The leading `//` is
+    /// optional so hand-written tables of `LABEL x3000` pairs work too.
+    pub fn parse(text: &str) -> Result<SymbolTable, ParseError> {
+        let mut table = SymbolTable::new();
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let trimmed = raw_line.trim();
+            let commented = trimmed.starts_with("//");
+            let fields: Vec<&str> = trimmed
+                .trim_start_matches("//")
+                .split_whitespace()
+                .collect();
+            if fields.len() != 2 {
+                continue;
+            }
+
+            // lc3as wraps its table in `//` header lines, so only complain
+            // about lines that are clearly meant to be entries.
+            let Some(address) = parse_hex_address(fields[1]) else {
+                if commented {
+                    continue;
+                }
+                return Err(ParseError {
+                    line: index + 1,
+                    message: format!("invalid address '{}' for {}", fields[1], fields[0]),
+                });
+            };
+
+            table.insert(fields[0], address);
+        }
+
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, label: &str, address: u16) {
+        self.symbols.insert(label.to_string(), address);
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.symbols.get(label).copied()
+    }
+
+    /// Returns the first label (alphabetically) defined at `address`.
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .find(|(_, value)| **value == address)
+            .map(|(label, _)| label.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.symbols
+            .iter()
+            .map(|(label, &address)| (label.as_str(), address))
+    }
+
+    /// The table in the layout `lc3as` writes, which `parse` reads back.
+    pub fn to_file(&self) -> String {
+        let mut out = String::from(
+            "// Symbol table\n\
+             // Scope level 0:\n\
+             //\tSymbol Name       Page Address\n\
+             //\t----------------  ------------\n",
+        );
+        for (label, address) in self.iter() {
+            writeln!(out, "//\t{:<16}  {:04X}", label, address).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbols::symbol_table::SymbolTable;
+
+    const LC3AS_OUTPUT: &str = "// Symbol table\n\
+        // Scope level 0:\n\
+        //\tSymbol Name       Page Address\n\
+        //\t----------------  ------------\n\
+        //\tSTART             3000\n\
+        //\tLOOP              3004\n\
+        //\tMESSAGE           3010\n";
+
+    #[test]
+    fn test_parse_lc3as_symbol_file() {
+        let table = SymbolTable::parse(LC3AS_OUTPUT).unwrap();
+
+        assert_eq!(table.address_of("START"), Some(0x3000));
+        assert_eq!(table.address_of("LOOP"), Some(0x3004));
+        assert_eq!(table.address_of("MESSAGE"), Some(0x3010));
+        assert_eq!(table.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_parse_plain_label_address_pairs() {
+        let table = SymbolTable::parse("MAIN x3000\nDATA x4000\n").unwrap();
+
+        assert_eq!(table.address_of("MAIN"), Some(0x3000));
+        assert_eq!(table.address_of("DATA"), Some(0x4000));
+    }
+
+    #[test]
+    fn test_parse_reports_bad_address_with_line_number() {
+        let error = SymbolTable::parse("MAIN x3000\nDATA xZZZZ\n").unwrap_err();
+
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn test_label_at_looks_up_by_address() {
+        let table = SymbolTable::parse(LC3AS_OUTPUT).unwrap();
+
+        assert_eq!(table.label_at(0x3004), Some("LOOP"));
+        assert_eq!(table.label_at(0x3005), None);
+    }
+}
@@ -0,0 +1,59 @@
+//! Helpers shared by the tests of the execution engines.
+
+use crate::registers::register::Register;
+use crate::Vm;
+
+/// A VM with `words` at x3000, the PC on them and output captured.
+pub fn vm_with(words: &[u16]) -> Vm {
+    let mut vm = Vm::new();
+    for (offset, &word) in words.iter().enumerate() {
+        vm.mem_write(0x3000 + offset as u16, word);
+    }
+    vm.write_to_register(Register::Pc, 0x3000);
+    vm.output = Some(String::new());
+    vm
+}
+
+/// Two VMs with the same pseudo-random registers and memory, one for the
+/// interpreter and one for the engine compared against it.
+pub fn seeded_vms(seed: u64) -> (Vm, Vm) {
+    let mut state = seed;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u16
+    };
+
+    let mut interpreter = Vm::new();
+    let mut other = Vm::new();
+    for register in 0..8 {
+        let value = next();
+        interpreter.registers[register] = value;
+        other.registers[register] = value;
+    }
+    for address in 0..0xFE00 {
+        let value = next();
+        interpreter.memory[address] = value;
+        other.memory[address] = value;
+    }
+    let cond = 1 << (next() % 3);
+    interpreter.write_to_register(Register::Cond, cond);
+    other.write_to_register(Register::Cond, cond);
+    (interpreter, other)
+}
+
+/// Like [`seeded_vms`], with the PC on x3000 and no traps in the 80 words
+/// from there: they would block on input or exit.
+pub fn seeded_block_vms(seed: u64) -> (Vm, Vm) {
+    let (mut interpreter, mut other) = seeded_vms(seed);
+    for address in 0x3000..0x3000 + 80 {
+        if interpreter.memory[address] >> 12 == 0xF {
+            interpreter.memory[address] &= 0x0FFF;
+            other.memory[address] &= 0x0FFF;
+        }
+    }
+    interpreter.write_to_register(Register::Pc, 0x3000);
+    other.write_to_register(Register::Pc, 0x3000);
+    (interpreter, other)
+}
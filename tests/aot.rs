@@ -0,0 +1,57 @@
+use rustvm::aot::{runtime, translate};
+use rustvm::registers::register::Register;
+use rustvm::registers::ConditionFlag;
+use rustvm::{LoadedImage, Vm};
+use std::collections::VecDeque;
+
+#[rustfmt::skip]
+#[allow(dead_code, clippy::all)]
+#[path = "translated/program.rs"]
+mod program;
+
+fn machine() -> Vm {
+    let mut vm = Vm::new();
+    vm.input = Some(VecDeque::from([b'x' as u16]));
+    vm.output = Some(String::new());
+    vm
+}
+
+#[test]
+fn test_translated_program_matches_the_interpreter() {
+    let mut translated = machine();
+    runtime::load(&mut translated, program::IMAGES, program::ENTRY);
+    runtime::run(&mut translated, program::dispatch);
+
+    let mut interpreted = machine();
+    runtime::load(&mut interpreted, program::IMAGES, program::ENTRY);
+    interpreted.run();
+
+    assert_eq!(translated.output.as_deref(), Some("Hix\n--- HALT ---\n"));
+    assert_eq!(translated.output, interpreted.output);
+    assert_eq!(translated.registers, interpreted.registers);
+    assert_eq!(translated.memory[..], interpreted.memory[..]);
+    // The patched instruction ran instead of the translated original
+    assert_eq!(translated.registers[Register::R6 as usize], 7);
+    assert_eq!(
+        translated.registers[Register::Cond as usize],
+        ConditionFlag::Pos as u16
+    );
+}
+
+#[test]
+fn test_checked_in_translation_is_what_translate_writes() {
+    let mut vm = Vm::new();
+    runtime::load(&mut vm, program::IMAGES, program::ENTRY);
+    for &(origin, words) in program::IMAGES {
+        vm.images.push(LoadedImage {
+            file_name: "program.obj".to_string(),
+            origin,
+            length: words.len() as u16,
+        });
+    }
+
+    assert_eq!(
+        translate(&vm, program::ENTRY),
+        include_str!("translated/program.rs")
+    );
+}
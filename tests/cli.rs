@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const RUSTVM: &str = env!("CARGO_BIN_EXE_RustVm");
+
+/// Assembles `source` in a scratch directory and runs it with `options`,
+/// giving back the exit code and what went to stderr.
+fn run(name: &str, source: &str, options: &[&str]) -> (Option<i32>, String) {
+    let directory = std::env::temp_dir().join(format!("rustvm-cli-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let asm: PathBuf = directory.join(format!("{}.asm", name));
+    let obj = asm.with_extension("obj");
+    fs::write(&asm, source).unwrap();
+
+    let assembled = Command::new(RUSTVM)
+        .arg("asm")
+        .arg(&asm)
+        .arg("-o")
+        .arg(&obj)
+        .output()
+        .unwrap();
+    assert!(assembled.status.success(), "{:?}", assembled);
+
+    let ran = Command::new(RUSTVM)
+        .args(["run", "--quiet"])
+        .args(options)
+        .arg(&obj)
+        .output()
+        .unwrap();
+    (
+        ran.status.code(),
+        String::from_utf8_lossy(&ran.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn test_halt_exits_cleanly() {
+    let (code, _) = run("halt", ".ORIG x3000\nHALT\n.END\n", &[]);
+
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn test_unknown_trap_has_its_own_exit_code() {
+    let (code, stderr) = run("trap", ".ORIG x3000\nTRAP x30\n.END\n", &[]);
+
+    assert_eq!(code, Some(7));
+    assert!(
+        stderr.contains("--- Stopped: unknown trap x30"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_memcheck_halt_has_its_own_exit_code() {
+    let source = ".ORIG x3000\nLD R1, COUNT\nHALT\nCOUNT .BLKW 1\n.END\n";
+
+    let (code, stderr) = run("memcheck", source, &["--memcheck"]);
+    assert_eq!(code, Some(0));
+    assert!(stderr.contains("x3000 ("), "{}", stderr);
+    assert!(
+        stderr.contains("reads x3002, which was never set"),
+        "{}",
+        stderr
+    );
+
+    let (code, stderr) = run("memcheck-halt", source, &["--memcheck-halt"]);
+    assert_eq!(code, Some(8));
+    assert!(
+        stderr.contains("--- Stopped: uninitialized read of x3002 at x3000"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_self_modifying_fault_has_its_own_exit_code() {
+    let source = ".ORIG x3000\nLOOP ADD R1, R1, #1\nST R1, LOOP\nHALT\n.END\n";
+
+    let (code, _) = run("self-modifying", source, &["--self-modifying", "allow"]);
+    assert_eq!(code, Some(0));
+
+    let (code, stderr) = run(
+        "self-modifying-fault",
+        source,
+        &["--self-modifying", "fault"],
+    );
+    assert_eq!(code, Some(9));
+    assert!(
+        stderr.contains("--- Stopped: self-modifying code at x3001: writes x3000"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_microcode_engine_keeps_to_the_instruction_limit() {
+    let source = ".ORIG x3000\nLOOP ADD R1, R1, #1\nBRnzp LOOP\n.END\n";
+
+    let (code, stderr) = run(
+        "microcode-limit",
+        source,
+        &["--microcode", "--max-instructions", "1000"],
+    );
+
+    assert_eq!(code, Some(3));
+    assert!(
+        stderr.contains("--- Stopped: instruction limit reached"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_running_out_of_input_has_its_own_exit_code() {
+    let source = ".ORIG x3000\nLOOP GETC\nOUT\nBRnzp LOOP\n.END\n";
+    let input = std::env::temp_dir().join(format!("rustvm-cli-{}-input", std::process::id()));
+    fs::write(&input, "ab").unwrap();
+
+    let (code, stderr) = run("input", source, &["--input", input.to_str().unwrap()]);
+
+    assert_eq!(code, Some(10));
+    assert!(stderr.contains("--- Stopped: out of input"), "{}", stderr);
+}
@@ -0,0 +1,164 @@
+//! Translated ahead of time from program.obj by RustVm. Build it against the
+//! rustvm crate; it behaves like running the image in the interpreter.
+
+use rustvm::aot::runtime::{self, Exit};
+use rustvm::instructions::trap::trap;
+use rustvm::instructions::update_flags;
+use rustvm::Vm;
+
+const PC: usize = 8;
+const COND: usize = 9;
+
+pub const ENTRY: u16 = 0x3000;
+
+/// The loaded images as (origin, words).
+pub const IMAGES: &[(u16, &[u16])] = &[
+    (
+        0x3000,
+        &[
+            0xE014, 0xF022, 0x5260, 0x1265, 0x480B, 0x127F, 0x03FD, 0xF020,
+            0xF021, 0x340A, 0x2609, 0xE802, 0x2A06, 0x7B00, 0x1DA1, 0xF025,
+            0x1482, 0x14A1, 0xC1C0, 0x1DA7, 0x0000, 0x0048, 0x0069, 0x0000,
+        ],
+    ),
+];
+
+/// Translated instructions. A store into them hands the rest of the run to
+/// the interpreter.
+const TRANSLATED: &[(u16, u32)] = &[
+    (0x3000, 0x3013),
+];
+
+fn store(vm: &mut Vm, address: u16, value: u16) -> bool {
+    runtime::store(vm, address, value, TRANSLATED)
+}
+
+/// Runs the block starting at the PC.
+pub fn dispatch(vm: &mut Vm) -> Exit {
+    match vm.registers[PC] {
+        0x3000 => block_3000(vm),
+        0x3002 => block_3002(vm),
+        0x3004 => block_3004(vm),
+        0x3005 => block_3005(vm),
+        0x3007 => block_3007(vm),
+        0x3008 => block_3008(vm),
+        0x3009 => block_3009(vm),
+        0x3010 => block_3010(vm),
+        _ => Exit::Interpret,
+    }
+}
+
+fn block_3000(vm: &mut Vm) -> Exit {
+    // x3000: LEA R0, x3015
+    vm.registers[0] = 0x3015;
+    update_flags(&mut vm.registers, 0);
+    // x3001: PUTS
+    vm.registers[PC] = 0x3002;
+    if trap(vm, 0xF022) {
+        Exit::Continue
+    } else {
+        Exit::Halt
+    }
+}
+
+fn block_3002(vm: &mut Vm) -> Exit {
+    // x3002: AND R1, R1, #0
+    vm.registers[1] = vm.registers[1] & 0x0000;
+    update_flags(&mut vm.registers, 1);
+    // x3003: ADD R1, R1, #5
+    vm.registers[1] = vm.registers[1].wrapping_add(0x0005);
+    update_flags(&mut vm.registers, 1);
+    vm.registers[PC] = 0x3004;
+    Exit::Continue
+}
+
+fn block_3004(vm: &mut Vm) -> Exit {
+    // x3004: JSR x3010
+    vm.registers[7] = 0x3005;
+    vm.registers[PC] = 0x3010;
+    Exit::Continue
+}
+
+fn block_3005(vm: &mut Vm) -> Exit {
+    // x3005: ADD R1, R1, #-1
+    vm.registers[1] = vm.registers[1].wrapping_add(0xFFFF);
+    update_flags(&mut vm.registers, 1);
+    // x3006: BRp x3004
+    vm.registers[PC] = if vm.registers[COND] & 0b001 != 0 {
+        0x3004
+    } else {
+        0x3007
+    };
+    Exit::Continue
+}
+
+fn block_3007(vm: &mut Vm) -> Exit {
+    // x3007: GETC
+    vm.registers[PC] = 0x3008;
+    if trap(vm, 0xF020) {
+        Exit::Continue
+    } else {
+        Exit::Halt
+    }
+}
+
+fn block_3008(vm: &mut Vm) -> Exit {
+    // x3008: OUT
+    vm.registers[PC] = 0x3009;
+    if trap(vm, 0xF021) {
+        Exit::Continue
+    } else {
+        Exit::Halt
+    }
+}
+
+fn block_3009(vm: &mut Vm) -> Exit {
+    // x3009: ST R2, x3014
+    let value = vm.registers[2];
+    if store(vm, 0x3014, value) {
+        vm.registers[PC] = 0x300A;
+        return Exit::Modified;
+    }
+    // x300A: LD R3, x3014
+    vm.registers[3] = vm.mem_read(0x3014);
+    update_flags(&mut vm.registers, 3);
+    // x300B: LEA R4, x300E
+    vm.registers[4] = 0x300E;
+    update_flags(&mut vm.registers, 4);
+    // x300C: LD R5, x3013
+    vm.registers[5] = vm.mem_read(0x3013);
+    update_flags(&mut vm.registers, 5);
+    // x300D: STR R5, R4, #0
+    let address = vm.registers[4].wrapping_add(0x0000);
+    let value = vm.registers[5];
+    if store(vm, address, value) {
+        vm.registers[PC] = 0x300E;
+        return Exit::Modified;
+    }
+    // x300E: ADD R6, R6, #1
+    vm.registers[6] = vm.registers[6].wrapping_add(0x0001);
+    update_flags(&mut vm.registers, 6);
+    // x300F: HALT
+    vm.registers[PC] = 0x3010;
+    if trap(vm, 0xF025) {
+        Exit::Continue
+    } else {
+        Exit::Halt
+    }
+}
+
+fn block_3010(vm: &mut Vm) -> Exit {
+    // x3010: ADD R2, R2, R2
+    vm.registers[2] = vm.registers[2].wrapping_add(vm.registers[2]);
+    update_flags(&mut vm.registers, 2);
+    // x3011: ADD R2, R2, #1
+    vm.registers[2] = vm.registers[2].wrapping_add(0x0001);
+    update_flags(&mut vm.registers, 2);
+    // x3012: RET
+    vm.registers[PC] = vm.registers[7];
+    Exit::Continue
+}
+
+pub fn main() {
+    runtime::main(IMAGES, ENTRY, dispatch);
+}